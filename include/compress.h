@@ -0,0 +1,80 @@
+/* The C interface of the compress crate, generated by compress::ffi::header() */
+
+#ifndef COMPRESS_H
+#define COMPRESS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Raw DEFLATE data */
+#define COMPRESS_DEFLATE 1
+/* The gzip file format */
+#define COMPRESS_GZIP 2
+/* The zlib format, of which only the decoder exists */
+#define COMPRESS_ZLIB 3
+/* The LZ4 frame format */
+#define COMPRESS_LZ4 4
+/* The bzip2 file format */
+#define COMPRESS_BZIP2 5
+/* The framing format of snappy */
+#define COMPRESS_SNAPPY 6
+/* The zstd format, of which only the decoder exists */
+#define COMPRESS_ZSTD 7
+/* The .xz format, of which only the decoder exists */
+#define COMPRESS_XZ 8
+/* Success */
+#define COMPRESS_OK 0
+/* Success, with the end of the stream reached and its output all given */
+#define COMPRESS_END 1
+/* The codec is unknown, disabled, or has no encoder */
+#define COMPRESS_ERROR_CODEC -1
+/* A pointer is null */
+#define COMPRESS_ERROR_ARGUMENT -2
+/* The destination is too small for the output */
+#define COMPRESS_ERROR_BUFFER -3
+/* The input is invalid */
+#define COMPRESS_ERROR_CORRUPT -4
+/* The input ends before the end of the stream */
+#define COMPRESS_ERROR_TRUNCATED -5
+/* The checksum of the input differs from that of the output */
+#define COMPRESS_ERROR_CHECKSUM -6
+/* The input exceeds a limit of the decoder */
+#define COMPRESS_ERROR_LIMIT -7
+/* The input uses a feature which isn't supported */
+#define COMPRESS_ERROR_UNSUPPORTED -8
+/* Any other failure */
+#define COMPRESS_ERROR_IO -9
+
+/* A compression or decompression stream */
+typedef struct compress_stream compress_stream;
+
+/* Compresses src into dst at level, from 0, or at the default level if negative.
+   On COMPRESS_ERROR_BUFFER, *dst_len is set to the size needed. */
+int compress_buffer(int codec, int level, const uint8_t *src, size_t src_len, uint8_t *dst, size_t *dst_len);
+
+/* Decompresses src into dst, stopping on COMPRESS_ERROR_BUFFER once the output exceeds *dst_len. */
+int decompress_buffer(int codec, const uint8_t *src, size_t src_len, uint8_t *dst, size_t *dst_len);
+
+/* Creates a compression stream, or returns NULL if the codec has no encoder. */
+compress_stream *compress_stream_new(int codec, int level);
+
+/* Creates a decompression stream, or returns NULL if the codec is unknown. */
+compress_stream *decompress_stream_new(int codec);
+
+/* Consumes up to *src_len bytes of src and produces up to *dst_len bytes into dst,
+   setting both to the sizes used. finish is set once src holds the end of the input.
+   Returns COMPRESS_END once the stream is over, and its output all produced. */
+int compress_stream_process(compress_stream *stream, const uint8_t *src, size_t *src_len, uint8_t *dst, size_t *dst_len, int finish);
+
+/* Destroys a stream. */
+void compress_stream_free(compress_stream *stream);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
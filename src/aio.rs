@@ -0,0 +1,635 @@
+//! Asynchronous readers and writers. Requires `async` feature
+//!
+//! `Decoder` decodes the data of an `AsyncRead` with a `Process`, as the
+//! `lz4::Processor`, and `Encoder` writes the output of an encoder, as the
+//! `lz4`, `gzip` or `bwt` ones, into an `AsyncWrite`, so that a service may
+//! code its streams without blocking the threads of its executor. The coding
+//! itself is done within the calls to `poll_read` and `poll_write`, and only
+//! the I/O waits.
+//!
+//! `AsyncRead` and `AsyncWrite` are those of the `futures` crate, with the
+//! same methods, so that the streams of a runtime implement them by calling
+//! the methods of theirs. With the `futures-io` feature, `Decoder` and
+//! `Encoder` implement the traits of the `futures-io` crate as well, and
+//! `Compat` wraps its readers and writers, to code those of the runtimes
+//! built on it as they are.
+//!
+//! The gzip and BWT streams are decoded by their `Processor` likewise, as
+//! `aio::Decoder::new(r, gzip::Processor::new())`.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::pin::Pin;
+//! use std::ptr;
+//! use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+//! use compress::aio::{self, AsyncRead, AsyncWrite};
+//! use compress::lz4;
+//!
+//! fn noop(_: *const ()) {}
+//! fn clone(_: *const ()) -> RawWaker { RawWaker::new(ptr::null(), &VTABLE) }
+//! static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+//! let waker = unsafe { Waker::from_raw(clone(ptr::null())) };
+//! let mut cx = Context::from_waker(&waker);
+//!
+//! let mut encoder = aio::Encoder::new(Vec::new(), lz4::Encoder::new);
+//! assert!(Pin::new(&mut encoder).poll_write(&mut cx, b"not blocked").is_ready());
+//! assert!(Pin::new(&mut encoder).poll_close(&mut cx).is_ready());
+//! let compressed = encoder.into_inner();
+//!
+//! let mut decoder = aio::Decoder::new(&compressed[..], lz4::Processor::new());
+//! let mut decompressed = [0; 16];
+//! match Pin::new(&mut decoder).poll_read(&mut cx, &mut decompressed) {
+//!     Poll::Ready(Ok(n)) => assert_eq!(&decompressed[..n], b"not blocked"),
+//!     _ => panic!("the data is all there"),
+//! }
+//! ```
+
+use std::io::{self, Write};
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use error::truncated;
+use process::{Process, Status};
+use OutputLimit;
+
+#[cfg(feature="futures-io")]
+use futures_io;
+
+#[cfg(feature="bwt")]
+use bwt;
+#[cfg(feature="gzip")]
+use gzip;
+#[cfg(feature="lz4")]
+use lz4;
+
+/// Size of the input read at once by `Decoder`
+const BUF_SIZE: usize = 1 << 14;
+
+/// A source of bytes which may not have them yet
+pub trait AsyncRead {
+    /// Reads into `buf`, as `Read::read`, or returns `Poll::Pending` and
+    /// wakes the task once there is data.
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>>;
+}
+
+/// A sink of bytes which may not take them yet
+pub trait AsyncWrite {
+    /// Writes from `buf`, as `Write::write`, or returns `Poll::Pending` and
+    /// wakes the task once there is room.
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>>;
+
+    /// Flushes the data written, as `Write::flush`
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>>;
+
+    /// Flushes the data written and closes the sink
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>>;
+}
+
+impl<'a> AsyncRead for &'a [u8] {
+    fn poll_read(self: Pin<&mut Self>, _: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(io::Read::read(self.get_mut(), buf))
+    }
+}
+
+impl AsyncWrite for Vec<u8> {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.get_mut().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A reader or a writer of the `futures-io` crate, as an `AsyncRead` or an
+/// `AsyncWrite`. Requires `futures-io` feature
+#[cfg(feature="futures-io")]
+pub struct Compat<T>(pub T);
+
+#[cfg(feature="futures-io")]
+impl<T: futures_io::AsyncRead + Unpin> AsyncRead for Compat<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        futures_io::AsyncRead::poll_read(Pin::new(&mut self.get_mut().0), cx, buf)
+    }
+}
+
+#[cfg(feature="futures-io")]
+impl<T: futures_io::AsyncWrite + Unpin> AsyncWrite for Compat<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        futures_io::AsyncWrite::poll_write(Pin::new(&mut self.get_mut().0), cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        futures_io::AsyncWrite::poll_flush(Pin::new(&mut self.get_mut().0), cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        futures_io::AsyncWrite::poll_close(Pin::new(&mut self.get_mut().0), cx)
+    }
+}
+
+/// Decodes the data of an asynchronous reader with a `Process`
+pub struct Decoder<R, P> {
+    r: R,
+    p: P,
+    buf: Vec<u8>,
+    pos: usize,
+    end: usize,
+    eof: bool,
+    done: bool,
+    limit: OutputLimit,
+}
+
+impl<R: AsyncRead + Unpin, P: Process + Unpin> Decoder<R, P> {
+    /// Creates a reader of the data of `r` decoded by `p`
+    pub fn new(r: R, p: P) -> Decoder<R, P> {
+        Decoder {
+            r: r,
+            p: p,
+            buf: vec![0; BUF_SIZE],
+            pos: 0,
+            end: 0,
+            eof: false,
+            done: false,
+            limit: OutputLimit::new(),
+        }
+    }
+
+    /// Returns whether the whole stream has been decoded
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream produces
+    /// more than `size` bytes
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.limit.set(size);
+    }
+
+    /// Returns the decoder, as to reset it
+    pub fn processor_mut(&mut self) -> &mut P {
+        &mut self.p
+    }
+
+    /// Drops the data read ahead and starts reading from `r`, returning the
+    /// previous reader. The decoder is left as it is.
+    pub fn reset(&mut self, r: R) -> R {
+        self.pos = 0;
+        self.end = 0;
+        self.eof = false;
+        self.done = false;
+        self.limit.reset();
+        mem::replace(&mut self.r, r)
+    }
+
+    /// Returns the wrapped reader and the decoder, dropping the data read
+    /// ahead.
+    pub fn into_inner(self) -> (R, P) {
+        (self.r, self.p)
+    }
+}
+
+impl<R: AsyncRead + Unpin, P: Process + Unpin> AsyncRead for Decoder<R, P> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, dst: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if dst.is_empty() || this.done {
+            return Poll::Ready(Ok(0))
+        }
+        let len = this.limit.clamp(dst.len());
+        let dst = &mut dst[..len];
+        loop {
+            if this.pos == this.end && !this.eof {
+                this.end = match Pin::new(&mut this.r).poll_read(cx, &mut this.buf) {
+                    Poll::Ready(Ok(n)) => n,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                };
+                this.pos = 0;
+                if this.end == 0 {
+                    this.eof = true;
+                    if let Err(e) = this.p.finish() {
+                        return Poll::Ready(Err(e))
+                    }
+                }
+            }
+            let (consumed, produced, status) = match this.p.process(&this.buf[this.pos..this.end], dst) {
+                Ok(result) => result,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            this.pos += consumed;
+            if status == Status::Done {
+                this.done = true;
+            } else if produced == 0 && this.eof && this.pos == this.end {
+                return Poll::Ready(truncated("truncated stream"))
+            }
+            if produced > 0 || this.done {
+                return Poll::Ready(this.limit.count(produced))
+            }
+        }
+    }
+}
+
+#[cfg(feature="futures-io")]
+impl<R: AsyncRead + Unpin, P: Process + Unpin> futures_io::AsyncRead for Decoder<R, P> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        AsyncRead::poll_read(self, cx, buf)
+    }
+}
+
+/// The writer of the encoders of `Encoder`, keeping their output until it is
+/// written to the asynchronous writer
+#[derive(Clone)]
+pub struct Sink {
+    buf: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Sink {
+    /// Takes the output written so far
+    fn take(&self) -> Vec<u8> {
+        mem::replace(&mut *self.buf.lock().unwrap(), Vec::new())
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An encoder ending its stream by `finish`, as those of the crate do
+pub trait Finish<W>: Write {
+    /// Writes the end of the stream, then returns the wrapped writer
+    fn finish(self) -> (W, io::Result<()>);
+}
+
+#[cfg(feature="lz4")]
+impl<W: Write> Finish<W> for lz4::Encoder<W> {
+    fn finish(self) -> (W, io::Result<()>) {
+        lz4::Encoder::finish(self)
+    }
+}
+
+#[cfg(feature="gzip")]
+impl<W: Write> Finish<W> for gzip::Encoder<W> {
+    fn finish(self) -> (W, io::Result<()>) {
+        gzip::Encoder::finish(self)
+    }
+}
+
+#[cfg(all(feature="gzip", feature="threads"))]
+impl<W: Write> Finish<W> for gzip::ParallelEncoder<W> {
+    fn finish(self) -> (W, io::Result<()>) {
+        gzip::ParallelEncoder::finish(self)
+    }
+}
+
+#[cfg(feature="bwt")]
+impl<W: Write> Finish<W> for bwt::Encoder<W> {
+    fn finish(self) -> (W, io::Result<()>) {
+        bwt::Encoder::finish(self)
+    }
+}
+
+/// Encodes the data written into an asynchronous writer
+pub struct Encoder<W, E> {
+    w: W,
+    e: Option<E>,
+    sink: Sink,
+    out: Vec<u8>,
+    out_pos: usize,
+}
+
+impl<W: AsyncWrite + Unpin, E: Finish<Sink> + Unpin> Encoder<W, E> {
+    /// Creates a writer into `w` of the data encoded by the encoder which
+    /// `encoder` creates over a `Sink`
+    pub fn new<F: FnOnce(Sink) -> E>(w: W, encoder: F) -> Encoder<W, E> {
+        let sink = Sink { buf: Arc::new(Mutex::new(Vec::new())) };
+        Encoder {
+            w: w,
+            e: Some(encoder(sink.clone())),
+            sink: sink,
+            out: Vec::new(),
+            out_pos: 0,
+        }
+    }
+
+    /// Returns the wrapped writer, dropping the encoded data not yet written
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    /// Starts a new stream into `w`, encoded by the encoder which `encoder`
+    /// creates over the same `Sink`, keeping the buffers, and returns the
+    /// previous writer. The stream is to be closed first, as the encoded data
+    /// not yet written is dropped.
+    pub fn reset<F: FnOnce(Sink) -> E>(&mut self, w: W, encoder: F) -> W {
+        self.e = None;
+        self.sink.take();
+        self.out.truncate(0);
+        self.out_pos = 0;
+        self.e = Some(encoder(self.sink.clone()));
+        mem::replace(&mut self.w, w)
+    }
+
+    /// Writes the output of the encoder, until it is all written
+    fn poll_out(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        loop {
+            if self.out_pos == self.out.len() {
+                self.out = self.sink.take();
+                self.out_pos = 0;
+                if self.out.is_empty() {
+                    return Poll::Ready(Ok(()))
+                }
+            }
+            match Pin::new(&mut self.w).poll_write(cx, &self.out[self.out_pos..]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write the encoded data"
+                ))),
+                Poll::Ready(Ok(n)) => self.out_pos += n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin, E: Finish<Sink> + Unpin> AsyncWrite for Encoder<W, E> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // the output of the previous writes is written first, bounding the
+        // data kept to that of a write
+        match this.poll_out(cx) {
+            Poll::Ready(Ok(())) => (),
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+        let result = match this.e {
+            Some(ref mut e) => e.write(buf),
+            None => Err(io::Error::new(io::ErrorKind::Other, "the encoder is closed")),
+        };
+        Poll::Ready(result)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(ref mut e) = this.e {
+            if let Err(e) = e.flush() {
+                return Poll::Ready(Err(e))
+            }
+        }
+        match this.poll_out(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.w).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(e) = this.e.take() {
+            if let (_, Err(e)) = e.finish() {
+                return Poll::Ready(Err(e))
+            }
+        }
+        match this.poll_out(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.w).poll_close(cx),
+            other => other,
+        }
+    }
+}
+
+#[cfg(feature="futures-io")]
+impl<W: AsyncWrite + Unpin, E: Finish<Sink> + Unpin> futures_io::AsyncWrite for Encoder<W, E> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(self, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_close(self, cx)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="lz4")]
+mod test {
+    use std::cmp;
+    use std::io;
+    use std::pin::Pin;
+    use std::ptr;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+    #[cfg(feature="bwt")]
+    use bwt;
+    #[cfg(feature="gzip")]
+    use gzip;
+    use lz4;
+    use process::Process;
+    use super::{AsyncRead, AsyncWrite, Decoder, Encoder, Finish, Sink};
+
+    fn noop(_: *const ()) {}
+    fn clone(_: *const ()) -> RawWaker { RawWaker::new(ptr::null(), &VTABLE) }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+    /// A stream taking or giving a few bytes, every other call
+    struct Slow {
+        data: Vec<u8>,
+        pos: usize,
+        ready: bool,
+    }
+
+    impl AsyncRead for Slow {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+            if !this.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending
+            }
+            let n = cmp::min(cmp::min(buf.len(), 7), this.data.len() - this.pos);
+            buf[..n].copy_from_slice(&this.data[this.pos..(this.pos + n)]);
+            this.pos += n;
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl AsyncWrite for Slow {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+            if !this.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending
+            }
+            let n = cmp::min(buf.len(), 7);
+            this.data.extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Codes the test data through slow streams, with the encoder `encoder`
+    /// creates and the decoder `p`
+    fn roundtrip<E, F, P>(encoder: F, p: P)
+        where E: Finish<Sink> + Unpin, F: FnOnce(Sink) -> E, P: Process + Unpin {
+        let waker = unsafe { Waker::from_raw(clone(ptr::null())) };
+        let mut cx = Context::from_waker(&waker);
+        let input = include_bytes!("data/test.txt");
+
+        let slow = Slow { data: Vec::new(), pos: 0, ready: false };
+        let mut encoder = Encoder::new(slow, encoder);
+        for chunk in input.chunks(100) {
+            while Pin::new(&mut encoder).poll_write(&mut cx, chunk).is_pending() {}
+        }
+        while Pin::new(&mut encoder).poll_close(&mut cx).is_pending() {}
+        let compressed = encoder.into_inner().data;
+
+        let slow = Slow { data: compressed, pos: 0, ready: false };
+        let mut decoder = Decoder::new(slow, p);
+        let mut output = Vec::new();
+        let mut buf = [0; 10];
+        loop {
+            match Pin::new(&mut decoder).poll_read(&mut cx, &mut buf) {
+                Poll::Ready(Ok(0)) => break,
+                Poll::Ready(Ok(n)) => output.extend_from_slice(&buf[..n]),
+                Poll::Ready(Err(e)) => panic!("{}", e),
+                Poll::Pending => {},
+            }
+        }
+        assert!(decoder.is_done());
+        assert_eq!(&output[..], &input[..]);
+    }
+
+    #[test]
+    fn lz4() {
+        roundtrip(lz4::Encoder::new, lz4::Processor::new());
+    }
+
+    #[cfg(feature="gzip")]
+    #[test]
+    fn gzip() {
+        roundtrip(|w| gzip::Encoder::new(w, 6), gzip::Processor::new());
+    }
+
+    #[cfg(feature="bwt")]
+    #[test]
+    fn bwt() {
+        roundtrip(|w| bwt::Encoder::new(w, 1000), bwt::Processor::new(true));
+    }
+
+    #[cfg(feature="futures-io")]
+    #[test]
+    fn futures_io() {
+        use futures_io;
+        use super::Compat;
+        let waker = unsafe { Waker::from_raw(clone(ptr::null())) };
+        let mut cx = Context::from_waker(&waker);
+        let input = include_bytes!("data/test.txt");
+
+        let mut encoder = Encoder::new(Compat(Vec::new()), lz4::Encoder::new);
+        let written = futures_io::AsyncWrite::poll_write(Pin::new(&mut encoder), &mut cx, input);
+        assert!(match written { Poll::Ready(Ok(n)) => n == input.len(), _ => false });
+        assert!(futures_io::AsyncWrite::poll_close(Pin::new(&mut encoder), &mut cx).is_ready());
+        let compressed = encoder.into_inner().0;
+
+        let mut decoder = Decoder::new(Compat(&compressed[..]), lz4::Processor::new());
+        let mut output = Vec::new();
+        let mut buf = [0; 1000];
+        loop {
+            match futures_io::AsyncRead::poll_read(Pin::new(&mut decoder), &mut cx, &mut buf) {
+                Poll::Ready(Ok(0)) => break,
+                Poll::Ready(Ok(n)) => output.extend_from_slice(&buf[..n]),
+                Poll::Ready(Err(e)) => panic!("{}", e),
+                Poll::Pending => panic!("the data is all there"),
+            }
+        }
+        assert_eq!(&output[..], &input[..]);
+    }
+
+    #[test]
+    fn reset() {
+        let waker = unsafe { Waker::from_raw(clone(ptr::null())) };
+        let mut cx = Context::from_waker(&waker);
+        let input = include_bytes!("data/test.lz4.1");
+        let mut decoder = Decoder::new(&input[..100], lz4::Processor::new());
+        let mut buf = [0; 10];
+        assert!(Pin::new(&mut decoder).poll_read(&mut cx, &mut buf).is_ready());
+        decoder.processor_mut().reset();
+        decoder.reset(&input[..]);
+        let mut output = Vec::new();
+        loop {
+            match Pin::new(&mut decoder).poll_read(&mut cx, &mut buf) {
+                Poll::Ready(Ok(0)) => break,
+                Poll::Ready(Ok(n)) => output.extend_from_slice(&buf[..n]),
+                Poll::Ready(Err(e)) => panic!("{}", e),
+                Poll::Pending => panic!("the data is all there"),
+            }
+        }
+        assert!(decoder.is_done());
+        assert_eq!(&output[..], &include_bytes!("data/test.txt")[..]);
+    }
+
+    #[test]
+    fn encoder_reset() {
+        let waker = unsafe { Waker::from_raw(clone(ptr::null())) };
+        let mut cx = Context::from_waker(&waker);
+        let input = include_bytes!("data/test.txt");
+        let encode = |encoder: &mut Encoder<Slow, lz4::Encoder<_>>, data: &[u8], cx: &mut Context| {
+            for chunk in data.chunks(100) {
+                while Pin::new(&mut *encoder).poll_write(cx, chunk).is_pending() {}
+            }
+            while Pin::new(&mut *encoder).poll_close(cx).is_pending() {}
+        };
+
+        let mut encoder = Encoder::new(Slow { data: Vec::new(), pos: 0, ready: false }, lz4::Encoder::new);
+        encode(&mut encoder, &input[..1000], &mut cx);
+        let first = encoder.reset(Slow { data: Vec::new(), pos: 0, ready: false }, lz4::Encoder::new);
+        encode(&mut encoder, &input[1000..], &mut cx);
+        let second = encoder.into_inner().data;
+
+        let mut fresh = Encoder::new(Slow { data: Vec::new(), pos: 0, ready: false }, lz4::Encoder::new);
+        encode(&mut fresh, &input[1000..], &mut cx);
+        assert_eq!(second, fresh.into_inner().data);
+        let mut fresh = Encoder::new(Slow { data: Vec::new(), pos: 0, ready: false }, lz4::Encoder::new);
+        encode(&mut fresh, &input[..1000], &mut cx);
+        assert_eq!(first.data, fresh.into_inner().data);
+    }
+
+    #[test]
+    fn truncated() {
+        let waker = unsafe { Waker::from_raw(clone(ptr::null())) };
+        let mut cx = Context::from_waker(&waker);
+        let input = include_bytes!("data/test.lz4.1");
+        let mut decoder = Decoder::new(&input[..100], lz4::Processor::new());
+        let mut buf = [0; 1 << 16];
+        loop {
+            match Pin::new(&mut decoder).poll_read(&mut cx, &mut buf) {
+                Poll::Ready(Ok(n)) => assert!(n > 0),
+                Poll::Ready(Err(_)) => break,
+                Poll::Pending => panic!("the data is all there"),
+            }
+        }
+    }
+}
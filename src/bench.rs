@@ -0,0 +1,373 @@
+/*!
+
+Benchmarks of the codecs on corpora of files. Requires `bench` feature
+
+The standard corpora, as those of Calgary, Canterbury and Silesia, are
+downloaded from their sites and unpacked into a directory, which
+`Corpus::load` reads; `Corpus::samples` generates a few kinds of data instead,
+to run without them. `run` compresses each file with each codec, keeping the
+fastest of several iterations, checks the data decompresses back, and measures
+the ratio and the throughput of both ways, which `report` prints with the
+totals of each codec, so that the regressions across the crate show up with a
+single command: `compress corpus -iter3 path/to/corpus`.
+
+# Example
+
+```rust
+use compress::bench::{self, Corpus};
+
+let corpus = Corpus {
+    name: "tiny".to_string(),
+    files: vec![("text".to_string(), b"abracadabra abracadabra".to_vec())],
+};
+let measures = bench::run(&bench::codecs(), &corpus, 1).unwrap();
+let mut table = Vec::new();
+bench::report(&measures, &mut table).unwrap();
+```
+
+*/
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use codec::{Compressor, Decompressor};
+
+/// Files to benchmark the codecs on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Corpus {
+    /// Name of the corpus
+    pub name: String,
+    /// Name and data of each file
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+impl Corpus {
+    /// Loads the files of the directory `dir`, without its subdirectories,
+    /// in the order of their names
+    pub fn load<P: AsRef<Path>>(dir: P) -> io::Result<Corpus> {
+        let dir = dir.as_ref();
+        let mut files = Vec::new();
+        for entry in try!(fs::read_dir(dir)) {
+            let entry = try!(entry);
+            if !try!(entry.file_type()).is_file() {
+                continue
+            }
+            let mut data = Vec::new();
+            try!(try!(fs::File::open(entry.path())).read_to_end(&mut data));
+            files.push((entry.file_name().to_string_lossy().into_owned(), data));
+        }
+        files.sort();
+        let name = dir.file_name().map_or_else(|| dir.to_string_lossy(), |name| name.to_string_lossy());
+        Ok(Corpus { name: name.into_owned(), files: files })
+    }
+
+    /// Generates 256 kB of each of text made of words, runs of bytes, a table
+    /// of growing integers and noise
+    pub fn samples() -> Corpus {
+        const SIZE: usize = 256 << 10;
+        const WORDS: [&str; 16] = [
+            "the", "of", "and", "compression", "data", "block", "to", "in",
+            "a", "stream", "is", "codec", "that", "with", "for", "bytes",
+        ];
+        let mut seed = 0x2545f491u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+
+        let mut text = Vec::with_capacity(SIZE);
+        while text.len() < SIZE {
+            let n = next();
+            text.extend_from_slice(WORDS[n as usize % 16].as_bytes());
+            text.push(if n >> 28 == 0 { b'\n' } else { b' ' });
+        }
+        let mut runs = Vec::with_capacity(SIZE);
+        while runs.len() < SIZE {
+            let n = next();
+            runs.extend((0..(n >> 24)).map(|_| n as u8));
+        }
+        let mut table = Vec::with_capacity(SIZE);
+        let mut value = 0u32;
+        while table.len() < SIZE {
+            value += next() & 0xff;
+            table.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+        }
+        let noise = (0..SIZE).map(|_| next() as u8).collect();
+
+        let files = vec![("text", text), ("runs", runs), ("table", table), ("noise", noise)];
+        Corpus {
+            name: "samples".to_string(),
+            files: files.into_iter().map(|(name, mut data)| {
+                data.truncate(SIZE);
+                (name.to_string(), data)
+            }).collect(),
+        }
+    }
+
+    /// Returns the size of all the files
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|&(_, ref data)| data.len() as u64).sum()
+    }
+}
+
+/// Both ways of a codec
+trait Coder {
+    fn compress(&self, input: &[u8]) -> io::Result<Vec<u8>>;
+    fn decompress(&self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()>;
+}
+
+impl<C: Compressor + Decompressor> Coder for C {
+    fn compress(&self, input: &[u8]) -> io::Result<Vec<u8>> {
+        Compressor::compress_to_vec(self, input)
+    }
+
+    fn decompress(&self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        Decompressor::decompress(self, input, output)
+    }
+}
+
+/// A codec with the settings benchmarked
+pub struct Codec {
+    /// Name of the codec and of its settings
+    pub name: String,
+    coder: Box<dyn Coder>,
+}
+
+impl Codec {
+    /// Names the codec, which both compresses and decompresses
+    pub fn new<C: Compressor + Decompressor + 'static>(name: &str, codec: C) -> Codec {
+        Codec { name: name.to_string(), coder: Box::new(codec) }
+    }
+}
+
+/// Returns the codecs of the enabled features which compress, at a few
+/// settings each
+pub fn codecs() -> Vec<Codec> {
+    #[allow(unused_mut)]
+    let mut codecs = Vec::new();
+    #[cfg(feature="flate")]
+    {
+        use codec::Deflate;
+        codecs.push(Codec::new("deflate -1", Deflate { level: 1 }));
+        codecs.push(Codec::new("deflate -6", Deflate { level: 6 }));
+        codecs.push(Codec::new("deflate -9", Deflate { level: 9 }));
+    }
+    #[cfg(feature="gzip")]
+    codecs.push(Codec::new("gzip -6", ::codec::Gzip { level: 6 }));
+    #[cfg(feature="bzip2")]
+    {
+        use codec::Bzip2;
+        codecs.push(Codec::new("bzip2 -1", Bzip2 { level: 1 }));
+        codecs.push(Codec::new("bzip2 -9", Bzip2 { level: 9 }));
+    }
+    #[cfg(feature="bwt")]
+    codecs.push(Codec::new("bwt 1 MB", ::codec::Bwt { block_size: 1 << 20 }));
+    #[cfg(feature="lz4")]
+    codecs.push(Codec::new("lz4", ::codec::Lz4));
+    #[cfg(feature="snappy")]
+    codecs.push(Codec::new("snappy", ::codec::Snappy));
+    #[cfg(feature="lzw")]
+    codecs.push(Codec::new("compress -b16", ::codec::Z { max_bits: 16 }));
+    #[cfg(feature="lz78")]
+    codecs.push(Codec::new("lz78 16 bits", ::codec::Lz78 { max_bits: 16 }));
+    #[cfg(feature="lzp")]
+    codecs.push(Codec::new("lzp 32", ::codec::Lzp { min_len: 32 }));
+    #[cfg(feature="lzss")]
+    codecs.push(Codec::new("lzss", ::codec::Lzss { config: Default::default() }));
+    #[cfg(feature="lzf")]
+    codecs.push(Codec::new("lzf", ::codec::Lzf));
+    #[cfg(feature="chameleon")]
+    codecs.push(Codec::new("chameleon", ::codec::Chameleon));
+    #[cfg(feature="rolz")]
+    codecs.push(Codec::new("rolz", ::codec::Rolz));
+    #[cfg(feature="bpe")]
+    codecs.push(Codec::new("bpe", ::codec::Bpe));
+    #[cfg(feature="rle")]
+    codecs.push(Codec::new("rle zero", ::codec::Rle { scheme: ::rle::Scheme::Zero }));
+    codecs
+}
+
+/// The results of a codec on a file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Measure {
+    /// Name of the codec
+    pub codec: String,
+    /// Name of the file
+    pub file: String,
+    /// Size of the file
+    pub input: u64,
+    /// Size of the compressed file
+    pub output: u64,
+    /// Time spent compressing
+    pub compress: Duration,
+    /// Time spent decompressing
+    pub decompress: Duration,
+}
+
+impl Measure {
+    /// Returns the size of the compressed file relative to the file
+    pub fn ratio(&self) -> f64 {
+        if self.input == 0 {
+            return 1.0
+        }
+        self.output as f64 / self.input as f64
+    }
+
+    /// Returns the throughput of the compression, in MB/s of the file
+    pub fn compress_speed(&self) -> f64 {
+        speed(self.input, self.compress)
+    }
+
+    /// Returns the throughput of the decompression, in MB/s of the file
+    pub fn decompress_speed(&self) -> f64 {
+        speed(self.input, self.decompress)
+    }
+}
+
+fn speed(size: u64, time: Duration) -> f64 {
+    let secs = time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9;
+    size as f64 / (1 << 20) as f64 / secs.max(1e-9)
+}
+
+/// Compresses then decompresses each file of `corpus` with each codec,
+/// keeping the fastest of `iterations` times, and fails when a file does not
+/// decompress back
+pub fn run(codecs: &[Codec], corpus: &Corpus, iterations: u32) -> io::Result<Vec<Measure>> {
+    let mut measures = Vec::with_capacity(codecs.len() * corpus.files.len());
+    for codec in codecs.iter() {
+        for &(ref file, ref data) in corpus.files.iter() {
+            let (mut compress, mut decompress) = (Duration::from_secs(u64::MAX), Duration::from_secs(u64::MAX));
+            let mut output = 0;
+            for _ in 0..iterations.max(1) {
+                let start = Instant::now();
+                let compressed = try!(codec.coder.compress(data));
+                compress = compress.min(start.elapsed());
+                output = compressed.len() as u64;
+
+                let mut decompressed = Vec::with_capacity(data.len());
+                let start = Instant::now();
+                try!(codec.coder.decompress(&compressed, &mut decompressed));
+                decompress = decompress.min(start.elapsed());
+                if decompressed != *data {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              format!("{} decompressed {} to other data", codec.name, file)))
+                }
+            }
+            measures.push(Measure {
+                codec: codec.name.clone(),
+                file: file.clone(),
+                input: data.len() as u64,
+                output: output,
+                compress: compress,
+                decompress: decompress,
+            });
+        }
+    }
+    Ok(measures)
+}
+
+/// Sums the measures of each codec, in the order they come, under the file
+/// name "total"
+pub fn totals(measures: &[Measure]) -> Vec<Measure> {
+    let mut totals: Vec<Measure> = Vec::new();
+    for m in measures.iter() {
+        match totals.iter_mut().find(|t| t.codec == m.codec) {
+            Some(t) => {
+                t.input += m.input;
+                t.output += m.output;
+                t.compress += m.compress;
+                t.decompress += m.decompress;
+            }
+            None => totals.push(Measure { file: "total".to_string(), ..m.clone() }),
+        }
+    }
+    totals
+}
+
+/// Writes a table of the measures, then of the totals of each codec
+pub fn report<W: Write>(measures: &[Measure], w: &mut W) -> io::Result<()> {
+    let line = |w: &mut W, m: &Measure| {
+        writeln!(w, "{:<16} {:<16} {:>12} {:>12} {:>7.2}% {:>10.2} {:>10.2}", m.codec, m.file, m.input,
+                 m.output, 100.0 * m.ratio(), m.compress_speed(), m.decompress_speed())
+    };
+    try!(writeln!(w, "{:<16} {:<16} {:>12} {:>12} {:>8} {:>10} {:>10}", "codec", "file", "size",
+                  "compressed", "ratio", "comp MB/s", "dec MB/s"));
+    for m in measures.iter() {
+        try!(line(w, m));
+    }
+    try!(writeln!(w));
+    for m in totals(measures).iter() {
+        try!(line(w, m));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::{self, Read, Write};
+    use codec::{Compressor, Decompressor};
+    use super::{codecs, report, run, totals, Codec, Corpus};
+
+    /// A codec losing the last byte
+    struct Lossy;
+
+    impl Compressor for Lossy {
+        fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+            let mut data = Vec::new();
+            try!(input.read_to_end(&mut data));
+            data.pop();
+            try!(output.write_all(&data));
+            Ok(data.len() as u64)
+        }
+    }
+
+    impl Decompressor for Lossy {
+        fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+            input
+        }
+    }
+
+    #[test]
+    fn corpus() {
+        let samples = Corpus::samples();
+        assert_eq!(samples.files.len(), 4);
+        assert_eq!(samples.size(), 4 * (256 << 10));
+
+        let dir = ::std::env::temp_dir().join("compress-bench-corpus");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::File::create(dir.join("b")).unwrap().write_all(b"bb").unwrap();
+        fs::File::create(dir.join("a")).unwrap().write_all(b"a").unwrap();
+        let corpus = Corpus::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(corpus.name, "compress-bench-corpus");
+        assert_eq!(corpus.files, vec![("a".to_string(), b"a".to_vec()), ("b".to_string(), b"bb".to_vec())]);
+    }
+
+    #[test]
+    fn measures() {
+        let mut corpus = Corpus::samples();
+        for file in corpus.files.iter_mut() {
+            file.1.truncate(10000);
+        }
+        let codecs = codecs();
+        let measures = run(&codecs, &corpus, 2).unwrap();
+        assert_eq!(measures.len(), codecs.len() * 4);
+        let totals = totals(&measures);
+        assert_eq!(totals.len(), codecs.len());
+        for t in totals.iter() {
+            assert_eq!(t.input, 40000);
+            assert_eq!(t.file, "total");
+        }
+        let mut table = Vec::new();
+        report(&measures, &mut table).unwrap();
+        assert_eq!(table.iter().filter(|&&b| b == b'\n').count(), 1 + measures.len() + 1 + totals.len());
+
+        assert!(run(&[Codec::new("lossy", Lossy)], &corpus, 1).is_err());
+    }
+}
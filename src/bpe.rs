@@ -0,0 +1,492 @@
+/*!
+
+Byte Pair Encoding Decompression and Compression. Requires `bpe` feature,
+enabled by default
+
+This module contains an implementation of the byte pair encoding of Philip
+Gage: the most frequent pair of adjacent bytes of a block is replaced by a
+byte which the block doesn't hold, and so on until no byte is left unused or
+no pair is frequent enough. Shrinking the data without widening its alphabet
+much, it works well as a stage before the entropy coders of the crate.
+
+The data is cut in blocks of 16 kB, each made of the number of pairs, the
+pairs as the byte replacing them followed by the bytes of the pair, in the
+order of their replacement, the size of the packed data, in 16 bits
+little-endian, and the packed data. Whole buffers are handled by
+`decode_block` and `encode_block`, while streams are exposed as standard
+`Reader` and `Writer` interfaces wrapping an underlying stream.
+
+# Example
+
+```rust,ignore
+use compress::bpe;
+use std::fs::File;
+use std::path::Path;
+use std::io::Read;
+
+let stream = File::open(&Path::new("path/to/file.bpe")).unwrap();
+let mut decompressed = Vec::new();
+bpe::Decoder::new(stream).read_to_end(&mut decompressed);
+```
+
+# Related links
+
+* http://www.pennelynn.com/Documents/CUJ/HTML/94HTML/19940045.HTM - the
+  article of Philip Gage describing the algorithm
+
+*/
+
+use std::cmp;
+use std::mem;
+use std::io::{self, Read, Write};
+
+use error::{corrupt, truncated};
+use process::{drain, gather, Process, Reader, Status};
+
+/// Largest amount of data in a block
+const BLOCK_SIZE: usize = 1 << 14;
+/// A pair is replaced only if it saves more than the size of its entry
+const MIN_COUNT: u16 = 4;
+
+/// The pairs replaced by the bytes of a block
+struct Table {
+    pairs: [(u8, u8); 256],
+    is_code: [bool; 256],
+}
+
+impl Table {
+    /// Reads the table of the pairs, whose bytes may only replace the earlier
+    /// pairs, so that the pairs expand without a cycle
+    fn new(entries: &[u8]) -> io::Result<Table> {
+        let mut table = Table { pairs: [(0, 0); 256], is_code: [false; 256] };
+        let mut seen = [false; 256];
+        for entry in entries.chunks(3) {
+            let code = entry[0] as usize;
+            if table.is_code[code] || seen[code] || entry[1..].iter().any(|&b| b as usize == code) {
+                return corrupt("invalid bpe pair")
+            }
+            table.pairs[code] = (entry[1], entry[2]);
+            table.is_code[code] = true;
+            seen[entry[1] as usize] = true;
+            seen[entry[2] as usize] = true;
+        }
+        Ok(table)
+    }
+
+    /// Expands the packed data of a block into `output`
+    fn expand(&self, data: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        let limit = output.len() + BLOCK_SIZE;
+        let mut stack = Vec::new();
+        for &byte in data.iter() {
+            stack.push(byte);
+            while let Some(byte) = stack.pop() {
+                if self.is_code[byte as usize] {
+                    let (left, right) = self.pairs[byte as usize];
+                    stack.push(right);
+                    stack.push(left);
+                } else {
+                    if output.len() == limit {
+                        return corrupt("bpe block too large")
+                    }
+                    output.push(byte);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the blocks of the input, appending the data to `output`
+pub fn decode_block(input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+    let mut pos = 0;
+    while pos < input.len() {
+        let header = 1 + 3 * input[pos] as usize + 2;
+        if input.len() - pos < header {
+            return truncated("truncated bpe header")
+        }
+        let table = try!(Table::new(&input[(pos + 1)..(pos + header - 2)]));
+        let len = input[pos + header - 2] as usize | (input[pos + header - 1] as usize) << 8;
+        pos += header;
+        if len > input.len() - pos {
+            return truncated("truncated bpe block")
+        }
+        try!(table.expand(&input[pos..(pos + len)], output));
+        pos += len;
+    }
+    Ok(())
+}
+
+/// Packs a block of no more than `BLOCK_SIZE` bytes, appended to `output`
+fn encode_chunk(input: &[u8], counts: &mut [u16], output: &mut Vec<u8>) {
+    let mut data = input.to_vec();
+    let mut used = [false; 256];
+    for &byte in data.iter() {
+        used[byte as usize] = true;
+    }
+    let codes = (0..256).filter(|&c| !used[c]).map(|c| c as u8);
+    let mut table: Vec<u8> = Vec::new();
+    for code in codes {
+        // count the pairs, those of the runs without overlap
+        let (mut best, mut best_count) = (0, 0);
+        let mut i = 0;
+        while i + 1 < data.len() {
+            let pair = (data[i] as usize) << 8 | data[i + 1] as usize;
+            counts[pair] += 1;
+            if counts[pair] > best_count {
+                best = pair;
+                best_count = counts[pair];
+            }
+            i += if data[i] == data[i + 1] && data.get(i + 2) == Some(&data[i]) { 2 } else { 1 };
+        }
+        for pair in data.windows(2) {
+            counts[(pair[0] as usize) << 8 | pair[1] as usize] = 0;
+        }
+        if best_count < MIN_COUNT {
+            break
+        }
+        let (left, right) = ((best >> 8) as u8, best as u8);
+        let (mut r, mut w) = (0, 0);
+        while r < data.len() {
+            if data[r] == left && data.get(r + 1) == Some(&right) {
+                data[w] = code;
+                r += 2;
+            } else {
+                data[w] = data[r];
+                r += 1;
+            }
+            w += 1;
+        }
+        data.truncate(w);
+        table.extend([code, left, right].iter());
+    }
+    output.push((table.len() / 3) as u8);
+    output.extend(table);
+    output.push(data.len() as u8);
+    output.push((data.len() >> 8) as u8);
+    output.extend(data);
+}
+
+/// Encodes the input into blocks, appended to `output`
+pub fn encode_block(input: &[u8], output: &mut Vec<u8>) {
+    let mut counts = vec![0; 1 << 16];
+    for chunk in input.chunks(BLOCK_SIZE) {
+        encode_chunk(chunk, &mut counts, output);
+    }
+}
+
+/// Length of the header of a block, its table of pairs and the size of its
+/// data, once enough of it is gathered to tell
+fn header_len(head: &[u8]) -> usize {
+    match head.first() {
+        Some(&n) => 3 * n as usize + 3,
+        None => 1,
+    }
+}
+
+/// This structure is used to decode a stream of BPE blocks, fed with slices
+/// of it. The stream ends with its input.
+pub struct Processor {
+    head: Vec<u8>,
+    /// The table of the block, once its header is read
+    table: Option<Table>,
+    block: Vec<u8>,
+    output: Vec<u8>,
+    start: usize,
+    eof: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder, waiting for the first block
+    pub fn new() -> Processor {
+        Processor {
+            head: Vec::new(),
+            table: None,
+            block: Vec::new(),
+            output: Vec::new(),
+            start: 0,
+            eof: false,
+        }
+    }
+
+    /// Starts decoding a new stream, keeping the buffers
+    pub fn reset(&mut self) {
+        self.head.truncate(0);
+        self.table = None;
+        self.block.truncate(0);
+        self.output.truncate(0);
+        self.start = 0;
+        self.eof = false;
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.output, &mut self.start, output, &mut produced) {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            let gathered = match self.table {
+                None => {
+                    let len = header_len(&self.head);
+                    gather(&mut self.head, input, &mut pos, len)
+                }
+                Some(_) => {
+                    let end = self.head.len();
+                    let len = self.head[end - 2] as usize | (self.head[end - 1] as usize) << 8;
+                    gather(&mut self.block, input, &mut pos, len)
+                }
+            };
+            if !gathered {
+                let status = if self.eof { Status::Done } else { Status::NeedInput };
+                return Ok((pos, produced, status))
+            }
+            match self.table.take() {
+                None if self.head.len() < header_len(&self.head) => {}
+                None => {
+                    let end = self.head.len() - 2;
+                    self.table = Some(try!(Table::new(&self.head[1..end])));
+                }
+                Some(table) => {
+                    self.output.truncate(0);
+                    self.start = 0;
+                    try!(table.expand(&self.block, &mut self.output));
+                    self.head.truncate(0);
+                    self.block.truncate(0);
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the stream ends in between the blocks
+        if self.table.is_some() || !self.head.is_empty() {
+            return truncated("truncated bpe block")
+        }
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a stream of BPE blocks, which ends with
+/// the underlying stream. The wrapped stream can be re-acquired through the
+/// unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new()),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Returns whether the end of the data has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.is_done()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+/// This structure is used to compress a stream of bytes into BPE blocks. This
+/// is a wrapper around an internal writer which bytes will be written to.
+pub struct Encoder<W> {
+    w: W,
+    buf: Vec<u8>,
+    output: Vec<u8>,
+    counts: Vec<u16>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will have its output written to the given
+    /// output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder {
+            w: w,
+            buf: Vec::with_capacity(BLOCK_SIZE),
+            output: Vec::new(),
+            counts: vec![0; 1 << 16],
+        }
+    }
+
+    fn encode_block(&mut self) -> io::Result<()> {
+        self.output.truncate(0);
+        encode_chunk(&self.buf, &mut self.counts, &mut self.output);
+        self.buf.truncate(0);
+        self.w.write_all(&self.output)
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.flush();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`,
+    /// keeping the buffers and the table of the pair counts. The previous
+    /// writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.flush();
+        (mem::replace(&mut self.w, w), result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let amt = cmp::min(rest.len(), BLOCK_SIZE - self.buf.len());
+            self.buf.extend(rest[..amt].iter().cloned());
+            if self.buf.len() == BLOCK_SIZE {
+                try!(self.encode_block());
+            }
+            rest = &rest[amt..];
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            try!(self.encode_block());
+        }
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing::{noise, push};
+    use super::{Decoder, Encoder, Processor, decode_block, encode_block};
+
+    fn roundtrip(input: &[u8]) -> usize {
+        let mut encoded = Vec::new();
+        encode_block(input, &mut encoded);
+        let mut decoded = Vec::new();
+        decode_block(&encoded, &mut decoded).unwrap();
+        assert!(&decoded[..] == input);
+        encoded.len()
+    }
+
+    #[test]
+    fn known_data() {
+        // "ab" is replaced by 0, then "00" by 1
+        let expected = [2, 0, b'a', b'b', 1, 0, 0, 6, 0, 1, 1, 1, 1, 0, b'c'];
+        let mut out = Vec::new();
+        encode_block(b"abababababababababc", &mut out);
+        assert_eq!(&out[..], &expected[..]);
+        out.truncate(0);
+        decode_block(&expected, &mut out).unwrap();
+        assert_eq!(&out[..], b"abababababababababc");
+        // pairs seen less than 4 times are left
+        out.truncate(0);
+        encode_block(b"abcabcabc", &mut out);
+        assert_eq!(&out[..], b"\x00\x09\x00abcabcabc");
+        assert_eq!(roundtrip(b""), 0);
+    }
+
+    #[test]
+    fn roundtrips() {
+        roundtrip(b"a");
+        roundtrip(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+        let text = include_bytes!("data/test.txt");
+        assert!(roundtrip(text) < text.len() * 3 / 4);
+        roundtrip(&noise(100000, 7));
+        let runs: Vec<u8> = (0..100000).map(|i| (i / 1000) as u8).collect();
+        assert!(roundtrip(&runs) < runs.len() / 10);
+    }
+
+    #[test]
+    fn streams() {
+        let input = include_bytes!("data/test.txt");
+        let mut e = Encoder::new(Vec::new());
+        for chunk in input.chunks(100) {
+            e.write_all(chunk).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let mut expected = Vec::new();
+        encode_block(input, &mut expected);
+        assert_eq!(encoded, expected);
+        let mut d = Decoder::new(BufReader::new(&encoded[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(&out[..] == &input[..]);
+        assert!(&push(&mut Processor::new(), &encoded, 11).unwrap()[..] == &input[..]);
+    }
+
+    #[test]
+    fn reset() {
+        let (first, second) = (include_bytes!("data/test.txt"), noise(20000, 5));
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(first).unwrap();
+        let (a, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(&second).unwrap();
+        let (b, result) = e.finish();
+        result.unwrap();
+        let mut fresh = Encoder::new(Vec::new());
+        fresh.write_all(&second).unwrap();
+        assert!(b == fresh.finish().0);
+
+        let mut d = Decoder::new(BufReader::new(&a[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(&out[..] == &first[..]);
+        d.reset(BufReader::new(&b[..]));
+        out.truncate(0);
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof() && out == second);
+    }
+
+    #[test]
+    fn invalid_data() {
+        for input in [&[1, 0, b'a'][..], // truncated header
+                      &[0, 3, 0, b'a'], // truncated block
+                      &[1, 0, 0, b'a', 1, 0, 0], // pair holding its byte
+                      &[2, 0, b'a', b'b', 0, b'c', b'd', 0, 0], // pair replaced twice
+                      &[2, 0, 1, 1, 1, 0, 0, 1, 0, 0]].iter() { // cycle of pairs
+            assert!(decode_block(input, &mut Vec::new()).is_err());
+        }
+        // a block expanding beyond the size of the blocks
+        let mut bomb = vec![20];
+        for i in 0..20 {
+            bomb.extend([i + 1, i, i].iter());
+        }
+        bomb.extend([1, 0, 20].iter());
+        assert!(decode_block(&bomb, &mut Vec::new()).is_err());
+        let mut d = Decoder::new(BufReader::new(&bomb[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! The static dictionary of brotli, and the transforms of its words
+//!
+//! The words of each length from 4 to 24 follow each other in the data of
+//! the dictionary. A reference to the dictionary gives the index of a word
+//! and one of 121 transforms, which adds a prefix and a suffix to the word,
+//! and may cut its ends or uppercase it.
+
+/// The words, as given by RFC 7932
+pub static DICTIONARY: &'static [u8] = include_bytes!("dictionary.bin");
+
+pub const MIN_WORD_LENGTH: usize = 4;
+pub const MAX_WORD_LENGTH: usize = 24;
+
+/// Number of bits of the index of the words of each length
+pub static SIZE_BITS: [u8; 25] = [
+    0, 0, 0, 0, 10, 10, 11, 11, 10, 10, 10, 10, 10,
+    9, 9, 8, 7, 7, 8, 7, 7, 6, 6, 5, 5,
+];
+
+/// Position of the first word of each length
+pub static OFFSETS: [u32; 25] = [
+    0, 0, 0, 0, 0, 4096, 9216, 21504,
+    35840, 44032, 53248, 63488, 74752, 87040, 93696, 100864,
+    104704, 106752, 108928, 113536, 115968, 118528, 119872, 121280,
+    122016,
+];
+
+/// Kinds of transforms, after the ones which omit the last 0 to 9 bytes
+const OMIT_LAST_9: u8 = 9;
+const UPPERCASE_FIRST: u8 = 10;
+/// Then come the ones which omit the first 1 to 9 bytes
+const OMIT_FIRST_1: u8 = 12;
+
+/// Prefix, kind and suffix of the transforms
+pub static TRANSFORMS: [(&'static [u8], u8, &'static [u8]); 121] = [
+    (b"", 0, b""),
+    (b"", 0, b" "),
+    (b" ", 0, b" "),
+    (b"", 12, b""),
+    (b"", 10, b" "),
+    (b"", 0, b" the "),
+    (b" ", 0, b""),
+    (b"s ", 0, b" "),
+    (b"", 0, b" of "),
+    (b"", 10, b""),
+    (b"", 0, b" and "),
+    (b"", 13, b""),
+    (b"", 1, b""),
+    (b", ", 0, b" "),
+    (b"", 0, b", "),
+    (b" ", 10, b" "),
+    (b"", 0, b" in "),
+    (b"", 0, b" to "),
+    (b"e ", 0, b" "),
+    (b"", 0, b"\""),
+    (b"", 0, b"."),
+    (b"", 0, b"\">"),
+    (b"", 0, b"\n"),
+    (b"", 3, b""),
+    (b"", 0, b"]"),
+    (b"", 0, b" for "),
+    (b"", 14, b""),
+    (b"", 2, b""),
+    (b"", 0, b" a "),
+    (b"", 0, b" that "),
+    (b" ", 10, b""),
+    (b"", 0, b". "),
+    (b".", 0, b""),
+    (b" ", 0, b", "),
+    (b"", 15, b""),
+    (b"", 0, b" with "),
+    (b"", 0, b"'"),
+    (b"", 0, b" from "),
+    (b"", 0, b" by "),
+    (b"", 16, b""),
+    (b"", 17, b""),
+    (b" the ", 0, b""),
+    (b"", 4, b""),
+    (b"", 0, b". The "),
+    (b"", 11, b""),
+    (b"", 0, b" on "),
+    (b"", 0, b" as "),
+    (b"", 0, b" is "),
+    (b"", 7, b""),
+    (b"", 1, b"ing "),
+    (b"", 0, b"\n\x09"),
+    (b"", 0, b":"),
+    (b" ", 0, b". "),
+    (b"", 0, b"ed "),
+    (b"", 20, b""),
+    (b"", 18, b""),
+    (b"", 6, b""),
+    (b"", 0, b"("),
+    (b"", 10, b", "),
+    (b"", 8, b""),
+    (b"", 0, b" at "),
+    (b"", 0, b"ly "),
+    (b" the ", 0, b" of "),
+    (b"", 5, b""),
+    (b"", 9, b""),
+    (b" ", 10, b", "),
+    (b"", 10, b"\""),
+    (b".", 0, b"("),
+    (b"", 11, b" "),
+    (b"", 10, b"\">"),
+    (b"", 0, b"=\""),
+    (b" ", 0, b"."),
+    (b".com/", 0, b""),
+    (b" the ", 0, b" of the "),
+    (b"", 10, b"'"),
+    (b"", 0, b". This "),
+    (b"", 0, b","),
+    (b".", 0, b" "),
+    (b"", 10, b"("),
+    (b"", 10, b"."),
+    (b"", 0, b" not "),
+    (b" ", 0, b"=\""),
+    (b"", 0, b"er "),
+    (b" ", 11, b" "),
+    (b"", 0, b"al "),
+    (b" ", 11, b""),
+    (b"", 0, b"='"),
+    (b"", 11, b"\""),
+    (b"", 10, b". "),
+    (b" ", 0, b"("),
+    (b"", 0, b"ful "),
+    (b" ", 10, b". "),
+    (b"", 0, b"ive "),
+    (b"", 0, b"less "),
+    (b"", 11, b"'"),
+    (b"", 0, b"est "),
+    (b" ", 10, b"."),
+    (b"", 11, b"\">"),
+    (b" ", 0, b"='"),
+    (b"", 10, b","),
+    (b"", 0, b"ize "),
+    (b"", 11, b"."),
+    (b"\xc2\xa0", 0, b""),
+    (b" ", 0, b","),
+    (b"", 10, b"=\""),
+    (b"", 11, b"=\""),
+    (b"", 0, b"ous "),
+    (b"", 11, b", "),
+    (b"", 10, b"='"),
+    (b" ", 10, b","),
+    (b" ", 11, b"=\""),
+    (b" ", 11, b", "),
+    (b"", 11, b","),
+    (b"", 11, b"("),
+    (b"", 11, b". "),
+    (b" ", 11, b"."),
+    (b"", 11, b"='"),
+    (b" ", 11, b". "),
+    (b" ", 10, b"=\""),
+    (b" ", 11, b"='"),
+    (b" ", 10, b"='"),
+];
+
+/// Uppercases the character at the start of `p` the way brotli does,
+/// returning its length
+fn uppercase(p: &mut [u8]) -> usize {
+    if p[0] < 0xc0 {
+        if p[0] >= b'a' && p[0] <= b'z' {
+            p[0] ^= 32;
+        }
+        1
+    } else if p[0] < 0xe0 {
+        if p.len() > 1 {
+            p[1] ^= 32;
+        }
+        2
+    } else {
+        if p.len() > 2 {
+            p[2] ^= 5;
+        }
+        3
+    }
+}
+
+/// Appends the word of the given length and index to `out`, transformed
+pub fn write_word(len: usize, index: usize, transform: usize, out: &mut Vec<u8>) {
+    let start = OFFSETS[len] as usize + index * len;
+    let word = &DICTIONARY[start..(start + len)];
+    let (prefix, kind, suffix) = TRANSFORMS[transform];
+    out.extend(prefix.iter().cloned());
+    let pos = out.len();
+    if kind <= OMIT_LAST_9 {
+        out.extend(word[..len.saturating_sub(kind as usize)].iter().cloned());
+    } else if kind >= OMIT_FIRST_1 {
+        let skip = (kind - OMIT_FIRST_1 + 1) as usize;
+        if skip < len {
+            out.extend(word[skip..].iter().cloned());
+        }
+    } else {
+        out.extend(word.iter().cloned());
+        if kind == UPPERCASE_FIRST {
+            uppercase(&mut out[pos..]);
+        } else {
+            let mut i = pos;
+            while i < out.len() {
+                i += uppercase(&mut out[i..]);
+            }
+        }
+    }
+    out.extend(suffix.iter().cloned());
+}
+
+#[cfg(test)]
+mod test {
+    use super::write_word;
+
+    fn word(len: usize, index: usize, transform: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_word(len, index, transform, &mut out);
+        out
+    }
+
+    #[test]
+    fn transforms() {
+        assert_eq!(word(4, 0, 0), b"time");
+        assert_eq!(word(4, 0, 1), b"time ");
+        assert_eq!(word(4, 0, 3), b"ime");
+        assert_eq!(word(4, 0, 9), b"Time");
+        assert_eq!(word(4, 0, 44), b"TIME");
+        assert_eq!(word(4, 0, 12), b"tim");
+        assert_eq!(word(5, 0, 49), b"firsing ");
+    }
+}
@@ -0,0 +1,738 @@
+//! Brotli Decompression. Requires `brotli` feature, enabled by default
+//!
+//! This module contains a decoder of the Brotli format of RFC 7932, as used
+//! by the `br` HTTP content encoding. A stream is a sequence of meta-blocks,
+//! either uncompressed or made of commands: literals to insert, then a copy
+//! from the window or from a static dictionary of words. The literals, the
+//! commands and the distances are coded with prefix codes, switched along the
+//! data by block types, and for the literals and distances, by the context:
+//! the last two bytes, or the length of the copy.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::brotli;
+//! use std::fs::File;
+//! use std::path::Path;
+//! use std::io::Read;
+//!
+//! let stream = File::open(&Path::new("path/to/file.br")).unwrap();
+//! let mut decompressed = Vec::new();
+//! brotli::Decoder::new(stream).read_to_end(&mut decompressed);
+//! ```
+//!
+//! # Related links
+//!
+//! * https://tools.ietf.org/html/rfc7932 - the specification of the format
+//! * https://github.com/google/brotli - the reference implementation
+
+use std::cmp;
+use std::io::{self, Read};
+
+use super::ReadExact;
+
+use self::prefix::PrefixCode;
+use error::{corrupt, truncated, unsupported};
+use process::{drain, Process, Reader, Status, Window};
+
+mod context;
+mod dictionary;
+mod prefix;
+
+const LITERAL_ALPHABET: usize = 256;
+const COMMAND_ALPHABET: usize = 704;
+const BLOCK_COUNT_ALPHABET: usize = 26;
+/// Number of contexts of each block type of literals, and of distances
+const LITERAL_CONTEXTS: usize = 64;
+const DISTANCE_CONTEXTS: usize = 4;
+
+static BLOCK_COUNT_BASE: [u32; 26] = [
+    1, 5, 9, 13, 17, 25, 33, 41, 49, 65, 81, 97, 113, 145, 177, 209,
+    241, 305, 369, 497, 753, 1265, 2289, 4337, 8433, 16625];
+static BLOCK_COUNT_EXTRA: [u8; 26] = [
+    2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5,
+    6, 6, 7, 8, 9, 10, 11, 12, 13, 24];
+
+static INSERT_BASE: [u32; 24] = [
+    0, 1, 2, 3, 4, 5, 6, 8, 10, 14, 18, 26, 34, 50, 66, 98,
+    130, 194, 322, 578, 1090, 2114, 6210, 22594];
+static INSERT_EXTRA: [u8; 24] = [
+    0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5,
+    6, 7, 8, 9, 10, 12, 14, 24];
+static COPY_BASE: [u32; 24] = [
+    2, 3, 4, 5, 6, 7, 8, 9, 10, 12, 14, 18, 22, 30, 38, 54,
+    70, 102, 134, 198, 326, 582, 1094, 2118];
+static COPY_EXTRA: [u8; 24] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4,
+    5, 5, 6, 7, 8, 9, 10, 24];
+/// The first insert and copy length codes of the cells of 64 commands. The
+/// commands of the first two cells reuse the last distance.
+static CELL_INSERT: [usize; 11] = [0, 0, 0, 0, 8, 8, 0, 16, 8, 16, 16];
+static CELL_COPY: [usize; 11] = [0, 8, 0, 8, 0, 8, 16, 0, 16, 8, 16];
+
+/// Changes to the last distances made by the distance codes 4 to 15
+static DISTANCE_DELTAS: [isize; 6] = [-1, 1, -2, 2, -3, 3];
+const NUM_DISTANCE_SHORT_CODES: usize = 16;
+
+/// Reader of the bits of the stream, from the lowest bit of each byte
+pub struct BitReader<R> {
+    r: R,
+    buf: u64,
+    count: usize,
+}
+
+impl<R: Read> BitReader<R> {
+    fn pull(&mut self) -> io::Result<()> {
+        let mut byte = [0];
+        if try!(self.r.read(&mut byte)) == 0 {
+            return truncated("truncated brotli stream")
+        }
+        self.buf |= (byte[0] as u64) << self.count;
+        self.count += 8;
+        Ok(())
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.buf >>= n;
+        self.count -= n;
+    }
+
+    fn peek(&mut self, n: usize) -> io::Result<u32> {
+        while self.count < n {
+            try!(self.pull());
+        }
+        Ok((self.buf & ((1 << n) - 1)) as u32)
+    }
+
+    fn bits(&mut self, n: usize) -> io::Result<u32> {
+        let value = try!(self.peek(n));
+        self.consume(n);
+        Ok(value)
+    }
+
+    /// Skips the bits up to the next byte boundary, which must be zeroes
+    fn align(&mut self) -> io::Result<()> {
+        let n = self.count % 8;
+        if try!(self.bits(n)) != 0 {
+            return corrupt("invalid brotli padding")
+        }
+        Ok(())
+    }
+
+    /// Reads bytes, once aligned
+    fn bytes(&mut self, n: usize, out: &mut Vec<u8>) -> io::Result<()> {
+        let buffered = cmp::min(n, self.count / 8);
+        for _ in 0..buffered {
+            out.push(self.buf as u8);
+            self.consume(8);
+        }
+        self.r.push_exactly((n - buffered) as u64, out)
+    }
+
+    /// Reads a number from 1 to 256
+    fn var_len(&mut self) -> io::Result<usize> {
+        if try!(self.bits(1)) == 0 {
+            return Ok(1)
+        }
+        match try!(self.bits(3)) as usize {
+            0 => Ok(2),
+            n => Ok((1 << n) + try!(self.bits(n)) as usize + 1),
+        }
+    }
+}
+
+/// The block types of one of the three categories of symbols, which select
+/// their prefix codes and contexts along the meta-block
+struct BlockTypes {
+    count: usize,
+    /// The codes of the types and the block counts, with several types
+    codes: Option<(PrefixCode, PrefixCode)>,
+    current: usize,
+    previous: usize,
+    /// Number of symbols left in the current block
+    left: usize,
+}
+
+impl BlockTypes {
+    fn read<R: Read>(br: &mut BitReader<R>) -> io::Result<BlockTypes> {
+        let count = try!(br.var_len());
+        let mut types = BlockTypes {
+            count: count,
+            codes: None,
+            current: 0,
+            previous: 1,
+            left: usize::max_value(),
+        };
+        if count > 1 {
+            let type_code = try!(PrefixCode::read(br, count + 2));
+            let count_code = try!(PrefixCode::read(br, BLOCK_COUNT_ALPHABET));
+            types.left = try!(block_count(br, &count_code));
+            types.codes = Some((type_code, count_code));
+        }
+        Ok(types)
+    }
+
+    /// Counts a symbol, switching to the next block when the current one is
+    /// over
+    #[inline]
+    fn next<R: Read>(&mut self, br: &mut BitReader<R>) -> io::Result<()> {
+        if self.left == 0 {
+            let (type_code, count_code) = self.codes.as_ref().unwrap();
+            // the previous type, the next one, or a type given directly
+            let new = match try!(type_code.decode(br)) as usize {
+                0 => self.previous,
+                1 => (self.current + 1) % self.count,
+                n => n - 2,
+            };
+            self.previous = self.current;
+            self.current = new;
+            self.left = try!(block_count(br, count_code));
+        }
+        self.left -= 1;
+        Ok(())
+    }
+}
+
+fn block_count<R: Read>(br: &mut BitReader<R>, code: &PrefixCode) -> io::Result<usize> {
+    let sym = try!(code.decode(br)) as usize;
+    Ok(BLOCK_COUNT_BASE[sym] as usize + try!(br.bits(BLOCK_COUNT_EXTRA[sym] as usize)) as usize)
+}
+
+/// Reads the map of the contexts to the prefix codes
+fn context_map<R: Read>(br: &mut BitReader<R>, size: usize, trees: usize) -> io::Result<Vec<u8>> {
+    if trees < 2 {
+        return Ok(vec![0; size])
+    }
+    // runs of zeroes have codes of their own
+    let max_run_prefix = if try!(br.bits(1)) == 1 { try!(br.bits(4)) as usize + 1 } else { 0 };
+    let code = try!(PrefixCode::read(br, trees + max_run_prefix));
+    let mut map = Vec::with_capacity(size);
+    while map.len() < size {
+        let sym = try!(code.decode(br)) as usize;
+        if sym == 0 {
+            map.push(0);
+        } else if sym <= max_run_prefix {
+            let run = (1 << sym) + try!(br.bits(sym)) as usize;
+            if map.len() + run > size {
+                return corrupt("invalid brotli context map")
+            }
+            map.extend((0..run).map(|_| 0));
+        } else {
+            map.push((sym - max_run_prefix) as u8);
+        }
+    }
+    // values may be coded with an inverse move-to-front transform
+    if try!(br.bits(1)) == 1 {
+        let mut mtf: Vec<u8> = (0..256).map(|i| i as u8).collect();
+        for v in map.iter_mut() {
+            let value = mtf.remove(*v as usize);
+            mtf.insert(0, value);
+            *v = value;
+        }
+    }
+    Ok(map)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    /// Reading the window size, or the next meta-block
+    Block,
+    /// Producing the bytes of a meta-block
+    Data,
+    Done,
+}
+
+/// This structure is used to decode a brotli stream, fed with slices of it.
+/// The input of a meta-block is gathered until it holds the whole meta-block,
+/// which is decoded again from its start whenever it runs out.
+pub struct Processor {
+    br: BitReader<Window>,
+    state: State,
+    /// Size of the window, once the stream header is read
+    window_size: Option<usize>,
+    /// The decoded data, kept for as long as the copies can reach it
+    history: Vec<u8>,
+    /// Position in the history of the next byte to return
+    pos: usize,
+    /// Number of bytes decoded
+    total: u64,
+    /// The last distances, the last first
+    distances: [usize; 4],
+    /// Whether the last meta-block was read
+    last: bool,
+}
+
+impl Processor {
+    /// Creates a new brotli decoder
+    pub fn new() -> Processor {
+        Processor {
+            br: BitReader { r: Window::new(), buf: 0, count: 0 },
+            state: State::Block,
+            window_size: None,
+            history: Vec::new(),
+            pos: 0,
+            total: 0,
+            distances: [4, 11, 15, 16],
+            last: false,
+        }
+    }
+
+    /// Starts decoding a new stream, keeping the buffers of the decoder
+    pub fn reset(&mut self) {
+        self.br.r.reset();
+        self.br.buf = 0;
+        self.br.count = 0;
+        self.state = State::Block;
+        self.window_size = None;
+        self.history.truncate(0);
+        self.pos = 0;
+        self.total = 0;
+        self.distances = [4, 11, 15, 16];
+        self.last = false;
+    }
+
+    /// Tests whether the end of the stream has been reached
+    pub fn eof(&self) -> bool {
+        self.state == State::Done
+    }
+
+    fn read_window_bits(&mut self) -> io::Result<()> {
+        let br = &mut self.br;
+        let bits = if try!(br.bits(1)) == 0 {
+            16
+        } else {
+            match try!(br.bits(3)) {
+                0 => match try!(br.bits(3)) {
+                    0 => 17,
+                    1 => return unsupported("unsupported brotli large window"),
+                    n => 8 + n,
+                },
+                n => 17 + n,
+            }
+        };
+        self.window_size = Some((1 << bits) - 16);
+        Ok(())
+    }
+
+    /// Decodes the next meta-block, returning false once the last one is over
+    fn meta_block(&mut self) -> io::Result<bool> {
+        let last = try!(self.br.bits(1)) == 1;
+        if last && try!(self.br.bits(1)) == 1 {
+            return Ok(false)
+        }
+        let nibbles = try!(self.br.bits(2)) as usize;
+        if nibbles == 3 {
+            // metadata, which is skipped
+            if try!(self.br.bits(1)) != 0 {
+                return corrupt("invalid brotli meta-block header")
+            }
+            let bytes = try!(self.br.bits(2)) as usize;
+            let mut len = 0;
+            for i in 0..bytes {
+                let b = try!(self.br.bits(8)) as usize;
+                if i + 1 == bytes && bytes > 1 && b == 0 {
+                    return corrupt("invalid brotli meta-block header")
+                }
+                len |= b << (8 * i);
+            }
+            if bytes > 0 {
+                len += 1;
+            }
+            try!(self.br.align());
+            try!(self.br.bytes(len, &mut Vec::with_capacity(len)));
+            return Ok(!last)
+        }
+
+        let mut len = 0;
+        for i in 0..(nibbles + 4) {
+            let n = try!(self.br.bits(4)) as usize;
+            if i + 1 == nibbles + 4 && nibbles > 0 && n == 0 {
+                return corrupt("invalid brotli meta-block header")
+            }
+            len |= n << (4 * i);
+        }
+        len += 1;
+
+        if !last && try!(self.br.bits(1)) == 1 {
+            try!(self.br.align());
+            try!(self.br.bytes(len, &mut self.history));
+            self.total += len as u64;
+            return Ok(true)
+        }
+        try!(self.compressed(len));
+        Ok(!last)
+    }
+
+    /// Decodes the commands of a compressed meta-block of `len` bytes
+    fn compressed(&mut self, len: usize) -> io::Result<()> {
+        let br = &mut self.br;
+        let mut literal_types = try!(BlockTypes::read(br));
+        let mut command_types = try!(BlockTypes::read(br));
+        let mut distance_types = try!(BlockTypes::read(br));
+        let postfix = try!(br.bits(2)) as usize;
+        let direct = (try!(br.bits(4)) as usize) << postfix;
+        let mut modes = Vec::with_capacity(literal_types.count);
+        for _ in 0..literal_types.count {
+            modes.push(try!(br.bits(2)) as u8);
+        }
+        let literal_trees = try!(br.var_len());
+        let literal_map = try!(context_map(br, LITERAL_CONTEXTS * literal_types.count,
+                                           literal_trees));
+        let distance_trees = try!(br.var_len());
+        let distance_map = try!(context_map(br, DISTANCE_CONTEXTS * distance_types.count,
+                                            distance_trees));
+        let mut literal_codes = Vec::with_capacity(literal_trees);
+        for _ in 0..literal_trees {
+            literal_codes.push(try!(PrefixCode::read(br, LITERAL_ALPHABET)));
+        }
+        let mut command_codes = Vec::with_capacity(command_types.count);
+        for _ in 0..command_types.count {
+            command_codes.push(try!(PrefixCode::read(br, COMMAND_ALPHABET)));
+        }
+        let distance_alphabet = NUM_DISTANCE_SHORT_CODES + direct + (48 << postfix);
+        let mut distance_codes = Vec::with_capacity(distance_trees);
+        for _ in 0..distance_trees {
+            distance_codes.push(try!(PrefixCode::read(br, distance_alphabet)));
+        }
+
+        let window_size = self.window_size.unwrap();
+        let history = &mut self.history;
+        let distances = &mut self.distances;
+        let mut left = len;
+        while left > 0 {
+            try!(command_types.next(br));
+            let command = try!(command_codes[command_types.current].decode(br)) as usize;
+            let cell = command >> 6;
+            let insert_code = CELL_INSERT[cell] + ((command >> 3) & 7);
+            let copy_code = CELL_COPY[cell] + (command & 7);
+            let insert_len = INSERT_BASE[insert_code] as usize +
+                try!(br.bits(INSERT_EXTRA[insert_code] as usize)) as usize;
+            let copy_len = COPY_BASE[copy_code] as usize +
+                try!(br.bits(COPY_EXTRA[copy_code] as usize)) as usize;
+
+            if insert_len > left {
+                return corrupt("brotli insert past the end of the meta-block")
+            }
+            for _ in 0..insert_len {
+                try!(literal_types.next(br));
+                let n = history.len();
+                let (p1, p2) = match n {
+                    0 => (0, 0),
+                    1 => (history[0], 0),
+                    _ => (history[n - 1], history[n - 2]),
+                };
+                let context = context::context(modes[literal_types.current], p1, p2);
+                let tree = literal_map[literal_types.current * LITERAL_CONTEXTS + context];
+                history.push(try!(literal_codes[tree as usize].decode(br)) as u8);
+            }
+            left -= insert_len;
+            self.total += insert_len as u64;
+            if left == 0 {
+                break
+            }
+
+            let code = if cell < 2 {
+                0
+            } else {
+                try!(distance_types.next(br));
+                let context = cmp::min(copy_len, 5) - 2;
+                let tree = distance_map[distance_types.current * DISTANCE_CONTEXTS + context];
+                try!(distance_codes[tree as usize].decode(br)) as usize
+            };
+            let distance = if code < NUM_DISTANCE_SHORT_CODES {
+                let (last, delta) = if code < 4 {
+                    (distances[code], 0)
+                } else if code < 10 {
+                    (distances[0], DISTANCE_DELTAS[code - 4])
+                } else {
+                    (distances[1], DISTANCE_DELTAS[code - 10])
+                };
+                let distance = last as isize + delta;
+                if distance <= 0 {
+                    return corrupt("invalid brotli distance")
+                }
+                distance as usize
+            } else if code < NUM_DISTANCE_SHORT_CODES + direct {
+                code - NUM_DISTANCE_SHORT_CODES + 1
+            } else {
+                let c = code - NUM_DISTANCE_SHORT_CODES - direct;
+                let bits = 1 + (c >> (postfix + 1));
+                let offset = ((2 + ((c >> postfix) & 1)) << bits) - 4;
+                let extra = try!(br.bits(bits)) as usize;
+                ((offset + extra) << postfix) + (c & ((1 << postfix) - 1)) + direct + 1
+            };
+
+            let max_distance = cmp::min(window_size as u64, self.total) as usize;
+            if distance > max_distance {
+                // a word of the static dictionary
+                if copy_len < dictionary::MIN_WORD_LENGTH || copy_len > dictionary::MAX_WORD_LENGTH {
+                    return corrupt("invalid brotli dictionary reference")
+                }
+                let id = distance - max_distance - 1;
+                let bits = dictionary::SIZE_BITS[copy_len];
+                let transform = id >> bits;
+                if transform >= dictionary::TRANSFORMS.len() {
+                    return corrupt("invalid brotli dictionary reference")
+                }
+                let start = history.len();
+                dictionary::write_word(copy_len, id & ((1 << bits) - 1), transform, history);
+                let n = history.len() - start;
+                if n > left {
+                    return corrupt("brotli copy past the end of the meta-block")
+                }
+                left -= n;
+                self.total += n as u64;
+                continue
+            }
+
+            if copy_len > left {
+                return corrupt("brotli copy past the end of the meta-block")
+            }
+            if code != 0 {
+                *distances = [distance, distances[0], distances[1], distances[2]];
+            }
+            for _ in 0..copy_len {
+                let b = history[history.len() - distance];
+                history.push(b);
+            }
+            left -= copy_len;
+            self.total += copy_len as u64;
+        }
+        Ok(())
+    }
+
+    /// Reads the window size or the next meta-block from its start, rolling
+    /// the input back when it runs out, or returns `None` to wait for more
+    /// of it
+    fn next_block(&mut self) -> io::Result<Option<()>> {
+        if !self.br.r.is_ready() {
+            return Ok(None)
+        }
+        if let Some(window_size) = self.window_size {
+            // forget what the copies can't reach anymore, once it was read
+            if self.history.len() > 2 * window_size {
+                let drop = self.history.len() - window_size;
+                self.history.drain(..drop);
+                self.pos -= drop;
+            }
+        }
+        let mark = self.br.r.mark();
+        let (buf, count) = (self.br.buf, self.br.count);
+        let (len, total, distances) = (self.history.len(), self.total, self.distances);
+        let result = match self.window_size {
+            None => self.read_window_bits(),
+            Some(_) => match self.meta_block() {
+                Ok(false) => {
+                    self.last = true;
+                    self.br.align()
+                }
+                result => result.map(|_| ()),
+            },
+        };
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.br.r.rollback(mark);
+                self.br.buf = buf;
+                self.br.count = count;
+                self.history.truncate(len);
+                self.total = total;
+                self.distances = distances;
+                self.last = false;
+                Ok(None)
+            }
+            result => result.map(Some),
+        }
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            match self.state {
+                State::Block => {
+                    self.br.r.extend(&input[pos..]);
+                    pos = input.len();
+                    match try!(self.next_block()) {
+                        Some(()) => self.state = State::Data,
+                        None => return Ok((pos, produced, Status::NeedInput)),
+                    }
+                }
+                State::Data => {
+                    drain(&self.history, &mut self.pos, output, &mut produced);
+                    if self.pos < self.history.len() {
+                        return Ok((pos, produced, Status::NeedOutput))
+                    }
+                    self.state = if self.last { State::Done } else { State::Block };
+                }
+                State::Done => {
+                    // the input gathered past the end of the stream isn't used
+                    pos -= cmp::min(self.br.r.data().len(), pos);
+                    return Ok((pos, produced, Status::Done))
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the meta-blocks left may come from the input gathered, and reading
+        // past it fails as truncated
+        self.br.r.finish();
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a brotli stream. The wrapped stream can be
+/// re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new brotli decoder which will wrap the specified reader.
+    /// This decoder also implements the `Reader` trait, and the underlying
+    /// reader can be re-acquired through the `unwrap` method.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new()),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Tests whether the end of the stream has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.processor().eof()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read};
+    use testing::{noise, push};
+    use super::{Decoder, Processor};
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        out
+    }
+
+    /// The input of test.multi.br, compressed with a window of 1 kB:
+    /// incompressible data, then text, then a run
+    fn multi() -> Vec<u8> {
+        let mut data = noise(4000, 1);
+        for _ in 0..4 {
+            data.extend(include_bytes!("../data/test.txt").iter().cloned());
+        }
+        data.extend((0..3000).map(|_| b'z'));
+        data
+    }
+
+    #[test]
+    fn decode_file() {
+        assert!(&decode(include_bytes!("../data/test.br"))[..] ==
+                &include_bytes!("../data/test.txt")[..]);
+        assert!(decode(include_bytes!("../data/test.multi.br")) == multi());
+    }
+
+    #[test]
+    fn decode_push() {
+        let input = include_bytes!("../data/test.multi.br");
+        for &step in [1, 333, 1 << 16].iter() {
+            assert!(push(&mut Processor::new(), input, step).unwrap() == multi());
+        }
+        let input = include_bytes!("../data/test.br");
+        for &len in [0, 100, input.len() - 1].iter() {
+            assert!(push(&mut Processor::new(), &input[..len], 100).is_err());
+        }
+    }
+
+    #[test]
+    fn reset() {
+        // a stream left halfway doesn't affect the next one
+        let mut d = Decoder::new(BufReader::new(&include_bytes!("../data/test.multi.br")[..]));
+        let mut out = [0; 5000];
+        d.read_exact(&mut out).unwrap();
+        d.reset(BufReader::new(&include_bytes!("../data/test.br")[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(&out[..] == &include_bytes!("../data/test.txt")[..]);
+        d.reset(BufReader::new(&include_bytes!("../data/test.multi.br")[..]));
+        out.truncate(0);
+        d.read_to_end(&mut out).unwrap();
+        assert!(out == multi());
+    }
+
+    #[test]
+    fn meta_blocks() {
+        // an empty stream
+        assert_eq!(decode(&[0x06]), b"");
+        // metadata, then an uncompressed meta-block, then an empty last one
+        let input = [0xac, 0x00, b'?', b'?', 0x10, 0x00, 0x08, b'a', b'b', b'c', 0x03];
+        assert_eq!(decode(&input), b"abc");
+        let mut d = Decoder::new(&input[..10]);
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn small_reads() {
+        let input = include_bytes!("../data/test.multi.br");
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        let mut out = Vec::new();
+        let mut buf = [0u8; 7];
+        loop {
+            match d.read(&mut buf).unwrap() {
+                0 => break,
+                n => out.extend(buf[..n].iter().cloned()),
+            }
+        }
+        assert!(out == multi());
+    }
+
+    #[test]
+    fn corrupted() {
+        // there is no checksum, but the data can't come out right
+        let input = include_bytes!("../data/test.br");
+        for &pos in [0, 5, input.len() / 2, input.len() - 1].iter() {
+            let mut bad = input.to_vec();
+            bad[pos] ^= 0x10;
+            let mut d = Decoder::new(BufReader::new(&bad[..]));
+            let mut out = Vec::new();
+            if d.read_to_end(&mut out).is_ok() {
+                assert!(&out[..] != &include_bytes!("../data/test.txt")[..]);
+            }
+        }
+        let mut d = Decoder::new(BufReader::new(&input[..100]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+        let mut d = Decoder::new(BufReader::new(&b""[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Prefix codes of brotli
+//!
+//! A code is either simple, listing up to four symbols, or complex: the code
+//! lengths of the symbols are coded with a prefix code of their own, with
+//! codes repeating the last length or zeroes. The codes are canonical, and
+//! packed from their first bit, as in deflate.
+
+use std::cmp;
+use std::io::{self, Read};
+
+use super::BitReader;
+use error::corrupt;
+
+/// The codes up to this length are resolved with a single lookup
+const ROOT_BITS: usize = 8;
+const MAX_LENGTH: usize = 15;
+
+/// Order in which the lengths of the code length code are given
+static CODE_LENGTH_ORDER: [usize; 18] = [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+/// The static code of the lengths of the code length code, by the next 4 bits
+static LENGTH_CODE_BITS: [u8; 16] = [2, 2, 2, 3, 2, 2, 2, 4, 2, 2, 2, 3, 2, 2, 2, 4];
+static LENGTH_CODE_VALUES: [u8; 16] = [0, 4, 3, 2, 0, 4, 3, 1, 0, 4, 3, 2, 0, 4, 3, 5];
+
+/// Code length symbols repeating the last non-zero length, and zeroes
+const REPEAT_LENGTH: u8 = 16;
+const REPEAT_ZERO: u8 = 17;
+
+#[derive(Clone, Copy)]
+enum Entry {
+    Symbol(u16, u8),
+    /// Offset and number of bits of a secondary table
+    Link(u16, u8),
+}
+
+pub struct PrefixCode {
+    table: Vec<Entry>,
+}
+
+impl PrefixCode {
+    /// Builds the code of the given lengths, which must fill the code space,
+    /// unless a single symbol has a length, which then takes no bits
+    fn new(lens: &[u8]) -> io::Result<PrefixCode> {
+        let mut symbols = lens.iter().enumerate().filter(|&(_, &len)| len != 0);
+        match (symbols.next(), symbols.next()) {
+            (None, _) => return corrupt("invalid brotli prefix code"),
+            (Some((sym, _)), None) => {
+                return Ok(PrefixCode { table: vec![Entry::Symbol(sym as u16, 0); 1 << ROOT_BITS] })
+            }
+            _ => {}
+        }
+
+        let mut count = [0u16; MAX_LENGTH + 1];
+        for &len in lens.iter() {
+            count[len as usize] += 1;
+        }
+        let mut left = 1isize;
+        for i in 1..(MAX_LENGTH + 1) {
+            left = 2 * left - count[i] as isize;
+            if left < 0 {
+                return corrupt("invalid brotli prefix code")
+            }
+        }
+        if left != 0 {
+            return corrupt("incomplete brotli prefix code")
+        }
+
+        let mut next = [0u16; MAX_LENGTH + 1];
+        for i in 1..MAX_LENGTH {
+            next[i + 1] = (next[i] + count[i]) << 1;
+        }
+        let mut codes = vec![0u16; lens.len()];
+        for (sym, &len) in lens.iter().enumerate() {
+            if len != 0 {
+                codes[sym] = next[len as usize].reverse_bits() >> (16 - len as usize);
+                next[len as usize] += 1;
+            }
+        }
+
+        // size the secondary tables after the longest code of each prefix
+        let mask = (1 << ROOT_BITS) - 1;
+        let mut table = vec![Entry::Symbol(0, 0); 1 << ROOT_BITS];
+        let mut sub_bits = vec![0u8; 1 << ROOT_BITS];
+        for (sym, &len) in lens.iter().enumerate() {
+            if len as usize > ROOT_BITS {
+                let prefix = codes[sym] as usize & mask;
+                sub_bits[prefix] = cmp::max(sub_bits[prefix], len - ROOT_BITS as u8);
+            }
+        }
+        for (prefix, &bits) in sub_bits.iter().enumerate() {
+            if bits != 0 {
+                table[prefix] = Entry::Link(table.len() as u16, bits);
+                table.extend((0..(1 << bits)).map(|_| Entry::Symbol(0, 0)));
+            }
+        }
+
+        for (sym, &len) in lens.iter().enumerate() {
+            let len = len as usize;
+            if len == 0 {
+                continue
+            }
+            let code = codes[sym] as usize;
+            let entry = Entry::Symbol(sym as u16, len as u8);
+            let (start, size, step, mut i) = if len <= ROOT_BITS {
+                (0, 1 << ROOT_BITS, 1 << len, code)
+            } else {
+                match table[code & mask] {
+                    Entry::Link(offset, bits) => {
+                        (offset as usize, 1 << bits, 1 << (len - ROOT_BITS), code >> ROOT_BITS)
+                    }
+                    _ => unreachable!(),
+                }
+            };
+            while i < size {
+                table[start + i] = entry;
+                i += step;
+            }
+        }
+        Ok(PrefixCode { table: table })
+    }
+
+    /// Reads the description of a code of the symbols below `alphabet`
+    pub fn read<R: Read>(br: &mut BitReader<R>, alphabet: usize) -> io::Result<PrefixCode> {
+        let skip = try!(br.bits(2)) as usize;
+        if skip == 1 {
+            return PrefixCode::read_simple(br, alphabet)
+        }
+
+        let mut length_lens = [0u8; 18];
+        let mut space = 32i32;
+        let mut codes = 0;
+        for &sym in CODE_LENGTH_ORDER[skip..].iter() {
+            let v = try!(br.peek(4)) as usize;
+            br.consume(LENGTH_CODE_BITS[v] as usize);
+            let len = LENGTH_CODE_VALUES[v];
+            length_lens[sym] = len;
+            if len != 0 {
+                space -= 32 >> len;
+                codes += 1;
+                if space <= 0 {
+                    break
+                }
+            }
+        }
+        if codes != 1 && space != 0 {
+            return corrupt("invalid brotli code length code")
+        }
+        let length_code = try!(PrefixCode::new(&length_lens));
+
+        let mut lens = vec![0u8; alphabet];
+        let mut sym = 0;
+        let mut space = 1i32 << MAX_LENGTH;
+        let mut last = 8;
+        let (mut repeat, mut repeat_len) = (0, 0);
+        while sym < alphabet && space > 0 {
+            let code = try!(length_code.decode(br)) as u8;
+            if code < REPEAT_LENGTH {
+                repeat = 0;
+                lens[sym] = code;
+                sym += 1;
+                if code != 0 {
+                    last = code;
+                    space -= (1 << MAX_LENGTH) >> code;
+                }
+                continue
+            }
+            // successive repeat codes make a longer run
+            let (extra, len) = if code == REPEAT_ZERO { (3, 0) } else { (2, last) };
+            if repeat_len != len {
+                repeat = 0;
+                repeat_len = len;
+            }
+            let old = repeat;
+            if repeat > 0 {
+                repeat = (repeat - 2) << extra;
+            }
+            repeat += try!(br.bits(extra)) as usize + 3;
+            let run = repeat - old;
+            if sym + run > alphabet {
+                return corrupt("invalid brotli code lengths")
+            }
+            for l in lens[sym..(sym + run)].iter_mut() {
+                *l = len;
+            }
+            sym += run;
+            if len != 0 {
+                space -= run as i32 * ((1 << MAX_LENGTH) >> len);
+            }
+        }
+        if space != 0 {
+            return corrupt("invalid brotli code lengths")
+        }
+        PrefixCode::new(&lens)
+    }
+
+    fn read_simple<R: Read>(br: &mut BitReader<R>, alphabet: usize) -> io::Result<PrefixCode> {
+        let count = try!(br.bits(2)) as usize + 1;
+        let mut bits = 0;
+        while (1 << bits) < alphabet {
+            bits += 1;
+        }
+        let mut symbols = Vec::with_capacity(4);
+        for _ in 0..count {
+            let sym = try!(br.bits(bits)) as usize;
+            if sym >= alphabet || symbols.contains(&sym) {
+                return corrupt("invalid brotli simple prefix code")
+            }
+            symbols.push(sym);
+        }
+        let code_lens: &[u8] = match count {
+            1 => &[1],
+            2 => &[1, 1],
+            3 => &[1, 2, 2],
+            _ if try!(br.bits(1)) == 0 => &[2, 2, 2, 2],
+            _ => &[1, 2, 3, 3],
+        };
+        let mut lens = vec![0u8; alphabet];
+        for (&sym, &len) in symbols.iter().zip(code_lens.iter()) {
+            lens[sym] = len;
+        }
+        PrefixCode::new(&lens)
+    }
+
+    /// Decodes a symbol, pulling bytes from the stream only while the entry
+    /// found needs more bits than there are
+    #[inline]
+    pub fn decode<R: Read>(&self, br: &mut BitReader<R>) -> io::Result<u16> {
+        loop {
+            let mut entry = self.table[br.buf as usize & ((1 << ROOT_BITS) - 1)];
+            if let Entry::Link(offset, bits) = entry {
+                let index = (br.buf >> ROOT_BITS) as usize & ((1 << bits) - 1);
+                entry = self.table[offset as usize + index];
+            }
+            match entry {
+                Entry::Symbol(sym, len) if len as usize <= br.count => {
+                    br.consume(len as usize);
+                    return Ok(sym)
+                }
+                _ => try!(br.pull()),
+            }
+        }
+    }
+}
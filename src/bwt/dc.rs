@@ -25,10 +25,12 @@ Thanks to Edgar Binder for inventing DC!
 */
 
 use std::io;
-use std::iter::{self, repeat};
+use std::iter;
 use std::slice as vec;
+use std::vec::Vec;
 use super::num::traits::{NumCast, ToPrimitive};
 use super::mtf::MTF;
+use error::truncated;
 
 pub type Symbol = u8;
 pub type Rank = u8;
@@ -149,13 +151,41 @@ pub fn encode<'a, 'b, D: Clone + Copy + Eq + NumCast>(input: &'a [Symbol], dista
 }
 
 
+/// Buffers of the distance coder, allocated once and reused by every block
+/// coded with them
+pub struct Workspace<D> {
+    distances: Vec<D>,
+    output: Vec<D>,
+    symbols: Vec<Symbol>,
+}
+
+impl<D> Workspace<D> {
+    /// Creates an empty workspace, growing to the largest block coded
+    pub fn new() -> Workspace<D> {
+        Workspace {
+            distances: Vec::new(),
+            output: Vec::new(),
+            symbols: Vec::new(),
+        }
+    }
+}
+
+/// Encode a block of bytes into the initial distances of the symbols
+/// followed by the distance stream, as `encode_simple`, into `work`
+pub fn encode_with<'w, D: Clone + Copy + Eq + NumCast>(input: &[Symbol], work: &'w mut Workspace<D>) -> &'w [D] {
+    let n = input.len();
+    work.distances.clear();
+    work.distances.resize(n, NumCast::from(0).unwrap());
+    let mut eniter = encode(input, &mut work.distances, &mut MTF::new());
+    work.output.clear();
+    work.output.extend((0..TOTAL_SYMBOLS).map(|i| -> D { NumCast::from(eniter.get_init()[i]).unwrap() }));
+    work.output.extend(eniter.by_ref().map(|(d,_)| d));
+    &work.output[..]
+}
+
 /// Encode version with "batteries included" for quick testing
 pub fn encode_simple<D: Clone + Copy + Eq + NumCast>(input: &[Symbol]) -> Vec<D> {
-    let n = input.len();
-    let mut raw_dist: Vec<D> = repeat(NumCast::from(0).unwrap()).take(n).collect();
-    let mut eniter = encode(input, &mut raw_dist, &mut MTF::new());
-    let init: Vec<D> = (0..TOTAL_SYMBOLS).map(|i| NumCast::from(eniter.get_init()[i]).unwrap()).collect();
-    init.iter().map(|d| d.clone()).chain(eniter.by_ref().map(|(d,_)| d)).collect()
+    encode_with(input, &mut Workspace::new()).to_vec()
 }
 
 /// Decode a block of distances given the initial symbol positions
@@ -232,9 +262,12 @@ pub fn decode<F>(mut next: [usize; TOTAL_SYMBOLS], output: &mut [Symbol], mtf: &
     Ok(())
 }
 
-/// Decode version with "batteries included" for quick testing
-pub fn decode_simple<D: ToPrimitive>(n: usize, distances: &[D]) -> Vec<Symbol> {
-    let mut output: Vec<Symbol> = repeat(0 as Symbol).take(n).collect();
+/// Decode a block of `n` bytes from the distances of `encode_with`, into
+/// `work`
+pub fn decode_with<'w, D: ToPrimitive>(n: usize, distances: &[D], work: &'w mut Workspace<D>) -> &'w [Symbol] {
+    let output = &mut work.symbols;
+    output.clear();
+    output.resize(n, 0);
     let mut init = [0; TOTAL_SYMBOLS];
     for i in 0..TOTAL_SYMBOLS {
         init[i] = distances[i].to_usize().unwrap();
@@ -243,12 +276,17 @@ pub fn decode_simple<D: ToPrimitive>(n: usize, distances: &[D]) -> Vec<Symbol> {
     decode(init, &mut output[..], &mut MTF::new(), |_ctx| {
         di += 1;
         if di > distances.len() {
-            Err(io::Error::new(io::ErrorKind::Other, "Unexpected end of file"))
+            truncated("unexpected end of file")
         } else {
             Ok(distances[di-1].to_usize().unwrap())
         }
     }).unwrap();
-    output.into_iter().collect()
+    &output[..]
+}
+
+/// Decode version with "batteries included" for quick testing
+pub fn decode_simple<D: ToPrimitive>(n: usize, distances: &[D]) -> Vec<Symbol> {
+    decode_with(n, distances, &mut Workspace::new()).to_vec()
 }
 
 
@@ -288,6 +326,16 @@ mod test {
         assert_eq!(&output[..], bytes);
     }
 
+    #[test]
+    fn workspace() {
+        let mut enc = super::Workspace::<u32>::new();
+        let mut dec = super::Workspace::new();
+        for bytes in [&include_bytes!("../data/test.txt")[..], b"teeesst_dc", b""].iter() {
+            let distances = super::encode_with(bytes, &mut enc);
+            assert_eq!(super::decode_with(bytes.len(), distances, &mut dec), *bytes);
+        }
+    }
+
     #[test]
     fn roundtrips() {
         roundtrip(b"teeesst_dc");
@@ -16,7 +16,7 @@ BWT + DC + EC               : ybs
 Where the stage families are:
 BWT: BWT (Burrows-Wheeler Transform), ST (Shindler transform)
 RLE: RLE (Run-Length Encoding)
-MTF: MTF (Move-To-Front), WFC (Weighted Frequency Coding)
+MTF: MTF (Move-To-Front), SR (Symbol Ranking), WFC (Weighted Frequency Coding)
 DC: DC (Distance Coding), IF (Inverse Frequencies)
 EC (Entropy Coder): Huffman, Arithmetic, RC (Range Coder)
 
@@ -52,17 +52,42 @@ This is an original (mostly trivial) implementation.
 
 extern crate num;
 
-use std::{cmp, fmt, slice};
-use std::ptr;
-use std::iter::{self, Extend, repeat};
+#[cfg(feature="std")]
+use std::cmp;
+use std::{fmt, mem, slice};
+use std::iter::{self, repeat};
+use std::vec::Vec;
+#[cfg(feature="std")]
 use std::io::{self, Read, Write};
+#[cfg(feature="threads")]
+use std::collections::VecDeque;
+#[cfg(feature="threads")]
+use std::sync::Mutex;
+#[cfg(feature="threads")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature="threads")]
+use std::thread;
 use self::num::traits::{NumCast, ToPrimitive};
 
-use super::byteorder::{self, LittleEndian, WriteBytesExt, ReadBytesExt};
-use super::{byteorder_err_to_io, ReadExact};
+#[cfg(feature="std")]
+use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(feature="std")]
+use super::at;
+#[cfg(feature="std")]
+use error::{corrupt, truncated};
+use histogram::histogram;
+#[cfg(feature="std")]
+use pool::{self, Buffer, Pool};
+#[cfg(feature="std")]
+use process::{drain, gather, Process, Pump, Status};
+#[cfg(feature="std")]
+use stats::Sink;
+#[cfg(feature="std")]
+use tune;
 
 pub mod dc;
 pub mod mtf;
+pub mod sr;
 
 /// A base element for the transformation
 pub type Symbol = u8;
@@ -93,8 +118,8 @@ impl Radix  {
 
     /// count elements in the input
     pub fn gather(&mut self, input: &[Symbol])  {
-        for &b in input.iter()  {
-            self.freq[b as usize] += 1;
+        for (fr, n) in self.freq.iter_mut().zip(histogram(input).iter()) {
+            *fr += *n;
         }
     }
 
@@ -165,6 +190,219 @@ pub fn compute_suffixes<SUF: NumCast + ToPrimitive + fmt::Debug>(input: &[Symbol
     debug!("sorted SA: {:?}", suf_array);
 }
 
+/// Suffixes sharing their first symbols, by the number of them
+#[cfg(feature="threads")]
+type Bucket<'a, SUF> = (&'a mut [SUF], usize);
+
+/// Buckets larger than this are split by their next symbol
+#[cfg(feature="threads")]
+const SPLIT_SIZE: usize = 1 << 12;
+
+/// Number of leading symbols past which buckets are sorted whole
+#[cfg(feature="threads")]
+const SPLIT_DEPTH: usize = 16;
+
+/// Sorts the suffixes of a bucket by the symbols after their common prefix,
+/// either whole or by splitting it into buckets for `queue`
+#[cfg(feature="threads")]
+fn sort_bucket<'a, SUF>(input: &[Symbol], (suffixes, depth): Bucket<'a, SUF>,
+                        queue: &Mutex<VecDeque<Bucket<'a, SUF>>>, pending: &AtomicUsize)
+    where SUF: NumCast + ToPrimitive + Copy
+{
+    if suffixes.len() <= SPLIT_SIZE || depth >= SPLIT_DEPTH {
+        suffixes.sort_by(|a, b| {
+            input[a.to_usize().unwrap() + depth..].cmp(&input[b.to_usize().unwrap() + depth..])
+        });
+        return
+    }
+
+    // a counting sort by the next symbol, the suffixes ending first
+    let key = |p: &SUF| input.get(p.to_usize().unwrap() + depth).map_or(0, |&b| b as usize + 1);
+    let mut offsets = [0usize; ALPHABET_SIZE + 2];
+    for p in suffixes.iter() {
+        offsets[key(p) + 1] += 1;
+    }
+    let sizes = offsets;
+    for i in 1..offsets.len() {
+        offsets[i] += offsets[i-1];
+    }
+    let sorted = suffixes.to_vec();
+    for p in sorted.iter() {
+        let k = key(p);
+        suffixes[offsets[k]] = *p;
+        offsets[k] += 1;
+    }
+
+    let mut rest = suffixes;
+    for &size in sizes[1..].iter() {
+        let (bucket, tail) = mem::take(&mut rest).split_at_mut(size);
+        rest = tail;
+        if size > 1 {
+            pending.fetch_add(1, Ordering::SeqCst);
+            queue.lock().unwrap().push_back((bucket, depth + 1));
+        }
+    }
+}
+
+/// Compute a suffix array as `compute_suffixes` does, on `threads` threads.
+/// The buckets of the suffixes starting with the same symbols are sorted by
+/// the threads, those larger than a few thousand suffixes being split again by
+/// the next symbols. Each thread takes the buckets it split first and steals
+/// those of the others once done, so that a block of skewed symbols still
+/// keeps every thread busy.
+#[cfg(feature="threads")]
+pub fn compute_suffixes_parallel<SUF>(input: &[Symbol], suf_array: &mut [SUF], threads: usize)
+    where SUF: NumCast + ToPrimitive + Copy + Send + fmt::Debug
+{
+    if threads <= 1 || input.len() <= SPLIT_SIZE {
+        return compute_suffixes(input, suf_array)
+    }
+    for (i, p) in suf_array.iter_mut().enumerate() {
+        *p = NumCast::from(i).unwrap();
+    }
+
+    {
+        let queues: Vec<_> = (0..threads).map(|_| Mutex::new(VecDeque::new())).collect();
+        queues[0].lock().unwrap().push_back((&mut *suf_array, 0));
+        let pending = AtomicUsize::new(1);
+        thread::scope(|scope| {
+            for me in 0..threads {
+                let (queues, pending) = (&queues, &pending);
+                scope.spawn(move || loop {
+                    let own = queues[me].lock().unwrap().pop_back();
+                    let bucket = own.or_else(|| {
+                        (1..threads).filter_map(|i| {
+                            queues[(me + i) % threads].lock().unwrap().pop_front()
+                        }).next()
+                    });
+                    match bucket {
+                        Some(bucket) => {
+                            sort_bucket(input, bucket, &queues[me], pending);
+                            pending.fetch_sub(1, Ordering::SeqCst);
+                        }
+                        None if pending.load(Ordering::SeqCst) == 0 => return,
+                        None => thread::yield_now(),
+                    }
+                });
+            }
+        });
+    }
+
+    debug!("sorted SA: {:?}", suf_array);
+}
+
+/// Scratch buffers of the cyclic transform, allocated once and reused by
+/// every block sorted with them
+#[derive(Default)]
+pub struct Workspace {
+    rotations: Vec<u32>,
+    rank: Vec<u32>,
+    second: Vec<u32>,
+    count: Vec<usize>,
+    output: Vec<Symbol>,
+}
+
+impl Workspace {
+    /// Creates an empty workspace, growing to the largest block sorted
+    pub fn new() -> Workspace {
+        Workspace::default()
+    }
+}
+
+/// Empties `v` and fills it with `n` times `value`, keeping its allocation
+fn refill<T: Copy>(v: &mut Vec<T>, n: usize, value: T) {
+    v.clear();
+    v.resize(n, value);
+}
+
+/// Sort the cyclic rotations of the input, as the BWT of bzip2 does, storing
+/// their starting offsets in `rotations`. Unlike suffixes, rotations may be
+/// equal if the input is periodic: those come in no particular order.
+/// Run time: O(N log N) by prefix doubling, memory: 3N words + ALPHABET_SIZE
+/// words, taken from `work`
+pub fn compute_rotations(input: &[Symbol], rotations: &mut [u32], work: &mut Workspace) {
+    let n = input.len();
+    assert_eq!(n, rotations.len());
+    if n == 0 {
+        return
+    }
+    let Workspace { ref mut rank, ref mut second, ref mut count, .. } = *work;
+
+    // Sort by the first symbol, ranking by equivalence class
+    let mut radix = Radix::new();
+    radix.gather(input);
+    radix.accumulate();
+    for (i, &ch) in input.iter().enumerate() {
+        rotations[radix.place(ch)] = i as u32;
+    }
+    refill(rank, n, 0);
+    let mut classes = 1;
+    for i in 1..n {
+        if input[rotations[i] as usize] != input[rotations[i-1] as usize] {
+            classes += 1;
+        }
+        rank[rotations[i] as usize] = classes - 1;
+    }
+
+    // Rotations sorted by their first `h` symbols are sorted by their first
+    // `2h` ones when ordered by the rank of both halves
+    refill(second, n, 0);
+    refill(count, n + 1, 0);
+    let mut h = 1;
+    while h < n && (classes as usize) < n {
+        for (j, &r) in rotations.iter().enumerate() {
+            second[j] = ((r as usize + n - h) % n) as u32;
+        }
+        for c in count.iter_mut() {
+            *c = 0;
+        }
+        for &r in rank.iter() {
+            count[r as usize + 1] += 1;
+        }
+        for c in 1..(n + 1) {
+            count[c] += count[c-1];
+        }
+        for &p in second.iter() {
+            let r = rank[p as usize] as usize;
+            rotations[count[r]] = p;
+            count[r] += 1;
+        }
+
+        // `second` now holds the new ranks
+        let key = |p: u32| (rank[p as usize], rank[(p as usize + h) % n]);
+        classes = 1;
+        second[rotations[0] as usize] = 0;
+        for i in 1..n {
+            if key(rotations[i]) != key(rotations[i-1]) {
+                classes += 1;
+            }
+            second[rotations[i] as usize] = classes - 1;
+        }
+        mem::swap(rank, second);
+        h *= 2;
+    }
+}
+
+/// Transform an input block with sorted cyclic rotations, returning the
+/// output, held by `work` until the next block, and the index of the original
+/// string, as bzip2 expects them
+pub fn encode_cyclic<'w>(input: &[Symbol], work: &'w mut Workspace) -> (&'w [Symbol], usize) {
+    let n = input.len();
+    let mut rotations = mem::replace(&mut work.rotations, Vec::new());
+    refill(&mut rotations, n, 0);
+    compute_rotations(input, &mut rotations[..], work);
+    let mut origin = 0;
+    work.output.clear();
+    work.output.extend(rotations.iter().enumerate().map(|(i, &r)| {
+        if r == 0 {
+            origin = i;
+        }
+        input[(r as usize + n - 1) % n]
+    }));
+    work.rotations = rotations;
+    (&work.output[..], origin)
+}
+
 /// An iterator over BWT output
 pub struct TransformIterator<'a, SUF: 'a> {
     input      : &'a [Symbol],
@@ -287,6 +525,72 @@ pub fn decode<'a, SUF: NumCast + fmt::Debug>(input: &'a [Symbol], origin: usize,
     InverseIterator::new(input, origin, table)
 }
 
+/// Decodes a block as `decode`, into `output`. The jumps through a table of
+/// `compute_inversion_table` stay within the block for a valid `origin`.
+#[cfg(feature="std")]
+fn chase(input: &[Symbol], origin: usize, table: &[usize], output: &mut Vec<Symbol>) {
+    let mut current = origin;
+    for _ in 0..input.len() {
+        current = at(table, current).wrapping_sub(1);
+        if current == usize::max_value() {
+            // a corrupt block may end before its last symbol
+            output.push(at(input, origin));
+            break
+        }
+        output.push(at(input, current));
+    }
+}
+
+/// Largest block of which the jumps fit above the symbols in a packed table
+#[cfg(feature="std")]
+const MAX_PACKED: usize = (1 << 24) - 1;
+
+/// Computes the table of `compute_inversion_table` in 32-bit words, each
+/// holding the jump above the symbol it leads to, so that the block is no
+/// longer needed to decode it. The block must be no longer than `MAX_PACKED`.
+#[cfg(feature="std")]
+fn compute_packed_table(input: &[Symbol], origin: usize, table: &mut [u32]) {
+    debug_assert!(input.len() <= MAX_PACKED && input.len() == table.len());
+    let mut radix = Radix::new();
+    radix.gather(input);
+    radix.accumulate();
+    // the origin comes first among its symbols, with no jump
+    table[radix.place(input[origin])] = input[origin] as u32;
+    for (i, &ch) in input.iter().enumerate().filter(|&(i, _)| i != origin) {
+        table[radix.place(ch)] = ((i + 1) << 8) as u32 | ch as u32;
+    }
+}
+
+/// Decodes a block of `n` symbols as `chase`, from a table of
+/// `compute_packed_table`
+#[cfg(feature="std")]
+fn chase_packed(n: usize, origin: usize, table: &[u32], output: &mut Vec<Symbol>) {
+    let mut current = origin;
+    for _ in 0..n {
+        let entry = at(table, current) as usize;
+        output.push(entry as Symbol);
+        if entry >> 8 == 0 {
+            break
+        }
+        current = (entry >> 8) - 1;
+    }
+}
+
+/// Decodes a block as `chase`, from a table of `compute_inversion_table` in
+/// 32-bit words
+#[cfg(feature="std")]
+fn chase_words(input: &[Symbol], origin: usize, table: &[u32], output: &mut Vec<Symbol>) {
+    let mut current = origin;
+    for _ in 0..input.len() {
+        current = (at(table, current) as usize).wrapping_sub(1);
+        if current == usize::max_value() {
+            output.push(at(input, origin));
+            break
+        }
+        output.push(at(input, current));
+    }
+}
+
 /// A simplified BWT decode function, which allocates a temporary suffix array
 pub fn decode_simple(input: &[Symbol], origin: usize) -> Vec<Symbol> {
     let mut suf: Vec<usize> = repeat(0).take(input.len()).collect();
@@ -295,6 +599,7 @@ pub fn decode_simple(input: &[Symbol], origin: usize) -> Vec<Symbol> {
 
 /// Decode without additional memory, can be greatly optimized
 /// Run time: O(n^2), Memory: 0n
+#[cfg(feature="std")]
 fn decode_minimal(input: &[Symbol], origin: usize, output: &mut [Symbol]) {
     assert_eq!(input.len(), output.len());
     if input.len() == 0 {
@@ -315,133 +620,372 @@ fn decode_minimal(input: &[Symbol], origin: usize, output: &mut [Symbol]) {
 }
 
 
-/// This structure is used to decode a stream of BWT blocks. This wraps an
-/// internal reader which is read from when this decoder's read method is
-/// called.
-pub struct Decoder<R> {
-    /// The internally wrapped reader. This is exposed so it may be moved out
-    /// of. Note that if data is read from the reader while decoding is in
-    /// progress the output stream will get corrupted.
-    pub r: R,
+/// The settings of the BWT encoder and decoder, which builds them once they
+/// are checked
+#[cfg(feature="std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BwtOptions {
+    block_size: usize,
+    extra_memory: bool,
+    memory_budget: Option<usize>,
+    threads: usize,
+}
+
+#[cfg(feature="std")]
+impl Default for BwtOptions {
+    fn default() -> BwtOptions {
+        BwtOptions {
+            block_size: 4 << 20,
+            extra_memory: true,
+            memory_budget: None,
+            threads: 1,
+        }
+    }
+}
+
+#[cfg(feature="std")]
+impl BwtOptions {
+    /// Creates the default settings: blocks of 4 MB, decoded with the extra
+    /// memory
+    pub fn new() -> BwtOptions {
+        BwtOptions::default()
+    }
+
+    /// Creates the settings fitting `budget` bytes of memory on this machine:
+    /// the largest blocks, up to 8 MB, of which the suffix array fits in the
+    /// budget, sorted on all the cores, and decoded within the budget as well
+    pub fn auto(budget: usize) -> BwtOptions {
+        let size = tune::block_size(budget, 1, 0, 1 + mem::size_of::<usize>(), 1 << 16, 8 << 20);
+        BwtOptions::new().block_size(size).memory_budget(budget).threads(tune::cores())
+    }
+
+    /// Sets the size of the blocks of the encoder, see `Encoder::new`
+    pub fn block_size(mut self, size: usize) -> BwtOptions {
+        self.block_size = size;
+        self
+    }
+
+    /// Sets whether the decoder uses a word of memory per byte of the block,
+    /// to decode faster
+    pub fn extra_memory(mut self, extra_memory: bool) -> BwtOptions {
+        self.extra_memory = extra_memory;
+        self
+    }
+
+    /// Sets the memory of the decoder, see `Decoder::set_memory_budget`
+    pub fn memory_budget(mut self, budget: usize) -> BwtOptions {
+        self.memory_budget = Some(budget);
+        self
+    }
+
+    /// Sets the number of threads of the encoder, see `Encoder::set_threads`.
+    /// This needs the `threads` feature, without which the blocks are sorted
+    /// on the calling thread.
+    pub fn threads(mut self, threads: usize) -> BwtOptions {
+        self.threads = threads;
+        self
+    }
+
+    /// Creates an encoder with these settings, writing to `w`, or fails if
+    /// they are invalid
+    pub fn encoder<W: Write>(&self, w: W) -> io::Result<Encoder<W>> {
+        if self.block_size == 0 || self.block_size as u64 > u32::max_value() as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid bwt block size"))
+        }
+        #[cfg_attr(not(feature="threads"), allow(unused_mut))]
+        let mut e = Encoder::new(w, self.block_size);
+        #[cfg(feature="threads")]
+        e.set_threads(self.threads);
+        Ok(e)
+    }
+
+    /// Creates a decoder with these settings, reading from `r`
+    pub fn decoder<R: Read>(&self, r: R) -> Decoder<R> {
+        let mut d = Decoder::new(r, self.extra_memory);
+        if let Some(budget) = self.memory_budget {
+            d.set_memory_budget(budget);
+        }
+        d
+    }
+}
+
+/// The part of a BWT stream which comes next
+#[cfg(feature="std")]
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Header,
+    Size,
+    /// The symbols of a block, and whether it is decoded with a packed table
+    Block(usize, bool),
+    Origin(bool),
+}
+
+/// This structure decodes a stream of BWT blocks pushed into it, for the
+/// callers which hold the buffers. See the `process` module.
+#[cfg(feature="std")]
+pub struct Processor {
+    state  : State,
+    /// The size, header and origin fields, as they come
+    head   : Vec<u8>,
     start  : usize,
+    end    : usize,
+    eof    : bool,
 
-    temp   : Vec<u8>,
-    output : Vec<u8>,
-    table  : Vec<usize>,
+    temp   : Buffer<u8>,
+    output : Buffer<u8>,
+    table  : Buffer<usize>,
+    words  : Buffer<u32>,
+    pool   : Option<Pool>,
 
-    header         : bool,
     max_block_size : usize,
     extra_memory   : bool,
+    budget         : Option<usize>,
 }
 
-impl<R: Read> Decoder<R> {
-    /// Creates a new decoder which will read data from the given stream. The
-    /// inner stream can be re-acquired by moving out of the `r` field of this
-    /// structure.
+#[cfg(feature="std")]
+impl Processor {
+    /// Creates a new decoder, waiting for the header of a stream.
     /// 'extra_mem' switch allows allocating extra N words of memory for better performance
-    pub fn new(r: R, extra_mem: bool) -> Decoder<R> {
-        Decoder {
-            r: r,
+    pub fn new(extra_mem: bool) -> Processor {
+        Processor {
+            state: State::Header,
+            head: Vec::new(),
             start: 0,
-            temp: Vec::new(),
-            output: Vec::new(),
-            table: Vec::new(),
-            header: false,
+            end: 0,
+            eof: false,
+            temp: Buffer::default(),
+            output: Buffer::default(),
+            table: Buffer::default(),
+            words: Buffer::default(),
+            pool: None,
             max_block_size: 0,
             extra_memory: extra_mem,
+            budget: None,
         }
     }
 
-    /// Resets this decoder back to its initial state. Note that the underlying
-    /// stream is not seeked on or has any alterations performed on it.
-    pub fn reset(&mut self) {
-        self.header = false;
-        self.start = 0;
+    /// Takes the buffers of the blocks from `pool`, to which they go back
+    /// once the decoder is dropped. This must be called before decoding.
+    pub fn set_pool(&mut self, pool: &Pool) {
+        self.pool = Some(pool.clone());
     }
 
-    fn read_header(&mut self) -> io::Result<()> {
-        match self.r.read_u32::<LittleEndian>() {
-            Ok(size) => {
-                self.max_block_size = size as usize;
-                debug!("max size: {}", self.max_block_size);
-                Ok(())
-            },
-            Err(e) => Err(byteorder_err_to_io(e)),
-        }
+    /// Decodes the blocks for which the extra memory, of `2 + size_of::<usize>()`
+    /// bytes per byte of the block, would exceed `budget` bytes with a table
+    /// of 32-bit words instead: a block under 16 MiB then takes 5 bytes per
+    /// byte, packing its symbols in the table, and a larger one 6, at the cost
+    /// of some speed. This applies only to the decoders with the extra memory.
+    pub fn set_memory_budget(&mut self, budget: usize) {
+        self.budget = Some(budget);
     }
 
-    fn decode_block(&mut self) -> io::Result<bool> {
-        let n = match self.r.read_u32::<LittleEndian>() {
-            Ok(n) => n as usize,
-            Err(byteorder::Error::Io(e)) => return Err(e),
-            Err(..) => return Ok(false) // EOF
-        };
+    /// Forgets the stream decoded, keeping the buffers, to decode another one
+    pub fn reset(&mut self) {
+        self.state = State::Header;
+        self.head.truncate(0);
+        self.start = 0;
+        self.end = 0;
+        self.eof = false;
+    }
 
-        self.temp.truncate(0);
-        self.temp.reserve(n);
-        try!(self.r.push_exactly(n as u64, &mut self.temp));
+    /// Returns the state of the block of `n` symbols which follows
+    fn block_size(&mut self, n: usize) -> State {
+        let over_budget = match self.budget {
+            Some(budget) => n.saturating_mul(2 + mem::size_of::<usize>()) > budget,
+            None => false,
+        };
+        // a packed table is computed from the symbols read straight into the
+        // output, which it then replaces
+        let packed = self.extra_memory && over_budget && n <= MAX_PACKED;
+        let buf = if packed { &mut self.output } else { &mut self.temp };
+        buf.truncate(0);
+        pool::reserve_bytes(self.pool.as_ref(), buf, n);
+        self.start = 0;
+        self.end = 0;
+        State::Block(n, packed)
+    }
 
-        let origin = try!(self.r.read_u32::<LittleEndian>()) as usize;
+    fn decode_block(&mut self, origin: usize) -> io::Result<()> {
+        let n = self.temp.len();
+        if origin >= n {
+            return corrupt("BWT origin out of the block")
+        }
         self.output.truncate(0);
-        self.output.reserve(n);
+        pool::reserve_bytes(self.pool.as_ref(), &mut self.output, n);
 
-        if self.extra_memory    {
+        let over_budget = match self.budget {
+            Some(budget) => n.saturating_mul(2 + mem::size_of::<usize>()) > budget,
+            None => false,
+        };
+        if self.extra_memory && over_budget {
+            self.words.truncate(0);
+            pool::reserve_words(self.pool.as_ref(), &mut self.words, n);
+            self.words.extend((0..n).map(|_| 0));
+            compute_inversion_table(&self.temp[..], origin, &mut self.words[..]);
+            chase_words(&self.temp[..], origin, &self.words[..], &mut self.output);
+        }else if self.extra_memory    {
             self.table.truncate(0);
+            pool::reserve_sizes(self.pool.as_ref(), &mut self.table, n);
             self.table.extend((0..n).map(|_| 0));
-            for ch in decode(&self.temp[..], origin, &mut self.table[..]) {
-                self.output.push(ch);
-            }
+            compute_inversion_table(&self.temp[..], origin, &mut self.table[..]);
+            chase(&self.temp[..], origin, &self.table[..], &mut self.output);
         }else   {
             self.output.extend((0..n).map(|_| 0));
             decode_minimal(&self.temp[..], origin, &mut self.output[..]);
         }
+        Ok(())
+    }
 
-        self.start = 0;
-        return Ok(true);
+    /// Decodes the block of the output with a packed table
+    fn decode_packed(&mut self, origin: usize) -> io::Result<()> {
+        let n = self.output.len();
+        if origin >= n {
+            return corrupt("BWT origin out of the block")
+        }
+        self.words.truncate(0);
+        pool::reserve_words(self.pool.as_ref(), &mut self.words, n);
+        self.words.extend((0..n).map(|_| 0));
+        compute_packed_table(&self.output[..], origin, &mut self.words[..]);
+        self.output.truncate(0);
+        chase_packed(n, origin, &self.words[..], &mut self.output);
+        Ok(())
     }
 }
 
-impl<R: Read> Read for Decoder<R> {
-    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
-        if !self.header {
-            try!(self.read_header());
-            self.header = true;
-        }
-        let mut amt = dst.len();
-        let dst_len = amt;
-
-        while amt > 0 {
-            if self.output.len() == self.start {
-                let keep_going = try!(self.decode_block());
-                if !keep_going {
-                   break
-                }
+#[cfg(feature="std")]
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.output[..self.end], &mut self.start, output, &mut produced) {
+                return Ok((pos, produced, Status::NeedOutput))
             }
-            let n = cmp::min(amt, self.output.len() - self.start);
-            unsafe { ptr::copy_nonoverlapping(
-                &self.output[self.start],
-                &mut dst[dst_len - amt],
-                n,
-            )};
-            self.start += n;
-            amt -= n;
+            let gathered = match self.state {
+                State::Block(n, false) => gather(&mut self.temp, input, &mut pos, n),
+                State::Block(n, true) => gather(&mut self.output, input, &mut pos, n),
+                _ => gather(&mut self.head, input, &mut pos, 4),
+            };
+            if !gathered {
+                let status = if self.eof { Status::Done } else { Status::NeedInput };
+                return Ok((pos, produced, status))
+            }
+
+            self.state = match self.state {
+                State::Header => {
+                    self.max_block_size = (&self.head[..]).read_u32::<LittleEndian>().unwrap() as usize;
+                    debug!("max size: {}", self.max_block_size);
+                    State::Size
+                }
+                State::Size => {
+                    let n = (&self.head[..]).read_u32::<LittleEndian>().unwrap() as usize;
+                    self.block_size(n)
+                }
+                State::Block(_, packed) => State::Origin(packed),
+                State::Origin(packed) => {
+                    let origin = (&self.head[..]).read_u32::<LittleEndian>().unwrap() as usize;
+                    if packed {
+                        try!(self.decode_packed(origin));
+                    } else {
+                        try!(self.decode_block(origin));
+                    }
+                    self.end = self.output.len();
+                    State::Size
+                }
+            };
+            self.head.truncate(0);
         }
+    }
 
-        Ok(dst_len - amt)
+    fn finish(&mut self) -> io::Result<()> {
+        // the stream ends in between the blocks
+        if self.state != State::Size || !self.head.is_empty() {
+            return truncated("truncated bwt stream")
+        }
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a stream of BWT blocks. This wraps an
+/// internal reader which is read from when this decoder's read method is
+/// called.
+#[cfg(feature="std")]
+pub struct Decoder<R> {
+    /// The internally wrapped reader. This is exposed so it may be moved out
+    /// of. Note that the data read ahead of the output is lost with it, and
+    /// that if data is read from the reader while decoding is in progress the
+    /// output stream will get corrupted.
+    pub r: R,
+
+    p: Processor,
+    pump: Pump,
+}
+
+#[cfg(feature="std")]
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream. The
+    /// inner stream can be re-acquired by moving out of the `r` field of this
+    /// structure.
+    /// 'extra_mem' switch allows allocating extra N words of memory for better performance
+    pub fn new(r: R, extra_mem: bool) -> Decoder<R> {
+        Decoder {
+            r: r,
+            p: Processor::new(extra_mem),
+            pump: Pump::new(),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.pump.set_max_output_size(size);
+    }
+
+    /// Takes the buffers of the blocks from `pool`, to which they go back
+    /// once the decoder is dropped. This must be called before decoding.
+    pub fn set_pool(&mut self, pool: &Pool) {
+        self.p.set_pool(pool);
+    }
+
+    /// See `Processor::set_memory_budget`
+    pub fn set_memory_budget(&mut self, budget: usize) {
+        self.p.set_memory_budget(budget);
+    }
+
+    /// Resets this decoder back to its initial state. Note that the underlying
+    /// stream is not seeked on or has any alterations performed on it, while
+    /// the data read ahead from it is dropped.
+    pub fn reset(&mut self) {
+        self.p.reset();
+        self.pump.reset();
+    }
+}
+
+#[cfg(feature="std")]
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.pump.read(&mut self.r, &mut self.p, dst)
     }
 }
 
 
 /// This structure is used to compress a stream of bytes using the BWT.
 /// This is a wrapper around an internal writer which bytes will be written to.
+#[cfg(feature="std")]
 pub struct Encoder<W> {
     w: W,
-    buf: Vec<u8>,
-    suf: Vec<usize>,
+    buf: Buffer<u8>,
+    suf: Buffer<usize>,
     wrote_header: bool,
     block_size: usize,
+    stats: Option<Sink>,
+    pool: Option<Pool>,
+    #[cfg(feature="threads")]
+    threads: usize,
 }
 
+#[cfg(feature="std")]
 impl<W: Write> Encoder<W> {
     /// Creates a new encoder which will have its output written to the given
     /// output stream. The output stream can be re-acquired by calling
@@ -451,29 +995,89 @@ impl<W: Write> Encoder<W> {
     pub fn new(w: W, block_size: usize) -> Encoder<W> {
         Encoder {
             w: w,
-            buf: Vec::new(),
-            suf: Vec::new(),
+            buf: Buffer::default(),
+            suf: Buffer::default(),
             wrote_header: false,
             block_size: block_size,
+            stats: None,
+            pool: None,
+            #[cfg(feature="threads")]
+            threads: 1,
         }
     }
 
+    /// Counts the bytes, the blocks and the symbols of the transformed blocks
+    /// into `sink` from now on
+    pub fn set_stats(&mut self, sink: Sink) {
+        self.stats = Some(sink);
+    }
+
+    /// Takes the block and its suffix array from `pool`, to which they go
+    /// back once the encoder is dropped. The whole block is taken at once.
+    pub fn set_pool(&mut self, pool: &Pool) {
+        self.pool = Some(pool.clone());
+    }
+
+    /// Sorts the suffixes of each block on `threads` threads
+    #[cfg(feature="threads")]
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads;
+    }
+
+    #[cfg(feature="threads")]
+    fn sort(&mut self) {
+        compute_suffixes_parallel(&self.buf[..], &mut self.suf[..], self.threads);
+    }
+
+    #[cfg(not(feature="threads"))]
+    fn sort(&mut self) {
+        compute_suffixes(&self.buf[..], &mut self.suf[..]);
+    }
+
+    /// Writes the maximum block size in front of the stream, even one
+    /// without data, which the decoder expects
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.wrote_header {
+            try!(self.w.write_u32::<LittleEndian>(self.block_size as u32));
+            self.wrote_header = true;
+            if let Some(ref sink) = self.stats {
+                sink.update(|stats| stats.output += 4);
+            }
+        }
+        Ok(())
+    }
+
     fn encode_block(&mut self) -> io::Result<()> {
         let n = self.buf.len();
         try!(self.w.write_u32::<LittleEndian>(n as u32));
 
         self.suf.truncate(0);
+        pool::reserve_sizes(self.pool.as_ref(), &mut self.suf, n);
         self.suf.extend((0..n).map(|_| n));
+        self.sort();
         let w = &mut self.w;
 
         {
-            let mut iter = encode(&self.buf[..], &mut self.suf[..]);
+            let mut iter = TransformIterator::new(&self.buf[..], &self.suf[..]);
             for ch in iter.by_ref() {
                 try!(w.write_u8(ch));
             }
 
             try!(w.write_u32::<LittleEndian>(iter.get_origin() as u32));
         }
+
+        if let Some(ref sink) = self.stats {
+            // the transform permutes the block, so it has the same symbols
+            let symbols = histogram(&self.buf);
+            sink.update(|stats| {
+                // the block with its size and origin
+                stats.output += n as u64 + 8;
+                stats.blocks += 1;
+                for (a, b) in stats.symbols.iter_mut().zip(symbols.iter()) {
+                    *a += *b as u64;
+                }
+            });
+        }
         self.buf.truncate(0);
 
         Ok(())
@@ -483,18 +1087,33 @@ impl<W: Write> Encoder<W> {
     /// with. The stream is finished up (final bytes are written), and then the
     /// wrapped writer is returned.
     pub fn finish(mut self) -> (W, io::Result<()>) {
-        let result = self.flush();
+        let result = self.write_header().and_then(|_| self.flush());
         (self.w, result)
     }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`
+    /// with the same block size, keeping the buffers. The previous writer is
+    /// returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.write_header().and_then(|_| self.flush());
+        self.buf.truncate(0);
+        self.wrote_header = false;
+        (mem::replace(&mut self.w, w), result)
+    }
 }
 
+#[cfg(feature="std")]
 impl<W: Write> Write for Encoder<W> {
     fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
-        if !self.wrote_header {
-            try!(self.w.write_u32::<LittleEndian>(self.block_size as u32));
-            self.wrote_header = true;
-        }
+        try!(self.write_header());
 
+        let len = buf.len();
+        if let Some(ref sink) = self.stats {
+            sink.update(|stats| stats.input += len as u64);
+        }
+        if self.pool.is_some() {
+            pool::reserve_bytes(self.pool.as_ref(), &mut self.buf, self.block_size);
+        }
         while buf.len() > 0 {
             let amt = cmp::min( self.block_size - self.buf.len(), buf.len() );
             self.buf.extend(buf[..amt].iter().map(|b| *b));
@@ -504,7 +1123,7 @@ impl<W: Write> Write for Encoder<W> {
             }
             buf = &buf[amt..];
         }
-        Ok(buf.len())
+        Ok(len)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -523,7 +1142,8 @@ mod test {
     use std::io::{BufReader, BufWriter, Read, Write};
     #[cfg(feature="unstable")]
     use test::Bencher;
-    use super::{Decoder, Encoder};
+    use testing::push;
+    use super::{BwtOptions, Decoder, Encoder, Processor};
 
     fn roundtrip(bytes: &[u8], extra_mem: bool) {
         let mut e = Encoder::new(BufWriter::new(Vec::new()), 1<<10);
@@ -536,6 +1156,10 @@ mod test {
         let mut decoded = Vec::new();
         d.read_to_end(&mut decoded).unwrap();
         assert_eq!(&decoded[..], bytes);
+        assert_eq!(&push(&mut Processor::new(extra_mem), &encoded, 97).unwrap()[..], bytes);
+        let mut p = Processor::new(extra_mem);
+        p.set_memory_budget(0);
+        assert_eq!(&push(&mut p, &encoded, 97).unwrap()[..], bytes);
     }
 
     #[test]
@@ -545,6 +1169,189 @@ mod test {
         roundtrip(include_bytes!("../data/test.txt"), true);
     }
 
+    #[test]
+    fn reset() {
+        let data = &include_bytes!("../data/test.txt")[..];
+        let mut e = Encoder::new(Vec::new(), 1 << 10);
+        e.write_all(&data[..3000]).unwrap();
+        let (first, err) = e.reset(Vec::new());
+        err.unwrap();
+        e.write_all(&data[3000..]).unwrap();
+        let (second, err) = e.finish();
+        err.unwrap();
+        let mut fresh = Encoder::new(Vec::new(), 1 << 10);
+        fresh.write_all(&data[3000..]).unwrap();
+        assert_eq!(second, fresh.finish().0);
+
+        let mut d = Decoder::new(&first[..], true);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], &data[..3000]);
+        d.r = &second[..];
+        d.reset();
+        decoded.truncate(0);
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], &data[3000..]);
+    }
+
+    #[test]
+    fn stats() {
+        use stats::Sink;
+        let data = &include_bytes!("../data/test.txt")[..];
+        let sink = Sink::new();
+        let mut e = Encoder::new(Vec::new(), 1 << 10);
+        e.set_stats(sink.clone());
+        e.write_all(data).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+
+        let stats = sink.get();
+        assert_eq!(stats.input, data.len() as u64);
+        assert_eq!(stats.output, encoded.len() as u64);
+        assert_eq!(stats.blocks, ((data.len() + 1023) >> 10) as u64);
+        assert_eq!(stats.symbols.iter().sum::<u64>(), data.len() as u64);
+        assert!(stats.symbol_entropy() > 0.0 && stats.symbol_entropy() < 8.0);
+    }
+
+    #[test]
+    fn pool() {
+        use pool::Pool;
+        let data = &include_bytes!("../data/test.txt")[..];
+        let pool = Pool::new(1 << 20);
+        for _ in 0..3 {
+            let mut e = Encoder::new(Vec::new(), 1 << 10);
+            e.set_pool(&pool);
+            e.write_all(data).unwrap();
+            let (encoded, err) = e.finish();
+            err.unwrap();
+
+            let mut d = Decoder::new(&encoded[..], true);
+            d.set_pool(&pool);
+            let mut decoded = Vec::new();
+            d.read_to_end(&mut decoded).unwrap();
+            assert_eq!(&decoded[..], data);
+        }
+        assert!(pool.reused() >= 8);
+    }
+
+    #[cfg(feature="threads")]
+    #[test]
+    fn parallel_suffixes() {
+        use testing::Lcg;
+        use super::{compute_suffixes, compute_suffixes_parallel};
+        let text = &include_bytes!("../data/test.txt")[..];
+        // skewed buckets: mostly one symbol, with long repeats
+        let mut skewed = vec![b'a'; 30000];
+        let mut lcg = Lcg(7);
+        for b in skewed.iter_mut().step_by(3) {
+            let x = lcg.step();
+            if x >> 30 == 0 {
+                *b = (x >> 16) as u8;
+            }
+        }
+        let repeated: Vec<u8> = text.iter().chain(text.iter()).cloned().collect();
+        for input in [text, &skewed[..], &repeated[..], b"banana"].iter() {
+            let mut expected = vec![0u32; input.len()];
+            compute_suffixes(input, &mut expected[..]);
+            for &threads in [1, 2, 4, 7].iter() {
+                let mut suffixes = vec![0u32; input.len()];
+                compute_suffixes_parallel(input, &mut suffixes[..], threads);
+                assert!(suffixes == expected);
+            }
+        }
+
+        let large = &include_bytes!("../data/test.large")[..100000];
+        let encode = |threads| {
+            let mut e = Encoder::new(Vec::new(), 1 << 16);
+            e.set_threads(threads);
+            e.write_all(large).unwrap();
+            let (encoded, err) = e.finish();
+            err.unwrap();
+            encoded
+        };
+        assert!(encode(4) == encode(1));
+    }
+
+    #[test]
+    fn cyclic_rotations() {
+        use super::{encode_cyclic, Workspace};
+        let mut work = Workspace::new();
+        for input in [&b""[..], b"a", b"banana", b"abababab", b"aaaa", b"mississippi",
+                      &include_bytes!("../data/test.txt")[..]].iter() {
+            let n = input.len();
+            let mut naive: Vec<Vec<u8>> = (0..n).map(|i| {
+                input[i..].iter().chain(input[..i].iter()).cloned().collect()
+            }).collect();
+            naive.sort();
+            let (output, origin) = encode_cyclic(input, &mut work);
+            let expected: Vec<u8> = naive.iter().map(|r| r[n - 1]).collect();
+            assert_eq!(output, expected);
+            if n > 0 {
+                assert_eq!(&naive[origin][..], &input[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_origin() {
+        let mut e = Encoder::new(Vec::new(), 1 << 10);
+        e.write_all(b"abracadabra").unwrap();
+        let (mut encoded, err) = e.finish();
+        err.unwrap();
+        let len = encoded.len();
+        encoded[len - 4..].copy_from_slice(&[11, 0, 0, 0]);
+        for &extra_mem in [true, false].iter() {
+            let mut d = Decoder::new(&encoded[..], extra_mem);
+            let mut decoded = Vec::new();
+            assert!(d.read_to_end(&mut decoded).is_err());
+        }
+    }
+
+    #[test]
+    fn auto_options() {
+        let data = include_bytes!("../data/test.txt");
+        for &budget in [0, 1 << 20, 1 << 30].iter() {
+            let options = BwtOptions::auto(budget);
+            let mut e = options.encoder(Vec::new()).unwrap();
+            e.write_all(data).unwrap();
+            let (encoded, err) = e.finish();
+            err.unwrap();
+            let mut decoded = Vec::new();
+            options.decoder(&encoded[..]).read_to_end(&mut decoded).unwrap();
+            assert!(&decoded[..] == &data[..]);
+        }
+        assert!(BwtOptions::auto(1 << 20).block_size < BwtOptions::auto(1 << 30).block_size);
+    }
+
+    #[test]
+    fn memory_budget() {
+        let data = include_bytes!("../data/test.txt");
+        let mut e = Encoder::new(Vec::new(), 1 << 10);
+        e.write_all(data).unwrap();
+        let (mut encoded, err) = e.finish();
+        err.unwrap();
+        for &budget in [0, 1 << 10, 1 << 20].iter() {
+            let mut d = BwtOptions::new().memory_budget(budget).decoder(&encoded[..]);
+            let mut decoded = Vec::new();
+            d.read_to_end(&mut decoded).unwrap();
+            assert!(&decoded[..] == &data[..]);
+        }
+
+        // the tables of the larger blocks
+        let (output, origin) = super::encode_simple(&data[..]);
+        let mut table = vec![0u32; output.len()];
+        super::compute_inversion_table(&output, origin, &mut table[..]);
+        let mut decoded = Vec::new();
+        super::chase_words(&output, origin, &table, &mut decoded);
+        assert!(&decoded[..] == &data[..]);
+
+        let len = encoded.len();
+        encoded[len - 4..].copy_from_slice(&[0, 4, 0, 0]);
+        let mut d = Decoder::new(&encoded[..], true);
+        d.set_memory_budget(0);
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
     #[test]
     fn decode_minimal() {
         roundtrip(b"abracadabra", false);
@@ -571,4 +1378,21 @@ mod test {
         });
         bh.bytes = n as u64;
     }
+
+    #[cfg(feature="unstable")]
+    #[bench]
+    fn decode_block_speed(bh: &mut Bencher) {
+        // a block larger than the caches, of which the walk misses at each step
+        let input = &include_bytes!("../data/test.large")[..1 << 22];
+        let mut e = Encoder::new(Vec::new(), input.len());
+        e.write_all(input).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        let mut output = Vec::with_capacity(input.len());
+        bh.iter(|| {
+            output.truncate(0);
+            Decoder::new(&encoded[..], true).read_to_end(&mut output).unwrap();
+        });
+        bh.bytes = input.len() as u64;
+    }
 }
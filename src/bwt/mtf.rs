@@ -11,6 +11,7 @@ http://en.wikipedia.org/wiki/Move-to-front_transform
 # Example
 
 ```rust
+#[cfg(feature="std")]
 use std::io::{self, Read, Write};
 use compress::bwt::mtf;
 
@@ -30,10 +31,16 @@ let result = d.read_to_end(&mut decoded).unwrap();
 
 */
 
+use std::cmp;
+use std::io;
+#[cfg(feature="std")]
+use std::io::{Read, Write};
 use std::mem;
-use std::io::{self, Read, Write};
 
-use super::super::byteorder::{self, WriteBytesExt, ReadBytesExt};
+use process::{Process, Status};
+#[cfg(feature="std")]
+use process::Reader;
+
 
 pub type Symbol = u8;
 pub type Rank = u8;
@@ -79,24 +86,44 @@ impl MTF {
     }
 
     /// decode a rank into its symbol
+    #[inline]
     pub fn decode(&mut self, rank: Rank) -> Symbol {
-        let sym = self.symbols[rank as usize];
-        debug!("\tDecoding rank {} with symbol {}", rank, sym);
-        for i in (0 .. rank as usize).rev() {
-            self.symbols[i+1] = self.symbols[i];
+        let rank = rank as usize;
+        let sym = self.symbols[rank];
+        // most ranks after a BWT are zeros, which leave the list as it is
+        if rank != 0 {
+            self.symbols.copy_within(..rank, 1);
+            self.symbols[0] = sym;
         }
-        self.symbols[0] = sym;
         sym
     }
+
+    /// encode a block of symbols into their ranks, in place
+    pub fn encode_all(&mut self, block: &mut [Symbol]) {
+        for sym in block.iter_mut() {
+            *sym = self.encode(*sym);
+        }
+    }
+
+    /// decode a block of ranks into their symbols, in place
+    pub fn decode_all(&mut self, block: &mut [Rank]) {
+        for rank in block.iter_mut() {
+            *rank = self.decode(*rank);
+        }
+    }
 }
 
 
 /// A simple MTF stream encoder
+#[cfg(feature="std")]
 pub struct Encoder<W> {
     w: W,
     mtf: MTF,
+    /// ranks of the last write, kept for the next one
+    buf: Vec<Rank>,
 }
 
+#[cfg(feature="std")]
 impl<W> Encoder<W> {
     /// start encoding into the given writer
     pub fn new(w: W) -> Encoder<W> {
@@ -105,6 +132,7 @@ impl<W> Encoder<W> {
         Encoder {
             w: w,
             mtf: mtf,
+            buf: Vec::new(),
         }
     }
 
@@ -112,14 +140,21 @@ impl<W> Encoder<W> {
     pub fn finish(self) -> W {
         self.w
     }
+
+    /// start encoding a new stream into `w`, returning the previous writer
+    pub fn reset(&mut self, w: W) -> W {
+        self.mtf.reset_alphabetical();
+        mem::replace(&mut self.w, w)
+    }
 }
 
+#[cfg(feature="std")]
 impl<W: Write> Write for Encoder<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        for sym in buf.iter() {
-            let rank = self.mtf.encode(*sym);
-            try!(self.w.write_u8(rank));
-        }
+        self.buf.clear();
+        self.buf.extend_from_slice(buf);
+        self.mtf.encode_all(&mut self.buf);
+        try!(self.w.write_all(&self.buf));
         Ok(buf.len())
     }
 
@@ -129,42 +164,83 @@ impl<W: Write> Write for Encoder<W> {
 }
 
 
+/// A MTF decoder of the ranks pushed into it, see the `process` module
+pub struct Processor {
+    mtf: MTF,
+    eof: bool,
+}
+
+impl Processor {
+    /// start decoding
+    pub fn new() -> Processor {
+        let mut mtf = MTF::new();
+        mtf.reset_alphabetical();
+        Processor {
+            mtf: mtf,
+            eof: false,
+        }
+    }
+
+    /// start decoding a new stream
+    pub fn reset(&mut self) {
+        self.mtf.reset_alphabetical();
+        self.eof = false;
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let n = cmp::min(input.len(), output.len());
+        output[..n].copy_from_slice(&input[..n]);
+        self.mtf.decode_all(&mut output[..n]);
+        let status = if n < input.len() {
+            Status::NeedOutput
+        } else if self.eof {
+            Status::Done
+        } else {
+            Status::NeedInput
+        };
+        Ok((n, n, status))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.eof = true;
+        Ok(())
+    }
+}
+
 /// A simple MTF stream decoder
+#[cfg(feature="std")]
 pub struct Decoder<R> {
-    r: R,
-    mtf: MTF,
+    inner: Reader<R, Processor>,
 }
 
-impl<R> Decoder<R> {
+#[cfg(feature="std")]
+impl<R: Read> Decoder<R> {
     /// start decoding the given reader
     pub fn new(r: R) -> Decoder<R> {
-        let mut mtf = MTF::new();
-        mtf.reset_alphabetical();
         Decoder {
-            r: r,
-            mtf: mtf,
+            inner: Reader::new(r, Processor::new()),
         }
     }
 
-    /// finish decoder and return the wrapped reader
+    /// finish decoder and return the wrapped reader, without the data read
+    /// ahead
     pub fn finish(self) -> R {
-        self.r
+        self.inner.into_inner().0
+    }
+
+    /// start decoding a new stream from `r`, returning the previous reader
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
     }
 }
 
+#[cfg(feature="std")]
 impl<R: Read> Read for Decoder<R> {
     fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
-        let mut bytes_read = 0;
-        for sym in dst.iter_mut() {
-            let rank = match self.r.read_u8() {
-                Ok(r) => r,
-                Err(byteorder::Error::UnexpectedEOF) => break,
-                Err(byteorder::Error::Io(e)) => return Err(e)
-            };
-            bytes_read += 1;
-            *sym = self.mtf.decode(rank);
-        }
-        Ok((bytes_read))
+        self.inner.read(dst)
     }
 }
 
@@ -174,7 +250,8 @@ mod test {
     use std::io::{self, Read, Write};
     #[cfg(feature="unstable")]
     use test::Bencher;
-    use super::{Encoder, Decoder};
+    use testing::{push, Lcg};
+    use super::{Encoder, Decoder, Processor};
 
     fn roundtrip(bytes: &[u8]) {
         info!("Roundtrip MTF of size {}", bytes.len());
@@ -187,6 +264,7 @@ mod test {
         let mut decoded = Vec::new();
         d.read_to_end(&mut decoded).unwrap();
         assert_eq!(&decoded[..], bytes);
+        assert_eq!(&push(&mut Processor::new(), &encoded, 5).unwrap()[..], bytes);
     }
 
     #[test]
@@ -196,6 +274,44 @@ mod test {
         roundtrip(include_bytes!("../data/test.txt"));
     }
 
+    #[test]
+    fn reset() {
+        let (first, second) = (&b"teeesst_mtf"[..], &include_bytes!("../data/test.txt")[..]);
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(first).unwrap();
+        let a = e.reset(Vec::new());
+        e.write_all(second).unwrap();
+        let b = e.finish();
+        let mut fresh = Encoder::new(Vec::new());
+        fresh.write_all(second).unwrap();
+        assert!(b == fresh.finish());
+
+        let mut d = Decoder::new(io::BufReader::new(&a[..]));
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], first);
+        d.reset(io::BufReader::new(&b[..]));
+        decoded.truncate(0);
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], second);
+    }
+
+    #[test]
+    fn decode_ranks() {
+        let mut mtf = super::MTF::new();
+        mtf.reset_alphabetical();
+        let mut naive: Vec<u8> = (0..256).map(|i| i as u8).collect();
+        let mut lcg = Lcg(1);
+        for _ in 0..10000 {
+            let x = lcg.step();
+            let rank = if x & 0x10000 != 0 { 0 } else { (x >> 24) as u8 };
+            let sym = naive.remove(rank as usize);
+            naive.insert(0, sym);
+            assert_eq!(mtf.decode(rank), sym);
+        }
+        assert_eq!(&mtf.symbols[..], &naive[..]);
+    }
+
     #[cfg(feature="unstable")]
     #[bench]
     fn encode_speed(bh: &mut Bencher) {
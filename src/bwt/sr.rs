@@ -0,0 +1,388 @@
+/*!
+
+SR (Symbol Ranking) encoder/decoder
+Produces a rank for each input character within the context of the previous one.
+Each context keeps its last seen symbols, which get the lowest ranks, the other
+symbols being escaped and ranked by a MTF list, without the symbols of the context.
+An alternative to MTF for the BWT output, where a symbol tells much about the next one.
+
+The ranks are on a byte, and `context()` gives the state of the ranking before each
+of them, so that an entropy coder may model the ranks apart in each state.
+
+# Links
+
+http://www.cs.auckland.ac.nz/~peter-f/FTPfiles/TechRep132.ps - Symbol ranking text
+compression, by Peter Fenwick
+
+# Example
+
+```rust
+#[cfg(feature="std")]
+use std::io::{self, Read, Write};
+use compress::bwt::sr;
+
+// Encode a stream of bytes
+let bytes = b"abracadabra";
+let mut e = sr::Encoder::new(io::BufWriter::new(Vec::new()));
+e.write_all(bytes).unwrap();
+let encoded = e.finish().into_inner().unwrap();
+
+// Decode a stream of ranks
+let mut d = sr::Decoder::new(io::BufReader::new(&encoded[..]));
+let mut decoded = Vec::new();
+d.read_to_end(&mut decoded).unwrap();
+assert_eq!(&decoded[..], &bytes[..]);
+```
+
+# Credit
+
+This is an original implementation.
+
+*/
+
+use std::cmp;
+use std::io;
+#[cfg(feature="std")]
+use std::io::{Read, Write};
+#[cfg(feature="std")]
+use std::mem;
+
+#[cfg(feature="std")]
+use super::super::byteorder::WriteBytesExt;
+use process::{Process, Status};
+#[cfg(feature="std")]
+use process::Reader;
+use super::mtf::{MTF, Rank, Symbol, TOTAL_SYMBOLS};
+
+/// Number of symbols kept by each context, and first rank of the escaped symbols
+pub const RANKS: usize = 3;
+/// Number of states returned by `context()`
+pub const CONTEXTS: usize = 4;
+
+
+/// Symbol ranking encoder/decoder
+pub struct SR {
+    /// last seen symbols after each symbol, the most recent first
+    contexts: [[Symbol; RANKS]; TOTAL_SYMBOLS],
+    /// rank-ordered list of all the symbols, for the escaped ones
+    mtf: MTF,
+    prev: Symbol,
+    /// number of ranks 0 in a row
+    hits: usize,
+}
+
+impl SR {
+    /// create a new SR, in the same state as the decoder
+    pub fn new() -> SR {
+        let mut mtf = MTF::new();
+        mtf.reset_alphabetical();
+        let mut contexts = [[0; RANKS]; TOTAL_SYMBOLS];
+        for (i, ctx) in contexts.iter_mut().enumerate() {
+            // the symbol itself first, as for the runs
+            for (j, sym) in ctx.iter_mut().enumerate() {
+                *sym = (i + j) as Symbol;
+            }
+        }
+        SR {
+            contexts: contexts,
+            mtf: mtf,
+            prev: 0,
+            hits: 0,
+        }
+    }
+
+    /// start a new stream, back in the initial state
+    pub fn reset(&mut self) {
+        *self = SR::new();
+    }
+
+    /// return the state of the ranking, below `CONTEXTS`, which is the
+    /// number of ranks 0 in a row before the next rank
+    pub fn context(&self) -> usize {
+        if self.hits < CONTEXTS { self.hits } else { CONTEXTS - 1 }
+    }
+
+    fn update(&mut self, sym: Symbol, rank: Rank) {
+        {
+            let ctx = &mut self.contexts[self.prev as usize];
+            let pos = if (rank as usize) < RANKS { rank as usize } else { RANKS - 1 };
+            for i in (0 .. pos).rev() {
+                ctx[i+1] = ctx[i];
+            }
+            ctx[0] = sym;
+        }
+        self.mtf.encode(sym);
+        self.prev = sym;
+        self.hits = if rank == 0 { self.hits + 1 } else { 0 };
+    }
+
+    /// encode a symbol into its rank
+    pub fn encode(&mut self, sym: Symbol) -> Rank {
+        let rank = match self.contexts[self.prev as usize].iter().position(|&s| s == sym) {
+            Some(r) => r as Rank,
+            None => {
+                let ctx = &self.contexts[self.prev as usize];
+                let excluded = self.mtf.symbols.iter()
+                                   .take_while(|&&s| s != sym)
+                                   .filter(|s| !ctx.contains(s))
+                                   .count();
+                (RANKS + excluded) as Rank
+            }
+        };
+        self.update(sym, rank);
+        rank
+    }
+
+    /// decode a rank into its symbol
+    pub fn decode(&mut self, rank: Rank) -> Symbol {
+        let sym = if (rank as usize) < RANKS {
+            self.contexts[self.prev as usize][rank as usize]
+        } else {
+            let ctx = &self.contexts[self.prev as usize];
+            *self.mtf.symbols.iter()
+                 .filter(|s| !ctx.contains(s))
+                 .nth(rank as usize - RANKS).unwrap()
+        };
+        debug!("\tDecoding rank {} with symbol {}", rank, sym);
+        self.update(sym, rank);
+        sym
+    }
+}
+
+
+/// A simple SR stream encoder
+#[cfg(feature="std")]
+pub struct Encoder<W> {
+    w: W,
+    sr: SR,
+}
+
+#[cfg(feature="std")]
+impl<W> Encoder<W> {
+    /// start encoding into the given writer
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder {
+            w: w,
+            sr: SR::new(),
+        }
+    }
+
+    /// finish encoding and return the wrapped writer
+    pub fn finish(self) -> W {
+        self.w
+    }
+
+    /// start encoding a new stream into `w`, returning the previous writer
+    pub fn reset(&mut self, w: W) -> W {
+        self.sr.reset();
+        mem::replace(&mut self.w, w)
+    }
+}
+
+#[cfg(feature="std")]
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for sym in buf.iter() {
+            let rank = self.sr.encode(*sym);
+            try!(self.w.write_u8(rank));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+
+/// A SR decoder of the ranks pushed into it, see the `process` module
+pub struct Processor {
+    sr: SR,
+    eof: bool,
+}
+
+impl Processor {
+    /// start decoding
+    pub fn new() -> Processor {
+        Processor {
+            sr: SR::new(),
+            eof: false,
+        }
+    }
+
+    /// start decoding a new stream
+    pub fn reset(&mut self) {
+        self.sr.reset();
+        self.eof = false;
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let n = cmp::min(input.len(), output.len());
+        for (sym, &rank) in output[..n].iter_mut().zip(input.iter()) {
+            *sym = self.sr.decode(rank);
+        }
+        let status = if n < input.len() {
+            Status::NeedOutput
+        } else if self.eof {
+            Status::Done
+        } else {
+            Status::NeedInput
+        };
+        Ok((n, n, status))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// A simple SR stream decoder
+#[cfg(feature="std")]
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+#[cfg(feature="std")]
+impl<R: Read> Decoder<R> {
+    /// start decoding the given reader
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new()),
+        }
+    }
+
+    /// finish decoder and return the wrapped reader, without the data read
+    /// ahead
+    pub fn finish(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// start decoding a new stream from `r`, returning the previous reader
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+}
+
+#[cfg(feature="std")]
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use super::super::{encode_simple, mtf};
+    use testing::push;
+    use super::{Encoder, Decoder, Processor, SR, CONTEXTS};
+
+    fn roundtrip(bytes: &[u8]) -> Vec<u8> {
+        let mut e = Encoder::new(io::BufWriter::new(Vec::new()));
+        e.write_all(bytes).unwrap();
+        let encoded = e.finish().into_inner().unwrap();
+        let mut d = Decoder::new(io::BufReader::new(&encoded[..]));
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], bytes);
+        assert_eq!(&push(&mut Processor::new(), &encoded, 5).unwrap()[..], bytes);
+        encoded
+    }
+
+    #[test]
+    fn known_data() {
+        // the contexts start with their symbol and the next ones, so that a run
+        // and b after a have low ranks, while a after 0 is escaped, ranked
+        // after the 3 excluded symbols of the context of 0
+        assert_eq!(roundtrip(b"\x00\x00ab"), [0, 0, 3 + 0x61 - 3, 1]);
+        assert_eq!(roundtrip(b"abcb"), [3 + 0x61 - 3, 1, 1, 3]);
+    }
+
+    #[test]
+    fn reset() {
+        let (first, second) = (&b"teeesst_sr"[..], &include_bytes!("../data/test.txt")[..]);
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(first).unwrap();
+        let a = e.reset(Vec::new());
+        e.write_all(second).unwrap();
+        let b = e.finish();
+        let mut fresh = Encoder::new(Vec::new());
+        fresh.write_all(second).unwrap();
+        assert!(b == fresh.finish());
+
+        let mut d = Decoder::new(io::BufReader::new(&a[..]));
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], first);
+        d.reset(io::BufReader::new(&b[..]));
+        decoded.truncate(0);
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], second);
+    }
+
+    #[test]
+    fn some_roundtrips() {
+        roundtrip(b"teeesst_sr");
+        roundtrip(b"");
+        let all: Vec<u8> = (0..10000).map(|i| ((i * 7919) % 251) as u8).rev().collect();
+        roundtrip(&all);
+        roundtrip(include_bytes!("../data/test.txt"));
+    }
+
+    /// Returns the size in bits of order 0 codes of the ranks
+    fn entropy(ranks: &[(usize, u8)], contexts: usize) -> f64 {
+        let mut freq = vec![[0usize; 0x100]; contexts];
+        for &(ctx, rank) in ranks.iter() {
+            freq[ctx][rank as usize] += 1;
+        }
+        freq.iter().map(|f| {
+            let total = f.iter().sum::<usize>() as f64;
+            f.iter().filter(|&&n| n > 0).map(|&n| -(n as f64) * (n as f64 / total).log2()).sum::<f64>()
+        }).sum()
+    }
+
+    #[test]
+    fn after_bwt() {
+        // coded within their contexts, the ranks of text take less than those
+        // of MTF
+        let (bwt, _) = encode_simple(include_bytes!("../data/test.txt"));
+        let mut sr = SR::new();
+        let ranks: Vec<(usize, u8)> = bwt.iter().map(|&sym| (sr.context(), sr.encode(sym))).collect();
+        let mut e = mtf::Encoder::new(Vec::new());
+        e.write_all(&bwt).unwrap();
+        let mtf_ranks: Vec<(usize, u8)> = e.finish().into_iter().map(|rank| (0, rank)).collect();
+        assert!(entropy(&ranks, CONTEXTS) < entropy(&mtf_ranks, 1) * 0.97);
+        let decoded: Vec<u8> = {
+            let mut sr = SR::new();
+            ranks.iter().map(|&(ctx, rank)| {
+                assert_eq!(sr.context(), ctx);
+                sr.decode(rank)
+            }).collect()
+        };
+        assert!(decoded == bwt);
+    }
+
+    #[test]
+    fn contexts() {
+        let (bwt, _) = encode_simple(include_bytes!("../data/test.txt"));
+        let mut sr = SR::new();
+        let mut zeros = [0usize; CONTEXTS];
+        let mut counts = [0usize; CONTEXTS];
+        for &sym in bwt.iter() {
+            let ctx = sr.context();
+            counts[ctx] += 1;
+            if sr.encode(sym) == 0 {
+                zeros[ctx] += 1;
+            }
+        }
+        // the longer the run of hits, the likelier the next one
+        for i in 1..CONTEXTS {
+            assert!(zeros[i] * counts[i - 1] > zeros[i - 1] * counts[i]);
+        }
+    }
+}
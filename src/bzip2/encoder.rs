@@ -0,0 +1,569 @@
+//! bzip2 compression
+//!
+//! Each block is sorted with the cyclic BWT of the `bwt` module, and the MTF
+//! ranks, with their runs of zeroes coded as RUNA/RUNB, are written with up to
+//! six huffman tables. The tables are refined over a few passes which also
+//! pick the cheapest table for every group of 50 symbols.
+
+use std::cmp;
+use std::collections::BinaryHeap;
+use std::io::{self, Write};
+use std::mem;
+
+use bwt;
+use bwt::mtf::MTF;
+use super::{MAGIC, BLOCK_MAGIC, END_MAGIC, BLOCK_UNIT, RUNA, RUNB, MAX_GROUPS,
+            GROUP_SIZE, MAX_ALPHA_SIZE, RUN_LENGTH};
+use checksum::crc32::Bzip2State32;
+use histogram::histogram;
+
+/// Longest code the encoder produces, as bzip2 does, under the limit of 20
+/// that decoders accept
+const MAX_CODE_LEN: u8 = 17;
+/// Number of passes refining the huffman tables
+const ITERATIONS: usize = 4;
+/// Room left in a block for the longest run that may be added to it
+const BLOCK_SLACK: usize = 19;
+
+/// Writes bits MSB first
+struct BitWriter {
+    out: Vec<u8>,
+    buf: u64,
+    cnt: usize,
+}
+
+impl BitWriter {
+    fn bits(&mut self, cnt: usize, value: u32) {
+        debug_assert!(cnt <= 32);
+        self.buf = (self.buf << cnt) | value as u64;
+        self.cnt += cnt;
+        while self.cnt >= 8 {
+            self.cnt -= 8;
+            self.out.push((self.buf >> self.cnt) as u8);
+        }
+    }
+
+    fn bits48(&mut self, value: u64) {
+        self.bits(24, (value >> 24) as u32);
+        self.bits(24, value as u32 & 0xffffff);
+    }
+
+    /// Completes the current byte with zeroes
+    fn pad(&mut self) {
+        if self.cnt > 0 {
+            let cnt = 8 - self.cnt;
+            self.bits(cnt, 0);
+        }
+    }
+}
+
+/// Computes huffman code lengths no longer than `MAX_CODE_LEN` for all the
+/// symbols, giving some code even to the unused ones. The frequencies are
+/// flattened until the longest code fits, as bzip2 does.
+fn code_lengths(freq: &[u32], lens: &mut [u8]) {
+    let n = freq.len();
+    let mut weight: Vec<u32> = freq.iter().map(|&f| cmp::max(f, 1)).collect();
+    let mut parent: Vec<usize> = Vec::with_capacity(2 * n);
+    loop {
+        // leaves are the nodes below `n`, internal nodes are pushed after
+        parent.truncate(0);
+        parent.extend((0..n).map(|_| 0));
+        let mut heap: BinaryHeap<cmp::Reverse<(u32, usize)>> = weight.iter()
+            .enumerate().map(|(i, &w)| cmp::Reverse((w, i))).collect();
+        while heap.len() > 1 {
+            let cmp::Reverse((w1, a)) = heap.pop().unwrap();
+            let cmp::Reverse((w2, b)) = heap.pop().unwrap();
+            let node = parent.len();
+            parent.push(0);
+            parent[a] = node;
+            parent[b] = node;
+            heap.push(cmp::Reverse((w1 + w2, node)));
+        }
+        // parents come after their children, so depths can be propagated
+        // from the root down
+        let root = parent.len() - 1;
+        let mut depth: Vec<u8> = parent.iter().map(|_| 0).collect();
+        for node in (0..root).rev() {
+            depth[node] = depth[parent[node]] + 1;
+        }
+        let mut longest = 0;
+        for (len, &d) in lens.iter_mut().zip(depth.iter()) {
+            *len = d;
+            longest = cmp::max(longest, d);
+        }
+        if longest <= MAX_CODE_LEN {
+            return
+        }
+        for w in weight.iter_mut() {
+            *w = 1 + *w / 2;
+        }
+    }
+}
+
+/// Assigns the canonical codes of the given lengths, as the decoder expects
+fn canonical_codes(lens: &[u8], codes: &mut [u32]) {
+    let mut code = 0;
+    for len in 1..(MAX_CODE_LEN + 1) {
+        for (sym, &l) in lens.iter().enumerate() {
+            if l == len {
+                codes[sym] = code;
+                code += 1;
+            }
+        }
+        code <<= 1;
+    }
+}
+
+/// Appends a run of `n` zero ranks, in bijective base 2
+fn push_zeroes(syms: &mut Vec<u16>, mut n: usize) {
+    while n > 0 {
+        n -= 1;
+        syms.push(if n & 1 == 0 { RUNA } else { RUNB });
+        n >>= 1;
+    }
+}
+
+/// Buffers of the blocks, kept from one block to the next
+#[derive(Default)]
+struct Workspace {
+    bwt: bwt::Workspace,
+    syms: Vec<u16>,
+}
+
+/// Compresses a block, whose runs of identical bytes are already coded, along
+/// with the CRC of its original bytes. A randomized block must have been
+/// flipped beforehand.
+fn encode_block(bits: &mut BitWriter, work: &mut Workspace, block: &[u8], crc: u32, randomized: bool) {
+    debug_assert!(!block.is_empty());
+    let (transformed, origin) = bwt::encode_cyclic(block, &mut work.bwt);
+
+    let freq = histogram(block);
+    let mut in_use = [false; 256];
+    for (u, &f) in in_use.iter_mut().zip(freq.iter()) {
+        *u = f > 0;
+    }
+    let mut unseq_to_seq = [0u8; 256];
+    let mut used = 0;
+    for (i, _) in in_use.iter().enumerate().filter(|&(_, &u)| u) {
+        unseq_to_seq[i] = used as u8;
+        used += 1;
+    }
+    let alpha_size = used + 2;
+    let end_of_block = alpha_size as u16 - 1;
+
+    // MTF ranks, shifted by one to make room for RUNA and RUNB
+    let syms = &mut work.syms;
+    syms.clear();
+    let mut mtf = MTF::new();
+    mtf.reset_alphabetical();
+    let mut zeroes = 0;
+    for &b in transformed.iter() {
+        match mtf.encode(unseq_to_seq[b as usize]) {
+            0 => zeroes += 1,
+            rank => {
+                push_zeroes(syms, zeroes);
+                zeroes = 0;
+                syms.push(rank as u16 + 1);
+            }
+        }
+    }
+    push_zeroes(syms, zeroes);
+    syms.push(end_of_block);
+
+    let mut freq = [0u32; MAX_ALPHA_SIZE];
+    for &s in syms.iter() {
+        freq[s as usize] += 1;
+    }
+    let num_groups = if syms.len() < 200 {
+        2
+    } else if syms.len() < 600 {
+        3
+    } else if syms.len() < 1200 {
+        4
+    } else if syms.len() < 2400 {
+        5
+    } else {
+        MAX_GROUPS
+    };
+
+    // Start with tables favouring consecutive ranges of symbols, of about
+    // the same total frequency
+    let mut lens = [[0u8; MAX_ALPHA_SIZE]; MAX_GROUPS];
+    let mut remaining = syms.len() as u32;
+    let mut lo = 0;
+    for t in 0..num_groups {
+        let target = remaining / (num_groups - t) as u32;
+        let mut hi = lo;
+        let mut sum = freq[lo];
+        while sum < target && hi + 1 < alpha_size {
+            hi += 1;
+            sum += freq[hi];
+        }
+        // give the remainder back to the next table, as bzip2 does
+        if hi > lo && t != 0 && t != num_groups - 1 && t % 2 == 1 {
+            sum -= freq[hi];
+            hi -= 1;
+        }
+        for sym in 0..alpha_size {
+            lens[t][sym] = if sym >= lo && sym <= hi { 0 } else { 15 };
+        }
+        remaining = remaining.saturating_sub(sum);
+        lo = cmp::min(hi + 1, alpha_size - 1);
+    }
+
+    let num_selectors = (syms.len() + GROUP_SIZE - 1) / GROUP_SIZE;
+    let mut selectors = vec![0u8; num_selectors];
+    for _ in 0..ITERATIONS {
+        let mut group_freq = [[0u32; MAX_ALPHA_SIZE]; MAX_GROUPS];
+        for (group, selector) in syms.chunks(GROUP_SIZE).zip(selectors.iter_mut()) {
+            let mut best = (u32::max_value(), 0);
+            for t in 0..num_groups {
+                let cost = group.iter().map(|&s| lens[t][s as usize] as u32).sum();
+                if cost < best.0 {
+                    best = (cost, t);
+                }
+            }
+            *selector = best.1 as u8;
+            for &s in group.iter() {
+                group_freq[best.1][s as usize] += 1;
+            }
+        }
+        for t in 0..num_groups {
+            code_lengths(&group_freq[t][..alpha_size], &mut lens[t][..alpha_size]);
+        }
+    }
+
+    bits.bits48(BLOCK_MAGIC);
+    bits.bits(32, crc);
+    bits.bits(1, randomized as u32);
+    bits.bits(24, origin as u32);
+
+    let mut groups = 0;
+    for i in 0..16 {
+        if in_use[i * 16..(i + 1) * 16].iter().any(|&u| u) {
+            groups |= 0x8000 >> i;
+        }
+    }
+    bits.bits(16, groups);
+    for i in 0..16 {
+        if groups & (0x8000 >> i) != 0 {
+            let mut map = 0;
+            for j in 0..16 {
+                if in_use[i * 16 + j] {
+                    map |= 0x8000 >> j;
+                }
+            }
+            bits.bits(16, map);
+        }
+    }
+
+    bits.bits(3, num_groups as u32);
+    bits.bits(15, num_selectors as u32);
+    mtf.reset_alphabetical();
+    for &selector in selectors.iter() {
+        let rank = mtf.encode(selector) as usize;
+        bits.bits(rank + 1, ((1u32 << rank) - 1) << 1);
+    }
+
+    // Code lengths, delta coded
+    let mut codes = [[0u32; MAX_ALPHA_SIZE]; MAX_GROUPS];
+    for t in 0..num_groups {
+        let mut len = lens[t][0];
+        bits.bits(5, len as u32);
+        for &target in lens[t][..alpha_size].iter() {
+            while len < target {
+                bits.bits(2, 2);
+                len += 1;
+            }
+            while len > target {
+                bits.bits(2, 3);
+                len -= 1;
+            }
+            bits.bits(1, 0);
+        }
+        canonical_codes(&lens[t][..alpha_size], &mut codes[t][..alpha_size]);
+    }
+
+    for (group, &selector) in syms.chunks(GROUP_SIZE).zip(selectors.iter()) {
+        let (lens, codes) = (&lens[selector as usize], &codes[selector as usize]);
+        for &s in group.iter() {
+            bits.bits(lens[s as usize] as usize, codes[s as usize]);
+        }
+    }
+}
+
+/// This structure is used to compress a stream to the bzip2 format. The output
+/// is completed by the `finish` method, which returns the wrapped writer.
+pub struct Encoder<W> {
+    w: W,
+    level: usize,
+    bits: BitWriter,
+    header: bool,
+
+    /// Current block, with the runs of identical bytes already coded
+    block: Vec<u8>,
+    work: Workspace,
+    block_crc: Bzip2State32,
+    stream_crc: u32,
+    /// Run of identical bytes not yet added to the block
+    last: u8,
+    run: usize,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will write the compressed stream to `w`.
+    /// The level, from 1 to 9, gives the size of the blocks in units of
+    /// 100 kB: larger blocks compress better, but sorting them takes more
+    /// time and memory.
+    pub fn new(w: W, level: u8) -> Encoder<W> {
+        assert!(level >= 1 && level <= 9, "invalid bzip2 level {}", level);
+        Encoder {
+            w: w,
+            level: level as usize,
+            bits: BitWriter { out: Vec::new(), buf: 0, cnt: 0 },
+            header: false,
+            block: Vec::new(),
+            work: Workspace::default(),
+            block_crc: Bzip2State32::new(),
+            stream_crc: 0,
+            last: 0,
+            run: 0,
+        }
+    }
+
+    fn block_limit(&self) -> usize {
+        self.level * BLOCK_UNIT - BLOCK_SLACK
+    }
+
+    /// Adds the pending run to the block, in at most 5 bytes, giving way to
+    /// the next block once this one is full
+    fn push_run(&mut self) {
+        if self.run == 0 {
+            return
+        }
+        self.block_crc.feed(&[self.last; 255][..self.run]);
+        let (n, last) = (cmp::min(self.run, RUN_LENGTH), self.last);
+        self.block.extend((0..n).map(|_| last));
+        if self.run >= RUN_LENGTH {
+            self.block.push((self.run - RUN_LENGTH) as u8);
+        }
+        self.run = 0;
+        if self.block.len() >= self.block_limit() {
+            self.end_block();
+        }
+    }
+
+    fn write_header(&mut self) {
+        if !self.header {
+            self.bits.out.extend(MAGIC.iter().cloned());
+            self.bits.out.push(b'0' + self.level as u8);
+            self.header = true;
+        }
+    }
+
+    fn end_block(&mut self) {
+        self.write_header();
+        if self.block.is_empty() {
+            return
+        }
+        let crc = self.block_crc.result();
+        encode_block(&mut self.bits, &mut self.work, &self.block, crc, false);
+        self.stream_crc = self.stream_crc.rotate_left(1) ^ crc;
+        self.block.truncate(0);
+        self.block_crc.reset();
+    }
+
+    /// Writes out the complete bytes of the stream so far
+    fn write_out(&mut self) -> io::Result<()> {
+        try!(self.w.write_all(&self.bits.out));
+        self.bits.out.truncate(0);
+        Ok(())
+    }
+
+    /// Compresses the last block and writes the end of the stream, returning
+    /// the wrapped writer along with the result of the operation.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.end();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`
+    /// with the same level, keeping the buffers. The previous writer is
+    /// returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.end();
+        self.bits.out.truncate(0);
+        self.header = false;
+        self.stream_crc = 0;
+        (mem::replace(&mut self.w, w), result)
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        self.push_run();
+        self.end_block();
+        self.bits.bits48(END_MAGIC);
+        self.bits.bits(32, self.stream_crc);
+        self.bits.pad();
+        self.write_out().and_then(|_| self.w.flush())
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf.iter() {
+            if self.run > 0 && (byte != self.last || self.run == 255) {
+                self.push_run();
+            }
+            self.last = byte;
+            self.run += 1;
+        }
+        if !self.bits.out.is_empty() {
+            try!(self.write_out());
+        }
+        Ok(buf.len())
+    }
+
+    /// Ends the current block, so that all the data written so far can be
+    /// decoded but for the few bits completing the last byte
+    fn flush(&mut self) -> io::Result<()> {
+        self.push_run();
+        self.end_block();
+        try!(self.write_out());
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing::Lcg;
+    use super::{BitWriter, Encoder, Workspace, encode_block};
+    use super::super::{Decoder, RNUMS, MAGIC, END_MAGIC};
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        out
+    }
+
+    fn encode(input: &[u8], level: u8) -> Vec<u8> {
+        let mut e = Encoder::new(Vec::new(), level);
+        e.write_all(input).unwrap();
+        let (out, result) = e.finish();
+        result.unwrap();
+        out
+    }
+
+    fn roundtrip(input: &[u8], level: u8) {
+        let compressed = encode(input, level);
+        assert!(&decode(&compressed)[..] == input);
+    }
+
+    #[test]
+    fn some_roundtrips() {
+        roundtrip(b"", 9);
+        roundtrip(b"a", 9);
+        roundtrip(b"abababababababababab", 9);
+        roundtrip(&include_bytes!("../data/test.txt")[..], 9);
+        let runs: Vec<u8> = (0..3000).map(|i| (i / 300) as u8).collect();
+        roundtrip(&runs, 1);
+        let long: Vec<u8> = (0..1000).map(|_| b'z').collect();
+        roundtrip(&long, 1);
+    }
+
+    #[test]
+    fn multiple_blocks() {
+        let mut input = Vec::new();
+        let mut lcg = Lcg(1);
+        while input.len() < 250000 {
+            let seed = lcg.step();
+            let len = (seed >> 16) as usize % 300;
+            let byte = b'a' + (seed >> 28) as u8;
+            input.extend((0..len).map(|_| byte));
+            input.extend(include_bytes!("../data/test.txt")[..len].iter().cloned());
+        }
+        let compressed = encode(&input, 1);
+        assert!(compressed.len() < input.len() / 4);
+        assert!(decode(&compressed) == input);
+    }
+
+    #[test]
+    fn compresses() {
+        let input = include_bytes!("../data/test.txt");
+        let compressed = encode(input, 9);
+        // as well as bzip2 does, give or take a few bytes
+        assert!(compressed.len() <= include_bytes!("../data/test.bz2").len() * 102 / 100);
+    }
+
+    #[test]
+    fn reset() {
+        let input = include_bytes!("../data/test.txt");
+        let mut e = Encoder::new(Vec::new(), 9);
+        e.write_all(b"hello").unwrap();
+        let (first, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(input).unwrap();
+        let (second, result) = e.finish();
+        result.unwrap();
+        assert!(first == encode(b"hello", 9));
+        assert!(second == encode(input, 9));
+
+        let mut d = Decoder::new(BufReader::new(&first[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"hello");
+        d.reset(BufReader::new(&second[..]));
+        out.truncate(0);
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(&out[..] == &input[..]);
+    }
+
+    #[test]
+    fn flush_and_small_writes() {
+        let input = include_bytes!("../data/test.txt");
+        let mut e = Encoder::new(Vec::new(), 9);
+        for (i, chunk) in input.chunks(777).enumerate() {
+            for piece in chunk.chunks(13) {
+                e.write_all(piece).unwrap();
+            }
+            if i % 2 == 1 {
+                e.flush().unwrap();
+            }
+        }
+        let (out, result) = e.finish();
+        result.unwrap();
+        assert!(&decode(&out)[..] == &input[..]);
+    }
+
+    #[test]
+    fn randomized_block() {
+        let input = &include_bytes!("../data/test.txt")[..];
+        let mut e = Encoder::new(Vec::new(), 9);
+        e.write_all(input).unwrap();
+        e.push_run();
+        let (mut block, crc) = (e.block.clone(), e.block_crc.result());
+        let (mut index, mut to_go) = (0, 0);
+        for b in block.iter_mut() {
+            if to_go == 0 {
+                to_go = RNUMS[index];
+                index = (index + 1) % RNUMS.len();
+            }
+            to_go -= 1;
+            if to_go == 1 {
+                *b ^= 1;
+            }
+        }
+        assert!(block != e.block);
+
+        let mut bits = BitWriter { out: MAGIC.to_vec(), buf: 0, cnt: 0 };
+        bits.out.push(b'9');
+        encode_block(&mut bits, &mut Workspace::default(), &block, crc, true);
+        bits.bits48(END_MAGIC);
+        bits.bits(32, crc);
+        bits.pad();
+        assert!(&decode(&bits.out)[..] == input);
+    }
+}
@@ -0,0 +1,796 @@
+//! BZIP2 Compression and Decompression. Requires `bzip2` feature, enabled by default
+//!
+//! This module contains an implementation of the bzip2 file format, as read
+//! by `bunzip2`. Each block of a stream goes through the following stages:
+//!
+//! * huffman decoding, with a table switched every 50 symbols
+//! * RUNA/RUNB decoding of the runs of zeroes, then MTF decoding
+//! * inverse BWT (Burrows-Wheeler Transform)
+//! * decoding of the runs of 4 to 255 identical bytes
+//!
+//! The decoded blocks are checked against their CRC, and the whole stream
+//! against the combined CRC found at its end. Concatenated streams are decoded
+//! one after another, as `bunzip2` does.
+//!
+//! The encoder goes through the same stages backwards, with block sizes of
+//! 100 to 900 kB as chosen by the level.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::bzip2;
+//! use std::fs::File;
+//! use std::path::Path;
+//!
+//! let stream = File::open(&Path::new("path/to/file.bz2")).unwrap();
+//! let mut decompressed = Vec::new();
+//! bzip2::Decoder::new(stream).read_to_end(&mut decompressed);
+//! ```
+//!
+//! # Related links
+//!
+//! * https://github.com/dsnet/compress/blob/master/doc/bzip2-format.pdf -
+//!   an unofficial description of the format
+//! * https://sourceware.org/bzip2/ - the reference implementation
+
+use std::cmp;
+use std::io::{self, Read};
+
+use super::byteorder::ReadBytesExt;
+use bwt::mtf::MTF;
+use checksum::crc32::Bzip2State32;
+use error::{checksum_mismatch, corrupt, truncated};
+use process::{Process, Reader, Status, Window};
+
+pub use self::encoder::Encoder;
+
+mod encoder;
+
+const MAGIC: [u8; 3] = [b'B', b'Z', b'h'];
+const BLOCK_MAGIC: u64 = 0x314159265359;
+const END_MAGIC: u64 = 0x177245385090;
+/// Unit of the block size given by the stream header
+const BLOCK_UNIT: usize = 100000;
+
+const RUNA: u16 = 0;
+const RUNB: u16 = 1;
+const MIN_GROUPS: usize = 2;
+const MAX_GROUPS: usize = 6;
+/// Number of symbols coded by a table before the next selector applies
+const GROUP_SIZE: usize = 50;
+const MAX_SELECTORS: usize = 2 + (900000 / GROUP_SIZE);
+const MAX_ALPHA_SIZE: usize = 258;
+const MAX_CODE_LEN: usize = 20;
+
+/// Length of a run of identical bytes past which a repeat count follows
+const RUN_LENGTH: usize = 4;
+
+/// Positions of the bytes flipped in randomized blocks, which old versions of
+/// bzip2 produced when sorting the block got too slow
+static RNUMS: [u16; 512] = [
+    619, 720, 127, 481, 931, 816, 813, 233, 566, 247, 985, 724, 205, 454, 863, 491,
+    741, 242, 949, 214, 733, 859, 335, 708, 621, 574, 73, 654, 730, 472, 419, 436,
+    278, 496, 867, 210, 399, 680, 480, 51, 878, 465, 811, 169, 869, 675, 611, 697,
+    867, 561, 862, 687, 507, 283, 482, 129, 807, 591, 733, 623, 150, 238, 59, 379,
+    684, 877, 625, 169, 643, 105, 170, 607, 520, 932, 727, 476, 693, 425, 174, 647,
+    73, 122, 335, 530, 442, 853, 695, 249, 445, 515, 909, 545, 703, 919, 874, 474,
+    882, 500, 594, 612, 641, 801, 220, 162, 819, 984, 589, 513, 495, 799, 161, 604,
+    958, 533, 221, 400, 386, 867, 600, 782, 382, 596, 414, 171, 516, 375, 682, 485,
+    911, 276, 98, 553, 163, 354, 666, 933, 424, 341, 533, 870, 227, 730, 475, 186,
+    263, 647, 537, 686, 600, 224, 469, 68, 770, 919, 190, 373, 294, 822, 808, 206,
+    184, 943, 795, 384, 383, 461, 404, 758, 839, 887, 715, 67, 618, 276, 204, 918,
+    873, 777, 604, 560, 951, 160, 578, 722, 79, 804, 96, 409, 713, 940, 652, 934,
+    970, 447, 318, 353, 859, 672, 112, 785, 645, 863, 803, 350, 139, 93, 354, 99,
+    820, 908, 609, 772, 154, 274, 580, 184, 79, 626, 630, 742, 653, 282, 762, 623,
+    680, 81, 927, 626, 789, 125, 411, 521, 938, 300, 821, 78, 343, 175, 128, 250,
+    170, 774, 972, 275, 999, 639, 495, 78, 352, 126, 857, 956, 358, 619, 580, 124,
+    737, 594, 701, 612, 669, 112, 134, 694, 363, 992, 809, 743, 168, 974, 944, 375,
+    748, 52, 600, 747, 642, 182, 862, 81, 344, 805, 988, 739, 511, 655, 814, 334,
+    249, 515, 897, 955, 664, 981, 649, 113, 974, 459, 893, 228, 433, 837, 553, 268,
+    926, 240, 102, 654, 459, 51, 686, 754, 806, 760, 493, 403, 415, 394, 687, 700,
+    946, 670, 656, 610, 738, 392, 760, 799, 887, 653, 978, 321, 576, 617, 626, 502,
+    894, 679, 243, 440, 680, 879, 194, 572, 640, 724, 926, 56, 204, 700, 707, 151,
+    457, 449, 797, 195, 791, 558, 945, 679, 297, 59, 87, 824, 713, 663, 412, 693,
+    342, 606, 134, 108, 571, 364, 631, 212, 174, 643, 304, 329, 343, 97, 430, 751,
+    497, 314, 983, 374, 822, 928, 140, 206, 73, 263, 980, 736, 876, 478, 430, 305,
+    170, 514, 364, 692, 829, 82, 855, 953, 676, 246, 369, 970, 294, 750, 807, 827,
+    150, 790, 288, 923, 804, 378, 215, 828, 592, 281, 565, 555, 710, 82, 896, 831,
+    547, 261, 524, 462, 293, 465, 502, 56, 661, 821, 976, 991, 658, 869, 905, 758,
+    745, 193, 768, 550, 608, 933, 378, 286, 215, 979, 792, 961, 61, 688, 793, 644,
+    986, 403, 106, 366, 905, 644, 372, 567, 466, 434, 645, 210, 389, 550, 919, 135,
+    780, 773, 635, 389, 707, 100, 626, 958, 165, 504, 920, 176, 193, 713, 857, 265,
+    203, 50, 668, 108, 645, 990, 626, 197, 510, 357, 358, 850, 858, 364, 936, 638,
+];
+
+/// Number of leading bits looking up the short codes at once
+const LOOKUP_BITS: usize = 10;
+
+/// Canonical huffman code. The codes of up to `LOOKUP_BITS` bits are looked
+/// up by the next bits of the stream, the longer ones are compared with the
+/// largest code of each length.
+struct HuffmanTable {
+    /// Symbol and length of the code starting each `LOOKUP_BITS` bits, as
+    /// `symbol << 5 | length`, or 0 for the longer codes
+    lookup: [u16; 1 << LOOKUP_BITS],
+    /// limit[n] is the largest n-bit code, or -1
+    limit: [i32; MAX_CODE_LEN + 1],
+    /// base[n] added to an n-bit code gives its index in `symbol`
+    base: [i32; MAX_CODE_LEN + 1],
+    /// Symbols by increasing code
+    symbol: [u16; MAX_ALPHA_SIZE],
+    max_len: usize,
+}
+
+impl HuffmanTable {
+    fn new(lens: &[u8]) -> io::Result<HuffmanTable> {
+        let mut table = HuffmanTable {
+            lookup: [0; 1 << LOOKUP_BITS],
+            limit: [-1; MAX_CODE_LEN + 1],
+            base: [0; MAX_CODE_LEN + 1],
+            symbol: [0; MAX_ALPHA_SIZE],
+            max_len: 0,
+        };
+        let mut count = [0u16; MAX_CODE_LEN + 1];
+        for &len in lens.iter() {
+            count[len as usize] += 1;
+            table.max_len = cmp::max(table.max_len, len as usize);
+        }
+        let mut left = 1isize;
+        for len in 1..(MAX_CODE_LEN + 1) {
+            left = 2 * left - count[len] as isize;
+            if left < 0 {
+                return corrupt("invalid bzip2 huffman table")
+            }
+        }
+        let mut offs = [0u16; MAX_CODE_LEN + 1];
+        for len in 1..MAX_CODE_LEN {
+            offs[len + 1] = offs[len] + count[len];
+        }
+        for (sym, &len) in lens.iter().enumerate() {
+            table.symbol[offs[len as usize] as usize] = sym as u16;
+            offs[len as usize] += 1;
+        }
+
+        let (mut code, mut index) = (0i32, 0i32);
+        for len in 1..(MAX_CODE_LEN + 1) {
+            let n = count[len] as i32;
+            table.base[len] = index - code;
+            if len <= LOOKUP_BITS {
+                let shift = LOOKUP_BITS - len;
+                for i in 0..n {
+                    let entry = table.symbol[(index + i) as usize] << 5 | len as u16;
+                    let first = ((code + i) as usize) << shift;
+                    for slot in table.lookup[first..first + (1 << shift)].iter_mut() {
+                        *slot = entry;
+                    }
+                }
+            }
+            if n > 0 {
+                table.limit[len] = code + n - 1;
+            }
+            code = (code + n) << 1;
+            index += n;
+        }
+        Ok(table)
+    }
+
+    fn decode<R: Read>(&self, bits: &mut BitReader<R>) -> io::Result<u16> {
+        // a valid block is followed by at least 80 bits of headers, so the
+        // bits looked ahead are always there
+        let entry = self.lookup[try!(bits.peek(LOOKUP_BITS)) as usize];
+        if entry != 0 {
+            bits.consume(entry as usize & 0x1f);
+            return Ok(entry >> 5)
+        }
+        for len in (LOOKUP_BITS + 1)..(self.max_len + 1) {
+            let code = try!(bits.peek(len)) as i32;
+            if code <= self.limit[len] {
+                bits.consume(len);
+                return Ok(self.symbol[(code + self.base[len]) as usize])
+            }
+        }
+        corrupt("invalid bzip2 huffman code")
+    }
+}
+
+/// Reads bits MSB first, pulling bytes from the stream only as needed
+struct BitReader<R> {
+    r: R,
+    buf: u64,
+    cnt: usize,
+}
+
+impl<R: Read> BitReader<R> {
+    fn bits(&mut self, cnt: usize) -> io::Result<u32> {
+        let value = try!(self.peek(cnt));
+        self.cnt -= cnt;
+        Ok(value)
+    }
+
+    /// Returns the next `cnt` bits, leaving them in the stream
+    fn peek(&mut self, cnt: usize) -> io::Result<u32> {
+        debug_assert!(cnt <= 32);
+        while self.cnt < cnt {
+            let byte = try!(self.r.read_u8());
+            self.buf = (self.buf << 8) | byte as u64;
+            self.cnt += 8;
+        }
+        Ok(((self.buf >> (self.cnt - cnt)) & ((1 << cnt) - 1)) as u32)
+    }
+
+    /// Drops the next `cnt` bits, once peeked
+    fn consume(&mut self, cnt: usize) {
+        self.cnt -= cnt;
+    }
+
+    fn bit(&mut self) -> io::Result<bool> {
+        self.bits(1).map(|b| b == 1)
+    }
+
+    /// Drops the bits left in the current byte
+    fn align(&mut self) {
+        self.cnt -= self.cnt % 8;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    /// Reading the header of a stream
+    Header,
+    /// Reading the next block, or the end of the stream
+    Block,
+    /// Producing the bytes of a block
+    Data,
+    Done,
+}
+
+/// This structure is used to decode a bzip2 stream, fed with slices of it.
+/// The input of a block is gathered until it holds the whole block, which is
+/// decoded again from its start whenever it runs out.
+pub struct Processor {
+    bits: BitReader<Window>,
+    state: State,
+    /// Maximum size of a block, before the runs of identical bytes are
+    /// expanded, or 0 when the stream header is yet to be read
+    block_size: usize,
+    stream_crc: u32,
+
+    /// Low bytes hold the block after the MTF stage, high bits hold the
+    /// inverse BWT links
+    tt: Vec<u32>,
+    /// Position of the next byte in the chain of `tt`, and number of bytes
+    /// left in the block
+    tpos: u32,
+    left: usize,
+    randomized: bool,
+    rand_index: usize,
+    rand_to_go: u16,
+
+    /// State of the decoding of runs of identical bytes
+    last: u8,
+    count: usize,
+    run: usize,
+
+    block_crc: Bzip2State32,
+    expected_crc: u32,
+}
+
+impl Processor {
+    /// Creates a new bzip2 decoder
+    pub fn new() -> Processor {
+        Processor {
+            bits: BitReader { r: Window::new(), buf: 0, cnt: 0 },
+            state: State::Header,
+            block_size: 0,
+            stream_crc: 0,
+            tt: Vec::new(),
+            tpos: 0,
+            left: 0,
+            randomized: false,
+            rand_index: 0,
+            rand_to_go: 0,
+            last: 0,
+            count: 0,
+            run: 0,
+            block_crc: Bzip2State32::new(),
+            expected_crc: 0,
+        }
+    }
+
+    /// Starts decoding a new stream, keeping the buffers of the decoder
+    pub fn reset(&mut self) {
+        self.bits.r.reset();
+        self.bits.buf = 0;
+        self.bits.cnt = 0;
+        self.state = State::Header;
+        self.block_size = 0;
+        self.tt.truncate(0);
+        self.left = 0;
+        self.run = 0;
+    }
+
+    /// Tests if this stream has reached the EOF point yet.
+    pub fn eof(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Reads the header of a stream. Only the first stream has to be there.
+    fn read_stream_header(&mut self) -> io::Result<Status> {
+        let first = self.block_size == 0;
+        let level = {
+            let magic = self.bits.r.data();
+            if magic.len() < MAGIC.len() + 1 {
+                return if !self.bits.r.is_finished() {
+                    Ok(Status::NeedInput)
+                } else if magic.len() == 0 && !first {
+                    Ok(Status::Done)
+                } else {
+                    truncated("unexpected end of bzip2 stream")
+                }
+            }
+            if magic[..3] != MAGIC[..] || magic[3] < b'1' || magic[3] > b'9' {
+                return corrupt("invalid bzip2 stream header")
+            }
+            magic[3] - b'0'
+        };
+        self.bits.r.consume(MAGIC.len() + 1);
+        self.block_size = level as usize * BLOCK_UNIT;
+        self.stream_crc = 0;
+        Ok(Status::NeedOutput)
+    }
+
+    /// Reads the next block, returning false at the end of the stream
+    fn read_block(&mut self) -> io::Result<bool> {
+        let magic = ((try!(self.bits.bits(24)) as u64) << 24) | try!(self.bits.bits(24)) as u64;
+        if magic == END_MAGIC {
+            let crc = try!(self.bits.bits(32));
+            if crc != self.stream_crc {
+                return checksum_mismatch("invalid bzip2 stream checksum")
+            }
+            self.bits.align();
+            return Ok(false)
+        }
+        if magic != BLOCK_MAGIC {
+            return corrupt("invalid bzip2 block header")
+        }
+        self.expected_crc = try!(self.bits.bits(32));
+        self.randomized = try!(self.bits.bit());
+        let origin = try!(self.bits.bits(24)) as usize;
+
+        // Bitmap of the bytes in use, by groups of 16
+        let mut seq_to_unseq = Vec::with_capacity(256);
+        let groups = try!(self.bits.bits(16));
+        for i in 0..16 {
+            if groups & (0x8000 >> i) != 0 {
+                let used = try!(self.bits.bits(16));
+                for j in 0..16 {
+                    if used & (0x8000 >> j) != 0 {
+                        seq_to_unseq.push((i * 16 + j) as u8);
+                    }
+                }
+            }
+        }
+        if seq_to_unseq.is_empty() {
+            return corrupt("bzip2 block uses no symbols")
+        }
+        let alpha_size = seq_to_unseq.len() + 2;
+
+        let num_groups = try!(self.bits.bits(3)) as usize;
+        if num_groups < MIN_GROUPS || num_groups > MAX_GROUPS {
+            return corrupt("invalid number of bzip2 huffman tables")
+        }
+        let num_selectors = try!(self.bits.bits(15)) as usize;
+        if num_selectors == 0 {
+            return corrupt("invalid number of bzip2 selectors")
+        }
+        let mut mtf = MTF::new();
+        mtf.reset_alphabetical();
+        let mut selectors = Vec::with_capacity(cmp::min(num_selectors, MAX_SELECTORS));
+        for i in 0..num_selectors {
+            let mut rank = 0;
+            while try!(self.bits.bit()) {
+                rank += 1;
+                if rank >= num_groups {
+                    return corrupt("invalid bzip2 selector")
+                }
+            }
+            let selector = mtf.decode(rank as u8);
+            // selectors past the maximum are ignored, as bzip2 1.0.8 does
+            if i < MAX_SELECTORS {
+                selectors.push(selector);
+            }
+        }
+
+        // Code lengths, delta coded
+        let mut tables = Vec::with_capacity(num_groups);
+        let mut lens = [0u8; MAX_ALPHA_SIZE];
+        for _ in 0..num_groups {
+            let mut len = try!(self.bits.bits(5)) as i32;
+            for sym in 0..alpha_size {
+                loop {
+                    if len < 1 || len > MAX_CODE_LEN as i32 {
+                        return corrupt("invalid bzip2 code length")
+                    }
+                    if !try!(self.bits.bit()) {
+                        break
+                    }
+                    len += if try!(self.bits.bit()) { -1 } else { 1 };
+                }
+                lens[sym] = len as u8;
+            }
+            tables.push(try!(HuffmanTable::new(&lens[..alpha_size])));
+        }
+
+        // The symbols, MTF and run length coded
+        let end_of_block = alpha_size as u16 - 1;
+        mtf.reset_alphabetical();
+        self.tt.truncate(0);
+        let (mut run, mut weight) = (0usize, 1usize);
+        let mut selector = 0;
+        let mut group_left = 0;
+        loop {
+            if group_left == 0 {
+                if selector == selectors.len() {
+                    return corrupt("not enough bzip2 selectors")
+                }
+                group_left = GROUP_SIZE;
+                selector += 1;
+            }
+            group_left -= 1;
+            let sym = try!(tables[selectors[selector - 1] as usize].decode(&mut self.bits));
+
+            if sym == RUNA || sym == RUNB {
+                // runs of zeroes are written in bijective base 2
+                run += weight << sym;
+                weight <<= 1;
+                if run > self.block_size {
+                    return corrupt("bzip2 block is too large")
+                }
+                continue
+            }
+            if run > 0 {
+                if self.tt.len() + run > self.block_size {
+                    return corrupt("bzip2 block is too large")
+                }
+                let byte = seq_to_unseq[mtf.symbols[0] as usize] as u32;
+                self.tt.extend((0..run).map(|_| byte));
+                run = 0;
+                weight = 1;
+            }
+            if sym == end_of_block {
+                break
+            }
+            if self.tt.len() == self.block_size {
+                return corrupt("bzip2 block is too large")
+            }
+            let index = mtf.decode((sym - 1) as u8);
+            self.tt.push(seq_to_unseq[index as usize] as u32);
+        }
+        if origin >= self.tt.len() {
+            return corrupt("invalid bzip2 block origin")
+        }
+
+        // Link each byte to the next one of the original block
+        let mut cftab = [0u32; 256];
+        for &b in self.tt.iter() {
+            cftab[b as usize & 0xff] += 1;
+        }
+        let mut sum = 0;
+        for c in cftab.iter_mut() {
+            let n = *c;
+            *c = sum;
+            sum += n;
+        }
+        for i in 0..self.tt.len() {
+            let b = self.tt[i] as usize & 0xff;
+            let slot = cftab[b] as usize;
+            self.tt[slot] |= (i as u32) << 8;
+            cftab[b] += 1;
+        }
+
+        self.tpos = self.tt[origin] >> 8;
+        self.left = self.tt.len();
+        self.rand_index = 0;
+        self.rand_to_go = 0;
+        self.count = 0;
+        self.run = 0;
+        self.block_crc.reset();
+        Ok(true)
+    }
+
+    /// Reads the next block from its start, rolling the input back when it
+    /// runs out, or returns `None` to wait for more of it
+    fn next_block(&mut self) -> io::Result<Option<bool>> {
+        if !self.bits.r.is_ready() {
+            return Ok(None)
+        }
+        let (mark, buf, cnt) = (self.bits.r.mark(), self.bits.buf, self.bits.cnt);
+        match self.read_block() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.bits.r.rollback(mark);
+                self.bits.buf = buf;
+                self.bits.cnt = cnt;
+                Ok(None)
+            }
+            result => result.map(Some),
+        }
+    }
+
+    /// Follows the inverse BWT chain to the next byte of the block
+    #[inline]
+    fn next_byte(&mut self) -> u8 {
+        let t = self.tt[self.tpos as usize];
+        self.tpos = t >> 8;
+        self.left -= 1;
+        let mut byte = t as u8;
+        if self.randomized {
+            if self.rand_to_go == 0 {
+                self.rand_to_go = RNUMS[self.rand_index];
+                self.rand_index = (self.rand_index + 1) % RNUMS.len();
+            }
+            self.rand_to_go -= 1;
+            if self.rand_to_go == 1 {
+                byte ^= 1;
+            }
+        }
+        byte
+    }
+
+    /// Decodes the runs of identical bytes of the block into `buf`
+    fn fill(&mut self, buf: &mut [u8]) -> usize {
+        let mut n = 0;
+        while n < buf.len() {
+            if self.run > 0 {
+                let amt = cmp::min(self.run, buf.len() - n);
+                for b in buf[n..(n + amt)].iter_mut() {
+                    *b = self.last;
+                }
+                n += amt;
+                self.run -= amt;
+                continue
+            }
+            if self.left == 0 {
+                break
+            }
+            let byte = self.next_byte();
+            if self.count == RUN_LENGTH {
+                self.run = byte as usize;
+                self.count = 0;
+                continue
+            }
+            if self.count > 0 && byte == self.last {
+                self.count += 1;
+            } else {
+                self.last = byte;
+                self.count = 1;
+            }
+            buf[n] = byte;
+            n += 1;
+        }
+        self.block_crc.feed(&buf[..n]);
+        n
+    }
+
+    fn end_block(&mut self) -> io::Result<()> {
+        let crc = self.block_crc.result();
+        if crc != self.expected_crc {
+            return checksum_mismatch("invalid bzip2 block checksum")
+        }
+        self.stream_crc = self.stream_crc.rotate_left(1) ^ crc;
+        self.tt.truncate(0);
+        Ok(())
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if self.state != State::Data {
+                self.bits.r.extend(&input[pos..]);
+                pos = input.len();
+            }
+            match self.state {
+                State::Header => match try!(self.read_stream_header()) {
+                    Status::NeedOutput => self.state = State::Block,
+                    Status::NeedInput => return Ok((pos, produced, Status::NeedInput)),
+                    Status::Done => self.state = State::Done,
+                },
+                State::Block => match try!(self.next_block()) {
+                    Some(true) => self.state = State::Data,
+                    // another stream may follow
+                    Some(false) => self.state = State::Header,
+                    None => return Ok((pos, produced, Status::NeedInput)),
+                },
+                State::Data => {
+                    produced += self.fill(&mut output[produced..]);
+                    if self.left == 0 && self.run == 0 {
+                        try!(self.end_block());
+                        self.state = State::Block;
+                    } else if produced == output.len() {
+                        return Ok((pos, produced, Status::NeedOutput))
+                    }
+                }
+                State::Done => {
+                    // the input gathered past the end of the stream isn't used
+                    pos -= cmp::min(self.bits.r.data().len(), pos);
+                    return Ok((pos, produced, Status::Done))
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the blocks left may come from the input gathered, and reading past
+        // it fails as truncated
+        self.bits.r.finish();
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a bzip2 stream. The wrapped stream can be
+/// re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new bzip2 decoder which will wrap the specified reader.
+    /// This decoder also implements the `Reader` trait, and the underlying
+    /// reader can be re-acquired through the `unwrap` method.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new()),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Tests if this stream has reached the EOF point yet.
+    pub fn eof(&self) -> bool {
+        self.inner.processor().eof()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read};
+    use testing::push;
+    use super::{Decoder, Processor};
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        out
+    }
+
+    /// The input of test.multi.bz2, two blocks at level 1
+    fn multi() -> Vec<u8> {
+        (0..200000usize).map(|i| {
+            if (i / 1000) % 3 == 0 {
+                b'x'
+            } else {
+                (((i as u32).wrapping_mul(2654435761) >> 13) % 16) as u8 + b'a'
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn decode_file() {
+        assert!(&decode(include_bytes!("../data/test.bz2"))[..] ==
+                &include_bytes!("../data/test.txt")[..]);
+        assert!(decode(include_bytes!("../data/test.multi.bz2")) == multi());
+    }
+
+    #[test]
+    fn decode_push() {
+        let input = include_bytes!("../data/test.multi.bz2");
+        for &step in [1, 333, 1 << 16].iter() {
+            assert!(push(&mut Processor::new(), input, step).unwrap() == multi());
+        }
+        let input = include_bytes!("../data/test.bz2");
+        let mut p = Processor::new();
+        assert!(&push(&mut p, input, 100).unwrap()[..] == &include_bytes!("../data/test.txt")[..]);
+        assert!(p.eof());
+        for &len in [3, 100, input.len() - 3].iter() {
+            assert!(push(&mut Processor::new(), &input[..len], 100).is_err());
+        }
+    }
+
+    #[test]
+    fn concatenated_streams() {
+        let mut input = include_bytes!("../data/test.bz2").to_vec();
+        input.extend(include_bytes!("../data/test.multi.bz2").iter().cloned());
+        let mut expected = include_bytes!("../data/test.txt").to_vec();
+        expected.extend(multi().into_iter());
+        assert!(decode(&input) == expected);
+    }
+
+    #[test]
+    fn small_reads() {
+        let input = include_bytes!("../data/test.multi.bz2");
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        let mut out = Vec::new();
+        let mut buf = [0u8; 7];
+        loop {
+            match d.read(&mut buf).unwrap() {
+                0 => break,
+                n => out.extend(buf[..n].iter().cloned()),
+            }
+        }
+        assert!(out == multi());
+    }
+
+    #[test]
+    fn huffman_lookup() {
+        use super::{BitReader, HuffmanTable};
+        // codes of 1 to 20 bits, with the lengths out of order
+        let mut lens: Vec<u8> = (1..21).rev().collect();
+        lens.push(20);
+        lens.swap(3, 17);
+        let table = HuffmanTable::new(&lens).unwrap();
+
+        let mut by_len: Vec<(u8, usize)> = lens.iter().cloned().zip(0..).collect();
+        by_len.sort();
+        let (mut code, mut last) = (0u32, 1u8);
+        let (mut out, mut buf, mut cnt) = (Vec::new(), 0u64, 0);
+        for &(len, _) in by_len.iter() {
+            code <<= len - last;
+            last = len;
+            buf = buf << len | code as u64;
+            cnt += len as usize;
+            code += 1;
+            while cnt >= 8 {
+                cnt -= 8;
+                out.push((buf >> cnt) as u8);
+            }
+        }
+        out.push((buf << (8 - cnt)) as u8);
+        out.extend_from_slice(&[0; 4]);
+
+        let mut bits = BitReader { r: &out[..], buf: 0, cnt: 0 };
+        for &(_, sym) in by_len.iter() {
+            assert_eq!(table.decode(&mut bits).unwrap(), sym as u16);
+        }
+    }
+
+    #[test]
+    fn corrupted() {
+        let input = include_bytes!("../data/test.bz2");
+        // block checksum, stream checksum, then somewhere in the data
+        for &pos in [10, input.len() - 2, input.len() / 2].iter() {
+            let mut bad = input.to_vec();
+            bad[pos] ^= 0x10;
+            let mut d = Decoder::new(BufReader::new(&bad[..]));
+            assert!(d.read_to_end(&mut Vec::new()).is_err());
+        }
+        let mut d = Decoder::new(BufReader::new(&input[..100]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}
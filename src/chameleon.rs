@@ -0,0 +1,202 @@
+/*!
+
+Chameleon Decompression and Compression. Requires `chameleon` feature, enabled
+by default
+
+This module contains an implementation of the Chameleon algorithm of the
+Density library, a dictionary coder which gives up on ratio for speed, for a
+first pass before slower coders or where a few GB/s are needed. The input is
+read as little-endian words of 4 bytes, each hashed into a dictionary of 64k
+words: the words found in the dictionary are written as their hash of 2
+bytes, the others as such, replacing the word of their hash. Groups of 64
+items are preceded by a signature of 64 bits, little-endian, whose set bits
+mark the hashes, from the lowest. The bytes left after the last word follow
+the last item, flagged as a word. The dictionary starts empty for each
+block, whose size is not stored. Whole buffers are handled by `decode_block`
+and `encode_block`.
+
+# Example
+
+```rust,ignore
+use compress::chameleon;
+
+let mut compressed = Vec::new();
+chameleon::encode_block(b"hello hello hello!", &mut compressed);
+let mut decompressed = Vec::new();
+chameleon::decode_block(&compressed, &mut decompressed).unwrap();
+```
+
+# Related links
+
+* https://github.com/k0dai/density - the Density library, of which Chameleon
+  is the fastest algorithm
+
+*/
+
+use std::io;
+
+use error::truncated;
+use hash;
+
+/// Number of items after a signature
+const GROUP: usize = 64;
+const HASH_BITS: u32 = 16;
+const HASH_MULTIPLIER: u32 = 0x9d6ef916;
+
+/// Returns the maximum size of the block compressing `size` bytes
+pub fn max_compressed_len(size: usize) -> usize {
+    size + 8 * (size / (4 * GROUP) + 1)
+}
+
+#[inline(always)]
+fn hash(word: u32) -> usize {
+    hash::fold32(word.wrapping_mul(HASH_MULTIPLIER), HASH_BITS)
+}
+
+#[inline(always)]
+fn read_word(input: &[u8], pos: usize) -> u32 {
+    (input[pos + 3] as u32) << 24
+        | (input[pos + 2] as u32) << 16
+        | (input[pos + 1] as u32) << 8
+        | (input[pos] as u32)
+}
+
+#[inline(always)]
+fn write_le(output: &mut [u8], value: u64) {
+    for (i, byte) in output.iter_mut().enumerate() {
+        *byte = (value >> (8 * i)) as u8;
+    }
+}
+
+/// Decodes a Chameleon block, appending the data to `output`
+pub fn decode_block(input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+    let mut dict = vec![0u32; 1 << HASH_BITS];
+    let mut pos = 0;
+    let mut words = [0u8; 4 * GROUP];
+    while pos < input.len() {
+        if input.len() - pos < 8 {
+            return truncated("truncated chameleon signature")
+        }
+        let signature = read_word(input, pos) as u64 | (read_word(input, pos + 4) as u64) << 32;
+        pos += 8;
+        for i in 0..GROUP {
+            let word = if (signature >> i) & 1 != 0 {
+                if input.len() - pos < 2 {
+                    return truncated("truncated chameleon hash")
+                }
+                pos += 2;
+                dict[input[pos - 2] as usize | (input[pos - 1] as usize) << 8]
+            } else if input.len() - pos >= 4 {
+                let word = read_word(input, pos);
+                pos += 4;
+                dict[hash(word)] = word;
+                word
+            } else {
+                // the last bytes, after which no item may be flagged
+                if signature >> i != 0 {
+                    return truncated("truncated chameleon hash")
+                }
+                output.extend(words[..(4 * i)].iter().chain(input[pos..].iter()).cloned());
+                return Ok(())
+            };
+            write_le(&mut words[(4 * i)..(4 * i + 4)], word as u64);
+        }
+        output.extend(words.iter().cloned());
+    }
+    Ok(())
+}
+
+/// Encodes the input into a Chameleon block, appended to `output`
+pub fn encode_block(input: &[u8], output: &mut Vec<u8>) {
+    let start = output.len();
+    // the items are written in place, and the unused room cut at the end
+    output.resize(start + max_compressed_len(input.len()), 0);
+    let mut dict = vec![0u32; 1 << HASH_BITS];
+    let mut pos = start;
+    for group in input.chunks(4 * GROUP) {
+        let at = pos;
+        pos += 8;
+        let mut signature = 0u64;
+        for (i, word) in group.chunks(4).enumerate() {
+            if word.len() < 4 {
+                output[pos..(pos + word.len())].copy_from_slice(word);
+                pos += word.len();
+                break
+            }
+            let value = read_word(word, 0);
+            let hash = hash(value);
+            if dict[hash] == value {
+                signature |= 1 << i;
+                output[pos] = hash as u8;
+                output[pos + 1] = (hash >> 8) as u8;
+                pos += 2;
+            } else {
+                dict[hash] = value;
+                output[pos..(pos + 4)].copy_from_slice(word);
+                pos += 4;
+            }
+        }
+        write_le(&mut output[at..(at + 8)], signature);
+    }
+    output.truncate(pos);
+}
+
+#[cfg(test)]
+mod test {
+    use testing::noise;
+    use super::{decode_block, encode_block, hash, max_compressed_len};
+
+    fn roundtrip(input: &[u8]) -> usize {
+        let mut encoded = Vec::new();
+        encode_block(input, &mut encoded);
+        assert!(encoded.len() <= max_compressed_len(input.len()));
+        let mut decoded = Vec::new();
+        decode_block(&encoded, &mut decoded).unwrap();
+        assert!(&decoded[..] == input);
+        encoded.len()
+    }
+
+    #[test]
+    fn known_data() {
+        // a word, its hash and the last bytes
+        let h = hash(0x64636261);
+        let expected = [2, 0, 0, 0, 0, 0, 0, 0, b'a', b'b', b'c', b'd', h as u8, (h >> 8) as u8,
+                        b'x', b'y'];
+        let mut out = Vec::new();
+        encode_block(b"abcdabcdxy", &mut out);
+        assert_eq!(&out[..], &expected[..]);
+        out.truncate(0);
+        decode_block(&expected, &mut out).unwrap();
+        assert_eq!(&out[..], b"abcdabcdxy");
+        // a full group is followed by a signature for the last bytes
+        let data = [0u8; 4 * 64 + 1];
+        out.truncate(0);
+        encode_block(&data, &mut out);
+        assert_eq!(out.len(), 8 + 2 * 64 + 8 + 1);
+        assert_eq!(&out[..8], &[0xff; 8]);
+        assert_eq!(roundtrip(b""), 0);
+    }
+
+    #[test]
+    fn roundtrips() {
+        for len in 0..9 {
+            roundtrip(&b"abcdefgh"[..len]);
+        }
+        let text = include_bytes!("data/test.txt");
+        roundtrip(text);
+        let large = include_bytes!("data/test.large");
+        assert!(roundtrip(large) < large.len());
+        roundtrip(&noise(100000, 7));
+        let runs: Vec<u8> = (0..200000).map(|i| (i / 1000) as u8).collect();
+        assert!(roundtrip(&runs) < runs.len() * 2 / 3);
+    }
+
+    #[test]
+    fn invalid_data() {
+        for input in [&[0, 0, 0][..], // truncated signature
+                      &[1, 0, 0, 0, 0, 0, 0, 0, 0], // truncated hash
+                      &[2, 0, 0, 0, 0, 0, 0, 0, b'a', b'b']].iter() { // hash after the last bytes
+            assert!(decode_block(input, &mut Vec::new()).is_err());
+        }
+    }
+}
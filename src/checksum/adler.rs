@@ -5,6 +5,11 @@ Adler-32 checksum
 This implementation is based off the example found at
 http://en.wikipedia.org/wiki/Adler-32.
 
+The sums are reduced once per block of `NMAX` bytes, the most which can be
+summed without overflowing 32 bits. With SSSE3, as detected at runtime, each
+block is summed by vectors of 16 bytes. The checksum of a window can also be
+rolled along the data, a byte at a time, as the weak hash of rsync.
+
 # Example
 
 ```rust
@@ -16,7 +21,67 @@ let checksum = state.result();
 
 */
 
+use super::{Checksum, Combine};
+
 const MOD_ADLER: u32 = 65521;
+/// Largest number of bytes summed before reducing the sums
+const NMAX: usize = 5552;
+
+/// Sums a block of at most `NMAX` bytes, without reducing the sums
+fn sum(mut a: u32, mut b: u32, block: &[u8]) -> (u32, u32) {
+    for &byte in block.iter() {
+        a += byte as u32;
+        b += a;
+    }
+    (a, b)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn sum_vector(a: u32, b: u32, block: &[u8]) -> Option<(u32, u32)> {
+    if is_x86_feature_detected!("ssse3") {
+        Some(unsafe { sum_ssse3(a, b, block) })
+    } else {
+        None
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+unsafe fn sum_ssse3(a: u32, b: u32, block: &[u8]) -> (u32, u32) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    // each vector adds the sum of its bytes to `a`, and to `b` the value of
+    // `a` before it 16 times, along with its bytes weighted from 16 down to 1
+    let weights = _mm_setr_epi8(16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1);
+    let ones = _mm_set1_epi16(1);
+    let zero = _mm_setzero_si128();
+    let (mut bytes_sum, mut prev_sums, mut weighted) = (zero, zero, zero);
+    let mut chunks = block.chunks_exact(16);
+    for chunk in &mut chunks {
+        let bytes = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+        prev_sums = _mm_add_epi32(prev_sums, bytes_sum);
+        bytes_sum = _mm_add_epi32(bytes_sum, _mm_sad_epu8(bytes, zero));
+        weighted = _mm_add_epi32(weighted, _mm_madd_epi16(_mm_maddubs_epi16(bytes, weights), ones));
+    }
+    let mut lanes = [[0u32; 4]; 3];
+    for (lane, &v) in lanes.iter_mut().zip([bytes_sum, prev_sums, weighted].iter()) {
+        _mm_storeu_si128(lane.as_mut_ptr() as *mut __m128i, v);
+    }
+    let total = |lane: &[u32; 4]| lane.iter().fold(0u32, |s, &x| s.wrapping_add(x));
+    // the sums of a block fit in 32 bits, though not these terms on their own
+    let n = (block.len() - chunks.remainder().len()) as u32;
+    let b = b.wrapping_add(n.wrapping_mul(a)).wrapping_add(total(&lanes[1]).wrapping_mul(16))
+        .wrapping_add(total(&lanes[2]));
+    sum(a + total(&lanes[0]), b, chunks.remainder())
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn sum_vector(_a: u32, _b: u32, _block: &[u8]) -> Option<(u32, u32)> {
+    None
+}
 
 /// Adler state for 32 bits
 pub struct State32 {
@@ -32,12 +97,24 @@ impl State32 {
 
     /// Mutate the state for given data
     pub fn feed(&mut self, buf: &[u8]) {
-        for byte in buf.iter() {
-            self.a = (self.a + *byte as u32) % MOD_ADLER;
-            self.b = (self.a + self.b) % MOD_ADLER;
+        for block in buf.chunks(NMAX) {
+            let (a, b) = match sum_vector(self.a, self.b, block) {
+                Some(sums) => sums,
+                None => sum(self.a, self.b, block),
+            };
+            self.a = a % MOD_ADLER;
+            self.b = b % MOD_ADLER;
         }
     }
 
+    /// Slides the window of `len` bytes covered by the state by one byte,
+    /// removing `old` from its start and adding `new` at its end
+    pub fn roll(&mut self, len: usize, old: u8, new: u8) {
+        let removed = ((len % MOD_ADLER as usize) as u32 * old as u32) % MOD_ADLER;
+        self.a = (self.a + MOD_ADLER - old as u32 + new as u32) % MOD_ADLER;
+        self.b = (self.b + MOD_ADLER - removed + self.a + MOD_ADLER - 1) % MOD_ADLER;
+    }
+
     /// Get checksum
     pub fn result(&self) -> u32 {
         (self.b << 16) | self.a
@@ -49,3 +126,89 @@ impl State32 {
         self.b = 0;
     }
 }
+
+impl Checksum for State32 {
+    type Output = u32;
+
+    fn feed(&mut self, buf: &[u8]) {
+        State32::feed(self, buf)
+    }
+
+    fn result(&self) -> u32 {
+        State32::result(self)
+    }
+
+    fn reset(&mut self) {
+        State32::reset(self)
+    }
+}
+
+impl Combine for State32 {
+    fn combine(sum1: u32, sum2: u32, len2: u64) -> u32 {
+        // the sums of the second piece, starting from those of the first
+        let rem = (len2 % MOD_ADLER as u64) as u32;
+        let (a1, b1) = (sum1 & 0xffff, sum1 >> 16);
+        let (a2, b2) = (sum2 & 0xffff, sum2 >> 16);
+        let a = (a1 + a2 + MOD_ADLER - 1) % MOD_ADLER;
+        let b = (rem * a1 % MOD_ADLER + b1 + b2 + MOD_ADLER - rem) % MOD_ADLER;
+        (b << 16) | a
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{State32, MOD_ADLER, NMAX, sum, sum_vector};
+
+    fn checksum(buf: &[u8]) -> u32 {
+        let mut state = State32::new();
+        state.feed(buf);
+        state.result()
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(checksum(b""), 1);
+        assert_eq!(checksum(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn blocks() {
+        // against the sums reduced after each byte, from all the alignments,
+        // and with the largest sums
+        let text = &include_bytes!("../data/test.txt")[..];
+        let ones = vec![0xff; NMAX * 3 + 100];
+        for &data in [&text[..200], &ones[..]].iter() {
+            for start in 0..17 {
+                for &end in [start, start + 1, start + 15, start + 16, start + 33, data.len()].iter() {
+                    let buf = &data[start..end];
+                    let (mut a, mut b) = (1, 0);
+                    for &byte in buf.iter() {
+                        a = (a + byte as u32) % MOD_ADLER;
+                        b = (b + a) % MOD_ADLER;
+                    }
+                    assert_eq!(checksum(buf), b << 16 | a);
+                }
+            }
+        }
+        let block = &ones[..NMAX];
+        let sums = sum(MOD_ADLER - 1, MOD_ADLER - 1, block);
+        assert_eq!(sum_vector(MOD_ADLER - 1, MOD_ADLER - 1, block).unwrap_or(sums), sums);
+    }
+
+    #[test]
+    fn rolling() {
+        let data = include_bytes!("../data/test.txt");
+        let mut noise = vec![0xffu8; 70000];
+        noise.extend_from_slice(data);
+        for &(buf, len) in [(&data[..], 1), (&data[..], 16), (&data[..], 3000), (&noise[..], 66000)].iter() {
+            let mut state = State32::new();
+            state.feed(&buf[..len]);
+            for i in len..buf.len() {
+                state.roll(len, buf[i - len], buf[i]);
+                if i % 97 == 0 || i == buf.len() - 1 {
+                    assert_eq!(state.result(), checksum(&buf[i + 1 - len..i + 1]));
+                }
+            }
+        }
+    }
+}
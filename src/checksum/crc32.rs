@@ -0,0 +1,297 @@
+/*!
+
+CRC-32 checksum
+
+This is the IEEE 802.3 variant (reflected polynomial 0xEDB88320) used by gzip,
+zip and png, along with the variant of bzip2, which shifts the bits MSB first
+with the same polynomial (0x04C11DB7). The data is processed 8 bytes at a time
+by slicing-by-8: eight tables give the CRC of each byte followed by up to 7
+zero bytes, so that the CRCs of 8 bytes are combined by xor.
+
+# Links
+
+http://www.ross.net/crc/download/crc_v3.txt - A painless guide to CRC error
+detection algorithms
+
+# Example
+
+```rust
+use compress::checksum::crc32;
+let mut state = crc32::State32::new();
+state.feed(b"abracadabra");
+let checksum = state.result();
+```
+
+*/
+
+use super::{Checksum, Combine};
+
+const POLYNOMIAL: u32 = 0xedb88320;
+const POLYNOMIAL_MSB: u32 = 0x04c11db7;
+
+static TABLES: [[u32; 256]; 8] = make_tables(POLYNOMIAL);
+static TABLES_MSB: [[u32; 256]; 8] = make_tables_msb(POLYNOMIAL_MSB);
+
+/// Makes the tables of slicing-by-8 for any reflected polynomial, where the
+/// table `k` gives the CRC of a byte followed by `k` zero bytes
+pub const fn make_tables(polynomial: u32) -> [[u32; 256]; 8] {
+    let mut tables = [[0u32; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { polynomial ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        tables[0][i] = c;
+        i += 1;
+    }
+    let mut k = 1;
+    while k < 8 {
+        let mut i = 0;
+        while i < 256 {
+            let c = tables[k - 1][i];
+            tables[k][i] = tables[0][(c & 0xff) as usize] ^ (c >> 8);
+            i += 1;
+        }
+        k += 1;
+    }
+    tables
+}
+
+/// Makes the tables for a polynomial shifted MSB first
+const fn make_tables_msb(polynomial: u32) -> [[u32; 256]; 8] {
+    let mut tables = [[0u32; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = (i as u32) << 24;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 0x80000000 != 0 { (c << 1) ^ polynomial } else { c << 1 };
+            k += 1;
+        }
+        tables[0][i] = c;
+        i += 1;
+    }
+    let mut k = 1;
+    while k < 8 {
+        let mut i = 0;
+        while i < 256 {
+            let c = tables[k - 1][i];
+            tables[k][i] = tables[0][(c >> 24) as usize] ^ (c << 8);
+            i += 1;
+        }
+        k += 1;
+    }
+    tables
+}
+
+/// Updates a reflected CRC with the tables of its polynomial, without the
+/// inversions at the start and the end
+pub fn update(tables: &[[u32; 256]; 8], mut crc: u32, buf: &[u8]) -> u32 {
+    let mut chunks = buf.chunks_exact(8);
+    for chunk in &mut chunks {
+        let lo = crc ^ (chunk[0] as u32 | (chunk[1] as u32) << 8 |
+                        (chunk[2] as u32) << 16 | (chunk[3] as u32) << 24);
+        crc = tables[7][(lo & 0xff) as usize] ^ tables[6][((lo >> 8) & 0xff) as usize] ^
+              tables[5][((lo >> 16) & 0xff) as usize] ^ tables[4][(lo >> 24) as usize] ^
+              tables[3][chunk[4] as usize] ^ tables[2][chunk[5] as usize] ^
+              tables[1][chunk[6] as usize] ^ tables[0][chunk[7] as usize];
+    }
+    for &byte in chunks.remainder().iter() {
+        crc = tables[0][((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+fn update_msb(tables: &[[u32; 256]; 8], mut crc: u32, buf: &[u8]) -> u32 {
+    let mut chunks = buf.chunks_exact(8);
+    for chunk in &mut chunks {
+        let hi = crc ^ ((chunk[0] as u32) << 24 | (chunk[1] as u32) << 16 |
+                        (chunk[2] as u32) << 8 | chunk[3] as u32);
+        crc = tables[7][(hi >> 24) as usize] ^ tables[6][((hi >> 16) & 0xff) as usize] ^
+              tables[5][((hi >> 8) & 0xff) as usize] ^ tables[4][(hi & 0xff) as usize] ^
+              tables[3][chunk[4] as usize] ^ tables[2][chunk[5] as usize] ^
+              tables[1][chunk[6] as usize] ^ tables[0][chunk[7] as usize];
+    }
+    for &byte in chunks.remainder().iter() {
+        crc = tables[0][((crc >> 24) ^ byte as u32) as usize] ^ (crc << 8);
+    }
+    crc
+}
+
+/// CRC state for 32 bits
+pub struct State32 {
+    crc: u32,
+}
+
+impl State32 {
+    /// Create a new state
+    pub fn new() -> State32 {
+        State32 { crc: !0 }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, buf: &[u8]) {
+        self.crc = update(&TABLES, self.crc, buf);
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u32 {
+        !self.crc
+    }
+
+    /// Reset the state
+    pub fn reset(&mut self) {
+        self.crc = !0;
+    }
+}
+
+/// CRC state for 32 bits, shifted MSB first as in bzip2
+pub struct Bzip2State32 {
+    crc: u32,
+}
+
+impl Bzip2State32 {
+    /// Create a new state
+    pub fn new() -> Bzip2State32 {
+        Bzip2State32 { crc: !0 }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, buf: &[u8]) {
+        self.crc = update_msb(&TABLES_MSB, self.crc, buf);
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u32 {
+        !self.crc
+    }
+
+    /// Reset the state
+    pub fn reset(&mut self) {
+        self.crc = !0;
+    }
+}
+
+/// Computes the CRC of the concatenation of two buffers from their own CRCs
+/// and the length of the second one, so that pieces of a stream can be summed
+/// independently.
+pub fn combine(crc1: u32, crc2: u32, len2: u64) -> u32 {
+    super::combine_crc(POLYNOMIAL as u64, 32, crc1 as u64, crc2 as u64, len2) as u32
+}
+
+impl Checksum for State32 {
+    type Output = u32;
+
+    fn feed(&mut self, buf: &[u8]) {
+        State32::feed(self, buf)
+    }
+
+    fn result(&self) -> u32 {
+        State32::result(self)
+    }
+
+    fn reset(&mut self) {
+        State32::reset(self)
+    }
+}
+
+impl Combine for State32 {
+    fn combine(sum1: u32, sum2: u32, len2: u64) -> u32 {
+        combine(sum1, sum2, len2)
+    }
+}
+
+impl Checksum for Bzip2State32 {
+    type Output = u32;
+
+    fn feed(&mut self, buf: &[u8]) {
+        Bzip2State32::feed(self, buf)
+    }
+
+    fn result(&self) -> u32 {
+        Bzip2State32::result(self)
+    }
+
+    fn reset(&mut self) {
+        Bzip2State32::reset(self)
+    }
+}
+
+impl Combine for Bzip2State32 {
+    fn combine(sum1: u32, sum2: u32, len2: u64) -> u32 {
+        // appending zero bytes is the same with the bits of the bytes reversed
+        combine(sum1.reverse_bits(), sum2.reverse_bits(), len2).reverse_bits()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Bzip2State32, State32, TABLES, TABLES_MSB, combine};
+
+    fn checksum(buf: &[u8]) -> u32 {
+        let mut state = State32::new();
+        state.feed(buf);
+        state.result()
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"a"), 0xe8b7be43);
+        assert_eq!(checksum(b"123456789"), 0xcbf43926);
+        assert_eq!(checksum(b"The quick brown fox jumps over the lazy dog"), 0x414fa339);
+    }
+
+    #[test]
+    fn bzip2_values() {
+        let mut state = Bzip2State32::new();
+        assert_eq!(state.result(), 0);
+        state.feed(b"123456789");
+        assert_eq!(state.result(), 0xfc891918);
+        state.reset();
+        state.feed(b"The quick brown fox jumps over the lazy dog");
+        assert_eq!(state.result(), 0x459dee61);
+    }
+
+    #[test]
+    fn slicing() {
+        // against the plain byte-wise lookup, from all the alignments
+        let data = &include_bytes!("../data/test.txt")[..100];
+        for start in 0..9 {
+            for end in start..data.len() {
+                let buf = &data[start..end];
+                let (mut lsb, mut msb) = (!0u32, !0u32);
+                for &byte in buf.iter() {
+                    lsb = TABLES[0][((lsb ^ byte as u32) & 0xff) as usize] ^ (lsb >> 8);
+                    msb = TABLES_MSB[0][((msb >> 24) ^ byte as u32) as usize] ^ (msb << 8);
+                }
+                assert_eq!(checksum(buf), !lsb);
+                let mut state = Bzip2State32::new();
+                state.feed(buf);
+                assert_eq!(state.result(), !msb);
+            }
+        }
+    }
+
+    #[test]
+    fn split_feed() {
+        let data = include_bytes!("../data/test.txt");
+        let mut state = State32::new();
+        for chunk in data.chunks(7) {
+            state.feed(chunk);
+        }
+        assert_eq!(state.result(), checksum(data));
+    }
+
+    #[test]
+    fn combined() {
+        let data = include_bytes!("../data/test.txt");
+        for &split in [0, 1, 100, data.len() - 1, data.len()].iter() {
+            let (a, b) = data.split_at(split);
+            assert_eq!(combine(checksum(a), checksum(b), b.len() as u64), checksum(data));
+        }
+    }
+}
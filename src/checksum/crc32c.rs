@@ -0,0 +1,188 @@
+/*!
+
+CRC-32C checksum
+
+This is the Castagnoli variant (reflected polynomial 0x82F63B78) used by
+iSCSI, ext4 and the snappy framing format. When the processor has an
+instruction for it, as with SSE4.2 on x86 or the CRC extension on AArch64, as
+detected at runtime, the data is processed 8 bytes per instruction. Otherwise
+the software slicing-by-8 of the `crc32` module is used.
+
+# Example
+
+```rust
+use compress::checksum::crc32c;
+let mut state = crc32c::State32::new();
+state.feed(b"abracadabra");
+let checksum = state.result();
+```
+
+*/
+
+use super::{Checksum, Combine};
+use super::crc32::{make_tables, update};
+
+const POLYNOMIAL: u32 = 0x82f63b78;
+
+static TABLES: [[u32; 256]; 8] = make_tables(POLYNOMIAL);
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn update_hardware(crc: u32, buf: &[u8]) -> Option<u32> {
+    if is_x86_feature_detected!("sse4.2") {
+        Some(unsafe { update_sse42(crc, buf) })
+    } else {
+        None
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse4.2")]
+unsafe fn update_sse42(crc: u32, buf: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::{_mm_crc32_u32, _mm_crc32_u8};
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut crc = crc;
+    let mut chunks = buf.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(chunk);
+        let word = u64::from_le_bytes(word);
+        #[cfg(target_arch = "x86_64")]
+        {
+            crc = _mm_crc32_u64(crc as u64, word) as u32;
+        }
+        #[cfg(target_arch = "x86")]
+        {
+            crc = _mm_crc32_u32(_mm_crc32_u32(crc, word as u32), (word >> 32) as u32);
+        }
+    }
+    for &byte in chunks.remainder().iter() {
+        crc = _mm_crc32_u8(crc, byte);
+    }
+    crc
+}
+
+#[cfg(target_arch = "aarch64")]
+fn update_hardware(crc: u32, buf: &[u8]) -> Option<u32> {
+    if std::arch::is_aarch64_feature_detected!("crc") {
+        Some(unsafe { update_arm(crc, buf) })
+    } else {
+        None
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "crc")]
+unsafe fn update_arm(crc: u32, buf: &[u8]) -> u32 {
+    use std::arch::aarch64::{__crc32cb, __crc32cd};
+
+    let mut crc = crc;
+    let mut chunks = buf.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(chunk);
+        crc = __crc32cd(crc, u64::from_le_bytes(word));
+    }
+    for &byte in chunks.remainder().iter() {
+        crc = __crc32cb(crc, byte);
+    }
+    crc
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+fn update_hardware(_crc: u32, _buf: &[u8]) -> Option<u32> {
+    None
+}
+
+/// Returns whether the CRC is computed by an instruction of the processor
+pub fn is_hardware() -> bool {
+    update_hardware(0, &[]).is_some()
+}
+
+/// CRC state for 32 bits
+pub struct State32 {
+    crc: u32,
+}
+
+impl State32 {
+    /// Create a new state
+    pub fn new() -> State32 {
+        State32 { crc: !0 }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, buf: &[u8]) {
+        self.crc = match update_hardware(self.crc, buf) {
+            Some(crc) => crc,
+            None => update(&TABLES, self.crc, buf),
+        };
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u32 {
+        !self.crc
+    }
+
+    /// Reset the state
+    pub fn reset(&mut self) {
+        self.crc = !0;
+    }
+}
+
+impl Checksum for State32 {
+    type Output = u32;
+
+    fn feed(&mut self, buf: &[u8]) {
+        State32::feed(self, buf)
+    }
+
+    fn result(&self) -> u32 {
+        State32::result(self)
+    }
+
+    fn reset(&mut self) {
+        State32::reset(self)
+    }
+}
+
+impl Combine for State32 {
+    fn combine(sum1: u32, sum2: u32, len2: u64) -> u32 {
+        super::combine_crc(POLYNOMIAL as u64, 32, sum1 as u64, sum2 as u64, len2) as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{State32, TABLES, update, update_hardware};
+
+    fn checksum(buf: &[u8]) -> u32 {
+        let mut state = State32::new();
+        state.feed(buf);
+        state.result()
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"a"), 0xc1d04330);
+        assert_eq!(checksum(b"123456789"), 0xe3069283);
+        assert_eq!(checksum(&[0u8; 32]), 0x8a9136aa);
+    }
+
+    #[test]
+    fn software() {
+        assert_eq!(!update(&TABLES, !0, b"123456789"), 0xe3069283);
+        // the instructions and the tables agree, from all the alignments
+        let data = &include_bytes!("../data/test.txt")[..100];
+        for start in 0..9 {
+            for end in start..data.len() {
+                let buf = &data[start..end];
+                let crc = update(&TABLES, !0, buf);
+                assert_eq!(update_hardware(!0, buf).unwrap_or(crc), crc);
+                assert_eq!(checksum(buf), !crc);
+            }
+        }
+    }
+}
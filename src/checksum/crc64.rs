@@ -0,0 +1,155 @@
+/*!
+
+CRC-64 checksum
+
+This is the variant of the xz format, from the polynomial of ECMA-182 with the
+bits reflected (0xC96C5795D7870F42). As for CRC-32, the data is processed 8
+bytes at a time by slicing-by-8.
+
+# Example
+
+```rust
+use compress::checksum::crc64;
+let mut state = crc64::State64::new();
+state.feed(b"abracadabra");
+let checksum = state.result();
+```
+
+*/
+
+use super::{Checksum, Combine};
+
+const POLYNOMIAL: u64 = 0xc96c5795d7870f42;
+
+static TABLES: [[u64; 256]; 8] = make_tables();
+
+/// Makes the tables of slicing-by-8, where the table `k` gives the CRC of a
+/// byte followed by `k` zero bytes
+const fn make_tables() -> [[u64; 256]; 8] {
+    let mut tables = [[0u64; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u64;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { POLYNOMIAL ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        tables[0][i] = c;
+        i += 1;
+    }
+    let mut k = 1;
+    while k < 8 {
+        let mut i = 0;
+        while i < 256 {
+            let c = tables[k - 1][i];
+            tables[k][i] = tables[0][(c & 0xff) as usize] ^ (c >> 8);
+            i += 1;
+        }
+        k += 1;
+    }
+    tables
+}
+
+fn update(mut crc: u64, buf: &[u8]) -> u64 {
+    let mut chunks = buf.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(chunk);
+        let x = crc ^ u64::from_le_bytes(word);
+        crc = TABLES[7][(x & 0xff) as usize] ^ TABLES[6][((x >> 8) & 0xff) as usize] ^
+              TABLES[5][((x >> 16) & 0xff) as usize] ^ TABLES[4][((x >> 24) & 0xff) as usize] ^
+              TABLES[3][((x >> 32) & 0xff) as usize] ^ TABLES[2][((x >> 40) & 0xff) as usize] ^
+              TABLES[1][((x >> 48) & 0xff) as usize] ^ TABLES[0][(x >> 56) as usize];
+    }
+    for &byte in chunks.remainder().iter() {
+        crc = TABLES[0][((crc ^ byte as u64) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// CRC state for 64 bits
+pub struct State64 {
+    crc: u64,
+}
+
+impl State64 {
+    /// Create a new state
+    pub fn new() -> State64 {
+        State64 { crc: !0 }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, buf: &[u8]) {
+        self.crc = update(self.crc, buf);
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u64 {
+        !self.crc
+    }
+
+    /// Reset the state
+    pub fn reset(&mut self) {
+        self.crc = !0;
+    }
+}
+
+impl Checksum for State64 {
+    type Output = u64;
+
+    fn feed(&mut self, buf: &[u8]) {
+        State64::feed(self, buf)
+    }
+
+    fn result(&self) -> u64 {
+        State64::result(self)
+    }
+
+    fn reset(&mut self) {
+        State64::reset(self)
+    }
+}
+
+impl Combine for State64 {
+    fn combine(sum1: u64, sum2: u64, len2: u64) -> u64 {
+        super::combine_crc(POLYNOMIAL, 64, sum1, sum2, len2)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{State64, TABLES};
+
+    fn checksum(buf: &[u8]) -> u64 {
+        let mut state = State64::new();
+        state.feed(buf);
+        state.result()
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"123456789"), 0x995dc9bbdf1939fa);
+        let mut state = State64::new();
+        state.feed(b"1234");
+        state.feed(b"56789");
+        assert_eq!(state.result(), 0x995dc9bbdf1939fa);
+    }
+
+    #[test]
+    fn slicing() {
+        // against the plain byte-wise lookup, from all the alignments
+        let data = &include_bytes!("../data/test.txt")[..100];
+        for start in 0..9 {
+            for end in start..data.len() {
+                let buf = &data[start..end];
+                let mut crc = !0u64;
+                for &byte in buf.iter() {
+                    crc = TABLES[0][((crc ^ byte as u64) & 0xff) as usize] ^ (crc >> 8);
+                }
+                assert_eq!(checksum(buf), !crc);
+            }
+        }
+    }
+}
@@ -0,0 +1,229 @@
+/*!
+
+Fletcher checksums
+
+Fletcher-16 sums the bytes modulo 255, and Fletcher-32 the 16-bit little
+endian words modulo 65535, along with the sums of these sums. The last byte of
+an odd length is completed by a zero for Fletcher-32. The sums are reduced by
+blocks rather than after each byte.
+
+# Links
+
+http://en.wikipedia.org/wiki/Fletcher%27s_checksum
+
+# Example
+
+```rust
+use compress::checksum::fletcher;
+let mut state = fletcher::State16::new();
+state.feed(b"abracadabra");
+let checksum = state.result();
+```
+
+*/
+
+use super::{Checksum, Combine};
+
+/// Number of bytes or words summed between two reductions
+const BLOCK: usize = 1 << 12;
+
+/// Fletcher state for 16 bits
+pub struct State16 {
+    a: u64,
+    b: u64,
+}
+
+impl State16 {
+    /// Create a new state
+    pub fn new() -> State16 {
+        State16 { a: 0, b: 0 }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, buf: &[u8]) {
+        for block in buf.chunks(BLOCK) {
+            for &byte in block.iter() {
+                self.a += byte as u64;
+                self.b += self.a;
+            }
+            self.a %= 255;
+            self.b %= 255;
+        }
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u16 {
+        ((self.b << 8) | self.a) as u16
+    }
+
+    /// Reset the state
+    pub fn reset(&mut self) {
+        self.a = 0;
+        self.b = 0;
+    }
+}
+
+/// Fletcher state for 32 bits
+pub struct State32 {
+    a: u64,
+    b: u64,
+    /// First byte of an incomplete word
+    odd: Option<u8>,
+}
+
+impl State32 {
+    /// Create a new state
+    pub fn new() -> State32 {
+        State32 { a: 0, b: 0, odd: None }
+    }
+
+    fn word(&mut self, word: u16) {
+        self.a += word as u64;
+        self.b += self.a;
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, mut buf: &[u8]) {
+        if let Some(low) = self.odd {
+            if buf.is_empty() {
+                return
+            }
+            self.word(low as u16 | (buf[0] as u16) << 8);
+            self.odd = None;
+            buf = &buf[1..];
+        }
+        for block in buf.chunks(BLOCK * 2) {
+            let mut words = block.chunks_exact(2);
+            for word in &mut words {
+                self.word(word[0] as u16 | (word[1] as u16) << 8);
+            }
+            if let Some(&low) = words.remainder().first() {
+                self.odd = Some(low);
+            }
+            self.a %= 65535;
+            self.b %= 65535;
+        }
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u32 {
+        let (mut a, mut b) = (self.a, self.b);
+        if let Some(low) = self.odd {
+            a = (a + low as u64) % 65535;
+            b = (b + a) % 65535;
+        }
+        ((b << 16) | a) as u32
+    }
+
+    /// Reset the state
+    pub fn reset(&mut self) {
+        self.a = 0;
+        self.b = 0;
+        self.odd = None;
+    }
+}
+
+impl Checksum for State16 {
+    type Output = u16;
+
+    fn feed(&mut self, buf: &[u8]) {
+        State16::feed(self, buf)
+    }
+
+    fn result(&self) -> u16 {
+        State16::result(self)
+    }
+
+    fn reset(&mut self) {
+        State16::reset(self)
+    }
+}
+
+impl Combine for State16 {
+    fn combine(sum1: u16, sum2: u16, len2: u64) -> u16 {
+        let (a1, b1) = ((sum1 & 0xff) as u64, (sum1 >> 8) as u64);
+        let (a2, b2) = ((sum2 & 0xff) as u64, (sum2 >> 8) as u64);
+        let a = (a1 + a2) % 255;
+        let b = (b1 + b2 + len2 % 255 * a1) % 255;
+        ((b << 8) | a) as u16
+    }
+}
+
+impl Checksum for State32 {
+    type Output = u32;
+
+    fn feed(&mut self, buf: &[u8]) {
+        State32::feed(self, buf)
+    }
+
+    fn result(&self) -> u32 {
+        State32::result(self)
+    }
+
+    fn reset(&mut self) {
+        State32::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{State16, State32};
+
+    fn checksum16(buf: &[u8]) -> u16 {
+        let mut state = State16::new();
+        state.feed(buf);
+        state.result()
+    }
+
+    fn checksum32(buf: &[u8]) -> u32 {
+        let mut state = State32::new();
+        state.feed(buf);
+        state.result()
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(checksum16(b""), 0);
+        assert_eq!(checksum16(b"abcde"), 0xc8f0);
+        assert_eq!(checksum16(b"abcdef"), 0x2057);
+        assert_eq!(checksum16(b"abcdefgh"), 0x0627);
+        assert_eq!(checksum32(b""), 0);
+        assert_eq!(checksum32(b"abcde"), 0xf04fc729);
+        assert_eq!(checksum32(b"abcdef"), 0x56502d2a);
+        assert_eq!(checksum32(b"abcdefgh"), 0xebe19591);
+    }
+
+    #[test]
+    fn long_data() {
+        // the sums are reduced as they would be after each byte
+        let data: Vec<u8> = (0..50001).map(|_| 0xff).collect();
+        let (mut a, mut b) = (0u32, 0u32);
+        for &byte in data.iter() {
+            a = (a + byte as u32) % 255;
+            b = (b + a) % 255;
+        }
+        assert_eq!(checksum16(&data) as u32, b << 8 | a);
+        let (mut a, mut b) = (0u32, 0u32);
+        for word in data.chunks(2) {
+            a = (a + word[0] as u32 + *word.get(1).unwrap_or(&0) as u32 * 256) % 65535;
+            b = (b + a) % 65535;
+        }
+        assert_eq!(checksum32(&data), b << 16 | a);
+    }
+
+    #[test]
+    fn split_feed() {
+        let data = include_bytes!("../data/test.txt");
+        for &step in [1, 3, 1000].iter() {
+            let mut state16 = State16::new();
+            let mut state32 = State32::new();
+            for chunk in data.chunks(step) {
+                state16.feed(chunk);
+                state32.feed(chunk);
+                state32.feed(&[]);
+            }
+            assert_eq!(state16.result(), checksum16(data));
+            assert_eq!(state32.result(), checksum32(data));
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! Checksum algorithms. Requires `checksum` feature, enabled by default
+//!
+//! Each checksum is a state fed with the data in pieces of any size, which
+//! gives the checksum of all the data fed so far. They all implement the
+//! `Checksum` trait, so that they can be chosen by the caller of a generic
+//! stream, and those of which the checksum of concatenated data can be
+//! computed from the checksums of its pieces also implement `Combine`, so
+//! that the pieces can be summed in parallel. Any of them can sum the data
+//! going through a stream, as wrapped by a `Reader` or a `Writer`.
+//!
+//! * `adler` is Adler-32, as in zlib, which can also be rolled along the data
+//! * `crc32` is the CRC-32 of gzip, zip and png, along with that of bzip2
+//! * `crc32c` is the Castagnoli CRC-32, as in iSCSI and snappy
+//! * `crc64` is the CRC-64 of xz
+//! * `fletcher` is Fletcher-16 and Fletcher-32, as in some network protocols
+//! * `xxhash` is the 32-bit and 64-bit xxHash, as in LZ4 and zstd
+//!
+//! The `lz4` feature alone only brings `xxhash`, which builds without `std`.
+// http://en.wikipedia.org/wiki/Checksum
+
+#[cfg(feature="checksum")]
+use std::io::{self, Read, Write};
+
+#[cfg(feature="checksum")]
+pub mod adler;
+#[cfg(feature="checksum")]
+pub mod crc32;
+#[cfg(feature="checksum")]
+pub mod crc32c;
+#[cfg(feature="checksum")]
+pub mod crc64;
+#[cfg(feature="checksum")]
+pub mod fletcher;
+pub mod xxhash;
+
+/// The state of a checksum
+pub trait Checksum {
+    /// Type of the checksum, `u32` or `u64`
+    type Output;
+
+    /// Mutate the state for given data
+    fn feed(&mut self, buf: &[u8]);
+
+    /// Get the checksum of the data fed so far
+    fn result(&self) -> Self::Output;
+
+    /// Reset the state, as new
+    fn reset(&mut self);
+}
+
+/// A checksum of which the value for concatenated data follows from the
+/// values for its pieces
+pub trait Combine: Checksum {
+    /// Computes the checksum of the concatenation of two buffers from their
+    /// own checksums and the length of the second one
+    fn combine(sum1: Self::Output, sum2: Self::Output, len2: u64) -> Self::Output;
+}
+
+#[cfg(feature="checksum")]
+/// A reader summing the data read through it
+pub struct Reader<R, C> {
+    r: R,
+    state: C,
+    count: u64,
+}
+
+#[cfg(feature="checksum")]
+impl<R: Read, C: Checksum> Reader<R, C> {
+    /// Creates a new reader, summing the data read from `r` with `state`
+    pub fn new(r: R, state: C) -> Reader<R, C> {
+        Reader {
+            r: r,
+            state: state,
+            count: 0,
+        }
+    }
+
+    /// Get the checksum of the data read so far
+    pub fn result(&self) -> C::Output {
+        self.state.result()
+    }
+
+    /// Get the number of bytes read so far
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns a mutable reference to the wrapped reader, of which the data
+    /// read directly is not summed
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.r
+    }
+
+    /// Returns the wrapped reader, along with the state of the checksum
+    pub fn unwrap(self) -> (R, C) {
+        (self.r, self.state)
+    }
+}
+
+#[cfg(feature="checksum")]
+impl<R: Read, C: Checksum> Read for Reader<R, C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.r.read(buf));
+        self.state.feed(&buf[..n]);
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(feature="checksum")]
+/// A writer summing the data written through it
+pub struct Writer<W, C> {
+    w: W,
+    state: C,
+    count: u64,
+}
+
+#[cfg(feature="checksum")]
+impl<W: Write, C: Checksum> Writer<W, C> {
+    /// Creates a new writer, summing the data written to `w` with `state`
+    pub fn new(w: W, state: C) -> Writer<W, C> {
+        Writer {
+            w: w,
+            state: state,
+            count: 0,
+        }
+    }
+
+    /// Get the checksum of the data written so far
+    pub fn result(&self) -> C::Output {
+        self.state.result()
+    }
+
+    /// Get the number of bytes written so far
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns a mutable reference to the wrapped writer, of which the data
+    /// written directly is not summed
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.w
+    }
+
+    /// Resets the checksum and the count, as new
+    pub fn reset(&mut self) {
+        self.state.reset();
+        self.count = 0;
+    }
+
+    /// Returns the wrapped writer, along with the state of the checksum
+    pub fn unwrap(self) -> (W, C) {
+        (self.w, self.state)
+    }
+}
+
+#[cfg(feature="checksum")]
+impl<W: Write, C: Checksum> Write for Writer<W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.w.write(buf));
+        self.state.feed(&buf[..n]);
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+#[cfg(feature="checksum")]
+/// Computes the CRC of the concatenation of two buffers from their CRCs, for
+/// a reflected polynomial of `width` bits, up to 64
+fn combine_crc(polynomial: u64, width: usize, crc1: u64, crc2: u64, len2: u64) -> u64 {
+    if len2 == 0 {
+        return crc1
+    }
+    // operators appending zero bits to a CRC, as matrices over GF(2), starting
+    // with a single zero bit
+    let mut even = [0u64; 64];
+    let mut odd = [0u64; 64];
+    odd[0] = polynomial;
+    for n in 1..width {
+        odd[n] = 1 << (n - 1);
+    }
+    gf2_square(&mut even, &odd, width);
+    gf2_square(&mut odd, &even, width);
+
+    // apply len2 zero bytes to crc1, squaring the operator for each bit
+    let mut crc1 = crc1;
+    let mut len2 = len2;
+    loop {
+        gf2_square(&mut even, &odd, width);
+        if len2 & 1 != 0 {
+            crc1 = gf2_times(&even, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break
+        }
+        gf2_square(&mut odd, &even, width);
+        if len2 & 1 != 0 {
+            crc1 = gf2_times(&odd, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break
+        }
+    }
+    crc1 ^ crc2
+}
+
+#[cfg(feature="checksum")]
+fn gf2_times(mat: &[u64; 64], mut vec: u64) -> u64 {
+    let mut sum = 0;
+    let mut i = 0;
+    while vec != 0 {
+        if vec & 1 != 0 {
+            sum ^= mat[i];
+        }
+        vec >>= 1;
+        i += 1;
+    }
+    sum
+}
+
+#[cfg(feature="checksum")]
+fn gf2_square(square: &mut [u64; 64], mat: &[u64; 64], width: usize) {
+    for n in 0..width {
+        square[n] = gf2_times(mat, mat[n]);
+    }
+}
+
+#[cfg(feature="checksum")]
+#[cfg(test)]
+mod test {
+    use std::fmt::Debug;
+    use std::io::{self, Read, Write};
+    use super::{adler, crc32, crc32c, crc64, fletcher, xxhash, Checksum, Combine, Reader, Writer};
+
+    fn checksum<C: Checksum>(state: &mut C, buf: &[u8]) -> C::Output {
+        state.reset();
+        state.feed(buf);
+        state.result()
+    }
+
+    fn check_combine<C: Combine>(mut state: C) where C::Output: Copy + Debug + PartialEq {
+        let data = include_bytes!("../data/test.txt");
+        let mut long = vec![0x55u8; 70000];
+        long.extend_from_slice(data);
+        for &split in [0, 1, 8, 100, data.len() - 1, data.len()].iter() {
+            let (a, b) = data.split_at(split);
+            let (sum1, sum2) = (checksum(&mut state, a), checksum(&mut state, b));
+            assert_eq!(C::combine(sum1, sum2, b.len() as u64), checksum(&mut state, data));
+        }
+        let (a, b) = long.split_at(10);
+        let (sum1, sum2) = (checksum(&mut state, a), checksum(&mut state, b));
+        assert_eq!(C::combine(sum1, sum2, b.len() as u64), checksum(&mut state, &long));
+    }
+
+    #[test]
+    fn combined() {
+        check_combine(adler::State32::new());
+        check_combine(crc32::State32::new());
+        check_combine(crc32::Bzip2State32::new());
+        check_combine(crc32c::State32::new());
+        check_combine(crc64::State64::new());
+        check_combine(fletcher::State16::new());
+    }
+
+    fn states() -> Vec<Box<dyn Checksum<Output = u32>>> {
+        vec![Box::new(adler::State32::new()), Box::new(crc32::State32::new()),
+             Box::new(crc32::Bzip2State32::new()), Box::new(crc32c::State32::new()),
+             Box::new(fletcher::State32::new()), Box::new(xxhash::State32::new(1))]
+    }
+
+    #[test]
+    fn generic_states() {
+        // the seeds are kept by a reset
+        for (mut state, mut fresh) in states().into_iter().zip(states().into_iter()) {
+            state.feed(b"Wiki");
+            state.reset();
+            state.feed(b"Wikipedia");
+            fresh.feed(b"Wikipedia");
+            assert_eq!(state.result(), fresh.result());
+        }
+    }
+
+    #[test]
+    fn streams() {
+        let data = &include_bytes!("../data/test.txt")[..];
+        let mut w = Writer::new(Vec::new(), crc32::State32::new());
+        for chunk in data.chunks(100) {
+            w.write_all(chunk).unwrap();
+        }
+        w.flush().unwrap();
+        assert_eq!((w.result(), w.count()), (checksum(&mut crc32::State32::new(), data), data.len() as u64));
+        let (buf, _) = w.unwrap();
+        assert!(&buf[..] == data);
+
+        let mut r = Reader::new(io::BufReader::with_capacity(7, data), xxhash::State64::new(3));
+        let mut decoded = Vec::new();
+        r.read_to_end(&mut decoded).unwrap();
+        assert!(&decoded[..] == data);
+        assert_eq!((r.result(), r.count()), (checksum(&mut xxhash::State64::new(3), data), data.len() as u64));
+    }
+}
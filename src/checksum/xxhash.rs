@@ -0,0 +1,320 @@
+/*!
+
+xxHash non-cryptographic hashes
+
+These are the 32-bit and 64-bit variants of xxHash, as used by the LZ4 frame
+format and by zstd; both take a seed, and are fast enough to key blocks for
+deduplication. The data goes by stripes of 4 lanes, buffering the incomplete
+stripe between feeds.
+
+# Links
+
+https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md - xxHash
+fast digest algorithm
+
+# Example
+
+```rust
+use compress::checksum::xxhash;
+let mut state = xxhash::State32::new(0);
+state.feed(b"abracadabra");
+let hash = state.result();
+```
+
+*/
+
+use std::cmp;
+
+use super::Checksum;
+
+const PRIME32_1: u32 = 0x9e3779b1;
+const PRIME32_2: u32 = 0x85ebca77;
+const PRIME32_3: u32 = 0xc2b2ae3d;
+const PRIME32_4: u32 = 0x27d4eb2f;
+const PRIME32_5: u32 = 0x165667b1;
+
+const PRIME64_1: u64 = 11400714785074694791;
+const PRIME64_2: u64 = 14029467366897019727;
+const PRIME64_3: u64 = 1609587929392839161;
+const PRIME64_4: u64 = 9650029242287828579;
+const PRIME64_5: u64 = 2870177450012600261;
+
+fn read_u32(buf: &[u8]) -> u32 {
+    buf[..4].iter().rev().fold(0, |v, &b| (v << 8) | b as u32)
+}
+
+fn read_u64(buf: &[u8]) -> u64 {
+    buf[..8].iter().rev().fold(0, |v, &b| (v << 8) | b as u64)
+}
+
+fn round32(acc: u32, input: u32) -> u32 {
+    acc.wrapping_add(input.wrapping_mul(PRIME32_2)).rotate_left(13).wrapping_mul(PRIME32_1)
+}
+
+fn round64(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2)).rotate_left(31).wrapping_mul(PRIME64_1)
+}
+
+fn merge64(acc: u64, v: u64) -> u64 {
+    (acc ^ round64(0, v)).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4)
+}
+
+/// Feeds `buf` by stripes, of which the incomplete one is kept in `stripe`
+fn feed_stripes<F: FnMut(&[u8])>(stripe: &mut [u8], fill: &mut usize, mut buf: &[u8], mut round: F) {
+    let size = stripe.len();
+    if *fill > 0 {
+        let amt = cmp::min(size - *fill, buf.len());
+        stripe[*fill..(*fill + amt)].copy_from_slice(&buf[..amt]);
+        *fill += amt;
+        buf = &buf[amt..];
+        if *fill < size {
+            return
+        }
+        round(stripe);
+        *fill = 0;
+    }
+    while buf.len() >= size {
+        round(&buf[..size]);
+        buf = &buf[size..];
+    }
+    stripe[..buf.len()].copy_from_slice(buf);
+    *fill = buf.len();
+}
+
+/// xxHash state for 32 bits
+pub struct State32 {
+    seed: u32,
+    v: [u32; 4],
+    buf: [u8; 16],
+    buf_len: usize,
+    total: u64,
+}
+
+impl State32 {
+    /// Create a new state with the given seed
+    pub fn new(seed: u32) -> State32 {
+        State32 {
+            seed: seed,
+            v: [seed.wrapping_add(PRIME32_1).wrapping_add(PRIME32_2), seed.wrapping_add(PRIME32_2),
+                seed, seed.wrapping_sub(PRIME32_1)],
+            buf: [0; 16],
+            buf_len: 0,
+            total: 0,
+        }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, buf: &[u8]) {
+        self.total += buf.len() as u64;
+        let v = &mut self.v;
+        feed_stripes(&mut self.buf, &mut self.buf_len, buf, |stripe| {
+            for i in 0..4 {
+                v[i] = round32(v[i], read_u32(&stripe[(4 * i)..]));
+            }
+        });
+    }
+
+    /// Get the hash
+    pub fn result(&self) -> u32 {
+        let mut h = if self.total >= 16 {
+            let v = self.v;
+            v[0].rotate_left(1).wrapping_add(v[1].rotate_left(7))
+                .wrapping_add(v[2].rotate_left(12)).wrapping_add(v[3].rotate_left(18))
+        } else {
+            self.seed.wrapping_add(PRIME32_5)
+        };
+        h = h.wrapping_add(self.total as u32);
+
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 4 {
+            h = h.wrapping_add(read_u32(rest).wrapping_mul(PRIME32_3));
+            h = h.rotate_left(17).wrapping_mul(PRIME32_4);
+            rest = &rest[4..];
+        }
+        for &b in rest.iter() {
+            h = h.wrapping_add((b as u32).wrapping_mul(PRIME32_5));
+            h = h.rotate_left(11).wrapping_mul(PRIME32_1);
+        }
+
+        h ^= h >> 15;
+        h = h.wrapping_mul(PRIME32_2);
+        h ^= h >> 13;
+        h = h.wrapping_mul(PRIME32_3);
+        h ^ (h >> 16)
+    }
+
+    /// Reset the state, keeping its seed
+    pub fn reset(&mut self) {
+        *self = State32::new(self.seed);
+    }
+}
+
+/// xxHash state for 64 bits
+pub struct State64 {
+    seed: u64,
+    v: [u64; 4],
+    buf: [u8; 32],
+    buf_len: usize,
+    total: u64,
+}
+
+impl State64 {
+    /// Create a new state with the given seed
+    pub fn new(seed: u64) -> State64 {
+        State64 {
+            seed: seed,
+            v: [seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2), seed.wrapping_add(PRIME64_2),
+                seed, seed.wrapping_sub(PRIME64_1)],
+            buf: [0; 32],
+            buf_len: 0,
+            total: 0,
+        }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, buf: &[u8]) {
+        self.total += buf.len() as u64;
+        let v = &mut self.v;
+        feed_stripes(&mut self.buf, &mut self.buf_len, buf, |stripe| {
+            for i in 0..4 {
+                v[i] = round64(v[i], read_u64(&stripe[(8 * i)..]));
+            }
+        });
+    }
+
+    /// Get the hash
+    pub fn result(&self) -> u64 {
+        let mut h = if self.total >= 32 {
+            let v = self.v;
+            let mut h = v[0].rotate_left(1).wrapping_add(v[1].rotate_left(7))
+                .wrapping_add(v[2].rotate_left(12)).wrapping_add(v[3].rotate_left(18));
+            for &v in v.iter() {
+                h = merge64(h, v);
+            }
+            h
+        } else {
+            self.seed.wrapping_add(PRIME64_5)
+        };
+        h = h.wrapping_add(self.total);
+
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 8 {
+            h ^= round64(0, read_u64(rest));
+            h = h.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            h ^= (read_u32(rest) as u64).wrapping_mul(PRIME64_1);
+            h = h.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
+            rest = &rest[4..];
+        }
+        for &b in rest.iter() {
+            h ^= (b as u64).wrapping_mul(PRIME64_5);
+            h = h.rotate_left(11).wrapping_mul(PRIME64_1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(PRIME64_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(PRIME64_3);
+        h ^ (h >> 32)
+    }
+
+    /// Reset the state, keeping its seed
+    pub fn reset(&mut self) {
+        *self = State64::new(self.seed);
+    }
+}
+
+impl Checksum for State32 {
+    type Output = u32;
+
+    fn feed(&mut self, buf: &[u8]) {
+        State32::feed(self, buf)
+    }
+
+    fn result(&self) -> u32 {
+        State32::result(self)
+    }
+
+    fn reset(&mut self) {
+        State32::reset(self)
+    }
+}
+
+impl Checksum for State64 {
+    type Output = u64;
+
+    fn feed(&mut self, buf: &[u8]) {
+        State64::feed(self, buf)
+    }
+
+    fn result(&self) -> u64 {
+        State64::result(self)
+    }
+
+    fn reset(&mut self) {
+        State64::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{State32, State64};
+
+    fn hash32(buf: &[u8], seed: u32) -> u32 {
+        let mut state = State32::new(seed);
+        state.feed(buf);
+        state.result()
+    }
+
+    fn hash64(buf: &[u8], seed: u64) -> u64 {
+        let mut state = State64::new(seed);
+        state.feed(buf);
+        state.result()
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(hash32(b"", 0), 0x02cc5d05);
+        assert_eq!(hash32(b"abc", 0), 0x32d153ff);
+        assert_eq!(hash32(b"Nobody inspects the spammish repetition", 0), 0xe2293b2f);
+        assert_eq!(hash64(b"", 0), 0xef46db3751d8e999);
+        assert_eq!(hash64(b"abc", 0), 0x44bc2cf5ad770999);
+        assert_eq!(hash64(b"Nobody inspects the spammish repetition", 0), 0xfbcea83c8a378bf1);
+    }
+
+    #[test]
+    fn split_feed() {
+        let text = include_bytes!("../data/test.txt");
+        for &step in [1, 13, 16, 100].iter() {
+            let mut state32 = State32::new(7);
+            let mut state64 = State64::new(7);
+            for chunk in text.chunks(step) {
+                state32.feed(chunk);
+                state64.feed(chunk);
+            }
+            assert_eq!(state32.result(), hash32(text, 7));
+            assert_eq!(state64.result(), hash64(text, 7));
+        }
+    }
+
+    #[test]
+    fn seeds() {
+        let text = &include_bytes!("../data/test.txt")[..100];
+        for len in 0..text.len() {
+            assert!(hash32(&text[..len], 0) != hash32(&text[..len], 1));
+            assert!(hash64(&text[..len], 0) != hash64(&text[..len], 1));
+        }
+        let mut state = State32::new(1);
+        state.feed(text);
+        state.reset();
+        state.feed(b"abc");
+        assert_eq!(state.result(), hash32(b"abc", 1));
+        let mut state = State64::new(1);
+        state.feed(text);
+        state.reset();
+        state.feed(b"abc");
+        assert_eq!(state.result(), hash64(b"abc", 1));
+    }
+}
@@ -0,0 +1,203 @@
+//! Content-defined chunking
+//!
+//! Cutting a stream into blocks of fixed size ties the blocks to their
+//! offsets: a byte inserted at the start shifts all of them, and no block of
+//! the new stream matches one of the old one. The boundaries of a `Chunker`
+//! depend on the content instead, as for the rsyncable modes and for the
+//! deduplication of the chunks: a gear hash rolls over the bytes, each one
+//! shifted out of it after 64 more, and a boundary follows the bytes for
+//! which its top bits are zero. Past an edit, the boundaries fall back on
+//! those of the original stream within a chunk or two.
+//!
+//! The chunks are no shorter than the minimum size, except the last one, and
+//! no longer than the maximum size. In between, the boundaries are harder to
+//! hit until the average size and easier afterwards, as in FastCDC, which
+//! keeps most chunk sizes close to the average.
+//!
+//! # Example
+//!
+//! ```rust
+//! use compress::chunk::Chunker;
+//!
+//! let data: Vec<u8> = (0..100000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+//! let chunker = Chunker::new(1 << 10, 1 << 12, 1 << 14);
+//! let mut total = 0;
+//! for chunk in chunker.chunks(&data) {
+//!     assert!(chunk.len() <= 1 << 14);
+//!     total += chunk.len();
+//! }
+//! assert_eq!(total, data.len());
+//! ```
+//!
+//! # Related links
+//!
+//! * https://www.usenix.org/conference/atc16/technical-sessions/presentation/xia - FastCDC
+
+/// Random values of the bytes, from the splitmix64 generator
+static GEAR: [u64; 256] = make_gear();
+
+const fn make_gear() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Returns the mask of the top `bits` bits of a hash
+fn top_bits(bits: u32) -> u64 {
+    if bits == 0 { 0 } else { !0 << (64 - bits) }
+}
+
+/// Finds the content-defined boundaries of a stream
+#[derive(Clone, Debug)]
+pub struct Chunker {
+    hash: u64,
+    /// Bytes since the last boundary
+    len: usize,
+    min: usize,
+    avg: usize,
+    max: usize,
+    /// Masks of the hash before and after the average size
+    small: u64,
+    large: u64,
+}
+
+impl Chunker {
+    /// Creates a chunker of chunks of `min` to `max` bytes, of about `avg`
+    /// bytes on average, which must be a power of two between the two
+    pub fn new(min: usize, avg: usize, max: usize) -> Chunker {
+        assert!(min > 0 && min <= avg && avg <= max, "invalid chunk sizes {}/{}/{}", min, avg, max);
+        assert!(avg.is_power_of_two(), "the average chunk size {} is not a power of two", avg);
+        let bits = avg.trailing_zeros();
+        Chunker {
+            hash: 0,
+            len: 0,
+            min: min,
+            avg: avg,
+            max: max,
+            small: top_bits(bits + 1),
+            large: top_bits(bits.saturating_sub(1)),
+        }
+    }
+
+    /// Rolls the hash over `buf` up to the first boundary, returning how many
+    /// bytes lead to it, or `None` if the chunk goes on past `buf`
+    pub fn scan(&mut self, buf: &[u8]) -> Option<usize> {
+        for (i, &byte) in buf.iter().enumerate() {
+            self.hash = (self.hash << 1).wrapping_add(GEAR[byte as usize]);
+            self.len += 1;
+            if self.len < self.min {
+                continue
+            }
+            let mask = if self.len < self.avg { self.small } else { self.large };
+            if self.hash & mask == 0 || self.len >= self.max {
+                self.len = 0;
+                return Some(i + 1)
+            }
+        }
+        None
+    }
+
+    /// Starts a new stream, of which the first chunk begins at the next byte
+    pub fn reset(&mut self) {
+        self.hash = 0;
+        self.len = 0;
+    }
+
+    /// Returns an iterator over the chunks of `data`, as a whole stream
+    pub fn chunks<'a>(mut self, data: &'a [u8]) -> Chunks<'a> {
+        self.reset();
+        Chunks { data: data, chunker: self }
+    }
+}
+
+/// Iterator over the chunks of a slice, see `Chunker::chunks`
+pub struct Chunks<'a> {
+    data: &'a [u8],
+    chunker: Chunker,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.data.is_empty() {
+            return None
+        }
+        let len = self.chunker.scan(self.data).unwrap_or(self.data.len());
+        let (chunk, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+    use testing::noise;
+    use super::Chunker;
+
+    #[test]
+    fn sizes() {
+        let data = noise(1 << 20, 1);
+        let chunks: Vec<&[u8]> = Chunker::new(1 << 11, 1 << 13, 1 << 15).chunks(&data).collect();
+        assert_eq!(chunks.iter().map(|c| c.len()).sum::<usize>(), data.len());
+        for chunk in chunks[..chunks.len() - 1].iter() {
+            assert!(chunk.len() >= 1 << 11 && chunk.len() <= 1 << 15);
+        }
+        let avg = data.len() / chunks.len();
+        assert!(avg > 1 << 12 && avg < 1 << 14, "average chunk of {} bytes", avg);
+
+        // runs hit no boundary but the maximum size
+        let zeros = [0u8; 10000];
+        let sizes: Vec<usize> = Chunker::new(1, 1 << 10, 3000).chunks(&zeros).map(|c| c.len()).collect();
+        assert_eq!(sizes, [3000, 3000, 3000, 1000]);
+        assert_eq!(Chunker::new(1, 1, 1).chunks(b"abc").count(), 3);
+        assert_eq!(Chunker::new(1, 1, 1).chunks(b"").count(), 0);
+    }
+
+    #[test]
+    fn streams() {
+        // the boundaries do not depend on the pieces the stream comes in
+        let data = noise(100000, 2);
+        let whole: Vec<usize> = Chunker::new(256, 1 << 10, 1 << 12).chunks(&data).map(|c| c.len()).collect();
+        let mut chunker = Chunker::new(256, 1 << 10, 1 << 12);
+        let (mut pieces, mut len) = (Vec::new(), 0);
+        for piece in data.chunks(777) {
+            let mut rest = piece;
+            while let Some(n) = chunker.scan(rest) {
+                pieces.push(len + n);
+                len = 0;
+                rest = &rest[n..];
+            }
+            len += rest.len();
+        }
+        if len > 0 {
+            pieces.push(len);
+        }
+        assert_eq!(whole, pieces);
+    }
+
+    #[test]
+    fn edits() {
+        // past the edits, the chunks are those of the original data
+        let original = noise(200000, 3);
+        let mut edited = original.clone();
+        edited.insert(1000, b'!');
+        edited.drain(50000..50100);
+        edited[100000] ^= 1;
+        let chunker = Chunker::new(1 << 10, 1 << 12, 1 << 14);
+        let a: Vec<&[u8]> = chunker.clone().chunks(&original).collect();
+        let b: Vec<&[u8]> = chunker.chunks(&edited).collect();
+        let shared = b.iter().filter(|chunk| a.contains(chunk)).count();
+        assert!(shared + 10 > b.len(), "{} chunks of {} shared", shared, b.len());
+    }
+}
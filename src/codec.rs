@@ -0,0 +1,1547 @@
+//! Codecs behind common traits
+//!
+//! Each module of the crate has its own encoder and decoder, with their own
+//! parameters. This module wraps them behind the `Compressor` and
+//! `Decompressor` traits, which take the parameters once and then compress
+//! or decompress buffers and streams alike, so that a codec can be chosen at
+//! runtime as a trait object. The codecs of which only the decoder exists
+//! only implement `Decompressor`. The formats of a single block, without
+//! streams, are read whole before being coded.
+//!
+//! The formats with a magic number at their start are also recognized by
+//! `detect`, and `AnyDecoder` decompresses any of them.
+//!
+//! The `_with_progress` variants of the streaming methods report the bytes
+//! consumed and produced so far, and the block being coded by the codecs of
+//! blocks of a fixed size, to a callback, which may abort the coding.
+//!
+//! The codecs whose memory grows with their parameters estimate it, with
+//! `encoder_memory` and `decoder_memory`, and `fit_memory` lowers their block
+//! or window size to keep the encoder within a budget. Those of a fixed,
+//! small memory estimate nothing. In the same way, `compress_bound` gives the
+//! largest compressed size of the codecs which store the data they can't
+//! compress.
+//!
+//! `compress_blocks` and `decompress_blocks` code the items of an iterator
+//! as streams of their own, as the values of a storage engine or the messages
+//! of a queue, for any codec.
+//!
+//! The codecs trading speed for compression map a common `Level` to their
+//! own parameters, with `set_level`, so that the same setting applies to any
+//! of them.
+//!
+//! # Example
+//!
+//! ```rust
+//! use compress::codec::{self, Compressor, Decompressor};
+//!
+//! let codecs: Vec<(Box<dyn Compressor>, Box<dyn Decompressor>)> = vec![
+//!     (Box::new(codec::Gzip { level: 6 }), Box::new(codec::Gzip { level: 6 })),
+//!     (Box::new(codec::Lz4), Box::new(codec::Lz4)),
+//! ];
+//! for &(ref compressor, ref decompressor) in codecs.iter() {
+//!     let compressed = compressor.compress_to_vec(b"abracadabra").unwrap();
+//!     let decompressed = decompressor.decompress_to_vec(&compressed).unwrap();
+//!     assert_eq!(&decompressed[..], b"abracadabra");
+//! }
+//! ```
+//!
+//! ```rust
+//! use std::io::Read;
+//! use compress::codec::{self, AnyDecoder, Compressor};
+//!
+//! let compressed = codec::Gzip { level: 6 }.compress_to_vec(b"abracadabra").unwrap();
+//! assert_eq!(codec::detect(&compressed), Some(codec::Format::Gzip));
+//! let mut decompressed = Vec::new();
+//! AnyDecoder::new(&compressed[..]).unwrap().read_to_end(&mut decompressed).unwrap();
+//! assert_eq!(&decompressed[..], b"abracadabra");
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::io::{self, Read, Write};
+#[cfg(feature="bwt")]
+use std::mem;
+
+use error::{aborted, corrupt, limit_exceeded, unsupported};
+#[cfg(any(feature="chameleon", feature="lzf"))]
+use process::{drain, Process, Reader, Status};
+use OutputLimit;
+
+#[cfg(feature="bpe")]
+use bpe;
+#[cfg(feature="bwt")]
+use bwt;
+#[cfg(feature="bzip2")]
+use bzip2;
+#[cfg(feature="brotli")]
+use brotli;
+#[cfg(feature="chameleon")]
+use chameleon;
+#[cfg(feature="flate")]
+use flate;
+#[cfg(feature="gzip")]
+use gzip;
+#[cfg(feature="lz4")]
+use lz4;
+#[cfg(feature="lz78")]
+use lz78;
+#[cfg(feature="lzf")]
+use lzf;
+#[cfg(feature="lzma")]
+use lzma;
+#[cfg(feature="lzo")]
+use lzo;
+#[cfg(feature="lzp")]
+use lzp;
+#[cfg(feature="lzss")]
+use lzss;
+#[cfg(feature="lzw")]
+use lzw;
+#[cfg(feature="ppmd")]
+use ppmd;
+#[cfg(feature="rle")]
+use rle;
+#[cfg(feature="rolz")]
+use rolz;
+#[cfg(feature="snappy")]
+use snappy;
+#[cfg(feature="sparse")]
+use sparse;
+#[cfg(feature="zlib")]
+use zlib;
+#[cfg(feature="zstd")]
+use zstd;
+
+/// A codec compressing data
+pub trait Compressor {
+    /// Compresses all the data of `input` into `output`, returning the number
+    /// of bytes read
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64>;
+
+    /// Compresses as `compress_stream`, giving the progress to `progress` as
+    /// the data goes through. The compression is aborted with
+    /// `Error::Aborted` once it returns false.
+    fn compress_stream_with_progress(&self, input: &mut dyn Read, output: &mut dyn Write,
+                                     progress: &mut dyn FnMut(Progress) -> bool) -> io::Result<u64> {
+        let tracker = Tracker::new(progress, self.block_size(), true);
+        self.compress_stream(&mut tracker.reader(input), &mut tracker.writer(output))
+    }
+
+    /// Compresses a buffer, appending the compressed data to `output`
+    fn compress(&self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        self.compress_stream(&mut &input[..], output).map(|_| ())
+    }
+
+    /// Compresses a buffer into a new vector
+    fn compress_to_vec(&self, input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(self.compress_bound(input.len()).unwrap_or(0));
+        try!(self.compress(input, &mut output));
+        Ok(output)
+    }
+
+    /// Returns the largest size of the compressed data of `len` bytes, if
+    /// the codec bounds it, so that the buffers are allocated once
+    fn compress_bound(&self, _len: usize) -> Option<usize> {
+        None
+    }
+
+    /// Estimates the memory used by the encoder, in bytes, if it depends on
+    /// the parameters
+    fn encoder_memory(&self) -> Option<usize> {
+        None
+    }
+
+    /// Lowers the block or the window size so that the encoder uses no more
+    /// than `budget` bytes, failing with `Error::LimitExceeded` if it can't
+    fn fit_memory(&mut self, budget: usize) -> io::Result<()> {
+        match self.encoder_memory() {
+            Some(size) if size > budget => limit_exceeded("the encoder needs more memory than the budget"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the size of the blocks the data is cut into before being
+    /// compressed, if the codec cuts it into blocks of a fixed size
+    fn block_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Sets the parameters of the codec from `level`. The codecs without a
+    /// tradeoff between speed and compression ignore it.
+    fn set_level(&mut self, _level: Level) {
+    }
+}
+
+/// A codec decompressing data
+pub trait Decompressor {
+    /// Wraps a reader of compressed data into a reader of the decompressed
+    /// data
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a>;
+
+    /// Decompresses all the data of `input` into `output`, returning the
+    /// number of bytes written
+    fn decompress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        io::copy(&mut self.decoder(Box::new(input)), output)
+    }
+
+    /// Decompresses as `decompress_stream`, giving the progress to `progress`
+    /// as the data goes through. The decompression is aborted with
+    /// `Error::Aborted` once it returns false.
+    fn decompress_stream_with_progress(&self, input: &mut dyn Read, output: &mut dyn Write,
+                                       progress: &mut dyn FnMut(Progress) -> bool) -> io::Result<u64> {
+        let tracker = Tracker::new(progress, self.block_size(), false);
+        self.decompress_stream(&mut tracker.reader(input), &mut tracker.writer(output))
+    }
+
+    /// Decompresses a buffer, appending the decompressed data to `output`
+    fn decompress(&self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        self.decompress_stream(&mut &input[..], output).map(|_| ())
+    }
+
+    /// Decompresses a buffer into a new vector
+    fn decompress_to_vec(&self, input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        try!(self.decompress(input, &mut output));
+        Ok(output)
+    }
+
+    /// Estimates the memory used by the decoder of the data compressed with
+    /// the same parameters, in bytes, if it depends on them
+    fn decoder_memory(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the size of the blocks of the decompressed data, as the
+    /// encoder of the same parameters cuts it, if they are of a fixed size
+    fn block_size(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// A compression level common to the codecs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    /// The fastest compression of the codec
+    Fastest,
+    /// The usual tradeoff of the codec
+    Default,
+    /// The strongest compression of the codec
+    Best,
+    /// A level in the own scale of the codec, brought within its range
+    Numeric(u32),
+}
+
+impl Default for Level {
+    fn default() -> Level {
+        Level::Default
+    }
+}
+
+impl Level {
+    /// Maps this level onto a scale from `fastest` to `best`, in which the
+    /// usual tradeoff is `default`
+    pub fn scale(&self, fastest: u32, default: u32, best: u32) -> u32 {
+        match *self {
+            Level::Fastest => fastest,
+            Level::Default => default,
+            Level::Best => best,
+            Level::Numeric(n) if fastest <= best => cmp::max(fastest, cmp::min(n, best)),
+            Level::Numeric(n) => cmp::max(best, cmp::min(n, fastest)),
+        }
+    }
+}
+
+/// The progress of a compression or a decompression
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Number of bytes read from the input
+    pub consumed: u64,
+    /// Number of bytes written to the output
+    pub produced: u64,
+    /// Index of the block being coded, from 0, by the codecs of a
+    /// `block_size`. It stays 0 for the others.
+    pub block: u64,
+}
+
+/// Counts the bytes going through the input and the output of a codec, for
+/// the callback
+struct Tracker<'a> {
+    progress: Cell<Progress>,
+    callback: RefCell<&'a mut dyn FnMut(Progress) -> bool>,
+    block_size: Option<usize>,
+    /// Whether the input is the uncompressed side, cut into the blocks
+    compressing: bool,
+}
+
+impl<'a> Tracker<'a> {
+    fn new(callback: &'a mut dyn FnMut(Progress) -> bool, block_size: Option<usize>,
+           compressing: bool) -> Tracker<'a> {
+        Tracker {
+            progress: Cell::new(Progress::default()),
+            callback: RefCell::new(callback),
+            block_size: block_size,
+            compressing: compressing,
+        }
+    }
+
+    fn update(&self, consumed: usize, produced: usize) -> io::Result<()> {
+        let mut progress = self.progress.get();
+        progress.consumed += consumed as u64;
+        progress.produced += produced as u64;
+        if let Some(size) = self.block_size {
+            // the block of the last byte, which is coded once it is complete
+            let data = if self.compressing { progress.consumed } else { progress.produced };
+            progress.block = data.saturating_sub(1) / size as u64;
+        }
+        self.progress.set(progress);
+        if (&mut *self.callback.borrow_mut())(progress) {
+            Ok(())
+        } else {
+            aborted()
+        }
+    }
+
+    fn reader<'b>(&'b self, r: &'b mut dyn Read) -> Tracked<'b, 'a, &'b mut dyn Read> {
+        Tracked { inner: r, tracker: self }
+    }
+
+    fn writer<'b>(&'b self, w: &'b mut dyn Write) -> Tracked<'b, 'a, &'b mut dyn Write> {
+        Tracked { inner: w, tracker: self }
+    }
+}
+
+/// The input or the output of a codec, counted by a tracker
+struct Tracked<'b, 'a: 'b, T> {
+    inner: T,
+    tracker: &'b Tracker<'a>,
+}
+
+impl<'b, 'a, T: Read> Read for Tracked<'b, 'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        try!(self.tracker.update(n, 0));
+        Ok(n)
+    }
+}
+
+impl<'b, 'a, T: Write> Write for Tracked<'b, 'a, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.inner.write(buf));
+        try!(self.tracker.update(0, n));
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Returns the iterator compressing each block of `blocks` as a stream of its
+/// own
+pub fn compress_blocks<'c, C, I>(compressor: &'c C, blocks: I) -> CompressBlocks<'c, C, I::IntoIter>
+    where C: Compressor + ?Sized, I: IntoIterator, I::Item: AsRef<[u8]> {
+    CompressBlocks { compressor: compressor, blocks: blocks.into_iter() }
+}
+
+/// Returns the iterator decompressing each block of `blocks`, as compressed
+/// by `compress_blocks`
+pub fn decompress_blocks<'c, D, I>(decompressor: &'c D, blocks: I) -> DecompressBlocks<'c, D, I::IntoIter>
+    where D: Decompressor + ?Sized, I: IntoIterator, I::Item: AsRef<[u8]> {
+    DecompressBlocks { decompressor: decompressor, blocks: blocks.into_iter() }
+}
+
+/// The compressed blocks of `compress_blocks`
+pub struct CompressBlocks<'c, C: ?Sized + 'c, I> {
+    compressor: &'c C,
+    blocks: I,
+}
+
+impl<'c, C: Compressor + ?Sized, I: Iterator> Iterator for CompressBlocks<'c, C, I> where I::Item: AsRef<[u8]> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        self.blocks.next().map(|block| self.compressor.compress_to_vec(block.as_ref()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.blocks.size_hint()
+    }
+}
+
+/// The decompressed blocks of `decompress_blocks`
+pub struct DecompressBlocks<'c, D: ?Sized + 'c, I> {
+    decompressor: &'c D,
+    blocks: I,
+}
+
+impl<'c, D: Decompressor + ?Sized, I: Iterator> Iterator for DecompressBlocks<'c, D, I> where I::Item: AsRef<[u8]> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        self.blocks.next().map(|block| self.decompressor.decompress_to_vec(block.as_ref()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.blocks.size_hint()
+    }
+}
+
+/// Memory of the deflate encoder: a window of twice 32 kB, the heads and the
+/// links of the hash chains, and the tokens of a block
+#[cfg(any(feature="flate", feature="gzip"))]
+const DEFLATE_ENCODER_MEMORY: usize = 2 * (1 << 15) + 4 * (1 << 15) + 4 * (1 << 15) + 4 * (1 << 14);
+
+/// Memory of the deflate decoder: the history of 32 kB and the block decoded
+#[cfg(any(feature="flate", feature="gzip", feature="zlib"))]
+const DEFLATE_DECODER_MEMORY: usize = (1 << 15) + (1 << 12);
+
+/// Maps `level` to the levels of deflate. The level 0 stores the data, so
+/// the fastest compression is 1, but 0 remains within the scale.
+#[cfg(any(feature="flate", feature="gzip"))]
+fn deflate_level(level: Level) -> u32 {
+    match level {
+        Level::Numeric(n) => cmp::min(n, 9),
+        _ => level.scale(1, 6, 9),
+    }
+}
+
+/// Returns the largest parameter from `current` down to `min` for which the
+/// `memory` of the encoder is within `budget`
+#[cfg(any(feature="bzip2", feature="lzw"))]
+fn fit(current: u8, min: u8, budget: usize, memory: fn(u8) -> usize) -> io::Result<u8> {
+    let mut param = current;
+    while memory(param) > budget {
+        if param <= min {
+            return limit_exceeded("the encoder needs more memory than the budget")
+        }
+        param -= 1;
+    }
+    Ok(param)
+}
+
+/// Copies `input` into an encoder, then finishes it
+#[cfg(any(feature="bpe", feature="bwt", feature="bzip2", feature="flate", feature="gzip", feature="lz4",
+          feature="lz78", feature="lzp", feature="lzss", feature="lzw", feature="rle", feature="rolz",
+          feature="snappy", feature="sparse"))]
+fn encode<W, F>(input: &mut dyn Read, mut encoder: W, finish: F) -> io::Result<u64>
+    where W: Write, F: FnOnce(W) -> io::Result<()>
+{
+    let n = try!(io::copy(input, &mut encoder));
+    try!(finish(encoder));
+    Ok(n)
+}
+
+/// Reads the whole of `input`, then writes its block encoded by `encode`
+#[cfg(any(feature="chameleon", feature="lzf"))]
+fn encode_whole(input: &mut dyn Read, output: &mut dyn Write, encode: fn(&[u8], &mut Vec<u8>)) -> io::Result<u64> {
+    let mut data = Vec::new();
+    try!(input.read_to_end(&mut data));
+    let mut block = Vec::new();
+    encode(&data, &mut block);
+    try!(output.write_all(&block));
+    Ok(data.len() as u64)
+}
+
+/// A decoder of the whole of its input as a block, once the input ends
+#[cfg(any(feature="chameleon", feature="lzf"))]
+struct Whole {
+    input: Vec<u8>,
+    decode: fn(&[u8], &mut Vec<u8>) -> io::Result<()>,
+    output: Vec<u8>,
+    start: usize,
+    finished: bool,
+}
+
+#[cfg(any(feature="chameleon", feature="lzf"))]
+impl Whole {
+    fn new(decode: fn(&[u8], &mut Vec<u8>) -> io::Result<()>) -> Whole {
+        Whole { input: Vec::new(), decode: decode, output: Vec::new(), start: 0, finished: false }
+    }
+}
+
+#[cfg(any(feature="chameleon", feature="lzf"))]
+impl Process for Whole {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        if !self.finished {
+            self.input.extend_from_slice(input);
+            return Ok((input.len(), 0, Status::NeedInput))
+        }
+        let mut produced = 0;
+        let status = if drain(&self.output, &mut self.start, output, &mut produced) {
+            Status::Done
+        } else {
+            Status::NeedOutput
+        };
+        Ok((0, produced, status))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        try!((self.decode)(&self.input, &mut self.output));
+        self.input = Vec::new();
+        self.finished = true;
+        Ok(())
+    }
+}
+
+/// Raw DEFLATE data, from level 0 (no compression) to 9
+#[cfg(feature="flate")]
+pub struct Deflate {
+    /// Compression level
+    pub level: u32,
+}
+
+#[cfg(feature="flate")]
+impl Compressor for Deflate {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, flate::Encoder::new(output, self.level), |e| e.finish().1)
+    }
+
+    fn compress_bound(&self, len: usize) -> Option<usize> {
+        Some(flate::max_compressed_len(len))
+    }
+
+    fn encoder_memory(&self) -> Option<usize> {
+        Some(DEFLATE_ENCODER_MEMORY)
+    }
+
+    fn set_level(&mut self, level: Level) {
+        self.level = deflate_level(level);
+    }
+}
+
+#[cfg(feature="flate")]
+impl Decompressor for Deflate {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        let mut d = flate::Decoder::new(input);
+        d.set_read_ahead(true);
+        Box::new(d)
+    }
+
+    fn decoder_memory(&self) -> Option<usize> {
+        // and the 16 kB read ahead
+        Some(DEFLATE_DECODER_MEMORY + (1 << 14))
+    }
+}
+
+/// The gzip file format, from level 0 (no compression) to 9
+#[cfg(feature="gzip")]
+pub struct Gzip {
+    /// Compression level
+    pub level: u32,
+}
+
+#[cfg(feature="gzip")]
+impl Compressor for Gzip {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, gzip::Encoder::new(output, self.level), |e| e.finish().1)
+    }
+
+    fn compress_bound(&self, len: usize) -> Option<usize> {
+        // the header and the trailer of the member
+        Some(10 + flate::max_compressed_len(len) + 8)
+    }
+
+    fn encoder_memory(&self) -> Option<usize> {
+        Some(DEFLATE_ENCODER_MEMORY)
+    }
+
+    fn set_level(&mut self, level: Level) {
+        self.level = deflate_level(level);
+    }
+}
+
+#[cfg(feature="gzip")]
+impl Decompressor for Gzip {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(gzip::Decoder::new(input))
+    }
+
+    fn decoder_memory(&self) -> Option<usize> {
+        Some(DEFLATE_DECODER_MEMORY)
+    }
+}
+
+/// The zlib format, of which only the decoder exists
+#[cfg(feature="zlib")]
+pub struct Zlib;
+
+#[cfg(feature="zlib")]
+impl Decompressor for Zlib {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(zlib::Decoder::new(input))
+    }
+
+    fn decoder_memory(&self) -> Option<usize> {
+        Some(DEFLATE_DECODER_MEMORY)
+    }
+}
+
+/// The bzip2 file format, with blocks of 100 kB times the level, from 1 to 9
+#[cfg(feature="bzip2")]
+pub struct Bzip2 {
+    /// Compression level
+    pub level: u8,
+}
+
+#[cfg(feature="bzip2")]
+impl Compressor for Bzip2 {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, bzip2::Encoder::new(output, self.level), |e| e.finish().1)
+    }
+
+    fn encoder_memory(&self) -> Option<usize> {
+        Some(bzip2_encoder_memory(self.level))
+    }
+
+    fn fit_memory(&mut self, budget: usize) -> io::Result<()> {
+        self.level = try!(fit(self.level, 1, budget, bzip2_encoder_memory));
+        Ok(())
+    }
+
+    fn block_size(&self) -> Option<usize> {
+        // about, as the runs of 4 bytes or more are shortened first
+        Some(100000 * self.level as usize)
+    }
+
+    fn set_level(&mut self, level: Level) {
+        self.level = level.scale(1, 9, 9) as u8;
+    }
+}
+
+#[cfg(feature="bzip2")]
+impl Decompressor for Bzip2 {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(bzip2::Decoder::new(input))
+    }
+
+    fn decoder_memory(&self) -> Option<usize> {
+        // the inverse BWT links of the block
+        Some(4 * 100000 * self.level as usize)
+    }
+
+    fn block_size(&self) -> Option<usize> {
+        Some(100000 * self.level as usize)
+    }
+}
+
+/// Memory of the bzip2 encoder: the block, its rotations sorted, its BWT and
+/// the MTF symbols
+#[cfg(feature="bzip2")]
+fn bzip2_encoder_memory(level: u8) -> usize {
+    (1 + 4 + 1 + 2) * 100000 * level as usize
+}
+
+/// The blocks of the BWT of the `bwt` module
+#[cfg(feature="bwt")]
+pub struct Bwt {
+    /// Size of the blocks
+    pub block_size: usize,
+}
+
+#[cfg(feature="bwt")]
+impl Compressor for Bwt {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, bwt::Encoder::new(output, self.block_size), |e| e.finish().1)
+    }
+
+    fn encoder_memory(&self) -> Option<usize> {
+        // the block and its suffix array
+        Some(self.block_size * (1 + mem::size_of::<usize>()))
+    }
+
+    fn fit_memory(&mut self, budget: usize) -> io::Result<()> {
+        let most = budget / (1 + mem::size_of::<usize>());
+        if most == 0 {
+            return limit_exceeded("the encoder needs more memory than the budget")
+        }
+        if most < self.block_size {
+            self.block_size = most;
+        }
+        Ok(())
+    }
+
+    fn block_size(&self) -> Option<usize> {
+        Some(self.block_size)
+    }
+
+    fn set_level(&mut self, level: Level) {
+        // blocks from 1 to 9 MB
+        self.block_size = (level.scale(1, 4, 9) as usize) << 20;
+    }
+}
+
+#[cfg(feature="bwt")]
+impl Decompressor for Bwt {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(bwt::Decoder::new(input, true))
+    }
+
+    fn decoder_memory(&self) -> Option<usize> {
+        // the block, its inverse and the table of the extra memory
+        Some(self.block_size * (2 + mem::size_of::<usize>()))
+    }
+
+    fn block_size(&self) -> Option<usize> {
+        Some(self.block_size)
+    }
+}
+
+/// The LZ4 frame format
+#[cfg(feature="lz4")]
+pub struct Lz4;
+
+#[cfg(feature="lz4")]
+impl Compressor for Lz4 {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, lz4::Encoder::new(output), |e| e.finish().1)
+    }
+
+    fn compress_bound(&self, len: usize) -> Option<usize> {
+        // the header, the blocks of 256 kB with their size, stored as is at
+        // worst, and the end mark
+        Some(7 + len + 4 * ((len + (256 << 10) - 1) >> 18) + 8)
+    }
+
+    fn encoder_memory(&self) -> Option<usize> {
+        // a block of 256 kB, and its compressed form
+        Some(2 * (256 << 10))
+    }
+
+    fn block_size(&self) -> Option<usize> {
+        Some(256 << 10)
+    }
+}
+
+#[cfg(feature="lz4")]
+impl Decompressor for Lz4 {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(lz4::Decoder::new(input))
+    }
+
+    fn decoder_memory(&self) -> Option<usize> {
+        // a block of 256 kB compressed and decompressed, as the encoder makes
+        Some(2 * (256 << 10))
+    }
+
+    fn block_size(&self) -> Option<usize> {
+        Some(256 << 10)
+    }
+}
+
+/// The legacy .lzma format, of which only the decoder exists
+#[cfg(feature="lzma")]
+pub struct Lzma;
+
+#[cfg(feature="lzma")]
+impl Decompressor for Lzma {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(lzma::Decoder::new(input))
+    }
+}
+
+/// The .xz format, of which only the decoder exists
+#[cfg(feature="lzma")]
+pub struct Xz;
+
+#[cfg(feature="lzma")]
+impl Decompressor for Xz {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(lzma::xz::Decoder::new(input))
+    }
+}
+
+/// The brotli format, of which only the decoder exists
+#[cfg(feature="brotli")]
+pub struct Brotli;
+
+#[cfg(feature="brotli")]
+impl Decompressor for Brotli {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(brotli::Decoder::new(input))
+    }
+}
+
+/// The zstd format, of which only the decoder exists
+#[cfg(feature="zstd")]
+pub struct Zstd;
+
+#[cfg(feature="zstd")]
+impl Decompressor for Zstd {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(zstd::Decoder::new(input))
+    }
+}
+
+/// The framing format of snappy
+#[cfg(feature="snappy")]
+pub struct Snappy;
+
+#[cfg(feature="snappy")]
+impl Compressor for Snappy {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, snappy::Encoder::new(output), |e| e.finish().1)
+    }
+
+    fn compress_bound(&self, len: usize) -> Option<usize> {
+        // the stream identifier, then the chunks of 64 kB with their header
+        // and checksum, stored as is at worst
+        Some(10 + len + 8 * ((len + (64 << 10) - 1) >> 16))
+    }
+
+    fn encoder_memory(&self) -> Option<usize> {
+        // a chunk of 64 kB, and its compressed form
+        Some(2 * (64 << 10))
+    }
+
+    fn block_size(&self) -> Option<usize> {
+        Some(64 << 10)
+    }
+}
+
+#[cfg(feature="snappy")]
+impl Decompressor for Snappy {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(snappy::Decoder::new(input))
+    }
+
+    fn decoder_memory(&self) -> Option<usize> {
+        Some(2 * (64 << 10))
+    }
+
+    fn block_size(&self) -> Option<usize> {
+        Some(64 << 10)
+    }
+}
+
+/// Run-length encoding with the given scheme
+#[cfg(feature="rle")]
+pub struct Rle {
+    /// Coding of the runs
+    pub scheme: rle::Scheme,
+}
+
+#[cfg(feature="rle")]
+impl Compressor for Rle {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, rle::Encoder::with_scheme(output, self.scheme), |e| e.finish().1)
+    }
+}
+
+#[cfg(feature="rle")]
+impl Decompressor for Rle {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(rle::Decoder::with_scheme(input, self.scheme))
+    }
+}
+
+/// The .lzo files of lzop, of which only the decoder exists
+#[cfg(feature="lzo")]
+pub struct Lzop;
+
+#[cfg(feature="lzo")]
+impl Decompressor for Lzop {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(lzo::Decoder::new(input))
+    }
+}
+
+/// The .Z files of compress, with codes from 9 to 16 bits wide
+#[cfg(feature="lzw")]
+pub struct Z {
+    /// Size of the largest codes
+    pub max_bits: u8,
+}
+
+#[cfg(feature="lzw")]
+impl Compressor for Z {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, lzw::z::Encoder::new(output, self.max_bits), |e| e.finish().1)
+    }
+
+    fn encoder_memory(&self) -> Option<usize> {
+        Some(z_encoder_memory(self.max_bits))
+    }
+
+    fn fit_memory(&mut self, budget: usize) -> io::Result<()> {
+        self.max_bits = try!(fit(self.max_bits, 9, budget, z_encoder_memory));
+        Ok(())
+    }
+
+    fn set_level(&mut self, level: Level) {
+        self.max_bits = level.scale(9, 16, 16) as u8;
+    }
+}
+
+#[cfg(feature="lzw")]
+impl Decompressor for Z {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(lzw::z::Decoder::new(input))
+    }
+
+    fn decoder_memory(&self) -> Option<usize> {
+        // an entry of 8 bytes per code
+        Some(8 << self.max_bits)
+    }
+}
+
+/// Memory of the .Z encoder: a hash table of twice as many keys and codes as
+/// the codes
+#[cfg(feature="lzw")]
+fn z_encoder_memory(max_bits: u8) -> usize {
+    2 * (4 + 2) << max_bits
+}
+
+/// Byte pair encoding
+#[cfg(feature="bpe")]
+pub struct Bpe;
+
+#[cfg(feature="bpe")]
+impl Compressor for Bpe {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, bpe::Encoder::new(output), |e| e.finish().1)
+    }
+}
+
+#[cfg(feature="bpe")]
+impl Decompressor for Bpe {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(bpe::Decoder::new(input))
+    }
+}
+
+/// A single Chameleon block
+#[cfg(feature="chameleon")]
+pub struct Chameleon;
+
+#[cfg(feature="chameleon")]
+impl Compressor for Chameleon {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode_whole(input, output, chameleon::encode_block)
+    }
+
+    fn compress_bound(&self, len: usize) -> Option<usize> {
+        Some(chameleon::max_compressed_len(len))
+    }
+}
+
+#[cfg(feature="chameleon")]
+impl Decompressor for Chameleon {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(Reader::new(input, Whole::new(chameleon::decode_block)))
+    }
+}
+
+/// A single LZF block
+#[cfg(feature="lzf")]
+pub struct Lzf;
+
+#[cfg(feature="lzf")]
+impl Compressor for Lzf {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode_whole(input, output, lzf::encode_block)
+    }
+
+    fn compress_bound(&self, len: usize) -> Option<usize> {
+        Some(lzf::max_compressed_len(len))
+    }
+}
+
+#[cfg(feature="lzf")]
+impl Decompressor for Lzf {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(Reader::new(input, Whole::new(|input, output| lzf::decode_block(input, output, usize::MAX))))
+    }
+}
+
+/// LZ78, with codes up to the given size
+#[cfg(feature="lz78")]
+pub struct Lz78 {
+    /// Size of the largest codes
+    pub max_bits: u8,
+}
+
+#[cfg(feature="lz78")]
+impl Compressor for Lz78 {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, lz78::Encoder::new(output, self.max_bits), |e| e.finish().1)
+    }
+}
+
+#[cfg(feature="lz78")]
+impl Decompressor for Lz78 {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(lz78::Decoder::new(input, self.max_bits))
+    }
+}
+
+/// LZP, predicting matches of at least the given length
+#[cfg(feature="lzp")]
+pub struct Lzp {
+    /// Shortest match
+    pub min_len: usize,
+}
+
+#[cfg(feature="lzp")]
+impl Compressor for Lzp {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, lzp::Encoder::new(output, self.min_len), |e| e.finish().1)
+    }
+}
+
+#[cfg(feature="lzp")]
+impl Decompressor for Lzp {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(lzp::Decoder::new(input))
+    }
+}
+
+/// LZSS in the given format
+#[cfg(feature="lzss")]
+pub struct Lzss {
+    /// Parameters of the format
+    pub config: lzss::Config,
+}
+
+#[cfg(feature="lzss")]
+impl Compressor for Lzss {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, lzss::Encoder::new(output, self.config), |e| e.finish().1)
+    }
+}
+
+#[cfg(feature="lzss")]
+impl Decompressor for Lzss {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(lzss::Decoder::new(input, self.config))
+    }
+}
+
+/// LZW in the given variant
+#[cfg(feature="lzw")]
+pub struct Lzw {
+    /// Parameters of the variant
+    pub config: lzw::Config,
+}
+
+#[cfg(feature="lzw")]
+impl Compressor for Lzw {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, lzw::Encoder::new(output, self.config), |e| e.finish().1)
+    }
+}
+
+#[cfg(feature="lzw")]
+impl Decompressor for Lzw {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(lzw::Decoder::new(input, self.config))
+    }
+}
+
+/// ROLZ, with its stream of blocks
+#[cfg(feature="rolz")]
+pub struct Rolz;
+
+#[cfg(feature="rolz")]
+impl Compressor for Rolz {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, rolz::Encoder::new(output), |e| e.finish().1)
+    }
+
+    fn block_size(&self) -> Option<usize> {
+        Some(rolz::BLOCK_SIZE)
+    }
+}
+
+#[cfg(feature="rolz")]
+impl Decompressor for Rolz {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(rolz::Decoder::new(input))
+    }
+
+    fn block_size(&self) -> Option<usize> {
+        Some(rolz::BLOCK_SIZE)
+    }
+}
+
+/// Sparse data, of which the runs of zeros of at least the given length are
+/// holes
+#[cfg(feature="sparse")]
+pub struct Sparse {
+    /// Shortest hole
+    pub min_run: usize,
+}
+
+#[cfg(feature="sparse")]
+impl Compressor for Sparse {
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+        encode(input, sparse::Encoder::new(output, self.min_run), |e| e.finish().1)
+    }
+}
+
+#[cfg(feature="sparse")]
+impl Decompressor for Sparse {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(sparse::Decoder::new(input))
+    }
+}
+
+/// A PPMd stream of the given parameters, of which only the decoder exists
+#[cfg(feature="ppmd")]
+pub struct Ppmd {
+    /// Parameters of the stream
+    pub props: ppmd::Properties,
+    /// Size of the decompressed data, when the stream has no end marker
+    pub size: Option<u64>,
+}
+
+#[cfg(feature="ppmd")]
+impl Decompressor for Ppmd {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(ppmd::Decoder::new(input, self.props, self.size))
+    }
+}
+
+/// The formats recognized by their first bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The gzip file format
+    Gzip,
+    /// The zlib format
+    Zlib,
+    /// The bzip2 file format
+    Bzip2,
+    /// The LZ4 frame format
+    Lz4,
+    /// The .xz format
+    Xz,
+    /// The zstd format, also starting with a skippable frame
+    Zstd,
+    /// The framing format of snappy
+    Snappy,
+    /// The .lzo files of lzop
+    Lzop,
+    /// The .Z files of compress
+    Z,
+}
+
+/// Number of bytes needed to detect any format
+pub const HEADER_SIZE: usize = 10;
+
+/// The magic numbers at the start of the formats
+const MAGICS: [(&'static [u8], Format); 8] = [
+    (&[0x1f, 0x8b], Format::Gzip),
+    (&[0x1f, 0x9d], Format::Z),
+    (b"BZh", Format::Bzip2),
+    (&[0x04, 0x22, 0x4d, 0x18], Format::Lz4),
+    (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], Format::Xz),
+    (&[0x28, 0xb5, 0x2f, 0xfd], Format::Zstd),
+    (b"\xff\x06\x00\x00sNaPpY", Format::Snappy),
+    (b"\x89LZO\x00\r\n\x1a\n", Format::Lzop),
+];
+
+/// Returns the format of which `header` is the start, as many bytes as
+/// available up to `HEADER_SIZE`, if any
+pub fn detect(header: &[u8]) -> Option<Format> {
+    if let Some(&(_, format)) = MAGICS.iter().find(|&&(magic, _)| header.starts_with(magic)) {
+        return Some(format)
+    }
+    // the skippable frames of zstd, as those of LZ4 which no decoder skips
+    if header.len() >= 4 && header[0] & 0xf0 == 0x50 && header[1..4] == [0x2a, 0x4d, 0x18] {
+        return Some(Format::Zstd)
+    }
+    // zlib has no magic, but a deflate method with a window of up to 32 kB,
+    // and a check of its first 2 bytes
+    if header.len() >= 2 && header[0] & 0x0f == 8 && header[0] >> 4 <= 7 &&
+        (header[0] as u16 * 256 + header[1] as u16) % 31 == 0 {
+        return Some(Format::Zlib)
+    }
+    None
+}
+
+impl Format {
+    /// Returns the decompressor of this format, unless its feature is disabled
+    #[allow(unreachable_patterns)]
+    pub fn decompressor(&self) -> Option<Box<dyn Decompressor>> {
+        match *self {
+            #[cfg(feature="gzip")]
+            Format::Gzip => Some(Box::new(Gzip { level: 6 })),
+            #[cfg(feature="zlib")]
+            Format::Zlib => Some(Box::new(Zlib)),
+            #[cfg(feature="bzip2")]
+            Format::Bzip2 => Some(Box::new(Bzip2 { level: 9 })),
+            #[cfg(feature="lz4")]
+            Format::Lz4 => Some(Box::new(Lz4)),
+            #[cfg(feature="lzma")]
+            Format::Xz => Some(Box::new(Xz)),
+            #[cfg(feature="zstd")]
+            Format::Zstd => Some(Box::new(Zstd)),
+            #[cfg(feature="snappy")]
+            Format::Snappy => Some(Box::new(Snappy)),
+            #[cfg(feature="lzo")]
+            Format::Lzop => Some(Box::new(Lzop)),
+            #[cfg(feature="lzw")]
+            Format::Z => Some(Box::new(Z { max_bits: 16 })),
+            _ => None,
+        }
+    }
+}
+
+/// A reader of the data decompressed from any of the detected formats
+pub struct AnyDecoder<'a> {
+    format: Format,
+    inner: Box<dyn Read + 'a>,
+    limit: OutputLimit,
+}
+
+impl<'a> AnyDecoder<'a> {
+    /// Creates a decoder of the data read from `r`, of which the first bytes
+    /// are read to detect the format. This fails when they match no format,
+    /// or one of which the feature is disabled.
+    pub fn new<R: Read + 'a>(mut r: R) -> io::Result<AnyDecoder<'a>> {
+        let mut header = vec![0; HEADER_SIZE];
+        let mut len = 0;
+        while len < HEADER_SIZE {
+            match r.read(&mut header[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        header.truncate(len);
+        let format = match detect(&header) {
+            Some(format) => format,
+            None => return corrupt("unknown compressed format"),
+        };
+        let decompressor = match format.decompressor() {
+            Some(decompressor) => decompressor,
+            None => return unsupported("unsupported compressed format"),
+        };
+        // the header is read again by the decoder
+        let inner = decompressor.decoder(Box::new(io::Cursor::new(header).chain(r)));
+        Ok(AnyDecoder { format: format, inner: inner, limit: OutputLimit::new() })
+    }
+
+    /// Returns the detected format
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.limit.set(size);
+    }
+}
+
+impl<'a> Read for AnyDecoder<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.limit.clamp(buf.len());
+        let n = try!(self.inner.read(&mut buf[..len]));
+        self.limit.count(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[allow(unused_imports)]
+    use std::io::{BufReader, Read, Write};
+    use error::Error;
+    use super::{compress_blocks, decompress_blocks, detect, AnyDecoder, Compressor, Decompressor, Format, Level,
+                Progress};
+
+    /// The codecs of which the encoder exists, as enabled
+    #[allow(unused_mut)]
+    fn codecs() -> Vec<(Box<dyn Compressor>, Box<dyn Decompressor>)> {
+        let mut codecs: Vec<(Box<dyn Compressor>, Box<dyn Decompressor>)> = Vec::new();
+        #[cfg(feature="flate")]
+        codecs.push((Box::new(super::Deflate { level: 6 }), Box::new(super::Deflate { level: 6 })));
+        #[cfg(feature="gzip")]
+        codecs.push((Box::new(super::Gzip { level: 9 }), Box::new(super::Gzip { level: 9 })));
+        #[cfg(feature="bzip2")]
+        codecs.push((Box::new(super::Bzip2 { level: 1 }), Box::new(super::Bzip2 { level: 1 })));
+        #[cfg(feature="bwt")]
+        codecs.push((Box::new(super::Bwt { block_size: 1000 }), Box::new(super::Bwt { block_size: 1000 })));
+        #[cfg(feature="lz4")]
+        codecs.push((Box::new(super::Lz4), Box::new(super::Lz4)));
+        #[cfg(feature="snappy")]
+        codecs.push((Box::new(super::Snappy), Box::new(super::Snappy)));
+        #[cfg(feature="lzw")]
+        codecs.push((Box::new(super::Z { max_bits: 12 }), Box::new(super::Z { max_bits: 12 })));
+        #[cfg(feature="lzw")]
+        {
+            use lzw::Config;
+            codecs.push((Box::new(super::Lzw { config: Config::gif(8) }), Box::new(super::Lzw { config: Config::gif(8) })));
+        }
+        #[cfg(feature="bpe")]
+        codecs.push((Box::new(super::Bpe), Box::new(super::Bpe)));
+        #[cfg(feature="chameleon")]
+        codecs.push((Box::new(super::Chameleon), Box::new(super::Chameleon)));
+        #[cfg(feature="lzf")]
+        codecs.push((Box::new(super::Lzf), Box::new(super::Lzf)));
+        #[cfg(feature="lz78")]
+        codecs.push((Box::new(super::Lz78 { max_bits: 12 }), Box::new(super::Lz78 { max_bits: 12 })));
+        #[cfg(feature="lzp")]
+        codecs.push((Box::new(super::Lzp { min_len: 8 }), Box::new(super::Lzp { min_len: 8 })));
+        #[cfg(feature="lzss")]
+        {
+            use lzss::Config;
+            codecs.push((Box::new(super::Lzss { config: Config::default() }),
+                         Box::new(super::Lzss { config: Config::default() })));
+        }
+        #[cfg(feature="rolz")]
+        codecs.push((Box::new(super::Rolz), Box::new(super::Rolz)));
+        #[cfg(feature="sparse")]
+        codecs.push((Box::new(super::Sparse { min_run: 16 }), Box::new(super::Sparse { min_run: 16 })));
+        #[cfg(feature="rle")]
+        {
+            use rle::Scheme;
+            codecs.push((Box::new(super::Rle { scheme: Scheme::PackBits }),
+                         Box::new(super::Rle { scheme: Scheme::PackBits })));
+        }
+        codecs
+    }
+
+    #[test]
+    #[cfg(feature="lzw")]
+    fn memory_budget() {
+        let mut z = super::Z { max_bits: 16 };
+        let most = z.encoder_memory().unwrap();
+        z.fit_memory(most).unwrap();
+        assert_eq!(z.max_bits, 16);
+        z.fit_memory(most / 2).unwrap();
+        assert_eq!(z.max_bits, 15);
+        assert!(z.fit_memory(1000).is_err());
+        assert_eq!(z.max_bits, 15);
+
+        let data = &include_bytes!("data/test.txt")[..];
+        let compressed = z.compress_to_vec(data).unwrap();
+        assert!(&z.decompress_to_vec(&compressed).unwrap()[..] == data);
+    }
+
+    #[test]
+    fn bound() {
+        // text, and noise which doesn't compress
+        let mut noise = Vec::with_capacity(300000);
+        let mut x = 0x2545f491u32;
+        for _ in 0..noise.capacity() {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            noise.push(x as u8);
+        }
+        let text = &include_bytes!("data/test.txt")[..];
+        #[cfg(feature="flate")]
+        let codecs = {
+            // the stored blocks, at the bound
+            let mut codecs = codecs();
+            codecs.push((Box::new(super::Deflate { level: 0 }), Box::new(super::Deflate { level: 0 })));
+            codecs
+        };
+        #[cfg(not(feature="flate"))]
+        let codecs = codecs();
+        for &(ref compressor, _) in codecs.iter() {
+            for data in [&noise[..], &noise[..1000], text, &[]].iter() {
+                if let Some(bound) = compressor.compress_bound(data.len()) {
+                    assert!(compressor.compress_to_vec(data).unwrap().len() <= bound);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blocks() {
+        let data = &include_bytes!("data/test.txt")[..];
+        for &(ref compressor, ref decompressor) in codecs().iter() {
+            let compressed: Vec<Vec<u8>> = compress_blocks(&**compressor, data.chunks(1000))
+                .collect::<Result<_, _>>().unwrap();
+            assert_eq!(compressed.len(), 4);
+            let decompressed: Vec<Vec<u8>> = decompress_blocks(&**decompressor, &compressed)
+                .collect::<Result<_, _>>().unwrap();
+            assert!(decompressed.concat() == data);
+        }
+
+        #[cfg(feature="lz4")]
+        {
+            let mut compressed: Vec<Vec<u8>> = compress_blocks(&super::Lz4, data.chunks(1000))
+                .collect::<Result<_, _>>().unwrap();
+            compressed[1].truncate(20);
+            let mut blocks = decompress_blocks(&super::Lz4, compressed);
+            assert!(blocks.next().unwrap().is_ok());
+            assert!(blocks.next().unwrap().is_err());
+            assert!(blocks.next().unwrap().is_ok());
+        }
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(Level::Fastest.scale(1, 6, 9), 1);
+        assert_eq!(Level::default().scale(1, 6, 9), 6);
+        assert_eq!(Level::Best.scale(1, 6, 9), 9);
+        assert_eq!(Level::Numeric(3).scale(1, 6, 9), 3);
+        assert_eq!(Level::Numeric(0).scale(1, 6, 9), 1);
+        assert_eq!(Level::Numeric(20).scale(1, 6, 9), 9);
+        assert_eq!(Level::Numeric(20).scale(16, 8, 4), 16);
+
+        let data = &include_bytes!("data/test.txt")[..];
+        for &level in [Level::Fastest, Level::Default, Level::Best, Level::Numeric(100)].iter() {
+            for (mut compressor, decompressor) in codecs().into_iter() {
+                compressor.set_level(level);
+                let compressed = compressor.compress_to_vec(data).unwrap();
+                assert!(&decompressor.decompress_to_vec(&compressed).unwrap()[..] == data);
+            }
+        }
+    }
+
+    #[test]
+    fn progress() {
+        let data = &include_bytes!("data/test.txt")[..];
+        for &(ref compressor, ref decompressor) in codecs().iter() {
+            let mut last = Progress::default();
+            let mut compressed = Vec::new();
+            compressor.compress_stream_with_progress(&mut BufReader::with_capacity(100, data), &mut compressed,
+                                                     &mut |p| { assert!(p.consumed >= last.consumed); last = p; true }).unwrap();
+            assert_eq!((last.consumed, last.produced), (data.len() as u64, compressed.len() as u64));
+            let blocks = compressor.block_size().map_or(1, |size| (data.len() + size - 1) / size);
+            assert_eq!(last.block, blocks as u64 - 1);
+
+            let mut decompressed = Vec::new();
+            decompressor.decompress_stream_with_progress(&mut &compressed[..], &mut decompressed,
+                                                         &mut |p| { last = p; true }).unwrap();
+            assert_eq!((last.consumed, last.produced), (compressed.len() as u64, data.len() as u64));
+            assert_eq!(last.block, blocks as u64 - 1);
+
+            let err = compressor.compress_stream_with_progress(&mut BufReader::with_capacity(100, data), &mut Vec::new(),
+                                                               &mut |p| p.consumed < 1000).unwrap_err();
+            assert_eq!(Error::of(&err), Some(Error::Aborted));
+        }
+    }
+
+    #[cfg(feature="bwt")]
+    #[test]
+    fn progress_blocks() {
+        /// Reads 100 bytes at most at a time
+        struct Trickle<'a>(&'a [u8]);
+
+        impl<'a> Read for Trickle<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+                let n = ::std::cmp::min(buf.len(), 100);
+                self.0.read(&mut buf[..n])
+            }
+        }
+
+        let data = &include_bytes!("data/test.txt")[..];
+        let codec = super::Bwt { block_size: 1000 };
+        let mut blocks = Vec::new();
+        let mut compressed = Vec::new();
+        codec.compress_stream_with_progress(&mut Trickle(data), &mut compressed,
+                                            &mut |p| { blocks.push(p.block); true }).unwrap();
+        blocks.dedup();
+        assert_eq!(blocks, [0, 1, 2, 3]);
+
+        let mut first = None;
+        let err = codec.decompress_stream_with_progress(&mut &compressed[..], &mut Vec::new(), &mut |p| {
+            first = first.or(Some(p));
+            p.block < 2
+        }).unwrap_err();
+        assert_eq!(Error::of(&err), Some(Error::Aborted));
+        assert_eq!(first.map(|p| p.block), Some(0));
+    }
+
+    #[test]
+    fn roundtrips() {
+        let data = &include_bytes!("data/test.txt")[..];
+        for &(ref compressor, ref decompressor) in codecs().iter() {
+            let mut compressed = Vec::new();
+            compressor.compress(data, &mut compressed).unwrap();
+            let mut decompressed = Vec::new();
+            decompressor.decompress(&compressed, &mut decompressed).unwrap();
+            assert!(&decompressed[..] == data);
+            assert!(compressor.compress_to_vec(data).unwrap() == compressed);
+            assert!(&decompressor.decompress_to_vec(&compressed).unwrap()[..] == data);
+
+            let mut streamed = Vec::new();
+            let n = compressor.compress_stream(&mut BufReader::with_capacity(7, data), &mut streamed).unwrap();
+            assert_eq!(n, data.len() as u64);
+            assert!(streamed == compressed);
+            let mut decoder = decompressor.decoder(Box::new(&streamed[..]));
+            let mut decompressed = Vec::new();
+            decoder.read_to_end(&mut decompressed).unwrap();
+            assert!(&decompressed[..] == data);
+
+            let empty = compressor.compress_to_vec(b"").unwrap();
+            assert!(decompressor.decompress_to_vec(&empty).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    #[allow(unused_mut)]
+    fn decoders() {
+        let mut files: Vec<(Box<dyn Decompressor>, &[u8])> = Vec::new();
+        #[cfg(feature="zlib")]
+        files.push((Box::new(super::Zlib), include_bytes!("data/test.z.5")));
+        #[cfg(feature="lzma")]
+        files.push((Box::new(super::Lzma), include_bytes!("data/test.lzma")));
+        #[cfg(feature="lzma")]
+        files.push((Box::new(super::Xz), include_bytes!("data/test.xz")));
+        #[cfg(feature="brotli")]
+        files.push((Box::new(super::Brotli), include_bytes!("data/test.br")));
+        #[cfg(feature="zstd")]
+        files.push((Box::new(super::Zstd), include_bytes!("data/test.zst")));
+        #[cfg(feature="lzo")]
+        files.push((Box::new(super::Lzop), include_bytes!("data/test.lzo")));
+        #[cfg(feature="ppmd")]
+        {
+            use ppmd::Properties;
+            let props = Properties::from_7z(&[6, 0, 0, 0, 1]).unwrap();
+            files.push((Box::new(super::Ppmd { props: props, size: Some(3050) }), include_bytes!("data/test.ppmd")));
+        }
+        for &(ref decompressor, file) in files.iter() {
+            let decompressed = decompressor.decompress_to_vec(file).unwrap();
+            assert!(&decompressed[..] == &include_bytes!("data/test.txt")[..]);
+        }
+    }
+
+    #[test]
+    fn detected() {
+        let files: [(&[u8], Format); 8] = [
+            (include_bytes!("data/test.gz.1"), Format::Gzip),
+            (include_bytes!("data/test.z.0"), Format::Zlib),
+            (include_bytes!("data/test.z.9"), Format::Zlib),
+            (include_bytes!("data/test.bz2"), Format::Bzip2),
+            (include_bytes!("data/test.lz4.1"), Format::Lz4),
+            (include_bytes!("data/test.xz"), Format::Xz),
+            (include_bytes!("data/test.zst"), Format::Zstd),
+            (include_bytes!("data/test.lzo"), Format::Lzop),
+        ];
+        for &(file, format) in files.iter() {
+            assert_eq!(detect(file), Some(format));
+            let mut decoder = match AnyDecoder::new(BufReader::with_capacity(3, file)) {
+                Ok(decoder) => decoder,
+                // the feature of the format is disabled
+                Err(_) => { assert!(format.decompressor().is_none()); continue },
+            };
+            assert_eq!(decoder.format(), format);
+            let mut decompressed = Vec::new();
+            decoder.read_to_end(&mut decompressed).unwrap();
+            assert!(&decompressed[..] == &include_bytes!("data/test.txt")[..]);
+        }
+        // with a dictionary, and of another text
+        assert_eq!(detect(include_bytes!("data/test.prefix.zst")), Some(Format::Zstd));
+        assert_eq!(detect(include_bytes!("data/test.Z")), Some(Format::Z));
+        assert_eq!(detect(b"\xff\x06\x00\x00sNaPpY\x01"), Some(Format::Snappy));
+        assert_eq!(detect(b"\x5a\x2a\x4d\x18"), Some(Format::Zstd));
+    }
+
+    #[test]
+    fn undetected() {
+        assert_eq!(detect(b""), None);
+        assert_eq!(detect(b"\x1f"), None);
+        assert_eq!(detect(b"BZ"), None);
+        assert_eq!(detect(&include_bytes!("data/test.txt")[..]), None);
+        assert_eq!(detect(include_bytes!("data/test.br")), None);
+        assert!(AnyDecoder::new(&include_bytes!("data/test.txt")[..]).is_err());
+        assert!(AnyDecoder::new(&b""[..]).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+/*!
+
+Comparison of codecs on the same data. Requires `threads` feature
+
+`Compare` is a writer handing the data written to it to several codecs of
+the `codec` module at once, each on a thread of its own, so that the data is
+read a single time. Once it is finished, it reports the size of the output of
+each codec and the time it took, without the time it spent waiting for the
+data, to pick the best codec for the data at hand. The compressed data itself
+is dropped.
+
+# Example
+
+```rust
+use std::io::Write;
+use compress::codec;
+use compress::compare::Compare;
+
+let data: Vec<u8> = (0..100000).map(|i| (i / 100) as u8).collect();
+let mut c = Compare::new()
+    .codec("lz4", codec::Lz4)
+    .codec("lz4 again", codec::Lz4);
+c.write_all(&data).unwrap();
+for report in c.finish().unwrap() {
+    assert_eq!(report.input, data.len() as u64);
+    println!("{}: {} bytes in {:?}", report.name, report.output, report.time);
+}
+```
+
+*/
+
+use std::io::{self, Read, Write};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use codec::Compressor;
+use ring::{ring, Consumer, Producer};
+
+/// Number of pieces of data waiting for a codec before the writes block
+const QUEUE: usize = 16;
+
+/// The results of a codec
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    /// Name given to the codec
+    pub name: String,
+    /// Number of bytes compressed
+    pub input: u64,
+    /// Size of the compressed data
+    pub output: u64,
+    /// Time spent compressing
+    pub time: Duration,
+}
+
+impl Report {
+    /// Returns the size of the compressed data relative to the input
+    pub fn ratio(&self) -> f64 {
+        if self.input == 0 {
+            return 1.0
+        }
+        self.output as f64 / self.input as f64
+    }
+}
+
+/// The reader of the data sent to a codec, timing how long it waits for it
+struct Feed {
+    data: Consumer<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+    input: u64,
+    waited: Duration,
+}
+
+impl Read for Feed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            let start = Instant::now();
+            let next = self.data.pop();
+            self.waited += start.elapsed();
+            match next {
+                Some(data) => {
+                    self.buf = data;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = (&self.buf[self.pos..]).read(buf).unwrap();
+        self.pos += n;
+        self.input += n as u64;
+        Ok(n)
+    }
+}
+
+/// A writer counting the bytes written
+struct Count(u64);
+
+impl Write for Count {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Worker {
+    name: String,
+    data: Option<Producer<Vec<u8>>>,
+    thread: JoinHandle<io::Result<(u64, u64, Duration)>>,
+}
+
+/// A writer compressing the data with several codecs, to compare them
+pub struct Compare {
+    workers: Vec<Worker>,
+}
+
+impl Default for Compare {
+    fn default() -> Compare {
+        Compare::new()
+    }
+}
+
+impl Compare {
+    /// Creates a comparison of no codecs yet
+    pub fn new() -> Compare {
+        Compare { workers: Vec::new() }
+    }
+
+    /// Adds a codec to the comparison, under the given name, which is given
+    /// the data written from now on
+    pub fn codec<C: Compressor + Send + 'static>(mut self, name: &str, compressor: C) -> Compare {
+        let (tx, rx) = ring(QUEUE);
+        let thread = thread::spawn(move || {
+            let mut feed = Feed { data: rx, buf: Vec::new(), pos: 0, input: 0, waited: Duration::from_secs(0) };
+            let mut count = Count(0);
+            let start = Instant::now();
+            try!(compressor.compress_stream(&mut feed, &mut count));
+            let time = start.elapsed();
+            Ok((feed.input, count.0, time - feed.waited))
+        });
+        self.workers.push(Worker { name: name.to_string(), data: Some(tx), thread: thread });
+        self
+    }
+
+    /// Ends the data, and returns the reports of the codecs in the order they
+    /// were added, or the first error of a codec
+    pub fn finish(mut self) -> io::Result<Vec<Report>> {
+        for worker in self.workers.iter_mut() {
+            worker.data = None;
+        }
+        let mut reports = Vec::with_capacity(self.workers.len());
+        for worker in self.workers.into_iter() {
+            let result = try!(worker.thread.join().unwrap_or_else(|_| {
+                Err(io::Error::new(io::ErrorKind::Other, "comparison thread panicked"))
+            }));
+            let (input, output, time) = result;
+            reports.push(Report { name: worker.name, input: input, output: output, time: time });
+        }
+        Ok(reports)
+    }
+}
+
+impl Write for Compare {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for worker in self.workers.iter_mut() {
+            // a codec which stopped on an error tells it at the end
+            if let Some(ref mut data) = worker.data {
+                let _ = data.push(buf.to_vec());
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use codec::Compressor;
+    use super::Compare;
+
+    /// A codec failing once it is given data
+    struct Failing;
+
+    impl Compressor for Failing {
+        fn compress_stream(&self, input: &mut dyn Read, _: &mut dyn Write) -> io::Result<u64> {
+            let mut buf = [0; 1];
+            try!(input.read(&mut buf));
+            Err(io::Error::new(io::ErrorKind::Other, "failing codec"))
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature="flate", feature="lz4"))]
+    fn codecs() {
+        use codec::{Deflate, Lz4};
+        let data = &include_bytes!("data/test.txt")[..];
+        let mut c = Compare::new()
+            .codec("deflate", Deflate { level: 6 })
+            .codec("lz4", Lz4);
+        for piece in data.chunks(100) {
+            c.write_all(piece).unwrap();
+        }
+        let reports = c.finish().unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].name, "deflate");
+        assert_eq!(reports[0].output, Deflate { level: 6 }.compress_to_vec(data).unwrap().len() as u64);
+        assert_eq!(reports[1].output, Lz4.compress_to_vec(data).unwrap().len() as u64);
+        for report in reports.iter() {
+            assert_eq!(report.input, data.len() as u64);
+        }
+        assert!(reports[0].ratio() < reports[1].ratio());
+    }
+
+    #[test]
+    fn failure() {
+        let mut c = Compare::new().codec("failing", Failing);
+        for _ in 0..100 {
+            c.write_all(b"some data").unwrap();
+        }
+        assert!(c.finish().is_err());
+        assert!(Compare::new().finish().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,642 @@
+/*!
+
+Self-describing container of the data of the crate. Requires `container`
+feature
+
+The container records how its data was compressed, so that it is
+decompressed without being told: the pipelines of the `pipeline` module, as
+BWT, MTF, RLE and range coding, or a codec of the crate. The data is cut in
+blocks compressed apart, each with the CRC32 of its data.
+
+The container starts with a magic number as a little-endian 32-bit integer,
+the version of the format, the identifier of the method, the length of its
+options as a little-endian 16-bit integer, the options themselves, and the
+size of the blocks as a little-endian 32-bit integer. Each block is preceded
+by the sizes of its compressed and decompressed data, and by the CRC32 of the
+latter, as little-endian 32-bit integers. A block of no data ends the
+container. The decoders refuse the versions of the format newer than theirs.
+
+# Example
+
+```rust
+use std::io::{Read, Write};
+use compress::container::{Decoder, Encoder, Method};
+use compress::pipeline::{Pipeline, Stage};
+
+let data: Vec<u8> = (0..10000).map(|i| (i / 100) as u8).collect();
+let pipeline = Pipeline::new()
+    .stage(Stage::Bwt { block_size: 4096 })
+    .stage(Stage::Mtf)
+    .stage(Stage::Ari);
+let mut e = Encoder::new(Vec::new(), Method::Pipeline(pipeline.clone()));
+e.write_all(&data).unwrap();
+let (encoded, result) = e.finish();
+result.unwrap();
+
+let mut d = Decoder::new(&encoded[..]).unwrap();
+assert_eq!(d.method(), &Method::Pipeline(pipeline));
+let mut decoded = Vec::new();
+d.read_to_end(&mut decoded).unwrap();
+assert!(decoded == data);
+```
+
+*/
+
+use std::io::{self, Read, Write};
+use std::mem;
+
+use Crc32;
+#[cfg(any(feature="flate", feature="lz4"))]
+use codec::{Compressor, Decompressor};
+use error::{checksum_mismatch, corrupt, truncated, unsupported};
+use pipeline::Pipeline;
+use process::{drain, gather, Process, Reader, Status};
+
+const MAGIC: u32 = 0x63706321; // !cpc
+
+/// Version of the format written
+pub const VERSION: u8 = 1;
+
+/// Default amount of data of each block
+const BLOCK_SIZE: usize = 1 << 20;
+
+/// Largest amount of data of a block
+const MAX_BLOCK_SIZE: usize = 1 << 30;
+
+/// Size of the frame in front of each block
+const FRAME_SIZE: usize = 12;
+
+fn put_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend_from_slice(&[n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]);
+}
+
+fn get_u32(buf: &[u8]) -> u32 {
+    buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24
+}
+
+/// How the blocks are compressed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// The data as is
+    Stored,
+    /// The stages of a pipeline, without its header
+    Pipeline(Pipeline),
+    /// Raw DEFLATE data of the given level, from 0 to 9
+    #[cfg(feature="flate")]
+    Deflate {
+        /// Compression level
+        level: u32,
+    },
+    /// The LZ4 frame format
+    #[cfg(feature="lz4")]
+    Lz4,
+}
+
+impl Method {
+    /// The identifier of the method and its options
+    fn to_header(&self) -> (u8, Vec<u8>) {
+        match *self {
+            Method::Stored => (0, Vec::new()),
+            Method::Pipeline(ref pipeline) => (1, pipeline.options()),
+            #[cfg(feature="flate")]
+            Method::Deflate { level } => (2, vec![level as u8]),
+            #[cfg(feature="lz4")]
+            Method::Lz4 => (3, Vec::new()),
+        }
+    }
+
+    fn from_header(id: u8, options: &[u8]) -> io::Result<Method> {
+        match (id, options.len()) {
+            (0, 0) => Ok(Method::Stored),
+            (1, _) => Pipeline::from_options(options).map(Method::Pipeline),
+            #[cfg(feature="flate")]
+            (2, 1) if options[0] <= 9 => Ok(Method::Deflate { level: options[0] as u32 }),
+            #[cfg(feature="lz4")]
+            (3, 0) => Ok(Method::Lz4),
+            (2, _) | (3, _) if cfg!(not(all(feature="flate", feature="lz4"))) => {
+                unsupported("container method of a disabled feature")
+            }
+            _ => corrupt("invalid container method"),
+        }
+    }
+
+    fn compress(&self, data: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        match *self {
+            Method::Stored => {
+                output.extend_from_slice(data);
+                Ok(())
+            }
+            Method::Pipeline(ref pipeline) => {
+                let mut e = pipeline.raw_encoder(output);
+                try!(e.write_all(data));
+                e.finish().1
+            }
+            #[cfg(feature="flate")]
+            Method::Deflate { level } => ::codec::Deflate { level: level }.compress(data, output),
+            #[cfg(feature="lz4")]
+            Method::Lz4 => ::codec::Lz4.compress(data, output),
+        }
+    }
+
+    /// Decompresses the data of a block, of `size` bytes
+    fn decompress(&self, data: &[u8], size: usize) -> io::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(size);
+        {
+            let r: Box<dyn Read> = match *self {
+                Method::Stored => Box::new(data),
+                Method::Pipeline(ref pipeline) => Box::new(pipeline.raw_decoder(data)),
+                #[cfg(feature="flate")]
+                Method::Deflate { level } => ::codec::Deflate { level: level }.decoder(Box::new(data)),
+                #[cfg(feature="lz4")]
+                Method::Lz4 => ::codec::Lz4.decoder(Box::new(data)),
+            };
+            try!(r.take(size as u64 + 1).read_to_end(&mut output));
+        }
+        if output.len() != size {
+            return corrupt("container block of another size")
+        }
+        Ok(output)
+    }
+}
+
+/// Encoder of the data into a container
+pub struct Encoder<W> {
+    w: W,
+    method: Method,
+    block_size: usize,
+    buf: Vec<u8>,
+    block: Vec<u8>,
+    wrote_header: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder compressing the blocks with `method`, which will
+    /// have its output written to the given output stream. The output stream
+    /// can be re-acquired by calling `finish()`
+    pub fn new(w: W, method: Method) -> Encoder<W> {
+        Encoder {
+            w: w,
+            method: method,
+            block_size: BLOCK_SIZE,
+            buf: Vec::new(),
+            block: Vec::new(),
+            wrote_header: false,
+        }
+    }
+
+    /// Sets the amount of data of each block, 1MB by default and 1GB at
+    /// most. This must be called before any data is written.
+    pub fn set_block_size(&mut self, size: usize) {
+        assert!(size > 0 && size <= MAX_BLOCK_SIZE && !self.wrote_header);
+        self.block_size = size;
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.wrote_header {
+            let (id, options) = self.method.to_header();
+            let mut header = Vec::with_capacity(12 + options.len());
+            put_u32(&mut header, MAGIC);
+            header.extend_from_slice(&[VERSION, id, options.len() as u8, (options.len() >> 8) as u8]);
+            header.extend_from_slice(&options);
+            put_u32(&mut header, self.block_size as u32);
+            try!(self.w.write_all(&header));
+            self.wrote_header = true;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        try!(self.write_header());
+        if self.buf.is_empty() {
+            return Ok(())
+        }
+        let mut crc = Crc32::new();
+        crc.feed(&self.buf);
+        self.block.truncate(0);
+        put_u32(&mut self.block, 0);
+        put_u32(&mut self.block, self.buf.len() as u32);
+        put_u32(&mut self.block, crc.result());
+        try!(self.method.compress(&self.buf, &mut self.block));
+        let len = self.block.len() - FRAME_SIZE;
+        if len == 0 || len > u32::MAX as usize {
+            return corrupt("container block which can't be framed")
+        }
+        self.block[..4].copy_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+        try!(self.w.write_all(&self.block));
+        self.buf.truncate(0);
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The last block and the end mark are written, and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.end();
+        (self.w, result)
+    }
+
+    /// Finishes the container, as `finish` does, then starts a new one into
+    /// `w` with the same method and block size, keeping the buffers. The
+    /// previous writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.end();
+        self.buf.truncate(0);
+        self.wrote_header = false;
+        (mem::replace(&mut self.w, w), result)
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        try!(self.write_block());
+        try!(self.w.write_all(&[0; FRAME_SIZE]));
+        self.w.flush()
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len();
+        while !buf.is_empty() {
+            let n = ::std::cmp::min(self.block_size - self.buf.len(), buf.len());
+            self.buf.extend_from_slice(&buf[..n]);
+            buf = &buf[n..];
+            if self.buf.len() == self.block_size {
+                try!(self.write_block());
+            }
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// State of a `Processor`, with the number of bytes it gathers
+enum State {
+    /// The magic number, the version, the method and the length of options
+    Header,
+    /// The options of the method, with its identifier, and the block size
+    Options(u8, usize),
+    /// The sizes and the CRC32 of a block
+    Frame,
+    /// The compressed data of a block, with its size and its CRC32
+    Block(usize, usize, u32),
+    Done,
+}
+
+/// This structure is used to decode a container, fed with slices of it
+pub struct Processor {
+    state: State,
+    version: u8,
+    method: Option<Method>,
+    block_size: usize,
+    block: Vec<u8>,
+    output: Vec<u8>,
+    start: usize,
+}
+
+impl Processor {
+    /// Creates a new decoder, waiting for the header of a container
+    pub fn new() -> Processor {
+        Processor {
+            state: State::Header,
+            version: 0,
+            method: None,
+            block_size: 0,
+            block: Vec::new(),
+            output: Vec::new(),
+            start: 0,
+        }
+    }
+
+    /// Resets this decoder back to its initial state, keeping its buffers
+    pub fn reset(&mut self) {
+        self.state = State::Header;
+        self.version = 0;
+        self.method = None;
+        self.block_size = 0;
+        self.block.truncate(0);
+        self.output.truncate(0);
+        self.start = 0;
+    }
+
+    /// Returns the version of the format of the container, or 0 until its
+    /// header is decoded
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Returns how the blocks are compressed, once the header is decoded
+    pub fn method(&self) -> Option<&Method> {
+        self.method.as_ref()
+    }
+
+    /// Tests whether the end of the container has been reached
+    pub fn eof(&self) -> bool {
+        match self.state { State::Done => self.start == self.output.len(), _ => false }
+    }
+
+    /// Decodes the part gathered in `block`, returning the next state
+    fn decode_part(&mut self) -> io::Result<State> {
+        match self.state {
+            State::Header => {
+                if get_u32(&self.block) != MAGIC {
+                    return corrupt("invalid container magic")
+                }
+                let (version, id) = (self.block[4], self.block[5]);
+                if version == 0 {
+                    return corrupt("invalid container version")
+                }
+                if version > VERSION {
+                    return unsupported("container of a newer version")
+                }
+                self.version = version;
+                Ok(State::Options(id, self.block[6] as usize | (self.block[7] as usize) << 8))
+            }
+            State::Options(id, len) => {
+                let method = try!(Method::from_header(id, &self.block[..len]));
+                let block_size = get_u32(&self.block[len..]) as usize;
+                if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+                    return corrupt("invalid container block size")
+                }
+                self.method = Some(method);
+                self.block_size = block_size;
+                Ok(State::Frame)
+            }
+            State::Frame => {
+                let (len, size, expected) = (get_u32(&self.block) as usize, get_u32(&self.block[4..]) as usize,
+                                             get_u32(&self.block[8..]));
+                if len == 0 {
+                    if size != 0 || expected != 0 {
+                        return corrupt("invalid container end")
+                    }
+                    return Ok(State::Done)
+                }
+                if size == 0 || size > self.block_size {
+                    return corrupt("invalid container block size")
+                }
+                Ok(State::Block(len, size, expected))
+            }
+            State::Block(_, size, expected) => {
+                let output = match self.method {
+                    Some(ref method) => try!(method.decompress(&self.block, size)),
+                    None => unreachable!(),
+                };
+                let mut crc = Crc32::new();
+                crc.feed(&output);
+                if crc.result() != expected {
+                    return checksum_mismatch("container block checksum mismatch")
+                }
+                self.output = output;
+                self.start = 0;
+                Ok(State::Frame)
+            }
+            State::Done => unreachable!(),
+        }
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.output, &mut self.start, output, &mut produced) {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            let len = match self.state {
+                State::Header => 8,
+                State::Options(_, len) => len + 4,
+                State::Frame => FRAME_SIZE,
+                State::Block(len, _, _) => len,
+                State::Done => return Ok((pos, produced, Status::Done)),
+            };
+            if !gather(&mut self.block, input, &mut pos, len) {
+                return Ok((pos, produced, Status::NeedInput))
+            }
+            self.state = try!(self.decode_part());
+            self.block.truncate(0);
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.state {
+            State::Done => Ok(()),
+            _ => truncated("truncated container"),
+        }
+    }
+}
+
+/// Reads the header of a container a byte at a time, not to read ahead of it,
+/// and returns its bytes
+fn read_header<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut p = Processor::new();
+    let mut header = Vec::new();
+    let mut byte = [0];
+    while p.method().is_none() {
+        if try!(r.read(&mut byte)) == 0 {
+            return truncated("truncated container header")
+        }
+        try!(p.process(&byte, &mut []));
+        header.push(byte[0]);
+    }
+    Ok(header)
+}
+
+/// Decoder of the data of a container
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Reads the header of the container from the given input stream, and
+    /// creates a decoder of its blocks
+    pub fn new(mut r: R) -> io::Result<Decoder<R>> {
+        let header = try!(read_header(&mut r));
+        let mut p = Processor::new();
+        try!(p.process(&header, &mut []));
+        Ok(Decoder {
+            inner: Reader::new(r, p),
+        })
+    }
+
+    /// Returns the version of the format of the container
+    pub fn version(&self) -> u8 {
+        self.inner.processor().version()
+    }
+
+    /// Returns how the blocks are compressed
+    pub fn method(&self) -> &Method {
+        match self.inner.processor().method() {
+            Some(method) => method,
+            None => unreachable!(),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the container decodes
+    /// to more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Reads the header of a new container from `r`, keeping the buffers of
+    /// the decoder and its settings, and returns the previous reader. The
+    /// decoder is left as it is if the header can't be read.
+    pub fn reset(&mut self, mut r: R) -> io::Result<R> {
+        let header = try!(read_header(&mut r));
+        self.inner.processor_mut().reset();
+        try!(self.inner.processor_mut().process(&header, &mut []));
+        Ok(self.inner.reset(r))
+    }
+
+    /// Tests whether the end of the container has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.processor().eof()
+    }
+
+    /// Returns the wrapped reader. The data read ahead of the output is lost
+    /// with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use error::Error;
+    use pipeline::{Pipeline, Stage};
+    use process::Process;
+    use rle::Scheme;
+    use testing::push;
+    use super::{Decoder, Encoder, Method, Processor};
+
+    fn encode(data: &[u8], method: Method, block_size: usize) -> Vec<u8> {
+        let mut e = Encoder::new(Vec::new(), method);
+        e.set_block_size(block_size);
+        for piece in data.chunks(777) {
+            e.write_all(piece).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        encoded
+    }
+
+    fn decode(encoded: &[u8]) -> ::std::io::Result<Vec<u8>> {
+        let mut d = try!(Decoder::new(encoded));
+        let mut decoded = Vec::new();
+        try!(d.read_to_end(&mut decoded));
+        assert!(d.eof());
+        Ok(decoded)
+    }
+
+    fn methods() -> Vec<Method> {
+        let mut methods = vec![Method::Stored];
+        methods.push(Method::Pipeline(Pipeline::new()
+            .stage(Stage::Lzp { min_len: 16 })
+            .stage(Stage::Bwt { block_size: 5000 })
+            .stage(Stage::Mtf)
+            .stage(Stage::Rle(Scheme::Zero))
+            .stage(Stage::Ari)));
+        #[cfg(feature="flate")]
+        methods.push(Method::Deflate { level: 6 });
+        #[cfg(feature="lz4")]
+        methods.push(Method::Lz4);
+        methods
+    }
+
+    #[test]
+    fn roundtrips() {
+        let data = &include_bytes!("data/test.txt")[..];
+        for method in methods() {
+            for &size in [100, 1000, 1 << 20].iter() {
+                let encoded = encode(data, method.clone(), size);
+                let d = Decoder::new(&encoded[..]).unwrap();
+                assert_eq!(d.method(), &method);
+                assert_eq!(d.version(), super::VERSION);
+                assert!(&decode(&encoded).unwrap()[..] == data);
+            }
+            assert!(decode(&encode(b"", method.clone(), 100)).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn corruption() {
+        let data = &include_bytes!("data/test.txt")[..];
+        let mut encoded = encode(data, Method::Stored, 1000);
+        // the first byte of data
+        encoded[12 + 12] ^= 1;
+        let err = decode(&encoded).err().unwrap();
+        assert!(match Error::of(&err) { Some(Error::ChecksumMismatch { .. }) => true, _ => false });
+        encoded[12 + 12] ^= 1;
+
+        encoded[4] = super::VERSION + 1;
+        let err = decode(&encoded).err().unwrap();
+        assert!(match Error::of(&err) { Some(Error::Unsupported { .. }) => true, _ => false });
+        encoded[4] = super::VERSION;
+        encoded[5] = 100;
+        assert!(decode(&encoded).is_err());
+        encoded[5] = 0;
+
+        let len = encoded.len();
+        assert!(decode(&encoded[..(len - 12)]).is_err());
+        assert!(decode(&encoded[..(len - 100)]).is_err());
+        assert!(&decode(&encoded).unwrap()[..] == data);
+    }
+
+    #[test]
+    fn reset() {
+        let data = &include_bytes!("data/test.txt")[..];
+        let method = methods().pop().unwrap();
+        let mut e = Encoder::new(Vec::new(), Method::Stored);
+        e.set_block_size(1000);
+        e.write_all(data).unwrap();
+        let (a, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(b"hello").unwrap();
+        let (b, result) = e.finish();
+        result.unwrap();
+        assert!(a == encode(data, Method::Stored, 1000));
+        assert!(b == encode(b"hello", Method::Stored, 1000));
+
+        let other = encode(data, method.clone(), 333);
+        let mut d = Decoder::new(&a[..]).unwrap();
+        d.read_exact(&mut [0; 100]).unwrap();
+        assert!(d.reset(&b""[..]).is_err());
+        assert_eq!(d.method(), &Method::Stored);
+        d.reset(&other[..]).unwrap();
+        assert_eq!(d.method(), &method);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(d.eof() && &decoded[..] == data);
+    }
+
+    #[test]
+    fn decode_push() {
+        let data = &include_bytes!("data/test.txt")[..];
+        for method in methods() {
+            let encoded = encode(data, method.clone(), 1000);
+            for &step in [1, 333, 1 << 16].iter() {
+                let mut p = Processor::new();
+                assert!(&push(&mut p, &encoded, step).unwrap()[..] == data);
+                assert_eq!(p.method(), Some(&method));
+            }
+            for &len in [0, 10, 100, encoded.len() - 1].iter() {
+                assert!(push(&mut Processor::new(), &encoded[..len], 100).is_err());
+            }
+        }
+
+        // the header is decoded before the blocks are fed
+        let encoded = encode(data, Method::Stored, 1000);
+        let mut p = Processor::new();
+        assert_eq!(p.process(&encoded[..12], &mut []).unwrap().0, 12);
+        assert_eq!(p.method(), Some(&Method::Stored));
+        assert_eq!(p.version(), super::VERSION);
+    }
+}
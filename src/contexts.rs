@@ -0,0 +1,119 @@
+//! Tables of the contexts of the models, allocated on first use
+//!
+//! The models coding a symbol in the context of the previous ones keep a
+//! table of probabilities for each context, as the literals of LZMA after the
+//! high bits of the previous byte or those of ROLZ after the whole byte. Most
+//! contexts never occur in a short or simple input, so that allocating and
+//! initializing all their tables up front takes longer than coding it.
+//! `Tables` gives each context its table the first time it is asked for, out
+//! of a single arena, so that the memory grows with the contexts seen. The
+//! tables released by `reset` stay in the arena, free for the next contexts,
+//! which initialize them again only once they occur.
+//!
+//! # Example
+//!
+//! ```rust
+//! use compress::contexts::Tables;
+//!
+//! let mut tables = Tables::new(1 << 16, 0x300, 0x400u16);
+//! tables.get(42)[7] += 1;
+//! assert_eq!(tables.get(42)[7], 0x401);
+//! assert_eq!(tables.allocated(), 1);
+//! tables.reset(1 << 8);
+//! assert_eq!(tables.get(42)[7], 0x400);
+//! ```
+
+use std::vec::Vec;
+
+/// The tables of `size` values of a number of contexts
+pub struct Tables<T> {
+    /// The table of each context, as its number in the arena plus 1, or 0
+    /// before its first use
+    index: Vec<u32>,
+    /// The tables one after the other, those from `used` on being free
+    arena: Vec<T>,
+    used: usize,
+    size: usize,
+    init: T,
+}
+
+impl<T: Clone> Tables<T> {
+    /// Creates the tables of `contexts` contexts, each of `size` values
+    /// starting at `init` once allocated
+    pub fn new(contexts: usize, size: usize, init: T) -> Tables<T> {
+        assert!(contexts <= u32::max_value() as usize, "too many contexts");
+        Tables {
+            index: vec![0; contexts],
+            arena: Vec::new(),
+            used: 0,
+            size: size,
+            init: init,
+        }
+    }
+
+    /// Returns the table of `context`, allocating it on its first use
+    #[inline]
+    pub fn get(&mut self, context: usize) -> &mut [T] {
+        let mut slot = self.index[context] as usize;
+        if slot == 0 {
+            slot = self.allocate();
+            self.index[context] = slot as u32;
+        }
+        let start = (slot - 1) * self.size;
+        &mut self.arena[start..(start + self.size)]
+    }
+
+    /// Takes a free table of the arena, or a new one, initialized
+    #[inline(never)]
+    fn allocate(&mut self) -> usize {
+        let start = self.used * self.size;
+        if start == self.arena.len() {
+            let init = self.init.clone();
+            self.arena.resize(start + self.size, init);
+        } else {
+            for value in self.arena[start..(start + self.size)].iter_mut() {
+                *value = self.init.clone();
+            }
+        }
+        self.used += 1;
+        self.used
+    }
+
+    /// Releases the tables, as for new tables of `contexts` contexts, the
+    /// arena being kept for them
+    pub fn reset(&mut self, contexts: usize) {
+        assert!(contexts <= u32::max_value() as usize, "too many contexts");
+        self.index.clear();
+        self.index.resize(contexts, 0);
+        self.used = 0;
+    }
+
+    /// Returns the number of tables given to the contexts so far
+    pub fn allocated(&self) -> usize {
+        self.used
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Tables;
+
+    #[test]
+    fn first_use() {
+        let mut tables = Tables::new(100, 3, 7u8);
+        assert_eq!(tables.allocated(), 0);
+        tables.get(99)[2] = 1;
+        tables.get(0)[0] = 2;
+        assert_eq!(tables.get(99), &[7, 7, 1]);
+        assert_eq!(tables.get(0), &[2, 7, 7]);
+        assert_eq!(tables.allocated(), 2);
+
+        // the tables released are initialized again once used
+        tables.reset(200);
+        assert_eq!(tables.allocated(), 0);
+        assert_eq!(tables.get(150), &[7, 7, 7]);
+        assert_eq!(tables.get(99), &[7, 7, 7]);
+        assert_eq!(tables.get(1), &[7, 7, 7]);
+        assert_eq!(tables.allocated(), 3);
+    }
+}
@@ -0,0 +1,77 @@
+//! Copies of the matches of LZ77 decoders
+//!
+//! The decoders append each match to their output from some distance back,
+//! the match overlapping the bytes it produces when it is longer than its
+//! distance. `copy_match` copies it 16 bytes at a time rather than byte by
+//! byte: the last copy runs past the end of the match into the spare capacity
+//! of the output, and a match closer than 16 bytes first spreads its period
+//! until a multiple of it is 16 bytes back or more.
+//!
+//! # Example
+//!
+//! ```rust
+//! use compress::copy::copy_match;
+//! let mut output = b"abc".to_vec();
+//! copy_match(&mut output, 3, 7);
+//! assert_eq!(&output[..], b"abcabcabca");
+//! ```
+
+use std::cmp;
+use std::ptr;
+use std::vec::Vec;
+
+/// Number of bytes of each copy, which may be written past the match
+const WILD: usize = 16;
+
+/// Appends `len` bytes to `output`, copied from `dist` bytes back. The
+/// distance, from 1 to the length of `output`, is checked by the decoders
+/// beforehand, which fail on any other as corrupt: this panics on them.
+#[inline]
+pub fn copy_match(output: &mut Vec<u8>, dist: usize, len: usize) {
+    assert!(dist > 0 && dist <= output.len(), "match distance out of the output");
+    let start = output.len();
+    output.reserve(len + WILD);
+    // the reserve leaves room for the copies past the match
+    unsafe {
+        let mut dst = output.as_mut_ptr().add(start);
+        let end = dst.add(len);
+        let mut dist = dist;
+        if dist < WILD {
+            // the bytes from `dist` back repeat every `dist` bytes, so those
+            // written first are copied again from a multiple of it
+            let wide = dist * ((WILD + dist - 1) / dist);
+            for _ in 0..cmp::min(wide - dist, len) {
+                *dst = *dst.sub(dist);
+                dst = dst.add(1);
+            }
+            dist = wide;
+        }
+        while dst < end {
+            ptr::copy_nonoverlapping(dst.sub(dist), dst, WILD);
+            dst = dst.add(WILD);
+        }
+        output.set_len(start + len);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::copy_match;
+
+    #[test]
+    fn matches() {
+        let data = include_bytes!("data/test.txt");
+        for dist in 1..40 {
+            for &len in [0, 1, 2, 5, 15, 16, 17, 31, 32, 33, 100, 1000].iter() {
+                let mut output = data[..100].to_vec();
+                copy_match(&mut output, dist, len);
+                let mut expected = data[..100].to_vec();
+                for _ in 0..len {
+                    let b = expected[expected.len() - dist];
+                    expected.push(b);
+                }
+                assert!(output == expected, "distance {}, length {}", dist, len);
+            }
+        }
+    }
+}
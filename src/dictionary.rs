@@ -0,0 +1,414 @@
+/*!
+
+Preset dictionaries and their training. Requires `dictionary` feature
+
+Small messages, as log lines or JSON documents, compress poorly on their own,
+as their matches can only refer to what precedes them in the same message.
+This module builds a dictionary from many samples of such messages: its
+content, given to a decoder, is the history the matches of each message may
+also refer to.
+
+The trainer follows the cover algorithm of zstd. The samples are cut in
+substrings of 8 bytes, scored with the number of samples containing them.
+The samples are then split in epochs, and the segment of each epoch covering
+the substrings of the best total score is taken into the dictionary, the
+scores of its substrings being cleared so that they count once. The segments
+end up sorted by their score, the best one at the end of the dictionary, at
+the shortest offsets.
+
+The dictionary is made of raw content only, without entropy tables, as read
+by `zstd::Dictionary::new`.
+
+A `Dictionary` holds such content, trained or not, for the codecs taking a
+preset dictionary: the LZ4 and DEFLATE encoders and decoders, the zlib
+decoder, through their `with_dictionary` constructors, and the zstd decoder,
+through `zstd::Dictionary::from_shared`. Each of them uses the end of the
+content, as much as its window holds.
+
+# Example
+
+```rust
+use compress::dictionary::Trainer;
+
+let mut trainer = Trainer::new();
+for i in 0..1000 {
+    let line = format!("{{\"level\":\"info\",\"service\":\"api\",\"request\":{}}}", i);
+    trainer.add_sample(line.as_bytes());
+}
+let dictionary = trainer.train(1024);
+assert!(dictionary.len() <= 1024);
+```
+
+```rust
+use std::io::{Read, Write};
+use compress::dictionary::Dictionary;
+use compress::lz4;
+
+let dictionary = Dictionary::with_id(b"{\"level\":\"info\"}".to_vec(), 7);
+let mut e = lz4::Encoder::with_dictionary(Vec::new(), &dictionary);
+e.write_all(b"{\"level\":\"info\",\"request\":1}").unwrap();
+let (compressed, result) = e.finish();
+result.unwrap();
+
+let mut d = lz4::Decoder::with_dictionary(&compressed[..], &dictionary);
+let mut decompressed = Vec::new();
+d.read_to_end(&mut decompressed).unwrap();
+assert_eq!(&decompressed[..], b"{\"level\":\"info\",\"request\":1}");
+```
+
+*/
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+
+/// Length of the substrings scored
+const DMER: usize = 8;
+
+/// Size of the segments taken by default
+const SEGMENT_SIZE: usize = 64;
+
+/// Returns the substring of `data` at `pos`, as an integer
+fn dmer(data: &[u8], pos: usize) -> u64 {
+    data[pos..(pos + DMER)].iter().fold(0, |acc, &b| (acc << 8) | b as u64)
+}
+
+/// The content of a preset dictionary, shared by the codecs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dictionary {
+    content: Vec<u8>,
+    id: u32,
+}
+
+impl Dictionary {
+    /// Creates a dictionary of the given content, without identifier
+    pub fn new(content: Vec<u8>) -> Dictionary {
+        Dictionary { content: content, id: 0 }
+    }
+
+    /// Creates a dictionary of the given content, with the identifier the
+    /// formats which record one, as LZ4, write along with the data to check
+    /// the dictionary of the decoder
+    pub fn with_id(content: Vec<u8>, id: u32) -> Dictionary {
+        Dictionary { content: content, id: id }
+    }
+
+    /// Returns the content of the dictionary
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Returns the end of the content which fits a window of `size` bytes
+    pub fn tail(&self, size: usize) -> &[u8] {
+        &self.content[self.content.len().saturating_sub(size)..]
+    }
+
+    /// Returns the identifier of the dictionary, or 0 if it has none
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// This structure gathers the samples to train a dictionary from
+pub struct Trainer {
+    /// The samples, one after the other
+    data: Vec<u8>,
+    /// The end of each sample in `data`
+    ends: Vec<usize>,
+    segment_size: usize,
+}
+
+impl Default for Trainer {
+    fn default() -> Trainer {
+        Trainer::new()
+    }
+}
+
+impl Trainer {
+    /// Creates a trainer without samples, taking segments of 64 bytes
+    pub fn new() -> Trainer {
+        Trainer {
+            data: Vec::new(),
+            ends: Vec::new(),
+            segment_size: SEGMENT_SIZE,
+        }
+    }
+
+    /// Sets the size of the segments taken from the samples, from 8 bytes.
+    /// Longer segments suit samples sharing longer strings.
+    pub fn set_segment_size(&mut self, size: usize) {
+        assert!(size >= DMER, "invalid dictionary segment size {}", size);
+        self.segment_size = size;
+    }
+
+    /// Adds a sample of the data the dictionary is meant for
+    pub fn add_sample(&mut self, sample: &[u8]) {
+        self.data.extend_from_slice(sample);
+        self.ends.push(self.data.len());
+    }
+
+    /// Returns the number of samples added
+    pub fn samples(&self) -> usize {
+        self.ends.len()
+    }
+
+    /// Builds a dictionary of at most `size` bytes from the samples. It is
+    /// shorter when the samples have too little in common to fill it.
+    pub fn train(&self, size: usize) -> Vec<u8> {
+        let mut scores = self.scores();
+        let segment_size = cmp::min(self.segment_size, size);
+        if segment_size < DMER || self.data.is_empty() {
+            return Vec::new()
+        }
+        let epochs = cmp::max(1, size / segment_size);
+        let epoch_size = cmp::max(segment_size, self.data.len() / epochs);
+        let mut segments = Vec::new();
+        let mut start = 0;
+        while start < self.data.len() {
+            let end = cmp::min(start + epoch_size, self.data.len());
+            if let Some((score, pos)) = self.best_segment(&scores, start, end, segment_size) {
+                let segment = &self.data[pos..(pos + segment_size)];
+                for i in 0..(segment_size - DMER + 1) {
+                    scores.remove(&dmer(segment, i));
+                }
+                segments.push((score, segment));
+            }
+            start = end;
+        }
+        // the best segments last, closer to the data
+        segments.sort_by_key(|s| s.0);
+        let skip = segments.len().saturating_sub(size / segment_size);
+        segments[skip..].iter().fold(Vec::with_capacity(size), |mut dict, &(_, segment)| {
+            dict.extend_from_slice(segment);
+            dict
+        })
+    }
+
+    /// Counts the samples containing each substring
+    fn scores(&self) -> HashMap<u64, u64> {
+        let mut scores = HashMap::new();
+        let mut seen = HashSet::new();
+        let mut start = 0;
+        for &end in self.ends.iter() {
+            let sample = &self.data[start..end];
+            seen.clear();
+            for i in 0..(sample.len().saturating_sub(DMER - 1)) {
+                let d = dmer(sample, i);
+                if seen.insert(d) {
+                    *scores.entry(d).or_insert(0) += 1;
+                }
+            }
+            start = end;
+        }
+        // the substrings of a single sample are not worth keeping
+        scores.retain(|_, &mut score| score > 1);
+        scores
+    }
+
+    /// Finds the segment starting within `start..end`, and not crossing the
+    /// end of its sample, of the best score, returning it with its position
+    fn best_segment(&self, scores: &HashMap<u64, u64>, start: usize, end: usize, size: usize)
+                    -> Option<(u64, usize)> {
+        let mut best = None;
+        let first = match self.ends.binary_search(&start) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        let mut sample_start = if first == 0 { 0 } else { self.ends[first - 1] };
+        for &sample_end in self.ends[first..].iter() {
+            if sample_start >= end {
+                break
+            }
+            // the substrings within the window, counted once
+            let mut window: HashMap<u64, usize> = HashMap::new();
+            let mut score = 0;
+            let begin = cmp::max(start, sample_start);
+            let last = cmp::min(end, sample_end.saturating_sub(size - 1));
+            let mut pos = begin;
+            while pos < last {
+                // the substrings entering, then leaving the window
+                let from = if pos == begin { pos } else { pos + size - DMER };
+                for i in from..(pos + size - DMER + 1) {
+                    let d = dmer(&self.data, i);
+                    let count = window.entry(d).or_insert(0);
+                    *count += 1;
+                    if *count == 1 {
+                        score += scores.get(&d).cloned().unwrap_or(0);
+                    }
+                }
+                if score > best.map_or(0, |b: (u64, usize)| b.0) {
+                    best = Some((score, pos));
+                }
+                let d = dmer(&self.data, pos);
+                let remove = {
+                    let count = window.get_mut(&d).unwrap();
+                    *count -= 1;
+                    *count == 0
+                };
+                if remove {
+                    window.remove(&d);
+                    score -= scores.get(&d).cloned().unwrap_or(0);
+                }
+                pos += 1;
+            }
+            sample_start = sample_end;
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[allow(unused_imports)]
+    use std::io::{Read, Write};
+    use testing::Lcg;
+    #[cfg(any(feature="zlib", feature="lz4"))]
+    use super::Dictionary;
+    use super::Trainer;
+
+    fn messages(n: usize) -> Vec<Vec<u8>> {
+        let mut lcg = Lcg(3);
+        (0..n).map(|i| {
+            let seed = lcg.step();
+            let level = ["info", "warn", "error"][(seed >> 16) as usize % 3];
+            format!("{{\"time\":{},\"level\":\"{}\",\"service\":\"checkout\",\"message\":\"order {} processed\"}}",
+                    1500000000 + i * 7, level, seed >> 20).into_bytes()
+        }).collect()
+    }
+
+    fn contains(data: &[u8], pattern: &[u8]) -> bool {
+        data.windows(pattern.len()).any(|w| w == pattern)
+    }
+
+    #[test]
+    fn trains() {
+        let mut trainer = Trainer::new();
+        for message in messages(2000).iter() {
+            trainer.add_sample(message);
+        }
+        assert_eq!(trainer.samples(), 2000);
+        let dictionary = trainer.train(512);
+        assert!(dictionary.len() > 0 && dictionary.len() <= 512);
+        assert!(contains(&dictionary, b"\"service\":\"checkout\""));
+        assert!(contains(&dictionary, b"processed"));
+        assert_eq!(trainer.train(512), dictionary);
+    }
+
+    #[test]
+    fn segment_sizes() {
+        let mut trainer = Trainer::new();
+        for message in messages(500).iter() {
+            trainer.add_sample(message);
+        }
+        trainer.set_segment_size(16);
+        let dictionary = trainer.train(256);
+        assert!(dictionary.len() > 0 && dictionary.len() <= 256);
+        assert_eq!(dictionary.len() % 16, 0);
+        // segments longer than the dictionary are cut to its size
+        trainer.set_segment_size(1000);
+        assert!(trainer.train(100).len() <= 100);
+    }
+
+    #[test]
+    fn nothing_in_common() {
+        assert_eq!(Trainer::new().train(1024), b"");
+        let mut trainer = Trainer::new();
+        trainer.add_sample(b"abcdefghijklmnopqrstuvwxyz");
+        trainer.add_sample(b"0123456789");
+        trainer.add_sample(b"short");
+        assert_eq!(trainer.train(1024), b"");
+    }
+
+    /// A dictionary trained on the messages, with one more message
+    #[cfg(any(feature="zlib", feature="lz4"))]
+    fn trained() -> (Dictionary, Vec<u8>) {
+        let mut messages = messages(1001);
+        let message = messages.pop().unwrap();
+        let mut trainer = Trainer::new();
+        for m in messages.iter() {
+            trainer.add_sample(m);
+        }
+        (Dictionary::with_id(trainer.train(2048), 42), message)
+    }
+
+    #[test]
+    #[cfg(feature="zlib")]
+    fn deflate_and_zlib() {
+        use {flate, zlib, Adler32};
+
+        let (dictionary, message) = trained();
+        let deflate = |dictionary: Option<&Dictionary>| {
+            let mut e = match dictionary {
+                Some(dictionary) => flate::Encoder::with_dictionary(Vec::new(), 9, dictionary),
+                None => flate::Encoder::new(Vec::new(), 9),
+            };
+            e.write_all(&message).unwrap();
+            let (out, result) = e.finish();
+            result.unwrap();
+            out
+        };
+        let compressed = deflate(Some(&dictionary));
+        assert!(compressed.len() < deflate(None).len());
+        let mut out = Vec::new();
+        flate::Decoder::with_dictionary(&compressed[..], &dictionary).read_to_end(&mut out).unwrap();
+        assert_eq!(out, message);
+
+        // a zlib stream giving the Adler-32 of its dictionary
+        let mut hash = Adler32::new();
+        hash.feed(dictionary.content());
+        let id = hash.result();
+        hash = Adler32::new();
+        hash.feed(&message);
+        let check = hash.result();
+        let mut stream = vec![0x78, 0xbb, (id >> 24) as u8, (id >> 16) as u8, (id >> 8) as u8, id as u8];
+        stream.extend(compressed.iter().cloned());
+        stream.extend([(check >> 24) as u8, (check >> 16) as u8, (check >> 8) as u8, check as u8].iter().cloned());
+        let mut d = zlib::Decoder::with_dictionary(&stream[..], &dictionary);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert_eq!(out, message);
+        assert!(zlib::Decoder::new(&stream[..]).read_to_end(&mut Vec::new()).is_err());
+        let other = Dictionary::new(b"another dictionary".to_vec());
+        assert!(zlib::Decoder::with_dictionary(&stream[..], &other).read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    #[cfg(feature="lz4")]
+    fn lz4() {
+        use lz4;
+
+        let (dictionary, message) = trained();
+        let mut e = lz4::Encoder::with_dictionary(Vec::new(), &dictionary);
+        e.write_all(&message).unwrap();
+        let (compressed, result) = e.finish();
+        result.unwrap();
+        let mut out = Vec::new();
+        lz4::Decoder::with_dictionary(&compressed[..], &dictionary).read_to_end(&mut out).unwrap();
+        assert_eq!(out, message);
+        assert!(lz4::Decoder::new(&compressed[..]).read_to_end(&mut Vec::new()).is_err());
+        let other = Dictionary::with_id(dictionary.content().to_vec(), 43);
+        assert!(lz4::Decoder::with_dictionary(&compressed[..], &other).read_to_end(&mut Vec::new()).is_err());
+
+        // a block of a match of 8 bytes at the offset 4, into the dictionary,
+        // then of 4 literals
+        let dictionary = Dictionary::new(b"0123abcd".to_vec());
+        let stream = [0x04, 0x22, 0x4d, 0x18, 0x60, 0x40, 0x82,
+                      8, 0, 0, 0, 0x04, 4, 0, 0x40, b'w', b'x', b'y', b'z',
+                      0, 0, 0, 0];
+        let mut out = Vec::new();
+        lz4::Decoder::with_dictionary(&stream[..], &dictionary).read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"abcdabcdwxyz");
+    }
+
+    #[test]
+    #[cfg(feature="zstd")]
+    fn zstd() {
+        use zstd;
+
+        let text = include_bytes!("data/test.txt");
+        let dictionary = zstd::Dictionary::from_shared(&Dictionary::new(text.to_vec())).unwrap();
+        let input = include_bytes!("data/test.prefix.zst");
+        let mut out = Vec::new();
+        zstd::Decoder::with_dictionary(&input[..], dictionary).read_to_end(&mut out).unwrap();
+        assert_eq!(&out[b"prefix: ".len()..], &text[1000..2000]);
+    }
+}
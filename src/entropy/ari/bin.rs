@@ -14,6 +14,7 @@ The simplicity of the domain allows for normalized updates in place using bit sh
 use super::Border;
 
 /// A binary value frequency model
+#[derive(Clone)]
 pub struct Model {
     /// frequency of bit 0
     zero: Border,
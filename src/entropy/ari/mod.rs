@@ -35,13 +35,24 @@ This is an original implementation.
 #![allow(missing_docs)]
 
 use std::fmt::Display;
-use std::io::{self, Read, Write};
+use std::io;
+#[cfg(feature="std")]
+use std::io::{Read, Write};
+#[cfg(feature="std")]
+use std::mem;
 
+#[cfg(feature="std")]
 use super::super::byteorder::{BigEndian, WriteBytesExt, ReadBytesExt};
+#[cfg(feature="std")]
+#[cfg(feature="std")]
 use super::super::byteorder_err_to_io;
+use error::corrupt;
 
+#[cfg(feature="std")]
 pub use self::table::{ByteDecoder, ByteEncoder};
+pub use self::table::ByteProcessor;
 
+#[cfg(feature="std")]
 pub mod apm;
 pub mod bin;
 pub mod table;
@@ -205,11 +216,13 @@ pub trait Model<V: Copy + Display> {
 
 
 /// An arithmetic encoder helper
+#[cfg(feature="std")]
 pub struct Encoder<W> {
     stream: W,
     range: RangeEncoder,
 }
 
+#[cfg(feature="std")]
 impl<W: Write> Encoder<W> {
     /// Create a new encoder on top of a given Writer
     pub fn new(w: W) -> Encoder<W> {
@@ -228,12 +241,24 @@ impl<W: Write> Encoder<W> {
 
     /// Finish encoding by writing the code tail word
     pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.end();
+        (self.stream, result)
+    }
+
+    /// Finish encoding as `finish` does, then start a new code into `w`
+    /// and return the previous writer
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.end();
+        self.range.reset();
+        (mem::replace(&mut self.stream, w), result)
+    }
+
+    fn end(&mut self) -> io::Result<()> {
         debug_assert!(BORDER_BITS == 32);
         let code = self.range.get_code_tail();
         let result = self.stream.write_u32::<BigEndian>(code)
                                 .map_err(byteorder_err_to_io);
-        let result = result.and(self.stream.flush());
-        (self.stream, result)
+        result.and(self.stream.flush())
     }
 
     /// Flush the output stream
@@ -249,40 +274,102 @@ impl<W: Write> Encoder<W> {
     }
 }
 
-/// An arithmetic decoder helper
-pub struct Decoder<R> {
-    stream: R,
+/// Decoding half of the range coder, fed with the bytes of the code
+pub struct RangeDecoder {
     range: RangeEncoder,
     code: Border,
     bytes_pending: usize,
 }
 
+impl RangeDecoder {
+    /// Create a decoder expecting the first bytes of the code
+    pub fn new() -> RangeDecoder {
+        RangeDecoder {
+            range: RangeEncoder::new(RANGE_DEFAULT_THRESHOLD),
+            code: 0,
+            bytes_pending: BORDER_BYTES,
+        }
+    }
+
+    /// Start decoding a new code
+    pub fn reset(&mut self) {
+        self.range.reset();
+        self.code = 0;
+        self.bytes_pending = BORDER_BYTES;
+    }
+
+    /// Feed the code with the bytes of `input` from `pos` it needs before the
+    /// next value, returns whether it has them all
+    pub fn feed(&mut self, input: &[u8], pos: &mut usize) -> bool {
+        while self.bytes_pending != 0 && *pos < input.len() {
+            self.code = (self.code<<8) + (input[*pos] as Border);
+            self.bytes_pending -= 1;
+            *pos += 1;
+        }
+        self.bytes_pending == 0
+    }
+
+    /// Decode an abstract value based on the given Model, once fed
+    /// fails on codes out of the range, which corrupted data may produce
+    pub fn decode<V: Copy + Display, M: Model<V>>(&mut self, model: &M) -> io::Result<V> {
+        debug_assert_eq!(self.bytes_pending, 0);
+        let total = model.get_denominator();
+        if self.code < self.range.low || self.code >= self.range.hai {
+            return corrupt("invalid arithmetic code")
+        }
+        let offset = self.range.query(total, self.code);
+        if offset >= total {
+            return corrupt("invalid arithmetic code")
+        }
+        let (value, lo, hi) = model.find_value(offset);
+        let mut out = [0 as Symbol; BORDER_BYTES];
+        let shift = self.range.process(total, lo, hi, &mut out[..]);
+        // the symbols shifted out have to be those of the code
+        if shift != 0 && self.code>>(BORDER_BITS - shift*8) !=
+                out[..shift].iter().fold(0 as Border, |u,&b| (u<<8)+(b as Border)) {
+            return corrupt("invalid arithmetic code")
+        }
+        self.bytes_pending = shift;
+        Ok(value)
+    }
+}
+
+/// An arithmetic decoder helper
+#[cfg(feature="std")]
+pub struct Decoder<R> {
+    stream: R,
+    state: RangeDecoder,
+}
+
+#[cfg(feature="std")]
 impl<R: Read> Decoder<R> {
     /// Create a decoder on top of a given Reader
     pub fn new(r: R) -> Decoder<R> {
         Decoder {
             stream: r,
-            range: RangeEncoder::new(RANGE_DEFAULT_THRESHOLD),
-            code: 0,
-            bytes_pending: BORDER_BYTES,
+            state: RangeDecoder::new(),
         }
     }
 
     fn feed(&mut self) -> io::Result<()> {
-        while self.bytes_pending != 0 {
+        while self.state.bytes_pending != 0 {
             let b = try!(self.stream.read_u8());
-            self.code = (self.code<<8) + (b as Border);
-            self.bytes_pending -= 1;
+            self.state.feed(&[b], &mut 0);
         }
         Ok(())
     }
 
     /// Decode an abstract value based on the given Model
+    /// fails on codes out of the range, which corrupted data may produce
     pub fn decode<V: Copy + Display, M: Model<V>>(&mut self, model: &M) -> io::Result<V> {
-        self.feed().unwrap();
-        let (value, shift) = model.decode(self.code, &mut self.range);
-        self.bytes_pending = shift;
-        Ok(value)
+        try!(self.feed());
+        self.state.decode(model)
+    }
+
+    /// Start decoding a new code from `r`, returning the previous reader
+    pub fn reset(&mut self, r: R) -> R {
+        self.state.reset();
+        mem::replace(&mut self.stream, r)
     }
 
     /// Finish decoding
@@ -11,8 +11,16 @@ The module also implements Reader/Writer using simple byte coding.
 
 */
 
-use std::io::{self, Read, Write};
+use std::io;
+#[cfg(feature="std")]
+use std::io::{Read, Write};
+use std::vec::Vec;
 use super::Border;
+#[cfg(feature="std")]
+use error::truncated;
+use process::{Process, Status};
+#[cfg(feature="std")]
+use process::Reader;
 
 pub type Frequency = u16;
 
@@ -182,6 +190,7 @@ impl<'a> super::Model<usize> for SumProxy<'a> {
 
 /// A basic byte-encoding arithmetic
 /// uses a special terminator code to end the stream
+#[cfg(feature="std")]
 pub struct ByteEncoder<W> {
     /// A lower level encoder
     pub encoder: super::Encoder<W>,
@@ -189,6 +198,7 @@ pub struct ByteEncoder<W> {
     pub freq: Model,
 }
 
+#[cfg(feature="std")]
 impl<W: Write> ByteEncoder<W> {
     /// Create a new encoder on top of a given Writer
     pub fn new(w: W) -> ByteEncoder<W> {
@@ -205,8 +215,18 @@ impl<W: Write> ByteEncoder<W> {
         let (w,r2) = self.encoder.finish();
         (w, ret.and(r2))
     }
+
+    /// Finish encoding as `finish` does, then start a new stream into `w`
+    /// with a flat frequency table, and return the previous writer
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let ret = self.encoder.encode(super::SYMBOL_TOTAL, &self.freq);
+        let (w, r2) = self.encoder.reset(w);
+        self.freq.reset_flat();
+        (w, ret.and(r2))
+    }
 }
 
+#[cfg(feature="std")]
 impl<W: Write> Write for ByteEncoder<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         for byte in buf.iter() {
@@ -224,50 +244,103 @@ impl<W: Write> Write for ByteEncoder<W> {
 }
 
 
-/// A basic byte-decoding arithmetic
+/// A basic byte-decoding arithmetic, fed with slices of its input
 /// expects a special terminator code for the end of the stream
-pub struct ByteDecoder<R> {
+pub struct ByteProcessor {
     /// A lower level decoder
-    pub decoder: super::Decoder<R>,
+    pub decoder: super::RangeDecoder,
     /// A basic frequency table
     pub freq: Model,
     /// Remember if we found the terminator code
     is_eof: bool,
 }
 
-impl<R: Read> ByteDecoder<R> {
-    /// Create a decoder on top of a given Reader
-    pub fn new(r: R) -> ByteDecoder<R> {
+impl ByteProcessor {
+    /// Create a decoder expecting the start of a stream
+    pub fn new() -> ByteProcessor {
         let freq_max = super::RANGE_DEFAULT_THRESHOLD >> 2;
-        ByteDecoder {
-            decoder: super::Decoder::new(r),
+        ByteProcessor {
+            decoder: super::RangeDecoder::new(),
             freq: Model::new_flat(super::SYMBOL_TOTAL+1, freq_max),
             is_eof: false,
         }
     }
 
-    /// Finish decoding
-    pub fn finish(self) -> (R, io::Result<()>) {
-        self.decoder.finish()
+    /// Start decoding a new stream
+    pub fn reset(&mut self) {
+        self.decoder.reset();
+        self.freq.reset_flat();
+        self.is_eof = false;
     }
 }
 
-impl<R: Read> Read for ByteDecoder<R> {
-    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
-        if self.is_eof {
-            return Ok(0)
-        }
-        let mut amount = 0;
-        for out_byte in dst.iter_mut() {
+impl Process for ByteProcessor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let (mut pos, mut produced) = (0, 0);
+        loop {
+            // the code ends with the bytes pending after the terminator
+            if !self.decoder.feed(input, &mut pos) {
+                return Ok((pos, produced, Status::NeedInput))
+            }
+            if self.is_eof {
+                return Ok((pos, produced, Status::Done))
+            }
+            if produced == output.len() {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
             let value = try!(self.decoder.decode(&self.freq));
             if value == super::SYMBOL_TOTAL {
                 self.is_eof = true;
-                break
+                continue
             }
             self.freq.update(value, 10, 1);
-            *out_byte = value as u8;
-            amount += 1;
+            output[produced] = value as u8;
+            produced += 1;
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the symbols of the bytes fed may still decode up to the terminator
+        Ok(())
+    }
+}
+
+/// A basic byte-decoding arithmetic
+/// expects a special terminator code for the end of the stream
+#[cfg(feature="std")]
+pub struct ByteDecoder<R> {
+    inner: Reader<R, ByteProcessor>,
+}
+
+#[cfg(feature="std")]
+impl<R: Read> ByteDecoder<R> {
+    /// Create a decoder on top of a given Reader, which is left at the end of
+    /// the stream
+    pub fn new(r: R) -> ByteDecoder<R> {
+        let mut inner = Reader::new(r, ByteProcessor::new());
+        inner.set_read_ahead(false);
+        ByteDecoder {
+            inner: inner,
         }
-        Ok(amount)
+    }
+
+    /// Start decoding a new stream from `r`, returning the previous reader
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Finish decoding, which fails if the stream isn't over
+    pub fn finish(self) -> (R, io::Result<()>) {
+        let done = self.inner.is_done();
+        let (r, _) = self.inner.into_inner();
+        (r, if done { Ok(()) } else { truncated("truncated arithmetic stream") })
+    }
+}
+
+#[cfg(feature="std")]
+impl<R: Read> Read for ByteDecoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
     }
 }
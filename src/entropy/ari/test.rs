@@ -1,5 +1,6 @@
 use std::io::{BufReader, BufWriter, Write, Read};
 use std::vec::Vec;
+use testing::push;
 #[cfg(feature="unstable")]
 use test::Bencher;
 
@@ -17,6 +18,7 @@ fn roundtrip(bytes: &[u8]) {
     let mut decoded = Vec::new();
     d.read_to_end(&mut decoded).unwrap();
     assert_eq!(&bytes[..], &decoded[..]);
+    assert_eq!(&bytes[..], &push(&mut super::ByteProcessor::new(), &encoded, 3).unwrap()[..]);
 }
 
 fn encode_binary(bytes: &[u8], model: &mut super::bin::Model) -> Vec<u8> {
@@ -88,6 +90,48 @@ fn roundtrip_term(bytes1: &[u8], bytes2: &[u8]) {
     };
 }
 
+fn roundtrip_reset(bytes1: &[u8], bytes2: &[u8]) {
+    let mut e = super::table::ByteEncoder::new(Vec::new());
+    e.write(bytes1).unwrap();
+    let (first, rez) = e.reset(Vec::new());
+    rez.unwrap();
+    e.write(bytes2).unwrap();
+    let (second, rez) = e.finish();
+    rez.unwrap();
+    let mut fresh = super::table::ByteEncoder::new(Vec::new());
+    fresh.write(bytes2).unwrap();
+    assert_eq!(second, fresh.finish().0);
+
+    let mut d = super::ByteDecoder::new(BufReader::new(&first[..]));
+    let mut decoded = Vec::new();
+    d.read_to_end(&mut decoded).unwrap();
+    assert_eq!(&bytes1[..], &decoded[..]);
+    d.reset(BufReader::new(&second[..]));
+    decoded.truncate(0);
+    d.read_to_end(&mut decoded).unwrap();
+    assert_eq!(&bytes2[..], &decoded[..]);
+    d.finish().1.unwrap();
+
+    // the lower level coders, with models of their own
+    let mut e = super::Encoder::new(Vec::new());
+    let model = super::bin::Model::new_flat(super::RANGE_DEFAULT_THRESHOLD >> 3, 1);
+    for &bit in [true, false, true].iter() {
+        e.encode(bit, &model).unwrap();
+    }
+    let (first, rez) = e.reset(Vec::new());
+    rez.unwrap();
+    e.encode(false, &model).unwrap();
+    let (second, rez) = e.finish();
+    rez.unwrap();
+    let mut d = super::Decoder::new(&first[..]);
+    for &bit in [true, false, true].iter() {
+        assert_eq!(d.decode(&model).unwrap(), bit);
+    }
+    d.reset(&second[..]);
+    assert_eq!(d.decode(&model).unwrap(), false);
+    d.finish().1.unwrap();
+}
+
 fn roundtrip_proxy(bytes: &[u8]) {
     // prepare data
     let update0 = 10;
@@ -200,6 +244,11 @@ fn roundtrips_term() {
     roundtrip_term(b"abra", b"cadabra");
 }
 
+#[test]
+fn roundtrips_reset() {
+    roundtrip_reset(b"abracadabra", TEXT_INPUT);
+}
+
 #[test]
 fn roundtrips_proxy() {
     roundtrip_proxy(b"abracadabra");
@@ -0,0 +1,169 @@
+//! The errors of the decoders
+//!
+//! The decoders return `io::Error`s, as the `Read` trait does, of which the
+//! inner error is an `Error` telling what went wrong: the input may be
+//! corrupt or truncated, its data or a checksum may not match, it may exceed
+//! a limit or use a feature which isn't supported. `Error::of` retrieves it,
+//! so that callers can handle these cases apart from the errors of the
+//! underlying streams. Without `std`, the coders return an `Error` itself.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::io::Read;
+//! use compress::error::Error;
+//! use compress::lz4;
+//!
+//! let mut decompressed = Vec::new();
+//! let err = lz4::Decoder::new(&b"\x04\x22\x4d"[..]).read_to_end(&mut decompressed).unwrap_err();
+//! match Error::of(&err) {
+//!     Some(Error::UnexpectedEof { .. }) => {},
+//!     _ => panic!("the stream isn't truncated"),
+//! }
+//! ```
+
+#[cfg(feature="std")]
+use std::error;
+use std::fmt;
+use std::io;
+
+/// An error in the data given to a decoder
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The data is invalid
+    CorruptInput {
+        /// Position in the input of the invalid data, when known
+        offset: Option<u64>,
+        /// What is invalid
+        detail: &'static str,
+    },
+    /// The data ends before the end of the stream
+    UnexpectedEof {
+        /// What is truncated
+        detail: &'static str,
+    },
+    /// The checksum in the data differs from that of the decoded data
+    ChecksumMismatch {
+        /// What is checked
+        detail: &'static str,
+    },
+    /// The data exceeds a limit of the decoder
+    LimitExceeded {
+        /// What is over the limit
+        detail: &'static str,
+    },
+    /// The data uses a feature which isn't supported
+    Unsupported {
+        /// The unsupported feature
+        feature: &'static str,
+    },
+    /// The coding was stopped by a callback of the caller
+    Aborted,
+}
+
+#[cfg(feature="std")]
+impl Error {
+    /// Returns the error of the decoders within an I/O error, if any. The
+    /// end of the input met by `read_exact`, or by the reads of byteorder
+    /// which have an error of their own, is also an unexpected end.
+    pub fn of(err: &io::Error) -> Option<Error> {
+        if let Some(&inner) = err.get_ref().and_then(|inner| inner.downcast_ref::<Error>()) {
+            return Some(inner)
+        }
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => Some(Error::UnexpectedEof { detail: "unexpected end of file" }),
+            io::ErrorKind::Other if err.to_string() == "unexpected EOF" =>
+                Some(Error::UnexpectedEof { detail: "unexpected end of file" }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::CorruptInput { offset: Some(offset), detail } => write!(f, "{} at offset {}", detail, offset),
+            Error::CorruptInput { offset: None, detail } |
+            Error::UnexpectedEof { detail } |
+            Error::ChecksumMismatch { detail } |
+            Error::LimitExceeded { detail } => f.write_str(detail),
+            Error::Unsupported { feature } => f.write_str(feature),
+            Error::Aborted => f.write_str("aborted by the caller"),
+        }
+    }
+}
+
+#[cfg(feature="std")]
+impl error::Error for Error {}
+
+#[cfg(feature="std")]
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err {
+            Error::Aborted => io::ErrorKind::Other,
+            _ => io::ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// Returns the error of invalid data
+pub fn corrupt<T>(detail: &'static str) -> io::Result<T> {
+    Err(Error::CorruptInput { offset: None, detail: detail }.into())
+}
+
+/// Returns the error of invalid data at the given position of the input
+pub fn corrupt_at<T>(offset: u64, detail: &'static str) -> io::Result<T> {
+    Err(Error::CorruptInput { offset: Some(offset), detail: detail }.into())
+}
+
+/// Returns the error of truncated data
+pub fn truncated<T>(detail: &'static str) -> io::Result<T> {
+    Err(Error::UnexpectedEof { detail: detail }.into())
+}
+
+/// Returns the error of a checksum which doesn't match
+pub fn checksum_mismatch<T>(detail: &'static str) -> io::Result<T> {
+    Err(Error::ChecksumMismatch { detail: detail }.into())
+}
+
+/// Returns the error of data over a limit
+pub fn limit_exceeded<T>(detail: &'static str) -> io::Result<T> {
+    Err(Error::LimitExceeded { detail: detail }.into())
+}
+
+/// Returns the error of an unsupported feature
+pub fn unsupported<T>(feature: &'static str) -> io::Result<T> {
+    Err(Error::Unsupported { feature: feature }.into())
+}
+
+/// Returns the error of a coding stopped by the caller
+pub fn aborted<T>() -> io::Result<T> {
+    Err(Error::Aborted.into())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use super::{aborted, corrupt, corrupt_at, truncated, unsupported, Error};
+
+    #[test]
+    fn conversions() {
+        let err = corrupt::<()>("invalid data").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "invalid data");
+        assert_eq!(Error::of(&err), Some(Error::CorruptInput { offset: None, detail: "invalid data" }));
+        let err = corrupt_at::<()>(12, "invalid data").unwrap_err();
+        assert_eq!(err.to_string(), "invalid data at offset 12");
+        let err = truncated::<()>("truncated data").unwrap_err();
+        assert_eq!(Error::of(&err), Some(Error::UnexpectedEof { detail: "truncated data" }));
+        let err = unsupported::<()>("unsupported feature").unwrap_err();
+        assert_eq!(err.to_string(), "unsupported feature");
+        let err = aborted::<()>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(Error::of(&err), Some(Error::Aborted));
+        assert_eq!(Error::of(&io::Error::new(io::ErrorKind::Other, "other")), None);
+        let err = io::Read::read_exact(&mut &b"ab"[..], &mut [0; 3]).unwrap_err();
+        assert_eq!(Error::of(&err), Some(Error::UnexpectedEof { detail: "unexpected end of file" }));
+    }
+}
@@ -0,0 +1,662 @@
+/*!
+
+C interface. Requires `ffi` feature
+
+This module exports the main codecs to the programs written in other
+languages, as functions of the C calling convention. The crate is built as a
+shared library with:
+
+```text
+cargo rustc --release --features "ffi gzip lz4" --crate-type cdylib
+```
+
+and the declarations of the functions are in `include/compress.h`, which
+`header()` generates.
+
+The codecs are given by their `COMPRESS_*` number, those of which the feature
+is disabled being unknown. The functions return `COMPRESS_OK`, or a negative
+`COMPRESS_ERROR_*` status on failure, which is `COMPRESS_ERROR_IO` for a
+panic of the codec rather than an unwinding into the caller.
+
+`compress_buffer` and `decompress_buffer` code a whole buffer at once. The
+size of the destination is given by `*dst_len`, which is set to the size of
+the output. When it is too small, `compress_buffer` sets it to the size
+needed, while `decompress_buffer` stops decoding past it.
+
+`compress_stream_new` and `decompress_stream_new` create a stream, which
+`compress_stream_process` feeds with input and drains of output, and which
+`compress_stream_free` destroys. The encoders take all the input given at
+once, and the decoders decode the input as it comes, keeping the output which
+doesn't fit for the next calls.
+
+# Example
+
+```c
+#include "compress.h"
+
+uint8_t compressed[1024], decompressed[1024];
+size_t compressed_len = sizeof(compressed), decompressed_len = sizeof(decompressed);
+compress_buffer(COMPRESS_GZIP, -1, (const uint8_t *)"hello", 5, compressed, &compressed_len);
+decompress_buffer(COMPRESS_GZIP, compressed, compressed_len, decompressed, &decompressed_len);
+```
+
+*/
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::raw::c_int;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+
+#[cfg(any(feature="bzip2", feature="flate", feature="gzip", feature="lz4", feature="snappy"))]
+use codec;
+use codec::{Compressor, Level};
+use error::{truncated, Error};
+use process::{Process, Reader, Status};
+use OUTPUT_LIMIT_EXCEEDED;
+
+#[cfg(feature="bzip2")]
+use bzip2;
+#[cfg(feature="flate")]
+use flate;
+#[cfg(feature="gzip")]
+use gzip;
+#[cfg(feature="lz4")]
+use lz4;
+#[cfg(feature="lzma")]
+use lzma;
+#[cfg(feature="snappy")]
+use snappy;
+#[cfg(feature="zlib")]
+use zlib;
+#[cfg(feature="zstd")]
+use zstd;
+
+/// Raw DEFLATE data
+pub const COMPRESS_DEFLATE: c_int = 1;
+/// The gzip file format
+pub const COMPRESS_GZIP: c_int = 2;
+/// The zlib format, of which only the decoder exists
+pub const COMPRESS_ZLIB: c_int = 3;
+/// The LZ4 frame format
+pub const COMPRESS_LZ4: c_int = 4;
+/// The bzip2 file format
+pub const COMPRESS_BZIP2: c_int = 5;
+/// The framing format of snappy
+pub const COMPRESS_SNAPPY: c_int = 6;
+/// The zstd format, of which only the decoder exists
+pub const COMPRESS_ZSTD: c_int = 7;
+/// The .xz format, of which only the decoder exists
+pub const COMPRESS_XZ: c_int = 8;
+
+/// Success
+pub const COMPRESS_OK: c_int = 0;
+/// Success, with the end of the stream reached and its output all given
+pub const COMPRESS_END: c_int = 1;
+/// The codec is unknown, disabled, or has no encoder
+pub const COMPRESS_ERROR_CODEC: c_int = -1;
+/// A pointer is null
+pub const COMPRESS_ERROR_ARGUMENT: c_int = -2;
+/// The destination is too small for the output
+pub const COMPRESS_ERROR_BUFFER: c_int = -3;
+/// The input is invalid
+pub const COMPRESS_ERROR_CORRUPT: c_int = -4;
+/// The input ends before the end of the stream
+pub const COMPRESS_ERROR_TRUNCATED: c_int = -5;
+/// The checksum of the input differs from that of the output
+pub const COMPRESS_ERROR_CHECKSUM: c_int = -6;
+/// The input exceeds a limit of the decoder
+pub const COMPRESS_ERROR_LIMIT: c_int = -7;
+/// The input uses a feature which isn't supported
+pub const COMPRESS_ERROR_UNSUPPORTED: c_int = -8;
+/// Any other failure
+pub const COMPRESS_ERROR_IO: c_int = -9;
+
+/// The constants of the header, with their description
+static CONSTANTS: [(&'static str, c_int, &'static str); 19] = [
+    ("COMPRESS_DEFLATE", COMPRESS_DEFLATE, "Raw DEFLATE data"),
+    ("COMPRESS_GZIP", COMPRESS_GZIP, "The gzip file format"),
+    ("COMPRESS_ZLIB", COMPRESS_ZLIB, "The zlib format, of which only the decoder exists"),
+    ("COMPRESS_LZ4", COMPRESS_LZ4, "The LZ4 frame format"),
+    ("COMPRESS_BZIP2", COMPRESS_BZIP2, "The bzip2 file format"),
+    ("COMPRESS_SNAPPY", COMPRESS_SNAPPY, "The framing format of snappy"),
+    ("COMPRESS_ZSTD", COMPRESS_ZSTD, "The zstd format, of which only the decoder exists"),
+    ("COMPRESS_XZ", COMPRESS_XZ, "The .xz format, of which only the decoder exists"),
+    ("COMPRESS_OK", COMPRESS_OK, "Success"),
+    ("COMPRESS_END", COMPRESS_END, "Success, with the end of the stream reached and its output all given"),
+    ("COMPRESS_ERROR_CODEC", COMPRESS_ERROR_CODEC, "The codec is unknown, disabled, or has no encoder"),
+    ("COMPRESS_ERROR_ARGUMENT", COMPRESS_ERROR_ARGUMENT, "A pointer is null"),
+    ("COMPRESS_ERROR_BUFFER", COMPRESS_ERROR_BUFFER, "The destination is too small for the output"),
+    ("COMPRESS_ERROR_CORRUPT", COMPRESS_ERROR_CORRUPT, "The input is invalid"),
+    ("COMPRESS_ERROR_TRUNCATED", COMPRESS_ERROR_TRUNCATED, "The input ends before the end of the stream"),
+    ("COMPRESS_ERROR_CHECKSUM", COMPRESS_ERROR_CHECKSUM, "The checksum of the input differs from that of the output"),
+    ("COMPRESS_ERROR_LIMIT", COMPRESS_ERROR_LIMIT, "The input exceeds a limit of the decoder"),
+    ("COMPRESS_ERROR_UNSUPPORTED", COMPRESS_ERROR_UNSUPPORTED, "The input uses a feature which isn't supported"),
+    ("COMPRESS_ERROR_IO", COMPRESS_ERROR_IO, "Any other failure"),
+];
+
+/// The functions of the header, with their description
+static FUNCTIONS: [(&'static str, &'static str); 6] = [
+    ("Compresses src into dst at level, from 0, or at the default level if negative.\n\
+      On COMPRESS_ERROR_BUFFER, *dst_len is set to the size needed.",
+     "int compress_buffer(int codec, int level, const uint8_t *src, size_t src_len, uint8_t *dst, size_t *dst_len);"),
+    ("Decompresses src into dst, stopping on COMPRESS_ERROR_BUFFER once the output exceeds *dst_len.",
+     "int decompress_buffer(int codec, const uint8_t *src, size_t src_len, uint8_t *dst, size_t *dst_len);"),
+    ("Creates a compression stream, or returns NULL if the codec has no encoder.",
+     "compress_stream *compress_stream_new(int codec, int level);"),
+    ("Creates a decompression stream, or returns NULL if the codec is unknown.",
+     "compress_stream *decompress_stream_new(int codec);"),
+    ("Consumes up to *src_len bytes of src and produces up to *dst_len bytes into dst,\n\
+      setting both to the sizes used. finish is set once src holds the end of the input.\n\
+      Returns COMPRESS_END once the stream is over, and its output all produced.",
+     "int compress_stream_process(compress_stream *stream, const uint8_t *src, size_t *src_len, \
+      uint8_t *dst, size_t *dst_len, int finish);"),
+    ("Destroys a stream.",
+     "void compress_stream_free(compress_stream *stream);"),
+];
+
+/// Returns the declarations of the C interface, the content of
+/// `include/compress.h`
+pub fn header() -> String {
+    let mut h = String::new();
+    h.push_str("/* The C interface of the compress crate, generated by compress::ffi::header() */\n\n");
+    h.push_str("#ifndef COMPRESS_H\n#define COMPRESS_H\n\n");
+    h.push_str("#include <stddef.h>\n#include <stdint.h>\n\n");
+    h.push_str("#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n");
+    for &(name, value, doc) in CONSTANTS.iter() {
+        h.push_str(&format!("/* {} */\n#define {} {}\n", doc, name, value));
+    }
+    h.push_str("\n/* A compression or decompression stream */\n");
+    h.push_str("typedef struct compress_stream compress_stream;\n");
+    for &(doc, declaration) in FUNCTIONS.iter() {
+        h.push_str(&format!("\n/* {} */\n{}\n", doc.replace("\n", "\n   "), declaration));
+    }
+    h.push_str("\n#ifdef __cplusplus\n}\n#endif\n\n#endif\n");
+    h
+}
+
+/// Runs the body of a function of the interface, of which a panic is
+/// returned as `COMPRESS_ERROR_IO` rather than unwinding into the caller
+fn guard<F: FnOnce() -> c_int>(f: F) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(COMPRESS_ERROR_IO)
+}
+
+/// Returns the status of an error
+fn status(err: &io::Error) -> c_int {
+    match Error::of(err) {
+        Some(Error::CorruptInput { .. }) => COMPRESS_ERROR_CORRUPT,
+        Some(Error::UnexpectedEof { .. }) => COMPRESS_ERROR_TRUNCATED,
+        Some(Error::ChecksumMismatch { .. }) => COMPRESS_ERROR_CHECKSUM,
+        Some(Error::LimitExceeded { .. }) => COMPRESS_ERROR_LIMIT,
+        Some(Error::Unsupported { .. }) => COMPRESS_ERROR_UNSUPPORTED,
+        _ => COMPRESS_ERROR_IO,
+    }
+}
+
+/// Maps the level of the C interface, negative for the default one
+fn level(level: c_int) -> Level {
+    if level < 0 { Level::Default } else { Level::Numeric(level as u32) }
+}
+
+/// Returns the compressor of the codec numbered `codec` at `level`, if it is
+/// enabled and has an encoder
+fn compressor(codec: c_int, level: c_int) -> Option<Box<dyn Compressor>> {
+    let compressor: Option<Box<dyn Compressor>> = match codec {
+        #[cfg(feature="flate")]
+        COMPRESS_DEFLATE => Some(Box::new(codec::Deflate { level: 6 })),
+        #[cfg(feature="gzip")]
+        COMPRESS_GZIP => Some(Box::new(codec::Gzip { level: 6 })),
+        #[cfg(feature="lz4")]
+        COMPRESS_LZ4 => Some(Box::new(codec::Lz4)),
+        #[cfg(feature="bzip2")]
+        COMPRESS_BZIP2 => Some(Box::new(codec::Bzip2 { level: 9 })),
+        #[cfg(feature="snappy")]
+        COMPRESS_SNAPPY => Some(Box::new(codec::Snappy)),
+        _ => None,
+    };
+    compressor.map(|mut c| {
+        c.set_level(self::level(level));
+        c
+    })
+}
+
+/// Returns the streaming decoder of the codec numbered `codec`, if it is
+/// enabled
+fn processor(codec: c_int) -> Option<Box<dyn Process>> {
+    match codec {
+        #[cfg(feature="flate")]
+        COMPRESS_DEFLATE => Some(Box::new(flate::Processor::new())),
+        #[cfg(feature="gzip")]
+        COMPRESS_GZIP => Some(Box::new(gzip::Processor::new())),
+        #[cfg(feature="zlib")]
+        COMPRESS_ZLIB => Some(Box::new(zlib::Processor::new())),
+        #[cfg(feature="lz4")]
+        COMPRESS_LZ4 => Some(Box::new(lz4::Processor::new())),
+        #[cfg(feature="bzip2")]
+        COMPRESS_BZIP2 => Some(Box::new(bzip2::Processor::new())),
+        #[cfg(feature="snappy")]
+        COMPRESS_SNAPPY => Some(Box::new(snappy::Processor::new())),
+        #[cfg(feature="zstd")]
+        COMPRESS_ZSTD => Some(Box::new(zstd::Processor::new())),
+        #[cfg(feature="lzma")]
+        COMPRESS_XZ => Some(Box::new(lzma::xz::Processor::new())),
+        _ => None,
+    }
+}
+
+/// The writer of the encoders of the streams, keeping their output until it
+/// is taken
+#[derive(Clone)]
+struct Sink {
+    buf: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An encoder of a stream, ending it by `finish`
+trait StreamEncoder: Write {
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+macro_rules! stream_encoders {
+    ($($feature:tt => $encoder:ty),*) => {$(
+        #[cfg(feature=$feature)]
+        impl StreamEncoder for $encoder {
+            fn finish(self: Box<Self>) -> io::Result<()> {
+                (*self).finish().1
+            }
+        }
+    )*}
+}
+
+stream_encoders! {
+    "flate" => flate::Encoder<Sink>,
+    "gzip" => gzip::Encoder<Sink>,
+    "lz4" => lz4::Encoder<Sink>,
+    "bzip2" => bzip2::Encoder<Sink>,
+    "snappy" => snappy::Encoder<Sink>
+}
+
+/// Returns the coding of a stream encoded with the codec numbered `codec` at
+/// `level`, with the level of its compressor
+fn stream_encoder(codec: c_int, level: c_int) -> Option<Coding> {
+    let sink = Sink { buf: Rc::new(RefCell::new(Vec::new())) };
+    // the level and the sink are bound by the arms of the enabled codecs
+    let encoder: Option<Box<dyn StreamEncoder>> = match (codec, self::level(level), sink.clone()) {
+        #[cfg(feature="flate")]
+        (COMPRESS_DEFLATE, level, sink) => {
+            let mut c = codec::Deflate { level: 6 };
+            c.set_level(level);
+            Some(Box::new(flate::Encoder::new(sink, c.level)))
+        }
+        #[cfg(feature="gzip")]
+        (COMPRESS_GZIP, level, sink) => {
+            let mut c = codec::Gzip { level: 6 };
+            c.set_level(level);
+            Some(Box::new(gzip::Encoder::new(sink, c.level)))
+        }
+        #[cfg(feature="lz4")]
+        (COMPRESS_LZ4, _, sink) => Some(Box::new(lz4::Encoder::new(sink))),
+        #[cfg(feature="bzip2")]
+        (COMPRESS_BZIP2, level, sink) => {
+            let mut c = codec::Bzip2 { level: 9 };
+            c.set_level(level);
+            Some(Box::new(bzip2::Encoder::new(sink, c.level)))
+        }
+        #[cfg(feature="snappy")]
+        (COMPRESS_SNAPPY, _, sink) => Some(Box::new(snappy::Encoder::new(sink))),
+        _ => None,
+    };
+    encoder.map(|e| Coding::Encode(Some(e), sink))
+}
+
+/// How a stream codes its data
+enum Coding {
+    /// An encoder, until the stream is finished, and its output
+    Encode(Option<Box<dyn StreamEncoder>>, Sink),
+    /// A decoder taking the input as it comes, and whether it was told the
+    /// input is over
+    Decode(Box<dyn Process>, bool),
+}
+
+/// A compression or decompression stream of the C interface
+pub struct Stream {
+    coding: Coding,
+    /// The output of the encoder not given yet
+    out: Vec<u8>,
+    out_pos: usize,
+}
+
+impl Stream {
+    fn new(coding: Coding) -> Stream {
+        Stream { coding: coding, out: Vec::new(), out_pos: 0 }
+    }
+
+    /// Codes from `input` into `output`, returning the bytes consumed and
+    /// produced, and whether the stream is over
+    fn process(&mut self, input: &[u8], output: &mut [u8], finish: bool) -> io::Result<(usize, usize, bool)> {
+        match self.coding {
+            Coding::Encode(ref mut encoder, ref sink) => {
+                let mut consumed = 0;
+                if let Some(ref mut e) = *encoder {
+                    try!(e.write_all(input));
+                    consumed = input.len();
+                }
+                if finish {
+                    if let Some(e) = encoder.take() {
+                        try!(e.finish());
+                    }
+                }
+                if self.out_pos == self.out.len() {
+                    self.out = mem::take(&mut *sink.buf.borrow_mut());
+                    self.out_pos = 0;
+                }
+                let n = ::std::cmp::min(output.len(), self.out.len() - self.out_pos);
+                output[..n].copy_from_slice(&self.out[self.out_pos..(self.out_pos + n)]);
+                self.out_pos += n;
+                let done = encoder.is_none() && self.out_pos == self.out.len() && sink.buf.borrow().is_empty();
+                Ok((consumed, n, done))
+            }
+            Coding::Decode(ref mut p, ref mut finished) => {
+                let (consumed, mut produced, mut status) = try!(p.process(input, output));
+                if status == Status::NeedInput && finish && !*finished {
+                    // the output the decoder keeps until the end of the input
+                    try!(p.finish());
+                    *finished = true;
+                    let (_, n, next) = try!(p.process(&[], &mut output[produced..]));
+                    produced += n;
+                    status = next;
+                }
+                match status {
+                    Status::Done => Ok((consumed, produced, true)),
+                    Status::NeedInput if *finished => truncated("truncated stream"),
+                    _ => Ok((consumed, produced, false)),
+                }
+            }
+        }
+    }
+}
+
+/// Returns the slice of `len` bytes at `ptr`, which may be null when empty
+unsafe fn input<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 { &[] } else { slice::from_raw_parts(ptr, len) }
+}
+
+/// Returns the slice of `len` bytes at `ptr`, which may be null when empty
+unsafe fn output<'a>(ptr: *mut u8, len: usize) -> &'a mut [u8] {
+    if len == 0 { &mut [] } else { slice::from_raw_parts_mut(ptr, len) }
+}
+
+/// Copies `data` into the destination of `*dst_len` bytes
+unsafe fn copy_out(data: &[u8], dst: *mut u8, dst_len: *mut usize) -> c_int {
+    let fits = data.len() <= *dst_len;
+    *dst_len = data.len();
+    if !fits {
+        return COMPRESS_ERROR_BUFFER
+    }
+    output(dst, data.len()).copy_from_slice(data);
+    COMPRESS_OK
+}
+
+/// Compresses `src` into `dst` with the codec numbered `codec`, at `level`
+/// or at its default level if negative
+///
+/// # Safety
+///
+/// `src` and `dst` must point to `src_len` and `*dst_len` bytes, unless
+/// these are 0
+#[no_mangle]
+pub unsafe extern "C" fn compress_buffer(codec: c_int, level: c_int, src: *const u8, src_len: usize,
+                                         dst: *mut u8, dst_len: *mut usize) -> c_int {
+    if (src.is_null() && src_len > 0) || dst_len.is_null() || (dst.is_null() && *dst_len > 0) {
+        return COMPRESS_ERROR_ARGUMENT
+    }
+    guard(|| {
+        let compressor = match compressor(codec, level) {
+            Some(compressor) => compressor,
+            None => return COMPRESS_ERROR_CODEC,
+        };
+        match compressor.compress_to_vec(input(src, src_len)) {
+            Ok(data) => copy_out(&data, dst, dst_len),
+            Err(e) => status(&e),
+        }
+    })
+}
+
+/// Decompresses `src` into `dst` with the codec numbered `codec`, decoding no
+/// more than `*dst_len` bytes, and fails with `COMPRESS_ERROR_BUFFER` if the
+/// output is larger
+///
+/// # Safety
+///
+/// `src` and `dst` must point to `src_len` and `*dst_len` bytes, unless
+/// these are 0
+#[no_mangle]
+pub unsafe extern "C" fn decompress_buffer(codec: c_int, src: *const u8, src_len: usize,
+                                           dst: *mut u8, dst_len: *mut usize) -> c_int {
+    if (src.is_null() && src_len > 0) || dst_len.is_null() || (dst.is_null() && *dst_len > 0) {
+        return COMPRESS_ERROR_ARGUMENT
+    }
+    guard(|| {
+        let p = match processor(codec) {
+            Some(p) => p,
+            None => return COMPRESS_ERROR_CODEC,
+        };
+        let mut r = Reader::new(input(src, src_len), p);
+        r.set_max_output_size(*dst_len as u64);
+        let mut data = Vec::new();
+        match r.read_to_end(&mut data) {
+            Ok(_) => copy_out(&data, dst, dst_len),
+            Err(ref e) if Error::of(e) == Some(Error::LimitExceeded { detail: OUTPUT_LIMIT_EXCEEDED }) => {
+                COMPRESS_ERROR_BUFFER
+            }
+            Err(e) => status(&e),
+        }
+    })
+}
+
+/// Creates a compression stream with the codec numbered `codec`, at `level`
+/// or at its default level if negative, or returns null if the codec has no
+/// encoder
+#[no_mangle]
+pub extern "C" fn compress_stream_new(codec: c_int, level: c_int) -> *mut Stream {
+    match stream_encoder(codec, level) {
+        Some(coding) => Box::into_raw(Box::new(Stream::new(coding))),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Creates a decompression stream with the codec numbered `codec`, or
+/// returns null if it is unknown
+#[no_mangle]
+pub extern "C" fn decompress_stream_new(codec: c_int) -> *mut Stream {
+    match processor(codec) {
+        Some(p) => Box::into_raw(Box::new(Stream::new(Coding::Decode(p, false)))),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Consumes up to `*src_len` bytes of `src` and produces up to `*dst_len`
+/// bytes into `dst`, setting both to the sizes used. `finish` is set once
+/// `src` holds the end of the input. Returns `COMPRESS_END` once the stream
+/// is over and its output all produced.
+///
+/// # Safety
+///
+/// `stream` must have been created by `compress_stream_new` or
+/// `decompress_stream_new`, and `src` and `dst` must point to `*src_len`
+/// and `*dst_len` bytes, unless these are 0
+#[no_mangle]
+pub unsafe extern "C" fn compress_stream_process(stream: *mut Stream, src: *const u8, src_len: *mut usize,
+                                                 dst: *mut u8, dst_len: *mut usize, finish: c_int) -> c_int {
+    if stream.is_null() || src_len.is_null() || dst_len.is_null() ||
+            (src.is_null() && *src_len > 0) || (dst.is_null() && *dst_len > 0) {
+        return COMPRESS_ERROR_ARGUMENT
+    }
+    let stream = &mut *stream;
+    guard(|| {
+        match stream.process(input(src, *src_len), output(dst, *dst_len), finish != 0) {
+            Ok((consumed, produced, done)) => {
+                *src_len = consumed;
+                *dst_len = produced;
+                if done { COMPRESS_END } else { COMPRESS_OK }
+            }
+            Err(e) => status(&e),
+        }
+    })
+}
+
+/// Destroys a stream
+///
+/// # Safety
+///
+/// `stream` must have been created by `compress_stream_new` or
+/// `decompress_stream_new`, and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn compress_stream_free(stream: *mut Stream) {
+    if !stream.is_null() {
+        drop(Box::from_raw(stream));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature="snappy")]
+    use std::ptr;
+    use super::*;
+
+    #[test]
+    fn header_is_current() {
+        assert_eq!(include_str!("../include/compress.h"), header());
+        // every function exported is declared
+        let source = include_str!("ffi.rs");
+        for item in source.split("\n#[no_mangle]\n").skip(1) {
+            let name = item.split("fn ").nth(1).unwrap().split('(').next().unwrap();
+            assert!(FUNCTIONS.iter().any(|&(_, decl)| decl.contains(&format!(" {}(", name))
+                                                       || decl.contains(&format!("*{}(", name))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn panics() {
+        assert_eq!(guard(|| panic!("a codec failing")), COMPRESS_ERROR_IO);
+        assert_eq!(guard(|| COMPRESS_OK), COMPRESS_OK);
+    }
+
+    #[cfg(feature="snappy")]
+    #[test]
+    fn buffers() {
+        let data = include_bytes!("data/test.txt");
+        let mut compressed = vec![0u8; 2 * data.len()];
+        let mut len = compressed.len();
+        unsafe {
+            assert_eq!(compress_buffer(COMPRESS_SNAPPY, -1, data.as_ptr(), data.len(),
+                                       compressed.as_mut_ptr(), &mut len), COMPRESS_OK);
+            compressed.truncate(len);
+
+            let mut decompressed = vec![0u8; 10];
+            let mut len = decompressed.len();
+            assert_eq!(decompress_buffer(COMPRESS_SNAPPY, compressed.as_ptr(), compressed.len(),
+                                         decompressed.as_mut_ptr(), &mut len), COMPRESS_ERROR_BUFFER);
+            // the decoder stops past the destination, not knowing the size
+            assert_eq!(len, 10);
+            decompressed.resize(data.len(), 0);
+            let mut len = decompressed.len();
+            assert_eq!(decompress_buffer(COMPRESS_SNAPPY, compressed.as_ptr(), compressed.len(),
+                                         decompressed.as_mut_ptr(), &mut len), COMPRESS_OK);
+            assert!(decompressed[..] == data[..]);
+
+            let mut len = decompressed.len();
+            assert_eq!(decompress_buffer(COMPRESS_SNAPPY, compressed.as_ptr(), compressed.len() - 5,
+                                         decompressed.as_mut_ptr(), &mut len), COMPRESS_ERROR_TRUNCATED);
+            compressed[20] ^= 0xff;
+            let mut len = decompressed.len();
+            assert!(decompress_buffer(COMPRESS_SNAPPY, compressed.as_ptr(), compressed.len(),
+                                      decompressed.as_mut_ptr(), &mut len) < 0);
+            assert_eq!(compress_buffer(100, -1, data.as_ptr(), data.len(), ptr::null_mut(), &mut 0),
+                       COMPRESS_ERROR_CODEC);
+            assert_eq!(compress_buffer(COMPRESS_SNAPPY, -1, ptr::null(), 10, ptr::null_mut(), &mut 0),
+                       COMPRESS_ERROR_ARGUMENT);
+        }
+    }
+
+    /// Codes `input` through `stream`, in pieces of `chunk` bytes
+    #[cfg(all(feature="lz4", feature="snappy"))]
+    unsafe fn code(stream: *mut Stream, input: &[u8], chunk: usize) -> Result<Vec<u8>, i32> {
+        assert!(!stream.is_null());
+        let mut out = Vec::new();
+        let mut pos = 0;
+        loop {
+            let mut src_len = ::std::cmp::min(chunk, input.len() - pos);
+            let finish = (pos + src_len == input.len()) as c_int;
+            let mut buf = [0u8; 100];
+            let mut dst_len = buf.len();
+            let status = compress_stream_process(stream, input[pos..].as_ptr(), &mut src_len,
+                                                 buf.as_mut_ptr(), &mut dst_len, finish);
+            if status < 0 {
+                compress_stream_free(stream);
+                return Err(status)
+            }
+            pos += src_len;
+            out.extend_from_slice(&buf[..dst_len]);
+            if status == COMPRESS_END {
+                compress_stream_free(stream);
+                return Ok(out)
+            }
+        }
+    }
+
+    #[cfg(all(feature="lz4", feature="snappy"))]
+    #[test]
+    fn streams() {
+        let data = include_bytes!("data/test.txt");
+        #[allow(unused_mut)]
+        let mut codecs = vec![COMPRESS_LZ4, COMPRESS_SNAPPY];
+        #[cfg(feature="flate")]
+        codecs.push(COMPRESS_DEFLATE);
+        #[cfg(feature="gzip")]
+        codecs.push(COMPRESS_GZIP);
+        #[cfg(feature="bzip2")]
+        codecs.push(COMPRESS_BZIP2);
+        unsafe {
+            for &codec in codecs.iter() {
+                let compressed = code(compress_stream_new(codec, -1), data, 333).unwrap();
+                assert!(&code(decompress_stream_new(codec), &compressed, 77).unwrap()[..] == &data[..]);
+                assert_eq!(code(decompress_stream_new(codec), &compressed[..50], 7),
+                           Err(COMPRESS_ERROR_TRUNCATED));
+            }
+            assert!(compress_stream_new(COMPRESS_ZSTD, -1).is_null());
+            assert!(decompress_stream_new(100).is_null());
+        }
+    }
+
+    #[cfg(all(feature="lz4", feature="snappy", feature="zlib", feature="zstd", feature="lzma"))]
+    #[test]
+    fn decoder_streams() {
+        let data = include_bytes!("data/test.txt");
+        let streams: [(c_int, &[u8]); 3] = [
+            (COMPRESS_ZLIB, include_bytes!("data/test.z.9")),
+            (COMPRESS_ZSTD, include_bytes!("data/test.zst")),
+            (COMPRESS_XZ, include_bytes!("data/test.xz")),
+        ];
+        unsafe {
+            for &(codec, compressed) in streams.iter() {
+                assert!(&code(decompress_stream_new(codec), compressed, 13).unwrap()[..] == &data[..]);
+                assert_eq!(code(decompress_stream_new(codec), &compressed[..compressed.len() - 4], 13),
+                           Err(COMPRESS_ERROR_TRUNCATED));
+            }
+        }
+    }
+}
@@ -0,0 +1,519 @@
+//! The branch converters of executables
+//!
+//! The calls and jumps of machine code hold the distance to their target, so
+//! that calls to a same function are all different. These filters, known as
+//! BCJ, replace the distances by the targets, which repeat, before the
+//! compression, and back after the decompression. They are those of xz and
+//! 7z, for x86 (the `E8` calls and `E9` jumps, with a heuristic skipping the
+//! bytes which don't look like instructions), 32-bit ARM (the `BL`
+//! instructions) and ARM64 (the `BL` and `ADRP` instructions).
+//!
+//! As an instruction may span the end of a buffer, the filters convert the
+//! start of the buffers they are given and return its size, the rest having
+//! to be given again with the data which follows. The bytes at the end of the
+//! data are left as such. The positions of the instructions are counted from
+//! a start offset, which is that of the data in the executable file.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::io::{Read, Write};
+//! use compress::filter::bcj;
+//!
+//! let code = [0xe8, 0xfb, 0xff, 0xff, 0xff, 0x90, 0x90, 0x90];
+//! let mut e = bcj::Encoder::new(Vec::new(), bcj::Arch::X86, 0);
+//! e.write_all(&code).unwrap();
+//! let (encoded, result) = e.finish();
+//! result.unwrap();
+//! // a call to the start of the code
+//! assert_eq!(&encoded[..5], &[0xe8, 0, 0, 0, 0]);
+//!
+//! let mut d = bcj::Decoder::new(&encoded[..], bcj::Arch::X86, 0);
+//! let mut decoded = Vec::new();
+//! d.read_to_end(&mut decoded).unwrap();
+//! assert_eq!(&decoded[..], &code[..]);
+//! ```
+//!
+//! # Related links
+//!
+//! * https://tukaani.org/xz/ - the xz tools, whose `src/liblzma/simple`
+//!   sources these filters follow
+
+use std::cmp;
+use std::mem;
+use std::io::{self, Read, Write};
+
+use process::{Process, Reader, Status};
+
+/// Size of the input converted at once by the decoder
+const BUF_SIZE: usize = 1 << 16;
+
+/// The instruction set of the code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arch {
+    /// 32 and 64-bit x86
+    X86,
+    /// 32-bit ARM, little-endian
+    Arm,
+    /// 64-bit ARM
+    Arm64,
+}
+
+impl Arch {
+    /// The alignment of the instructions
+    fn alignment(&self) -> u32 {
+        match *self {
+            Arch::X86 => 1,
+            Arch::Arm | Arch::Arm64 => 4,
+        }
+    }
+}
+
+/// Whether the byte may be the highest of the distance of a x86 call
+fn is_x86_ms_byte(byte: u8) -> bool {
+    byte == 0 || byte == 0xff
+}
+
+/// The state of a filter
+pub struct Bcj {
+    arch: Arch,
+    /// Offset of the data in the executable, and position of the next byte
+    start: u32,
+    pos: u32,
+    /// Which of the last bytes of x86 code were `E8` or `E9`
+    prev_mask: u32,
+    prev_pos: u32,
+}
+
+impl Bcj {
+    /// Creates a filter of the given instruction set, for data found at the
+    /// `start` offset of the executable, a multiple of 4 for ARM
+    pub fn new(arch: Arch, start: u32) -> Bcj {
+        assert!(start % arch.alignment() == 0, "unaligned bcj start offset {}", start);
+        Bcj {
+            arch: arch,
+            start: start,
+            pos: start,
+            prev_mask: 0,
+            prev_pos: start.wrapping_sub(5),
+        }
+    }
+
+    /// Starts filtering new data, found at the same offset
+    pub fn reset(&mut self) {
+        self.pos = self.start;
+        self.prev_mask = 0;
+        self.prev_pos = self.start.wrapping_sub(5);
+    }
+
+    /// Converts the start of the buffer before the compression, returning the
+    /// number of bytes converted
+    pub fn encode(&mut self, buf: &mut [u8]) -> usize {
+        self.convert(buf, true)
+    }
+
+    /// Converts the start of the buffer after the decompression, returning
+    /// the number of bytes converted
+    pub fn decode(&mut self, buf: &mut [u8]) -> usize {
+        self.convert(buf, false)
+    }
+
+    fn convert(&mut self, buf: &mut [u8], encoder: bool) -> usize {
+        let n = match self.arch {
+            Arch::X86 => self.x86(buf, encoder),
+            Arch::Arm => self.arm(buf, encoder),
+            Arch::Arm64 => self.arm64(buf, encoder),
+        };
+        self.pos = self.pos.wrapping_add(n as u32);
+        n
+    }
+
+    fn x86(&mut self, buf: &mut [u8], encoder: bool) -> usize {
+        const ALLOWED: [bool; 8] = [true, true, true, false, true, false, false, false];
+        const BIT_NUMBER: [u32; 8] = [0, 1, 2, 2, 3, 3, 3, 3];
+        if buf.len() < 5 {
+            return 0
+        }
+        if self.pos.wrapping_sub(self.prev_pos) > 5 {
+            self.prev_pos = self.pos.wrapping_sub(5);
+        }
+        let mut i = 0;
+        while i + 5 <= buf.len() {
+            if buf[i] != 0xe8 && buf[i] != 0xe9 {
+                i += 1;
+                continue
+            }
+            let pos = self.pos.wrapping_add(i as u32);
+            let offset = pos.wrapping_sub(self.prev_pos);
+            self.prev_pos = pos;
+            if offset > 5 {
+                self.prev_mask = 0;
+            } else {
+                for _ in 0..offset {
+                    self.prev_mask = (self.prev_mask & 0x77) << 1;
+                }
+            }
+            let byte = buf[i + 4];
+            if !is_x86_ms_byte(byte) || !ALLOWED[((self.prev_mask >> 1) & 7) as usize] ||
+                    (self.prev_mask >> 1) >= 0x10 {
+                i += 1;
+                self.prev_mask |= 1;
+                if is_x86_ms_byte(byte) {
+                    self.prev_mask |= 0x10;
+                }
+                continue
+            }
+            let mut src = (byte as u32) << 24 | (buf[i + 3] as u32) << 16 |
+                          (buf[i + 2] as u32) << 8 | buf[i + 1] as u32;
+            let next = pos.wrapping_add(5);
+            let mut dest;
+            loop {
+                dest = if encoder { src.wrapping_add(next) } else { src.wrapping_sub(next) };
+                if self.prev_mask == 0 {
+                    break
+                }
+                let bits = BIT_NUMBER[(self.prev_mask >> 1) as usize] * 8;
+                if !is_x86_ms_byte((dest >> (24 - bits)) as u8) {
+                    break
+                }
+                src = dest ^ ((1 << (32 - bits)) - 1);
+            }
+            // the highest byte is extended from bit 24
+            buf[i + 4] = !(((dest >> 24) & 1).wrapping_sub(1)) as u8;
+            buf[i + 3] = (dest >> 16) as u8;
+            buf[i + 2] = (dest >> 8) as u8;
+            buf[i + 1] = dest as u8;
+            i += 5;
+            self.prev_mask = 0;
+        }
+        i
+    }
+
+    fn arm(&mut self, buf: &mut [u8], encoder: bool) -> usize {
+        let mut i = 0;
+        while i + 4 <= buf.len() {
+            if buf[i + 3] == 0xeb {
+                let src = ((buf[i + 2] as u32) << 16 | (buf[i + 1] as u32) << 8 |
+                           buf[i] as u32) << 2;
+                let pc = self.pos.wrapping_add(i as u32 + 8);
+                let dest = if encoder { src.wrapping_add(pc) } else { src.wrapping_sub(pc) } >> 2;
+                buf[i + 2] = (dest >> 16) as u8;
+                buf[i + 1] = (dest >> 8) as u8;
+                buf[i] = dest as u8;
+            }
+            i += 4;
+        }
+        i
+    }
+
+    fn arm64(&mut self, buf: &mut [u8], encoder: bool) -> usize {
+        let mut i = 0;
+        while i + 4 <= buf.len() {
+            let mut instr = (buf[i + 3] as u32) << 24 | (buf[i + 2] as u32) << 16 |
+                            (buf[i + 1] as u32) << 8 | buf[i] as u32;
+            let pc = self.pos.wrapping_add(i as u32);
+            if instr >> 26 == 0x25 {
+                // BL, whose distance counts 4-byte words
+                let pc = if encoder { pc >> 2 } else { (pc >> 2).wrapping_neg() };
+                instr = 0x94000000 | (instr.wrapping_add(pc) & 0x03ffffff);
+            } else if instr & 0x9f000000 == 0x90000000 {
+                // ADRP, whose distance counts 4 kB pages, converted within
+                // 512 MB only
+                let src = ((instr >> 29) & 3) | ((instr >> 3) & 0x001ffffc);
+                if src.wrapping_add(0x00020000) & 0x001c0000 != 0 {
+                    i += 4;
+                    continue
+                }
+                let pc = if encoder { pc >> 12 } else { (pc >> 12).wrapping_neg() };
+                let dest = src.wrapping_add(pc);
+                instr &= 0x9000001f;
+                instr |= (dest & 3) << 29;
+                instr |= (dest & 0x0003fffc) << 3;
+                instr |= (dest & 0x00020000).wrapping_neg() & 0x00e00000;
+            } else {
+                i += 4;
+                continue
+            }
+            buf[i] = instr as u8;
+            buf[i + 1] = (instr >> 8) as u8;
+            buf[i + 2] = (instr >> 16) as u8;
+            buf[i + 3] = (instr >> 24) as u8;
+            i += 4;
+        }
+        i
+    }
+}
+
+/// This structure is used to convert a stream of machine code before its
+/// compression. This is a wrapper around an internal writer which bytes will
+/// be written to, the last bytes being held until the next write or the end.
+pub struct Encoder<W> {
+    w: W,
+    bcj: Bcj,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder of the given instruction set, for data at the
+    /// `start` offset of the executable, which will have its output written
+    /// to the given output stream. The output stream can be re-acquired by
+    /// calling `finish()`
+    pub fn new(w: W, arch: Arch, start: u32) -> Encoder<W> {
+        Encoder {
+            w: w,
+            bcj: Bcj::new(arch, start),
+            buf: Vec::new(),
+        }
+    }
+
+    /// This function is used to flag that this session of filtering is done
+    /// with. The bytes held are written as such, and then the wrapped writer
+    /// is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.end();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`
+    /// for the same instruction set and offset. The previous writer is
+    /// returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.end();
+        self.buf.truncate(0);
+        self.bcj.reset();
+        (mem::replace(&mut self.w, w), result)
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        try!(self.w.write_all(&self.buf));
+        self.w.flush()
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend(buf.iter().cloned());
+        let n = self.bcj.encode(&mut self.buf);
+        try!(self.w.write_all(&self.buf[..n]));
+        self.buf.drain(..n);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// This structure restores the machine code pushed into it, for the callers
+/// which hold the buffers. See the `process` module.
+pub struct Processor {
+    bcj: Bcj,
+    /// The bytes from `pos` to `filtered` are ready, those from `filtered` on
+    /// wait for more data
+    buf: Vec<u8>,
+    pos: usize,
+    filtered: usize,
+    eof: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder of the given instruction set, for data at the
+    /// `start` offset of the executable
+    pub fn new(arch: Arch, start: u32) -> Processor {
+        Processor {
+            bcj: Bcj::new(arch, start),
+            buf: Vec::new(),
+            pos: 0,
+            filtered: 0,
+            eof: false,
+        }
+    }
+
+    /// Starts decoding a new stream, keeping the buffer
+    pub fn reset(&mut self) {
+        self.bcj.reset();
+        self.buf.truncate(0);
+        self.pos = 0;
+        self.filtered = 0;
+        self.eof = false;
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            let n = cmp::min(output.len() - produced, self.filtered - self.pos);
+            output[produced..(produced + n)].copy_from_slice(&self.buf[self.pos..(self.pos + n)]);
+            self.pos += n;
+            produced += n;
+            if self.pos < self.filtered {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            if pos == input.len() {
+                if !self.eof {
+                    return Ok((pos, produced, Status::NeedInput))
+                }
+                if self.filtered == self.buf.len() {
+                    return Ok((pos, produced, Status::Done))
+                }
+                // the bytes at the end of the data are left as such
+                self.filtered = self.buf.len();
+                continue
+            }
+            self.buf.drain(..self.filtered);
+            self.pos = 0;
+            let n = cmp::min(input.len() - pos, BUF_SIZE);
+            self.buf.extend_from_slice(&input[pos..(pos + n)]);
+            pos += n;
+            self.filtered = self.bcj.decode(&mut self.buf);
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// This structure is used to restore a stream of machine code after its
+/// decompression. The wrapped stream can be re-acquired through the unwrap()
+/// method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder of the given instruction set, for data at the
+    /// `start` offset of the executable, which will read the converted code
+    /// from the given stream
+    pub fn new(r: R, arch: Arch, start: u32) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new(arch, start)),
+        }
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader, without the
+    /// data read ahead.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing::{push, Lcg};
+    use super::{Arch, Bcj, Decoder, Encoder, Processor};
+
+    fn encode(input: &[u8], arch: Arch, start: u32) -> Vec<u8> {
+        let mut e = Encoder::new(Vec::new(), arch, start);
+        e.write_all(input).unwrap();
+        let (out, result) = e.finish();
+        result.unwrap();
+        out
+    }
+
+    fn decode(input: &[u8], arch: Arch, start: u32) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::with_capacity(7, input), arch, start);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        out
+    }
+
+    /// Noise holding a call in one word out of four
+    fn code(arch: Arch) -> Vec<u8> {
+        let mut lcg = Lcg(7);
+        (0..10000).map(|i| {
+            let seed = lcg.step();
+            let byte = (seed >> 16) as u8;
+            match (arch, i % 16) {
+                (Arch::X86, 0) => 0xe8,
+                (Arch::X86, 4) => 0,
+                (Arch::Arm, 3) => 0xeb,
+                (Arch::Arm64, 3) => 0x94,
+                (Arch::Arm64, 7) => 0x90,
+                (Arch::Arm64, 6) => 0,
+                _ => byte,
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn known_data() {
+        // a call to the start, a jump whose last byte doesn't fit, a call
+        let mut code = [0xe8, 0xfb, 0xff, 0xff, 0xff, 0xe9, 0, 0, 0, 0x12, 0xe8, 0, 0, 0, 0, 0];
+        assert_eq!(Bcj::new(Arch::X86, 0).encode(&mut code), 15);
+        assert_eq!(code, [0xe8, 0, 0, 0, 0, 0xe9, 0, 0, 0, 0x12, 0xe8, 0x0f, 0, 0, 0, 0]);
+        // BL of ARM, at the offset 8, to the start
+        let mut code = [0, 0, 0, 0, 0xfc, 0xff, 0xff, 0xeb];
+        assert_eq!(Bcj::new(Arch::Arm, 4).encode(&mut code[..7]), 4);
+        assert_eq!(Bcj::new(Arch::Arm, 4).encode(&mut code), 8);
+        assert_eq!(code, [0, 0, 0, 0, 0, 0, 0, 0xeb]);
+        // BL and ADRP of ARM64
+        let mut code = [0xff, 0xff, 0xff, 0x97, 0x00, 0x00, 0x00, 0xf0];
+        assert_eq!(Bcj::new(Arch::Arm64, 0x1000).encode(&mut code), 8);
+        assert_eq!(code, [0xff, 0x03, 0x00, 0x94, 0x20, 0x00, 0x00, 0x90]);
+    }
+
+    #[test]
+    fn roundtrips() {
+        for &arch in [Arch::X86, Arch::Arm, Arch::Arm64].iter() {
+            let input = code(arch);
+            let encoded = encode(&input, arch, 0x400);
+            assert!(encoded != input);
+            assert!(decode(&encoded, arch, 0x400) == input);
+            assert!(push(&mut Processor::new(arch, 0x400), &encoded, 3).unwrap() == input);
+            // the conversion doesn't depend on the size of the buffers
+            let mut e = Encoder::new(Vec::new(), arch, 0x400);
+            for chunk in input.chunks(3) {
+                e.write_all(chunk).unwrap();
+            }
+            assert!(e.finish().0 == encoded);
+        }
+        let noise: Vec<u8> = (0..10000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        assert!(decode(&encode(&noise, Arch::X86, 0), Arch::X86, 0) == noise);
+        assert_eq!(encode(b"\xe8\x00", Arch::X86, 0), b"\xe8\x00");
+    }
+
+    #[test]
+    fn reset() {
+        let input = code(Arch::X86);
+        let mut e = Encoder::new(Vec::new(), Arch::X86, 0x400);
+        e.write_all(&input[..5001]).unwrap();
+        let (a, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(&input).unwrap();
+        let (b, result) = e.finish();
+        result.unwrap();
+        assert!(a == encode(&input[..5001], Arch::X86, 0x400));
+        assert!(b == encode(&input, Arch::X86, 0x400));
+
+        let mut d = Decoder::new(BufReader::with_capacity(7, &b[..]), Arch::X86, 0x400);
+        d.read_exact(&mut [0u8; 1000]).unwrap();
+        d.reset(BufReader::with_capacity(7, &b[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(out == input);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unaligned_start() {
+        Bcj::new(Arch::Arm, 2);
+    }
+}
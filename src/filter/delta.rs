@@ -0,0 +1,349 @@
+//! The delta filter
+//!
+//! Each value of the data is replaced by its difference with the value a
+//! stride before it, the values before the start counting as zero, which
+//! turns slowly changing samples into small numbers and repeated rows into
+//! zeros. The values are either bytes, as in the delta filter of xz, or 16-bit
+//! little-endian words, whose differences carry from the low byte into the
+//! high one, as for the samples of a PCM audio stream. The stride is counted in
+//! values, from 1 to 16, so that the channels of interleaved samples or the
+//! fields of fixed-size records are filtered apart.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::io::{Read, Write};
+//! use compress::filter::delta;
+//!
+//! let samples = [10u8, 0, 12, 0, 14, 0, 16, 0];
+//! let mut e = delta::Encoder::new(Vec::new(), 1, delta::Width::Word);
+//! e.write_all(&samples).unwrap();
+//! let (encoded, result) = e.finish();
+//! result.unwrap();
+//! assert_eq!(&encoded[..], &[10, 0, 2, 0, 2, 0, 2, 0]);
+//!
+//! let mut d = delta::Decoder::new(&encoded[..], 1, delta::Width::Word);
+//! let mut decoded = Vec::new();
+//! d.read_to_end(&mut decoded).unwrap();
+//! assert_eq!(&decoded[..], &samples[..]);
+//! ```
+//!
+//! # Related links
+//!
+//! * https://tukaani.org/xz/xz-file-format.txt - the format of xz, whose
+//!   section 5.3.2 describes its delta filter
+
+use std::cmp;
+use std::mem;
+use std::io::{self, Read, Write};
+
+use process::{Process, Reader, Status};
+
+/// The largest stride, in values
+pub const MAX_STRIDE: usize = 16;
+
+/// The size of the values the differences are taken of
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    /// Single bytes
+    Byte,
+    /// 16-bit little-endian words
+    Word,
+}
+
+impl Width {
+    fn bytes(&self) -> usize {
+        match *self {
+            Width::Byte => 1,
+            Width::Word => 2,
+        }
+    }
+}
+
+/// The state of the filter, which is the last stride of the data
+pub struct Delta {
+    width: Width,
+    /// Number of bytes of the stride
+    dist: usize,
+    history: [u8; 2 * MAX_STRIDE],
+    /// Position in the history, whose parity tells the low bytes of the words
+    pos: usize,
+    /// Carry of the low byte of a word into its high byte
+    carry: bool,
+}
+
+impl Delta {
+    /// Creates a filter of the given stride, from 1 to 16 values of the given
+    /// width, as at the start of the data
+    pub fn new(stride: usize, width: Width) -> Delta {
+        assert!(stride >= 1 && stride <= MAX_STRIDE, "invalid delta stride {}", stride);
+        Delta {
+            width: width,
+            dist: stride * width.bytes(),
+            history: [0; 2 * MAX_STRIDE],
+            pos: 0,
+            carry: false,
+        }
+    }
+
+    /// Starts filtering new data, as at the start of the data
+    pub fn reset(&mut self) {
+        self.history = [0; 2 * MAX_STRIDE];
+        self.pos = 0;
+        self.carry = false;
+    }
+
+    /// Replaces the data of the buffer, following the data of the previous
+    /// calls, by its differences
+    pub fn encode(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            let prev = self.history[self.pos];
+            self.history[self.pos] = *byte;
+            *byte = match self.width {
+                Width::Word if self.pos & 1 == 0 => {
+                    let (diff, borrow) = byte.overflowing_sub(prev);
+                    self.carry = borrow;
+                    diff
+                }
+                Width::Word => byte.wrapping_sub(prev).wrapping_sub(self.carry as u8),
+                Width::Byte => byte.wrapping_sub(prev),
+            };
+            self.pos = (self.pos + 1) % self.dist;
+        }
+    }
+
+    /// Replaces the differences of the buffer, following the data of the
+    /// previous calls, by the data
+    pub fn decode(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            let prev = self.history[self.pos];
+            *byte = match self.width {
+                Width::Word if self.pos & 1 == 0 => {
+                    let (sum, carry) = byte.overflowing_add(prev);
+                    self.carry = carry;
+                    sum
+                }
+                Width::Word => byte.wrapping_add(prev).wrapping_add(self.carry as u8),
+                Width::Byte => byte.wrapping_add(prev),
+            };
+            self.history[self.pos] = *byte;
+            self.pos = (self.pos + 1) % self.dist;
+        }
+    }
+}
+
+/// This structure is used to filter a stream of bytes into their differences.
+/// This is a wrapper around an internal writer which bytes will be written to.
+pub struct Encoder<W> {
+    w: W,
+    delta: Delta,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder of the given stride and width, which will have
+    /// its output written to the given output stream. The output stream can
+    /// be re-acquired by calling `finish()`
+    pub fn new(w: W, stride: usize, width: Width) -> Encoder<W> {
+        Encoder {
+            w: w,
+            delta: Delta::new(stride, width),
+            buf: Vec::new(),
+        }
+    }
+
+    /// This function is used to flag that this session of filtering is done
+    /// with. The wrapped writer is flushed, and then returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.flush();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`
+    /// of the same stride and width. The previous writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.flush();
+        self.delta.reset();
+        (mem::replace(&mut self.w, w), result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.truncate(0);
+        self.buf.extend(buf.iter().cloned());
+        self.delta.encode(&mut self.buf);
+        try!(self.w.write_all(&self.buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// This structure restores the data pushed into it from their differences,
+/// for the callers which hold the buffers. See the `process` module.
+pub struct Processor {
+    delta: Delta,
+    eof: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder of the given stride and width
+    pub fn new(stride: usize, width: Width) -> Processor {
+        Processor {
+            delta: Delta::new(stride, width),
+            eof: false,
+        }
+    }
+
+    /// Starts decoding a new stream
+    pub fn reset(&mut self) {
+        self.delta.reset();
+        self.eof = false;
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let n = cmp::min(input.len(), output.len());
+        output[..n].copy_from_slice(&input[..n]);
+        self.delta.decode(&mut output[..n]);
+        let status = if n < input.len() {
+            Status::NeedOutput
+        } else if self.eof {
+            Status::Done
+        } else {
+            Status::NeedInput
+        };
+        Ok((n, n, status))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// This structure is used to restore a stream of bytes from their
+/// differences. The wrapped stream can be re-acquired through the unwrap()
+/// method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder of the given stride and width, which will read
+    /// the differences from the given stream
+    pub fn new(r: R, stride: usize, width: Width) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new(stride, width)),
+        }
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader, without the
+    /// data read ahead.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing::{noise, push};
+    use super::{Decoder, Delta, Encoder, MAX_STRIDE, Processor, Width};
+
+    fn encode(input: &[u8], stride: usize, width: Width) -> Vec<u8> {
+        let mut out = input.to_vec();
+        Delta::new(stride, width).encode(&mut out);
+        out
+    }
+
+    #[test]
+    fn known_data() {
+        assert_eq!(encode(&[1, 2, 3, 5, 4], 1, Width::Byte), [1, 1, 1, 2, 0xff]);
+        assert_eq!(encode(&[1, 2, 3, 5, 4], 2, Width::Byte), [1, 2, 2, 3, 1]);
+        // 0x00ff, 0x0100 and 0x0000, the difference borrowing from the high byte
+        assert_eq!(encode(&[0xff, 0, 0, 1, 0, 0], 1, Width::Word), [0xff, 0, 1, 0, 0, 0xff]);
+        assert_eq!(encode(&[1, 2, 3, 4, 5, 6, 7, 8], 2, Width::Word), [1, 2, 3, 4, 4, 4, 4, 4]);
+        // a ramp of words becomes constant
+        let ramp: Vec<u8> = (0..1000u32).flat_map(|i| vec![(i * 300) as u8, (i * 300 >> 8) as u8])
+                                        .collect();
+        assert!(encode(&ramp, 1, Width::Word)[2..].chunks(2).all(|w| w == [44, 1]));
+    }
+
+    #[test]
+    fn roundtrips() {
+        let input = noise(10001, 7);
+        for &width in [Width::Byte, Width::Word].iter() {
+            for stride in 1..(MAX_STRIDE + 1) {
+                let mut data = encode(&input, stride, width);
+                let mut delta = Delta::new(stride, width);
+                // in pieces, cutting the words
+                for chunk in data.chunks_mut(7) {
+                    delta.decode(chunk);
+                }
+                assert!(data == input);
+            }
+        }
+    }
+
+    #[test]
+    fn streams() {
+        let input = noise(10001, 7);
+        let mut e = Encoder::new(Vec::new(), 3, Width::Word);
+        for chunk in input.chunks(33) {
+            e.write_all(chunk).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        assert_eq!(encoded, encode(&input, 3, Width::Word));
+        let mut d = Decoder::new(BufReader::with_capacity(5, &encoded[..]), 3, Width::Word);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(out == input);
+        assert!(push(&mut Processor::new(3, Width::Word), &encoded, 7).unwrap() == input);
+    }
+
+    #[test]
+    fn reset() {
+        let input = noise(10001, 7);
+        let mut e = Encoder::new(Vec::new(), 3, Width::Word);
+        e.write_all(&input[..777]).unwrap();
+        let (a, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(&input).unwrap();
+        let (b, result) = e.finish();
+        result.unwrap();
+        assert_eq!(a, encode(&input[..777], 3, Width::Word));
+        assert_eq!(b, encode(&input, 3, Width::Word));
+
+        let mut d = Decoder::new(BufReader::with_capacity(5, &b[..]), 3, Width::Word);
+        d.read_exact(&mut [0u8; 1000]).unwrap();
+        d.reset(BufReader::with_capacity(5, &b[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(out == input);
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_stride() {
+        Delta::new(MAX_STRIDE + 1, Width::Byte);
+    }
+}
@@ -0,0 +1,16 @@
+//! Reversible filters. Requires `filter` feature, enabled by default
+//!
+//! This module contains transforms which don't compress by themselves, but
+//! reshape the data of a known kind so that the LZ and entropy stages which
+//! follow do better. Each filter works in place on buffers, keeping its state
+//! from one buffer to the next, and is also exposed as standard `Reader` and
+//! `Writer` interfaces wrapping an underlying stream, so that they compose
+//! with the other streams of the crate.
+//!
+//! * `delta` stores the differences between the bytes or words of the data
+//!   and those a stride before, for audio, images and tables of numbers
+//! * `bcj` converts the distances of the calls and jumps of machine code into
+//!   their targets, for executables
+
+pub mod bcj;
+pub mod delta;
@@ -0,0 +1,1669 @@
+/*!
+
+DEFLATE encoder
+
+The encoder runs an LZ77 pass over a 32KB sliding window using hash chains,
+then emits each batch of symbols as a stored, fixed or dynamic huffman block,
+whichever is the smallest.
+
+The match finder follows zlib's tuning table: the low levels are greedy, while
+levels 4 and up use a one byte lazy evaluation, deferring a match whenever the
+next position holds a longer one.
+
+`Strategy::HuffmanOnly` skips the match finder altogether, coding every byte as
+a literal, while `Strategy::Optimal` replaces these heuristics with a search
+for the cheapest parse: all the matches of a segment of input are gathered,
+then a shortest path is computed through them, pricing each symbol by its
+entropy in the previous pass. The best of the passes is kept.
+
+In rsyncable mode, the output is also cut at points chosen by a rolling hash of
+the input, each piece starting on a byte boundary.
+
+# Credit
+
+The parsing heuristics and their parameters are the ones of zlib's deflate.c.
+The optimal parser follows the iterative approach of zopfli.
+
+*/
+
+use std::cmp;
+use std::io::{self, Write};
+use std::mem;
+
+use chunk::Chunker;
+#[cfg(feature="dictionary")]
+use dictionary::Dictionary;
+use hash;
+#[cfg(all(feature="gzip", feature="threads"))]
+use gzip;
+use histogram::histogram;
+use stats::Sink;
+use tune;
+use super::{EXTRALENS, EXTRABITS, EXTRADIST, EXTRADBITS, ORDER};
+
+const WSIZE: usize = 1 << 15;
+const WMASK: usize = WSIZE - 1;
+const HASH_BITS: u32 = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+/// The hash table starts with this many entries, and grows to `HASH_SIZE`
+/// along with the chains once the window holds more than `SHORT_INPUT` bytes,
+/// so that short inputs do not pay for the clearing of the full tables
+const SHORT_HASH_SIZE: usize = 1 << 12;
+const SHORT_INPUT: usize = 1 << 12;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Minimum amount of lookahead, except at the end of the input
+const MIN_LOOKAHEAD: usize = MAX_MATCH + MIN_MATCH + 1;
+/// Matches are not searched further back than this, so that a full lookahead
+/// always fits in the window
+const MAX_DIST: usize = WSIZE - MIN_LOOKAHEAD;
+/// Matches of length 3 are discarded if their distance exceeds this
+const TOO_FAR: usize = 4096;
+/// Number of symbols gathered before a block is emitted
+const BLOCK_TOKENS: usize = (1 << 14) - 1;
+const MAX_STORED: usize = 0xffff;
+const NIL: u32 = !0;
+/// Number of input bytes the optimal parser handles at once
+const SEGMENT: usize = 1 << 14;
+/// Number of chain links the optimal parser follows at each position
+const OPTIMAL_CHAIN: usize = 1024;
+/// The rsyncable mode marks a boundary at the content-defined boundaries of
+/// chunks of 1 to 64KB, every 4KB on average
+const RSYNC_MIN: usize = 1 << 10;
+const RSYNC_AVG: usize = 1 << 12;
+const RSYNC_MAX: usize = 1 << 16;
+/// Memory of an encoder: its window, hash chains and tokens
+const ENCODER_MEMORY: usize = 2 * WSIZE + 4 * HASH_SIZE + 4 * WSIZE + 4 * (BLOCK_TOKENS + 1);
+/// Smallest input of the jobs of `DeflateOptions::auto`
+const MIN_CHUNK: usize = 32 * 1024;
+
+const LITERALS: usize = 256;
+const END_OF_BLOCK: usize = 256;
+const LCODES: usize = 286;
+const DCODES: usize = 30;
+const BLCODES: usize = 19;
+const MAX_BITS: u8 = 15;
+const MAX_BL_BITS: u8 = 7;
+
+/// Matching parameters of a compression level
+struct Config {
+    /// reduce the chain search once a match of this length is at hand
+    good: usize,
+    /// do not attempt a lazy match past this length; for the greedy levels,
+    /// do not insert the strings of matches longer than this
+    lazy: usize,
+    /// stop searching once a match of this length is found
+    nice: usize,
+    /// maximum number of hash chain links followed
+    chain: usize,
+    /// whether to use the lazy evaluation
+    lazy_eval: bool,
+}
+
+static CONFIGS: [Config; 10] = [
+    Config { good: 0, lazy: 0, nice: 0, chain: 0, lazy_eval: false }, // store only
+    Config { good: 4, lazy: 4, nice: 8, chain: 4, lazy_eval: false },
+    Config { good: 4, lazy: 5, nice: 16, chain: 8, lazy_eval: false },
+    Config { good: 4, lazy: 6, nice: 32, chain: 32, lazy_eval: false },
+    Config { good: 4, lazy: 4, nice: 16, chain: 16, lazy_eval: true },
+    Config { good: 8, lazy: 16, nice: 32, chain: 32, lazy_eval: true },
+    Config { good: 8, lazy: 16, nice: 128, chain: 128, lazy_eval: true },
+    Config { good: 8, lazy: 32, nice: 128, chain: 256, lazy_eval: true },
+    Config { good: 32, lazy: 128, nice: 258, chain: 1024, lazy_eval: true },
+    Config { good: 32, lazy: 258, nice: 258, chain: 4096, lazy_eval: true },
+];
+
+/// Length code (minus 257) of each match length minus 3
+const LENGTH_CODE: [u8; 256] = make_length_code();
+
+/// Distance code of each distance minus one for the first 256 entries, then
+/// of the distance minus one shifted right by 7
+const DIST_CODE: [u8; 512] = make_dist_code();
+
+const fn make_length_code() -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut code = 0;
+    while code < 28 {
+        let base = EXTRALENS[code] as usize - 3;
+        let mut i = 0;
+        while i < (1 << EXTRABITS[code]) {
+            table[base + i] = code as u8;
+            i += 1;
+        }
+        code += 1;
+    }
+    // 258 has a dedicated code
+    table[255] = 28;
+    table
+}
+
+const fn make_dist_code() -> [u8; 512] {
+    let mut table = [0u8; 512];
+    let mut code = 0;
+    while code < DCODES {
+        let base = EXTRADIST[code] as usize - 1;
+        let mut d = base;
+        while d < base + (1 << EXTRADBITS[code]) {
+            if d < 256 {
+                table[d] = code as u8;
+            } else {
+                table[256 + (d >> 7)] = code as u8;
+            }
+            d += 1;
+        }
+        code += 1;
+    }
+    table
+}
+
+/// Returns the maximum size of the raw stream compressing `size` bytes, for
+/// the encoders which are neither flushed nor rsyncable
+pub fn max_compressed_len(size: usize) -> usize {
+    // a block costs no more than its symbols in the fixed codes, 9 bits per
+    // byte at worst, plus 42 bits of headers if it is stored, and a block
+    // ends at least every 4 kB
+    size + size / 8 + 6 * (size / 4096 + 3) + 1
+}
+
+#[inline]
+fn dist_code(dist: usize) -> usize {
+    let d = dist - 1;
+    if d < 256 {
+        DIST_CODE[d] as usize
+    } else {
+        DIST_CODE[256 + (d >> 7)] as usize
+    }
+}
+
+/// A literal (`dist == 0`) or a match
+#[derive(Clone, Copy)]
+struct Token {
+    len: u16,
+    dist: u16,
+}
+
+/// Accumulates bits LSB first into a byte buffer
+struct BitWriter {
+    out: Vec<u8>,
+    buf: u64,
+    cnt: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { out: Vec::new(), buf: 0, cnt: 0 }
+    }
+
+    #[inline]
+    fn bits(&mut self, value: u32, cnt: u8) {
+        self.buf |= (value as u64) << self.cnt;
+        self.cnt += cnt as u32;
+        while self.cnt >= 8 {
+            self.out.push(self.buf as u8);
+            self.buf >>= 8;
+            self.cnt -= 8;
+        }
+    }
+
+    /// Pads the output to a byte boundary
+    fn align(&mut self) {
+        if self.cnt > 0 {
+            self.out.push(self.buf as u8);
+        }
+        self.buf = 0;
+        self.cnt = 0;
+    }
+
+    /// Writes an empty non-final stored block, which aligns the output
+    fn sync(&mut self) {
+        self.bits(0, 3);
+        self.align();
+        self.out.extend([0, 0, 0xff, 0xff].iter().cloned());
+    }
+}
+
+/// Computes huffman code lengths limited to `max_bits` for the given symbol
+/// frequencies. Unused symbols get a zero length.
+fn build_lengths(freqs: &[u32], max_bits: u8, lens: &mut [u8]) {
+    for len in lens.iter_mut() {
+        *len = 0;
+    }
+    let mut syms: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] != 0).collect();
+    match syms.len() {
+        0 => return,
+        1 => {
+            // a single code still needs one bit, pair it with a dummy symbol
+            // so that the code is complete
+            lens[syms[0]] = 1;
+            lens[if syms[0] == 0 { 1 } else { 0 }] = 1;
+            return
+        }
+        _ => (),
+    }
+    syms.sort_by(|&a, &b| freqs[a].cmp(&freqs[b]));
+
+    // Two-queue huffman construction: leaves are taken in frequency order
+    // from `syms`, internal nodes are created in increasing weight order.
+    let n = syms.len();
+    let mut weight: Vec<u64> = syms.iter().map(|&s| freqs[s] as u64).collect();
+    let mut parent = vec![0usize; 2 * n - 1];
+    let (mut leaf, mut inner) = (0, n);
+    for node in n..(2 * n - 1) {
+        let mut sum = 0;
+        for _ in 0..2 {
+            let pick = if leaf < n && (inner == node || weight[leaf] <= weight[inner]) {
+                leaf += 1;
+                leaf - 1
+            } else {
+                inner += 1;
+                inner - 1
+            };
+            parent[pick] = node;
+            sum += weight[pick];
+        }
+        weight.push(sum);
+    }
+    let mut depth = vec![0usize; 2 * n - 1];
+    let mut count = [0usize; 16];
+    for node in (0..(2 * n - 2)).rev() {
+        depth[node] = depth[parent[node]] + 1;
+        if node < n {
+            count[cmp::min(depth[node], max_bits as usize)] += 1;
+        }
+    }
+
+    // Fold the overlong codes into `max_bits` and rebalance the code space
+    let max = max_bits as usize;
+    let mut total: usize = (1..(max + 1)).map(|i| count[i] << (max - i)).sum();
+    while total > (1 << max) {
+        count[max] -= 1;
+        for i in (1..max).rev() {
+            if count[i] != 0 {
+                count[i] -= 1;
+                count[i + 1] += 2;
+                break
+            }
+        }
+        total -= 1;
+    }
+
+    // The least frequent symbols get the longest codes
+    let mut i = 0;
+    for len in (1..(max + 1)).rev() {
+        for _ in 0..count[len] {
+            lens[syms[i]] = len as u8;
+            i += 1;
+        }
+    }
+}
+
+/// Assigns canonical codes to the given lengths, bit-reversed so that they
+/// can be written LSB first
+fn build_codes(lens: &[u8], codes: &mut [u16]) {
+    let mut count = [0u16; 16];
+    for &len in lens.iter() {
+        count[len as usize] += 1;
+    }
+    count[0] = 0;
+    let mut next = [0u16; 16];
+    let mut code = 0;
+    for bits in 1..16 {
+        code = (code + count[bits - 1]) << 1;
+        next[bits] = code;
+    }
+    for (sym, &len) in lens.iter().enumerate() {
+        if len != 0 {
+            let c = next[len as usize];
+            next[len as usize] += 1;
+            codes[sym] = c.reverse_bits() >> (16 - len as u32);
+        }
+    }
+}
+
+/// Run-length encodes a sequence of code lengths into code length symbols
+/// (0-18) along with their extra bits value
+fn encode_lengths(lens: &[u8], out: &mut Vec<(u8, u8)>) {
+    let mut i = 0;
+    while i < lens.len() {
+        let len = lens[i];
+        let mut run = 1;
+        while i + run < lens.len() && lens[i + run] == len {
+            run += 1;
+        }
+        i += run;
+        if len == 0 {
+            while run >= 11 {
+                let n = cmp::min(run, 138);
+                out.push((18, (n - 11) as u8));
+                run -= n;
+            }
+            if run >= 3 {
+                out.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            out.push((len, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = cmp::min(run, 6);
+                out.push((16, (n - 3) as u8));
+                run -= n;
+            }
+        }
+        for _ in 0..run {
+            out.push((len, 0));
+        }
+    }
+}
+
+fn last_used(lens: &[u8]) -> usize {
+    lens.iter().rposition(|&l| l != 0).map_or(0, |i| i + 1)
+}
+
+/// Number of bits needed by symbols of the given frequencies under the given
+/// code lengths, extra bits included
+fn data_bits(lit_freq: &[u32], dist_freq: &[u32], lit_lens: &[u8], dist_lens: &[u8]) -> usize {
+    let mut bits = 0;
+    for (sym, &freq) in lit_freq.iter().enumerate() {
+        bits += freq as usize * lit_lens[sym] as usize;
+        if sym > END_OF_BLOCK {
+            bits += freq as usize * EXTRABITS[sym - END_OF_BLOCK - 1] as usize;
+        }
+    }
+    for (code, &freq) in dist_freq.iter().enumerate() {
+        bits += freq as usize * (dist_lens[code] as usize + EXTRADBITS[code] as usize);
+    }
+    bits
+}
+
+fn fixed_lengths() -> ([u8; 288], [u8; DCODES]) {
+    let mut lit = [8u8; 288];
+    for len in lit[144..256].iter_mut() { *len = 9; }
+    for len in lit[256..280].iter_mut() { *len = 7; }
+    (lit, [5u8; DCODES])
+}
+
+/// The huffman codes of a dynamic block, along with the run-length encoded
+/// sequence of their lengths that makes the block header
+pub struct DynamicCodes {
+    lit_lens: [u8; LCODES],
+    dist_lens: [u8; DCODES],
+    bl_lens: [u8; BLCODES],
+    bl_syms: Vec<(u8, u8)>,
+    hlit: usize,
+    hdist: usize,
+    hclen: usize,
+    /// Size of the whole block in bits
+    pub bits: usize,
+}
+
+impl DynamicCodes {
+    /// Builds the codes for the given symbol frequencies, which must account
+    /// for the end of block symbol
+    pub fn new(lit_freq: &[u32], dist_freq: &[u32]) -> DynamicCodes {
+        let mut lit_lens = [0u8; LCODES];
+        let mut dist_lens = [0u8; DCODES];
+        build_lengths(lit_freq, MAX_BITS, &mut lit_lens);
+        build_lengths(dist_freq, MAX_BITS, &mut dist_lens);
+        if last_used(&dist_lens) == 0 {
+            // some decoders insist on a complete distance code, even unused
+            dist_lens[0] = 1;
+            dist_lens[1] = 1;
+        }
+        let hlit = cmp::max(257, last_used(&lit_lens));
+        let hdist = cmp::max(1, last_used(&dist_lens));
+
+        let mut lengths = Vec::with_capacity(hlit + hdist);
+        lengths.extend(lit_lens[..hlit].iter().cloned());
+        lengths.extend(dist_lens[..hdist].iter().cloned());
+        let mut bl_syms = Vec::new();
+        encode_lengths(&lengths, &mut bl_syms);
+        let mut bl_freq = [0u32; BLCODES];
+        for &(sym, _) in bl_syms.iter() {
+            bl_freq[sym as usize] += 1;
+        }
+        let mut bl_lens = [0u8; BLCODES];
+        build_lengths(&bl_freq, MAX_BL_BITS, &mut bl_lens);
+        let mut hclen = BLCODES;
+        while hclen > 4 && bl_lens[ORDER[hclen - 1]] == 0 {
+            hclen -= 1;
+        }
+
+        let mut bits = 3 + 5 + 5 + 4 + 3 * hclen +
+            data_bits(lit_freq, dist_freq, &lit_lens, &dist_lens);
+        for (sym, &freq) in bl_freq.iter().enumerate() {
+            let extra = match sym { 16 => 2, 17 => 3, 18 => 7, _ => 0 };
+            bits += freq as usize * (bl_lens[sym] as usize + extra);
+        }
+
+        DynamicCodes {
+            lit_lens: lit_lens,
+            dist_lens: dist_lens,
+            bl_lens: bl_lens,
+            bl_syms: bl_syms,
+            hlit: hlit,
+            hdist: hdist,
+            hclen: hclen,
+            bits: bits,
+        }
+    }
+}
+
+/// Bits needed by a block holding symbols of the given frequencies, with the
+/// cheapest of the fixed and dynamic codes
+fn block_bits(lit_freq: &[u32], dist_freq: &[u32]) -> usize {
+    let (fixed_lit, fixed_dist) = fixed_lengths();
+    let fixed = 3 + data_bits(lit_freq, dist_freq, &fixed_lit, &fixed_dist);
+    cmp::min(fixed, DynamicCodes::new(lit_freq, dist_freq).bits)
+}
+
+/// The matches found at each position of a segment. For every position, the
+/// pairs of (length, distance) come by increasing length, and each distance
+/// is the closest one reaching its length.
+struct Matches {
+    pairs: Vec<(u16, u16)>,
+    ends: Vec<usize>,
+}
+
+impl Matches {
+    fn new() -> Matches {
+        Matches { pairs: Vec::new(), ends: Vec::new() }
+    }
+
+    fn clear(&mut self) {
+        self.pairs.truncate(0);
+        self.ends.truncate(0);
+    }
+
+    fn at(&self, pos: usize) -> &[(u16, u16)] {
+        let start = if pos == 0 { 0 } else { self.ends[pos - 1] };
+        &self.pairs[start..self.ends[pos]]
+    }
+}
+
+/// Price in bits of each symbol, extra bits excluded
+struct CostModel {
+    lit: [f32; LCODES],
+    dist: [f32; DCODES],
+}
+
+impl CostModel {
+    /// Prices each symbol by its entropy under the given frequencies. Unseen
+    /// symbols cost as much as the rarest possible one.
+    fn from_stats(lit_freq: &[u32], dist_freq: &[u32]) -> CostModel {
+        fn fill(freqs: &[u32], costs: &mut [f32]) {
+            let total = cmp::max(1, freqs.iter().fold(0, |a, &f| a + f));
+            let log_total = (total as f32).log2();
+            for (cost, &freq) in costs.iter_mut().zip(freqs.iter()) {
+                *cost = if freq == 0 { log_total } else { log_total - (freq as f32).log2() };
+            }
+        }
+        let mut model = CostModel { lit: [0.0; LCODES], dist: [0.0; DCODES] };
+        fill(lit_freq, &mut model.lit);
+        fill(dist_freq, &mut model.dist);
+        model
+    }
+
+    #[inline]
+    fn length(&self, len: usize) -> f32 {
+        let code = LENGTH_CODE[len - MIN_MATCH] as usize;
+        self.lit[LITERALS + 1 + code] + EXTRABITS[code] as f32
+    }
+
+    #[inline]
+    fn distance(&self, dist: usize) -> f32 {
+        let code = dist_code(dist);
+        self.dist[code] + EXTRADBITS[code] as f32
+    }
+}
+
+/// Returns the number of bytes equal to each byte of `data` from it on
+fn repeats(data: &[u8]) -> Vec<usize> {
+    let mut same = vec![1; data.len()];
+    for i in (1..data.len()).rev() {
+        if data[i - 1] == data[i] {
+            same[i - 1] = same[i] + 1;
+        }
+    }
+    same
+}
+
+/// Finds the cheapest sequence of literals and matches covering `data` up to
+/// `end` at least under the given prices, storing it in `path`. `same` holds
+/// the `repeats` of `data`.
+fn shortest_path(data: &[u8], end: usize, same: &[usize], matches: &Matches, model: &CostModel,
+                 costs: &mut Vec<f32>, steps: &mut Vec<Token>, path: &mut Vec<Token>) {
+    let n = data.len();
+    costs.truncate(0);
+    costs.resize(n + 1, ::std::f32::INFINITY);
+    steps.truncate(0);
+    steps.resize(n + 1, Token { len: 0, dist: 0 });
+    costs[0] = 0.0;
+    let mut i = 0;
+    while i < n {
+        // in the middle of a long run, the longest matches of the previous
+        // byte follow each other, as in zopfli
+        if i > MAX_MATCH && same[i - MAX_MATCH] > MAX_MATCH && same[i] > 2 * MAX_MATCH {
+            let cost = model.length(MAX_MATCH) + model.distance(1);
+            for _ in 0..MAX_MATCH {
+                costs[i + MAX_MATCH] = costs[i] + cost;
+                steps[i + MAX_MATCH] = Token { len: MAX_MATCH as u16, dist: 1 };
+                i += 1;
+            }
+            continue
+        }
+        let base = costs[i];
+        let lit = base + model.lit[data[i] as usize];
+        if lit < costs[i + 1] {
+            costs[i + 1] = lit;
+            steps[i + 1] = Token { len: data[i] as u16, dist: 0 };
+        }
+        let mut shorter = MIN_MATCH - 1;
+        for &(len, dist) in matches.at(i) {
+            let dist_cost = base + model.distance(dist as usize);
+            for l in (shorter + 1)..(len as usize + 1) {
+                let cost = dist_cost + model.length(l);
+                if cost < costs[i + l] {
+                    costs[i + l] = cost;
+                    steps[i + l] = Token { len: l as u16, dist: dist };
+                }
+            }
+            shorter = len as usize;
+        }
+        i += 1;
+    }
+
+    // the path stops wherever past `end` is cheapest, rather than placing
+    // the lengths which reach `n` exactly anywhere along it
+    path.truncate(0);
+    let mut pos = (end..(n + 1)).fold(end, |best, e| if costs[e] < costs[best] { e } else { best });
+    while pos > 0 {
+        let step = steps[pos];
+        path.push(step);
+        pos -= if step.dist == 0 { 1 } else { step.len as usize };
+    }
+    path.reverse();
+}
+
+/// Symbol frequencies of a path, including the end of block
+fn path_stats(path: &[Token]) -> ([u32; LCODES], [u32; DCODES]) {
+    let mut lit_freq = [0u32; LCODES];
+    let mut dist_freq = [0u32; DCODES];
+    for t in path.iter() {
+        if t.dist == 0 {
+            lit_freq[t.len as usize] += 1;
+        } else {
+            lit_freq[LITERALS + 1 + LENGTH_CODE[t.len as usize - MIN_MATCH] as usize] += 1;
+            dist_freq[dist_code(t.dist as usize)] += 1;
+        }
+    }
+    lit_freq[END_OF_BLOCK] += 1;
+    (lit_freq, dist_freq)
+}
+
+/// Takes the longest match at each position, or a literal if there is none,
+/// up to `end` at least
+fn greedy_path(data: &[u8], end: usize, matches: &Matches, path: &mut Vec<Token>) {
+    path.truncate(0);
+    let mut pos = 0;
+    while pos < end {
+        match matches.at(pos).last() {
+            Some(&(len, dist)) => {
+                path.push(Token { len: len, dist: dist });
+                pos += len as usize;
+            }
+            None => {
+                path.push(Token { len: data[pos] as u16, dist: 0 });
+                pos += 1;
+            }
+        }
+    }
+}
+
+/// Parses `data` up to `end` at least in `iterations` passes, each one priced
+/// by the statistics of the previous, and returns the path that makes the
+/// smallest block. The first pass is priced by a greedy parse, which also
+/// competes.
+fn optimal_parse(data: &[u8], end: usize, matches: &Matches, iterations: u32) -> Vec<Token> {
+    let mut costs = Vec::new();
+    let mut steps = Vec::new();
+    let mut path = Vec::new();
+    let same = repeats(data);
+    greedy_path(data, end, matches, &mut path);
+    let mut best = path.clone();
+    let mut best_bits = usize::max_value();
+    for _ in 0..cmp::max(1, iterations) {
+        let (lit_freq, dist_freq) = path_stats(&path);
+        let bits = block_bits(&lit_freq, &dist_freq);
+        if bits < best_bits {
+            best_bits = bits;
+            best.truncate(0);
+            best.extend(path.iter().cloned());
+        }
+        let model = CostModel::from_stats(&lit_freq, &dist_freq);
+        shortest_path(data, end, &same, matches, &model, &mut costs, &mut steps, &mut path);
+    }
+    let (lit_freq, dist_freq) = path_stats(&path);
+    if block_bits(&lit_freq, &dist_freq) < best_bits {
+        best = path;
+    }
+    best
+}
+
+/// How the encoder parses its input into literals and matches
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Greedy or lazy matching, as tuned by the compression level
+    Default,
+    /// No matches at all, the input is only entropy coded: this is the
+    /// fastest mode, fit for data that has already been through some LZ
+    /// stage
+    HuffmanOnly,
+    /// Looks for the cheapest parse of each segment of input among all the
+    /// matches found, over several passes refining the symbol prices. This
+    /// is many times slower than level 9, for a few percents of gain.
+    Optimal {
+        /// Number of parsing passes, 15 is a reasonable choice
+        iterations: u32,
+    },
+}
+
+/// The settings of a DEFLATE encoder, which builds it once they are checked
+///
+/// ```rust
+/// use std::io::Write;
+/// use compress::flate::{DeflateOptions, Strategy};
+///
+/// let mut e = DeflateOptions::new().level(9).strategy(Strategy::HuffmanOnly).encoder(Vec::new()).unwrap();
+/// e.write_all(b"hello").unwrap();
+/// let (compressed, result) = e.finish();
+/// result.unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeflateOptions {
+    level: u32,
+    strategy: Strategy,
+    rsyncable: bool,
+    threads: usize,
+    chunk_size: usize,
+}
+
+impl Default for DeflateOptions {
+    fn default() -> DeflateOptions {
+        DeflateOptions {
+            level: 6,
+            strategy: Strategy::Default,
+            rsyncable: false,
+            threads: 1,
+            chunk_size: 128 * 1024,
+        }
+    }
+}
+
+impl DeflateOptions {
+    /// Creates the default settings: level 6, the default strategy and no
+    /// rsyncable mode
+    pub fn new() -> DeflateOptions {
+        DeflateOptions::default()
+    }
+
+    /// Creates the default settings with the threads and the chunks of the
+    /// parallel encoder fitting `budget` bytes of memory on this machine: a
+    /// job per core, as many as the budget holds, with the largest chunks,
+    /// from 32 kB to 1 MB, for which the jobs and their output fit as well
+    pub fn auto(budget: usize) -> DeflateOptions {
+        // each job holds its input and its output besides the encoder
+        let threads = tune::jobs(budget, ENCODER_MEMORY + 2 * MIN_CHUNK);
+        let size = tune::block_size(budget, threads, ENCODER_MEMORY, 2, MIN_CHUNK, 1 << 20);
+        DeflateOptions::new().threads(threads).chunk_size(size)
+    }
+
+    /// Sets the level, from 0 (no compression) to 9 (best compression)
+    pub fn level(mut self, level: u32) -> DeflateOptions {
+        self.level = level;
+        self
+    }
+
+    /// Sets how the input is parsed, see `Encoder::set_strategy`
+    pub fn strategy(mut self, strategy: Strategy) -> DeflateOptions {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets the rsyncable mode, see `Encoder::set_rsyncable`
+    pub fn rsyncable(mut self, rsyncable: bool) -> DeflateOptions {
+        self.rsyncable = rsyncable;
+        self
+    }
+
+    /// Sets the number of jobs of the parallel encoder, see
+    /// `gzip::ParallelEncoder::new`
+    pub fn threads(mut self, threads: usize) -> DeflateOptions {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets the input of each job of the parallel encoder, see
+    /// `gzip::ParallelEncoder::set_chunk_size`
+    pub fn chunk_size(mut self, size: usize) -> DeflateOptions {
+        self.chunk_size = size;
+        self
+    }
+
+    /// Creates an encoder with these settings, writing to `w`, or fails if
+    /// they are invalid
+    pub fn encoder<W: Write>(&self, w: W) -> io::Result<Encoder<W>> {
+        if self.level > 9 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid deflate compression level"))
+        }
+        let mut e = Encoder::new(w, self.level);
+        e.set_strategy(self.strategy);
+        e.set_rsyncable(self.rsyncable);
+        Ok(e)
+    }
+
+    /// Creates a gzip encoder compressing chunks on several threads with
+    /// these settings, writing to `w`, or fails if they are invalid. The
+    /// chunks are compressed with the default strategy, without the rsyncable
+    /// mode.
+    #[cfg(all(feature="gzip", feature="threads"))]
+    pub fn parallel_encoder<W: Write>(&self, w: W) -> io::Result<gzip::ParallelEncoder<W>> {
+        if self.level > 9 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid deflate compression level"))
+        }
+        if self.chunk_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid deflate chunk size"))
+        }
+        let mut e = gzip::ParallelEncoder::new(w, self.level, self.threads);
+        e.set_chunk_size(self.chunk_size);
+        Ok(e)
+    }
+}
+
+/// This structure is used to compress a stream of bytes into a raw DEFLATE
+/// stream. This is a wrapper around an internal writer which bytes will be
+/// written to.
+pub struct Encoder<W> {
+    w: W,
+    level: u32,
+    strategy: Strategy,
+    rsync: Option<Chunker>,
+    out: BitWriter,
+
+    window: Vec<u8>,
+    /// The end of the preset dictionary, the window starts with
+    preset: Vec<u8>,
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    strstart: usize,
+    block_start: isize,
+
+    match_length: usize,
+    match_start: usize,
+    prev_length: usize,
+    prev_match: usize,
+    match_available: bool,
+
+    tokens: Vec<Token>,
+    lit_freq: [u32; LCODES],
+    dist_freq: [u32; DCODES],
+    stats: Option<Sink>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will have its output written to the given
+    /// output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    /// 'level' ranges from 0 (no compression) to 9 (best compression), 6 is
+    /// a good default.
+    pub fn new(w: W, level: u32) -> Encoder<W> {
+        assert!(level <= 9, "Invalid deflate compression level {}", level);
+        Encoder {
+            w: w,
+            level: level,
+            strategy: Strategy::Default,
+            rsync: None,
+            out: BitWriter::new(),
+            window: Vec::with_capacity(2 * WSIZE),
+            preset: Vec::new(),
+            head: vec![NIL; SHORT_HASH_SIZE],
+            // only read at the positions inserted, which set it
+            prev: vec![0; SHORT_INPUT],
+            strstart: 0,
+            block_start: 0,
+            match_length: MIN_MATCH - 1,
+            match_start: 0,
+            prev_length: MIN_MATCH - 1,
+            prev_match: 0,
+            match_available: false,
+            tokens: Vec::with_capacity(BLOCK_TOKENS + 1),
+            lit_freq: [0; LCODES],
+            dist_freq: [0; DCODES],
+            stats: None,
+        }
+    }
+
+    /// Creates a new encoder as `new`, of which the matches may also refer
+    /// to the given preset dictionary, as much of its end as the window
+    /// holds. The decoder must be given the same dictionary.
+    #[cfg(feature="dictionary")]
+    pub fn with_dictionary(w: W, level: u32, dictionary: &Dictionary) -> Encoder<W> {
+        let mut e = Encoder::new(w, level);
+        e.preset = dictionary.tail(MAX_DIST).to_vec();
+        e.prime();
+        e
+    }
+
+    /// Fills the window with the preset dictionary, if any, as if it had
+    /// been compressed already
+    fn prime(&mut self) {
+        let len = self.preset.len();
+        self.window.extend_from_slice(&self.preset);
+        self.fit_hash();
+        for pos in 0..len.saturating_sub(MIN_MATCH - 1) {
+            self.insert(pos);
+        }
+        self.strstart = len;
+        self.block_start = len as isize;
+    }
+
+    /// Selects how the input is parsed, see `Strategy`. Level 0 keeps storing
+    /// the input whatever the strategy. This must be called before any data
+    /// is written.
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        assert!(self.window.len() == self.preset.len(), "the strategy must be set before writing");
+        self.strategy = strategy;
+    }
+
+    /// Enables the rsyncable mode, where the output is cut into byte aligned
+    /// pieces at boundaries defined by the content, as with the `--rsyncable`
+    /// option of gzip. A local change of the input then only alters the
+    /// output around it, which suits delta transfer tools, for a slight loss
+    /// of compression.
+    pub fn set_rsyncable(&mut self, rsyncable: bool) {
+        self.rsync = if rsyncable { Some(Chunker::new(RSYNC_MIN, RSYNC_AVG, RSYNC_MAX)) } else { None };
+    }
+
+    /// Counts the bytes, the blocks, the literals and the lengths of the
+    /// matches into `sink` from now on
+    pub fn set_stats(&mut self, sink: Sink) {
+        self.stats = Some(sink);
+    }
+
+    /// Returns the compression level
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    fn config(&self) -> &'static Config {
+        &CONFIGS[self.level as usize]
+    }
+
+    /// Inserts the string starting at `pos` in the hash table, returning the
+    /// previous head of its chain
+    #[inline]
+    fn insert(&mut self, pos: usize) -> u32 {
+        let h = hash::hash3(&self.window[pos..], HASH_BITS) & (self.head.len() - 1);
+        let head = self.head[h];
+        self.prev[pos & WMASK] = head;
+        self.head[h] = pos as u32;
+        head
+    }
+
+    /// Grows the hash table and the chains to their full size once the window
+    /// outgrows the short ones, inserting again the strings before `strstart`
+    fn fit_hash(&mut self) {
+        if self.head.len() == HASH_SIZE || self.window.len() <= SHORT_INPUT {
+            return
+        }
+        self.head.clear();
+        self.head.resize(HASH_SIZE, NIL);
+        self.prev.resize(WSIZE, 0);
+        let end = cmp::min(self.strstart, (self.window.len() + 1).saturating_sub(MIN_MATCH));
+        for pos in 0..end {
+            self.insert(pos);
+        }
+    }
+
+    /// Drops the oldest half of the window, rebasing all positions
+    fn slide(&mut self) {
+        debug_assert!(self.strstart >= WSIZE);
+        self.window.drain(..WSIZE);
+        self.strstart -= WSIZE;
+        self.match_start = self.match_start.saturating_sub(WSIZE);
+        self.prev_match = self.prev_match.saturating_sub(WSIZE);
+        self.block_start -= WSIZE as isize;
+        for p in self.head.iter_mut().chain(self.prev.iter_mut()) {
+            *p = if *p != NIL && *p as usize >= WSIZE { *p - WSIZE as u32 } else { NIL };
+        }
+    }
+
+    /// Finds the longest match for the string at `strstart`, following the
+    /// chain from `cur`. Updates `match_start` and returns the match length,
+    /// which is only meaningful if it exceeds `prev_length`.
+    fn longest_match(&mut self, mut cur: usize) -> usize {
+        let config = self.config();
+        let scan = self.strstart;
+        let lookahead = self.window.len() - scan;
+        let max_len = cmp::min(MAX_MATCH, lookahead);
+        let nice = cmp::min(config.nice, lookahead);
+        let limit = if scan > MAX_DIST { scan - MAX_DIST } else { 0 };
+        let mut chain = config.chain;
+        let mut best_len = self.prev_length;
+        if best_len >= max_len {
+            return best_len
+        }
+        if best_len >= config.good {
+            chain >>= 2;
+        }
+        let window = &self.window[..];
+        loop {
+            // Check the byte right past the current best first, as it is the
+            // one most likely to differ
+            if window[cur + best_len] == window[scan + best_len] && window[cur] == window[scan] {
+                let len = hash::match_len(&window[cur..(cur + max_len)], &window[scan..(scan + max_len)]);
+                if len > best_len {
+                    self.match_start = cur;
+                    best_len = len;
+                    if len >= nice {
+                        break
+                    }
+                }
+            }
+            let next = self.prev[cur & WMASK];
+            chain -= 1;
+            if next == NIL || next as usize >= cur || (next as usize) < limit || chain == 0 {
+                break
+            }
+            cur = next as usize;
+        }
+        best_len
+    }
+
+    fn tally_literal(&mut self, byte: u8) {
+        self.tokens.push(Token { len: byte as u16, dist: 0 });
+        self.lit_freq[byte as usize] += 1;
+    }
+
+    fn tally_match(&mut self, dist: usize, len: usize) {
+        debug_assert!(dist >= 1 && dist <= WSIZE);
+        debug_assert!(len >= MIN_MATCH && len <= MAX_MATCH);
+        self.tokens.push(Token { len: len as u16, dist: dist as u16 });
+        self.lit_freq[LITERALS + 1 + LENGTH_CODE[len - MIN_MATCH] as usize] += 1;
+        self.dist_freq[dist_code(dist)] += 1;
+    }
+
+    /// Runs the parser over the buffered input. Unless `flush` is set, it
+    /// stops as soon as the lookahead gets too short to find long matches.
+    fn deflate(&mut self, flush: bool) {
+        self.fit_hash();
+        if self.level == 0 || self.strategy == Strategy::HuffmanOnly {
+            self.deflate_literals(flush)
+        } else if let Strategy::Optimal { iterations } = self.strategy {
+            self.deflate_optimal(iterations, flush)
+        } else if self.config().lazy_eval {
+            self.deflate_lazy(flush)
+        } else {
+            self.deflate_greedy(flush)
+        }
+    }
+
+    /// Emits every byte as a literal. Those blocks are stored at level 0, and
+    /// huffman coded otherwise.
+    fn deflate_literals(&mut self, flush: bool) {
+        loop {
+            let lookahead = self.window.len() - self.strstart;
+            if lookahead == 0 || (lookahead < MIN_LOOKAHEAD && !flush) {
+                return
+            }
+            let take = cmp::min(lookahead, BLOCK_TOKENS - self.tokens.len());
+            let literals = &self.window[self.strstart..(self.strstart + take)];
+            self.tokens.extend(literals.iter().map(|&byte| Token { len: byte as u16, dist: 0 }));
+            for (f, &n) in self.lit_freq.iter_mut().zip(histogram(literals).iter()) {
+                *f += n as u32;
+            }
+            self.strstart += take;
+            if self.tokens.len() >= BLOCK_TOKENS {
+                self.flush_block(false);
+            }
+        }
+    }
+
+    fn deflate_greedy(&mut self, flush: bool) {
+        let max_insert = self.config().lazy;
+        loop {
+            let lookahead = self.window.len() - self.strstart;
+            if lookahead == 0 || (lookahead < MIN_LOOKAHEAD && !flush) {
+                return
+            }
+            let head = if lookahead >= MIN_MATCH {
+                let pos = self.strstart;
+                self.insert(pos)
+            } else {
+                NIL
+            };
+            self.prev_length = MIN_MATCH - 1;
+            self.match_length = MIN_MATCH - 1;
+            if head != NIL && self.strstart - head as usize <= MAX_DIST {
+                self.match_length = self.longest_match(head as usize);
+            }
+            if self.match_length >= MIN_MATCH {
+                let (dist, len) = (self.strstart - self.match_start, self.match_length);
+                self.tally_match(dist, len);
+                if len <= max_insert && lookahead - len >= MIN_MATCH {
+                    for _ in 1..len {
+                        self.strstart += 1;
+                        let pos = self.strstart;
+                        self.insert(pos);
+                    }
+                    self.strstart += 1;
+                } else {
+                    self.strstart += len;
+                }
+            } else {
+                let byte = self.window[self.strstart];
+                self.tally_literal(byte);
+                self.strstart += 1;
+            }
+            if self.tokens.len() >= BLOCK_TOKENS {
+                self.flush_block(false);
+            }
+        }
+    }
+
+    fn deflate_lazy(&mut self, flush: bool) {
+        let max_lazy = self.config().lazy;
+        loop {
+            let lookahead = self.window.len() - self.strstart;
+            if lookahead == 0 || (lookahead < MIN_LOOKAHEAD && !flush) {
+                break
+            }
+            let head = if lookahead >= MIN_MATCH {
+                let pos = self.strstart;
+                self.insert(pos)
+            } else {
+                NIL
+            };
+
+            // Find the longest match, discarding those that are no longer
+            // than the previous match
+            self.prev_length = self.match_length;
+            self.prev_match = self.match_start;
+            self.match_length = MIN_MATCH - 1;
+            if head != NIL && self.prev_length < max_lazy &&
+                    self.strstart - head as usize <= MAX_DIST {
+                self.match_length = self.longest_match(head as usize);
+                if self.match_length == MIN_MATCH &&
+                        self.strstart - self.match_start > TOO_FAR {
+                    // a short far match is more expensive than literals
+                    self.match_length = MIN_MATCH - 1;
+                }
+            }
+
+            if self.prev_length >= MIN_MATCH && self.match_length <= self.prev_length {
+                // The previous match is at least as good: emit it, and
+                // insert all the strings it covers
+                let max_insert = self.window.len() - MIN_MATCH;
+                let (dist, len) = (self.strstart - 1 - self.prev_match, self.prev_length);
+                self.tally_match(dist, len);
+                for _ in 0..(len - 2) {
+                    self.strstart += 1;
+                    if self.strstart <= max_insert {
+                        let pos = self.strstart;
+                        self.insert(pos);
+                    }
+                }
+                self.match_available = false;
+                self.match_length = MIN_MATCH - 1;
+                self.strstart += 1;
+                if self.tokens.len() >= BLOCK_TOKENS {
+                    self.flush_block(false);
+                }
+            } else if self.match_available {
+                // No better match: the previous position is a literal, and
+                // the current match becomes the candidate
+                let byte = self.window[self.strstart - 1];
+                self.tally_literal(byte);
+                if self.tokens.len() >= BLOCK_TOKENS {
+                    self.flush_block(false);
+                }
+                self.strstart += 1;
+            } else {
+                // Wait for the next step to decide
+                self.match_available = true;
+                self.strstart += 1;
+            }
+        }
+        if flush && self.match_available {
+            let byte = self.window[self.strstart - 1];
+            self.tally_literal(byte);
+            self.match_available = false;
+        }
+    }
+
+    /// Parses the input by segments, gathering them into blocks as long as
+    /// the symbols of the next one fit. Each segment is parsed along with
+    /// the next `MAX_MATCH` bytes, so that its last match may run past its
+    /// end, and the following segment starts where that match stops.
+    fn deflate_optimal(&mut self, iterations: u32, flush: bool) {
+        let mut matches = Matches::new();
+        loop {
+            let lookahead = self.window.len() - self.strstart;
+            let len = if flush || lookahead >= SEGMENT + MIN_LOOKAHEAD {
+                cmp::min(lookahead, SEGMENT + MAX_MATCH)
+            } else {
+                0
+            };
+            if len == 0 {
+                return
+            }
+            let start = self.strstart;
+            let segment = cmp::min(len, SEGMENT);
+            self.find_matches(start, start + segment, start + len, &mut matches);
+            let path = optimal_parse(&self.window[start..(start + len)], segment, &matches, iterations);
+            if self.tokens.len() + path.len() > BLOCK_TOKENS {
+                self.flush_block(false);
+            }
+            let mut end = start;
+            for t in path.into_iter() {
+                if t.dist == 0 {
+                    self.tally_literal(t.len as u8);
+                    end += 1;
+                } else {
+                    self.tally_match(t.dist as usize, t.len as usize);
+                    end += t.len as usize;
+                }
+            }
+            // the strings past the segment which the last match covers
+            for pos in (start + segment)..cmp::min(end, (self.window.len() + 1).saturating_sub(MIN_MATCH)) {
+                self.insert(pos);
+            }
+            self.strstart = end;
+        }
+    }
+
+    /// Inserts every position of `[start, end)` in the hash table, gathering
+    /// the matches which do not cross `stop`, and gathers those of the
+    /// positions of `[end, stop)` without inserting them
+    fn find_matches(&mut self, start: usize, end: usize, stop: usize, matches: &mut Matches) {
+        matches.clear();
+        for pos in start..stop {
+            let max_len = cmp::min(MAX_MATCH, stop - pos);
+            let mut cur = if pos + MIN_MATCH > self.window.len() {
+                NIL
+            } else if pos < end {
+                self.insert(pos)
+            } else {
+                let h = hash::hash3(&self.window[pos..], HASH_BITS) & (self.head.len() - 1);
+                self.head[h]
+            };
+            let limit = pos.saturating_sub(MAX_DIST);
+            let mut best = MIN_MATCH - 1;
+            let mut chain = OPTIMAL_CHAIN;
+            while cur != NIL && best < max_len && chain > 0 {
+                let c = cur as usize;
+                if c < limit {
+                    break
+                }
+                if self.window[c + best] == self.window[pos + best] {
+                    let len = hash::match_len(&self.window[c..(c + max_len)], &self.window[pos..(pos + max_len)]);
+                    if len > best {
+                        matches.pairs.push((len as u16, (pos - c) as u16));
+                        best = len;
+                    }
+                }
+                chain -= 1;
+                cur = self.prev[c & WMASK];
+            }
+            matches.ends.push(matches.pairs.len());
+        }
+    }
+
+    fn write_tokens(&mut self, lit_lens: &[u8], lit_codes: &[u16],
+                    dist_lens: &[u8], dist_codes: &[u16]) {
+        for t in self.tokens.iter() {
+            if t.dist == 0 {
+                let sym = t.len as usize;
+                self.out.bits(lit_codes[sym] as u32, lit_lens[sym]);
+            } else {
+                let len = t.len as usize;
+                let code = LENGTH_CODE[len - MIN_MATCH] as usize;
+                let sym = LITERALS + 1 + code;
+                self.out.bits(lit_codes[sym] as u32, lit_lens[sym]);
+                self.out.bits((len - EXTRALENS[code] as usize) as u32, EXTRABITS[code] as u8);
+                let dist = t.dist as usize;
+                let code = dist_code(dist);
+                self.out.bits(dist_codes[code] as u32, dist_lens[code]);
+                self.out.bits((dist - EXTRADIST[code] as usize) as u32, EXTRADBITS[code] as u8);
+            }
+        }
+        self.out.bits(lit_codes[END_OF_BLOCK] as u32, lit_lens[END_OF_BLOCK]);
+    }
+
+    fn write_stored(&mut self, last: bool) {
+        let start = self.block_start as usize;
+        let data = &self.window[start..self.strstart];
+        let mut chunks = data.chunks(MAX_STORED).peekable();
+        if data.is_empty() {
+            self.out.bits(last as u32, 3);
+            self.out.align();
+            self.out.out.extend([0, 0, 0xff, 0xff].iter().cloned());
+        }
+        while let Some(chunk) = chunks.next() {
+            let final_chunk = last && chunks.peek().is_none();
+            self.out.bits(final_chunk as u32, 3);
+            self.out.align();
+            let len = chunk.len() as u16;
+            self.out.out.extend([len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8].iter().cloned());
+            self.out.out.extend(chunk.iter().cloned());
+        }
+    }
+
+    /// Emits the gathered symbols as one block, choosing the cheapest of the
+    /// three block types
+    fn flush_block(&mut self, last: bool) {
+        self.lit_freq[END_OF_BLOCK] += 1;
+        if let Some(ref sink) = self.stats {
+            let tokens = &self.tokens;
+            sink.update(|stats| {
+                stats.blocks += 1;
+                for t in tokens.iter() {
+                    if t.dist == 0 {
+                        stats.literals[t.len as usize] += 1;
+                    } else {
+                        stats.add_match(t.len as usize);
+                    }
+                }
+            });
+        }
+
+        let codes = DynamicCodes::new(&self.lit_freq, &self.dist_freq);
+        let (fixed_lit, fixed_dist) = fixed_lengths();
+        let fixed_bits = 3 + data_bits(&self.lit_freq, &self.dist_freq, &fixed_lit, &fixed_dist);
+        let stored_bits = if self.block_start >= 0 {
+            let len = self.strstart - self.block_start as usize;
+            let chunks = cmp::max(1, (len + MAX_STORED - 1) / MAX_STORED);
+            // header, worst case alignment, then LEN and NLEN per chunk
+            len * 8 + chunks * (3 + 7 + 32)
+        } else {
+            usize::max_value()
+        };
+
+        if self.level == 0 || stored_bits <= cmp::min(fixed_bits, codes.bits) {
+            assert!(self.block_start >= 0, "stored block data is out of the window");
+            self.write_stored(last);
+        } else if fixed_bits <= codes.bits {
+            self.out.bits(last as u32 | (1 << 1), 3);
+            let mut lit_codes = [0u16; 288];
+            let mut dist_codes = [0u16; DCODES];
+            build_codes(&fixed_lit, &mut lit_codes);
+            build_codes(&fixed_dist, &mut dist_codes);
+            self.write_tokens(&fixed_lit, &lit_codes, &fixed_dist, &dist_codes);
+        } else {
+            self.out.bits(last as u32 | (2 << 1), 3);
+            self.out.bits((codes.hlit - 257) as u32, 5);
+            self.out.bits((codes.hdist - 1) as u32, 5);
+            self.out.bits((codes.hclen - 4) as u32, 4);
+            for &sym in ORDER[..codes.hclen].iter() {
+                self.out.bits(codes.bl_lens[sym] as u32, 3);
+            }
+            let mut bl_codes = [0u16; BLCODES];
+            build_codes(&codes.bl_lens, &mut bl_codes);
+            for &(sym, extra) in codes.bl_syms.iter() {
+                let sym = sym as usize;
+                self.out.bits(bl_codes[sym] as u32, codes.bl_lens[sym]);
+                match sym {
+                    16 => self.out.bits(extra as u32, 2),
+                    17 => self.out.bits(extra as u32, 3),
+                    18 => self.out.bits(extra as u32, 7),
+                    _ => (),
+                }
+            }
+            let mut lit_codes = [0u16; LCODES];
+            let mut dist_codes = [0u16; DCODES];
+            build_codes(&codes.lit_lens, &mut lit_codes);
+            build_codes(&codes.dist_lens, &mut dist_codes);
+            self.write_tokens(&codes.lit_lens, &lit_codes, &codes.dist_lens, &dist_codes);
+        }
+
+        self.tokens.truncate(0);
+        self.lit_freq = [0; LCODES];
+        self.dist_freq = [0; DCODES];
+        self.block_start = self.strstart as isize;
+    }
+
+    fn write_output(&mut self) -> io::Result<()> {
+        if self.out.out.len() > 0 {
+            if let Some(ref sink) = self.stats {
+                let len = self.out.out.len() as u64;
+                sink.update(|stats| stats.output += len);
+            }
+            try!(self.w.write_all(&self.out.out));
+            self.out.out.truncate(0);
+        }
+        Ok(())
+    }
+
+    /// Acquires a mutable reference to the underlying writer. Data written
+    /// there goes in front of whatever compressed output is still pending.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.w
+    }
+
+    /// Returns the underlying writer without ending the stream, dropping any
+    /// pending input. Right after a `flush()`, what was written is a valid
+    /// DEFLATE stream missing its final block, which another stream can
+    /// follow.
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.end();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`
+    /// with the same settings and dictionary, keeping the window and the hash
+    /// chains allocated. The previous writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.end();
+        self.window.truncate(0);
+        self.head.clear();
+        self.head.resize(SHORT_HASH_SIZE, NIL);
+        self.prev.truncate(SHORT_INPUT);
+        self.strstart = 0;
+        self.block_start = 0;
+        self.match_length = MIN_MATCH - 1;
+        self.match_start = 0;
+        self.prev_length = MIN_MATCH - 1;
+        self.prev_match = 0;
+        self.match_available = false;
+        self.tokens.truncate(0);
+        self.lit_freq = [0; LCODES];
+        self.dist_freq = [0; DCODES];
+        if let Some(ref mut rsync) = self.rsync {
+            rsync.reset();
+        }
+        self.prime();
+        (mem::replace(&mut self.w, w), result)
+    }
+
+    /// Writes the last block of the stream
+    fn end(&mut self) -> io::Result<()> {
+        self.deflate(true);
+        self.flush_block(true);
+        self.out.align();
+        self.write_output().and_then(|_| self.w.flush())
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len();
+        if let Some(ref sink) = self.stats {
+            sink.update(|stats| stats.input += len as u64);
+        }
+        while buf.len() > 0 {
+            if self.window.len() == 2 * WSIZE {
+                self.slide();
+            }
+            let mut amt = cmp::min(2 * WSIZE - self.window.len(), buf.len());
+            let boundary = match self.rsync {
+                Some(ref mut rsync) => rsync.scan(&buf[..amt]),
+                None => None,
+            };
+            if let Some(n) = boundary {
+                amt = n;
+            }
+            self.window.extend(buf[..amt].iter().cloned());
+            buf = &buf[amt..];
+            if boundary.is_some() {
+                // compress everything up to the boundary, and start afresh
+                // on a byte boundary
+                self.deflate(true);
+                if self.tokens.len() > 0 {
+                    self.flush_block(false);
+                }
+                self.out.sync();
+            } else {
+                self.deflate(false);
+            }
+            try!(self.write_output());
+        }
+        Ok(len)
+    }
+
+    /// Compresses all the pending input and aligns the output on a byte
+    /// boundary with an empty stored block, so that everything written so
+    /// far can be decoded (this is zlib's sync flush).
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflate(true);
+        if self.tokens.len() > 0 {
+            self.flush_block(false);
+        }
+        self.out.sync();
+        try!(self.write_output());
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing::Lcg;
+    use super::super::Decoder;
+    use super::{Encoder, Strategy, HASH_SIZE, SHORT_HASH_SIZE};
+    #[cfg(feature="unstable")]
+    use test::Bencher;
+
+    fn compress(bytes: &[u8], level: u32) -> Vec<u8> {
+        compress_with(bytes, level, Strategy::Default)
+    }
+
+    fn compress_with(bytes: &[u8], level: u32, strategy: Strategy) -> Vec<u8> {
+        let mut e = Encoder::new(Vec::new(), level);
+        e.set_strategy(strategy);
+        e.write_all(bytes).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        encoded
+    }
+
+    fn decompress(encoded: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::new(encoded));
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        decoded
+    }
+
+    fn roundtrip(bytes: &[u8], level: u32) -> usize {
+        let encoded = compress(bytes, level);
+        assert!(decompress(&encoded) == bytes);
+        encoded.len()
+    }
+
+    /// Deterministic pseudo-random bytes with a small alphabet, so that there
+    /// are plenty of short matches at all distances
+    fn noise(n: usize, alphabet: u32) -> Vec<u8> {
+        let mut lcg = Lcg(12345);
+        (0..n).map(|_| (((lcg.step() >> 16) % alphabet) as u8).wrapping_add(b'a')).collect()
+    }
+
+    #[test]
+    fn roundtrips() {
+        let text = include_bytes!("../data/test.txt");
+        let long = noise(200000, 4);
+        let runs: Vec<u8> = (0..100000).map(|i| (i / 1000) as u8).collect();
+        for level in 0..10 {
+            roundtrip(b"", level);
+            roundtrip(b"a", level);
+            roundtrip(b"test", level);
+            roundtrip(text, level);
+            roundtrip(&long, level);
+            roundtrip(&runs, level);
+        }
+    }
+
+    #[test]
+    fn incompressible() {
+        let random = noise(100000, 256);
+        let size = roundtrip(&random, 6);
+        // stored blocks bound the expansion
+        assert!(size < random.len() + random.len() / 100);
+    }
+
+    #[test]
+    fn lazy_beats_greedy() {
+        let text = include_bytes!("../data/test.txt");
+        let long = noise(100000, 4);
+        assert!(roundtrip(text, 6) < roundtrip(text, 1));
+        assert!(roundtrip(&long, 9) <= roundtrip(&long, 6));
+        assert!(roundtrip(&long, 6) < roundtrip(&long, 3));
+    }
+
+    #[test]
+    fn small_writes_and_flush() {
+        let text = include_bytes!("../data/test.txt");
+        let mut e = Encoder::new(Vec::new(), 6);
+        for chunk in text.chunks(37) {
+            e.write_all(chunk).unwrap();
+        }
+        e.flush().unwrap();
+        e.write_all(text).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        let mut expected = text.to_vec();
+        expected.extend(text.iter().cloned());
+        assert!(decompress(&encoded) == expected);
+    }
+
+    #[test]
+    fn short_inputs() {
+        // the hash table grows in the middle of the stream, and shrinks back
+        // for the next one
+        let long = noise(20000, 4);
+        for level in 1..10 {
+            for &strategy in [Strategy::Default, Strategy::Optimal { iterations: 1 }].iter() {
+                let mut e = Encoder::new(Vec::new(), level);
+                e.set_strategy(strategy);
+                e.write_all(b"short").unwrap();
+                let (short, err) = e.reset(Vec::new());
+                err.unwrap();
+                assert!(decompress(&short) == b"short");
+                assert_eq!(e.head.len(), SHORT_HASH_SIZE);
+                for chunk in long.chunks(100) {
+                    e.write_all(chunk).unwrap();
+                }
+                assert_eq!(e.head.len(), HASH_SIZE);
+                let (encoded, err) = e.reset(Vec::new());
+                err.unwrap();
+                assert!(decompress(&encoded) == long);
+                assert!(encoded.len() <= compress_with(&long, level, strategy).len() + 100);
+                assert_eq!(e.head.len(), SHORT_HASH_SIZE);
+            }
+        }
+    }
+
+    #[test]
+    fn sync_flush_is_decodable() {
+        let mut e = Encoder::new(Vec::new(), 6);
+        e.write_all(b"hello hello hello").unwrap();
+        e.flush().unwrap();
+        let (partial, _) = e.finish();
+        let mut d = Decoder::new(BufReader::new(&partial[..]));
+        let mut buf = [0u8; 17];
+        d.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &b"hello hello hello"[..]);
+    }
+
+    #[test]
+    fn optimal() {
+        let text = include_bytes!("../data/test.txt");
+        let long = noise(50000, 4);
+        let runs: Vec<u8> = (0..50000).map(|i| (i / 1000) as u8).collect();
+        let strategy = Strategy::Optimal { iterations: 5 };
+        for input in [&b""[..], &b"a"[..], &text[..], &long[..], &runs[..]].iter() {
+            let encoded = compress_with(input, 9, strategy);
+            assert!(&decompress(&encoded)[..] == *input);
+        }
+        assert!(compress_with(text, 9, strategy).len() < compress(text, 9).len());
+        assert!(compress_with(&long, 9, strategy).len() < compress(&long, 9).len());
+        // the matches run across the ends of the segments and the blocks
+        // across the slides of the window
+        assert!(compress_with(&runs, 9, strategy).len() <= compress(&runs, 9).len());
+        let zeros = vec![0u8; 1 << 17];
+        assert!(compress_with(&zeros, 9, strategy).len() <= compress(&zeros, 9).len());
+    }
+
+    #[test]
+    fn huffman_only() {
+        let text = include_bytes!("../data/test.txt");
+        let runs: Vec<u8> = (0..50000).map(|i| (i / 1000) as u8).collect();
+        let strategy = Strategy::HuffmanOnly;
+        for input in [&b""[..], &b"a"[..], &text[..], &runs[..]].iter() {
+            let encoded = compress_with(input, 6, strategy);
+            assert!(&decompress(&encoded)[..] == *input);
+        }
+        // literals cost at least a bit each
+        let encoded = compress_with(&runs, 6, strategy);
+        assert!(encoded.len() >= runs.len() / 8);
+        assert!(compress_with(text, 6, strategy).len() < text.len());
+    }
+
+    #[test]
+    fn optimal_small_writes_and_flush() {
+        let text = include_bytes!("../data/test.txt");
+        let mut e = Encoder::new(Vec::new(), 9);
+        e.set_strategy(Strategy::Optimal { iterations: 2 });
+        for chunk in text.chunks(1000) {
+            e.write_all(chunk).unwrap();
+        }
+        e.flush().unwrap();
+        e.write_all(text).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        let mut expected = text.to_vec();
+        expected.extend(text.iter().cloned());
+        assert!(decompress(&encoded) == expected);
+    }
+
+    #[test]
+    fn rsyncable() {
+        fn common_suffix(a: &[u8], b: &[u8]) -> usize {
+            a.iter().rev().zip(b.iter().rev()).take_while(|&(x, y)| x == y).count()
+        }
+        fn compress_rsyncable(bytes: &[u8], rsyncable: bool) -> Vec<u8> {
+            let mut e = Encoder::new(Vec::new(), 6);
+            e.set_rsyncable(rsyncable);
+            e.write_all(bytes).unwrap();
+            let (encoded, err) = e.finish();
+            err.unwrap();
+            assert!(decompress(&encoded) == bytes);
+            encoded
+        }
+        let original = noise(100000, 16);
+        let mut edited = original.clone();
+        edited.insert(1000, b'!');
+        edited[2000] = b'?';
+
+        let (a, b) = (compress_rsyncable(&original, true), compress_rsyncable(&edited, true));
+        // only the blocks within a window of the edits differ
+        assert!(common_suffix(&a, &b) > a.len() / 2);
+        let plain = compress_rsyncable(&original, false);
+        assert!(common_suffix(&plain, &compress_rsyncable(&edited, false)) < 100);
+        assert!(a.len() < plain.len() + plain.len() / 50);
+    }
+
+    #[cfg(feature="unstable")]
+    #[bench]
+    fn compress_speed(bh: &mut Bencher) {
+        // level 9 follows the longest chains
+        let input = &include_bytes!("../data/test.large")[..1 << 20];
+        bh.iter(|| compress(input, 9));
+        bh.bytes = input.len() as u64;
+    }
+}
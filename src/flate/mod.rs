@@ -0,0 +1,1523 @@
+//! DEFLATE Compression and Decompression. Requires `flate` feature, enabled by default
+//!
+//! This module contains an implementation of the DEFLATE compression scheme.
+//! This format is often used as the underpinning of other compression formats.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::flate;
+//! use std::fs::File;
+//! use std::path::Path;
+//!
+//! let stream = File::open(&Path::new("path/to/file.flate")).unwrap();
+//! let mut decompressed = Vec::new();
+//! flate::Decoder::new(stream).read_to_end(&mut decompressed);
+//! ```
+//!
+//! # Related links
+//!
+//! * http://tools.ietf.org/html/rfc1951 - RFC that this implementation is based
+//!   on
+//! * http://www.gzip.org/zlib/rfc-deflate.html - simplified version of RFC 1951
+//!   used as a reference
+//! * http://svn.ghostscript.com/ghostscript/trunk/gs/zlib/contrib/puff/puff.c -
+//!   Much of this code is based on the puff.c implementation found here
+
+use std::borrow::Cow;
+use std::cmp;
+use std::ptr::{self, copy_nonoverlapping};
+use std::io::{self, Read};
+use std::vec::Vec;
+
+use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use super::ReadExact;
+use copy::copy_match;
+#[cfg(feature="dictionary")]
+use dictionary::Dictionary;
+use error::{corrupt, truncated};
+use pool::{self, Buffer, Pool};
+use process::{decode_to_vec, drain, Process, Pump, Status};
+use OutputLimit;
+
+pub use self::encoder::{max_compressed_len, DeflateOptions, Encoder, Strategy};
+
+mod encoder;
+
+const MAXBITS: usize = 15;
+const MAXLCODES: u16 = 286;
+// Deflate64 adds two distance codes, and doubles the window
+const MAXDCODES: u16 = 30;
+const MAXDCODES64: u16 = 32;
+const MAXCODES: u16 = MAXLCODES + MAXDCODES64;
+const HISTORY: usize = 32 * 1024;
+const HISTORY64: usize = 64 * 1024;
+// bytes of input taken at once by a decoder
+const READ_AHEAD: usize = 16 * 1024;
+// bytes decoded ahead of the output, give or take a match
+const BLOCK_CHUNK: usize = 64 * 1024;
+// leading bytes of a saved decoder state, the last one being its version
+const STATE_MAGIC: [u8; 4] = [b'I', b'N', b'F', 2];
+const STATE_EOF: u8 = 1 << 0;
+const STATE_DEFLATE64: u8 = 1 << 1;
+// the part of the stream a saved decoder state decodes next
+const STATE_HEADER: u8 = 0;
+const STATE_STORED: u8 = 1;
+const STATE_CODES: u8 = 2;
+
+// extra base length for codes 257-285
+const EXTRALENS: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51,
+    59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+// extra bits to read for codes 257-285
+const EXTRABITS: [u16; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4,
+    4, 5, 5, 5, 5, 0,
+];
+// base offset for distance codes, the last two being Deflate64 only
+const EXTRADIST: [u16; 32] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385,
+    513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385,
+    24577, 32769, 49153,
+];
+// number of bits to read for distance codes (to add to the offset)
+const EXTRADBITS: [u16; 32] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9,
+    10, 10, 11, 11, 12, 12, 13, 13, 14, 14,
+];
+// in Deflate64, length code 285 reads 16 extra bits on top of a base of 3
+const EXTRABITS64: usize = 16;
+// order in which the code length code lengths are transmitted
+const ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+enum Error {
+    HuffmanTreeTooLarge,
+    InvalidBlockCode,
+    InvalidHuffmanHeaderSymbol,
+    InvalidHuffmanTree,
+    InvalidHuffmanTreeHeader,
+    InvalidHuffmanCode,
+    InvalidStaticSize,
+    InvalidState,
+}
+
+fn error<T>(e: Error) -> io::Result<T> {
+    corrupt(match e {
+        Error::HuffmanTreeTooLarge => "huffman tree too large",
+        Error::InvalidBlockCode => "invalid block code",
+        Error::InvalidHuffmanHeaderSymbol => "invalid huffman header symbol",
+        Error::InvalidHuffmanTree => "invalid huffman tree",
+        Error::InvalidHuffmanTreeHeader => "invalid huffman tree header",
+        Error::InvalidHuffmanCode => "invalid huffman code",
+        Error::InvalidStaticSize => "invalid static size",
+        Error::InvalidState => "invalid inflate state",
+    })
+}
+
+/// Number of bits resolved by the first lookup of a literal/length symbol
+const LEN_ROOT_BITS: usize = 9;
+/// Number of bits resolved by the first lookup of a distance symbol
+const DIST_ROOT_BITS: usize = 6;
+/// Number of bits resolved by the first lookup of a code length symbol
+const CLEN_ROOT_BITS: usize = 7;
+
+#[derive(Clone, Copy)]
+enum Entry {
+    /// A symbol, and the total length of its code
+    Symbol(u16, u8),
+    /// A secondary table at the given offset, indexed by the given number of
+    /// bits past the root ones
+    Link(u16, u8),
+    /// No code starts with these bits, which are that many
+    Invalid(u8),
+}
+
+struct HuffmanTree {
+    /// The root table, indexed by the next `root` bits of the stream, followed
+    /// by the secondary tables of the codes which are longer
+    table: Vec<Entry>,
+    root: usize,
+}
+
+impl HuffmanTree {
+    /// Constructs a new huffman tree for decoding. If the given array has
+    /// length N, then the huffman tree can be used to decode N symbols. Each
+    /// entry in the array corresponds to the length of the nth symbol. The
+    /// codes up to `root` bits long are resolved with a single lookup.
+    fn construct(lens: &[u16], root: usize) -> io::Result<HuffmanTree> {
+        // Collect the lengths of all symbols
+        let mut count = [0u16; MAXBITS + 1];
+        for len in lens.iter() {
+            count[*len as usize] += 1;
+        }
+
+        // Make sure that this tree is sane. Each bit gives us 2x more codes to
+        // work with, but if the counts add up to greater than the available
+        // amount, then this is an invalid table.
+        let mut left = 1;
+        for i in 1..(MAXBITS + 1) {
+            left *= 2;
+            left -= count[i] as isize;
+            if left < 0 { return error(Error::InvalidHuffmanTree) }
+        }
+
+        // Generate the first canonical code of each length
+        let mut next = [0u16; MAXBITS + 1];
+        for i in 1..MAXBITS {
+            next[i + 1] = (next[i] + count[i]) << 1;
+        }
+        let mut codes = vec![0u16; lens.len()];
+        for (sym, &len) in lens.iter().enumerate() {
+            if len != 0 {
+                // codes are packed starting from their first bit, which is
+                // the least significant one of the stream
+                codes[sym] = reverse(next[len as usize], len as usize);
+                next[len as usize] += 1;
+            }
+        }
+
+        // Size the secondary tables after the longest code sharing each root
+        // prefix
+        let mut tree = HuffmanTree {
+            table: vec![Entry::Invalid(root as u8); 1 << root],
+            root: root,
+        };
+        let mask = (1 << root) - 1;
+        let mut sub_bits = vec![0u8; 1 << root];
+        for (sym, &len) in lens.iter().enumerate() {
+            let len = len as usize;
+            if len > root {
+                let prefix = codes[sym] as usize & mask;
+                sub_bits[prefix] = cmp::max(sub_bits[prefix], (len - root) as u8);
+            }
+        }
+        for (prefix, &bits) in sub_bits.iter().enumerate() {
+            if bits != 0 {
+                let offset = tree.table.len();
+                tree.table[prefix] = Entry::Link(offset as u16, bits);
+                let invalid = Entry::Invalid((root + bits as usize) as u8);
+                tree.table.extend((0..(1 << bits)).map(|_| invalid));
+            }
+        }
+
+        // Fill every slot whose bits start with a code
+        for (sym, &len) in lens.iter().enumerate() {
+            let len = len as usize;
+            if len == 0 { continue }
+            let code = codes[sym] as usize;
+            let entry = Entry::Symbol(sym as u16, len as u8);
+            if len <= root {
+                let mut i = code;
+                while i < (1 << root) {
+                    tree.table[i] = entry;
+                    i += 1 << len;
+                }
+            } else {
+                let (offset, bits) = match tree.table[code & mask] {
+                    Entry::Link(offset, bits) => (offset as usize, bits as usize),
+                    _ => unreachable!(),
+                };
+                let mut i = code >> root;
+                while i < (1 << bits) {
+                    tree.table[offset + i] = entry;
+                    i += 1 << (len - root);
+                }
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Decodes a codepoint from the buffer.
+    ///
+    /// This looks up the pending bits in the root table, then in a secondary
+    /// table for the longer codes. Bytes are only pulled from the stream while
+    /// the entry found needs more bits than there are, so that nothing past
+    /// the end of the deflate data is ever consumed.
+    fn decode<S: Source>(&self, s: &mut Processor, src: &mut S) -> io::Result<u16> {
+        let mask = (1 << self.root) - 1;
+        s.refill();
+        loop {
+            let mut entry = self.table[s.bitbuf as usize & mask];
+            if let Entry::Link(offset, bits) = entry {
+                let index = (s.bitbuf >> self.root) as usize & ((1 << bits) - 1);
+                entry = self.table[offset as usize + index];
+            }
+            match entry {
+                Entry::Symbol(sym, len) if len as usize <= s.bitcnt => {
+                    s.consume(len as usize);
+                    return Ok(sym)
+                }
+                Entry::Invalid(len) if len as usize <= s.bitcnt => {
+                    return error(Error::InvalidHuffmanCode)
+                }
+                _ => try!(s.pull_byte(src)),
+            }
+        }
+    }
+}
+
+/// Reverses the order of the `len` low bits of `code`
+fn reverse(code: u16, len: usize) -> u16 {
+    code.reverse_bits() >> (16 - len)
+}
+
+/// The part of the stream a `Processor` decodes next
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// The header of the next block
+    Header,
+    /// The data of a stored block, of which this many bytes are left
+    Stored(usize),
+    /// The codes of a compressed block
+    Codes,
+    /// Nothing, the final block being over
+    Done,
+}
+
+/// Where a `Processor` takes the bytes it needs past its input
+trait Source {
+    /// Appends from 1 to `n` bytes of the stream to `input`, failing with
+    /// `WouldBlock` if there are none at hand
+    fn pull(&mut self, input: &mut Vec<u8>, n: usize) -> io::Result<()>;
+}
+
+/// The source of a `Processor` fed with slices, which has nothing more
+struct Fed;
+
+impl Source for Fed {
+    fn pull(&mut self, _: &mut Vec<u8>, _: usize) -> io::Result<()> {
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+}
+
+/// The source of a `Decoder` which doesn't read ahead, reading the bytes as
+/// they are needed
+struct Pull<'a, R: 'a>(&'a mut R);
+
+impl<'a, R: Read> Source for Pull<'a, R> {
+    fn pull(&mut self, input: &mut Vec<u8>, n: usize) -> io::Result<()> {
+        let len = input.len();
+        input.resize(len + n, 0);
+        loop {
+            match self.0.read(&mut input[len..]) {
+                Ok(0) => {
+                    input.truncate(len);
+                    return truncated("truncated deflate stream")
+                }
+                Ok(read) => {
+                    input.truncate(len + read);
+                    return Ok(())
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    input.truncate(len);
+                    return Err(e)
+                }
+            }
+        }
+    }
+}
+
+/// The position of a `Processor` in its input, to decode a symbol again when
+/// the input ran out in the middle of it
+#[derive(Clone, Copy)]
+struct Mark {
+    bitbuf: u64,
+    bitcnt: usize,
+    inpos: usize,
+    total_in: u64,
+}
+
+/// This structure is used to decode a DEFLATE stream, fed with slices of
+/// it. The symbols which the input ends in the middle of are decoded again
+/// from their start once more comes, so that no input is used past the end
+/// of the stream.
+pub struct Processor {
+    output: Buffer<u8>,
+    outpos: usize,
+
+    /// The decoded bytes, read from `pos`, of which those up to `updated`
+    /// are in the window
+    block: Buffer<u8>,
+    pos: usize,
+    updated: usize,
+
+    bitbuf: u64,
+    bitcnt: usize,
+    state: State,
+    /// Whether the block is the final one
+    eof: bool,
+    total_in: u64,
+
+    /// The input left to decode, from `inpos`, with up to 8 bytes already
+    /// consumed kept in front of it
+    input: Buffer<u8>,
+    inpos: usize,
+    /// Whether the input is over
+    finished: bool,
+    pool: Option<Pool>,
+
+    deflate64: bool,
+    history: usize,
+    /// The end of the preset dictionary, the window starts with
+    preset: Vec<u8>,
+
+    /// The code lengths of the compressed block, none for the fixed codes,
+    /// of which `hlit` are those of the literals and lengths
+    lens: Vec<u16>,
+    hlit: usize,
+    dynamic_codes: Option<(HuffmanTree, HuffmanTree)>,
+    fixed_codes: Option<(HuffmanTree, HuffmanTree)>,
+}
+
+impl Processor {
+    /// Creates a new flate decoder, waiting for the first block
+    pub fn new() -> Processor {
+        Processor::with_history(false, HISTORY)
+    }
+
+    /// Creates a new decoder for the Deflate64 variant (method 9 of ZIP
+    /// archives), which has a 64KB window, longer matches and two more
+    /// distance codes
+    pub fn new_deflate64() -> Processor {
+        Processor::with_history(true, HISTORY64)
+    }
+
+    /// Creates a new flate decoder of a stream compressed with the given
+    /// preset dictionary, of which the last 32 kB are used
+    #[cfg(feature="dictionary")]
+    pub fn with_dictionary(dictionary: &Dictionary) -> Processor {
+        let mut p = Processor::new();
+        p.preset = dictionary.tail(HISTORY).to_vec();
+        p.output.extend_from_slice(&p.preset);
+        p.outpos = p.output.len();
+        p
+    }
+
+    fn with_history(deflate64: bool, history: usize) -> Processor {
+        Processor {
+            output: Buffer::default(),
+            outpos: 0,
+            block: Buffer::default(),
+            pos: 0,
+            updated: 0,
+            bitbuf: 0,
+            bitcnt: 0,
+            state: State::Header,
+            eof: false,
+            total_in: 0,
+            input: Buffer::default(),
+            inpos: 0,
+            finished: false,
+            pool: None,
+            deflate64: deflate64,
+            history: history,
+            preset: Vec::new(),
+            lens: Vec::new(),
+            hlit: 0,
+            dynamic_codes: None,
+            fixed_codes: None,
+        }
+    }
+
+    /// Takes the window and the other buffers of the decoder from `pool`, to
+    /// which they go back once it is dropped. This must be called before
+    /// decoding.
+    pub fn set_pool(&mut self, pool: &Pool) {
+        self.pool = Some(pool.clone());
+    }
+
+    /// Returns whether this deflate stream has reached the EOF marker
+    pub fn eof(&self) -> bool {
+        self.state == State::Done && self.pos == self.block.len()
+    }
+
+    /// Resets this flate decoder, to decode another stream, keeping its
+    /// buffers and its dictionary. Note that this could corrupt an
+    /// in-progress decoding of a stream.
+    pub fn reset(&mut self) {
+        self.bitbuf = 0;
+        self.bitcnt = 0;
+        self.state = State::Header;
+        self.eof = false;
+        self.total_in = 0;
+        self.input.truncate(0);
+        self.inpos = 0;
+        self.finished = false;
+        self.output.truncate(0);
+        self.output.extend_from_slice(&self.preset);
+        self.outpos = self.output.len();
+        self.block.truncate(0);
+        self.pos = 0;
+        self.updated = 0;
+    }
+
+    /// Returns the number of bytes of the deflate stream consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Saves the state of the decoder to a byte blob, from which decoding can
+    /// be resumed with `resume()`, possibly in another process.
+    ///
+    /// The decoder stops in between the symbols, so the state is made of the
+    /// window, the decoded bytes not yet read, the pending bits and the code
+    /// lengths of the block, from which its huffman tables are built again.
+    /// The blob does not include the position in the underlying stream,
+    /// which is given by `total_in()`.
+    pub fn save_state(&self) -> Vec<u8> {
+        let pending = &self.block[self.pos..];
+        let mut out = Vec::with_capacity(32 + self.output.len() + pending.len() + self.lens.len());
+        out.extend(STATE_MAGIC.iter().cloned());
+        let flags = if self.eof { STATE_EOF } else { 0 } |
+                    if self.deflate64 { STATE_DEFLATE64 } else { 0 };
+        // writing to a vector does not fail
+        out.write_u8(flags).unwrap();
+        out.write_u8(self.bitcnt as u8).unwrap();
+        out.write_u64::<LittleEndian>(self.bitbuf).unwrap();
+        out.write_u32::<LittleEndian>(self.outpos as u32).unwrap();
+        out.write_u32::<LittleEndian>(self.output.len() as u32).unwrap();
+        out.extend(self.output.iter().cloned());
+        out.write_u32::<LittleEndian>(pending.len() as u32).unwrap();
+        out.extend(pending.iter().cloned());
+        match self.state {
+            State::Header | State::Done => out.write_u8(STATE_HEADER).unwrap(),
+            State::Stored(left) => {
+                out.write_u8(STATE_STORED).unwrap();
+                out.write_u16::<LittleEndian>(left as u16).unwrap();
+            }
+            State::Codes => {
+                out.write_u8(STATE_CODES).unwrap();
+                out.write_u16::<LittleEndian>(self.hlit as u16).unwrap();
+                out.write_u16::<LittleEndian>(self.lens.len() as u16).unwrap();
+                out.extend(self.lens.iter().map(|&len| len as u8));
+            }
+        }
+        out
+    }
+
+    /// Creates a decoder from a state saved by `save_state()`. The input
+    /// must continue the deflate stream at the offset which `total_in()` gave
+    /// when the state was saved.
+    pub fn resume(mut state: &[u8]) -> io::Result<Processor> {
+        let mut magic = [0u8; 4];
+        if state.read_exact(&mut magic).is_err() || magic != STATE_MAGIC {
+            return error(Error::InvalidState)
+        }
+        let parse = |state: &mut &[u8]| -> io::Result<(u8, u8, u64, u32, Vec<u8>, Vec<u8>, u8, usize, Vec<u8>)> {
+            let flags = try!(state.read_u8());
+            let bitcnt = try!(state.read_u8());
+            let bitbuf = try!(state.read_u64::<LittleEndian>());
+            let outpos = try!(state.read_u32::<LittleEndian>());
+            let len = try!(state.read_u32::<LittleEndian>());
+            let mut output = Vec::new();
+            try!(state.push_exactly(len as u64, &mut output));
+            let len = try!(state.read_u32::<LittleEndian>());
+            let mut pending = Vec::new();
+            try!(state.push_exactly(len as u64, &mut pending));
+            let kind = try!(state.read_u8());
+            let (left, mut lens) = (0, Vec::new());
+            let left = match kind {
+                STATE_STORED => try!(state.read_u16::<LittleEndian>()) as usize,
+                STATE_CODES => {
+                    let hlit = try!(state.read_u16::<LittleEndian>()) as usize;
+                    let len = try!(state.read_u16::<LittleEndian>());
+                    try!(state.push_exactly(len as u64, &mut lens));
+                    hlit
+                }
+                _ => left,
+            };
+            Ok((flags, bitcnt, bitbuf, outpos, output, pending, kind, left, lens))
+        };
+        let (flags, bitcnt, bitbuf, outpos, output, pending, kind, left, lens) = match parse(&mut state) {
+            Ok(fields) => fields,
+            Err(..) => return error(Error::InvalidState),
+        };
+
+        let deflate64 = flags & STATE_DEFLATE64 != 0;
+        let history = if deflate64 { HISTORY64 } else { HISTORY };
+        if state.len() != 0 || bitcnt >= 64 || bitbuf >> bitcnt != 0 ||
+                output.len() > history || outpos as usize > output.len() ||
+                (output.len() < history && outpos as usize != output.len()) {
+            return error(Error::InvalidState)
+        }
+        let mut p = Processor::with_history(deflate64, history);
+        p.output.extend(output.into_iter());
+        p.outpos = outpos as usize;
+        *p.block = pending;
+        p.updated = p.block.len();
+        p.bitbuf = bitbuf;
+        p.bitcnt = bitcnt as usize;
+        p.eof = flags & STATE_EOF != 0;
+        p.state = match kind {
+            STATE_HEADER if p.eof => State::Done,
+            STATE_HEADER => State::Header,
+            STATE_STORED => State::Stored(left),
+            STATE_CODES if lens.is_empty() => {
+                try!(p.fixed_codes());
+                State::Codes
+            }
+            STATE_CODES => {
+                let max_dcodes = if deflate64 { MAXDCODES64 } else { MAXDCODES } as usize;
+                if left < 257 || left > MAXLCODES as usize || lens.len() <= left ||
+                        lens.len() - left > max_dcodes || lens.iter().any(|&len| len as usize > MAXBITS) {
+                    return error(Error::InvalidState)
+                }
+                p.lens = lens.into_iter().map(|len| len as u16).collect();
+                p.hlit = left;
+                try!(p.dynamic_codes());
+                State::Codes
+            }
+            _ => return error(Error::InvalidState),
+        };
+        Ok(p)
+    }
+
+    /// Decodes the next part of the stream into the block, failing with
+    /// `WouldBlock` once it needs more input than `src` has
+    fn step<S: Source>(&mut self, src: &mut S) -> io::Result<()> {
+        match self.state {
+            State::Header => {
+                let mark = self.mark();
+                let result = self.header(src);
+                if result.is_err() {
+                    self.rewind(mark);
+                }
+                result
+            }
+            State::Stored(left) => self.stored(left, src),
+            State::Codes if self.lens.is_empty() => {
+                let (lencode, distcode) = self.fixed_codes.take().unwrap();
+                let result = self.codes(&lencode, &distcode, src);
+                self.fixed_codes = Some((lencode, distcode));
+                result
+            }
+            State::Codes => {
+                let (lencode, distcode) = self.dynamic_codes.take().unwrap();
+                let result = self.codes(&lencode, &distcode, src);
+                self.dynamic_codes = Some((lencode, distcode));
+                result
+            }
+            State::Done => Ok(()),
+        }
+    }
+
+    fn header<S: Source>(&mut self, src: &mut S) -> io::Result<()> {
+        pool::reserve_bytes(self.pool.as_ref(), &mut self.block, 4096);
+        pool::reserve_bytes(self.pool.as_ref(), &mut self.output, self.history);
+        let last = try!(self.bits(1, src)) == 1;
+        match try!(self.bits(2, src)) {
+            0 => try!(self.statik(src)),
+            1 => {
+                try!(self.fixed_codes());
+                self.lens.truncate(0);
+                self.state = State::Codes;
+            }
+            2 => {
+                try!(self.dynamic(src));
+                self.state = State::Codes;
+            }
+            3 => return error(Error::InvalidBlockCode),
+            _ => unreachable!(),
+        }
+        self.eof = last;
+        if self.state == State::Stored(0) {
+            self.end_block();
+        }
+        Ok(())
+    }
+
+    /// Moves on to the next block, or to the end
+    fn end_block(&mut self) {
+        self.unread();
+        self.state = if self.eof { State::Done } else { State::Header };
+    }
+
+    fn update_output(&mut self, mut from: usize) {
+        let to = self.block.len();
+        if to - from > self.history {
+            from = to - self.history;
+        }
+        let amt = to - from;
+        let remaining = self.history - self.outpos;
+        let n = cmp::min(amt, remaining);
+        if self.output.len() < self.history {
+            self.output.extend(self.block[from..(from + n)].iter().map(|b| *b));
+        } else if n > 0 {
+            assert_eq!(self.output.len(), self.history);
+            unsafe { copy_nonoverlapping(
+                &self.block[from],
+                &mut self.output[self.outpos],
+                n
+            )};
+        }
+        self.outpos += n;
+        if n < amt {
+            unsafe { copy_nonoverlapping(
+                &self.block[from+n],
+                &mut self.output[0],
+                amt - n
+            )};
+            self.outpos = amt - n;
+        }
+        self.updated = to;
+    }
+
+    fn statik<S: Source>(&mut self, src: &mut S) -> io::Result<()> {
+        // the block starts on the next byte boundary
+        let partial = self.bitcnt & 7;
+        self.consume(partial);
+        let len = try!(self.bits(16, src));
+        let nlen = try!(self.bits(16, src));
+        if !nlen != len { return error(Error::InvalidStaticSize) }
+        self.unread();
+        self.state = State::Stored(len as usize);
+        Ok(())
+    }
+
+    fn stored<S: Source>(&mut self, left: usize, src: &mut S) -> io::Result<()> {
+        if self.inpos == self.input.len() {
+            try!(self.pull(cmp::min(left, BLOCK_CHUNK), src));
+        }
+        let n = cmp::min(cmp::min(left, self.input.len() - self.inpos), BLOCK_CHUNK);
+        self.block.extend_from_slice(&self.input[self.inpos..(self.inpos + n)]);
+        self.inpos += n;
+        self.total_in += n as u64;
+        let from = self.updated;
+        self.update_output(from);
+        self.state = State::Stored(left - n);
+        if left == n {
+            self.end_block();
+        }
+        Ok(())
+    }
+
+    /// Drops the input consumed but for the last 8 bytes, which may be
+    /// put back from the bitbuf at the end of the block
+    fn compact(&mut self) {
+        if self.inpos >= READ_AHEAD {
+            self.input.drain(..(self.inpos - 8));
+            self.inpos = 8;
+        }
+    }
+
+    /// Moves the next bytes of `input` from `*pos` after those left to
+    /// decode
+    fn feed(&mut self, input: &[u8], pos: &mut usize) {
+        self.compact();
+        let n = cmp::min(input.len() - *pos, READ_AHEAD);
+        pool::reserve_bytes(self.pool.as_ref(), &mut self.input, 2 * READ_AHEAD);
+        self.input.extend_from_slice(&input[*pos..(*pos + n)]);
+        *pos += n;
+    }
+
+    /// Drops the input left of the last `fed` bytes fed, returning how many
+    /// of them were used
+    fn give_back(&mut self, fed: usize) -> usize {
+        let n = cmp::min(self.input.len() - self.inpos, fed);
+        let len = self.input.len() - n;
+        self.input.truncate(len);
+        fed - n
+    }
+
+    /// Takes up to `n` more bytes from `src` as the input runs out, failing
+    /// once it is over
+    fn pull<S: Source>(&mut self, n: usize, src: &mut S) -> io::Result<()> {
+        if self.finished {
+            return truncated("truncated deflate stream")
+        }
+        src.pull(&mut self.input, n)
+    }
+
+    fn mark(&self) -> Mark {
+        Mark {
+            bitbuf: self.bitbuf,
+            bitcnt: self.bitcnt,
+            inpos: self.inpos,
+            total_in: self.total_in,
+        }
+    }
+
+    fn rewind(&mut self, mark: Mark) {
+        self.bitbuf = mark.bitbuf;
+        self.bitcnt = mark.bitcnt;
+        self.inpos = mark.inpos;
+        self.total_in = mark.total_in;
+    }
+
+    // Bytes in the stream are LSB first, so the bitbuf is appended to from the
+    // left and consumed from the right.
+    fn pull_byte<S: Source>(&mut self, src: &mut S) -> io::Result<()> {
+        if self.inpos == self.input.len() {
+            try!(self.pull(1, src));
+        }
+        let byte = self.input[self.inpos];
+        self.inpos += 1;
+        self.bitbuf |= (byte as u64) << self.bitcnt;
+        self.bitcnt += 8;
+        self.total_in += 1;
+        Ok(())
+    }
+
+    /// Tops the bitbuf up with as many whole bytes as it holds, when there
+    /// are 8 bytes of input left
+    #[inline]
+    fn refill(&mut self) {
+        if self.input.len() - self.inpos < 8 {
+            return
+        }
+        let n = (63 - self.bitcnt) >> 3;
+        if n == 0 {
+            return
+        }
+        let word = unsafe {
+            u64::from_le(ptr::read_unaligned(self.input.as_ptr().add(self.inpos) as *const u64))
+        };
+        self.bitbuf |= (word & ((1 << (n * 8)) - 1)) << self.bitcnt;
+        self.bitcnt += n * 8;
+        self.inpos += n;
+        self.total_in += n as u64;
+    }
+
+    /// Puts the whole bytes of the bitbuf back with the input left, at the
+    /// end of a block
+    fn unread(&mut self) {
+        let n = self.bitcnt >> 3;
+        self.inpos -= n;
+        self.total_in -= n as u64;
+        self.bitcnt -= n * 8;
+        self.bitbuf &= (1 << self.bitcnt) - 1;
+    }
+
+    fn consume(&mut self, cnt: usize) {
+        self.bitbuf >>= cnt;
+        self.bitcnt -= cnt;
+    }
+
+    fn bits<S: Source>(&mut self, cnt: usize, src: &mut S) -> io::Result<u16> {
+        if self.bitcnt < cnt {
+            self.refill();
+        }
+        while self.bitcnt < cnt {
+            try!(self.pull_byte(src));
+        }
+        let ret = self.bitbuf & ((1 << cnt) - 1);
+        self.consume(cnt);
+        return Ok(ret as u16);
+    }
+
+    /// Decodes the symbols of the block until its end, or until the block
+    /// holds `BLOCK_CHUNK` bytes, each symbol cut off by the end of the
+    /// input being decoded again from its start
+    fn codes<S: Source>(&mut self, lens: &HuffmanTree, dist: &HuffmanTree,
+                        src: &mut S) -> io::Result<()> {
+        while self.block.len() < BLOCK_CHUNK {
+            let mark = self.mark();
+            match self.symbol(lens, dist, src) {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.end_block();
+                    break
+                }
+                Err(e) => {
+                    self.rewind(mark);
+                    let from = self.updated;
+                    self.update_output(from);
+                    return Err(e)
+                }
+            }
+        }
+        let from = self.updated;
+        self.update_output(from);
+        Ok(())
+    }
+
+    /// Decodes a literal or a match into the block, returning false at the
+    /// end of the block
+    #[inline]
+    fn symbol<S: Source>(&mut self, lens: &HuffmanTree, dist: &HuffmanTree,
+                         src: &mut S) -> io::Result<bool> {
+        let sym = try!(lens.decode(self, src));
+        match sym {
+            n if n < 256 => { self.block.push(sym as u8); }
+            256 => return Ok(false),
+            n if n < 290 => {
+                // figure out len/dist that we're working with
+                let n = n - 257;
+                if n as usize >= EXTRALENS.len() {
+                    return error(Error::InvalidHuffmanCode)
+                }
+                let len = if n == 28 && self.deflate64 {
+                    3 + try!(self.bits(EXTRABITS64, src)) as usize
+                } else {
+                    EXTRALENS[n as usize] as usize +
+                        try!(self.bits(EXTRABITS[n as usize] as usize, src)) as usize
+                };
+
+                let dist = try!(dist.decode(self, src)) as usize;
+                let dist = EXTRADIST[dist] as usize +
+                           try!(self.bits(EXTRADBITS[dist] as usize, src)) as usize;
+
+                // update the output buffer with any data we haven't pushed
+                // into it yet
+                if self.updated != self.block.len() {
+                    let from = self.updated;
+                    self.update_output(from);
+                }
+
+                if dist > self.output.len() {
+                    return error(Error::InvalidHuffmanCode)
+                }
+
+                // Perform the copy, from the window which is full once it
+                // wraps around, then from the bytes just copied
+                let finger = if self.outpos >= dist {
+                    self.outpos - dist
+                } else {
+                    self.history - (dist - self.outpos)
+                };
+                let min = cmp::min(dist, len);
+                let head = cmp::min(min, self.output.len() - finger);
+                self.block.extend_from_slice(&self.output[finger..(finger + head)]);
+                self.block.extend_from_slice(&self.output[..(min - head)]);
+                if len > min {
+                    copy_match(&mut self.block, dist, len - min);
+                }
+            }
+            _ => return error(Error::InvalidHuffmanCode)
+        }
+        Ok(true)
+    }
+
+    fn fixed_codes(&mut self) -> io::Result<()> {
+        if self.fixed_codes.is_none() {
+            let mut lengths = [0u16; 288];
+            for i in 0..144 { lengths[i] = 8; }
+            for i in 144..256 { lengths[i] = 9; }
+            for i in 256..280 { lengths[i] = 7; }
+            for i in 280..288 { lengths[i] = 8; }
+            let lencode = try!(HuffmanTree::construct(&lengths, LEN_ROOT_BITS));
+            let dcodes = if self.deflate64 { MAXDCODES64 } else { MAXDCODES };
+            let lengths = [5u16; MAXDCODES64 as usize];
+            let distcode = try!(HuffmanTree::construct(&lengths[..(dcodes as usize)],
+                                                       DIST_ROOT_BITS));
+            self.fixed_codes = Some((lencode, distcode));
+        }
+        Ok(())
+    }
+
+    fn dynamic<S: Source>(&mut self, src: &mut S) -> io::Result<()> {
+        let hlit = try!(self.bits(5, src)) + 257; // number of length codes
+        let hdist = try!(self.bits(5, src)) + 1;  // number of distance codes
+        let hclen = try!(self.bits(4, src)) + 4;  // number of code length codes
+        let max_dcodes = if self.deflate64 { MAXDCODES64 } else { MAXDCODES };
+        if hlit > MAXLCODES || hdist > max_dcodes {
+            return error(Error::HuffmanTreeTooLarge);
+        }
+
+        // Read off the code length codes, and then build the huffman tree which
+        // is then used to decode the actual huffman tree for the rest of the
+        // data.
+        let mut lengths = [0; 19];
+        for i in 0..(hclen as usize) {
+            lengths[ORDER[i]] = try!(self.bits(3, src));
+        }
+        let tree = try!(HuffmanTree::construct(&lengths, CLEN_ROOT_BITS));
+
+        // Decode all of the length and distance codes in one go, we'll
+        // partition them into two huffman trees later
+        let mut lengths = [0; MAXCODES as usize];
+        let mut i = 0;
+        while i < hlit + hdist {
+            let symbol = try!(tree.decode(self, src));
+            match symbol {
+                n if n < 16 => {
+                    lengths[i as usize] = symbol;
+                    i += 1;
+                }
+                16 if i == 0 => return error(Error::InvalidHuffmanHeaderSymbol),
+                16 => {
+                    let prev = lengths[i as usize - 1];
+                    for _ in 0..(try!(self.bits(2, src)) + 3) {
+                        lengths[i as usize] = prev;
+                        i += 1;
+                    }
+                }
+                // all codes start out as 0, so these just skip
+                17 => { i += try!(self.bits(3, src)) + 3; }
+                18 => { i += try!(self.bits(7, src)) + 11; }
+                _ => return error(Error::InvalidHuffmanHeaderSymbol),
+            }
+        }
+        if i > hlit + hdist { return error(Error::InvalidHuffmanTreeHeader) }
+
+        self.lens.truncate(0);
+        self.lens.extend_from_slice(&lengths[..((hlit + hdist) as usize)]);
+        self.hlit = hlit as usize;
+        self.dynamic_codes()
+    }
+
+    /// Builds the trees of the code lengths of the block
+    fn dynamic_codes(&mut self) -> io::Result<()> {
+        // Use the decoded codes to construct yet another huffman tree
+        let lencode = try!(HuffmanTree::construct(&self.lens[..self.hlit], LEN_ROOT_BITS));
+        let distcode = try!(HuffmanTree::construct(&self.lens[self.hlit..], DIST_ROOT_BITS));
+        self.dynamic_codes = Some((lencode, distcode));
+        Ok(())
+    }
+
+    /// Decodes into `output`, reading each byte from `r` as it is needed,
+    /// and stopping at the end of the blocks which produced some data
+    fn pull_output<R: Read>(&mut self, r: &mut R, output: &mut [u8]) -> io::Result<usize> {
+        let mut produced = 0;
+        loop {
+            if !drain(&self.block, &mut self.pos, output, &mut produced) ||
+                    self.state == State::Done ||
+                    (produced > 0 && self.state == State::Header) {
+                return Ok(produced)
+            }
+            self.block.truncate(0);
+            self.pos = 0;
+            self.updated = 0;
+            self.compact();
+            try!(self.step(&mut Pull(r)));
+        }
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.block, &mut self.pos, output, &mut produced) {
+                let pos = self.give_back(pos);
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            if self.state == State::Done {
+                let pos = self.give_back(pos);
+                return Ok((pos, produced, Status::Done))
+            }
+            // the bytes read are all in the window
+            self.block.truncate(0);
+            self.pos = 0;
+            self.updated = 0;
+            if self.inpos == self.input.len() && pos < input.len() {
+                self.feed(input, &mut pos);
+            }
+            match self.step(&mut Fed) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if pos == input.len() {
+                        return Ok((pos, produced, Status::NeedInput))
+                    }
+                    self.feed(input, &mut pos);
+                }
+                result => try!(result),
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the input left may hold the rest of the stream, which fails as
+        // truncated once decoded past
+        self.finished = true;
+        Ok(())
+    }
+}
+
+/// The structure that is used to decode a DEFLATE stream. This wraps an
+/// internal reader which is used as the source of all data.
+pub struct Decoder<R> {
+    /// Wrapped reader which is exposed to allow getting it back.
+    pub r: R,
+    p: Processor,
+    pump: Pump,
+    read_ahead: bool,
+    limit: OutputLimit,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new flate decoder which will read data from the specified
+    /// source
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder::with_processor(r, Processor::new())
+    }
+
+    /// Creates a new decoder for the Deflate64 variant (method 9 of ZIP
+    /// archives), which has a 64KB window, longer matches and two more
+    /// distance codes
+    pub fn new_deflate64(r: R) -> Decoder<R> {
+        Decoder::with_processor(r, Processor::new_deflate64())
+    }
+
+    /// Creates a new flate decoder of a stream compressed with the given
+    /// preset dictionary, of which the last 32 kB are used
+    #[cfg(feature="dictionary")]
+    pub fn with_dictionary(r: R, dictionary: &Dictionary) -> Decoder<R> {
+        Decoder::with_processor(r, Processor::with_dictionary(dictionary))
+    }
+
+    fn with_processor(r: R, p: Processor) -> Decoder<R> {
+        Decoder {
+            r: r,
+            p: p,
+            pump: Pump::new(),
+            read_ahead: false,
+            limit: OutputLimit::new(),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.limit.set(size);
+    }
+
+    /// Lets the decoder read its input ahead, many bytes at a time, and decode
+    /// them faster than when it pulls each byte from the reader. The reader is
+    /// then left past the end of the deflate stream, so this is for the
+    /// readers of nothing else. This must be called before decoding.
+    pub fn set_read_ahead(&mut self, read_ahead: bool) {
+        self.read_ahead = read_ahead;
+    }
+
+    /// Takes the window and the other buffers of the decoder from `pool`, to
+    /// which they go back once it is dropped. This must be called before
+    /// decoding.
+    pub fn set_pool(&mut self, pool: &Pool) {
+        self.p.set_pool(pool);
+    }
+
+    /// Returns whether this deflate stream has reached the EOF marker
+    pub fn eof(&self) -> bool {
+        self.p.eof()
+    }
+
+    /// Resets this flate decoder, to decode another stream from the reader,
+    /// keeping its buffers and its dictionary. Note that this could corrupt
+    /// an in-progress decoding of a stream.
+    pub fn reset(&mut self) {
+        self.p.reset();
+        self.pump.reset();
+        self.limit.reset();
+    }
+
+    /// Returns the number of bytes of the deflate stream consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.p.total_in()
+    }
+
+    /// Saves the state of the decoder to a byte blob, as
+    /// `Processor::save_state`. The data read ahead of the stream isn't part
+    /// of it.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.p.save_state()
+    }
+
+    /// Creates a decoder from a state saved by `save_state()`. The reader
+    /// must continue the deflate stream at the offset which `total_in()` gave
+    /// when the state was saved.
+    pub fn resume(r: R, state: &[u8]) -> io::Result<Decoder<R>> {
+        Ok(Decoder::with_processor(r, try!(Processor::resume(state))))
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.limit.clamp(buf.len());
+        let n = if self.read_ahead {
+            try!(self.pump.read(&mut self.r, &mut self.p, &mut buf[..len]))
+        } else {
+            try!(self.p.pull_output(&mut self.r, &mut buf[..len]))
+        };
+        self.limit.count(n)
+    }
+}
+
+/// Decodes the raw DEFLATE stream held by `input`. When it is a single
+/// stored block, as the encoder writes at level 0, its data is borrowed from
+/// `input` rather than copied.
+pub fn decode(input: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+    // the final bit and the type of a stored block, then its length and the
+    // complement of the length
+    if input.len() >= 5 && input[0] & 7 == 1 {
+        let len = input[1] as usize | (input[2] as usize) << 8;
+        let nlen = input[3] as usize | (input[4] as usize) << 8;
+        if len ^ nlen == 0xffff && input.len() >= 5 + len {
+            return Ok(Cow::Borrowed(&input[5..(5 + len)]))
+        }
+    }
+    decode_to_vec(&mut Processor::new(), input).map(Cow::Owned)
+}
+
+#[cfg(test)]
+#[allow(warnings)]
+mod test {
+    use std::io::{BufReader, BufWriter, Read, Write};
+    use super::super::rand::{Rand, random};
+    use super::super::byteorder::{LittleEndian, BigEndian, WriteBytesExt, ReadBytesExt};
+    use std::str;
+    use process::{Process, Status};
+    use testing::push;
+    use super::{Decoder, HuffmanTree, Processor};
+    #[cfg(feature="unstable")]
+    use test;
+
+    // The input data for these tests were all generated from the zpipe.c
+    // program found at http://www.zlib.net/zpipe.c and the zlib format has an
+    // extra 2 bytes of header with an 4-byte checksum at the end.
+    fn fixup<'a>(s: &'a [u8]) -> &'a [u8] {
+        &s[2..(s.len() - 4)]
+    }
+
+    fn test_decode(input: &[u8], output: &[u8]) {
+        let mut d = Decoder::new(BufReader::new(fixup(input)));
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(output.len(), buf.len());
+        let i = buf.iter().zip(output.iter()).position(|(a, b)| a != b);
+        assert!(buf == output);
+        for &step in [1, 333, 1 << 16].iter() {
+            assert!(push(&mut Processor::new(), fixup(input), step).unwrap() == output);
+        }
+    }
+
+    fn test_decode_pure(input: &[u8], output: &[u8]) {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(output.len(), buf.len());
+        let i = buf.iter().zip(output.iter()).position(|(a, b)| a != b);
+        assert!(buf == output);
+    }
+
+    #[test]
+    fn decode() {
+        let reference = include_bytes!("../data/test.txt");
+        test_decode(include_bytes!("../data/test.z.0"), reference);
+        test_decode(include_bytes!("../data/test.z.1"), reference);
+        test_decode(include_bytes!("../data/test.z.2"), reference);
+        test_decode(include_bytes!("../data/test.z.3"), reference);
+        test_decode(include_bytes!("../data/test.z.4"), reference);
+        test_decode(include_bytes!("../data/test.z.5"), reference);
+        test_decode(include_bytes!("../data/test.z.6"), reference);
+        test_decode(include_bytes!("../data/test.z.7"), reference);
+        test_decode(include_bytes!("../data/test.z.8"), reference);
+        test_decode(include_bytes!("../data/test.z.9"), reference);
+        test_decode_pure(include_bytes!("../data/test.z.go"), reference);
+    }
+
+    #[test]
+    fn large() {
+        let reference = include_bytes!("../data/test.large");
+        test_decode(include_bytes!("../data/test.large.z.5"), reference);
+    }
+
+    #[test]
+    fn one_byte_at_a_time() {
+        let input = include_bytes!("../data/test.z.1");
+        let mut d = Decoder::new(BufReader::new(fixup(input)));
+        assert!(!d.eof());
+        let mut out = Vec::new();
+        loop {
+            match d.read_u8() {
+                Ok(b) => out.push(b),
+                Err(..) => break
+            }
+        }
+
+        assert!(d.eof());
+        assert!(&out[..] == &include_bytes!("../data/test.txt")[..]);
+    }
+
+    #[test]
+    fn random_byte_lengths() {
+        let input = include_bytes!("../data/test.z.1");
+        let mut d = Decoder::new(BufReader::new(fixup(input)));
+        let mut out = Vec::new();
+        let mut buf = [0u8; 40];
+        loop {
+            match d.read(&mut buf[..(1 + random::<usize>() % 40)]) {
+                Err(..) | Ok(0) => break,
+                Ok(n) => {
+                    out.extend(buf[..n].iter().map(|b| *b));
+                }
+            }
+        }
+        assert!(&out[..] == &include_bytes!("../data/test.txt")[..]);
+    }
+
+    /// Packs (value, count) pairs of bits LSB first
+    fn pack(fields: &[(u16, usize)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (mut buf, mut cnt) = (0u32, 0);
+        for &(value, n) in fields.iter() {
+            buf |= (value as u32) << cnt;
+            cnt += n;
+            while cnt >= 8 {
+                out.push(buf as u8);
+                buf >>= 8;
+                cnt -= 8;
+            }
+        }
+        if cnt > 0 { out.push(buf as u8); }
+        out
+    }
+
+    /// Huffman codes are sent starting from their most significant bit
+    fn code(value: u16, len: usize) -> (u16, usize) {
+        (value.reverse_bits() >> (16 - len), len)
+    }
+
+    #[test]
+    fn long_codes() {
+        // one code of each length, up to the 15 bits maximum
+        let mut lens = [0u16; 16];
+        for i in 0..15 { lens[i] = i as u16 + 1; }
+        lens[15] = 15;
+        let tree = HuffmanTree::construct(&lens, 9).unwrap();
+        let mut fields = Vec::new();
+        for i in 0..16 {
+            let value = if i < 15 { (1 << (i + 1)) - 2 } else { (1 << 15) - 1 };
+            fields.push(code(value, lens[i] as usize));
+        }
+        let input = pack(&fields);
+        let mut p = Processor::new();
+        p.feed(&input, &mut 0);
+        for i in 0..16 {
+            assert_eq!(tree.decode(&mut p, &mut super::Fed).unwrap(), i as u16);
+        }
+    }
+
+    #[test]
+    fn invalid_symbols() {
+        // fixed blocks have codes for the unused length symbol 286 and the
+        // unused distance 30
+        let input = pack(&[(1, 1), (1, 2), code(0xc6, 8)]);
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+
+        let input = pack(&[(1, 1), (1, 2), code(b'a' as u16 + 0x30, 8), code(1, 7), code(30, 5)]);
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn stats() {
+        use stats::Sink;
+        let data = &include_bytes!("../data/test.txt")[..];
+        let sink = Sink::new();
+        let mut e = super::Encoder::new(Vec::new(), 6);
+        e.set_stats(sink.clone());
+        e.write_all(data).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+
+        let stats = sink.get();
+        assert_eq!(stats.input, data.len() as u64);
+        assert_eq!(stats.output, encoded.len() as u64);
+        assert!(stats.blocks >= 1);
+        let matched: u64 = stats.match_lengths.iter().enumerate().map(|(len, &n)| len as u64 * n).sum();
+        assert_eq!(stats.literals.iter().sum::<u64>() + matched, data.len() as u64);
+        assert!(stats.matches() > 0);
+    }
+
+    #[test]
+    fn stored() {
+        use std::borrow::Cow;
+        let data = include_bytes!("../data/test.txt");
+        let mut e = super::Encoder::new(Vec::new(), 0);
+        e.write_all(&data[..1000]).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        match super::decode(&encoded).unwrap() {
+            Cow::Borrowed(decoded) => assert!(decoded == &data[..1000]),
+            Cow::Owned(..) => panic!("stored block copied"),
+        }
+
+        let mut e = super::Encoder::new(Vec::new(), 6);
+        e.write_all(&data[..]).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        let decoded = super::decode(&encoded).unwrap();
+        assert!(match decoded { Cow::Owned(..) => true, _ => false });
+        assert!(&decoded[..] == &data[..]);
+    }
+
+    #[test]
+    fn deflate64() {
+        let data: Vec<u8> = (0..40000u32).map(|i| (i * 7 / 3) as u8).collect();
+        // a stored block, then a fixed block copying 1000 bytes from 40000
+        // bytes back: length code 285 with 16 extra bits, distance code 30
+        let mut fields = vec![(0, 1), (0, 2), (0, 5), (40000, 16), (!40000, 16)];
+        fields.extend(data.iter().map(|&b| (b as u16, 8)));
+        fields.extend([(1, 1), (1, 2), code(0xc5, 8), (997, 16),
+                       code(30, 5), (40000 - 32769, 14), code(0, 7)].iter().cloned());
+        let input = pack(&fields);
+
+        let mut d = Decoder::new_deflate64(BufReader::new(&input[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert_eq!(out.len(), 41000);
+        assert!(&out[..40000] == &data[..] && &out[40000..] == &data[..1000]);
+
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn suspend_resume() {
+        let reference = &include_bytes!("../data/test.large")[..];
+        let input = fixup(include_bytes!("../data/test.large.z.5"));
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut out = vec![0u8; 1000000];
+        d.read_exact(&mut out).unwrap();
+        let state = d.save_state();
+        let offset = d.total_in() as usize;
+        drop(d);
+
+        let mut d = Decoder::resume(BufReader::new(&input[offset..]), &state).unwrap();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(&out[..] == reference);
+
+        // the processors stop in the middle of the blocks
+        let mut p = Processor::new();
+        let mut out = vec![0u8; 1 << 20];
+        let (consumed, produced, status) = p.process(&input[..300000], &mut out).unwrap();
+        assert_eq!((consumed, status), (300000, Status::NeedInput));
+        let state = p.save_state();
+        let offset = p.total_in() as usize;
+        let mut p = Processor::resume(&state).unwrap();
+        out.truncate(produced);
+        out.extend(push(&mut p, &input[offset..], 1000).unwrap());
+        assert!(&out[..] == reference);
+
+        // the state after the end resumes to nothing
+        let state = d.save_state();
+        let mut d = Decoder::resume(BufReader::new(&b""[..]), &state).unwrap();
+        assert_eq!(d.read(&mut [0u8; 16]).unwrap(), 0);
+    }
+
+    #[test]
+    fn invalid_state() {
+        let input = fixup(include_bytes!("../data/test.z.9"));
+        let mut d = Decoder::new(BufReader::new(input));
+        d.read_exact(&mut [0u8; 100]).unwrap();
+        let state = d.save_state();
+        assert!(Decoder::resume(&b""[..], &state[..(state.len() - 1)]).is_err());
+        let mut bad = state.clone();
+        bad[3] += 1;
+        assert!(Decoder::resume(&b""[..], &bad).is_err());
+        let mut long = state.clone();
+        long.push(0);
+        assert!(Decoder::resume(&b""[..], &long).is_err());
+    }
+
+    /// A reader giving at most `n` bytes at a time
+    struct Trickle<'a>(&'a [u8], usize);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            let n = ::std::cmp::min(buf.len(), self.1);
+            self.0.read(&mut buf[..n])
+        }
+    }
+
+    #[test]
+    fn read_ahead() {
+        let reference = &include_bytes!("../data/test.large")[..];
+        let input = fixup(include_bytes!("../data/test.large.z.5"));
+        for &n in [1, 7, 100, 1 << 20].iter() {
+            let mut d = Decoder::new(Trickle(input, n));
+            d.set_read_ahead(true);
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).unwrap();
+            assert!(&out[..] == reference);
+            assert_eq!(d.total_in(), input.len() as u64);
+        }
+
+        // stored blocks in between the others, and bytes past the end
+        let data = include_bytes!("../data/test.txt");
+        let mut e = super::Encoder::new(Vec::new(), 6);
+        e.write_all(&data[..1000]).unwrap();
+        e.flush().unwrap();
+        e.write_all(&data[1000..]).unwrap();
+        let (mut encoded, err) = e.finish();
+        err.unwrap();
+        let len = encoded.len() as u64;
+        encoded.extend_from_slice(&[0xaa; 20]);
+        let mut d = Decoder::new(&encoded[..]);
+        d.set_read_ahead(true);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(&out[..] == &data[..]);
+        assert_eq!(d.total_in(), len);
+
+        // the state saved in between blocks resumes at `total_in()`
+        let mut d = Decoder::new(input);
+        d.set_read_ahead(true);
+        let mut out = vec![0u8; 1000000];
+        d.read_exact(&mut out).unwrap();
+        let state = d.save_state();
+        let offset = d.total_in() as usize;
+        let mut d = Decoder::resume(&input[offset..], &state).unwrap();
+        d.read_to_end(&mut out).unwrap();
+        assert!(&out[..] == reference);
+
+        let mut d = Decoder::new(&input[..(input.len() - 1)]);
+        d.set_read_ahead(true);
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn does_not_read_past_the_end() {
+        let mut input = pack(&[(1, 1), (1, 2), code(b'a' as u16 + 0x30, 8), code(0, 7)]);
+        input.push(0xaa);
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert_eq!(&out[..], b"a");
+        let mut rest = Vec::new();
+        d.r.read_to_end(&mut rest).unwrap();
+        assert_eq!(&rest[..], &[0xaa][..]);
+    }
+
+    //fn roundtrip(bytes: &[u8]) {
+    //    let mut e = Encoder::new(MemWriter::new());
+    //    e.write(bytes);
+    //    let encoded = e.finish().unwrap();
+    //
+    //    let mut d = Decoder::new(BufReader::new(encoded));
+    //    let decoded = d.read_to_end();
+    //    assert_eq!(&decoded[..], bytes);
+    //}
+    //
+    //#[test]
+    //fn some_roundtrips() {
+    //    roundtrip(bytes!("test"));
+    //    roundtrip(bytes!(""));
+    //    roundtrip(include_bytes!("../data/test.txt"));
+    //}
+
+    #[cfg(feature="unstable")]
+    #[bench]
+    fn decompress_speed(bh: &mut test::Bencher) {
+        let input = include_bytes!("../data/test.z.9");
+        let mut d = Decoder::new(BufReader::new(fixup(input)));
+        let mut output = [0u8; 65536];
+        let mut output_size = 0;
+        bh.iter(|| {
+            d.r = BufReader::new(fixup(input));
+            d.reset();
+            output_size = d.read(&mut output).unwrap();
+        });
+        bh.bytes = output_size as u64;
+    }
+}
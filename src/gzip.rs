@@ -0,0 +1,804 @@
+//! GZIP Compression and Decompression. Requires `gzip` feature, enabled by default
+//!
+//! This module contains an implementation of the GZIP file format. The payload
+//! of a gzip member is a DEFLATE-encoded stream, framed by a small header and a
+//! trailer carrying the CRC32 and the size of the original data.
+//!
+//! Besides the plain `Encoder`, the `ParallelEncoder` spreads the compression
+//! over several threads in the manner of pigz: the input is cut into chunks,
+//! each one compressed on its own and terminated by an empty stored block, so
+//! that their concatenation makes a single valid DEFLATE stream. It requires
+//! the `threads` feature.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::gzip;
+//! use std::fs::File;
+//! use std::path::Path;
+//!
+//! let stream = File::open(&Path::new("path/to/file.gz")).unwrap();
+//! let mut decompressed = Vec::new();
+//! gzip::Decoder::new(stream).read_to_end(&mut decompressed);
+//! ```
+//!
+//! # Related links
+//!
+//! * http://tools.ietf.org/html/rfc1952 - RFC that this implementation is based
+//!   on
+
+#[cfg(feature="threads")]
+use std::cmp;
+#[cfg(feature="threads")]
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+#[cfg(feature="threads")]
+use std::thread::{self, JoinHandle};
+use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use super::byteorder_err_to_io;
+
+use Crc32;
+use checksum;
+#[cfg(feature="threads")]
+use checksum::crc32;
+use error::{checksum_mismatch, corrupt, truncated, unsupported};
+use flate;
+use process::{gather, Process, Reader, Status};
+
+const ID1: u8 = 0x1f;
+const ID2: u8 = 0x8b;
+const CM_DEFLATE: u8 = 8;
+
+const FTEXT: u8 = 0x01;
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+const FRESERVED: u8 = !(FTEXT | FHCRC | FEXTRA | FNAME | FCOMMENT);
+
+const OS_UNKNOWN: u8 = 255;
+/// Default amount of input compressed by each job of a `ParallelEncoder`
+#[cfg(feature="threads")]
+const CHUNK_SIZE: usize = 128 * 1024;
+
+/// The part of the stream a `Processor` expects next
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// The fixed fields of the header
+    Header,
+    /// The length of the extra field
+    ExtraLen,
+    /// The extra field, of this length
+    Extra(usize),
+    /// The zero-terminated name
+    Name,
+    /// The zero-terminated comment
+    Comment,
+    /// The CRC16 of the header
+    HeaderCrc,
+    /// The DEFLATE stream
+    Data,
+    /// The CRC32 and the size of the data
+    Trailer,
+    Done,
+}
+
+/// This structure is used to decode a GZIP-encoded stream, fed with slices
+/// of it.
+pub struct Processor {
+    state: State,
+    head: Vec<u8>,
+    flags: u8,
+    header: Crc32,
+    hash: Crc32,
+    size: u32,
+    inner: flate::Processor,
+    verify: bool,
+    finished: bool,
+}
+
+impl Processor {
+    /// Creates a new GZIP-stream decoder, waiting for the header
+    pub fn new() -> Processor {
+        Processor {
+            state: State::Header,
+            head: Vec::new(),
+            flags: 0,
+            header: Crc32::new(),
+            hash: Crc32::new(),
+            size: 0,
+            inner: flate::Processor::new(),
+            verify: true,
+            finished: false,
+        }
+    }
+
+    /// Starts decoding a new stream, keeping the buffers of the decoder and
+    /// its settings
+    pub fn reset(&mut self) {
+        self.state = State::Header;
+        self.head.truncate(0);
+        self.header.reset();
+        self.hash.reset();
+        self.size = 0;
+        self.inner.reset();
+        self.finished = false;
+    }
+
+    /// Enables or disables the verification of the CRC32 and the size stored
+    /// in the trailer against the decompressed data. Verification is on by
+    /// default; turning it off skips the checksum computation entirely.
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    /// Tests if this stream has reached the EOF point yet.
+    pub fn eof(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Returns the field of the header following `state`, among those which
+    /// the flags tell are there
+    fn after(&self, state: State) -> State {
+        let fields = [(FEXTRA, State::ExtraLen), (FNAME, State::Name),
+                      (FCOMMENT, State::Comment), (FHCRC, State::HeaderCrc)];
+        let next = match state {
+            State::Header => 0,
+            State::Extra(_) => 1,
+            State::Name => 2,
+            _ => 3,
+        };
+        fields[next..].iter().find(|&&(flag, _)| self.flags & flag != 0)
+            .map_or(State::Data, |&(_, field)| field)
+    }
+
+    fn validate_header(&mut self) -> io::Result<State> {
+        if self.head[0] != ID1 || self.head[1] != ID2 {
+            return corrupt("invalid gzip header magic")
+        }
+
+        if self.head[2] != CM_DEFLATE {
+            return unsupported("unsupported gzip compression method")
+        }
+
+        self.flags = self.head[3];
+        if self.flags & FRESERVED != 0 {
+            return unsupported("reserved gzip header flags are set")
+        }
+
+        // bytes 4-7 are the modification time, 8 the extra flags and 9 the
+        // operating system, none of which affect decoding
+        Ok(self.after(State::Header))
+    }
+
+    fn validate_trailer(&self) -> io::Result<()> {
+        let mut trailer = &self.head[..];
+        let cksum = trailer.read_u32::<LittleEndian>().unwrap();
+        let size = trailer.read_u32::<LittleEndian>().unwrap();
+        if !self.verify {
+            return Ok(())
+        }
+        if cksum != self.hash.result() {
+            return checksum_mismatch("invalid checksum on gzip stream")
+        }
+        if size != self.size {
+            return corrupt("invalid size on gzip stream")
+        }
+        Ok(())
+    }
+
+    /// Waits for more input, unless it is over
+    fn need_input(&self, pos: usize, produced: usize) -> io::Result<(usize, usize, Status)> {
+        if self.finished {
+            return truncated("truncated gzip stream")
+        }
+        Ok((pos, produced, Status::NeedInput))
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            let len = match self.state {
+                State::Header => 10,
+                State::ExtraLen | State::HeaderCrc => 2,
+                State::Extra(len) => len,
+                State::Trailer => 8,
+                State::Name | State::Comment => {
+                    let end = input[pos..].iter().position(|&b| b == 0).map(|i| pos + i + 1);
+                    self.header.feed(&input[pos..end.unwrap_or(input.len())]);
+                    pos = end.unwrap_or(input.len());
+                    if end.is_none() {
+                        return self.need_input(pos, produced)
+                    }
+                    self.state = self.after(self.state);
+                    continue
+                }
+                State::Data => {
+                    let (consumed, n, status) = try!(self.inner.process(&input[pos..], &mut output[produced..]));
+                    if self.verify {
+                        self.hash.feed(&output[produced..(produced + n)]);
+                        // ISIZE is the input size modulo 2^32
+                        self.size = self.size.wrapping_add(n as u32);
+                    }
+                    pos += consumed;
+                    produced += n;
+                    match status {
+                        Status::Done => {
+                            self.state = State::Trailer;
+                            continue
+                        }
+                        status => return Ok((pos, produced, status)),
+                    }
+                }
+                State::Done => return Ok((pos, produced, Status::Done)),
+            };
+            if !gather(&mut self.head, input, &mut pos, len) {
+                return self.need_input(pos, produced)
+            }
+
+            self.state = match self.state {
+                State::Header => {
+                    self.header.feed(&self.head);
+                    try!(self.validate_header())
+                }
+                State::ExtraLen => {
+                    self.header.feed(&self.head);
+                    State::Extra((&self.head[..]).read_u16::<LittleEndian>().unwrap() as usize)
+                }
+                State::Extra(_) => {
+                    self.header.feed(&self.head);
+                    self.after(self.state)
+                }
+                State::HeaderCrc => {
+                    let crc16 = (&self.head[..]).read_u16::<LittleEndian>().unwrap();
+                    if self.verify && crc16 != (self.header.result() & 0xffff) as u16 {
+                        return checksum_mismatch("invalid header checksum on gzip stream")
+                    }
+                    State::Data
+                }
+                _ => {
+                    try!(self.validate_trailer());
+                    State::Done
+                }
+            };
+            self.head.truncate(0);
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.finished = true;
+        match self.state {
+            State::Data => self.inner.finish(),
+            State::Done => Ok(()),
+            _ => truncated("truncated gzip stream"),
+        }
+    }
+}
+
+/// Structure used to decode a GZIP-encoded stream. The wrapped stream can be
+/// re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new GZIP-stream decoder which will wrap the specified reader.
+    /// This decoder also implements the `Reader` trait, and the underlying
+    /// reader can be re-acquired through the `unwrap` method.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new()),
+        }
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Enables or disables the verification of the CRC32 and the size stored
+    /// in the trailer against the decompressed data. Verification is on by
+    /// default; turning it off skips the checksum computation entirely.
+    pub fn set_verify(&mut self, verify: bool) {
+        self.inner.processor_mut().set_verify(verify);
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Tests if this stream has reached the EOF point yet.
+    pub fn eof(&self) -> bool { self.inner.processor().eof() }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+fn write_header<W: Write>(w: &mut W, level: u32) -> io::Result<()> {
+    // no flags, no modification time, and the extra flags telling about
+    // the compression level
+    let xfl = match level { 9 => 2, 1 => 4, _ => 0 };
+    w.write_all(&[ID1, ID2, CM_DEFLATE, 0, 0, 0, 0, 0, xfl, OS_UNKNOWN])
+}
+
+fn write_trailer<W: Write>(w: &mut W, crc: u32, size: u32) -> io::Result<()> {
+    try!(w.write_u32::<LittleEndian>(crc));
+    w.write_u32::<LittleEndian>(size).map_err(byteorder_err_to_io)
+}
+
+/// This structure is used to compress a stream of bytes into a GZIP member.
+/// This is a wrapper around an internal writer which bytes will be written
+/// to.
+pub struct Encoder<W> {
+    level: u32,
+    inner: checksum::Writer<flate::Encoder<W>, Crc32>,
+    wrote_header: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will have its output written to the given
+    /// output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    /// 'level' ranges from 0 (no compression) to 9 (best compression).
+    pub fn new(w: W, level: u32) -> Encoder<W> {
+        Encoder {
+            level: level,
+            inner: checksum::Writer::new(flate::Encoder::new(w, level), Crc32::new()),
+            wrote_header: false,
+        }
+    }
+
+    /// Creates a new encoder with the given settings of the DEFLATE encoder,
+    /// or fails if they are invalid
+    pub fn with_options(w: W, options: &flate::DeflateOptions) -> io::Result<Encoder<W>> {
+        let inner = try!(options.encoder(w));
+        Ok(Encoder {
+            level: inner.level(),
+            inner: checksum::Writer::new(inner, Crc32::new()),
+            wrote_header: false,
+        })
+    }
+
+    /// Enables the rsyncable mode of the DEFLATE encoder, see
+    /// `flate::Encoder::set_rsyncable`.
+    pub fn set_rsyncable(&mut self, rsyncable: bool) {
+        self.inner.get_mut().set_rsyncable(rsyncable);
+    }
+
+    fn check_header(&mut self) -> io::Result<()> {
+        if !self.wrote_header {
+            try!(write_header(self.inner.get_mut().get_mut(), self.level));
+            self.wrote_header = true;
+        }
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.check_header();
+        // ISIZE is the input size modulo 2^32
+        let (crc, size) = (self.inner.result(), self.inner.count() as u32);
+        let (inner, _) = self.inner.unwrap();
+        let (mut w, result2) = inner.finish();
+        let result = result.and(result2).and_then(|_| write_trailer(&mut w, crc, size));
+        (w, result)
+    }
+
+    /// Finishes the member, as `finish` does, then starts a new one into `w`
+    /// with the same settings, keeping the buffers of the DEFLATE encoder.
+    /// The previous writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.check_header();
+        let (crc, size) = (self.inner.result(), self.inner.count() as u32);
+        self.inner.reset();
+        self.wrote_header = false;
+        let (mut w, result2) = self.inner.get_mut().reset(w);
+        let result = result.and(result2).and_then(|_| write_trailer(&mut w, crc, size));
+        (w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.check_header());
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.check_header());
+        self.inner.flush()
+    }
+}
+
+/// A compressed chunk: its DEFLATE data, CRC32 and length
+#[cfg(feature="threads")]
+type Chunk = (Vec<u8>, u32, usize);
+
+/// Compresses a chunk as a piece of a larger DEFLATE stream, byte aligned
+/// and with no final block unless it is the `last` one
+#[cfg(feature="threads")]
+fn compress_chunk(data: Vec<u8>, level: u32, last: bool) -> Chunk {
+    let mut hash = Crc32::new();
+    hash.feed(&data);
+    let mut e = flate::Encoder::new(Vec::new(), level);
+    // writing to a vector does not fail
+    e.write_all(&data).unwrap();
+    let out = if last {
+        e.finish().0
+    } else {
+        e.flush().unwrap();
+        e.into_inner()
+    };
+    (out, hash.result(), data.len())
+}
+
+/// This structure compresses a stream into a single GZIP member using several
+/// threads, each one handling a chunk of the input at a time. The chunks are
+/// compressed independently, which costs a little compression ratio.
+#[cfg(feature="threads")]
+pub struct ParallelEncoder<W> {
+    w: W,
+    level: u32,
+    threads: usize,
+    chunk_size: usize,
+    buf: Vec<u8>,
+    jobs: VecDeque<JoinHandle<Chunk>>,
+    crc: u32,
+    size: u32,
+    wrote_header: bool,
+}
+
+#[cfg(feature="threads")]
+impl<W: Write> ParallelEncoder<W> {
+    /// Creates a new encoder writing to the given output stream, running up to
+    /// `threads` compression jobs at once.
+    /// 'level' ranges from 0 (no compression) to 9 (best compression).
+    pub fn new(w: W, level: u32, threads: usize) -> ParallelEncoder<W> {
+        assert!(level <= 9, "Invalid deflate compression level {}", level);
+        ParallelEncoder {
+            w: w,
+            level: level,
+            threads: cmp::max(1, threads),
+            chunk_size: CHUNK_SIZE,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+            jobs: VecDeque::new(),
+            crc: 0,
+            size: 0,
+            wrote_header: false,
+        }
+    }
+
+    /// Creates a new encoder with the level, the number of jobs and the size
+    /// of their chunks of the given settings, or fails if they are invalid.
+    /// See `flate::DeflateOptions::auto` to fit them to the machine.
+    pub fn with_options(w: W, options: &flate::DeflateOptions) -> io::Result<ParallelEncoder<W>> {
+        options.parallel_encoder(w)
+    }
+
+    /// Sets the amount of input compressed by each job, 128KB by default.
+    /// This must be called before any data is written.
+    pub fn set_chunk_size(&mut self, size: usize) {
+        assert!(size > 0 && self.buf.is_empty() && self.jobs.is_empty());
+        self.chunk_size = size;
+    }
+
+    /// Hands over the buffered input to a new job, after making room for it
+    fn spawn(&mut self, last: bool) -> io::Result<()> {
+        while self.jobs.len() >= self.threads {
+            try!(self.write_oldest());
+        }
+        let data = ::std::mem::replace(&mut self.buf, Vec::with_capacity(self.chunk_size));
+        let level = self.level;
+        self.jobs.push_back(thread::spawn(move || compress_chunk(data, level, last)));
+        Ok(())
+    }
+
+    /// Waits for the oldest job, and appends its output to the stream
+    fn write_oldest(&mut self) -> io::Result<()> {
+        let job = match self.jobs.pop_front() {
+            Some(job) => job,
+            None => return Ok(()),
+        };
+        let (data, crc, len) = try!(job.join().map_err(|_| io::Error::new(
+            io::ErrorKind::Other,
+            "gzip compression thread panicked"
+        )));
+        if !self.wrote_header {
+            try!(write_header(&mut self.w, self.level));
+            self.wrote_header = true;
+        }
+        try!(self.w.write_all(&data));
+        self.crc = crc32::combine(self.crc, crc, len as u64);
+        self.size = self.size.wrapping_add(len as u32);
+        Ok(())
+    }
+
+    fn write_all_jobs(&mut self) -> io::Result<()> {
+        while !self.jobs.is_empty() {
+            try!(self.write_oldest());
+        }
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.end();
+        (self.w, result)
+    }
+
+    /// Finishes the member, as `finish` does, then starts a new one into `w`
+    /// with the same settings. The previous writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.end();
+        // the jobs left by an error are waited for, and dropped
+        for job in self.jobs.drain(..) {
+            let _ = job.join();
+        }
+        self.buf.truncate(0);
+        self.crc = 0;
+        self.size = 0;
+        self.wrote_header = false;
+        (::std::mem::replace(&mut self.w, w), result)
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        try!(self.spawn(true));
+        try!(self.write_all_jobs());
+        try!(write_trailer(&mut self.w, self.crc, self.size));
+        self.w.flush()
+    }
+}
+
+#[cfg(feature="threads")]
+impl<W: Write> Write for ParallelEncoder<W> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len();
+        while buf.len() > 0 {
+            let amt = cmp::min(self.chunk_size - self.buf.len(), buf.len());
+            self.buf.extend(buf[..amt].iter().cloned());
+            buf = &buf[amt..];
+            if self.buf.len() == self.chunk_size {
+                try!(self.spawn(false));
+            }
+        }
+        Ok(len)
+    }
+
+    /// Compresses the buffered input as a chunk of its own, and waits for all
+    /// the jobs to be written out
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.len() > 0 {
+            try!(self.spawn(false));
+        }
+        try!(self.write_all_jobs());
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing::push;
+    use super::{Decoder, Encoder, Processor};
+    #[cfg(feature="threads")]
+    use super::ParallelEncoder;
+
+    fn test_decode(input: &[u8], output: &[u8]) {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut buf = Vec::new();
+
+        if let Err(e) = d.read_to_end(&mut buf) {
+            panic!("error reading: {}", e);
+        }
+
+        assert!(d.eof());
+        assert!(&buf[..] == output);
+        for &step in [1, 333].iter() {
+            assert!(push(&mut Processor::new(), input, step).unwrap() == output);
+        }
+    }
+
+    /// Wraps a payload into a gzip member made of a single stored block,
+    /// with every optional header field present.
+    fn stored_member(data: &[u8], crc: u32, size: u32) -> Vec<u8> {
+        let mut out = vec![0x1f, 0x8b, 8, 0x1e, 0, 0, 0, 0, 0, 255];
+        out.extend([3u8, 0, b'a', b'b', b'c'].iter().cloned());
+        out.extend(b"name\0".iter().cloned());
+        out.extend(b"comment\0".iter().cloned());
+        let mut header = super::Crc32::new();
+        header.feed(&out);
+        let hcrc = header.result();
+        out.push(hcrc as u8);
+        out.push((hcrc >> 8) as u8);
+        let len = data.len() as u16;
+        out.extend([1u8, len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8].iter().cloned());
+        out.extend(data.iter().cloned());
+        for i in 0..4 { out.push((crc >> (8 * i)) as u8); }
+        for i in 0..4 { out.push((size >> (8 * i)) as u8); }
+        out
+    }
+
+    fn crc(data: &[u8]) -> u32 {
+        let mut state = super::Crc32::new();
+        state.feed(data);
+        state.result()
+    }
+
+    #[test]
+    fn decode() {
+        let reference = include_bytes!("data/test.txt");
+        test_decode(include_bytes!("data/test.gz.1"), reference);
+        test_decode(include_bytes!("data/test.gz.9"), reference);
+    }
+
+    #[test]
+    fn optional_header_fields() {
+        let data = b"hello gzip";
+        let member = stored_member(data, crc(data), data.len() as u32);
+        test_decode(&member[..], data);
+    }
+
+    #[test]
+    fn one_byte_at_a_time() {
+        let input = include_bytes!("data/test.gz.9");
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        let mut out = Vec::new();
+        let mut buf = [0u8; 1];
+        loop {
+            match d.read(&mut buf).unwrap() {
+                0 => break,
+                _ => out.push(buf[0]),
+            }
+        }
+        assert!(d.eof());
+        assert!(&out[..] == &include_bytes!("data/test.txt")[..]);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let data = b"hello gzip";
+        let member = stored_member(data, crc(data) ^ 1, data.len() as u32);
+        let mut d = Decoder::new(BufReader::new(&member[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+
+        let mut d = Decoder::new(BufReader::new(&member[..]));
+        d.set_verify(false);
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[..]);
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let data = b"hello gzip";
+        let member = stored_member(data, crc(data), data.len() as u32 + 1);
+        let mut d = Decoder::new(BufReader::new(&member[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn corrupted_trailer() {
+        let mut input = include_bytes!("data/test.gz.1").to_vec();
+        let n = input.len();
+        input[n - 6] ^= 0x40;
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn encode() {
+        let reference = include_bytes!("data/test.txt");
+        for &level in [0, 1, 6, 9].iter() {
+            let mut e = Encoder::new(Vec::new(), level);
+            e.write_all(reference).unwrap();
+            let (encoded, err) = e.finish();
+            err.unwrap();
+            test_decode(&encoded, reference);
+        }
+        let (empty, err) = Encoder::new(Vec::new(), 6).finish();
+        err.unwrap();
+        test_decode(&empty, b"");
+    }
+
+    #[test]
+    fn reset() {
+        let reference = include_bytes!("data/test.txt");
+        let mut e = Encoder::new(Vec::new(), 6);
+        e.write_all(b"hello").unwrap();
+        let (first, err) = e.reset(Vec::new());
+        err.unwrap();
+        e.write_all(reference).unwrap();
+        let (second, err) = e.finish();
+        err.unwrap();
+        let mut fresh = Encoder::new(Vec::new(), 6);
+        fresh.write_all(reference).unwrap();
+        assert!(second == fresh.finish().0);
+
+        let mut d = Decoder::new(BufReader::new(&second[..]));
+        d.read_exact(&mut [0; 100]).unwrap();
+        d.reset(BufReader::new(&first[..]));
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+        assert!(d.eof());
+        assert_eq!(buf, b"hello");
+        d.reset(BufReader::new(&second[..]));
+        buf.truncate(0);
+        d.read_to_end(&mut buf).unwrap();
+        assert!(&buf[..] == &reference[..]);
+    }
+
+    #[cfg(feature="threads")]
+    #[test]
+    fn parallel_reset() {
+        let reference = include_bytes!("data/test.txt");
+        let mut e = ParallelEncoder::new(Vec::new(), 6, 3);
+        e.set_chunk_size(1000);
+        e.write_all(reference).unwrap();
+        let (first, err) = e.reset(Vec::new());
+        err.unwrap();
+        e.write_all(b"hello").unwrap();
+        let (second, err) = e.finish();
+        err.unwrap();
+        test_decode(&first, reference);
+        test_decode(&second, b"hello");
+    }
+
+    #[cfg(feature="threads")]
+    #[test]
+    fn parallel_encode() {
+        let reference = include_bytes!("data/test.txt");
+        for &(threads, chunk_size) in [(1, 1000), (4, 1000), (3, 4096), (8, 1 << 20)].iter() {
+            let mut e = ParallelEncoder::new(Vec::new(), 6, threads);
+            e.set_chunk_size(chunk_size);
+            for piece in reference.chunks(777) {
+                e.write_all(piece).unwrap();
+            }
+            e.flush().unwrap();
+            e.write_all(reference).unwrap();
+            let (encoded, err) = e.finish();
+            err.unwrap();
+            let mut expected = reference.to_vec();
+            expected.extend(reference.iter().cloned());
+            test_decode(&encoded, &expected);
+        }
+        let (empty, err) = ParallelEncoder::new(Vec::new(), 6, 2).finish();
+        err.unwrap();
+        test_decode(&empty, b"");
+    }
+
+    #[test]
+    #[cfg(feature="threads")]
+    fn auto_options() {
+        let reference = include_bytes!("data/test.txt");
+        for &budget in [0, 1 << 20, 1 << 30].iter() {
+            let options = ::flate::DeflateOptions::auto(budget);
+            let mut e = ParallelEncoder::with_options(Vec::new(), &options).unwrap();
+            e.write_all(reference).unwrap();
+            let (encoded, err) = e.finish();
+            err.unwrap();
+            test_decode(&encoded, reference);
+        }
+        let options = ::flate::DeflateOptions::new().chunk_size(0);
+        assert!(ParallelEncoder::with_options(Vec::new(), &options).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! Hashes of the bytes starting at a position, for match finders
+//!
+//! The bytes are read as a little endian integer, multiplied by a large odd
+//! constant, and the top bits of the product are kept, folding the hash into
+//! a table of `1 << bits` entries. These are the hashes of the match finders
+//! of the crate, as of the LZ4, DEFLATE and LZP encoders, so that external
+//! ones built on them find the same matches. `hash3` and `hash4` suit the
+//! shortest matches of LZ77 coders, `hash_prefix` those of a minimum length
+//! given at run time, while `hash5` and `hash8` better separate the longer
+//! contexts. The formats which define their own multiplier, as Chameleon,
+//! only fold their products with `fold32`.
+//!
+//! `match_len` then extends the matches found, many bytes at a time.
+//!
+//! # Example
+//!
+//! ```rust
+//! use compress::hash;
+//! let mut table = vec![0usize; 1 << 12];
+//! let data = b"abracadabra";
+//! for pos in 0..data.len() - 3 {
+//!     table[hash::hash4(&data[pos..], 12)] = pos;
+//! }
+//! ```
+
+use std::cmp;
+
+/// Knuth's multiplier, for the hashes of 32 bits
+pub const PRIME32: u32 = 2654435761;
+/// Multiplier of the hashes of 5 bytes, as in zstd
+pub const PRIME40: u64 = 889523592379;
+/// Multiplier of the hashes of 8 bytes, as in zstd
+pub const PRIME64: u64 = 0xcf1bbcdcb7a56463;
+
+fn read(bytes: &[u8], len: usize) -> u64 {
+    bytes[..len].iter().rev().fold(0, |v, &b| (v << 8) | b as u64)
+}
+
+/// Keeps the top `bits` bits of a hash of 32 bits, from 1 to 32
+#[inline]
+pub fn fold32(hash: u32, bits: u32) -> usize {
+    (hash >> (32 - bits)) as usize
+}
+
+/// Keeps the top `bits` bits of a hash of 64 bits, from 1 to 64
+#[inline]
+pub fn fold64(hash: u64, bits: u32) -> usize {
+    (hash >> (64 - bits)) as usize
+}
+
+/// Hashes the first `len` bytes, from 1 to 4, into `bits` bits
+#[inline]
+pub fn hash_prefix(bytes: &[u8], len: usize, bits: u32) -> usize {
+    debug_assert!(len >= 1 && len <= 4);
+    fold32((read(bytes, len) as u32).wrapping_mul(PRIME32), bits)
+}
+
+/// Hashes the first 3 bytes into `bits` bits
+#[inline]
+pub fn hash3(bytes: &[u8], bits: u32) -> usize {
+    hash_prefix(bytes, 3, bits)
+}
+
+/// Hashes the first 4 bytes into `bits` bits
+#[inline]
+pub fn hash4(bytes: &[u8], bits: u32) -> usize {
+    fold32((read(bytes, 4) as u32).wrapping_mul(PRIME32), bits)
+}
+
+/// Hashes the first 5 bytes into `bits` bits
+#[inline]
+pub fn hash5(bytes: &[u8], bits: u32) -> usize {
+    fold64((read(bytes, 5) << 24).wrapping_mul(PRIME40), bits)
+}
+
+/// Hashes the first 8 bytes into `bits` bits
+#[inline]
+pub fn hash8(bytes: &[u8], bits: u32) -> usize {
+    fold64(read(bytes, 8).wrapping_mul(PRIME64), bits)
+}
+
+fn read_word(bytes: &[u8], pos: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[pos..(pos + 8)]);
+    u64::from_le_bytes(word)
+}
+
+/// Returns the number of bytes `a` and `b` start with in common. The bytes
+/// are compared 32 at a time with SSE2 on x86-64 and 8 at a time elsewhere,
+/// the first difference being the count of trailing zeros of the comparison.
+#[inline]
+pub fn match_len(a: &[u8], b: &[u8]) -> usize {
+    let max = cmp::min(a.len(), b.len());
+    let mut len = 0;
+    #[cfg(all(target_arch="x86_64", target_feature="sse2"))]
+    {
+        use std::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8};
+        while len + 32 <= max {
+            // the 32 bytes from `len` are within both slices
+            let equal = unsafe {
+                let (pa, pb) = (a.as_ptr().add(len) as *const __m128i, b.as_ptr().add(len) as *const __m128i);
+                let low = _mm_movemask_epi8(_mm_cmpeq_epi8(_mm_loadu_si128(pa), _mm_loadu_si128(pb)));
+                let high = _mm_movemask_epi8(_mm_cmpeq_epi8(_mm_loadu_si128(pa.add(1)), _mm_loadu_si128(pb.add(1))));
+                (low as u32 & 0xffff) | (high as u32) << 16
+            };
+            if equal != !0 {
+                return len + (!equal).trailing_zeros() as usize
+            }
+            len += 32;
+        }
+    }
+    while len + 8 <= max {
+        let diff = read_word(a, len) ^ read_word(b, len);
+        if diff != 0 {
+            return len + (diff.trailing_zeros() >> 3) as usize
+        }
+        len += 8;
+    }
+    while len < max && a[len] == b[len] {
+        len += 1;
+    }
+    len
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hash3, hash4, hash5, hash8, hash_prefix, match_len};
+
+    fn spread<F: Fn(&[u8], u32) -> usize>(hash: F, len: usize) -> usize {
+        // the positions of text, in a table of 1 << 10 entries
+        let data = include_bytes!("data/test.txt");
+        let mut seen = vec![false; 1 << 10];
+        for pos in 0..1000 {
+            let h = hash(&data[pos..], 10);
+            assert!(h < seen.len());
+            assert_eq!(h, hash(&data[pos..pos + len], 10));
+            seen[h] = true;
+        }
+        seen.iter().filter(|&&s| s).count()
+    }
+
+    #[test]
+    fn hashes() {
+        assert_eq!(hash4(b"abcd", 32), 0x64636261u32.wrapping_mul(2654435761) as usize);
+        assert_eq!(hash4(b"abcdefgh", 16), hash4(b"abcdXXXX", 16));
+        assert_eq!(hash3(b"abcd", 16), hash3(b"abcX", 16));
+        assert_eq!(hash3(b"abc", 32), hash_prefix(b"abcd", 3, 32));
+        assert_eq!(hash_prefix(b"abcd", 4, 32), hash4(b"abcd", 32));
+        assert!(hash5(b"abcdefgh", 16) != hash5(b"abcdXXXX", 16));
+        assert_eq!(hash5(b"abcdefgh", 16), hash5(b"abcdeXXX", 16));
+        assert!(hash8(b"abcdefgh", 16) != hash8(b"abcdefgX", 16));
+        for &(hash, len) in [(hash3 as fn(&[u8], u32) -> usize, 3), (hash4, 4), (hash5, 5), (hash8, 8)].iter() {
+            assert!(spread(hash, len) > 500);
+        }
+    }
+
+    #[test]
+    fn match_lengths() {
+        let data = include_bytes!("data/test.txt");
+        for &(len, diff) in [(0, 0), (100, 0), (100, 7), (100, 8), (100, 31), (100, 32), (100, 99)].iter() {
+            let a = &data[..len];
+            let mut b = a.to_vec();
+            if len > 0 {
+                b[diff] ^= 0x40;
+                assert_eq!(match_len(a, &b), diff);
+            }
+            assert_eq!(match_len(a, a), len);
+            assert_eq!(match_len(a, &a[..(len / 2)]), len / 2);
+        }
+    }
+}
@@ -1,43 +1,182 @@
 #![deny(missing_docs)]
 #![allow(missing_copy_implementations)]
+#![cfg_attr(not(feature="std"), no_std)]
+#![cfg_attr(feature="unstable", feature(test))]
 
 //! dox (placeholder)
+//!
+//! Without the default `std` feature, the crate only needs `alloc`: the
+//! readers and writers are left out, while the transforms on slices remain,
+//! as the BWT, DC and MTF of `bwt`, the block coding of `rle` and `lz4`, and
+//! the range coder of `entropy`. Their errors are then an `error::Error`.
+//!
+//! Only the `threads` feature spawns threads, and no module opens files, so
+//! that the crate targets WebAssembly without it: the `Compressor`s and
+//! `Decompressor`s of `codec` code slices to vectors, and
+//! `process::decode_to_vec` does so for the push decoders, with or without
+//! `std`.
 
+#[cfg(feature="std")]
 extern crate byteorder;
+#[cfg(feature="futures-io")]
+extern crate futures_io;
+#[cfg(not(feature="std"))]
+#[macro_use]
+extern crate alloc;
 //extern crate rand;
 
-#[macro_use]
+#[cfg_attr(any(feature="bwt", feature="entropy", feature="lz4"), macro_use)]
 extern crate log;
 
 #[cfg(test)]
 #[cfg(feature="unstable")]
 extern crate test;
 
+#[cfg(feature="std")]
+use std::cmp;
+#[cfg(feature="std")]
 use std::io::{self, Read};
 
+/// The parts of `std` found in `core` and `alloc`, by which the modules
+/// built without `std` refer to them the same way
+#[cfg(not(feature="std"))]
+mod std {
+    pub use core::*;
+    pub use alloc::vec;
+    #[cfg(feature="lz4")]
+    pub use alloc::borrow;
+    #[cfg(feature="rle")]
+    pub use alloc::boxed;
+
+    /// The results of the coders without `std`
+    pub mod io {
+        pub use error::Error;
+
+        /// A result whose error is that of the coders
+        pub type Result<T> = ::core::result::Result<T, Error>;
+    }
+}
+
 /// Public exports
 #[cfg(feature="checksum")]
 pub use self::checksum::adler::State32 as Adler32;
-
 #[cfg(feature="checksum")]
-/// Checksum algorithms. Requires `checksum` feature, enabled by default
-// http://en.wikipedia.org/wiki/Checksum
-pub mod checksum {
-    pub mod adler;
-}
+pub use self::checksum::crc32::State32 as Crc32;
+
+#[cfg(any(feature="checksum", feature="lz4"))]
+pub mod checksum;
+
+#[cfg(feature="async")]
+pub mod aio;
+#[cfg(feature="bench")]
+pub mod bench;
+pub mod chunk;
+#[cfg(feature="std")]
+pub mod codec;
+#[cfg(feature="threads")]
+pub mod compare;
+#[cfg(feature="container")]
+pub mod container;
+pub mod contexts;
+pub mod copy;
+pub mod error;
+pub mod hash;
+pub mod histogram;
+pub mod process;
+#[cfg(feature="std")]
+pub mod pool;
+#[cfg(feature="threads")]
+pub mod prefetch;
+#[cfg(feature="threads")]
+mod ring;
+#[cfg(feature="std")]
+pub mod stats;
+#[cfg(test)]
+mod testing;
+#[cfg(feature="std")]
+pub mod tune;
+
+#[cfg(feature="bpe")]
+pub mod bpe;
+
+#[cfg(feature="brotli")]
+pub mod brotli;
 
 #[cfg(feature="bwt")]
 pub mod bwt;
 
+#[cfg(feature="bzip2")]
+pub mod bzip2;
+
+#[cfg(feature="chameleon")]
+pub mod chameleon;
+
+#[cfg(feature="dictionary")]
+pub mod dictionary;
+
+#[cfg(feature="ffi")]
+pub mod ffi;
+
+#[cfg(feature="filter")]
+pub mod filter;
+
 #[cfg(feature="flate")]
 pub mod flate;
 
+#[cfg(feature="gzip")]
+pub mod gzip;
+
 #[cfg(feature="lz4")]
 pub mod lz4;
 
+#[cfg(feature="lz78")]
+pub mod lz78;
+
+#[cfg(feature="lzf")]
+pub mod lzf;
+
+#[cfg(feature="lzma")]
+pub mod lzma;
+
+#[cfg(feature="lzp")]
+pub mod lzp;
+
+#[cfg(feature="lzss")]
+pub mod lzss;
+
+#[cfg(feature="lzw")]
+pub mod lzw;
+
+#[cfg(feature="lzo")]
+pub mod lzo;
+
+#[cfg(feature="parallel")]
+pub mod parallel;
+
+#[cfg(feature="pipeline")]
+pub mod pipeline;
+
+#[cfg(feature="ppmd")]
+pub mod ppmd;
+
+#[cfg(feature="rolz")]
+pub mod rolz;
+
+#[cfg(feature="seekable")]
+pub mod seekable;
+
+#[cfg(feature="snappy")]
+pub mod snappy;
+
+#[cfg(feature="sparse")]
+pub mod sparse;
+
 #[cfg(feature="zlib")]
 pub mod zlib;
 
+#[cfg(feature="zstd")]
+pub mod zstd;
+
 /// Entropy coder family. Requires `entropy` feature, enabled by default
 // http://en.wikipedia.org/wiki/Entropy_encoding
 #[cfg(feature="entropy")]
@@ -45,37 +184,173 @@ pub mod entropy {
     pub mod ari;
 }
 
+#[cfg(feature="rice")]
+pub mod rice;
+
 #[cfg(feature="rle")]
 pub mod rle;
 
-#[cfg(any(feature = "lz4", feature = "entropy", feature = "bwt"))]
+#[cfg(all(feature="std", any(feature="entropy", feature="gzip", feature="lz4")))]
 fn byteorder_err_to_io(err: byteorder::Error) -> io::Error {
     match err {
         byteorder::Error::Io(e) => e,
-        byteorder::Error::UnexpectedEOF =>
-            io::Error::new(
-                io::ErrorKind::Other,
-                "unexpected end of file"
-            )
+        byteorder::Error::UnexpectedEOF => error::Error::UnexpectedEof { detail: "unexpected end of file" }.into(),
     }
 }
 
+/// Reads `slice[i]`, for the hot loops which validate their indices
+/// beforehand: the `unsafe-fast` feature leaves the index unchecked
+#[cfg(all(feature="bwt", feature="std"))]
+#[cfg(not(feature="unsafe-fast"))]
+#[inline(always)]
+fn at<T: Copy>(slice: &[T], i: usize) -> T {
+    slice[i]
+}
+
+#[cfg(all(feature="bwt", feature="std"))]
+#[cfg(feature="unsafe-fast")]
+#[inline(always)]
+fn at<T: Copy>(slice: &[T], i: usize) -> T {
+    debug_assert!(i < slice.len());
+    unsafe { *slice.get_unchecked(i) }
+}
+
 /// Adds a convenience method for types with the read trait, very similar
 /// to push_at_least in the late Reader trait
+#[cfg(feature="std")]
 pub trait ReadExact: Read + Sized {
     /// Appends exact number of bytes to a buffer
     fn push_exactly(&mut self, bytes: u64, buf: &mut Vec<u8>) -> io::Result<()> {
         let n = try!(self.by_ref().take(bytes).read_to_end(buf)) as u64;
 
         if n < bytes {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "unexpected end of file"
-            ));
+            return error::truncated("unexpected end of file")
         }
 
         Ok(())
     }
 }
 
+#[cfg(feature="std")]
 impl<T> ReadExact for T where T: Read + Sized {}
+
+/// Size of the buffer into which `Skip::skip` decodes the data it discards
+#[cfg(feature="std")]
+const SKIP_BUF_SIZE: usize = 1 << 16;
+
+/// Skips ahead in the decompressed data of a reader, for the callers which
+/// only want a region of a large stream
+#[cfg(feature="std")]
+pub trait Skip: Read {
+    /// Skips the next `n` bytes of data, returning how many were skipped,
+    /// which are fewer only if the data ends first. The data is decoded into
+    /// a buffer of the decoder and discarded, unless an index tells where to
+    /// go on from.
+    fn skip(&mut self, n: u64) -> io::Result<u64> {
+        let mut buf = vec![0; cmp::min(n, SKIP_BUF_SIZE as u64) as usize];
+        let mut left = n;
+        while left > 0 {
+            let len = cmp::min(left, buf.len() as u64) as usize;
+            match self.read(&mut buf[..len]) {
+                Ok(0) => break,
+                Ok(amt) => left -= amt as u64,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(n - left)
+    }
+}
+
+#[cfg(feature="std")]
+impl<'a> Skip for Box<dyn Read + 'a> {}
+
+#[cfg(feature="std")]
+impl<R: Read, P: process::Process> Skip for process::Reader<R, P> {}
+
+#[cfg(feature="std")]
+impl<'a> Skip for codec::AnyDecoder<'a> {}
+
+macro_rules! skip_by_decoding {
+    ($($feature:tt: $($decoder:ident)::+),*) => {$(
+        #[cfg(all(feature="std", feature=$feature))]
+        impl<R: Read> Skip for $($decoder)::+<R> where $($decoder)::+<R>: Read {}
+    )*}
+}
+
+skip_by_decoding! {
+    "bpe": bpe::Decoder,
+    "brotli": brotli::Decoder,
+    "bwt": bwt::Decoder,
+    "bzip2": bzip2::Decoder,
+    "flate": flate::Decoder,
+    "gzip": gzip::Decoder,
+    "lz4": lz4::Decoder,
+    "lz78": lz78::Decoder,
+    "lzma": lzma::Decoder,
+    "lzma": lzma::xz::Decoder,
+    "lzo": lzo::Decoder,
+    "lzp": lzp::Decoder,
+    "lzss": lzss::Decoder,
+    "lzw": lzw::Decoder,
+    "lzw": lzw::z::Decoder,
+    "ppmd": ppmd::Decoder,
+    "rle": rle::Decoder,
+    "rolz": rolz::Decoder,
+    "snappy": snappy::Decoder,
+    "sparse": sparse::Decoder,
+    "zlib": zlib::Decoder,
+    "zstd": zstd::Decoder
+}
+
+/// The detail of the error of the decoders stopped by `set_max_output_size`
+#[cfg(feature="std")]
+const OUTPUT_LIMIT_EXCEEDED: &'static str = "decoded data exceeds the maximum output size";
+
+/// The output a decoder may still produce, once given a maximum size by
+/// `set_max_output_size`, to stop on data which expands beyond reason
+#[cfg(feature="std")]
+#[derive(Clone, Copy, Debug)]
+struct OutputLimit {
+    max: Option<u64>,
+    left: u64,
+}
+
+#[cfg(feature="std")]
+impl OutputLimit {
+    fn new() -> OutputLimit {
+        OutputLimit { max: None, left: 0 }
+    }
+
+    /// Allows `max` bytes of output from now on
+    fn set(&mut self, max: u64) {
+        self.max = Some(max);
+        self.left = max;
+    }
+
+    /// Allows the whole maximum again, for another stream
+    fn reset(&mut self) {
+        self.left = self.max.unwrap_or(0);
+    }
+
+    /// Returns how much of a buffer of `len` bytes to decode into: one byte
+    /// past the limit is decoded to find whether the data goes beyond it.
+    fn clamp(&self, len: usize) -> usize {
+        match self.max {
+            Some(_) if self.left < len as u64 => self.left as usize + 1,
+            _ => len,
+        }
+    }
+
+    /// Counts the `n` bytes decoded into a clamped buffer, failing once they
+    /// exceed the limit
+    fn count(&mut self, n: usize) -> io::Result<usize> {
+        if self.max.is_some() {
+            if n as u64 > self.left {
+                return error::limit_exceeded(OUTPUT_LIMIT_EXCEEDED)
+            }
+            self.left -= n as u64;
+        }
+        Ok(n)
+    }
+}
@@ -26,19 +26,42 @@ can be found at https://github.com/bkaradzic/go-lz4.
 
 */
 
+use std::borrow::Cow;
 use std::cmp;
-use std::ptr::copy_nonoverlapping;
-use std::io::{self, Read, Write};
+use std::io;
+#[cfg(feature="std")]
+use std::io::{Read, Write};
+#[cfg(feature="std")]
+use std::mem;
 use std::iter::repeat;
 use std::vec::Vec;
 use std::num::Wrapping;
 use std::ops::Shr;
 
-use super::byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
-use super::{ReadExact, byteorder_err_to_io};
+#[cfg(feature="std")]
+use super::byteorder::{LittleEndian, WriteBytesExt};
+#[cfg(feature="std")]
+use super::byteorder_err_to_io;
+use checksum::xxhash;
+use copy::copy_match;
+#[cfg(feature="dictionary")]
+use dictionary::Dictionary;
+use error::{corrupt, truncated, unsupported};
+use hash;
+use process::{self, drain, gather, Process, Status};
+#[cfg(feature="std")]
+use process::Pump;
 
 const MAGIC: u32 = 0x184d2204;
 
+/// The maximum sizes of the blocks, by their code in the descriptor
+static MAX_SIZES: [usize; 8] =
+    [0, 0, 0, 0, // all N/A
+     64 << 10,   // 64KB
+     256 << 10,  // 256 KB
+     1 << 20,    // 1MB
+     4 << 20];   // 4MB
+
 const ML_BITS: u32 = 4;
 const ML_MASK: u32 = (1 << ML_BITS as usize) - 1;
 const RUN_BITS: u32 = 8 - ML_BITS;
@@ -47,7 +70,6 @@ const RUN_MASK: u32 = (1 << RUN_BITS as usize) - 1;
 const MIN_MATCH: u32 = 4;
 const HASH_LOG: u32 = 17;
 const HASH_TABLE_SIZE: u32 = 1 << (HASH_LOG as usize);
-const HASH_SHIFT: u32 = (MIN_MATCH * 8) - HASH_LOG;
 const INCOMPRESSIBLE: u32 = 128;
 const UNINITHASH: u32 = 0x88888888;
 const MAX_INPUT_SIZE: u32 = 0x7e000000;
@@ -57,106 +79,73 @@ struct BlockDecoder<'a> {
     output: &'a mut Vec<u8>,
     cur: usize,
 
-    start: usize,
     end: usize,
+    /// The end of the output the block may not decode past
+    limit: usize,
 }
 
 impl<'a> BlockDecoder<'a> {
     /// Decodes this block of data from 'input' to 'output', returning the
     /// number of valid bytes in the output.
-    fn decode(&mut self) -> usize {
+    fn decode(&mut self) -> io::Result<usize> {
+        self.output.truncate(self.end);
         while self.cur < self.input.len() {
-            let code = self.bump();
+            let code = try!(self.bump());
             debug!("block with code: {:x}", code);
             // Extract a chunk of data from the input to the output.
             {
-                let len = self.length(code >> 4);
+                let len = try!(self.length(code >> 4));
                 debug!("consume len {}", len);
-                if len > 0 {
-                    let end = self.end;
-                    self.grow_output(end + len);
-                    unsafe { copy_nonoverlapping(
-                        &self.input[self.cur],
-                        &mut self.output[end],
-                        len
-                    )};
-                    self.end += len;
-                    self.cur += len;
+                if len > self.input.len() - self.cur {
+                    return corrupt("lz4 literals past the end of the block")
+                }
+                if len > self.limit - self.end {
+                    return corrupt("lz4 block larger than the maximum size")
                 }
+                self.output.extend_from_slice(&self.input[self.cur..(self.cur + len)]);
+                self.end += len;
+                self.cur += len;
             }
             if self.cur == self.input.len() { break }
 
             // Read off the next i16 offset
-            {
-                let back = (self.bump() as usize) | ((self.bump() as usize) << 8);
-                debug!("found back {}", back);
-                self.start = self.end - back;
+            let back = (try!(self.bump()) as usize) | ((try!(self.bump()) as usize) << 8);
+            debug!("found back {}", back);
+            if back == 0 || back > self.end {
+                return corrupt("lz4 offset out of the output")
             }
 
             // Slosh around some bytes now
-            {
-                let mut len = self.length(code & 0xf);
-                let literal = self.end - self.start;
-                if literal < 4 {
-                    static DECR: [usize; 4] = [0, 3, 2, 3];
-                    self.cp(4, DECR[literal]);
-                } else {
-                    len += 4;
-                }
-                self.cp(len, 0);
+            let len = try!(self.length(code & 0xf)) + MIN_MATCH as usize;
+            if len > self.limit - self.end {
+                return corrupt("lz4 block larger than the maximum size")
             }
+            debug_assert_eq!(self.output.len(), self.end);
+            copy_match(self.output, back, len);
+            self.end += len;
         }
-        self.end
+        Ok(self.end)
     }
 
-    fn length(&mut self, code: u8) -> usize {
+    fn length(&mut self, code: u8) -> io::Result<usize> {
         let mut ret = code as usize;
         if code == 0xf {
             loop {
-                let tmp = self.bump();
+                let tmp = try!(self.bump());
                 ret += tmp as usize;
                 if tmp != 0xff { break }
             }
         }
-        ret
+        Ok(ret)
     }
 
-    fn bump(&mut self) -> u8 {
-        let ret = self.input[self.cur];
-        self.cur += 1;
-        ret
-    }
-
-    #[inline]
-    fn cp(&mut self, len: usize, decr: usize) {
-        let end = self.end;
-        self.grow_output(end + len);
-        for i in 0..len {
-            self.output[end + i] = (*self.output)[self.start + i];
-        }
-
-        self.end += len;
-        self.start += len - decr;
-    }
-
-    // Extends the output vector to a target number of bytes (in total), but
-    // does not actually initialize the new data. The length of the vector is
-    // updated, but the bytes will all have undefined values. It is assumed that
-    // the next operation is to pave over these bytes (so the initialization is
-    // unnecessary).
-    #[inline]
-    fn grow_output(&mut self, target: usize) {
-        if self.output.capacity() < target {
-            debug!("growing {} to {}", self.output.capacity(), target);
-            //let additional = target - self.output.capacity();
-            //self.output.reserve(additional);
-            while self.output.len() < target {
-                self.output.push(0);
-            }
-        }else {
-            unsafe {
-               self.output.set_len(target);
+    fn bump(&mut self) -> io::Result<u8> {
+        match self.input.get(self.cur) {
+            Some(&b) => {
+                self.cur += 1;
+                Ok(b)
             }
+            None => corrupt("truncated lz4 sequence"),
         }
     }
 }
@@ -245,7 +234,7 @@ impl<'a> BlockEncoder<'a> {
                     }
 
                     let seq = self.seq_at(self.pos);
-                    let hash = (Wrapping(seq) * Wrapping(2654435761)).shr(HASH_SHIFT as usize).0;
+                    let hash = hash::hash4(&self.input[self.pos as usize..], HASH_LOG);
                     let mut r = (Wrapping(self.hash_table[hash as usize]) + Wrapping(UNINITHASH)).0;
                     self.hash_table[hash as usize] = (Wrapping(self.pos) - Wrapping(UNINITHASH)).0;
 
@@ -275,9 +264,10 @@ impl<'a> BlockEncoder<'a> {
                     r += MIN_MATCH;
                     self.anchor = self.pos;
 
-                    while (self.pos < input_len - 5) && self.input[self.pos as usize] == self.input[r as usize] {
-                        self.pos += 1;
-                        r += 1
+                    if self.pos < input_len - 5 {
+                        let len = hash::match_len(&self.input[(r as usize)..],
+                                                  &self.input[(self.pos as usize)..((input_len - 5) as usize)]) as u32;
+                        self.pos += len;
                     }
 
                     let mut ml_len = self.pos - self.anchor;
@@ -307,70 +297,125 @@ impl<'a> BlockEncoder<'a> {
     }
 }
 
-/// This structure is used to decode a stream of LZ4 blocks. This wraps an
-/// internal reader which is read from when this decoder's read method is
-/// called.
-pub struct Decoder<R> {
-    /// The internally wrapped reader. This is exposed so it may be moved out
-    /// of. Note that if data is read from the reader while decoding is in
-    /// progress the output stream will get corrupted.
-    pub r: R,
+/// Reads a little endian integer of the length of `bytes`
+fn read_le(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |v, &b| (v << 8) | b as u64)
+}
+
+/// Returns the xxHash of `buf` with a seed of 0, as for the checksums of the
+/// frames
+fn xxh32(buf: &[u8]) -> u32 {
+    let mut state = xxhash::State32::new(0);
+    state.feed(buf);
+    state.result()
+}
+
+/// Returns the checksum byte of the frame descriptor `descriptor`, from the
+/// flags up to the checksum
+fn header_checksum(descriptor: &[u8]) -> u8 {
+    (xxh32(descriptor) >> 8) as u8
+}
+
+/// The part of the stream a `Processor` expects next
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// The magic number and the flags
+    Header,
+    /// The rest of the frame descriptor, of the given length
+    Descriptor(usize),
+    /// The size of the next block, or the end mark
+    Size,
+    /// A block of the given size, compressed or not
+    Block(usize, bool),
+    /// The checksum of the last block
+    BlockChecksum,
+    /// The checksum of the stream
+    StreamChecksum,
+    /// Nothing, the stream is over
+    End,
+}
 
+/// This structure decodes a stream of LZ4 blocks pushed into it, for the
+/// callers which hold the buffers, as an event loop does. See the `process`
+/// module.
+pub struct Processor {
+    state: State,
     temp: Vec<u8>,
     output: Vec<u8>,
 
     start: usize,
     end: usize,
-    eof: bool,
 
-    header: bool,
     blk_checksum: bool,
     stream_checksum: bool,
     max_block_size: usize,
+
+    /// The flags and the block size code, for the header checksum
+    flags: [u8; 2],
+    /// The checksum of the last block, and that of the data decoded so far
+    block_sum: u32,
+    content: xxhash::State32,
+
+    /// The end of the preset dictionary and its identifier, if any
+    dictionary: Option<(Vec<u8>, u32)>,
 }
 
-impl<R: Read + Sized> Decoder<R> {
-    /// Creates a new decoder which will read data from the given stream. The
-    /// inner stream can be re-acquired by moving out of the `r` field of this
-    /// structure.
-    pub fn new(r: R) -> Decoder<R> {
-        Decoder {
-            r: r,
+impl Processor {
+    /// Creates a new decoder, waiting for the header of a stream
+    pub fn new() -> Processor {
+        Processor {
+            state: State::Header,
             temp: Vec::new(),
             output: Vec::new(),
-            header: false,
-            blk_checksum: false,
-            stream_checksum: false,
             start: 0,
             end: 0,
-            eof: false,
+            blk_checksum: false,
+            stream_checksum: false,
             max_block_size: 0,
+            flags: [0; 2],
+            block_sum: 0,
+            content: xxhash::State32::new(0),
+            dictionary: None,
         }
     }
 
-    /// Resets this decoder back to its initial state. Note that the underlying
-    /// stream is not seeked on or has any alterations performed on it.
+    /// Creates a new decoder of streams compressed with the given dictionary,
+    /// of which the last 64 kB are used
+    #[cfg(feature="dictionary")]
+    pub fn with_dictionary(dictionary: &Dictionary) -> Processor {
+        let mut p = Processor::new();
+        p.dictionary = Some((dictionary.tail(64 << 10).to_vec(), dictionary.id()));
+        p
+    }
+
+    /// Resets this decoder back to its initial state, to decode another
+    /// stream.
     pub fn reset(&mut self) {
-        self.header = false;
-        self.eof = false;
+        self.state = State::Header;
+        self.temp.truncate(0);
         self.start = 0;
         self.end = 0;
+        self.content.reset();
     }
 
-    fn read_header(&mut self) -> io::Result<()> {
+    /// Tests whether the end of this LZ4 stream has been reached
+    pub fn eof(&self) -> bool {
+        self.state == State::End && self.start == self.end
+    }
+
+    fn read_header(&mut self) -> io::Result<State> {
         // Make sure the magic number is what's expected.
-        if try!(self.r.read_u32::<LittleEndian>()) != MAGIC {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, ""))
+        if read_le(&self.temp[..4]) as u32 != MAGIC {
+            return corrupt("invalid lz4 magic")
         }
 
-        let mut bits = [0; 3];
-        try!(self.r.read(&mut bits[..2]));
-        let flg = bits[0];
-        let bd = bits[1];
+        let flg = self.temp[4];
+        let bd = self.temp[5];
+        self.flags = [flg, bd];
 
         // bits 7/6, the version number. Right now this must be 1
         if (flg >> 6) != 0b01 {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, ""))
+            return unsupported("unsupported lz4 version")
         }
         // bit 5 is the "block independence", don't care about this yet
         // bit 4 is whether blocks have checksums or not
@@ -383,130 +428,279 @@ impl<R: Read + Sized> Decoder<R> {
         // bit 0 is whether there is a preset dictionary
         let preset_dictionary = (flg & 0x01) != 0;
 
-        static MAX_SIZES: [usize; 8] =
-            [0, 0, 0, 0, // all N/A
-             64 << 10,   // 64KB
-             256 << 10,  // 256 KB
-             1 << 20,    // 1MB
-             4 << 20];   // 4MB
-
         // bit 7 is reserved
         // bits 6-4 are the maximum block size
         let max_block_size = MAX_SIZES[(bd >> 4) as usize & 0x7];
         // bits 3-0 are reserved
 
-        // read off other portions of the stream
-        let size = if stream_size {
-            Some(try!(self.r.read_u64::<LittleEndian>()))
-        } else {
-            None
-        };
-        assert!(!preset_dictionary, "preset dictionaries not supported yet");
+        if preset_dictionary && self.dictionary.is_none() {
+            return unsupported("lz4 stream requires a dictionary")
+        }
 
         debug!("blk: {}", self.blk_checksum);
         debug!("stream: {}", self.stream_checksum);
         debug!("max size: {}", max_block_size);
-        debug!("stream size: {:?}", size);
 
         self.max_block_size = max_block_size;
 
-        // XXX: implement checksums
-        let cksum = try!(self.r.read_u8());
-        debug!("ignoring header checksum: {}", cksum);
-        return Ok(());
-    }
-
-    fn decode_block(&mut self) -> io::Result<bool> {
-        match try!(self.r.read_u32::<LittleEndian>()) {
-            // final block, we're done here
-            0 => return Ok(false),
-
-            // raw block to read
-            n if n & 0x80000000 != 0 => {
-                let amt = (n & 0x7fffffff) as usize;
-                self.output.truncate(0);
-                self.output.reserve(amt);
-                try!(self.r.push_exactly(amt as u64, &mut self.output));
-                self.start = 0;
-                self.end = amt;
-            }
+        // the stream size and the dictionary identifier, if any, and the
+        // header checksum follow
+        let len = 1 + if stream_size { 8 } else { 0 } + if preset_dictionary { 4 } else { 0 };
+        Ok(State::Descriptor(len))
+    }
 
-            // actual block to decompress
-            n => {
-                let n = n as usize;
-                self.temp.truncate(0);
-                self.temp.reserve(n);
-                try!(self.r.push_exactly(n as u64, &mut self.temp));
-
-                let target = cmp::min(self.max_block_size, 4 * n / 3);
-                self.output.truncate(0);
-                self.output.reserve(target);
-                let mut decoder = BlockDecoder {
-                    input: &self.temp[..n],
-                    output: &mut self.output,
-                    cur: 0,
-                    start: 0,
-                    end: 0,
-                };
-                self.start = 0;
-                self.end = decoder.decode();
+    fn read_descriptor(&mut self) -> io::Result<()> {
+        // read off other portions of the stream
+        let len = self.temp.len();
+        if len >= 9 {
+            debug!("stream size: {}", read_le(&self.temp[..8]));
+        }
+        if len == 5 || len == 13 {
+            let id = read_le(&self.temp[(len - 5)..(len - 1)]) as u32;
+            match self.dictionary {
+                Some((_, expected)) if expected != 0 && expected != id => {
+                    return corrupt("lz4 stream of another dictionary")
+                }
+                _ => (),
             }
         }
 
+        let mut descriptor = self.flags.to_vec();
+        descriptor.extend_from_slice(&self.temp[..(len - 1)]);
+        if header_checksum(&descriptor) != self.temp[len - 1] {
+            return corrupt("lz4 header checksum mismatch")
+        }
+        self.content.reset();
+        Ok(())
+    }
+
+    fn decode_block(&mut self, raw: bool) -> io::Result<()> {
         if self.blk_checksum {
-            let cksum = try!(self.r.read_u32::<LittleEndian>());
-            debug!("ignoring block checksum {}", cksum);
+            self.block_sum = xxh32(&self.temp);
+        }
+        try!(self.decode_data(raw));
+        if self.stream_checksum {
+            self.content.feed(&self.output[self.start..self.end]);
+        }
+        Ok(())
+    }
+
+    fn decode_data(&mut self, raw: bool) -> io::Result<()> {
+        self.output.truncate(0);
+        self.start = 0;
+        if raw {
+            self.output.extend_from_slice(&self.temp);
+            self.end = self.output.len();
+            return Ok(())
+        }
+
+        // the matches of the block may reach into the dictionary
+        if let Some((ref content, _)) = self.dictionary {
+            self.output.extend_from_slice(content);
+        }
+        let prefix = self.output.len();
+        let n = self.temp.len();
+        let target = cmp::min(self.max_block_size, 4 * n / 3);
+        self.output.reserve(target);
+        let mut decoder = BlockDecoder {
+            input: &self.temp[..n],
+            output: &mut self.output,
+            cur: 0,
+            end: prefix,
+            limit: prefix + self.max_block_size,
+        };
+        self.end = try!(decoder.decode());
+        self.start = prefix;
+        Ok(())
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.output[..self.end], &mut self.start, output, &mut produced) {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+
+            let len = match self.state {
+                State::Header => 6,
+                State::Descriptor(len) => len,
+                State::Size | State::BlockChecksum | State::StreamChecksum => 4,
+                State::Block(len, _) => len,
+                State::End => return Ok((pos, produced, Status::Done)),
+            };
+            if !gather(&mut self.temp, input, &mut pos, len) {
+                return Ok((pos, produced, Status::NeedInput))
+            }
+
+            self.state = match self.state {
+                State::Header => try!(self.read_header()),
+                State::Descriptor(_) => {
+                    try!(self.read_descriptor());
+                    State::Size
+                }
+                State::Size => match read_le(&self.temp) as u32 {
+                    // final block, we're done here
+                    0 if self.stream_checksum => State::StreamChecksum,
+                    0 => State::End,
+                    n if (n & 0x7fffffff) as usize > self.max_block_size => {
+                        return corrupt("lz4 block larger than the maximum size")
+                    }
+                    // raw block to read
+                    n if n & 0x80000000 != 0 => State::Block((n & 0x7fffffff) as usize, true),
+                    // actual block to decompress
+                    n => State::Block(n as usize, false),
+                },
+                State::Block(_, raw) => {
+                    try!(self.decode_block(raw));
+                    if self.blk_checksum { State::BlockChecksum } else { State::Size }
+                }
+                State::BlockChecksum => {
+                    if read_le(&self.temp) as u32 != self.block_sum {
+                        return corrupt("lz4 block checksum mismatch")
+                    }
+                    State::Size
+                }
+                State::StreamChecksum => {
+                    if read_le(&self.temp) as u32 != self.content.result() {
+                        return corrupt("lz4 content checksum mismatch")
+                    }
+                    State::End
+                }
+                State::End => State::End,
+            };
+            self.temp.truncate(0);
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.state != State::End {
+            return truncated("truncated lz4 stream")
+        }
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a stream of LZ4 blocks. This wraps an
+/// internal reader which is read from when this decoder's read method is
+/// called.
+#[cfg(feature="std")]
+pub struct Decoder<R> {
+    /// The internally wrapped reader. This is exposed so it may be moved out
+    /// of. Note that the data read ahead of the output is lost with it, and
+    /// that if data is read from the reader while decoding is in progress the
+    /// output stream will get corrupted.
+    pub r: R,
+
+    p: Processor,
+    pump: Pump,
+}
+
+#[cfg(feature="std")]
+impl<R: Read + Sized> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream. The
+    /// inner stream can be re-acquired by moving out of the `r` field of this
+    /// structure.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r: r,
+            p: Processor::new(),
+            pump: Pump::new(),
+        }
+    }
+
+    /// Creates a new decoder of the streams compressed with the given
+    /// dictionary, reading data from the given stream
+    #[cfg(feature="dictionary")]
+    pub fn with_dictionary(r: R, dictionary: &Dictionary) -> Decoder<R> {
+        Decoder {
+            r: r,
+            p: Processor::with_dictionary(dictionary),
+            pump: Pump::new(),
         }
-        return Ok(true);
+    }
+
+    /// Resets this decoder back to its initial state. Note that the underlying
+    /// stream is not seeked on or has any alterations performed on it, while
+    /// the data read ahead from it is dropped.
+    pub fn reset(&mut self) {
+        self.p.reset();
+        self.pump.reset();
     }
 
     /// Tests whether the end of this LZ4 stream has been reached
-    pub fn eof(&mut self) -> bool { self.eof }
+    pub fn eof(&mut self) -> bool { self.pump.is_done() }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes, as a guard against data which expands beyond
+    /// reason
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.pump.set_max_output_size(size);
+    }
 }
 
+#[cfg(feature="std")]
 impl<R: Read> Read for Decoder<R> {
     fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
-        if self.eof { return Ok(0) }
-        if !self.header {
-            try!(self.read_header());
-            self.header = true;
-        }
-        let mut amt = dst.len();
-        let len = amt;
-
-        while amt > 0 {
-            if self.start == self.end {
-                let keep_going = try!(self.decode_block());
-                if !keep_going {
-                    self.eof = true;
-                    break;
-                }
-            }
-            let n = cmp::min(amt, self.end - self.start);
-            unsafe { copy_nonoverlapping(
-                &self.output[self.start],
-                &mut dst[len - amt],
-                n
-            )};
-            self.start += n;
-            amt -= n;
-        }
+        self.pump.read(&mut self.r, &mut self.p, dst)
+    }
+}
+
+/// The settings of an LZ4 encoder, which builds it once they are checked
+#[cfg(feature="std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lz4Options {
+    block_size: usize,
+}
+
+#[cfg(feature="std")]
+impl Default for Lz4Options {
+    fn default() -> Lz4Options {
+        Lz4Options { block_size: 256 << 10 }
+    }
+}
+
+#[cfg(feature="std")]
+impl Lz4Options {
+    /// Creates the default settings, with blocks of 256 kB
+    pub fn new() -> Lz4Options {
+        Lz4Options::default()
+    }
+
+    /// Sets the size of the blocks: 64 kB, 256 kB, 1 MB or 4 MB
+    pub fn block_size(mut self, size: usize) -> Lz4Options {
+        self.block_size = size;
+        self
+    }
 
-        Ok(len - amt)
+    /// Creates an encoder with these settings, writing to `w`, or fails if
+    /// they are invalid
+    pub fn encoder<W: Write>(&self, w: W) -> io::Result<Encoder<W>> {
+        if !MAX_SIZES[4..].contains(&self.block_size) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid lz4 block size"))
+        }
+        let mut e = Encoder::new(w);
+        e.limit = self.block_size;
+        Ok(e)
     }
 }
 
 /// This structure is used to compress a stream of bytes using the LZ4
 /// compression algorithm. This is a wrapper around an internal writer which
 /// bytes will be written to.
+#[cfg(feature="std")]
 pub struct Encoder<W> {
     w: W,
     buf: Vec<u8>,
     tmp: Vec<u8>,
     wrote_header: bool,
     limit: usize,
+    dictionary_id: u32,
 }
 
+#[cfg(feature="std")]
 impl<W: Write> Encoder<W> {
     /// Creates a new encoder which will have its output written to the given
     /// output stream. The output stream can be re-acquired by calling
@@ -521,17 +715,29 @@ impl<W: Write> Encoder<W> {
             buf: Vec::with_capacity(1024),
             tmp: Vec::new(),
             limit: 256 * 1024,
+            dictionary_id: 0,
         }
     }
 
+    /// Creates a new encoder of a stream for the decoders holding the given
+    /// dictionary, of which the identifier, if any, is written in the header.
+    /// As blocks are only stored yet, the content of the dictionary is
+    /// unused.
+    #[cfg(feature="dictionary")]
+    pub fn with_dictionary(w: W, dictionary: &Dictionary) -> Encoder<W> {
+        let mut e = Encoder::new(w);
+        e.dictionary_id = dictionary.id();
+        e
+    }
+
     fn encode_block(&mut self) -> io::Result<()> {
         self.tmp.truncate(0);
         if self.compress() {
             try!(self.w.write_u32::<LittleEndian>(self.tmp.len() as u32));
-            try!(self.w.write(&self.tmp));
+            try!(self.w.write_all(&self.tmp));
         } else {
             try!(self.w.write_u32::<LittleEndian>((self.buf.len() as u32) | 0x80000000));
-            try!(self.w.write(&self.buf));
+            try!(self.w.write_all(&self.buf));
         }
         self.buf.truncate(0);
         Ok(())
@@ -545,33 +751,59 @@ impl<W: Write> Encoder<W> {
     /// with. The stream is finished up (final bytes are written), and then the
     /// wrapped writer is returned.
     pub fn finish(mut self) -> (W, io::Result<()>) {
-        let mut result = self.flush();
+        let result = self.end();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`,
+    /// keeping the buffers. The previous writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.end();
+        self.buf.truncate(0);
+        self.tmp.truncate(0);
+        self.wrote_header = false;
+        (mem::replace(&mut self.w, w), result)
+    }
 
-        for _ in 0..2 {
-            let tmp = self.w.write_u32::<LittleEndian>(0)
-                            .map_err(byteorder_err_to_io);
+    fn end(&mut self) -> io::Result<()> {
+        // an empty stream still has its header
+        try!(self.write_header());
+        let result = self.flush();
+        let tmp = self.w.write_u32::<LittleEndian>(0)
+                        .map_err(byteorder_err_to_io);
+        result.and_then(|_| tmp)
+    }
 
-            result = result.and_then(|_| tmp);
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.wrote_header {
+            return Ok(())
         }
-
-        (self.w, result)
+        try!(self.w.write_u32::<LittleEndian>(MAGIC));
+        // version 01, turn on block independence, but turn off
+        // everything else (we have no checksums right now), but the
+        // identifier of the dictionary
+        let preset_dictionary = self.dictionary_id != 0;
+        let mut descriptor = vec![0b01_100000 | preset_dictionary as u8];
+        // the code of the maximum block size
+        let code = MAX_SIZES.iter().position(|&size| size == self.limit).unwrap();
+        descriptor.push((code as u8) << 4);
+        if preset_dictionary {
+            try!(descriptor.write_u32::<LittleEndian>(self.dictionary_id));
+        }
+        let checksum = header_checksum(&descriptor);
+        descriptor.push(checksum);
+        try!(self.w.write_all(&descriptor));
+        self.wrote_header = true;
+        Ok(())
     }
 }
 
+#[cfg(feature="std")]
 impl<W: Write> Write for Encoder<W> {
     fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
-        if !self.wrote_header {
-            try!(self.w.write_u32::<LittleEndian>(MAGIC));
-            // version 01, turn on block independence, but turn off
-            // everything else (we have no checksums right now).
-            try!(self.w.write_u8(0b01_100000));
-            // Maximum block size is 256KB
-            try!(self.w.write_u8(0b0_101_0000));
-            // XXX: this checksum is just plain wrong.
-            try!(self.w.write_u8(0));
-            self.wrote_header = true;
-        }
+        try!(self.write_header());
 
+        let len = buf.len();
         while buf.len() > 0 {
             let amt = cmp::min(self.limit - self.buf.len(), buf.len());
             self.buf.extend(buf[..amt].iter().map(|b| *b));
@@ -582,7 +814,7 @@ impl<W: Write> Write for Encoder<W> {
             buf = &buf[amt..];
         }
 
-        Ok(buf.len())
+        Ok(len)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -595,19 +827,69 @@ impl<W: Write> Write for Encoder<W> {
 
 
 /// Decodes pure LZ4 block into output. Returns count of bytes
-/// processed.
-pub fn decode_block(input: &[u8], output: &mut Vec<u8>) -> usize {
+/// processed, or an error if the block is corrupt.
+pub fn decode_block(input: &[u8], output: &mut Vec<u8>) -> io::Result<usize> {
     let mut b = BlockDecoder {
         input: input,
         output: output,
         cur: 0,
-        start: 0,
-        end: 0
+        end: 0,
+        limit: usize::MAX,
     };
     b.decode()
 }
 
 
+/// Returns the data of the frame held by `input` if it is a single block
+/// stored as is, without a dictionary
+fn stored_block(input: &[u8]) -> Option<&[u8]> {
+    if input.len() < 7 || read_le(&input[..4]) as u32 != MAGIC {
+        return None
+    }
+    let flg = input[4];
+    if flg >> 6 != 0b01 || flg & 0x01 != 0 {
+        return None
+    }
+    let block_checksum = if flg & 0x10 != 0 { 4 } else { 0 };
+    let stream_checksum = if flg & 0x04 != 0 { 4 } else { 0 };
+    // the magic, the flags and the header checksum, and the stream size
+    let start = 7 + if flg & 0x08 != 0 { 8 } else { 0 };
+    if input.len() < start + 4 {
+        return None
+    }
+    if header_checksum(&input[4..(start - 1)]) != input[start - 1] {
+        return None
+    }
+    let size = read_le(&input[start..(start + 4)]) as u32;
+    if size & 0x80000000 == 0 {
+        return None
+    }
+    let len = (size & 0x7fffffff) as usize;
+    let end = start + 4 + len;
+    if input.len() < end + block_checksum + 4 + stream_checksum ||
+       read_le(&input[(end + block_checksum)..(end + block_checksum + 4)]) != 0 {
+        return None
+    }
+    // the checksums of the block and of the content are both that of the data
+    let data = &input[(start + 4)..end];
+    let sums = [(block_checksum, end), (stream_checksum, end + block_checksum + 4)];
+    if sums.iter().any(|&(len, at)| len > 0 && read_le(&input[at..(at + 4)]) as u32 != xxh32(data)) {
+        return None
+    }
+    Some(data)
+}
+
+/// Decodes the LZ4 frame held by `input`. When it is a single block stored
+/// as is, as the encoder writes for the data it can't compress, its data is
+/// borrowed from `input` rather than copied. The input after the end of the
+/// frame is ignored.
+pub fn decode_frame(input: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+    match stored_block(input) {
+        Some(data) => Ok(Cow::Borrowed(data)),
+        None => process::decode_to_vec(&mut Processor::new(), input).map(Cow::Owned),
+    }
+}
+
 /// Encodes input into pure LZ4 block. Return count of bytes
 /// processed.
 pub fn encode_block(input: &[u8], output: &mut Vec<u8>) -> usize {
@@ -641,6 +923,19 @@ mod test {
         assert!(&buf[..] == output);
     }
 
+    #[test]
+    fn skip() {
+        use Skip;
+        let reference = include_bytes!("data/test.txt");
+        let mut d = Decoder::new(&include_bytes!("data/test.lz4.1")[..]);
+        assert_eq!(d.skip(1000).unwrap(), 1000);
+        let mut buf = [0u8; 100];
+        d.read_exact(&mut buf).unwrap();
+        assert!(&buf[..] == &reference[1000..1100]);
+        assert_eq!(d.skip(1 << 20).unwrap(), reference.len() as u64 - 1100);
+        assert_eq!(d.skip(1).unwrap(), 0);
+    }
+
     #[test]
     fn decode() {
         let reference = include_bytes!("data/test.txt");
@@ -655,6 +950,50 @@ mod test {
         test_decode(include_bytes!("data/test.lz4.9"), reference);
     }
 
+    #[test]
+    fn frame() {
+        use std::borrow::Cow;
+        let data = include_bytes!("data/test.txt");
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(&data[..1000]).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        match super::decode_frame(&encoded).unwrap() {
+            Cow::Borrowed(decoded) => assert!(decoded == &data[..1000]),
+            Cow::Owned(..) => panic!("stored block copied"),
+        }
+
+        let decoded = super::decode_frame(include_bytes!("data/test.lz4.1")).unwrap();
+        assert!(match decoded { Cow::Owned(..) => true, _ => false });
+        assert!(&decoded[..] == &data[..]);
+        assert!(super::decode_frame(&encoded[..(encoded.len() - 10)]).is_err());
+    }
+
+    #[test]
+    fn checksums() {
+        // the header of the frames of the reference encoder
+        let reference = include_bytes!("data/test.lz4.1");
+        assert_eq!(super::header_checksum(&reference[4..6]), reference[6]);
+        let mut e = super::Lz4Options::new().block_size(64 << 10).encoder(Vec::new()).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        assert_eq!(&encoded[..], &[0x04, 0x22, 0x4d, 0x18, 0x60, 0x40, 0x82, 0, 0, 0, 0][..]);
+        e = super::Lz4Options::new().block_size(64 << 10).encoder(Vec::new()).unwrap();
+        e.write_all(b"test").unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        assert_eq!(&encoded[7..], &[4, 0, 0, 0x80, b't', b'e', b's', b't', 0, 0, 0, 0][..]);
+
+        // the header and the content are checked
+        test_decode(&encoded, b"test");
+        for &pos in [6, reference.len() - 1, reference.len() - 50].iter() {
+            let mut input = reference.to_vec();
+            input[pos] ^= 1;
+            assert!(Decoder::new(&input[..]).read_to_end(&mut Vec::new()).is_err());
+            assert!(super::decode_frame(&input).is_err());
+        }
+    }
+
     #[test]
     fn raw_encode_block() {
         let data = include_bytes!("data/test.txt");
@@ -663,11 +1002,49 @@ mod test {
         super::encode_block(data, &mut encoded);
         let mut decoded = Vec::new();
 
-        super::decode_block(&encoded[..], &mut decoded);
+        super::decode_block(&encoded[..], &mut decoded).unwrap();
 
         assert_eq!(&data[..], &decoded[..]);
     }
 
+    #[test]
+    fn bad_offsets() {
+        // a literal then a match of offset 0, then of an offset past the output
+        assert!(super::decode_block(&[0x10, b'a', 0x00, 0x00, 0x00], &mut Vec::new()).is_err());
+        assert!(super::decode_block(&[0x10, b'a', 0x02, 0x00, 0x00], &mut Vec::new()).is_err());
+        let mut decoded = Vec::new();
+        super::decode_block(&[0x10, b'a', 0x01, 0x00, 0x00], &mut decoded).unwrap();
+        assert_eq!(&decoded[..], b"aaaaa");
+    }
+
+    #[test]
+    fn corrupt_blocks() {
+        let data = include_bytes!("data/test.txt");
+        let mut encoded = Vec::new();
+        super::encode_block(data, &mut encoded);
+        // truncated sequences and literals, and mutated bytes, fail or decode
+        // to garbage but don't panic
+        for len in 0..200 {
+            let _ = super::decode_block(&encoded[..len], &mut Vec::new());
+        }
+        assert!(super::decode_block(&[0xf0], &mut Vec::new()).is_err());
+        assert!(super::decode_block(&[0x50, b'a', b'b'], &mut Vec::new()).is_err());
+        for i in 0..300 {
+            let mut block = encoded.clone();
+            let pos = (i * 7919) % block.len();
+            block[pos] ^= 1 << (i % 8);
+            let _ = super::decode_block(&block, &mut Vec::new());
+        }
+
+        let frame = include_bytes!("data/test.lz4.1");
+        for i in 0..300 {
+            let mut input = frame.to_vec();
+            let pos = (i * 7919) % input.len();
+            input[pos] ^= 1 << (i % 8);
+            let _ = Decoder::new(&input[..]).read_to_end(&mut Vec::new());
+        }
+    }
+
     #[test]
     fn one_byte_at_a_time() {
         let input = include_bytes!("data/test.lz4.1");
@@ -722,6 +1099,49 @@ mod test {
         roundtrip(include_bytes!("data/test.txt"));
     }
 
+    #[test]
+    fn options() {
+        let data = include_bytes!("data/test.txt");
+        let mut e = super::Lz4Options::new().block_size(64 << 10).encoder(Vec::new()).unwrap();
+        e.write_all(data).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        // the block size code follows the magic and the flags
+        assert_eq!(encoded[5] >> 4, 4);
+
+        let mut d = Decoder::new(BufReader::new(&encoded[..]));
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], &data[..]);
+
+        assert!(super::Lz4Options::new().block_size(1000).encoder(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn reset() {
+        let data = include_bytes!("data/test.txt");
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(&data[..1000]).unwrap();
+        let (first, err) = e.reset(Vec::new());
+        err.unwrap();
+        e.write_all(&data[1000..]).unwrap();
+        let (second, err) = e.finish();
+        err.unwrap();
+        let mut fresh = Encoder::new(Vec::new());
+        fresh.write_all(&data[1000..]).unwrap();
+        assert!(second == fresh.finish().0);
+
+        let mut d = Decoder::new(&first[..]);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(&decoded[..] == &data[..1000]);
+        d.r = &second[..];
+        d.reset();
+        decoded.truncate(0);
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(&decoded[..] == &data[1000..]);
+    }
+
     #[cfg(feature="unstable")]
     #[bench]
     fn decompress_speed(bh: &mut test::Bencher) {
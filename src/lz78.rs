@@ -0,0 +1,550 @@
+/*!
+
+LZ78 Decompression and Compression. Requires `lz78` feature, enabled by default
+
+This module contains an implementation of the LZ78 algorithm, which parses the
+data into phrases, each of them an earlier phrase followed by a byte, and
+writes each as the code of the earlier phrase and the byte. The codes take as
+many bits as the largest code of the dictionary, code 0 being the empty
+phrase, and are packed with their byte from the highest bit. Once the
+dictionary holds all the codes of the maximum width given, it is emptied,
+starting again from the next phrase. The data ends with the stream, after the
+padding of the last byte. Whole buffers are handled by `decode_block` and
+`encode_block`, while streams are exposed as standard `Reader` and `Writer`
+interfaces wrapping an underlying stream.
+
+# Example
+
+```rust,ignore
+use compress::lz78;
+use std::fs::File;
+use std::path::Path;
+use std::io::Read;
+
+let stream = File::open(&Path::new("path/to/file.lz78")).unwrap();
+let mut decompressed = Vec::new();
+lz78::Decoder::new(stream, 16).read_to_end(&mut decompressed);
+```
+
+# Related links
+
+* https://en.wikipedia.org/wiki/LZ77_and_LZ78 - the algorithm
+* https://doi.org/10.1109/TIT.1978.1055934 - "Compression of individual
+  sequences via variable-rate coding", the original paper
+
+*/
+
+use std::io::{self, Read, Write};
+use std::mem;
+
+use error::{corrupt, truncated};
+use process::{drain, Process, Reader, Status};
+
+/// Size of the output buffered by the streams
+const BUF_SIZE: usize = 1 << 14;
+const MIN_BITS: u8 = 8;
+const MAX_BITS: u8 = 20;
+
+fn check(max_bits: u8) {
+    assert!(max_bits >= MIN_BITS && max_bits <= MAX_BITS, "invalid lz78 code size {}", max_bits);
+}
+
+/// Number of bits of the codes up to `size`
+fn width(size: u32) -> u8 {
+    (32 - size.leading_zeros()) as u8
+}
+
+/// A phrase of the dictionary, as the code of the phrase without the last
+/// byte
+#[derive(Clone, Copy)]
+struct Entry {
+    prefix: u32,
+    last: u8,
+    len: u32,
+}
+
+/// The decoding state, shared by the buffer and stream interfaces
+struct Unpacker {
+    /// Number of codes of the full dictionary
+    limit: u32,
+    /// The phrases, from the empty one
+    table: Vec<Entry>,
+    bits: u64,
+    count: u8,
+}
+
+impl Unpacker {
+    fn new(max_bits: u8) -> Unpacker {
+        check(max_bits);
+        Unpacker {
+            limit: 1 << max_bits,
+            table: vec![Entry { prefix: 0, last: 0, len: 0 }],
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    /// Decodes the next byte of the stream, appending the phrases it
+    /// completes to `out`
+    fn push(&mut self, byte: u8, out: &mut Vec<u8>) -> io::Result<()> {
+        self.bits = (self.bits << 8) | byte as u64;
+        self.count += 8;
+        loop {
+            let size = self.table.len() as u32 - 1;
+            let n = width(size) + 8;
+            if self.count < n {
+                return Ok(())
+            }
+            self.count -= n;
+            let item = ((self.bits >> self.count) & ((1 << n) - 1)) as u32;
+            try!(self.phrase(item, out));
+        }
+    }
+
+    /// Appends the phrase of the code and byte of `item` to `out`
+    fn phrase(&mut self, item: u32, out: &mut Vec<u8>) -> io::Result<()> {
+        let size = self.table.len() as u32 - 1;
+        let (code, last) = (item >> 8, item as u8);
+        if code > size {
+            return corrupt("invalid lz78 code")
+        }
+
+        let prefix = self.table[code as usize];
+        let start = out.len();
+        out.resize(start + prefix.len as usize + 1, 0);
+        out[start + prefix.len as usize] = last;
+        let mut code = code as usize;
+        for slot in out[start..(start + prefix.len as usize)].iter_mut().rev() {
+            let entry = self.table[code];
+            *slot = entry.last;
+            code = entry.prefix as usize;
+        }
+
+        if size + 1 < self.limit {
+            self.table.push(Entry { prefix: item >> 8, last: last, len: prefix.len + 1 });
+        } else {
+            self.table.truncate(1);
+        }
+        Ok(())
+    }
+
+    /// Starts another stream, keeping the dictionary
+    fn reset(&mut self) {
+        self.table.truncate(1);
+        self.bits = 0;
+        self.count = 0;
+    }
+
+    /// Checks that the data may end here
+    fn finish(&self) -> io::Result<()> {
+        // a phrase takes at least a byte, more than the padding
+        if self.count >= 8 {
+            return truncated("truncated lz78 data")
+        }
+        Ok(())
+    }
+}
+
+/// Decodes LZ78 data of codes up to `max_bits` wide, from 8 to 20, appending
+/// it to `output`
+pub fn decode_block(input: &[u8], max_bits: u8, output: &mut Vec<u8>) -> io::Result<()> {
+    let mut unpacker = Unpacker::new(max_bits);
+    for &byte in input.iter() {
+        try!(unpacker.push(byte, output));
+    }
+    unpacker.finish()
+}
+
+/// The encoding state, shared by the buffer and stream interfaces
+struct Packer {
+    limit: u32,
+    /// Open addressing table of the phrases, from their prefix and last byte
+    /// to their code, keys being offset by one to keep zero for empty slots
+    keys: Vec<u32>,
+    values: Vec<u32>,
+    hash_shift: u32,
+    /// The prefix and last byte of the phrases, by code, from the empty one
+    phrases: Vec<(u32, u8)>,
+    /// The code of the current match
+    prefix: u32,
+    bits: u64,
+    count: u8,
+}
+
+impl Packer {
+    fn new(max_bits: u8) -> Packer {
+        check(max_bits);
+        let size = 2 << max_bits;
+        Packer {
+            limit: 1 << max_bits,
+            keys: vec![0; size],
+            values: vec![0; size],
+            hash_shift: 32 - size.trailing_zeros(),
+            phrases: vec![(0, 0)],
+            prefix: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn put_bits(&mut self, value: u32, n: u8, out: &mut Vec<u8>) {
+        self.bits = (self.bits << n) | value as u64;
+        self.count += n;
+        while self.count >= 8 {
+            self.count -= 8;
+            out.push((self.bits >> self.count) as u8);
+        }
+    }
+
+    /// Writes a phrase, adding it to the dictionary, or emptying it when full
+    fn put(&mut self, code: u32, last: u8, slot: usize, out: &mut Vec<u8>) {
+        let size = self.phrases.len() as u32 - 1;
+        self.put_bits(code << 8 | last as u32, width(size) + 8, out);
+        if size + 1 < self.limit {
+            self.keys[slot] = (code << 8 | last as u32) + 1;
+            self.values[slot] = size + 1;
+            self.phrases.push((code, last));
+        } else {
+            for key in self.keys.iter_mut() {
+                *key = 0;
+            }
+            self.phrases.truncate(1);
+        }
+    }
+
+    /// Starts another stream, keeping the dictionary
+    fn reset(&mut self) {
+        for key in self.keys.iter_mut() {
+            *key = 0;
+        }
+        self.phrases.truncate(1);
+        self.prefix = 0;
+        self.bits = 0;
+        self.count = 0;
+    }
+
+    /// Returns the slot of the key, either holding it or empty
+    fn slot(&self, key: u32) -> usize {
+        let mask = self.keys.len() - 1;
+        let mut i = (key.wrapping_mul(2654435761) >> self.hash_shift) as usize;
+        while self.keys[i] != 0 && self.keys[i] != key {
+            i = (i + 1) & mask;
+        }
+        i
+    }
+
+    fn push(&mut self, byte: u8, out: &mut Vec<u8>) {
+        let key = (self.prefix << 8 | byte as u32) + 1;
+        let slot = self.slot(key);
+        if self.keys[slot] == key {
+            self.prefix = self.values[slot];
+            return
+        }
+        let prefix = self.prefix;
+        self.put(prefix, byte, slot, out);
+        self.prefix = 0;
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        // the current match is a phrase of the dictionary, written again
+        if self.prefix != 0 {
+            let (code, last) = self.phrases[self.prefix as usize];
+            let key = (code << 8 | last as u32) + 1;
+            let slot = self.slot(key);
+            self.put(code, last, slot, out);
+            self.prefix = 0;
+        }
+        if self.count > 0 {
+            let n = 8 - self.count;
+            self.put_bits(0, n, out);
+        }
+    }
+}
+
+/// Encodes the input with codes up to `max_bits` wide, from 8 to 20,
+/// appending the data to `output`
+pub fn encode_block(input: &[u8], max_bits: u8, output: &mut Vec<u8>) {
+    let mut packer = Packer::new(max_bits);
+    for &byte in input.iter() {
+        packer.push(byte, output);
+    }
+    packer.finish(output);
+}
+
+/// This structure is used to decode a stream of LZ78 data, fed with slices
+/// of it. The stream ends with its input.
+pub struct Processor {
+    unpacker: Unpacker,
+    output: Vec<u8>,
+    start: usize,
+    eof: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder of codes up to `max_bits` wide, from 8 to 20
+    pub fn new(max_bits: u8) -> Processor {
+        Processor {
+            unpacker: Unpacker::new(max_bits),
+            output: Vec::new(),
+            start: 0,
+            eof: false,
+        }
+    }
+
+    /// Starts decoding a new stream with the same code size, keeping the
+    /// dictionary and the buffers
+    pub fn reset(&mut self) {
+        self.unpacker.reset();
+        self.output.truncate(0);
+        self.start = 0;
+        self.eof = false;
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.output, &mut self.start, output, &mut produced) {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            if pos == input.len() {
+                let status = if self.eof { Status::Done } else { Status::NeedInput };
+                return Ok((pos, produced, status))
+            }
+            self.output.truncate(0);
+            self.start = 0;
+            while pos < input.len() && self.output.len() < BUF_SIZE {
+                try!(self.unpacker.push(input[pos], &mut self.output));
+                pos += 1;
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        try!(self.unpacker.finish());
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a stream of LZ78 data, which ends with the
+/// underlying stream. The wrapped stream can be re-acquired through the
+/// unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder of codes up to `max_bits` wide, from 8 to 20,
+    /// which will read data from the given stream
+    pub fn new(r: R, max_bits: u8) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new(max_bits)),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Returns whether the end of the data has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.is_done()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+/// This structure is used to compress a stream of bytes into LZ78 data. This
+/// is a wrapper around an internal writer which bytes will be written to.
+pub struct Encoder<W> {
+    w: W,
+    packer: Packer,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder of codes up to `max_bits` wide, from 8 to 20,
+    /// which will have its output written to the given output stream. The
+    /// output stream can be re-acquired by calling `finish()`
+    pub fn new(w: W, max_bits: u8) -> Encoder<W> {
+        Encoder {
+            w: w,
+            packer: Packer::new(max_bits),
+            buf: Vec::with_capacity(BUF_SIZE),
+        }
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        self.packer.finish(&mut self.buf);
+        let result = self.flush();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`
+    /// with the same code size, keeping the dictionary and the buffers. The
+    /// previous writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        self.packer.finish(&mut self.buf);
+        let result = self.flush();
+        self.buf.truncate(0);
+        self.packer.reset();
+        (mem::replace(&mut self.w, w), result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf.iter() {
+            self.packer.push(byte, &mut self.buf);
+        }
+        if self.buf.len() >= BUF_SIZE {
+            try!(self.w.write_all(&self.buf));
+            self.buf.truncate(0);
+        }
+        Ok(buf.len())
+    }
+
+    /// Writes the complete bytes of data, the current match and the bits of a
+    /// partial byte remaining for the next writes
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.w.write_all(&self.buf));
+        self.buf.truncate(0);
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing::{noise, push};
+    use super::{Decoder, Encoder, Processor, decode_block, encode_block};
+
+    fn roundtrip(input: &[u8], max_bits: u8) -> usize {
+        let mut encoded = Vec::new();
+        encode_block(input, max_bits, &mut encoded);
+        let mut decoded = Vec::new();
+        decode_block(&encoded, max_bits, &mut decoded).unwrap();
+        assert!(&decoded[..] == input, "{}", max_bits);
+        encoded.len()
+    }
+
+    #[test]
+    fn known_data() {
+        let mut encoded = Vec::new();
+        encode_block(b"abababa", 16, &mut encoded);
+        // the phrases "a", "b", "ab" and "aba", of codes 0, 0, 1 and 3 in 0,
+        // 1, 2 and 2 bits
+        assert_eq!(encoded, [0x61, 0x31, 0x2c, 0x5b, 0x08]);
+        let mut decoded = Vec::new();
+        decode_block(&encoded, 16, &mut decoded).unwrap();
+        assert_eq!(&decoded[..], b"abababa");
+    }
+
+    #[test]
+    fn roundtrips() {
+        let text = include_bytes!("data/test.txt");
+        for max_bits in 8..21 {
+            assert_eq!(roundtrip(b"", max_bits), 0);
+            roundtrip(b"a", max_bits);
+            roundtrip(b"aba", max_bits);
+            assert!(roundtrip(text, max_bits) < text.len());
+            let random = noise(50000, 7);
+            assert!(roundtrip(&random, max_bits) <= random.len() * 3 / 2);
+            let runs: Vec<u8> = (0..100000).map(|i| (i / 1000) as u8).collect();
+            assert!(roundtrip(&runs, max_bits) < runs.len() / 5);
+        }
+        let large = include_bytes!("data/test.large");
+        assert!(roundtrip(large, 16) < large.len() * 2 / 3);
+    }
+
+    #[test]
+    fn streams() {
+        let input = include_bytes!("data/test.large");
+        let input = &input[..300000];
+        for &max_bits in [8, 12, 16].iter() {
+            let mut e = Encoder::new(Vec::new(), max_bits);
+            for chunk in input.chunks(999) {
+                e.write_all(chunk).unwrap();
+            }
+            let (encoded, result) = e.finish();
+            result.unwrap();
+            let mut block = Vec::new();
+            decode_block(&encoded, max_bits, &mut block).unwrap();
+            assert!(&block[..] == input);
+
+            let mut d = Decoder::new(BufReader::new(&encoded[..]), max_bits);
+            let mut out = Vec::new();
+            let mut buf = [0u8; 7];
+            loop {
+                match d.read(&mut buf).unwrap() {
+                    0 => break,
+                    n => out.extend(buf[..n].iter().cloned()),
+                }
+            }
+            assert!(d.eof());
+            assert!(&out[..] == input);
+            assert!(&push(&mut Processor::new(max_bits), &encoded, 777).unwrap()[..] == input);
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let (first, second) = (noise(20000, 7), include_bytes!("data/test.txt"));
+        for &max_bits in [8, 16].iter() {
+            let mut e = Encoder::new(Vec::new(), max_bits);
+            e.write_all(&first).unwrap();
+            let (a, result) = e.reset(Vec::new());
+            result.unwrap();
+            e.write_all(second).unwrap();
+            let (b, result) = e.finish();
+            result.unwrap();
+            let (mut block_a, mut block_b) = (Vec::new(), Vec::new());
+            encode_block(&first, max_bits, &mut block_a);
+            encode_block(second, max_bits, &mut block_b);
+            assert!(a == block_a && b == block_b);
+
+            let mut d = Decoder::new(BufReader::new(&a[..]), max_bits);
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).unwrap();
+            assert!(out == first);
+            d.reset(BufReader::new(&b[..]));
+            out.truncate(0);
+            d.read_to_end(&mut out).unwrap();
+            assert!(&out[..] == &second[..]);
+        }
+    }
+
+    #[test]
+    fn invalid_data() {
+        let mut out = Vec::new();
+        // a code of a phrase not yet in the dictionary
+        assert!(decode_block(&[0x61, 0x31, 0x6c, 0x20], 16, &mut out).is_err());
+        // a phrase cut in its byte
+        assert!(decode_block(&[0x61, 0x31], 16, &mut out).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+/*!
+
+LZF Decompression and Compression. Requires `lzf` feature, enabled by default
+
+This module contains an implementation of the block format of LZF, the small
+and fast LZ77 variant of Marc Lehmann, as used by the RDB dumps of Redis and
+by HDF5. Each item starts with a control byte: below 32 it is followed by as
+many literals plus one, otherwise its top 3 bits hold the length of a match
+minus 2, 7 meaning that a byte with the rest follows, and its low 5 bits the
+high bits of the distance minus one, whose low byte comes last. The blocks
+hold no header, their size being stored aside by the formats using them.
+Whole buffers are handled by `decode_block` and `encode_block`.
+
+# Example
+
+```rust,ignore
+use compress::lzf;
+
+let mut compressed = Vec::new();
+lzf::encode_block(b"hello hello hello", &mut compressed);
+let mut decompressed = Vec::new();
+lzf::decode_block(&compressed, &mut decompressed, 17).unwrap();
+```
+
+# Related links
+
+* http://software.schmorp.de/pkg/liblzf.html - the reference implementation,
+  whose `lzf_d.c` describes the format
+
+*/
+
+use std::cmp;
+use std::io;
+
+use copy::copy_match;
+use error::{corrupt_at, limit_exceeded, truncated};
+use hash;
+
+/// Longest run of literals of an item
+const MAX_LIT: usize = 1 << 5;
+/// Farthest distance of a match
+const MAX_OFF: usize = 1 << 13;
+/// Longest match
+const MAX_REF: usize = (1 << 8) + (1 << 3);
+const MIN_MATCH: usize = 3;
+const HASH_LOG: u32 = 16;
+
+/// Returns the maximum size of the block compressing `size` bytes
+pub fn max_compressed_len(size: usize) -> usize {
+    size + size / MAX_LIT + 1
+}
+
+/// Decodes a LZF block into `output`, producing no more than `limit` bytes.
+/// The whole input is decoded, as the blocks have no end marker.
+pub fn decode_block(input: &[u8], output: &mut Vec<u8>, limit: usize) -> io::Result<()> {
+    let start = output.len();
+    let mut pos = 0;
+    while pos < input.len() {
+        let item = pos;
+        let ctrl = input[pos] as usize;
+        pos += 1;
+        if ctrl < MAX_LIT {
+            let len = ctrl + 1;
+            if len > input.len() - pos {
+                return truncated("truncated lzf literal")
+            }
+            if output.len() - start + len > limit {
+                return limit_exceeded("lzf data exceeds the limit")
+            }
+            output.extend(input[pos..(pos + len)].iter().cloned());
+            pos += len;
+            continue
+        }
+        let mut len = ctrl >> 5;
+        if len == 7 {
+            if pos == input.len() {
+                return truncated("truncated lzf match")
+            }
+            len += input[pos] as usize;
+            pos += 1;
+        }
+        let len = len + 2;
+        if pos == input.len() {
+            return truncated("truncated lzf match")
+        }
+        let dist = ((ctrl & 0x1f) << 8 | input[pos] as usize) + 1;
+        pos += 1;
+        if dist > output.len() - start {
+            return corrupt_at(item as u64, "invalid lzf distance")
+        }
+        if output.len() - start + len > limit {
+            return limit_exceeded("lzf data exceeds the limit")
+        }
+        // the match may overlap the bytes it produces
+        copy_match(output, dist, len);
+    }
+    Ok(())
+}
+
+fn emit_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LIT) {
+        output.push((chunk.len() - 1) as u8);
+        output.extend(chunk.iter().cloned());
+    }
+}
+
+fn emit_match(output: &mut Vec<u8>, dist: usize, len: usize) {
+    debug_assert!(dist <= MAX_OFF && len >= MIN_MATCH && len <= MAX_REF);
+    let (off, len) = (dist - 1, len - 2);
+    if len < 7 {
+        output.push((len << 5 | off >> 8) as u8);
+    } else {
+        output.push((7 << 5 | off >> 8) as u8);
+        output.push((len - 7) as u8);
+    }
+    output.push(off as u8);
+}
+
+#[inline(always)]
+fn hash_at(input: &[u8], pos: usize) -> usize {
+    hash::hash3(&input[pos..], HASH_LOG)
+}
+
+/// Encodes the input into a LZF block, appended to `output`
+pub fn encode_block(input: &[u8], output: &mut Vec<u8>) {
+    output.reserve(max_compressed_len(input.len()));
+    // the last position of each hash, plus one
+    let mut table = vec![0usize; 1 << HASH_LOG];
+    let (mut pos, mut anchor) = (0, 0);
+    while pos + MIN_MATCH <= input.len() {
+        let hash = hash_at(input, pos);
+        let candidate = table[hash];
+        table[hash] = pos + 1;
+        if candidate == 0 || pos - (candidate - 1) > MAX_OFF ||
+                input[(candidate - 1)..(candidate - 1 + MIN_MATCH)] != input[pos..(pos + MIN_MATCH)] {
+            pos += 1;
+            continue
+        }
+        let candidate = candidate - 1;
+        let max = cmp::min(MAX_REF, input.len() - pos);
+        let len = MIN_MATCH + hash::match_len(&input[(candidate + MIN_MATCH)..(candidate + max)],
+                                              &input[(pos + MIN_MATCH)..(pos + max)]);
+        emit_literals(output, &input[anchor..pos]);
+        emit_match(output, pos - candidate, len);
+        for p in (pos + 1)..cmp::min(pos + len, input.len() + 1 - MIN_MATCH) {
+            table[hash_at(input, p)] = p + 1;
+        }
+        pos += len;
+        anchor = pos;
+    }
+    emit_literals(output, &input[anchor..]);
+}
+
+#[cfg(test)]
+mod test {
+    use error::Error;
+    use testing::noise;
+    use super::{decode_block, encode_block, max_compressed_len};
+
+    fn roundtrip(input: &[u8]) -> usize {
+        let mut encoded = Vec::new();
+        encode_block(input, &mut encoded);
+        assert!(encoded.len() <= max_compressed_len(input.len()));
+        let mut decoded = Vec::new();
+        decode_block(&encoded, &mut decoded, input.len()).unwrap();
+        assert!(&decoded[..] == input);
+        encoded.len()
+    }
+
+    #[test]
+    fn known_data() {
+        // a literal and an overlapping match, with an extra length byte
+        let run = [0x00, b'a', 0xe0, 0x00, 0x00];
+        // literals, a short match and literals
+        let text = [0x02, b'a', b'b', b'c', 0x80, 0x02, 0x00, b'X'];
+        for &(data, encoded) in [(&b"aaaaaaaaaa"[..], &run[..]), (b"abcabcabcX", &text)].iter() {
+            let mut out = Vec::new();
+            encode_block(data, &mut out);
+            assert_eq!(&out[..], encoded);
+            out.truncate(0);
+            decode_block(encoded, &mut out, data.len()).unwrap();
+            assert_eq!(&out[..], data);
+        }
+        let mut out = Vec::new();
+        decode_block(&[], &mut out, 0).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn roundtrips() {
+        roundtrip(b"");
+        roundtrip(b"a");
+        roundtrip(b"ab");
+        let text = include_bytes!("data/test.txt");
+        assert!(roundtrip(text) < text.len());
+        roundtrip(&noise(100000, 7));
+        let runs: Vec<u8> = (0..200000).map(|i| (i / 1000) as u8).collect();
+        assert!(roundtrip(&runs) < runs.len() / 20);
+        let far: Vec<u8> = noise(9000, 7).iter().chain(noise(9000, 7).iter()).cloned().collect();
+        roundtrip(&far);
+    }
+
+    #[test]
+    fn invalid_data() {
+        for input in [&[0x02, b'a'][..], // truncated literal
+                      &[0x00, b'a', 0xe0], // truncated length
+                      &[0x00, b'a', 0x20], // truncated distance
+                      &[0x20, 0x00], // match before any data
+                      &[0x00, b'a', 0x20, 0x01]].iter() { // distance too far
+            assert!(decode_block(input, &mut Vec::new(), 100).is_err());
+        }
+        // the limit is enforced on the literals and the matches
+        assert!(decode_block(&[0x02, b'a', b'b', b'c'], &mut Vec::new(), 2).is_err());
+        assert!(decode_block(&[0x00, b'a', 0xe0, 0x00, 0x00], &mut Vec::new(), 9).is_err());
+        // the invalid item is located
+        let err = decode_block(&[0x01, b'a', b'b', 0x20, 0x02], &mut Vec::new(), 100).unwrap_err();
+        assert_eq!(Error::of(&err), Some(Error::CorruptInput { offset: Some(3), detail: "invalid lzf distance" }));
+    }
+}
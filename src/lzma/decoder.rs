@@ -0,0 +1,357 @@
+//! The LZMA decoder proper, shared by the containers
+//!
+//! The decoder keeps its models and the match being copied between calls, so
+//! that the output can be produced in pieces of any size.
+
+use std::cmp;
+use std::io::{self, Read};
+use std::mem;
+
+use super::rangecoder::{RangeDecoder, PROB_INIT};
+use contexts::Tables;
+use error::corrupt;
+
+const NUM_STATES: usize = 12;
+const POS_BITS_MAX: usize = 4;
+const NUM_LEN_TO_POS_STATES: usize = 4;
+const NUM_ALIGN_BITS: usize = 4;
+const START_POS_MODEL_INDEX: u32 = 4;
+const END_POS_MODEL_INDEX: u32 = 14;
+const NUM_FULL_DISTANCES: usize = 1 << (END_POS_MODEL_INDEX >> 1);
+const MATCH_MIN_LEN: usize = 2;
+/// Smallest dictionary the decoder works with, as the reference one does
+const MIN_DICT_SIZE: u32 = 1 << 12;
+/// Most bytes of input a symbol reads, as found by the reference decoder
+pub const MAX_SYMBOL_INPUT: usize = 20;
+
+/// The literal coder settings and the size of the dictionary
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Properties {
+    /// Number of high bits of the previous byte used as literal context
+    pub lc: u32,
+    /// Number of low bits of the position used as literal context
+    pub lp: u32,
+    /// Number of low bits of the position used as match context
+    pub pb: u32,
+    pub dict_size: u32,
+}
+
+impl Properties {
+    /// Reads the properties from their packed byte
+    pub fn from_byte(mut d: u8, dict_size: u32) -> io::Result<Properties> {
+        if d >= 9 * 5 * 5 {
+            return corrupt("invalid lzma properties")
+        }
+        let lc = d % 9;
+        d /= 9;
+        Ok(Properties {
+            lc: lc as u32,
+            lp: (d % 5) as u32,
+            pb: (d / 5) as u32,
+            dict_size: cmp::max(dict_size, MIN_DICT_SIZE),
+        })
+    }
+}
+
+/// The sliding dictionary, grown as data is decoded up to its final size
+pub struct Window {
+    buf: Vec<u8>,
+    size: usize,
+    pos: usize,
+    full: bool,
+    /// Number of bytes decoded since the last reset
+    pub total: u64,
+}
+
+impl Window {
+    pub fn new(size: u32) -> Window {
+        Window {
+            buf: Vec::new(),
+            size: size as usize,
+            pos: 0,
+            full: false,
+            total: 0,
+        }
+    }
+
+    /// Empties the dictionary, possibly changing its size
+    pub fn reset(&mut self, size: u32) {
+        self.buf.truncate(0);
+        self.size = size as usize;
+        self.pos = 0;
+        self.full = false;
+        self.total = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == 0 && !self.full
+    }
+
+    #[inline]
+    pub fn put(&mut self, byte: u8) {
+        if self.full {
+            self.buf[self.pos] = byte;
+        } else {
+            self.buf.push(byte);
+        }
+        self.pos += 1;
+        if self.pos == self.size {
+            self.pos = 0;
+            self.full = true;
+        }
+        self.total += 1;
+    }
+
+    /// Returns the byte `dist` bytes back, starting from 1
+    #[inline]
+    pub fn get(&self, dist: usize) -> u8 {
+        if dist <= self.pos {
+            self.buf[self.pos - dist]
+        } else {
+            self.buf[self.size - dist + self.pos]
+        }
+    }
+
+    /// Tests whether a match may start `dist` bytes back
+    pub fn has_distance(&self, dist: usize) -> bool {
+        dist <= self.pos || (self.full && dist <= self.size)
+    }
+}
+
+/// Probabilities of the lengths of the matches
+struct LenDecoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 1 << 3]; 1 << POS_BITS_MAX],
+    mid: [[u16; 1 << 3]; 1 << POS_BITS_MAX],
+    high: [u16; 1 << 8],
+}
+
+impl LenDecoder {
+    fn new() -> LenDecoder {
+        LenDecoder {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: [[PROB_INIT; 1 << 3]; 1 << POS_BITS_MAX],
+            mid: [[PROB_INIT; 1 << 3]; 1 << POS_BITS_MAX],
+            high: [PROB_INIT; 1 << 8],
+        }
+    }
+
+    fn decode<R: Read>(&mut self, rc: &mut RangeDecoder<R>, pos_state: usize) -> io::Result<usize> {
+        if try!(rc.bit(&mut self.choice)) == 0 {
+            return rc.bit_tree(&mut self.low[pos_state], 3).map(|l| l as usize)
+        }
+        if try!(rc.bit(&mut self.choice2)) == 0 {
+            return rc.bit_tree(&mut self.mid[pos_state], 3).map(|l| 8 + l as usize)
+        }
+        rc.bit_tree(&mut self.high, 8).map(|l| 16 + l as usize)
+    }
+}
+
+/// The state of the decoding of a LZMA stream
+pub struct LzmaDecoder {
+    props: Properties,
+    literal: Tables<u16>,
+    pos_slot: [[u16; 1 << 6]; NUM_LEN_TO_POS_STATES],
+    pos_decoders: [u16; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+    align: [u16; 1 << NUM_ALIGN_BITS],
+    is_match: [u16; NUM_STATES << POS_BITS_MAX],
+    is_rep: [u16; NUM_STATES],
+    is_rep_g0: [u16; NUM_STATES],
+    is_rep_g1: [u16; NUM_STATES],
+    is_rep_g2: [u16; NUM_STATES],
+    is_rep0_long: [u16; NUM_STATES << POS_BITS_MAX],
+    len_decoder: LenDecoder,
+    rep_len_decoder: LenDecoder,
+
+    state: usize,
+    reps: [u32; 4],
+    /// Bytes of the current match left to copy
+    pending: usize,
+    /// Whether the end marker was found
+    pub finished: bool,
+}
+
+impl LzmaDecoder {
+    pub fn new(props: Properties) -> LzmaDecoder {
+        LzmaDecoder {
+            props: props,
+            literal: Tables::new(1 << (props.lc + props.lp), 0x300, PROB_INIT),
+            pos_slot: [[PROB_INIT; 1 << 6]; NUM_LEN_TO_POS_STATES],
+            pos_decoders: [PROB_INIT; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+            align: [PROB_INIT; 1 << NUM_ALIGN_BITS],
+            is_match: [PROB_INIT; NUM_STATES << POS_BITS_MAX],
+            is_rep: [PROB_INIT; NUM_STATES],
+            is_rep_g0: [PROB_INIT; NUM_STATES],
+            is_rep_g1: [PROB_INIT; NUM_STATES],
+            is_rep_g2: [PROB_INIT; NUM_STATES],
+            is_rep0_long: [PROB_INIT; NUM_STATES << POS_BITS_MAX],
+            len_decoder: LenDecoder::new(),
+            rep_len_decoder: LenDecoder::new(),
+            state: 0,
+            reps: [0; 4],
+            pending: 0,
+            finished: false,
+        }
+    }
+
+    /// Resets the models and the state, possibly with new literal settings.
+    /// The window stays as is.
+    pub fn reset(&mut self, props: Properties) {
+        // the literal tables already allocated are kept for the new contexts
+        let literal = mem::replace(&mut self.literal, Tables::new(0, 0, PROB_INIT));
+        *self = LzmaDecoder::new(props);
+        self.literal = literal;
+        self.literal.reset(1 << (props.lc + props.lp));
+    }
+
+    pub fn properties(&self) -> Properties {
+        self.props
+    }
+
+    /// Tests whether a match is still being copied
+    pub fn has_pending(&self) -> bool {
+        self.pending > 0
+    }
+
+    fn decode_literal<R: Read>(&mut self, rc: &mut RangeDecoder<R>, window: &mut Window) -> io::Result<u8> {
+        let prev = if window.is_empty() { 0 } else { window.get(1) as usize };
+        let lit_state = ((window.total as usize & ((1 << self.props.lp) - 1)) << self.props.lc)
+            + (prev >> (8 - self.props.lc));
+        let probs = self.literal.get(lit_state);
+        let mut symbol = 1;
+        if self.state >= 7 {
+            // the byte the last match would have continued with, as long as
+            // it agrees with the bits decoded so far
+            let mut match_byte = window.get(self.reps[0] as usize + 1) as usize;
+            while symbol < 0x100 {
+                let match_bit = (match_byte >> 7) & 1;
+                match_byte <<= 1;
+                let bit = try!(rc.bit(&mut probs[((1 + match_bit) << 8) + symbol])) as usize;
+                symbol = (symbol << 1) | bit;
+                if match_bit != bit {
+                    break
+                }
+            }
+        }
+        while symbol < 0x100 {
+            symbol = (symbol << 1) | try!(rc.bit(&mut probs[symbol])) as usize;
+        }
+        Ok((symbol - 0x100) as u8)
+    }
+
+    fn decode_distance<R: Read>(&mut self, rc: &mut RangeDecoder<R>, len: usize) -> io::Result<u32> {
+        let len_state = cmp::min(len, NUM_LEN_TO_POS_STATES - 1);
+        let pos_slot = try!(rc.bit_tree(&mut self.pos_slot[len_state], 6));
+        if pos_slot < START_POS_MODEL_INDEX {
+            return Ok(pos_slot)
+        }
+        let direct = ((pos_slot >> 1) - 1) as usize;
+        let mut dist = (2 | (pos_slot & 1)) << direct;
+        if pos_slot < END_POS_MODEL_INDEX {
+            let base = (dist - pos_slot) as usize;
+            dist += try!(rc.bit_tree_reverse(&mut self.pos_decoders[base..], direct));
+        } else {
+            dist += try!(rc.direct_bits(direct - NUM_ALIGN_BITS)) << NUM_ALIGN_BITS;
+            dist += try!(rc.bit_tree_reverse(&mut self.align, NUM_ALIGN_BITS));
+        }
+        Ok(dist)
+    }
+
+    /// Decodes into `out`, stopping at the end marker, once `left` bytes, if
+    /// given, are produced, or once `*symbols`, counted down, are decoded.
+    /// Returns the number of bytes decoded.
+    pub fn decode<R: Read>(&mut self, rc: &mut RangeDecoder<R>, window: &mut Window,
+                           out: &mut [u8], left: Option<u64>, symbols: &mut usize)
+                           -> io::Result<usize> {
+        let mut n = 0;
+        let limit = match left {
+            Some(left) if left < out.len() as u64 => left as usize,
+            _ => out.len(),
+        };
+        let pb_mask = (1 << self.props.pb) - 1;
+        while n < limit {
+            if self.pending > 0 {
+                let dist = self.reps[0] as usize + 1;
+                while self.pending > 0 && n < limit {
+                    let b = window.get(dist);
+                    window.put(b);
+                    out[n] = b;
+                    n += 1;
+                    self.pending -= 1;
+                }
+                continue
+            }
+            if self.finished || *symbols == 0 {
+                break
+            }
+            *symbols -= 1;
+
+            let pos_state = window.total as usize & pb_mask;
+            let state = self.state;
+            if try!(rc.bit(&mut self.is_match[(state << POS_BITS_MAX) + pos_state])) == 0 {
+                let b = try!(self.decode_literal(rc, window));
+                window.put(b);
+                out[n] = b;
+                n += 1;
+                self.state = if state < 4 { 0 } else if state < 10 { state - 3 } else { state - 6 };
+                continue
+            }
+
+            let len;
+            if try!(rc.bit(&mut self.is_rep[state])) != 0 {
+                if window.is_empty() {
+                    return corrupt("invalid lzma repeated match")
+                }
+                if try!(rc.bit(&mut self.is_rep_g0[state])) == 0 {
+                    if try!(rc.bit(&mut self.is_rep0_long[(state << POS_BITS_MAX) + pos_state])) == 0 {
+                        // a single byte from the last distance
+                        self.state = if state < 7 { 9 } else { 11 };
+                        self.pending = 1;
+                        continue
+                    }
+                } else {
+                    let dist;
+                    if try!(rc.bit(&mut self.is_rep_g1[state])) == 0 {
+                        dist = self.reps[1];
+                    } else {
+                        if try!(rc.bit(&mut self.is_rep_g2[state])) == 0 {
+                            dist = self.reps[2];
+                        } else {
+                            dist = self.reps[3];
+                            self.reps[3] = self.reps[2];
+                        }
+                        self.reps[2] = self.reps[1];
+                    }
+                    self.reps[1] = self.reps[0];
+                    self.reps[0] = dist;
+                }
+                len = try!(self.rep_len_decoder.decode(rc, pos_state));
+                self.state = if state < 7 { 8 } else { 11 };
+            } else {
+                self.reps[3] = self.reps[2];
+                self.reps[2] = self.reps[1];
+                self.reps[1] = self.reps[0];
+                len = try!(self.len_decoder.decode(rc, pos_state));
+                self.state = if state < 7 { 7 } else { 10 };
+                self.reps[0] = try!(self.decode_distance(rc, len));
+                if self.reps[0] == 0xffffffff {
+                    if !rc.is_finished() {
+                        return corrupt("invalid lzma end marker")
+                    }
+                    self.finished = true;
+                    break
+                }
+                if self.reps[0] >= self.props.dict_size {
+                    return corrupt("invalid lzma match distance")
+                }
+            }
+            if !window.has_distance(self.reps[0] as usize + 1) {
+                return corrupt("invalid lzma match distance")
+            }
+            self.pending = len + MATCH_MIN_LEN;
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,225 @@
+//! LZMA2 decoding
+//!
+//! LZMA2 splits the data in chunks, either stored or LZMA compressed, each of
+//! which may reset the dictionary, the state or the literal coder settings. A
+//! compressed chunk holds at most 64 kB of range coded data, so it is read
+//! in full before being decoded.
+
+use std::cmp;
+use std::io::{self, Cursor, Read};
+
+use super::super::byteorder::{BigEndian, ReadBytesExt};
+use error::{corrupt, truncated};
+use super::decoder::{LzmaDecoder, Properties, Window};
+use super::rangecoder::RangeDecoder;
+
+/// Chunk kinds
+const CONTROL_END: u8 = 0x00;
+const CONTROL_STORED_RESET: u8 = 0x01;
+const CONTROL_STORED: u8 = 0x02;
+const CONTROL_LZMA: u8 = 0x80;
+
+/// Resets asked for by a LZMA chunk, in bits 5 and 6 of its control byte
+const RESET_STATE: u8 = 1;
+const RESET_PROPS: u8 = 2;
+const RESET_DICT: u8 = 3;
+
+/// Returns the size of the dictionary from the byte of the LZMA2 filter
+pub fn dict_size(b: u8) -> io::Result<u32> {
+    if b < 40 {
+        Ok((2 | (b as u32 & 1)) << (b / 2 + 11))
+    } else if b == 40 {
+        Ok(0xffffffff)
+    } else {
+        corrupt("invalid lzma2 dictionary size")
+    }
+}
+
+/// Reads from `r` into `buf` until it holds `len` bytes, keeping those read
+/// when `r` fails
+fn fill<R: Read>(buf: &mut Vec<u8>, r: &mut R, len: usize) -> io::Result<()> {
+    while buf.len() < len {
+        let start = buf.len();
+        buf.resize(len, 0);
+        let result = r.read(&mut buf[start..]);
+        let n = *result.as_ref().unwrap_or(&0);
+        buf.truncate(start + n);
+        if n == 0 {
+            try!(result);
+            return truncated("truncated lzma2 chunk")
+        }
+    }
+    Ok(())
+}
+
+enum Chunk {
+    /// Between chunks
+    None,
+    /// Bytes left of a stored chunk
+    Stored(usize),
+    /// Bytes left of a compressed chunk
+    Lzma(u64),
+}
+
+/// The state of the decoding of a LZMA2 stream, which reads its input from a
+/// stream given on each call. A call failing with `WouldBlock` may be made
+/// again once the stream has more input.
+pub struct Lzma2Decoder {
+    dict_size: u32,
+    lzma: Option<LzmaDecoder>,
+    window: Window,
+    rc: RangeDecoder<Cursor<Vec<u8>>>,
+    /// The header of the next chunk, as far as it was read
+    head: Vec<u8>,
+    chunk: Chunk,
+    /// Whether the first chunk, which must reset everything, is yet to come
+    first: bool,
+    finished: bool,
+}
+
+impl Lzma2Decoder {
+    pub fn new(dict_size: u32) -> Lzma2Decoder {
+        Lzma2Decoder {
+            dict_size: dict_size,
+            lzma: None,
+            window: Window::new(dict_size),
+            rc: RangeDecoder::new(Cursor::new(Vec::new())),
+            head: Vec::with_capacity(6),
+            chunk: Chunk::None,
+            first: true,
+            finished: false,
+        }
+    }
+
+    /// Starts a new stream, forgetting everything of the last one
+    pub fn reset(&mut self, dict_size: u32) {
+        self.dict_size = dict_size;
+        self.lzma = None;
+        self.head.truncate(0);
+        self.chunk = Chunk::None;
+        self.first = true;
+        self.finished = false;
+    }
+
+    /// Reads the header of the next chunk, and the data of a compressed one.
+    /// The bytes read are kept when `r` fails, as with `WouldBlock`, so that
+    /// the chunk goes on from them on the next call.
+    fn next_chunk<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        try!(fill(&mut self.head, r, 1));
+        let control = self.head[0];
+        if control == CONTROL_END {
+            self.head.truncate(0);
+            self.finished = true;
+            return Ok(())
+        }
+        let dict_reset = control == CONTROL_STORED_RESET ||
+            (control >= CONTROL_LZMA && (control >> 5) & 3 == RESET_DICT);
+        if !dict_reset && self.first {
+            return corrupt("lzma2 stream must start with a dictionary reset")
+        }
+        if control < CONTROL_LZMA && control > CONTROL_STORED {
+            return corrupt("invalid lzma2 chunk")
+        }
+        let reset = (control >> 5) & 3;
+        let len = if control < CONTROL_LZMA { 3 } else if reset >= RESET_PROPS { 6 } else { 5 };
+        if self.head.len() < len {
+            try!(fill(&mut self.head, r, len));
+            self.rc.r.get_mut().truncate(0);
+        }
+
+        if control < CONTROL_LZMA {
+            let size = (&self.head[1..3]).read_u16::<BigEndian>().unwrap() as usize + 1;
+            self.head.truncate(0);
+            if dict_reset {
+                self.window.reset(self.dict_size);
+            }
+            self.first = false;
+            self.chunk = Chunk::Stored(size);
+            return Ok(())
+        }
+
+        let unpacked = ((control as u64 & 0x1f) << 16) +
+            (&self.head[1..3]).read_u16::<BigEndian>().unwrap() as u64 + 1;
+        let packed = (&self.head[3..5]).read_u16::<BigEndian>().unwrap() as usize + 1;
+        let props = if reset >= RESET_PROPS {
+            let props = try!(Properties::from_byte(self.head[5], self.dict_size));
+            if props.lc + props.lp > 4 {
+                return corrupt("invalid lzma2 properties")
+            }
+            Some(props)
+        } else if self.lzma.is_none() {
+            return corrupt("lzma2 chunk without properties")
+        } else {
+            None
+        };
+        try!(fill(self.rc.r.get_mut(), r, packed));
+        self.head.truncate(0);
+
+        if dict_reset {
+            self.window.reset(self.dict_size);
+        }
+        self.first = false;
+        match props {
+            Some(props) => self.lzma = Some(LzmaDecoder::new(props)),
+            None if reset >= RESET_STATE => {
+                let lzma = self.lzma.as_mut().unwrap();
+                let props = lzma.properties();
+                lzma.reset(props);
+            }
+            None => {}
+        }
+        self.rc.r.set_position(0);
+        try!(self.rc.reset());
+        self.chunk = Chunk::Lzma(unpacked);
+        Ok(())
+    }
+
+    /// Decodes into `out` from the stream `r`, returning 0 at the end of the
+    /// LZMA2 stream only
+    pub fn decode<R: Read>(&mut self, r: &mut R, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() {
+            return Ok(0)
+        }
+        loop {
+            match self.chunk {
+                Chunk::None if self.finished => return Ok(0),
+                Chunk::None => try!(self.next_chunk(r)),
+                Chunk::Stored(ref mut left) => {
+                    let amt = cmp::min(*left, out.len());
+                    let amt = match try!(r.read(&mut out[..amt])) {
+                        0 => return truncated("truncated lzma2 chunk"),
+                        n => n,
+                    };
+                    for &b in out[..amt].iter() {
+                        self.window.put(b);
+                    }
+                    *left -= amt;
+                    if *left == 0 {
+                        self.chunk = Chunk::None;
+                    }
+                    return Ok(amt)
+                }
+                Chunk::Lzma(ref mut left) => {
+                    let lzma = self.lzma.as_mut().unwrap();
+                    let mut symbols = usize::max_value();
+                    let n = try!(lzma.decode(&mut self.rc, &mut self.window, out, Some(*left),
+                                             &mut symbols));
+                    if lzma.finished {
+                        return corrupt("unexpected lzma end marker in lzma2 chunk")
+                    }
+                    *left -= n as u64;
+                    if *left == 0 {
+                        let used = self.rc.r.position() as usize == self.rc.r.get_ref().len();
+                        if lzma.has_pending() || !self.rc.is_finished() || !used {
+                            return corrupt("lzma2 chunk size mismatch")
+                        }
+                        self.chunk = Chunk::None;
+                    }
+                    if n > 0 {
+                        return Ok(n)
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! LZMA Decompression. Requires `lzma` feature, enabled by default
+//!
+//! This module contains a decoder of the LZMA compression scheme, where the
+//! literals, match lengths and distances of a LZ77 parse are coded bit by bit
+//! with adaptive binary models and a range coder. The streams are read in the
+//! legacy `.lzma` format (also known as "LZMA alone"), as written by `lzma`
+//! and `xz --format=lzma`: a 13-byte header carrying the literal coder
+//! settings, the dictionary size and the uncompressed size, if known. The
+//! `xz` submodule reads the `.xz` format, whose blocks hold LZMA2 data.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::lzma;
+//! use std::fs::File;
+//! use std::path::Path;
+//! use std::io::Read;
+//!
+//! let stream = File::open(&Path::new("path/to/file.lzma")).unwrap();
+//! let mut decompressed = Vec::new();
+//! lzma::Decoder::new(stream).read_to_end(&mut decompressed);
+//! ```
+//!
+//! # Related links
+//!
+//! * https://www.7-zip.org/sdk.html - the LZMA SDK, whose
+//!   `lzma-specification.txt` describes the decoder this one is based on
+
+use std::cmp;
+use std::io::{self, Read};
+
+use super::byteorder::{LittleEndian, ReadBytesExt};
+
+use self::decoder::{LzmaDecoder, Properties, Window, MAX_SYMBOL_INPUT};
+use self::rangecoder::RangeDecoder;
+use error::corrupt;
+use process::{self, Process, Reader, Status};
+
+mod decoder;
+mod lzma2;
+mod rangecoder;
+pub mod xz;
+
+/// Uncompressed size of the streams which end with a marker instead
+const UNKNOWN_SIZE: u64 = 0xffffffffffffffff;
+/// Size of the header, along with the first bytes of the range coder
+const HEADER_SIZE: usize = 13 + 5;
+
+/// This structure is used to decode a stream in the `.lzma` format, fed with
+/// slices of it. A symbol is decoded once the input holds enough bytes for
+/// the longest, or once it is over, so up to a few bytes past the end of the
+/// stream are gathered with it.
+pub struct Processor {
+    rc: RangeDecoder<process::Window>,
+    /// The decoder, once a header is read, kept for the next streams
+    lzma: Option<LzmaDecoder>,
+    header: bool,
+    window: Window,
+    /// Number of bytes left to decode, if known
+    left: Option<u64>,
+    done: bool,
+}
+
+impl Processor {
+    /// Creates a new LZMA decoder
+    pub fn new() -> Processor {
+        Processor {
+            rc: RangeDecoder::new(process::Window::new()),
+            lzma: None,
+            header: false,
+            window: Window::new(0),
+            left: None,
+            done: false,
+        }
+    }
+
+    /// Starts decoding a new stream, keeping the buffers of the decoder
+    pub fn reset(&mut self) {
+        self.rc.r.reset();
+        self.header = false;
+        self.left = None;
+        self.done = false;
+    }
+
+    /// Tests whether the end of the stream has been reached
+    pub fn eof(&self) -> bool {
+        self.done
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let d = try!(self.rc.r.read_u8());
+        let dict_size = try!(self.rc.r.read_u32::<LittleEndian>());
+        let props = try!(Properties::from_byte(d, dict_size));
+        let size = try!(self.rc.r.read_u64::<LittleEndian>());
+        self.left = if size == UNKNOWN_SIZE { None } else { Some(size) };
+        try!(self.rc.reset());
+        self.window.reset(props.dict_size);
+        match self.lzma {
+            Some(ref mut lzma) => lzma.reset(props),
+            None => self.lzma = Some(LzmaDecoder::new(props)),
+        }
+        self.header = true;
+        Ok(())
+    }
+
+    /// Returns how many symbols the input gathered holds for sure, all of
+    /// them once the input is over
+    fn symbols(&self) -> usize {
+        if self.rc.r.is_finished() {
+            usize::max_value()
+        } else {
+            self.rc.r.data().len() / MAX_SYMBOL_INPUT
+        }
+    }
+
+    /// Checks the end of the stream, which may also have a marker when its
+    /// size is known. Returns false if the marker may be yet to come.
+    fn end(&mut self) -> io::Result<bool> {
+        let lzma = self.lzma.as_mut().unwrap();
+        match self.left {
+            Some(0) if lzma.has_pending() => return corrupt("lzma match past the end of the stream"),
+            Some(0) if !lzma.finished && !self.rc.is_finished() => {
+                if self.rc.r.data().len() < MAX_SYMBOL_INPUT && !self.rc.r.is_finished() {
+                    return Ok(false)
+                }
+                let n = try!(lzma.decode(&mut self.rc, &mut self.window, &mut [0], None, &mut 1));
+                if n > 0 || !lzma.finished {
+                    return corrupt("lzma data past the end of the stream")
+                }
+            }
+            Some(0) => {}
+            Some(_) => return corrupt("lzma stream ended early"),
+            None => {}
+        }
+        self.done = true;
+        Ok(true)
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        self.rc.r.extend(input);
+        let mut produced = 0;
+        loop {
+            if self.done {
+                // the input gathered past the end of the stream isn't used
+                let pos = input.len() - cmp::min(self.rc.r.data().len(), input.len());
+                return Ok((pos, produced, Status::Done))
+            }
+            if !self.header {
+                if self.rc.r.data().len() < HEADER_SIZE && !self.rc.r.is_finished() {
+                    return Ok((input.len(), produced, Status::NeedInput))
+                }
+                try!(self.read_header());
+            }
+            if self.left == Some(0) || self.lzma.as_ref().unwrap().finished {
+                if !try!(self.end()) {
+                    return Ok((input.len(), produced, Status::NeedInput))
+                }
+                continue
+            }
+            if produced == output.len() {
+                return Ok((input.len(), produced, Status::NeedOutput))
+            }
+            let mut symbols = self.symbols();
+            let lzma = self.lzma.as_mut().unwrap();
+            let n = try!(lzma.decode(&mut self.rc, &mut self.window, &mut output[produced..],
+                                     self.left, &mut symbols));
+            produced += n;
+            if let Some(ref mut left) = self.left {
+                *left -= n as u64;
+            }
+            if n == 0 && !lzma.finished {
+                return Ok((input.len(), produced, Status::NeedInput))
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the symbols left may come from the input gathered, and reading
+        // past it fails as truncated
+        self.rc.r.finish();
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a stream in the `.lzma` format. The
+/// wrapped stream can be re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new LZMA decoder which will wrap the specified reader.
+    /// This decoder also implements the `Reader` trait, and the underlying
+    /// reader can be re-acquired through the `unwrap` method.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new()),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Tests whether the end of the stream has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.processor().eof()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read};
+    use testing::push;
+    use super::{Decoder, Processor};
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        out
+    }
+
+    /// The input of test.window.lzma, compressed with lc=0, lp=2, pb=0 and a
+    /// dictionary smaller than the data
+    fn window_input() -> Vec<u8> {
+        let text = include_bytes!("../data/test.txt");
+        (0..20000).map(|i| text[i % text.len()] ^ ((i / 5000) & 1) as u8).collect()
+    }
+
+    #[test]
+    fn decode_file() {
+        assert!(&decode(include_bytes!("../data/test.lzma"))[..] ==
+                &include_bytes!("../data/test.txt")[..]);
+        assert!(decode(include_bytes!("../data/test.window.lzma")) == window_input());
+    }
+
+    #[test]
+    fn decode_push() {
+        let input = include_bytes!("../data/test.window.lzma");
+        for &step in [1, 333, 1 << 16].iter() {
+            assert!(push(&mut Processor::new(), input, step).unwrap() == window_input());
+        }
+        let input = include_bytes!("../data/test.lzma");
+        for &len in [0, 10, 100, input.len() - 1].iter() {
+            assert!(push(&mut Processor::new(), &input[..len], 100).is_err());
+        }
+    }
+
+    #[test]
+    fn reset() {
+        // a stream left halfway doesn't affect the next one
+        let mut d = Decoder::new(BufReader::new(&include_bytes!("../data/test.window.lzma")[..]));
+        let mut out = [0; 5000];
+        d.read_exact(&mut out).unwrap();
+        d.reset(BufReader::new(&include_bytes!("../data/test.lzma")[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(&out[..] == &include_bytes!("../data/test.txt")[..]);
+        d.reset(BufReader::new(&include_bytes!("../data/test.window.lzma")[..]));
+        out.truncate(0);
+        d.read_to_end(&mut out).unwrap();
+        assert!(out == window_input());
+    }
+
+    #[test]
+    fn known_size() {
+        // the end marker is optional when the size is given
+        let mut input = include_bytes!("../data/test.lzma").to_vec();
+        let size = include_bytes!("../data/test.txt").len();
+        for i in 0..8 {
+            input[5 + i] = (size >> (8 * i)) as u8;
+        }
+        assert!(&decode(&input)[..] == &include_bytes!("../data/test.txt")[..]);
+        assert!(&push(&mut Processor::new(), &input, 7).unwrap()[..] ==
+                &include_bytes!("../data/test.txt")[..]);
+
+        input[5] -= 1;
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn small_reads() {
+        let input = include_bytes!("../data/test.window.lzma");
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        let mut out = Vec::new();
+        let mut buf = [0u8; 7];
+        loop {
+            match d.read(&mut buf).unwrap() {
+                0 => break,
+                n => out.extend(buf[..n].iter().cloned()),
+            }
+        }
+        assert!(out == window_input());
+    }
+
+    #[test]
+    fn corrupted() {
+        let input = include_bytes!("../data/test.lzma");
+        // invalid properties, then range coder header
+        for &(pos, value) in [(0, 225), (13, 1)].iter() {
+            let mut bad = input.to_vec();
+            bad[pos] = value;
+            let mut d = Decoder::new(BufReader::new(&bad[..]));
+            assert!(d.read_to_end(&mut Vec::new()).is_err());
+        }
+        // there is no checksum, but the data can't come out right
+        let mut bad = input.to_vec();
+        bad[input.len() / 2] ^= 0x10;
+        let mut d = Decoder::new(BufReader::new(&bad[..]));
+        let mut out = Vec::new();
+        if d.read_to_end(&mut out).is_ok() {
+            assert!(&out[..] != &include_bytes!("../data/test.txt")[..]);
+        }
+        let mut d = Decoder::new(BufReader::new(&input[..100]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! The binary range decoder of LZMA
+//!
+//! Unlike the coder of `entropy::ari`, this one must follow the reference
+//! implementation bit for bit: probabilities of 11 bits, adapted by a fifth of
+//! their distance to the bound, and a range normalized one byte at a time.
+
+use std::io::{self, Read};
+
+use super::super::byteorder::ReadBytesExt;
+use error::corrupt;
+
+const PROB_BITS: u32 = 11;
+/// Initial probability of all the bits, one half
+pub const PROB_INIT: u16 = 1 << (PROB_BITS - 1);
+const MOVE_BITS: u32 = 5;
+const TOP: u32 = 1 << 24;
+
+pub struct RangeDecoder<R> {
+    pub r: R,
+    range: u32,
+    code: u32,
+}
+
+impl<R: Read> RangeDecoder<R> {
+    /// Wraps the stream, which must be reset before any decoding
+    pub fn new(r: R) -> RangeDecoder<R> {
+        RangeDecoder { r: r, range: 0, code: 0 }
+    }
+
+    /// Starts decoding, reading the first 5 bytes of the stream
+    pub fn reset(&mut self) -> io::Result<()> {
+        if try!(self.r.read_u8()) != 0 {
+            return corrupt("invalid lzma range coder header")
+        }
+        self.range = 0xffffffff;
+        self.code = 0;
+        for _ in 0..4 {
+            self.code = (self.code << 8) | try!(self.r.read_u8()) as u32;
+        }
+        if self.code == self.range {
+            return corrupt("invalid lzma range coder header")
+        }
+        Ok(())
+    }
+
+    /// Tests whether the stream can end here, which requires all the bits of
+    /// the code to be used up
+    pub fn is_finished(&self) -> bool {
+        self.code == 0
+    }
+
+    #[inline]
+    fn normalize(&mut self) -> io::Result<()> {
+        if self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | try!(self.r.read_u8()) as u32;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn bit(&mut self, prob: &mut u16) -> io::Result<u32> {
+        let bound = (self.range >> PROB_BITS) * *prob as u32;
+        let bit = if self.code < bound {
+            self.range = bound;
+            *prob += ((1 << PROB_BITS) - *prob) >> MOVE_BITS;
+            0
+        } else {
+            self.range -= bound;
+            self.code -= bound;
+            *prob -= *prob >> MOVE_BITS;
+            1
+        };
+        try!(self.normalize());
+        Ok(bit)
+    }
+
+    /// Decodes bits of probability one half, MSB first
+    pub fn direct_bits(&mut self, count: usize) -> io::Result<u32> {
+        let mut res = 0;
+        for _ in 0..count {
+            self.range >>= 1;
+            let bit = if self.code >= self.range {
+                self.code -= self.range;
+                1
+            } else {
+                0
+            };
+            if self.code == self.range {
+                return corrupt("corrupted lzma stream")
+            }
+            try!(self.normalize());
+            res = (res << 1) | bit;
+        }
+        Ok(res)
+    }
+
+    /// Decodes `count` bits MSB first, each bit with the probability found
+    /// along the path of the previous ones in the tree
+    pub fn bit_tree(&mut self, probs: &mut [u16], count: usize) -> io::Result<u32> {
+        let mut m = 1;
+        for _ in 0..count {
+            m = (m << 1) | try!(self.bit(&mut probs[m as usize]));
+        }
+        Ok(m - (1 << count))
+    }
+
+    /// Decodes `count` bits of a tree LSB first
+    pub fn bit_tree_reverse(&mut self, probs: &mut [u16], count: usize) -> io::Result<u32> {
+        let (mut m, mut sym) = (1, 0);
+        for i in 0..count {
+            let bit = try!(self.bit(&mut probs[m as usize]));
+            m = (m << 1) | bit;
+            sym |= bit << i;
+        }
+        Ok(sym)
+    }
+}
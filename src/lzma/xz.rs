@@ -0,0 +1,724 @@
+//! XZ Decompression
+//!
+//! This module contains a decoder of the `.xz` file format, as written by
+//! `xz`: a sequence of streams, each made of blocks of LZMA2 data followed by
+//! an index of the blocks. The LZMA2 data may be preceded by a BCJ filter of
+//! x86, ARM or ARM64 code. The stream headers and footers, the block headers
+//! and the index are verified against their CRC32, as are the block contents
+//! against their CRC32 or CRC64 check. SHA-256 checks are skipped.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::lzma::xz;
+//! use std::fs::File;
+//! use std::path::Path;
+//! use std::io::Read;
+//!
+//! let stream = File::open(&Path::new("path/to/file.xz")).unwrap();
+//! let mut decompressed = Vec::new();
+//! xz::Decoder::new(stream).read_to_end(&mut decompressed);
+//! ```
+//!
+//! # Related links
+//!
+//! * https://tukaani.org/xz/xz-file-format.txt - the specification of the
+//!   file format
+
+use std::cmp;
+use std::io::{self, Read};
+
+use super::super::ReadExact;
+use super::super::byteorder::{LittleEndian, ReadBytesExt};
+use checksum::{crc32, crc64};
+use error::{checksum_mismatch, corrupt, truncated, unsupported};
+use process::{self, Process, Reader, Status};
+use filter::bcj::{Arch, Bcj};
+use super::lzma2::{self, Lzma2Decoder};
+
+const HEADER_MAGIC: [u8; 6] = [0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const FOOTER_MAGIC: [u8; 2] = [b'Y', b'Z'];
+
+const CHECK_NONE: u8 = 0x00;
+const CHECK_CRC32: u8 = 0x01;
+const CHECK_CRC64: u8 = 0x04;
+/// Size of the check of the blocks by check kind
+static CHECK_SIZES: [usize; 16] = [0, 4, 4, 4, 8, 8, 8, 16, 16, 16, 32, 32, 32, 64, 64, 64];
+
+const FILTER_X86: u64 = 0x04;
+const FILTER_ARM: u64 = 0x07;
+const FILTER_ARM64: u64 = 0x0a;
+const FILTER_LZMA2: u64 = 0x21;
+
+/// Size of the buffer of the data going through a BCJ filter
+const FILTER_BUF_SIZE: usize = 1 << 16;
+
+fn crc32_of(buf: &[u8]) -> u32 {
+    let mut state = crc32::State32::new();
+    state.feed(buf);
+    state.result()
+}
+
+/// The wrapped reader, counting the bytes read so that the sizes found in the
+/// headers and the index can be checked, and summing them when asked to
+struct Input<R> {
+    r: R,
+    count: u64,
+    crc: Option<crc32::State32>,
+}
+
+impl<R: Read> Read for Input<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.r.read(buf));
+        self.count += n as u64;
+        if let Some(ref mut crc) = self.crc {
+            crc.feed(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Read> Input<R> {
+    /// Reads a variable length integer, 7 bits at a time
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for i in 0..9 {
+            let byte = try!(self.read_u8());
+            if byte == 0 && i > 0 {
+                return corrupt("invalid xz integer")
+            }
+            value |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value)
+            }
+        }
+        corrupt("invalid xz integer")
+    }
+
+    fn padding(&mut self, size: u64) -> io::Result<()> {
+        for _ in 0..((4 - size % 4) % 4) {
+            if try!(self.read_u8()) != 0 {
+                return corrupt("invalid xz padding")
+            }
+        }
+        Ok(())
+    }
+}
+
+enum Check {
+    None,
+    Crc32(crc32::State32),
+    Crc64(crc64::State64),
+    /// Unsupported checks, which are skipped
+    Other,
+}
+
+impl Check {
+    fn new(kind: u8) -> Check {
+        match kind {
+            CHECK_NONE => Check::None,
+            CHECK_CRC32 => Check::Crc32(crc32::State32::new()),
+            CHECK_CRC64 => Check::Crc64(crc64::State64::new()),
+            _ => Check::Other,
+        }
+    }
+
+    fn feed(&mut self, buf: &[u8]) {
+        match *self {
+            Check::Crc32(ref mut state) => state.feed(buf),
+            Check::Crc64(ref mut state) => state.feed(buf),
+            Check::None | Check::Other => {}
+        }
+    }
+
+    fn verify(&self, check: &[u8]) -> bool {
+        match *self {
+            Check::Crc32(ref state) => {
+                (&check[..]).read_u32::<LittleEndian>().unwrap() == state.result()
+            }
+            Check::Crc64(ref state) => {
+                (&check[..]).read_u64::<LittleEndian>().unwrap() == state.result()
+            }
+            Check::None | Check::Other => true,
+        }
+    }
+}
+
+/// The BCJ filter of a block, with the LZMA2 data decoded but not yet
+/// converted
+struct Filter {
+    bcj: Bcj,
+    buf: Vec<u8>,
+    /// The bytes from `pos` to `filtered` are ready, those from `filtered` to
+    /// `end` wait for more data
+    pos: usize,
+    filtered: usize,
+    end: usize,
+}
+
+impl Filter {
+    fn new(bcj: Bcj) -> Filter {
+        Filter { bcj: bcj, buf: vec![0; FILTER_BUF_SIZE], pos: 0, filtered: 0, end: 0 }
+    }
+
+    /// Decodes the LZMA2 data of the block then converts it, the bytes at
+    /// its end being left as such
+    fn read<R: Read>(&mut self, lzma2: &mut Lzma2Decoder, r: &mut R, dst: &mut [u8])
+                     -> io::Result<usize> {
+        while self.pos == self.filtered {
+            let left = self.end - self.filtered;
+            self.buf.copy_within(self.filtered..self.end, 0);
+            self.pos = 0;
+            self.filtered = 0;
+            self.end = left;
+            match try!(lzma2.decode(r, &mut self.buf[left..])) {
+                0 if left == 0 => return Ok(0),
+                0 => self.filtered = self.end,
+                n => {
+                    self.end += n;
+                    self.filtered = self.bcj.decode(&mut self.buf[..self.end]);
+                }
+            }
+        }
+        let amt = cmp::min(dst.len(), self.filtered - self.pos);
+        dst[..amt].copy_from_slice(&self.buf[self.pos..(self.pos + amt)]);
+        self.pos += amt;
+        Ok(amt)
+    }
+}
+
+/// The block being decoded
+struct Block {
+    header_size: u64,
+    compressed_size: Option<u64>,
+    uncompressed_size: Option<u64>,
+    /// Position of the compressed data in the input
+    start: u64,
+    /// Number of bytes decoded so far
+    decoded: u64,
+    check: Check,
+    filter: Option<Filter>,
+}
+
+/// This structure is used to decode a xz file, fed with slices of it. The
+/// headers, the ends of the blocks and the indexes are gathered until they
+/// are whole, and decoded again from their start whenever they run out.
+pub struct Processor {
+    input: Input<process::Window>,
+    lzma2: Lzma2Decoder,
+    /// Flags of the current stream, once its header is read
+    flags: Option<[u8; 2]>,
+    block: Option<Block>,
+    /// Unpadded and uncompressed sizes of the blocks of the stream, to be
+    /// checked against the index
+    records: Vec<(u64, u64)>,
+    streams: usize,
+    eof: bool,
+}
+
+impl Processor {
+    /// Creates a new xz decoder
+    pub fn new() -> Processor {
+        Processor {
+            input: Input { r: process::Window::new(), count: 0, crc: None },
+            lzma2: Lzma2Decoder::new(0),
+            flags: None,
+            block: None,
+            records: Vec::new(),
+            streams: 0,
+            eof: false,
+        }
+    }
+
+    /// Starts decoding a new file, keeping the buffers of the decoder
+    pub fn reset(&mut self) {
+        self.input.r.reset();
+        self.input.count = 0;
+        self.input.crc = None;
+        self.flags = None;
+        self.block = None;
+        self.records.truncate(0);
+        self.streams = 0;
+        self.eof = false;
+    }
+
+    /// Tests whether the end of the file has been reached
+    pub fn eof(&self) -> bool {
+        self.eof
+    }
+
+    /// Reads the header of the next stream, skipping the padding in between.
+    /// Returns false at the end of the file.
+    fn read_stream_header(&mut self) -> io::Result<bool> {
+        let mut header = [0u8; 12];
+        loop {
+            let mut n = 0;
+            while n < 4 {
+                match try!(self.input.read(&mut header[n..4])) {
+                    0 => break,
+                    amt => n += amt,
+                }
+            }
+            if n == 0 && self.streams > 0 {
+                return Ok(false)
+            }
+            if n < 4 {
+                return truncated("truncated xz stream")
+            }
+            // padding comes in groups of 4 zeroes, between streams only
+            if header[..4] != [0; 4] || self.streams == 0 {
+                break
+            }
+        }
+        let mut rest = Vec::with_capacity(8);
+        try!(self.input.push_exactly(8, &mut rest));
+        header[4..].copy_from_slice(&rest);
+
+        if header[..6] != HEADER_MAGIC {
+            return corrupt("invalid xz magic number")
+        }
+        let flags = [header[6], header[7]];
+        if (&header[8..]).read_u32::<LittleEndian>().unwrap() != crc32_of(&flags) {
+            return checksum_mismatch("invalid xz stream header checksum")
+        }
+        if flags[0] != 0 || flags[1] & 0xf0 != 0 {
+            return unsupported("unsupported xz stream flags")
+        }
+        self.flags = Some(flags);
+        self.records.truncate(0);
+        self.streams += 1;
+        Ok(true)
+    }
+
+    /// Reads the header of a block, whose first byte is given
+    fn read_block_header(&mut self, size: u8) -> io::Result<()> {
+        let header_size = (size as usize + 1) * 4;
+        let mut header = vec![size];
+        try!(self.input.push_exactly(header_size as u64 - 1, &mut header));
+        let crc = (&header[(header_size - 4)..]).read_u32::<LittleEndian>().unwrap();
+        if crc != crc32_of(&header[..(header_size - 4)]) {
+            return checksum_mismatch("invalid xz block header checksum")
+        }
+
+        let mut fields = Input { r: &header[2..(header_size - 4)], count: 0, crc: None };
+        let flags = header[1];
+        if flags & 0x3c != 0 {
+            return unsupported("unsupported xz block flags")
+        }
+        let compressed_size = if flags & 0x40 != 0 { Some(try!(fields.varint())) } else { None };
+        let uncompressed_size = if flags & 0x80 != 0 { Some(try!(fields.varint())) } else { None };
+        let num_filters = (flags & 3) as usize + 1;
+        let mut dict_size = None;
+        let mut filter = None;
+        for i in 0..num_filters {
+            let id = try!(fields.varint());
+            let props_size = try!(fields.varint());
+            let mut props = Vec::new();
+            try!(fields.push_exactly(props_size, &mut props));
+            if i == num_filters - 1 {
+                // LZMA2 must come last
+                if id != FILTER_LZMA2 || props.len() != 1 {
+                    return unsupported("unsupported xz filter")
+                }
+                dict_size = Some(try!(lzma2::dict_size(props[0])));
+                continue
+            }
+            // then a single BCJ filter may come first, with its start offset
+            let arch = match id {
+                FILTER_X86 => Arch::X86,
+                FILTER_ARM => Arch::Arm,
+                FILTER_ARM64 => Arch::Arm64,
+                _ => return unsupported("unsupported xz filter"),
+            };
+            let start = match props.len() {
+                0 => 0,
+                4 => (&props[..]).read_u32::<LittleEndian>().unwrap(),
+                _ => return corrupt("invalid xz filter properties"),
+            };
+            if i > 0 || (arch != Arch::X86 && start % 4 != 0) {
+                return unsupported("unsupported xz filter")
+            }
+            filter = Some(Filter::new(Bcj::new(arch, start)));
+        }
+        if fields.r.iter().any(|&b| b != 0) {
+            return corrupt("invalid xz block header padding")
+        }
+
+        self.lzma2.reset(dict_size.unwrap());
+        self.block = Some(Block {
+            header_size: header_size as u64,
+            compressed_size: compressed_size,
+            uncompressed_size: uncompressed_size,
+            start: self.input.count,
+            decoded: 0,
+            check: Check::new(self.flags.unwrap()[1]),
+            filter: filter,
+        });
+        Ok(())
+    }
+
+    /// Checks the sizes of the block which just ended, then its check
+    fn end_block(&mut self) -> io::Result<()> {
+        let compressed = {
+            let block = self.block.as_ref().unwrap();
+            let compressed = self.input.count - block.start;
+            if block.compressed_size.map_or(false, |s| s != compressed) ||
+               block.uncompressed_size.map_or(false, |s| s != block.decoded) {
+                return corrupt("xz block size mismatch")
+            }
+            compressed
+        };
+        try!(self.input.padding(compressed));
+        let check_size = CHECK_SIZES[self.flags.unwrap()[1] as usize];
+        let mut check = Vec::with_capacity(check_size);
+        try!(self.input.push_exactly(check_size as u64, &mut check));
+        let block = self.block.take().unwrap();
+        if !block.check.verify(&check) {
+            return corrupt("invalid xz block check")
+        }
+        self.records.push((block.header_size + compressed + check_size as u64, block.decoded));
+        Ok(())
+    }
+
+    /// Reads the index, whose indicator byte was just read, then the stream
+    /// footer
+    fn read_index(&mut self) -> io::Result<()> {
+        let start = self.input.count - 1;
+        let mut state = crc32::State32::new();
+        state.feed(&[0]);
+        self.input.crc = Some(state);
+        let result = self.read_records();
+        let crc = self.input.crc.take().unwrap().result();
+        try!(result);
+        if try!(self.input.read_u32::<LittleEndian>()) != crc {
+            return checksum_mismatch("invalid xz index checksum")
+        }
+        let index_size = self.input.count - start;
+
+        let mut footer = Vec::with_capacity(12);
+        try!(self.input.push_exactly(12, &mut footer));
+        if (&footer[..4]).read_u32::<LittleEndian>().unwrap() != crc32_of(&footer[4..10]) {
+            return checksum_mismatch("invalid xz stream footer checksum")
+        }
+        let backward_size = ((&footer[4..8]).read_u32::<LittleEndian>().unwrap() as u64 + 1) * 4;
+        if backward_size != index_size || footer[8..10] != self.flags.unwrap() ||
+           footer[10..] != FOOTER_MAGIC {
+            return corrupt("invalid xz stream footer")
+        }
+        self.flags = None;
+        Ok(())
+    }
+
+    fn read_records(&mut self) -> io::Result<()> {
+        let start = self.input.count - 1;
+        if try!(self.input.varint()) != self.records.len() as u64 {
+            return corrupt("xz index does not match the blocks")
+        }
+        for i in 0..self.records.len() {
+            let unpadded = try!(self.input.varint());
+            let uncompressed = try!(self.input.varint());
+            if (unpadded, uncompressed) != self.records[i] {
+                return corrupt("xz index does not match the blocks")
+            }
+        }
+        let size = self.input.count - start;
+        self.input.padding(size)
+    }
+
+    /// Reads the next stream header, block header or index
+    fn next_part(&mut self) -> io::Result<()> {
+        if self.flags.is_none() {
+            if !try!(self.read_stream_header()) {
+                self.eof = true;
+            }
+            return Ok(())
+        }
+        match try!(self.input.read_u8()) {
+            0 => self.read_index(),
+            size => self.read_block_header(size),
+        }
+    }
+
+    /// Decodes the data of the block into `dst`, returning 0 at its end
+    fn read_data(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let block = self.block.as_mut().unwrap();
+        let n = match block.filter {
+            Some(ref mut filter) => try!(filter.read(&mut self.lzma2, &mut self.input, dst)),
+            None => try!(self.lzma2.decode(&mut self.input, dst)),
+        };
+        block.check.feed(&dst[..n]);
+        block.decoded += n as u64;
+        if block.uncompressed_size.map_or(false, |s| block.decoded > s) {
+            return corrupt("xz block size mismatch")
+        }
+        Ok(n)
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        self.input.r.extend(input);
+        let mut produced = 0;
+        loop {
+            if self.eof {
+                // the input gathered past the end of the file isn't used
+                let pos = input.len() - cmp::min(self.input.r.data().len(), input.len());
+                return Ok((pos, produced, Status::Done))
+            }
+            if self.block.is_some() {
+                if produced == output.len() {
+                    return Ok((input.len(), produced, Status::NeedOutput))
+                }
+                // the data goes on from what was read when it ran out
+                match self.read_data(&mut output[produced..]) {
+                    Ok(0) => {}
+                    Ok(n) => {
+                        produced += n;
+                        continue
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        return Ok((input.len(), produced, Status::NeedInput))
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            if !self.input.r.is_ready() {
+                return Ok((input.len(), produced, Status::NeedInput))
+            }
+            let (mark, count) = (self.input.r.mark(), self.input.count);
+            let result = if self.block.is_some() { self.end_block() } else { self.next_part() };
+            match result {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.input.r.rollback(mark);
+                    self.input.count = count;
+                    return Ok((input.len(), produced, Status::NeedInput))
+                }
+                result => try!(result),
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the parts left may come from the input gathered, and reading past
+        // it fails as truncated
+        self.input.r.finish();
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a xz file. The wrapped stream can be
+/// re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new xz decoder which will wrap the specified reader.
+    /// This decoder also implements the `Reader` trait, and the underlying
+    /// reader can be re-acquired through the `unwrap` method.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new()),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Tests whether the end of the file has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.processor().eof()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read};
+    use filter::bcj::Arch;
+    use testing::{noise, push, Lcg};
+    use super::{Decoder, Processor};
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        out
+    }
+
+    /// The input of test.multi.xz, incompressible then repeated data, in
+    /// blocks of 5000 bytes
+    fn multi() -> Vec<u8> {
+        let mut data = noise(4000, 1);
+        for _ in 0..4 {
+            data.extend(include_bytes!("../data/test.txt").iter().cloned());
+        }
+        data
+    }
+
+    #[test]
+    fn decode_file() {
+        assert!(&decode(include_bytes!("../data/test.xz"))[..] ==
+                &include_bytes!("../data/test.txt")[..]);
+        assert!(decode(include_bytes!("../data/test.multi.xz")) == multi());
+    }
+
+    #[test]
+    fn decode_push() {
+        let input = include_bytes!("../data/test.multi.xz");
+        for &step in [1, 333, 1 << 16].iter() {
+            assert!(push(&mut Processor::new(), input, step).unwrap() == multi());
+        }
+        let input = include_bytes!("../data/test.x86.xz");
+        assert!(push(&mut Processor::new(), input, 7).unwrap() == code(Arch::X86));
+        let input = include_bytes!("../data/test.xz");
+        for &len in [0, 10, 100, input.len() - 1].iter() {
+            assert!(push(&mut Processor::new(), &input[..len], 100).is_err());
+        }
+    }
+
+    #[test]
+    fn reset() {
+        // a stream left halfway doesn't affect the next one
+        let mut d = Decoder::new(BufReader::new(&include_bytes!("../data/test.multi.xz")[..]));
+        let mut out = [0; 5000];
+        d.read_exact(&mut out).unwrap();
+        d.reset(BufReader::new(&include_bytes!("../data/test.xz")[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(&out[..] == &include_bytes!("../data/test.txt")[..]);
+        d.reset(BufReader::new(&include_bytes!("../data/test.multi.xz")[..]));
+        out.truncate(0);
+        d.read_to_end(&mut out).unwrap();
+        assert!(out == multi());
+    }
+
+    /// The input of test.x86.xz, test.arm.xz and test.arm64.xz, as code
+    /// calling 16 functions
+    fn code(arch: Arch) -> Vec<u8> {
+        let mut lcg = Lcg(1);
+        let mut out = Vec::new();
+        let push = |out: &mut Vec<u8>, word: u32| {
+            out.extend([word as u8, (word >> 8) as u8, (word >> 16) as u8, (word >> 24) as u8].iter())
+        };
+        while out.len() < 8192 {
+            let seed = lcg.step();
+            let r = seed >> 16;
+            let target = 0x10000 + 0x100 * ((r >> 4) % 16);
+            let pos = out.len() as u32;
+            match (arch, r % 4) {
+                (Arch::X86, 0) => {
+                    out.push(0xe8);
+                    push(&mut out, target.wrapping_sub(pos + 5));
+                }
+                (Arch::X86, _) => out.push([0x89, 0x48, 0x8b, 0x0f][((r >> 8) % 4) as usize]),
+                (Arch::Arm, 0) => push(&mut out, 0xeb000000 | ((target - (pos + 8)) >> 2) & 0xffffff),
+                (Arch::Arm64, 0) => push(&mut out, 0x94000000 | ((target - pos) >> 2) & 0x03ffffff),
+                (Arch::Arm64, 1) => {
+                    let imm = (target >> 12).wrapping_sub(pos >> 12);
+                    push(&mut out, 0x90000000 | (imm & 3) << 29 | ((imm >> 2) & 0x7ffff) << 5 | (r & 31))
+                }
+                (Arch::Arm, _) => {
+                    let words = [0xe1a00000, 0xe5901000, 0xe3a00001, 0xe12fff1e];
+                    push(&mut out, words[((r >> 8) % 4) as usize])
+                }
+                (Arch::Arm64, _) => {
+                    let words = [0xaa0103e0, 0xf9400000, 0x52800020, 0xd65f03c0];
+                    push(&mut out, words[((r >> 8) % 4) as usize])
+                }
+            }
+        }
+        out.truncate(8192);
+        out
+    }
+
+    #[test]
+    fn bcj_filters() {
+        // the ARM64 code was filtered from the offset 4096
+        assert!(decode(include_bytes!("../data/test.x86.xz")) == code(Arch::X86));
+        assert!(decode(include_bytes!("../data/test.arm.xz")) == code(Arch::Arm));
+        assert!(decode(include_bytes!("../data/test.arm64.xz")) == code(Arch::Arm64));
+        let input = include_bytes!("../data/test.x86.xz");
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        let mut out = Vec::new();
+        let mut buf = [0u8; 7];
+        loop {
+            match d.read(&mut buf).unwrap() {
+                0 => break,
+                n => out.extend(buf[..n].iter().cloned()),
+            }
+        }
+        assert!(out == code(Arch::X86));
+    }
+
+    #[test]
+    fn concatenated_streams() {
+        let mut input = include_bytes!("../data/test.xz").to_vec();
+        input.extend([0u8; 8].iter().cloned());
+        input.extend(include_bytes!("../data/test.multi.xz").iter().cloned());
+        input.extend([0u8; 4].iter().cloned());
+        let mut expected = include_bytes!("../data/test.txt").to_vec();
+        expected.extend(multi().into_iter());
+        assert!(decode(&input) == expected);
+
+        // padding must come in groups of 4 bytes
+        input.push(0);
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn small_reads() {
+        let input = include_bytes!("../data/test.multi.xz");
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        let mut out = Vec::new();
+        let mut buf = [0u8; 7];
+        loop {
+            match d.read(&mut buf).unwrap() {
+                0 => break,
+                n => out.extend(buf[..n].iter().cloned()),
+            }
+        }
+        assert!(out == multi());
+    }
+
+    #[test]
+    fn corrupted() {
+        let input = include_bytes!("../data/test.xz");
+        // stream header, block header, data, then block check, index and footer
+        for &pos in [8, 14, input.len() / 2, input.len() - 30, input.len() - 20,
+                     input.len() - 8].iter() {
+            let mut bad = input.to_vec();
+            bad[pos] ^= 0x01;
+            let mut d = Decoder::new(BufReader::new(&bad[..]));
+            assert!(d.read_to_end(&mut Vec::new()).is_err(), "undetected at {}", pos);
+        }
+        let mut d = Decoder::new(BufReader::new(&input[..100]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+        let mut d = Decoder::new(BufReader::new(&b""[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}
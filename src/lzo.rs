@@ -0,0 +1,631 @@
+/*!
+
+LZO Decompression. Requires `lzo` feature, enabled by default
+
+This module contains a decoder of LZO1X blocks, as produced by all the LZO1X
+compressors (1, 1_15, 999), and of the lzop file format wrapping them, with
+the verification of its Adler-32 or CRC-32 checksums. Archives produced by
+`lzop` are exposed as a standard `Reader` wrapping an underlying stream.
+
+# Example
+
+```rust,ignore
+use compress::lzo;
+use std::fs::File;
+use std::path::Path;
+use std::io::Read;
+
+let stream = File::open(&Path::new("path/to/file.lzo")).unwrap();
+let mut decompressed = Vec::new();
+lzo::Decoder::new(stream).read_to_end(&mut decompressed);
+```
+
+# Related links
+
+* https://www.kernel.org/doc/Documentation/staging/lzo.rst - a description of
+  the LZO1X instructions
+* https://www.lzop.org/ - the lzop tool, whose sources describe the file format
+
+*/
+
+use std::cmp;
+use std::io::{self, Read};
+
+use checksum::{adler, crc32};
+use copy::copy_match;
+use error::{checksum_mismatch, corrupt, truncated, unsupported};
+use process::{drain, gather, Process, Pump, Status};
+
+const MAGIC: [u8; 9] = [0x89, b'L', b'Z', b'O', 0x00, b'\r', b'\n', 0x1a, b'\n'];
+/// First version of lzop with the extended header fields
+const VERSION_EXTENDED: u16 = 0x0940;
+/// Largest block lzop produces
+const MAX_BLOCK_SIZE: usize = 64 * 1024 * 1024;
+
+const F_ADLER32_D: u32 = 0x1;
+const F_ADLER32_C: u32 = 0x2;
+const F_H_EXTRA_FIELD: u32 = 0x40;
+const F_CRC32_D: u32 = 0x100;
+const F_CRC32_C: u32 = 0x200;
+const F_H_FILTER: u32 = 0x800;
+const F_H_CRC32: u32 = 0x1000;
+
+/// Compression methods, all producing LZO1X blocks
+const M_LZO1X_1: u8 = 1;
+const M_LZO1X_1_15: u8 = 2;
+const M_LZO1X_999: u8 = 3;
+
+/// Distance of a match which marks the end of the block instead
+const EOF_DISTANCE: usize = 0x4000;
+
+struct BlockDecoder<'a> {
+    input: &'a [u8],
+    output: &'a mut Vec<u8>,
+    pos: usize,
+    start: usize,
+    limit: usize,
+}
+
+impl<'a> BlockDecoder<'a> {
+    fn byte(&mut self) -> io::Result<u8> {
+        match self.input.get(self.pos) {
+            Some(&b) => {
+                self.pos += 1;
+                Ok(b)
+            }
+            None => truncated("truncated lzo block"),
+        }
+    }
+
+    fn le16(&mut self) -> io::Result<usize> {
+        let lo = try!(self.byte()) as usize;
+        Ok(lo | (try!(self.byte()) as usize) << 8)
+    }
+
+    /// Lengths not fitting in their instruction are continued by 255 for
+    /// every zero byte, then the next byte
+    fn length(&mut self, bits: u8, base: usize) -> io::Result<usize> {
+        if bits != 0 {
+            return Ok(bits as usize)
+        }
+        let mut len = base;
+        loop {
+            match try!(self.byte()) {
+                0 => len += 255,
+                b => return Ok(len + b as usize),
+            }
+            if len > self.limit {
+                return corrupt("invalid lzo length")
+            }
+        }
+    }
+
+    fn literals(&mut self, len: usize) -> io::Result<()> {
+        if len > self.input.len() - self.pos {
+            return truncated("truncated lzo block")
+        }
+        if self.output.len() - self.start + len > self.limit {
+            return corrupt("lzo block is too large")
+        }
+        self.output.extend(self.input[self.pos..(self.pos + len)].iter().cloned());
+        self.pos += len;
+        Ok(())
+    }
+
+    fn copy(&mut self, dist: usize, len: usize) -> io::Result<()> {
+        let done = self.output.len() - self.start;
+        if dist > done {
+            return corrupt("invalid lzo match distance")
+        }
+        if done + len > self.limit {
+            return corrupt("lzo block is too large")
+        }
+        copy_match(&mut self.output, dist, len);
+        Ok(())
+    }
+
+    fn decode(&mut self) -> io::Result<usize> {
+        // number of literals copied by the last instruction, up to 4, which
+        // gives the meaning of the short instructions
+        let mut state = 0;
+        if self.input.first().map_or(false, |&b| b > 17) {
+            let n = (try!(self.byte()) - 17) as usize;
+            try!(self.literals(n));
+            state = cmp::min(n, 4);
+        }
+        loop {
+            let t = try!(self.byte());
+            let (dist, len, next) = if t < 16 {
+                match state {
+                    0 => {
+                        let n = try!(self.length(t, 15));
+                        try!(self.literals(n + 3));
+                        state = 4;
+                        continue
+                    }
+                    4 => {
+                        let h = try!(self.byte()) as usize;
+                        ((h << 2) + ((t as usize >> 2) & 3) + 2049, 3, t & 3)
+                    }
+                    _ => {
+                        let h = try!(self.byte()) as usize;
+                        ((h << 2) + ((t as usize >> 2) & 3) + 1, 2, t & 3)
+                    }
+                }
+            } else if t < 32 {
+                let len = try!(self.length(t & 7, 7)) + 2;
+                let v = try!(self.le16());
+                let dist = EOF_DISTANCE + ((t as usize & 8) << 11) + (v >> 2);
+                if dist == EOF_DISTANCE {
+                    return Ok(self.pos)
+                }
+                (dist, len, v as u8 & 3)
+            } else if t < 64 {
+                let len = try!(self.length(t & 31, 31)) + 2;
+                let v = try!(self.le16());
+                ((v >> 2) + 1, len, v as u8 & 3)
+            } else {
+                let h = try!(self.byte()) as usize;
+                let len = if t < 128 { 3 + ((t >> 5) & 1) } else { 5 + ((t >> 5) & 3) };
+                ((h << 3) + ((t as usize >> 2) & 7) + 1, len as usize, t & 3)
+            };
+            try!(self.copy(dist, len));
+            try!(self.literals(next as usize));
+            state = next as usize;
+        }
+    }
+}
+
+/// Decodes a LZO1X block into `output`, producing no more than `limit` bytes.
+/// Returns the number of bytes of the input used, up to the end of block
+/// marker.
+pub fn decode_block(input: &[u8], output: &mut Vec<u8>, limit: usize) -> io::Result<usize> {
+    let start = output.len();
+    BlockDecoder {
+        input: input,
+        output: output,
+        pos: 0,
+        start: start,
+        limit: limit,
+    }.decode()
+}
+
+/// Checks a block against the checksums the flags ask for
+fn verify(data: &[u8], flags: u32, adler_flag: u32, crc_flag: u32,
+          adler_sum: u32, crc_sum: u32) -> io::Result<()> {
+    if flags & adler_flag != 0 {
+        let mut state = adler::State32::new();
+        state.feed(data);
+        if state.result() != adler_sum {
+            return checksum_mismatch("invalid lzop block checksum")
+        }
+    }
+    if flags & crc_flag != 0 {
+        let mut state = crc32::State32::new();
+        state.feed(data);
+        if state.result() != crc_sum {
+            return checksum_mismatch("invalid lzop block checksum")
+        }
+    }
+    Ok(())
+}
+
+/// Reads the big-endian 32-bit number at the start of `bytes`
+fn be32(bytes: &[u8]) -> u32 {
+    bytes[..4].iter().fold(0, |n, &b| n << 8 | b as u32)
+}
+
+/// Returns the version of lzop which wrote the header
+fn version(h: &[u8]) -> u16 {
+    (h[0] as u16) << 8 | h[1] as u16
+}
+
+/// Length of the fields of the header before the name, which end with its
+/// length
+fn fixed_len(h: &[u8]) -> usize {
+    if version(h) >= VERSION_EXTENDED { 25 } else { 18 }
+}
+
+/// Length of the header up to its checksum, once enough of it is gathered
+/// to tell
+fn header_len(h: &[u8]) -> usize {
+    if h.len() < 4 {
+        return 4
+    }
+    let fixed = fixed_len(h);
+    if h.len() < fixed {
+        return fixed
+    }
+    fixed + h[fixed - 1] as usize + 4
+}
+
+/// The part of the file a `Processor` expects next
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Magic,
+    Header,
+    Extra,
+    /// The sizes and the checksums of a block
+    BlockHead,
+    /// The data of a block of this size
+    Block(usize),
+    End,
+}
+
+/// This structure is used to decode a lzop file, fed with slices of it
+pub struct Processor {
+    state: State,
+    head: Vec<u8>,
+    temp: Vec<u8>,
+    output: Vec<u8>,
+    start: usize,
+
+    flags: u32,
+    name: Vec<u8>,
+}
+
+impl Processor {
+    /// Creates a new decoder, waiting for the header of a file
+    pub fn new() -> Processor {
+        Processor {
+            state: State::Magic,
+            head: Vec::new(),
+            temp: Vec::new(),
+            output: Vec::new(),
+            start: 0,
+            flags: 0,
+            name: Vec::new(),
+        }
+    }
+
+    /// Starts decoding a new file, keeping the buffers
+    pub fn reset(&mut self) {
+        self.state = State::Magic;
+        self.head.truncate(0);
+        self.temp.truncate(0);
+        self.output.truncate(0);
+        self.start = 0;
+        self.flags = 0;
+        self.name.truncate(0);
+    }
+
+    /// Returns the name of the original file, as stored in the header once it
+    /// has been read
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    fn checksum(&self, data: &[u8]) -> u32 {
+        if self.flags & F_H_CRC32 != 0 {
+            let mut state = crc32::State32::new();
+            state.feed(data);
+            state.result()
+        } else {
+            let mut state = adler::State32::new();
+            state.feed(data);
+            state.result()
+        }
+    }
+
+    /// Reads the header gathered in `head`, which its checksum ends
+    fn read_header(&mut self) -> io::Result<()> {
+        let h = &self.head[..(self.head.len() - 4)];
+        let extended = version(h) >= VERSION_EXTENDED;
+        let (method, flags) = if extended { (h[6], &h[8..]) } else { (h[4], &h[5..]) };
+        self.flags = be32(flags);
+        if self.flags & F_H_FILTER != 0 {
+            return unsupported("unsupported lzop filter")
+        }
+        self.name = h[fixed_len(h)..].to_vec();
+        if be32(&self.head[h.len()..]) != self.checksum(h) {
+            return checksum_mismatch("invalid lzop header checksum")
+        }
+        match method {
+            M_LZO1X_1 | M_LZO1X_1_15 | M_LZO1X_999 => Ok(()),
+            _ => unsupported("unsupported lzop compression method"),
+        }
+    }
+
+    /// Length of the sizes and the checksums of the block, once enough of
+    /// them is gathered to tell
+    fn block_head_len(&self) -> usize {
+        let h = &self.head;
+        // a size of 0 ends the file
+        if h.len() < 4 || be32(h) == 0 {
+            return 4
+        }
+        if h.len() < 8 {
+            return 8
+        }
+        let compressed = be32(&h[4..]) < be32(h);
+        let mut sums = [F_ADLER32_D, F_CRC32_D].iter().filter(|&&f| self.flags & f != 0).count();
+        if compressed {
+            sums += [F_ADLER32_C, F_CRC32_C].iter().filter(|&&f| self.flags & f != 0).count();
+        }
+        8 + 4 * sums
+    }
+
+    /// Decodes the block gathered in `temp`, of the sizes and the checksums
+    /// in `head`
+    fn decode_block(&mut self) -> io::Result<()> {
+        let flags = self.flags;
+        let dst_len = be32(&self.head) as usize;
+        let src_len = self.temp.len();
+        let mut sums = self.head[8..].chunks(4).map(be32);
+        let mut sum = |flag| if flags & flag != 0 { sums.next().unwrap() } else { 0 };
+        let d_adler = sum(F_ADLER32_D);
+        let d_crc = sum(F_CRC32_D);
+        let compressed = src_len < dst_len;
+        let c_adler = if compressed { sum(F_ADLER32_C) } else { 0 };
+        let c_crc = if compressed { sum(F_CRC32_C) } else { 0 };
+
+        self.output.truncate(0);
+        self.start = 0;
+        if compressed {
+            try!(verify(&self.temp, flags, F_ADLER32_C, F_CRC32_C, c_adler, c_crc));
+            let used = try!(decode_block(&self.temp, &mut self.output, dst_len));
+            if used != src_len || self.output.len() != dst_len {
+                return corrupt("lzop block size mismatch")
+            }
+        } else {
+            self.output.extend_from_slice(&self.temp);
+        }
+        verify(&self.output, flags, F_ADLER32_D, F_CRC32_D, d_adler, d_crc)
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.output, &mut self.start, output, &mut produced) {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            let gathered = match self.state {
+                State::Magic => gather(&mut self.head, input, &mut pos, MAGIC.len()),
+                State::Header => {
+                    let len = header_len(&self.head);
+                    gather(&mut self.head, input, &mut pos, len)
+                }
+                State::Extra => {
+                    let len = if self.head.len() < 4 { 4 } else { 8 + be32(&self.head) as usize };
+                    gather(&mut self.head, input, &mut pos, len)
+                }
+                State::BlockHead => {
+                    let len = self.block_head_len();
+                    gather(&mut self.head, input, &mut pos, len)
+                }
+                State::Block(len) => gather(&mut self.temp, input, &mut pos, len),
+                State::End => return Ok((pos, produced, Status::Done)),
+            };
+            if !gathered {
+                return Ok((pos, produced, Status::NeedInput))
+            }
+
+            // the fields gathered may tell that more follow
+            self.state = match self.state {
+                State::Magic => {
+                    if &self.head[..] != &MAGIC[..] {
+                        return corrupt("invalid lzop magic number")
+                    }
+                    self.head.truncate(0);
+                    State::Header
+                }
+                State::Header if self.head.len() < header_len(&self.head) => continue,
+                State::Header => {
+                    try!(self.read_header());
+                    self.head.truncate(0);
+                    if self.flags & F_H_EXTRA_FIELD != 0 { State::Extra } else { State::BlockHead }
+                }
+                State::Extra if self.head.len() == 4 => continue,
+                State::Extra => {
+                    let len = self.head.len() - 4;
+                    if be32(&self.head[len..]) != self.checksum(&self.head[..len]) {
+                        return checksum_mismatch("invalid lzop header checksum")
+                    }
+                    self.head.truncate(0);
+                    State::BlockHead
+                }
+                State::BlockHead if self.head.len() < self.block_head_len() => continue,
+                State::BlockHead => {
+                    let dst_len = be32(&self.head) as usize;
+                    if dst_len == 0 {
+                        State::End
+                    } else if dst_len > MAX_BLOCK_SIZE {
+                        return corrupt("lzop block is too large")
+                    } else {
+                        let src_len = be32(&self.head[4..]) as usize;
+                        if src_len > dst_len {
+                            return corrupt("invalid lzop block size")
+                        }
+                        State::Block(src_len)
+                    }
+                }
+                State::Block(_) => {
+                    try!(self.decode_block());
+                    self.head.truncate(0);
+                    self.temp.truncate(0);
+                    State::BlockHead
+                }
+                State::End => State::End,
+            };
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.state != State::End {
+            return truncated("truncated lzop file")
+        }
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a lzop file. This wraps an internal reader
+/// which is read from when this decoder's read method is called.
+pub struct Decoder<R> {
+    /// The internally wrapped reader. This is exposed so it may be moved out
+    /// of. Note that the data read ahead of the output is lost with it, and
+    /// that if data is read from the reader while decoding is in progress the
+    /// output stream will get corrupted.
+    pub r: R,
+
+    p: Processor,
+    pump: Pump,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream. The
+    /// inner stream can be re-acquired by moving out of the `r` field of this
+    /// structure.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r: r,
+            p: Processor::new(),
+            pump: Pump::new(),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.pump.set_max_output_size(size);
+    }
+
+    /// Returns the name of the original file, as stored in the header once it
+    /// has been read
+    pub fn name(&self) -> &[u8] {
+        self.p.name()
+    }
+
+    /// Resets this decoder back to its initial state, keeping its buffers.
+    /// Note that the underlying stream is not seeked on or has any
+    /// alterations performed on it, while the data read ahead from it is
+    /// dropped.
+    pub fn reset(&mut self) {
+        self.p.reset();
+        self.pump.reset();
+    }
+
+    /// Tests whether the end of this lzop file has been reached
+    pub fn eof(&self) -> bool { self.pump.is_done() }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.pump.read(&mut self.r, &mut self.p, dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read};
+    use testing::{noise, push};
+    use super::{Decoder, Processor, decode_block};
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        assert_eq!(decode_block(input, &mut out, 1 << 20).unwrap(), input.len());
+        out
+    }
+
+    #[test]
+    fn instructions() {
+        assert_eq!(decode(&[0x11, 0, 0]), b"");
+        assert_eq!(decode(&[22, b'h', b'e', b'l', b'l', b'o', 0x11, 0, 0]), b"hello");
+        // a 8-byte match overlapping its source
+        assert_eq!(decode(&[20, b'a', b'b', b'c', 0xe8, 0, 0x11, 0, 0]), b"abcabcabcab");
+        // a match followed by a literal, then a 2-byte match
+        assert_eq!(decode(&[19, b'a', b'b', 0x45, 0, b'x', 0x04, 0, 0x11, 0, 0]), b"ababaxax");
+        // a long literal run, then a 32-byte match
+        let mut input = vec![0, 2];
+        input.extend(b"0123456789abcdefghij".iter().cloned());
+        input.extend([0x3e, 0x4c, 0, 0x11, 0, 0].iter().cloned());
+        assert_eq!(decode(&input), &b"0123456789abcdefghij0123456789abcdefghij0123456789ab"[..]);
+    }
+
+    #[test]
+    fn far_matches() {
+        let noise = noise(20000, 1);
+        // a literal run of 20000 bytes
+        let mut input = vec![0];
+        input.extend((0..78).map(|_| 0));
+        input.push((20000 - 18 - 78 * 255) as u8);
+        input.extend(noise.iter().cloned());
+        // 3 bytes from 2049 bytes back, then 100 bytes from 20000 bytes back
+        input.extend([0, 0].iter().cloned());
+        input.extend([0x10, 91, (3616 << 2) as u8, (3616 >> 6) as u8].iter().cloned());
+        input.extend([0x11, 0, 0].iter().cloned());
+
+        let mut expected = noise.clone();
+        for _ in 0..3 {
+            let b = expected[expected.len() - 2049];
+            expected.push(b);
+        }
+        for _ in 0..100 {
+            let b = expected[expected.len() - 20000];
+            expected.push(b);
+        }
+        assert!(decode(&input) == expected);
+    }
+
+    #[test]
+    fn invalid_blocks() {
+        for input in [&[22, b'h', b'e', b'l', b'l', b'o'][..], // no end marker
+                      &[22, b'h', b'e'], // truncated literal
+                      &[20, b'a', b'b', b'c', 0xfc, 0, 0x11, 0, 0], // too far
+                      &[0x20, 0, 0, 0x11, 0, 0]].iter() {
+            assert!(decode_block(input, &mut Vec::new(), 1 << 20).is_err());
+        }
+        let input = [20, b'a', b'b', b'c', 0xe8, 0, 0x11, 0, 0];
+        assert!(decode_block(&input, &mut Vec::new(), 10).is_err());
+    }
+
+    #[test]
+    fn decode_file() {
+        let input = include_bytes!("data/test.lzo");
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        let mut out = Vec::new();
+        let mut buf = [0u8; 7];
+        loop {
+            match d.read(&mut buf).unwrap() {
+                0 => break,
+                n => out.extend(buf[..n].iter().cloned()),
+            }
+        }
+        assert!(d.eof());
+        assert_eq!(d.name(), b"test.txt");
+        assert!(&out[..] == &include_bytes!("data/test.txt")[..]);
+        assert!(&push(&mut Processor::new(), input, 13).unwrap()[..] == &include_bytes!("data/test.txt")[..]);
+    }
+
+    #[test]
+    fn reset() {
+        let input = include_bytes!("data/test.lzo");
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        let mut buf = [0u8; 1000];
+        d.read_exact(&mut buf).unwrap();
+        d.r = BufReader::new(&input[..]);
+        d.reset();
+        assert_eq!(d.name(), b"");
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert_eq!(d.name(), b"test.txt");
+        assert!(&out[..] == &include_bytes!("data/test.txt")[..]);
+    }
+
+    #[test]
+    fn corrupted() {
+        let input = include_bytes!("data/test.lzo");
+        // header, block checksum, then somewhere in the data
+        for &pos in [12, 50, input.len() / 2].iter() {
+            let mut bad = input.to_vec();
+            bad[pos] ^= 0x10;
+            let mut d = Decoder::new(BufReader::new(&bad[..]));
+            assert!(d.read_to_end(&mut Vec::new()).is_err());
+        }
+        let mut d = Decoder::new(BufReader::new(&input[..100]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,562 @@
+/*!
+
+LZP Decompression and Compression. Requires `lzp` feature, enabled by default
+
+This module contains a preprocessing stage removing the long repeats of the
+data, to be run before the BWT or an entropy coder. For each position, the
+hash of the 4 bytes before it predicts the position of a match, which is the
+last one following the same hash: no distance is stored, but a flag and the
+length of the match, when it is long enough. The stage is cheaper than the
+sort of the BWT, whose speed suffers on repetitive data, and leaves the short
+repeats to it.
+
+The blocks start with the byte used as a flag, which is the rarest of the
+block, and the minimum length of the matches. The other bytes are the
+literals, but for the flag at the positions following a known hash: it is
+followed either by 0, for a literal flag, or by the length of a match minus
+the minimum plus one, as a sum of bytes ended by a byte which is not 255. The
+streams are cut into blocks of 1 MB at most, each preceded by its size and the
+size of its data, as two 32-bit little-endian integers.
+
+# Example
+
+```rust
+use std::io::{Read, Write};
+use compress::lzp;
+
+let text = b"there is no place like home, there is no place like home.";
+let mut e = lzp::Encoder::new(Vec::new(), 16);
+e.write_all(text).unwrap();
+let (encoded, result) = e.finish();
+result.unwrap();
+
+let mut d = lzp::Decoder::new(&encoded[..]);
+let mut decoded = Vec::new();
+d.read_to_end(&mut decoded).unwrap();
+assert_eq!(&decoded[..], &text[..]);
+```
+
+# Related links
+
+* http://www.cbloom.com/src/index_lz.html - LZP, by Charles Bloom
+* https://github.com/IlyaGrebnov/libbsc - bsc, which runs LZP before its BWT
+
+*/
+
+use std::cmp;
+use std::mem;
+use std::io::{self, Read, Write};
+
+use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use error::{corrupt, limit_exceeded, truncated};
+use hash;
+use histogram::histogram;
+use process::{drain, gather, Process, Reader, Status};
+
+/// Largest block of the streams
+pub const BLOCK_SIZE: usize = 1 << 20;
+/// Number of bytes hashed to predict the matches
+pub const ORDER: usize = 4;
+
+const HASH_BITS: u32 = 18;
+/// Blocks up to this size keep the last positions in a `Table::Short`
+const SHORT_BLOCK: usize = 1 << 12;
+
+/// Hashes the `ORDER` bytes before `pos`
+fn hash(data: &[u8], pos: usize) -> usize {
+    hash::hash4(&data[(pos - ORDER)..], HASH_BITS)
+}
+
+/// The last position following each hash. The short blocks, which set few
+/// of them, keep them in an open addressing table sized after the block, with
+/// the same contents as the full one but no large table to clear.
+enum Table {
+    Full(Vec<u32>),
+    /// The hashes plus 1, or 0 for free slots, and their positions
+    Short(Vec<(u32, u32)>),
+}
+
+impl Table {
+    /// Creates the table of a block of `len` bytes
+    fn new(len: usize) -> Table {
+        if len <= SHORT_BLOCK {
+            Table::Short(vec![(0, 0); 2 * len.next_power_of_two()])
+        } else {
+            Table::Full(vec![0; 1 << HASH_BITS])
+        }
+    }
+
+    /// Sets the position of hash `h`, returning the previous one or 0
+    #[inline]
+    fn replace(&mut self, h: usize, pos: u32) -> u32 {
+        match *self {
+            Table::Full(ref mut table) => {
+                let pred = table[h];
+                table[h] = pos;
+                pred
+            }
+            Table::Short(ref mut table) => {
+                // there are more slots than positions, so that one is free
+                let mask = table.len() - 1;
+                let mut i = h & mask;
+                while table[i].0 != 0 && table[i].0 != h as u32 + 1 {
+                    i = (i + 1) & mask;
+                }
+                let pred = table[i].1;
+                table[i] = (h as u32 + 1, pos);
+                pred
+            }
+        }
+    }
+}
+
+/// Returns the maximum size of the block encoding `size` bytes
+pub fn max_compressed_len(size: usize) -> usize {
+    2 + 2 * size
+}
+
+/// Encodes a block of data into `output`, replacing the matches of at least
+/// `min_len` bytes, from 4 to 255
+pub fn encode_block(input: &[u8], min_len: usize, output: &mut Vec<u8>) {
+    assert!(min_len >= ORDER && min_len <= 255, "invalid lzp minimum length {}", min_len);
+    let freq = histogram(input);
+    let flag = (0..256).min_by_key(|&b| freq[b]).unwrap() as u8;
+    output.push(flag);
+    output.push(min_len as u8);
+    let mut table = Table::new(input.len());
+    let head = cmp::min(ORDER, input.len());
+    output.extend(input[..head].iter().cloned());
+    let mut pos = head;
+    while pos < input.len() {
+        let pred = table.replace(hash(input, pos), pos as u32) as usize;
+        let byte = input[pos];
+        if pred == 0 {
+            output.push(byte);
+            pos += 1;
+            continue
+        }
+        let len = input[pos..].iter().zip(input[pred..].iter())
+                              .take_while(|&(a, b)| a == b).count();
+        if len >= min_len {
+            output.push(flag);
+            let mut rest = len - min_len + 1;
+            while rest >= 255 {
+                output.push(255);
+                rest -= 255;
+            }
+            output.push(rest as u8);
+            pos += len;
+        } else {
+            output.push(byte);
+            if byte == flag {
+                output.push(0);
+            }
+            pos += 1;
+        }
+    }
+}
+
+/// Decodes a block into `output`, producing no more than `limit` bytes
+pub fn decode_block(input: &[u8], output: &mut Vec<u8>, limit: usize) -> io::Result<()> {
+    if input.len() < 2 {
+        return truncated("truncated lzp block")
+    }
+    let (flag, min_len) = (input[0], input[1] as usize);
+    if min_len < ORDER {
+        return corrupt("invalid lzp minimum length")
+    }
+    let base = output.len();
+    let mut table = Table::new(limit);
+    let mut iter = input[2..].iter();
+    loop {
+        let pos = output.len() - base;
+        let byte = match iter.next() {
+            Some(&byte) => byte,
+            None => return Ok(()),
+        };
+        if pos >= limit {
+            return limit_exceeded("lzp data exceeds the limit")
+        }
+        if pos < ORDER {
+            output.push(byte);
+            continue
+        }
+        let pred = table.replace(hash(&output[base..], pos), pos as u32) as usize;
+        if pred == 0 || byte != flag {
+            output.push(byte);
+            continue
+        }
+        let mut len = 0;
+        loop {
+            match iter.next() {
+                Some(&255) => len += 255,
+                Some(&n) => {
+                    len += n as usize;
+                    break
+                }
+                None => return truncated("truncated lzp match"),
+            }
+        }
+        if len == 0 {
+            output.push(flag);
+            continue
+        }
+        len += min_len - 1;
+        if len > limit - pos {
+            return limit_exceeded("lzp data exceeds the limit")
+        }
+        for i in 0..len {
+            let b = output[base + pred + i];
+            output.push(b);
+        }
+    }
+}
+
+/// This structure is used to compress a stream of bytes using LZP. This is a
+/// wrapper around an internal writer which bytes will be written to.
+pub struct Encoder<W> {
+    w: W,
+    min_len: usize,
+    buf: Vec<u8>,
+    packed: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will replace the matches of at least
+    /// `min_len` bytes, from 4 to 255, and have its output written to the
+    /// given output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(w: W, min_len: usize) -> Encoder<W> {
+        assert!(min_len >= ORDER && min_len <= 255, "invalid lzp minimum length {}", min_len);
+        Encoder {
+            w: w,
+            min_len: min_len,
+            buf: Vec::new(),
+            packed: Vec::new(),
+        }
+    }
+
+    fn encode_block(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(())
+        }
+        self.packed.truncate(0);
+        encode_block(&self.buf, self.min_len, &mut self.packed);
+        try!(self.w.write_u32::<LittleEndian>(self.buf.len() as u32));
+        try!(self.w.write_u32::<LittleEndian>(self.packed.len() as u32));
+        try!(self.w.write_all(&self.packed));
+        self.buf.truncate(0);
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.flush();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`
+    /// with the same minimum match length, keeping the buffers. The previous
+    /// writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.flush();
+        self.buf.truncate(0);
+        (mem::replace(&mut self.w, w), result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let amt = cmp::min(BLOCK_SIZE - self.buf.len(), rest.len());
+            self.buf.extend(rest[..amt].iter().cloned());
+            rest = &rest[amt..];
+            if self.buf.len() == BLOCK_SIZE {
+                try!(self.encode_block());
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.encode_block());
+        self.w.flush()
+    }
+}
+
+/// The part of the stream a `Processor` expects next
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// The sizes of the next block
+    Header,
+    /// The packed data of a block of this size
+    Block(usize),
+}
+
+/// This structure is used to decode a stream of LZP blocks, fed with slices
+/// of it. The stream ends with its input.
+pub struct Processor {
+    state: State,
+    head: Vec<u8>,
+    packed: Vec<u8>,
+    output: Vec<u8>,
+    start: usize,
+    eof: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder, waiting for the first block
+    pub fn new() -> Processor {
+        Processor {
+            state: State::Header,
+            head: Vec::new(),
+            packed: Vec::new(),
+            output: Vec::new(),
+            start: 0,
+            eof: false,
+        }
+    }
+
+    /// Starts decoding a new stream, keeping the buffers
+    pub fn reset(&mut self) {
+        self.state = State::Header;
+        self.head.truncate(0);
+        self.packed.truncate(0);
+        self.output.truncate(0);
+        self.start = 0;
+        self.eof = false;
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.output, &mut self.start, output, &mut produced) {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            let gathered = match self.state {
+                State::Header => gather(&mut self.head, input, &mut pos, 8),
+                State::Block(_) => {
+                    let packed_len = (&self.head[4..]).read_u32::<LittleEndian>().unwrap() as usize;
+                    gather(&mut self.packed, input, &mut pos, packed_len)
+                }
+            };
+            if !gathered {
+                let status = if self.eof { Status::Done } else { Status::NeedInput };
+                return Ok((pos, produced, status))
+            }
+
+            self.state = match self.state {
+                State::Header => {
+                    let len = (&self.head[..]).read_u32::<LittleEndian>().unwrap() as usize;
+                    let packed_len = (&self.head[4..]).read_u32::<LittleEndian>().unwrap() as usize;
+                    if len > BLOCK_SIZE || packed_len > max_compressed_len(len) {
+                        return corrupt("invalid lzp block")
+                    }
+                    State::Block(len)
+                }
+                State::Block(len) => {
+                    self.output.truncate(0);
+                    self.start = 0;
+                    try!(decode_block(&self.packed, &mut self.output, len));
+                    if self.output.len() != len {
+                        return truncated("truncated lzp block")
+                    }
+                    self.head.truncate(0);
+                    self.packed.truncate(0);
+                    State::Header
+                }
+            };
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the stream ends in between the blocks
+        if self.state != State::Header || !self.head.is_empty() {
+            return truncated("truncated lzp stream")
+        }
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a stream of LZP blocks, which ends with
+/// the underlying stream. The wrapped stream can be re-acquired through the
+/// unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new()),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Returns whether the end of the data has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.is_done()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing::{noise, push, Lcg};
+    use super::{decode_block, encode_block, max_compressed_len, Decoder, Encoder, Processor, Table,
+                BLOCK_SIZE, HASH_BITS, SHORT_BLOCK};
+
+    fn roundtrip(input: &[u8], min_len: usize) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        encode_block(input, min_len, &mut encoded);
+        assert!(encoded.len() <= max_compressed_len(input.len()));
+        let mut decoded = b"prefix".to_vec();
+        decode_block(&encoded, &mut decoded, input.len()).unwrap();
+        assert!(&decoded[6..] == input);
+        encoded
+    }
+
+    #[test]
+    fn known_data() {
+        // 1 is the first of the rarest bytes, flagging a match of 8 bytes after
+        // the second "abcd"
+        let input = b"abcdabcdabcdabcd\x00";
+        assert_eq!(roundtrip(input, 4), b"\x01\x04abcdabcd\x01\x05\x00");
+        assert_eq!(roundtrip(b"", 16), b"\x00\x10");
+        assert_eq!(roundtrip(b"abc", 16), b"\x00\x10abc");
+    }
+
+    #[test]
+    fn roundtrips() {
+        let text = include_bytes!("data/test.txt");
+        for &min_len in [4, 16, 32, 255].iter() {
+            roundtrip(text, min_len);
+        }
+        let noise = noise(100000, 7);
+        assert!(roundtrip(&noise, 32).len() < noise.len() + noise.len() / 100);
+        // a repetitive input shrinks to the size of its period
+        let mut repeats = Vec::new();
+        for _ in 0..100 {
+            repeats.extend(noise[..1000].iter().cloned());
+        }
+        assert!(roundtrip(&repeats, 32).len() < 2000);
+        // long matches
+        let runs: Vec<u8> = (0..100000).map(|i| (i / 10000) as u8).collect();
+        assert!(roundtrip(&runs, 32).len() < 1000);
+    }
+
+    #[test]
+    fn tables() {
+        // the short table predicts as the full one, colliding hashes included
+        let mut full = Table::new(SHORT_BLOCK + 1);
+        let mut short = Table::new(SHORT_BLOCK);
+        assert!(match short { Table::Short(_) => true, Table::Full(_) => false });
+        let mut lcg = Lcg(1);
+        for pos in 1..(SHORT_BLOCK as u32 + 1) {
+            let seed = lcg.step();
+            let h = (seed >> 8) as usize & ((1 << HASH_BITS) - 1) & !0xff00;
+            assert_eq!(short.replace(h, pos), full.replace(h, pos));
+        }
+        let text = include_bytes!("data/test.txt");
+        assert!(text.len() <= SHORT_BLOCK);
+        let mut encoded = Vec::new();
+        encode_block(text, 16, &mut encoded);
+        let mut decoded = Vec::new();
+        decode_block(&encoded, &mut decoded, BLOCK_SIZE).unwrap();
+        assert!(&decoded[..] == &text[..]);
+    }
+
+    #[test]
+    fn streams() {
+        let mut input = include_bytes!("data/test.large").to_vec();
+        input.truncate(BLOCK_SIZE + 12345);
+        let mut e = Encoder::new(Vec::new(), 32);
+        for chunk in input.chunks(100000) {
+            e.write_all(chunk).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let mut d = Decoder::new(BufReader::new(&encoded[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(out == input);
+        assert!(push(&mut Processor::new(), &encoded, 10000).unwrap() == input);
+    }
+
+    #[test]
+    fn reset() {
+        let (first, second) = (include_bytes!("data/test.txt"), noise(20000, 5));
+        let mut e = Encoder::new(Vec::new(), 32);
+        e.write_all(first).unwrap();
+        let (a, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(&second).unwrap();
+        let (b, result) = e.finish();
+        result.unwrap();
+        let mut fresh = Encoder::new(Vec::new(), 32);
+        fresh.write_all(&second).unwrap();
+        assert!(b == fresh.finish().0);
+
+        let mut d = Decoder::new(BufReader::new(&a[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(&out[..] == &first[..]);
+        d.reset(BufReader::new(&b[..]));
+        out.truncate(0);
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof() && out == second);
+    }
+
+    #[test]
+    fn invalid_data() {
+        // truncated match
+        assert!(decode_block(b"\x00\x04abcdabcd\x00", &mut Vec::new(), 100).is_err());
+        assert!(decode_block(b"\x00\x04abcdabcd\x00\xff", &mut Vec::new(), 100).is_err());
+        // match too long, and too much data
+        assert!(decode_block(b"\x00\x04abcdabcd\x00\x06", &mut Vec::new(), 16).is_err());
+        assert!(decode_block(b"\x00\x04abcd", &mut Vec::new(), 3).is_err());
+        assert!(decode_block(b"\x00\x03abcd", &mut Vec::new(), 100).is_err());
+        assert!(decode_block(b"\x00", &mut Vec::new(), 100).is_err());
+
+        let mut e = Encoder::new(Vec::new(), 32);
+        e.write_all(include_bytes!("data/test.txt")).unwrap();
+        let (stream, _) = e.finish();
+        for len in 1..stream.len() {
+            let mut d = Decoder::new(&stream[..len]);
+            assert!(d.read_to_end(&mut Vec::new()).is_err());
+        }
+    }
+}
@@ -0,0 +1,634 @@
+/*!
+
+LZSS Decompression and Compression. Requires `lzss` feature, enabled by default
+
+This module contains an implementation of LZSS, the variant of LZ77 which
+writes literals as such, and matches in the window as tokens of a distance and
+a length, a flag bit telling them apart. The flags of eight items are packed
+in a byte before them. The tokens take 16 bits, big-endian, the length over its
+minimum in the high bits and the distance minus one in the low bits, as in the
+LZ10 format of the Game Boy Advance BIOS, of which `Config::default()` is the
+layout. The window size, the minimum match length and the meaning of the flags
+are described by `Config`. Whole buffers are handled by `decode_block` and
+`encode_block`, while streams are exposed as standard `Reader` and `Writer`
+interfaces wrapping an underlying stream.
+
+# Example
+
+```rust,ignore
+use compress::lzss;
+use std::fs::File;
+use std::path::Path;
+use std::io::Read;
+
+let stream = File::open(&Path::new("path/to/file.lzss")).unwrap();
+let mut decompressed = Vec::new();
+lzss::Decoder::new(stream, lzss::Config::default()).read_to_end(&mut decompressed);
+```
+
+# Related links
+
+* https://en.wikipedia.org/wiki/Lempel%E2%80%93Ziv%E2%80%93Storer%E2%80%93Szymanski
+  - the algorithm
+* http://problemkaputt.de/gbatek.htm#biosdecompressionfunctions - the LZ10
+  format
+
+*/
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::mem;
+
+use copy::copy_match;
+use error::{corrupt, truncated};
+use hash;
+use process::{drain, Process, Reader, Status};
+
+/// Size of the output buffered by the streams, and of the input the encoder
+/// stream waits for
+const BUF_SIZE: usize = 1 << 16;
+/// Number of earlier positions looked at for a match
+const MAX_CHAIN: usize = 128;
+const HASH_LOG: u32 = 15;
+
+/// The parameters of the format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Number of bits of the distances of the matches, from 8 to 14: the
+    /// window holds `1 << window_bits` bytes, and the lengths take the other
+    /// bits of the tokens
+    pub window_bits: u8,
+    /// Length of the shortest match
+    pub min_match: usize,
+    /// Whether the flags are taken from the highest bit of their byte first
+    pub msb_flags: bool,
+    /// Whether the set flags mark literals rather than matches
+    pub literal_flags: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::new(12, 3)
+    }
+}
+
+impl Config {
+    /// The format of the given window and minimum match length, with the
+    /// flags of LZ10
+    pub fn new(window_bits: u8, min_match: usize) -> Config {
+        Config {
+            window_bits: window_bits,
+            min_match: min_match,
+            msb_flags: true,
+            literal_flags: false,
+        }
+    }
+
+    fn check(&self) {
+        assert!(self.window_bits >= 8 && self.window_bits <= 14,
+                "invalid lzss window size {}", self.window_bits);
+        assert!(self.min_match >= 1, "invalid lzss minimum match {}", self.min_match);
+    }
+
+    fn window(&self) -> usize {
+        1 << self.window_bits
+    }
+
+    fn max_match(&self) -> usize {
+        self.min_match + (1 << (16 - self.window_bits)) - 1
+    }
+
+    /// The mask of the flag of the `n`-th item of a group
+    fn flag(&self, n: usize) -> u8 {
+        if self.msb_flags { 0x80 >> n } else { 1 << n }
+    }
+}
+
+/// The decoding state, shared by the buffer and stream interfaces
+struct Unpacker {
+    config: Config,
+    flags: u8,
+    /// Number of items left in the current group
+    items: usize,
+    /// The first byte of the token being read, if any
+    high: Option<u8>,
+}
+
+impl Unpacker {
+    fn new(config: Config) -> Unpacker {
+        config.check();
+        Unpacker { config: config, flags: 0, items: 0, high: None }
+    }
+
+    /// Decodes the next byte of the stream, appending the items it completes
+    /// to the window ending `out`, which begins `start` bytes before the data
+    fn push(&mut self, byte: u8, out: &mut Vec<u8>, start: usize) -> io::Result<()> {
+        if let Some(high) = self.high.take() {
+            let token = (high as usize) << 8 | byte as usize;
+            let dist = (token & (self.config.window() - 1)) + 1;
+            let len = (token >> self.config.window_bits) + self.config.min_match;
+            if dist > out.len() - start {
+                return corrupt("invalid lzss distance")
+            }
+            copy_match(out, dist, len);
+            return Ok(())
+        }
+        if self.items == 0 {
+            self.flags = byte;
+            self.items = 8;
+            return Ok(())
+        }
+        let flag = self.flags & self.config.flag(8 - self.items) != 0;
+        self.items -= 1;
+        if flag == self.config.literal_flags {
+            out.push(byte);
+        } else {
+            self.high = Some(byte);
+        }
+        Ok(())
+    }
+
+    /// Starts another stream
+    fn reset(&mut self) {
+        self.flags = 0;
+        self.items = 0;
+        self.high = None;
+    }
+
+    /// Checks that the stream may end here: the flags of the items past the
+    /// end are padding
+    fn finish(&self) -> io::Result<()> {
+        match self.high {
+            Some(_) => truncated("truncated lzss match"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Decodes LZSS data of the given format, appending it to `output`
+pub fn decode_block(input: &[u8], config: Config, output: &mut Vec<u8>) -> io::Result<()> {
+    let mut unpacker = Unpacker::new(config);
+    let start = output.len();
+    for &byte in input.iter() {
+        try!(unpacker.push(byte, output, start));
+    }
+    unpacker.finish()
+}
+
+/// The encoding state, shared by the buffer and stream interfaces
+struct Packer {
+    config: Config,
+    /// The input, from the start of the window
+    data: Vec<u8>,
+    /// Position of the next byte to encode
+    pos: usize,
+    /// Position of the next byte to add to the hash chains
+    hashed: usize,
+    /// Last position of each hash plus one, and the previous position of the
+    /// same hash plus one, by position in the window
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    /// Position of the flags of the current group in the output, and number
+    /// of items in it
+    flags: usize,
+    items: usize,
+}
+
+impl Packer {
+    fn new(config: Config) -> Packer {
+        config.check();
+        Packer {
+            config: config,
+            data: Vec::new(),
+            pos: 0,
+            hashed: 0,
+            head: vec![0; 1 << HASH_LOG],
+            prev: vec![0; config.window()],
+            flags: 0,
+            items: 8,
+        }
+    }
+
+    /// Starts another stream, keeping the buffers and the tables
+    fn reset(&mut self) {
+        self.data.truncate(0);
+        self.pos = 0;
+        self.hashed = 0;
+        for p in self.head.iter_mut().chain(self.prev.iter_mut()) {
+            *p = 0;
+        }
+        self.flags = 0;
+        self.items = 8;
+    }
+
+    /// Number of bytes hashed, no more than the shortest match
+    fn hash_len(&self) -> usize {
+        cmp::min(self.config.min_match, 3)
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        hash::hash_prefix(&self.data[pos..], self.hash_len(), HASH_LOG)
+    }
+
+    /// Adds the positions up to `end` to the hash chains
+    fn insert(&mut self, end: usize) {
+        let end = cmp::min(end, (self.data.len() + 1).saturating_sub(self.hash_len()));
+        while self.hashed < end {
+            let h = self.hash(self.hashed);
+            let mask = self.config.window() - 1;
+            self.prev[self.hashed & mask] = self.head[h];
+            self.head[h] = self.hashed + 1;
+            self.hashed += 1;
+        }
+    }
+
+    /// Returns the distance and length of the longest match at the current
+    /// position
+    fn find_match(&self) -> (usize, usize) {
+        let max = cmp::min(self.config.max_match(), self.data.len() - self.pos);
+        if max < self.config.min_match {
+            return (0, 0)
+        }
+        let mask = self.config.window() - 1;
+        let target = &self.data[self.pos..(self.pos + max)];
+        let (mut best_dist, mut best_len) = (0, 0);
+        let mut candidate = self.head[self.hash(self.pos)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == 0 || self.pos - (candidate - 1) > self.config.window() {
+                break
+            }
+            let c = candidate - 1;
+            let len = target.iter().zip(self.data[c..].iter()).take_while(|&(a, b)| a == b).count();
+            if len > best_len {
+                best_dist = self.pos - c;
+                best_len = len;
+                if len == max {
+                    break
+                }
+            }
+            candidate = self.prev[c & mask];
+        }
+        (best_dist, best_len)
+    }
+
+    fn item(&mut self, is_match: bool, out: &mut Vec<u8>) {
+        if self.items == 8 {
+            self.flags = out.len();
+            out.push(0);
+            self.items = 0;
+        }
+        if is_match != self.config.literal_flags {
+            out[self.flags] |= self.config.flag(self.items);
+        }
+        self.items += 1;
+    }
+
+    /// Encodes the input up to `end`
+    fn encode(&mut self, end: usize, out: &mut Vec<u8>) {
+        while self.pos < end {
+            self.insert(self.pos);
+            let (dist, len) = self.find_match();
+            // a token takes as much as two literals
+            if len >= cmp::max(self.config.min_match, 2) {
+                self.item(true, out);
+                let token = ((len - self.config.min_match) << self.config.window_bits) | (dist - 1);
+                out.push((token >> 8) as u8);
+                out.push(token as u8);
+                self.pos += len;
+            } else {
+                self.item(false, out);
+                out.push(self.data[self.pos]);
+                self.pos += 1;
+            }
+        }
+    }
+
+    /// Forgets the input before the window, moving the positions along by
+    /// whole windows, so that the chains keep their slots
+    fn slide(&mut self) {
+        let window = self.config.window();
+        if self.pos <= 2 * window {
+            return
+        }
+        let drop = (self.pos - window) & !(window - 1);
+        self.data.drain(..drop);
+        self.pos -= drop;
+        self.hashed -= drop;
+        for p in self.head.iter_mut().chain(self.prev.iter_mut()) {
+            *p = p.saturating_sub(drop);
+        }
+    }
+}
+
+/// Encodes the input with the given format, appending the data to `output`
+pub fn encode_block(input: &[u8], config: Config, output: &mut Vec<u8>) {
+    let mut packer = Packer::new(config);
+    packer.data.extend(input.iter().cloned());
+    let end = packer.data.len();
+    packer.encode(end, output);
+}
+
+/// This structure is used to decode a stream of LZSS data, fed with slices
+/// of it. The stream ends with its input.
+pub struct Processor {
+    unpacker: Unpacker,
+    /// The window, followed by the data not produced yet
+    output: Vec<u8>,
+    start: usize,
+    eof: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder of the given format
+    pub fn new(config: Config) -> Processor {
+        Processor {
+            unpacker: Unpacker::new(config),
+            output: Vec::new(),
+            start: 0,
+            eof: false,
+        }
+    }
+
+    /// Starts decoding a new stream of the same format, keeping the buffers
+    pub fn reset(&mut self) {
+        self.unpacker.reset();
+        self.output.truncate(0);
+        self.start = 0;
+        self.eof = false;
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.output, &mut self.start, output, &mut produced) {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            if pos == input.len() {
+                let status = if self.eof { Status::Done } else { Status::NeedInput };
+                return Ok((pos, produced, status))
+            }
+            let window = self.unpacker.config.window();
+            if self.start > window {
+                let drop = self.start - window;
+                self.output.drain(..drop);
+                self.start = window;
+            }
+            while pos < input.len() && self.output.len() < self.start + BUF_SIZE {
+                try!(self.unpacker.push(input[pos], &mut self.output, 0));
+                pos += 1;
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        try!(self.unpacker.finish());
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a stream of LZSS data, which ends with the
+/// underlying stream. The wrapped stream can be re-acquired through the
+/// unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder of the given format which will read data from
+    /// the given stream
+    pub fn new(r: R, config: Config) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new(config)),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Returns whether the end of the data has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.is_done()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+/// This structure is used to compress a stream of bytes into LZSS data. This
+/// is a wrapper around an internal writer which bytes will be written to.
+pub struct Encoder<W> {
+    w: W,
+    packer: Packer,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder of the given format which will have its output
+    /// written to the given output stream. The output stream can be
+    /// re-acquired by calling `finish()`
+    pub fn new(w: W, config: Config) -> Encoder<W> {
+        Encoder {
+            w: w,
+            packer: Packer::new(config),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Writes the groups of items which are complete
+    fn write_groups(&mut self) -> io::Result<()> {
+        let end = if self.packer.items == 8 { self.buf.len() } else { self.packer.flags };
+        try!(self.w.write_all(&self.buf[..end]));
+        self.buf.drain(..end);
+        self.packer.flags -= cmp::min(self.packer.flags, end);
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.end();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one of the
+    /// same format into `w`, keeping the buffers and the tables. The previous
+    /// writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.end();
+        self.buf.truncate(0);
+        self.packer.reset();
+        (mem::replace(&mut self.w, w), result)
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        let end = self.packer.data.len();
+        self.packer.encode(end, &mut self.buf);
+        self.w.write_all(&self.buf).and_then(|_| self.w.flush())
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.packer.data.extend(buf.iter().cloned());
+        // keep enough input for the longest match
+        let pending = self.packer.data.len() - self.packer.pos;
+        if pending >= BUF_SIZE {
+            let end = self.packer.data.len() - self.packer.config.max_match();
+            self.packer.encode(end, &mut self.buf);
+            self.packer.slide();
+            try!(self.write_groups());
+        }
+        Ok(buf.len())
+    }
+
+    /// Writes the complete groups of items, the input of the last ones
+    /// remaining for the next writes
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.write_groups());
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing::{noise, push};
+    use super::{Config, Decoder, Encoder, Processor, decode_block, encode_block};
+
+    fn configs() -> Vec<Config> {
+        let mut okumura = Config::new(12, 3);
+        okumura.msb_flags = false;
+        okumura.literal_flags = true;
+        vec![Config::default(), Config::new(8, 2), Config::new(14, 4), Config::new(10, 1), okumura]
+    }
+
+    fn roundtrip(input: &[u8], config: Config) -> usize {
+        let mut encoded = Vec::new();
+        encode_block(input, config, &mut encoded);
+        let mut decoded = Vec::new();
+        decode_block(&encoded, config, &mut decoded).unwrap();
+        assert!(&decoded[..] == input, "{:?}", config);
+        encoded.len()
+    }
+
+    #[test]
+    fn known_data() {
+        let mut encoded = Vec::new();
+        encode_block(b"abcabcabcd", Config::default(), &mut encoded);
+        // three literals, a match of 6 bytes at distance 3, a literal
+        assert_eq!(encoded, [0x10, b'a', b'b', b'c', 0x30, 0x02, b'd']);
+        let mut decoded = Vec::new();
+        decode_block(&encoded, Config::default(), &mut decoded).unwrap();
+        assert_eq!(&decoded[..], b"abcabcabcd");
+    }
+
+    #[test]
+    fn roundtrips() {
+        let text = include_bytes!("data/test.txt");
+        for &config in configs().iter() {
+            assert_eq!(roundtrip(b"", config), 0);
+            roundtrip(b"a", config);
+            assert!(roundtrip(text, config) < text.len());
+            let random = noise(50000, 7);
+            assert!(roundtrip(&random, config) <= random.len() * 9 / 8 + 1);
+            let runs: Vec<u8> = (0..100000).map(|i| (i / 1000) as u8).collect();
+            assert!(roundtrip(&runs, config) < runs.len() / 3);
+        }
+    }
+
+    #[test]
+    fn streams() {
+        let input = include_bytes!("data/test.large");
+        let input = &input[..300000];
+        for &config in configs().iter() {
+            let mut e = Encoder::new(Vec::new(), config);
+            for (i, chunk) in input.chunks(999).enumerate() {
+                e.write_all(chunk).unwrap();
+                if i % 100 == 0 {
+                    e.flush().unwrap();
+                }
+            }
+            let (encoded, result) = e.finish();
+            result.unwrap();
+            let mut block = Vec::new();
+            decode_block(&encoded, config, &mut block).unwrap();
+            assert!(&block[..] == input);
+
+            let mut d = Decoder::new(BufReader::new(&encoded[..]), config);
+            let mut out = Vec::new();
+            let mut buf = [0u8; 7];
+            loop {
+                match d.read(&mut buf).unwrap() {
+                    0 => break,
+                    n => out.extend(buf[..n].iter().cloned()),
+                }
+            }
+            assert!(d.eof());
+            assert!(&out[..] == input);
+            assert!(&push(&mut Processor::new(config), &encoded, 777).unwrap()[..] == input);
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let (first, second) = (noise(20000, 7), include_bytes!("data/test.txt"));
+        for &config in configs().iter() {
+            let mut e = Encoder::new(Vec::new(), config);
+            e.write_all(second).unwrap();
+            let (a, result) = e.reset(Vec::new());
+            result.unwrap();
+            e.write_all(&first).unwrap();
+            e.write_all(second).unwrap();
+            let (b, result) = e.finish();
+            result.unwrap();
+            let mut both = first.clone();
+            both.extend(second.iter().cloned());
+            let (mut block_a, mut block_b) = (Vec::new(), Vec::new());
+            encode_block(second, config, &mut block_a);
+            encode_block(&both, config, &mut block_b);
+            assert!(a == block_a && b == block_b);
+
+            let mut d = Decoder::new(BufReader::new(&a[..]), config);
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).unwrap();
+            assert!(&out[..] == &second[..]);
+            d.reset(BufReader::new(&b[..]));
+            out.truncate(0);
+            d.read_to_end(&mut out).unwrap();
+            assert!(out == both);
+        }
+    }
+
+    #[test]
+    fn invalid_data() {
+        let mut out = Vec::new();
+        // a match before the start
+        assert!(decode_block(&[0x10, b'a', b'b', b'c', 0x30, 0x03], Config::default(), &mut out).is_err());
+        // a truncated match
+        assert!(decode_block(&[0x10, b'a', b'b', b'c', 0x30], Config::default(), &mut out).is_err());
+    }
+}
@@ -0,0 +1,923 @@
+/*!
+
+LZW Decompression and Compression. Requires `lzw` feature, enabled by default
+
+This module contains an implementation of the Lempel-Ziv-Welch algorithm, whose
+codes index a table of strings growing along the data, each new string being
+an earlier one followed by a byte. The codes widen as the table grows, up to a
+maximum width, and depending on the variant, some codes are reserved to clear
+the table and to end the data. The variants of GIF, TIFF and compress(1) are
+described by `Config`. Whole buffers are handled by `decode_block` and
+`encode_block`, while streams are exposed as standard `Reader` and `Writer`
+interfaces wrapping an underlying stream. The `z` submodule handles the `.Z`
+files of compress(1).
+
+# Example
+
+```rust,ignore
+use compress::lzw;
+use std::fs::File;
+use std::path::Path;
+use std::io::Read;
+
+let stream = File::open(&Path::new("path/to/file.lzw")).unwrap();
+let mut decompressed = Vec::new();
+lzw::Decoder::new(stream, lzw::Config::tiff()).read_to_end(&mut decompressed);
+```
+
+# Related links
+
+* https://en.wikipedia.org/wiki/Lempel%E2%80%93Ziv%E2%80%93Welch - the
+  algorithm
+* https://www.w3.org/Graphics/GIF/spec-gif89a.txt - the GIF format, of which
+  appendix F describes the variant
+* https://www.adobe.io/open/standards/TIFF.html - the TIFF format, of which
+  section 13 describes the variant
+
+*/
+
+use std::io::{self, Read, Write};
+use std::mem;
+
+use error::{corrupt, truncated};
+use process::{drain, Process, Reader, Status};
+
+pub mod z;
+
+/// Size of the output buffered by the streams
+const BUF_SIZE: usize = 1 << 14;
+
+/// Order in which the bits of the codes are packed in the bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitOrder {
+    /// From the lowest bit of each byte, as in GIF and compress(1)
+    Lsb,
+    /// From the highest bit of each byte, as in TIFF and PDF
+    Msb,
+}
+
+/// The parameters of a variant of LZW
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// How the codes are packed in the bytes
+    pub order: BitOrder,
+    /// Number of bits of the literals, which are the first codes, from 1 to
+    /// 8. The codes start one bit wider.
+    pub literal_bits: u8,
+    /// Width of the codes once the table is full, up to 16
+    pub max_code_size: u8,
+    /// Whether the code following the literals clears the table
+    pub clear_code: bool,
+    /// Whether the next code ends the data
+    pub end_code: bool,
+    /// Whether the codes widen one code earlier than needed, as in TIFF
+    pub early_change: bool,
+    /// Whether the codes go by groups of eight, of which the end is skipped
+    /// when the width changes, as in compress(1). These streams don't start
+    /// with a clear code.
+    pub grouped: bool,
+}
+
+impl Config {
+    /// The variant of GIF, where the literal size is given by the image,
+    /// from 2 to 8
+    pub fn gif(min_code_size: u8) -> Config {
+        Config {
+            order: BitOrder::Lsb,
+            literal_bits: min_code_size,
+            max_code_size: 12,
+            clear_code: true,
+            end_code: true,
+            early_change: false,
+            grouped: false,
+        }
+    }
+
+    /// The variant of TIFF, also used by PDF
+    pub fn tiff() -> Config {
+        Config {
+            order: BitOrder::Msb,
+            literal_bits: 8,
+            max_code_size: 12,
+            clear_code: true,
+            end_code: true,
+            early_change: true,
+            grouped: false,
+        }
+    }
+
+    /// The variant of compress(1), with codes of up to `max_bits` from 9 to
+    /// 16, and in block mode, a clear code
+    pub fn compress(max_bits: u8, block_mode: bool) -> Config {
+        Config {
+            order: BitOrder::Lsb,
+            literal_bits: 8,
+            max_code_size: max_bits,
+            clear_code: block_mode,
+            end_code: false,
+            early_change: false,
+            grouped: true,
+        }
+    }
+
+    /// Returns the clear code, the end code, and the first code of the table
+    fn codes(&self) -> (Option<u16>, Option<u16>, u16) {
+        assert!(self.literal_bits >= 1 && self.literal_bits <= 8,
+                "invalid lzw literal size {}", self.literal_bits);
+        assert!(self.max_code_size > self.literal_bits && self.max_code_size <= 16,
+                "invalid lzw code size {}", self.max_code_size);
+        let mut first = 1 << self.literal_bits;
+        let clear = if self.clear_code { first += 1; Some(first - 1) } else { None };
+        let end = if self.end_code { first += 1; Some(first - 1) } else { None };
+        assert!((first as u32) < self.limit(), "lzw code size {} is too small", self.max_code_size);
+        (clear, end, first)
+    }
+
+    /// Number of codes of the full table
+    fn limit(&self) -> u32 {
+        1 << self.max_code_size
+    }
+
+    /// Widens the codes for as long as the next one, while the decoder knows
+    /// `known` codes, may not fit. Returns whether the width changed.
+    fn widen(&self, width: &mut u8, known: u32) -> bool {
+        let early = self.early_change as u32;
+        // compress(1) only caps the width once widened, so that its codes
+        // of at most 9 bits become 10 bits wide when the table is full
+        let max = if self.grouped && self.max_code_size == self.literal_bits + 1 {
+            self.max_code_size + 1
+        } else {
+            self.max_code_size
+        };
+        let old = *width;
+        while *width < max && known + early >= 1 << *width {
+            *width += 1;
+        }
+        *width != old
+    }
+}
+
+/// Number of codes to skip to the end of the group, after `codes` of them
+fn group_padding(codes: usize) -> usize {
+    (8 - codes % 8) % 8
+}
+
+/// A string of the table, as a code of the string without the last byte
+#[derive(Clone, Copy)]
+struct Entry {
+    prefix: u16,
+    last: u8,
+    first: u8,
+    len: u32,
+}
+
+/// The decoding state, shared by the buffer and stream interfaces
+struct Unpacker {
+    config: Config,
+    clear: Option<u16>,
+    end: Option<u16>,
+    first: u16,
+    table: Vec<Entry>,
+    /// Number of codes in the table
+    known: u32,
+    /// The code decoded last, since the table was cleared
+    prev: Option<u16>,
+    width: u8,
+    /// Number of codes read with the current width
+    codes: usize,
+    /// Number of padding codes left to skip, and their width
+    skip: usize,
+    skip_width: u8,
+    /// Whether the width of the next code was checked
+    widened: bool,
+    bits: u32,
+    count: u8,
+    done: bool,
+}
+
+impl Unpacker {
+    fn new(config: Config) -> Unpacker {
+        let (clear, end, first) = config.codes();
+        let table = (0..config.limit()).map(|i| {
+            Entry { prefix: 0, last: i as u8, first: i as u8, len: 1 }
+        }).collect();
+        Unpacker {
+            config: config,
+            clear: clear,
+            end: end,
+            first: first,
+            table: table,
+            known: first as u32,
+            prev: None,
+            width: config.literal_bits + 1,
+            codes: 0,
+            skip: 0,
+            skip_width: 0,
+            widened: false,
+            bits: 0,
+            count: 0,
+            done: false,
+        }
+    }
+
+    /// Takes the next `n` bits, of the `count` gathered
+    fn take(&mut self, n: u8) -> u16 {
+        let mask = (1 << n) - 1;
+        let value = match self.config.order {
+            BitOrder::Lsb => {
+                let value = self.bits & mask;
+                self.bits >>= n;
+                value
+            }
+            BitOrder::Msb => (self.bits >> (self.count - n)) & mask,
+        };
+        self.count -= n;
+        value as u16
+    }
+
+    /// Skips the rest of the current group of codes, of `width` bits
+    fn skip_group(&mut self, width: u8) {
+        if self.config.grouped {
+            self.skip = group_padding(self.codes);
+            self.skip_width = width;
+        }
+        self.codes = 0;
+    }
+
+    fn reset(&mut self) {
+        self.known = self.first as u32;
+        self.prev = None;
+        self.width = self.config.literal_bits + 1;
+    }
+
+    /// Starts another stream, keeping the table
+    fn restart(&mut self) {
+        self.reset();
+        self.codes = 0;
+        self.skip = 0;
+        self.skip_width = 0;
+        self.widened = false;
+        self.bits = 0;
+        self.count = 0;
+        self.done = false;
+    }
+
+    fn write(&self, code: u16, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.resize(start + self.table[code as usize].len as usize, 0);
+        let mut code = code as usize;
+        for slot in out[start..].iter_mut().rev() {
+            let entry = self.table[code];
+            *slot = entry.last;
+            code = entry.prefix as usize;
+        }
+    }
+
+    /// Decodes the next byte of the stream, appending the strings of the
+    /// codes it completes to `out`
+    fn push(&mut self, byte: u8, out: &mut Vec<u8>) -> io::Result<()> {
+        match self.config.order {
+            BitOrder::Lsb => self.bits |= (byte as u32) << self.count,
+            BitOrder::Msb => self.bits = (self.bits << 8) | byte as u32,
+        }
+        self.count += 8;
+        while !self.done {
+            if self.skip > 0 {
+                if self.count < self.skip_width {
+                    break
+                }
+                self.take(self.skip_width);
+                self.skip -= 1;
+                continue
+            }
+            if !self.widened {
+                let width = self.width;
+                if self.config.widen(&mut self.width, self.known) {
+                    self.skip_group(width);
+                }
+                self.widened = true;
+                continue
+            }
+            if self.count < self.width {
+                break
+            }
+            let code = self.take(self.width);
+            self.widened = false;
+            try!(self.step(code, out));
+        }
+        Ok(())
+    }
+
+    /// Decodes the next code
+    fn step(&mut self, code: u16, out: &mut Vec<u8>) -> io::Result<()> {
+        self.codes += 1;
+        if Some(code) == self.clear {
+            let width = self.width;
+            self.skip_group(width);
+            self.reset();
+            return Ok(())
+        }
+        if Some(code) == self.end {
+            self.done = true;
+            return Ok(())
+        }
+
+        match self.prev {
+            None if code >= 1 << self.config.literal_bits => return corrupt("invalid lzw code"),
+            None => {}
+            Some(_) if code as u32 > self.known => return corrupt("invalid lzw code"),
+            // a full table adds no string for the code past it to refer to
+            Some(_) if code as u32 == self.known && self.known == self.config.limit() => {
+                return corrupt("invalid lzw code")
+            }
+            Some(prev) => {
+                if self.known < self.config.limit() {
+                    // the new string ends with the first byte of this one,
+                    // which is also the first of the previous one if this is
+                    // the new string
+                    let previous = self.table[prev as usize];
+                    let first = if code as u32 == self.known {
+                        previous.first
+                    } else {
+                        self.table[code as usize].first
+                    };
+                    self.table[self.known as usize] = Entry {
+                        prefix: prev,
+                        last: first,
+                        first: previous.first,
+                        len: previous.len + 1,
+                    };
+                    self.known += 1;
+                }
+            }
+        }
+        self.write(code, out);
+        self.prev = Some(code);
+        Ok(())
+    }
+
+    /// Checks that the data may end here: the variants with an end code end
+    /// with it, the others with the last whole code
+    fn finish(&self) -> io::Result<()> {
+        if !self.done && self.end.is_some() {
+            return truncated("truncated lzw data")
+        }
+        Ok(())
+    }
+}
+
+/// The encoding state, shared by the buffer and stream interfaces
+struct Packer {
+    config: Config,
+    clear: Option<u16>,
+    end: Option<u16>,
+    first: u16,
+    /// Open addressing table of the strings, from their prefix and last byte
+    /// to their code, keys being offset by one to keep zero for empty slots
+    keys: Vec<u32>,
+    values: Vec<u16>,
+    hash_shift: u32,
+    /// The next code of the table, and the number of them the decoder knows
+    next: u32,
+    known: u32,
+    /// Number of codes the table is allowed to have
+    capacity: u32,
+    /// The code of the current match
+    prefix: Option<u16>,
+    /// Whether a code was written since the table was cleared
+    started: bool,
+    /// Whether the stream was started, with a clear code unless grouped
+    header: bool,
+    width: u8,
+    codes: usize,
+    bits: u32,
+    count: u8,
+}
+
+impl Packer {
+    fn new(config: Config) -> Packer {
+        let (clear, end, first) = config.codes();
+        let size = 2 * config.limit() as usize;
+        Packer {
+            config: config,
+            clear: clear,
+            end: end,
+            first: first,
+            keys: vec![0; size],
+            values: vec![0; size],
+            hash_shift: 32 - size.trailing_zeros(),
+            next: first as u32,
+            known: first as u32,
+            // keep the decoders which don't cap the width of the early
+            // change variant from widening
+            capacity: config.limit() - config.early_change as u32,
+            prefix: None,
+            started: false,
+            header: false,
+            width: config.literal_bits + 1,
+            codes: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn start(&mut self, out: &mut Vec<u8>) {
+        if !self.header {
+            self.header = true;
+            if let Some(clear) = self.clear {
+                if !self.config.grouped {
+                    self.put_clear(clear, out);
+                }
+            }
+        }
+    }
+
+    fn put_bits(&mut self, value: u32, n: u8, out: &mut Vec<u8>) {
+        match self.config.order {
+            BitOrder::Lsb => {
+                self.bits |= value << self.count;
+                self.count += n;
+                while self.count >= 8 {
+                    out.push(self.bits as u8);
+                    self.bits >>= 8;
+                    self.count -= 8;
+                }
+            }
+            BitOrder::Msb => {
+                self.bits = (self.bits << n) | value;
+                self.count += n;
+                while self.count >= 8 {
+                    out.push((self.bits >> (self.count - 8)) as u8);
+                    self.count -= 8;
+                }
+            }
+        }
+    }
+
+    /// Fills the rest of the current group of codes
+    fn pad_group(&mut self, out: &mut Vec<u8>) {
+        if self.config.grouped {
+            for _ in 0..group_padding(self.codes) {
+                let width = self.width;
+                self.put_bits(0, width, out);
+            }
+        }
+        self.codes = 0;
+    }
+
+    fn put(&mut self, code: u16, out: &mut Vec<u8>) {
+        let width = self.width;
+        if self.config.widen(&mut self.width, self.known) {
+            let wide = self.width;
+            self.width = width;
+            self.pad_group(out);
+            self.width = wide;
+        }
+        let width = self.width;
+        self.put_bits(code as u32, width, out);
+        self.codes += 1;
+    }
+
+    /// Writes the code of a string, which the decoder follows with a new
+    /// string from the second code written after a clear
+    fn put_string(&mut self, code: u16, out: &mut Vec<u8>) {
+        self.put(code, out);
+        if self.started && self.known < self.config.limit() {
+            self.known += 1;
+        }
+        self.started = true;
+    }
+
+    fn put_clear(&mut self, clear: u16, out: &mut Vec<u8>) {
+        self.put(clear, out);
+        self.pad_group(out);
+        for key in self.keys.iter_mut() {
+            *key = 0;
+        }
+        self.next = self.first as u32;
+        self.known = self.first as u32;
+        self.started = false;
+        self.width = self.config.literal_bits + 1;
+    }
+
+    /// Starts another stream, keeping the table
+    fn restart(&mut self) {
+        for key in self.keys.iter_mut() {
+            *key = 0;
+        }
+        self.next = self.first as u32;
+        self.known = self.first as u32;
+        self.prefix = None;
+        self.started = false;
+        self.header = false;
+        self.width = self.config.literal_bits + 1;
+        self.codes = 0;
+        self.bits = 0;
+        self.count = 0;
+    }
+
+    /// Returns the slot of the key, either holding it or empty
+    fn slot(&self, key: u32) -> usize {
+        let mask = self.keys.len() - 1;
+        let mut i = (key.wrapping_mul(2654435761) >> self.hash_shift) as usize;
+        while self.keys[i] != 0 && self.keys[i] != key {
+            i = (i + 1) & mask;
+        }
+        i
+    }
+
+    fn push(&mut self, byte: u8, out: &mut Vec<u8>) {
+        assert!(byte as u32 >> self.config.literal_bits == 0, "lzw literal {} is too large", byte);
+        self.start(out);
+        let prefix = match self.prefix {
+            Some(prefix) => prefix,
+            None => {
+                self.prefix = Some(byte as u16);
+                return
+            }
+        };
+        let key = ((prefix as u32) << 8 | byte as u32) + 1;
+        let slot = self.slot(key);
+        if self.keys[slot] == key {
+            self.prefix = Some(self.values[slot]);
+            return
+        }
+        self.put_string(prefix, out);
+        if self.next < self.capacity {
+            self.keys[slot] = key;
+            self.values[slot] = self.next as u16;
+            self.next += 1;
+        } else if let Some(clear) = self.clear {
+            self.put_clear(clear, out);
+        }
+        self.prefix = Some(byte as u16);
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        self.start(out);
+        if let Some(prefix) = self.prefix.take() {
+            self.put_string(prefix, out);
+        }
+        if let Some(end) = self.end {
+            self.put(end, out);
+        }
+        if self.count > 0 {
+            let n = 8 - self.count;
+            self.put_bits(0, n, out);
+        }
+    }
+}
+
+/// Decodes LZW data of the given variant, appending it to `output`. The
+/// bytes after an end code are ignored.
+pub fn decode_block(input: &[u8], config: Config, output: &mut Vec<u8>) -> io::Result<()> {
+    let mut unpacker = Unpacker::new(config);
+    for &byte in input.iter() {
+        if unpacker.done {
+            break
+        }
+        try!(unpacker.push(byte, output));
+    }
+    unpacker.finish()
+}
+
+/// Encodes the input with the given variant, appending the data to `output`.
+/// The bytes must fit in the literals of the variant.
+pub fn encode_block(input: &[u8], config: Config, output: &mut Vec<u8>) {
+    let mut packer = Packer::new(config);
+    for &byte in input.iter() {
+        packer.push(byte, output);
+    }
+    packer.finish(output);
+}
+
+/// This structure is used to decode a stream of LZW data, fed with slices of
+/// it. The data ends with an end code, or else with its input.
+pub struct Processor {
+    unpacker: Unpacker,
+    output: Vec<u8>,
+    start: usize,
+    eof: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder of the given variant
+    pub fn new(config: Config) -> Processor {
+        Processor {
+            unpacker: Unpacker::new(config),
+            output: Vec::new(),
+            start: 0,
+            eof: false,
+        }
+    }
+
+    /// Starts decoding a new stream of the same variant, keeping the table
+    /// and the buffers
+    pub fn reset(&mut self) {
+        self.unpacker.restart();
+        self.output.truncate(0);
+        self.start = 0;
+        self.eof = false;
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.output, &mut self.start, output, &mut produced) {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            if self.unpacker.done || self.eof {
+                return Ok((pos, produced, Status::Done))
+            }
+            if pos == input.len() {
+                return Ok((pos, produced, Status::NeedInput))
+            }
+            self.output.truncate(0);
+            self.start = 0;
+            while pos < input.len() && self.output.len() < BUF_SIZE && !self.unpacker.done {
+                try!(self.unpacker.push(input[pos], &mut self.output));
+                pos += 1;
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        try!(self.unpacker.finish());
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a stream of LZW data. The wrapped stream
+/// can be re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder of the given variant which will read data from
+    /// the given stream
+    pub fn new(r: R, config: Config) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new(config)),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Returns whether the end of the data has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.is_done()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+/// This structure is used to compress a stream of bytes into LZW data. This
+/// is a wrapper around an internal writer which bytes will be written to.
+pub struct Encoder<W> {
+    w: W,
+    packer: Packer,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder of the given variant which will have its output
+    /// written to the given output stream. The output stream can be
+    /// re-acquired by calling `finish()`
+    pub fn new(w: W, config: Config) -> Encoder<W> {
+        Encoder {
+            w: w,
+            packer: Packer::new(config),
+            buf: Vec::with_capacity(BUF_SIZE),
+        }
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        self.packer.finish(&mut self.buf);
+        let result = self.flush();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one of the
+    /// same variant into `w`, keeping the table and the buffers. The previous
+    /// writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        self.packer.finish(&mut self.buf);
+        let result = self.flush();
+        self.buf.truncate(0);
+        self.packer.restart();
+        (mem::replace(&mut self.w, w), result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf.iter() {
+            self.packer.push(byte, &mut self.buf);
+        }
+        if self.buf.len() >= BUF_SIZE {
+            try!(self.w.write_all(&self.buf));
+            self.buf.truncate(0);
+        }
+        Ok(buf.len())
+    }
+
+    /// Writes the complete bytes of data, the codes of the current match and
+    /// the bits of a partial byte remaining for the next writes
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.w.write_all(&self.buf));
+        self.buf.truncate(0);
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing;
+    use super::{BitOrder, Config, Decoder, Encoder, Processor, decode_block, encode_block};
+
+    fn configs() -> Vec<Config> {
+        let mut configs = vec![Config::gif(2), Config::gif(8), Config::tiff(),
+                               Config::compress(9, true), Config::compress(16, true),
+                               Config::compress(12, false)];
+        let mut old_tiff = Config::tiff();
+        old_tiff.order = BitOrder::Lsb;
+        old_tiff.early_change = false;
+        configs.push(old_tiff);
+        configs
+    }
+
+    fn noise(len: usize, bits: u8) -> Vec<u8> {
+        testing::noise(len, 7).into_iter().map(|b| b >> (8 - bits)).collect()
+    }
+
+    fn roundtrip(input: &[u8], config: Config) -> usize {
+        let mut encoded = Vec::new();
+        encode_block(input, config, &mut encoded);
+        let mut decoded = Vec::new();
+        decode_block(&encoded, config, &mut decoded).unwrap();
+        assert!(&decoded[..] == input, "{:?}", config);
+        encoded.len()
+    }
+
+    #[test]
+    fn known_data() {
+        // the example of the TIFF specification
+        let input = [7, 7, 7, 8, 8, 7, 7, 6, 6];
+        let mut encoded = Vec::new();
+        encode_block(&input, Config::tiff(), &mut encoded);
+        // codes 256, 7, 258, 8, 8, 258, 6, 6, 257
+        assert_eq!(encoded, [0x80, 0x01, 0xe0, 0x40, 0x80, 0x44, 0x08, 0x0c, 0x06, 0x80, 0x80]);
+        let mut decoded = Vec::new();
+        decode_block(&encoded, Config::tiff(), &mut decoded).unwrap();
+        assert_eq!(&decoded[..], &input[..]);
+        // the bytes after the end code are ignored
+        encoded.push(0xff);
+        decoded.truncate(0);
+        decode_block(&encoded, Config::tiff(), &mut decoded).unwrap();
+        assert_eq!(&decoded[..], &input[..]);
+    }
+
+    #[test]
+    fn roundtrips() {
+        let text = include_bytes!("../data/test.txt");
+        for &config in configs().iter() {
+            let bits = config.literal_bits;
+            assert!(roundtrip(b"", config) <= 3);
+            roundtrip(&[1], config);
+            if bits == 8 {
+                assert!(roundtrip(text, config) < text.len() * 9 / 10);
+            }
+            // the table fills up and gets cleared, or not
+            roundtrip(&noise(100000, bits), config);
+            let runs: Vec<u8> = (0..200000).map(|i| (i / 1000) as u8 >> (8 - bits)).collect();
+            let size = roundtrip(&runs, config);
+            // without clear codes, the table stays full of the first runs
+            assert!(size < runs.len() / 10 || !config.clear_code);
+        }
+    }
+
+    #[test]
+    fn streams() {
+        let input = include_bytes!("../data/test.txt");
+        for &config in configs().iter().filter(|c| c.literal_bits == 8) {
+            let mut e = Encoder::new(Vec::new(), config);
+            for chunk in input.chunks(33) {
+                e.write_all(chunk).unwrap();
+            }
+            let (encoded, result) = e.finish();
+            result.unwrap();
+            let mut block = Vec::new();
+            encode_block(input, config, &mut block);
+            assert!(encoded == block);
+
+            let mut d = Decoder::new(BufReader::new(&encoded[..]), config);
+            let mut out = Vec::new();
+            let mut buf = [0u8; 7];
+            loop {
+                match d.read(&mut buf).unwrap() {
+                    0 => break,
+                    n => out.extend(buf[..n].iter().cloned()),
+                }
+            }
+            assert!(d.eof());
+            assert!(&out[..] == &input[..]);
+            assert!(&testing::push(&mut Processor::new(config), &encoded, 5).unwrap()[..] == &input[..]);
+        }
+    }
+
+    #[test]
+    fn reset() {
+        // the table filled by the first stream is cleared for the next
+        let (first, second) = (noise(20000, 8), include_bytes!("../data/test.txt"));
+        for &config in configs().iter().filter(|c| c.literal_bits == 8) {
+            let mut e = Encoder::new(Vec::new(), config);
+            e.write_all(&first).unwrap();
+            let (a, result) = e.reset(Vec::new());
+            result.unwrap();
+            e.write_all(second).unwrap();
+            let (b, result) = e.finish();
+            result.unwrap();
+            let (mut block_a, mut block_b) = (Vec::new(), Vec::new());
+            encode_block(&first, config, &mut block_a);
+            encode_block(second, config, &mut block_b);
+            assert!(a == block_a && b == block_b);
+
+            let mut d = Decoder::new(BufReader::new(&a[..]), config);
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).unwrap();
+            assert!(out == first);
+            d.reset(BufReader::new(&b[..]));
+            out.truncate(0);
+            d.read_to_end(&mut out).unwrap();
+            assert!(&out[..] == &second[..]);
+        }
+    }
+
+    #[test]
+    fn invalid_data() {
+        let mut out = Vec::new();
+        // a code past the table
+        assert!(decode_block(&[0x80, 0x01, 0xe5, 0x80], Config::tiff(), &mut out).is_err());
+        // a first code which isn't a literal
+        assert!(decode_block(&[0x80, 0x4b, 0x00], Config::tiff(), &mut out).is_err());
+        // no end code
+        assert!(decode_block(&[0x80, 0x01, 0xc0], Config::tiff(), &mut out).is_err());
+        assert!(decode_block(&[], Config::gif(8), &mut out).is_err());
+        // compress(1) has no end code
+        out.truncate(0);
+        decode_block(&[], Config::compress(16, true), &mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn corrupt_data() {
+        // once the table of compress(9) is full, its codes widen to 10 bits,
+        // of which the one past the table has no string
+        let config = Config::compress(9, true);
+        let mut encoded = Vec::new();
+        encode_block(&noise(2000, 8), config, &mut encoded);
+        let mut out = Vec::new();
+        let mut failed = 0;
+        for i in 0..encoded.len() - 1 {
+            // code 512 in the bits of a byte and the low two of the next
+            let mut corrupt = encoded.clone();
+            corrupt[i] = 0x00;
+            corrupt[i + 1] = (corrupt[i + 1] & !3) | 2;
+            out.truncate(0);
+            if decode_block(&corrupt, config, &mut out).is_err() {
+                failed += 1;
+            }
+        }
+        assert!(failed > 0);
+    }
+}
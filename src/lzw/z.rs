@@ -0,0 +1,297 @@
+//! Unix .Z Decompression and Compression
+//!
+//! This module contains an implementation of the `.Z` file format of
+//! compress(1): a 3-byte header, giving the maximum width of the codes and
+//! whether a code clears the table (block mode), followed by the LZW data.
+//! There is no end code nor checksum, the data ending with the file. The
+//! encoder always writes in block mode, as compress(1) does by default.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::lzw::z;
+//! use std::fs::File;
+//! use std::path::Path;
+//! use std::io::Read;
+//!
+//! let stream = File::open(&Path::new("path/to/file.Z")).unwrap();
+//! let mut decompressed = Vec::new();
+//! z::Decoder::new(stream).read_to_end(&mut decompressed);
+//! ```
+//!
+//! # Related links
+//!
+//! * https://en.wikipedia.org/wiki/Compress - the compress utility, and its
+//!   file format
+
+use std::io::{self, Read, Write};
+
+use super::Config;
+use error::{corrupt, truncated};
+use process::{gather, Process, Reader, Status};
+
+const MAGIC: [u8; 2] = [0x1f, 0x9d];
+const FLAG_BLOCK_MODE: u8 = 0x80;
+const FLAG_RESERVED: u8 = 0x60;
+const MASK_MAX_BITS: u8 = 0x1f;
+const MIN_BITS: u8 = 9;
+const MAX_BITS: u8 = 16;
+
+/// This structure is used to decode a .Z stream, fed with slices of it
+pub struct Processor {
+    /// The bytes of the header gathered
+    head: Vec<u8>,
+    /// The LZW data, once a header is read, kept for the next streams
+    lzw: Option<super::Processor>,
+    header: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder, waiting for the header
+    pub fn new() -> Processor {
+        Processor { head: Vec::new(), lzw: None, header: false }
+    }
+
+    /// Starts decoding a new stream, keeping the table of the previous one
+    /// if it has the same format
+    pub fn reset(&mut self) {
+        self.head.truncate(0);
+        self.header = false;
+    }
+
+    /// Reads the header, returning the format of the data
+    fn read_header(&self) -> io::Result<Config> {
+        if self.head[..2] != MAGIC {
+            return corrupt("invalid .Z magic")
+        }
+        let flags = self.head[2];
+        let bits = flags & MASK_MAX_BITS;
+        if flags & FLAG_RESERVED != 0 || bits < MIN_BITS || bits > MAX_BITS {
+            return corrupt("invalid .Z header")
+        }
+        Ok(Config::compress(bits, flags & FLAG_BLOCK_MODE != 0))
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        if !self.header {
+            if !gather(&mut self.head, input, &mut pos, 3) {
+                return Ok((pos, 0, Status::NeedInput))
+            }
+            let config = try!(self.read_header());
+            match self.lzw {
+                Some(ref mut lzw) if lzw.unpacker.config == config => lzw.reset(),
+                _ => self.lzw = Some(super::Processor::new(config)),
+            }
+            self.header = true;
+        }
+        let (consumed, produced, status) = try!(self.lzw.as_mut().unwrap().process(&input[pos..], output));
+        Ok((pos + consumed, produced, status))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.lzw {
+            Some(ref mut lzw) if self.header => lzw.finish(),
+            _ => truncated("truncated .Z header"),
+        }
+    }
+}
+
+/// This structure is used to decode a .Z stream. The wrapped stream can be
+/// re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new .Z decoder which will wrap the specified reader. This
+    /// decoder also implements the `Reader` trait, and the underlying reader
+    /// can be re-acquired through the `unwrap` method.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder { inner: Reader::new(r, Processor::new()) }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Tests whether the end of the stream has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.is_done()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+/// This structure is used to compress a stream of bytes in the .Z format.
+/// This is a wrapper around an internal writer which bytes will be written
+/// to.
+pub struct Encoder<W> {
+    lzw: super::Encoder<W>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder, of codes up to `max_bits` wide, from 9 to 16,
+    /// which will have its output written to the given output stream. The
+    /// output stream can be re-acquired by calling `finish()`
+    pub fn new(w: W, max_bits: u8) -> Encoder<W> {
+        assert!(max_bits >= MIN_BITS && max_bits <= MAX_BITS, "invalid .Z code size {}", max_bits);
+        let mut e = Encoder { lzw: super::Encoder::new(w, Config::compress(max_bits, true)) };
+        e.write_header();
+        e
+    }
+
+    fn write_header(&mut self) {
+        let max_bits = self.lzw.packer.config.max_code_size;
+        self.lzw.buf.extend(MAGIC.iter().cloned());
+        self.lzw.buf.push(FLAG_BLOCK_MODE | max_bits);
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(self) -> (W, io::Result<()>) {
+        self.lzw.finish()
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`
+    /// with the same code size, keeping the table and the buffers. The
+    /// previous writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.lzw.reset(w);
+        self.write_header();
+        result
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lzw.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lzw.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing::{noise, push};
+    use super::{Decoder, Encoder, Processor};
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        out
+    }
+
+    fn encode(input: &[u8], max_bits: u8) -> Vec<u8> {
+        let mut e = Encoder::new(Vec::new(), max_bits);
+        e.write_all(input).unwrap();
+        let (out, result) = e.finish();
+        result.unwrap();
+        out
+    }
+
+    /// The input of test.Z, compressed with codes of up to 9 bits by a port
+    /// of compress(1), which clears the table once the noise degrades the
+    /// compression
+    fn multi() -> Vec<u8> {
+        let text = include_bytes!("../data/test.txt");
+        let mut data = Vec::new();
+        for _ in 0..4 {
+            data.extend(text.iter().cloned());
+        }
+        data.extend(noise(9000, 1));
+        for _ in 0..2 {
+            data.extend(text.iter().cloned());
+        }
+        data
+    }
+
+    #[test]
+    fn decode_file() {
+        assert!(decode(include_bytes!("../data/test.Z")) == multi());
+    }
+
+    #[test]
+    fn roundtrips() {
+        assert_eq!(encode(b"", 16), [0x1f, 0x9d, 0x90]);
+        assert_eq!(decode(&encode(b"", 16)), b"");
+        let input = multi();
+        for bits in 9..17 {
+            assert!(decode(&encode(&input, bits)) == input);
+        }
+        let large = include_bytes!("../data/test.large");
+        let encoded = encode(large, 16);
+        assert!(encoded.len() < large.len() * 3 / 5);
+        assert!(&decode(&encoded)[..] == &large[..]);
+        assert!(&push(&mut Processor::new(), &encoded, 1000).unwrap()[..] == &large[..]);
+    }
+
+    #[test]
+    fn reset() {
+        let input = multi();
+        let mut e = Encoder::new(Vec::new(), 12);
+        e.write_all(&input).unwrap();
+        let (first, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(b"hello").unwrap();
+        let (second, result) = e.finish();
+        result.unwrap();
+        assert!(first == encode(&input, 12));
+        assert!(second == encode(b"hello", 12));
+
+        // the table is kept for the streams of the same code size
+        let mut d = Decoder::new(BufReader::new(&first[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(out == input);
+        d.reset(BufReader::new(&second[..]));
+        out.truncate(0);
+        d.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"hello");
+        let other = encode(&input, 16);
+        d.reset(BufReader::new(&other[..]));
+        out.truncate(0);
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(out == input);
+    }
+
+    #[test]
+    fn invalid_headers() {
+        for input in [&b""[..], b"\x1f", b"\x1f\x9e\x90", b"\x1f\x9d\x91", b"\x1f\x9d\x88",
+                      b"\x1f\x9d\xf0"].iter() {
+            let mut d = Decoder::new(BufReader::new(&input[..]));
+            assert!(d.read_to_end(&mut Vec::new()).is_err());
+        }
+        // a first code which isn't a literal
+        let mut d = Decoder::new(BufReader::new(&b"\x1f\x9d\x90\x2c\x01"[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}
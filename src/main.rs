@@ -4,50 +4,92 @@
 //! algorithms and their combinations using a simple command line.
 //! Example invocations:
 //! echo -n "abracadabra" | ./app bwt | xxd
-//! echo "banana" | ./app bwt | ./app -d
+//! echo "banana" | ./app bwt mtf ari | ./app -d
+//! ./app verify flate <file
+//! ./app bench -iter5 bwt mtf ari <file
+//! ./app corpus -iter3 path/to/corpus
 
 #[macro_use] extern crate log;
 extern crate compress;
 extern crate byteorder;
 
+use std::cell::RefCell;
+use std::cmp;
 use std::collections::HashMap;
 use std::io::{self, Read, Write};
-use std::{env, str};
-use compress::{bwt, lz4, ReadExact};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use std::{env, process, str};
+use compress::{bench, bwt, flate, lz4, rle, ReadExact};
 use compress::entropy::ari;
 use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
 
 static MAGIC    : u32   = 0x73632172;   //=r!cs
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Command {
+    /// Compress the input through the passes
+    Compress,
+    /// Decompress an archive made by `Compress`
+    Decompress,
+    /// Compress then decompress the input, checking the data comes back
+    Verify,
+    /// Time the compression and the decompression of the input
+    Bench,
+    /// Benchmark the codecs of the crate on the files of a directory
+    Corpus,
+}
+
+static COMMANDS: [(&'static str, Command); 5] = [
+    ("compress", Command::Compress),
+    ("decompress", Command::Decompress),
+    ("verify", Command::Verify),
+    ("bench", Command::Bench),
+    ("corpus", Command::Corpus),
+];
+
 struct Config {
     exe_name: String,
+    command: Command,
     methods: Vec<String>,
     block_size: usize,
-    decompress: bool,
+    level: u32,
+    iterations: u32,
 }
 
 impl Config {
     fn query<I>(mut args: I) -> Config where I: Iterator<Item = String> + Sized {
         let mut cfg = Config {
             exe_name: args.next().unwrap().clone(),
+            command: Command::Compress,
             methods: Vec::new(),
             block_size: 1<<16,
-            decompress: false,
+            level: 6,
+            iterations: 10,
         };
-        let mut handlers: HashMap<&str, Box<FnMut(&str, &mut Config)>> =
+        let mut handlers: HashMap<&str, Box<dyn FnMut(&str, &mut Config)>> =
             HashMap::new();
-        handlers.insert("d", Box::new(|_, cfg| { cfg.decompress = true; }));
+        handlers.insert("d", Box::new(|_, cfg| { cfg.command = Command::Decompress; }));
         handlers.insert("block", Box::new(|b, cfg| {
             cfg.block_size = b.parse().unwrap();
         }));
+        handlers.insert("level", Box::new(|l, cfg| {
+            cfg.level = l.parse().unwrap();
+            assert!(cfg.level <= 9, "Levels range from 0 to 9");
+        }));
+        handlers.insert("iter", Box::new(|n, cfg| {
+            cfg.iterations = n.parse().unwrap();
+        }));
 
-        for arg in args {
-			let slice = &arg[..];
+        for (i, arg) in args.enumerate() {
+            let slice = &arg[..];
             if slice.starts_with("-") {
                 match handlers.iter_mut().find(|&(&k,_)| slice[1..].starts_with(k)) {
                     Some((k,h)) => (*h)(&slice[1+k.len()..], &mut cfg),
                     None => println!("Warning: unrecognized option: {}", &arg[..]),
                 }
+            }else if let (0, Some(&(_, command))) = (i, COMMANDS.iter().find(|&&(name, _)| name == slice)) {
+                cfg.command = command;
             }else {
                 cfg.methods.push(arg.to_string());
             }
@@ -56,14 +98,179 @@ impl Config {
     }
 }
 
+/// A writer of a chain of passes, which ends its own stream then that of the
+/// writer it wraps
+trait Stage: Write {
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+/// The end of a chain of passes
+struct Output<W>(W);
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> Stage for Output<W> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+macro_rules! stages {
+    ($($encoder:ty),*) => {$(
+        impl Stage for $encoder {
+            fn finish(self: Box<Self>) -> io::Result<()> {
+                let (w, result) = (*self).finish();
+                try!(result);
+                w.finish()
+            }
+        }
+    )*}
+}
+
+stages!(ari::ByteEncoder<Box<dyn Stage>>, bwt::Encoder<Box<dyn Stage>>, flate::Encoder<Box<dyn Stage>>,
+        lz4::Encoder<Box<dyn Stage>>, rle::Encoder<Box<dyn Stage>>);
+
+impl Stage for bwt::mtf::Encoder<Box<dyn Stage>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish().finish()
+    }
+}
+
+/// A vector shared with the chain of passes writing into it
+#[derive(Clone)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 struct Pass {
-    encode: Box<FnMut(Box<Write + 'static>, &Config)
-                      -> Box<Write + 'static> + 'static>,
-    decode: Box<FnMut(Box<Read + 'static>, &Config)
-                      -> Box<Read + 'static> + 'static>,
+    encode: Box<dyn FnMut(Box<dyn Stage>, &Config) -> Box<dyn Stage>>,
+    decode: Box<dyn FnMut(Box<dyn Read>, &Config) -> Box<dyn Read>>,
     info: String,
 }
 
+/// Writes the header of an archive, then compresses `input` into `output`
+/// through the passes of `cfg`, the first one coding the input first
+fn compress(passes: &mut HashMap<String, Pass>, cfg: &Config, input: &mut dyn Read,
+            mut output: Box<dyn Stage>) -> io::Result<u64> {
+    try!(output.write_u32::<LittleEndian>(MAGIC));
+    try!(output.write_u8(cfg.methods.len() as u8));
+    for met in cfg.methods.iter() {
+        try!(output.write_u8(met.len() as u8));
+        try!(output.write_all(met.as_bytes()));
+    }
+    let mut wsum = output;
+    for met in cfg.methods.iter().rev() {
+        match passes.get_mut(met) {
+            Some(pa) => wsum = (pa.encode)(wsum, cfg),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              format!("Pass {} is not implemented", *met))),
+        }
+    }
+    let n = try!(io::copy(input, &mut wsum));
+    try!(wsum.finish());
+    Ok(n)
+}
+
+/// Decompresses an archive from `input` into `output`, with the passes named
+/// by its header
+fn decompress(passes: &mut HashMap<String, Pass>, cfg: &Config, mut input: Box<dyn Read>,
+              output: &mut dyn Write) -> io::Result<u64> {
+    if try!(input.read_u32::<LittleEndian>()) != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Input is not a rust-compress archive"))
+    }
+    let mut methods = Vec::new();
+    for _ in 0..try!(input.read_u8()) {
+        let len = try!(input.read_u8()) as u64;
+        let mut bytes = Vec::new();
+        try!(input.push_exactly(len, &mut bytes));
+        match String::from_utf8(bytes) {
+            Ok(met) => methods.push(met),
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid pass name")),
+        }
+    }
+    let mut rsum = input;
+    for met in methods.iter().rev() {
+        info!("Found pass {}", *met);
+        match passes.get_mut(met) {
+            Some(pa) => rsum = (pa.decode)(rsum, cfg),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              format!("Pass {} is not implemented", *met))),
+        }
+    }
+    let n = try!(io::copy(&mut rsum, output));
+    try!(output.flush());
+    Ok(n)
+}
+
+/// Compresses `input` into a new vector
+fn compress_to_vec(passes: &mut HashMap<String, Pass>, cfg: &Config, input: &[u8]) -> io::Result<Vec<u8>> {
+    let shared = Shared(Rc::new(RefCell::new(Vec::new())));
+    try!(compress(passes, cfg, &mut &input[..], Box::new(Output(shared.clone()))));
+    let output = shared.0.borrow().clone();
+    Ok(output)
+}
+
+/// Compresses then decompresses the input, failing if the data differs
+fn verify(passes: &mut HashMap<String, Pass>, cfg: &Config, input: &[u8]) -> io::Result<()> {
+    let compressed = try!(compress_to_vec(passes, cfg, input));
+    let mut decompressed = Vec::new();
+    try!(decompress(passes, cfg, Box::new(io::Cursor::new(compressed.clone())), &mut decompressed));
+    if decompressed != input {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "The data decompressed differs from the input"))
+    }
+    println!("{}: {} -> {} bytes ({:.2}%), ok", cfg.methods.join(" "), input.len(), compressed.len(),
+             100.0 * compressed.len() as f64 / (input.len() as f64).max(1.0));
+    Ok(())
+}
+
+/// Returns the throughput of `size` bytes coded in `time`, in MB/s
+fn speed(size: usize, time: Duration) -> f64 {
+    let secs = time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9;
+    size as f64 / (1 << 20) as f64 / secs.max(1e-9)
+}
+
+/// Times the compression and the decompression of the input, keeping the
+/// fastest of the iterations of `cfg`
+fn bench(passes: &mut HashMap<String, Pass>, cfg: &Config, input: &[u8]) -> io::Result<()> {
+    let mut compressed = Vec::new();
+    let (mut encode, mut decode) = (Duration::from_secs(u64::max_value()), Duration::from_secs(u64::max_value()));
+    for _ in 0..cmp::max(cfg.iterations, 1) {
+        let start = Instant::now();
+        compressed = try!(compress_to_vec(passes, cfg, input));
+        encode = encode.min(start.elapsed());
+
+        let data = io::Cursor::new(compressed.clone());
+        let mut decompressed = Vec::with_capacity(input.len());
+        let start = Instant::now();
+        try!(decompress(passes, cfg, Box::new(data), &mut decompressed));
+        decode = decode.min(start.elapsed());
+        if decompressed != input {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "The data decompressed differs from the input"))
+        }
+    }
+    println!("{}: {} -> {} bytes ({:.2}%), compression {:.2} MB/s, decompression {:.2} MB/s",
+             cfg.methods.join(" "), input.len(), compressed.len(),
+             100.0 * compressed.len() as f64 / (input.len() as f64).max(1.0),
+             speed(input.len(), encode), speed(input.len(), decode));
+    Ok(())
+}
 
 /// main entry point
 pub fn main() {
@@ -75,108 +282,114 @@ pub fn main() {
     });
     passes.insert("ari".to_string(), Pass {
         encode: Box::new(|w,_c| {
-            Box::new(ari::ByteEncoder::new(w)) as Box<Write + 'static>
+            Box::new(ari::ByteEncoder::new(w)) as Box<dyn Stage>
         }),
         decode: Box::new(|r,_c| {
-            Box::new(ari::ByteDecoder::new(r)) as Box<Read + 'static>
+            Box::new(ari::ByteDecoder::new(r)) as Box<dyn Read>
         }),
         info: "Adaptive arithmetic byte coder".to_string(),
     });
     passes.insert("bwt".to_string(), Pass {
         encode: Box::new(|w,c| {
-            Box::new(bwt::Encoder::new(w, c.block_size)) as Box<Write + 'static>
+            Box::new(bwt::Encoder::new(w, c.block_size)) as Box<dyn Stage>
         }),
         decode: Box::new(|r,_c| {
-            Box::new(bwt::Decoder::new(r, true)) as Box<Read + 'static>
+            Box::new(bwt::Decoder::new(r, true)) as Box<dyn Read>
         }),
         info: "Burrows-Wheeler Transformation".to_string(),
     });
     passes.insert("mtf".to_string(), Pass {
         encode: Box::new(|w,_c| {
-            Box::new(bwt::mtf::Encoder::new(w)) as Box<Write + 'static>
+            Box::new(bwt::mtf::Encoder::new(w)) as Box<dyn Stage>
         }),
         decode: Box::new(|r,_c| {
-            Box::new(bwt::mtf::Decoder::new(r)) as Box<Read + 'static>
+            Box::new(bwt::mtf::Decoder::new(r)) as Box<dyn Read>
         }),
         info: "Move-To-Front Transformation".to_string(),
     });
-    /* // looks like we are missing the encoder implementation
-    passes.insert(~"flate", Pass {
-        encode: |w,_c| {
-            ~flate::Encoder::new(w, true) as ~Write
-        },
-        decode: |r,_c| {
-            ~flate::Decoder::new(r, true) as ~Read
-        },
-        info: ~"Standardized Ziv-Lempel + Huffman variant",
-    });*/
+    passes.insert("flate".to_string(), Pass {
+        encode: Box::new(|w,c| {
+            Box::new(flate::Encoder::new(w, c.level)) as Box<dyn Stage>
+        }),
+        decode: Box::new(|r,_c| {
+            Box::new(flate::Decoder::new(r)) as Box<dyn Read>
+        }),
+        info: "Standardized Ziv-Lempel + Huffman variant".to_string(),
+    });
     passes.insert("lz4".to_string(), Pass {
         encode: Box::new(|w,_c| {
-            Box::new(lz4::Encoder::new(w)) as Box<Write + 'static>
+            Box::new(lz4::Encoder::new(w)) as Box<dyn Stage>
         }),
-        decode: Box::new(|r,_c| { // LZ4 decoder seem to work
-            Box::new(lz4::Decoder::new(r)) as Box<Read + 'static>
+        decode: Box::new(|r,_c| {
+            Box::new(lz4::Decoder::new(r)) as Box<dyn Read>
         }),
         info: "Ziv-Lempel derivative, focused at speed".to_string(),
     });
+    passes.insert("rle".to_string(), Pass {
+        encode: Box::new(|w,_c| {
+            Box::new(rle::Encoder::new(w)) as Box<dyn Stage>
+        }),
+        decode: Box::new(|r,_c| {
+            Box::new(rle::Decoder::new(r)) as Box<dyn Read>
+        }),
+        info: "Run-Length Encoding".to_string(),
+    });
 
     let config = Config::query(env::args());
-    let mut input = io::stdin();
-    let mut output = io::stdout();
-    if config.decompress {
-        assert!(config.methods.is_empty(), "Decompression methods are set in stone");
-        match input.read_u32::<LittleEndian>() {
-            Ok(magic) if magic != MAGIC => {
-                error!("Input is not a rust-compress archive");
-                return
-            },
-            Err(e) => {
-                error!("Unable to read input: {:?}", e);
-                return
-            },
-            _ => () //OK
-        }
-        let methods: Vec<_> = (0..(input.read_u8().unwrap() as usize)).map(|_| {
-            let len = input.read_u8().unwrap() as u64;
-            let mut bytes = Vec::new();
-            input.push_exactly(len, &mut bytes).unwrap();
-            str::from_utf8(&bytes[..]).unwrap().to_string()
-        }).collect();
-        let mut rsum: Box<Read> = Box::new(input);
-        for met in methods.iter() {
-            info!("Found pass {}", *met);
-            match passes.get_mut(met) {
-                Some(pa) => rsum = (pa.decode)(rsum, &config),
-                None => panic!("Pass is not implemented"),
-            }
-        }
-        io::copy(&mut rsum, &mut output).unwrap();
-    }else if config.methods.is_empty() {
-        println!("rust-compress test application");
-        println!("Usage:");
-        println!("\t{} <options> <method1> .. <methodN> <input >output", config.exe_name);
-        println!("Options:");
-        println!("\t-d (to decompress)");
-        println!("\t-block<N> (BWT block size)");
-        println!("Passes:");
-        for (name,pa) in passes.iter() {
-            println!("\t{} = {}", *name, pa.info);
-        }
-    }else {
-        output.write_u32::<LittleEndian>(MAGIC).unwrap();
-        output.write_u8(config.methods.len() as u8).unwrap();
-        for met in config.methods.iter() {
-            output.write_u8(met.len() as u8).unwrap();
-            output.write_all(met.as_bytes()).unwrap();
-        }
-        let mut wsum: Box<Write> = Box::new(output);
-        for met in config.methods.iter() {
-            match passes.get_mut(met) {
-                Some(pa) => wsum = (pa.encode)(wsum, &config),
-                None => panic!("Pass {} is not implemented", *met)
+    let result = match config.command {
+        Command::Decompress => {
+            assert!(config.methods.is_empty(), "Decompression methods are set in stone");
+            decompress(&mut passes, &config, Box::new(io::stdin()), &mut io::stdout()).map(|_| ())
+        },
+        Command::Corpus => {
+            let corpus = match config.methods.first() {
+                Some(dir) => bench::Corpus::load(dir),
+                None => Ok(bench::Corpus::samples()),
+            };
+            corpus.and_then(|corpus| {
+                println!("{}: {} files, {} bytes", corpus.name, corpus.files.len(), corpus.size());
+                let measures = try!(bench::run(&bench::codecs(), &corpus, config.iterations));
+                bench::report(&measures, &mut io::stdout())
+            })
+        },
+        _ if config.methods.is_empty() => {
+            println!("rust-compress test application");
+            println!("Usage:");
+            println!("\t{} [command] <options> <method1> .. <methodN> <input >output", config.exe_name);
+            println!("Commands:");
+            println!("\tcompress (the default, the first method coding the input first)");
+            println!("\tdecompress (as -d)");
+            println!("\tverify (to compress then decompress the input, and compare)");
+            println!("\tbench (to time the compression and decompression of the input)");
+            println!("\tcorpus [dir] (to benchmark all the codecs on the files of dir, or on generated samples)");
+            println!("Options:");
+            println!("\t-d (to decompress)");
+            println!("\t-block<N> (BWT block size)");
+            println!("\t-level<N> (DEFLATE level, from 0 to 9)");
+            println!("\t-iter<N> (iterations of bench and corpus)");
+            println!("Passes:");
+            for (name,pa) in passes.iter() {
+                println!("\t{} = {}", *name, pa.info);
             }
-        }
-        io::copy(&mut input, &mut wsum).unwrap();
-        wsum.flush().unwrap();
+            Ok(())
+        },
+        Command::Compress => {
+            compress(&mut passes, &config, &mut io::stdin(), Box::new(Output(io::stdout()))).map(|_| ())
+        },
+        command => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input).and_then(|_| {
+                if command == Command::Verify {
+                    verify(&mut passes, &config, &input)
+                } else {
+                    bench(&mut passes, &config, &input)
+                }
+            })
+        },
+    };
+    if let Err(e) = result {
+        error!("{}", e);
+        let _ = writeln!(io::stderr(), "{}: {}", config.exe_name, e);
+        process::exit(1);
     }
 }
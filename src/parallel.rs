@@ -0,0 +1,686 @@
+/*!
+
+Multi-threaded chunked compression. Requires `parallel` feature
+
+This module cuts a stream into chunks, compresses them apart with any codec
+of the `codec` module on a pool of threads, and frames them with the CRC32
+of their data, so that they are decompressed on as many threads again. The
+chunks are compressed independently, which costs a little compression ratio.
+
+The stream starts with a magic number and the size of the chunks, as
+little-endian 32-bit integers. Each chunk is preceded by the sizes of its
+compressed and decompressed data, and by the CRC32 of the latter, in the
+same way. A chunk of no compressed data ends the stream.
+
+The codecs are given by value, and must be `Send` and `Sync` to be shared by
+the threads, which the codecs of the `codec` module are, unlike their trait
+objects.
+
+# Example
+
+```rust
+use std::io::{Read, Write};
+use compress::{codec, parallel};
+
+let data: Vec<u8> = (0..100000).map(|i| (i / 100) as u8).collect();
+let mut e = parallel::Encoder::new(Vec::new(), codec::Lz4, 4);
+e.set_chunk_size(16384);
+e.write_all(&data).unwrap();
+let (encoded, result) = e.finish();
+result.unwrap();
+
+let mut d = parallel::Decoder::new(&encoded[..], codec::Lz4, 4);
+let mut decoded = Vec::new();
+d.read_to_end(&mut decoded).unwrap();
+assert!(decoded == data);
+```
+
+*/
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use Crc32;
+use codec::{Compressor, Decompressor};
+use error::{checksum_mismatch, corrupt, limit_exceeded, truncated};
+use process::{drain, gather, Process, Reader, Status};
+use ring::{ring, Consumer, Producer};
+
+const MAGIC: u32 = 0x7a706321; // !cpz
+
+/// Default amount of data of each chunk
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Largest amount of data of a chunk
+const MAX_CHUNK_SIZE: usize = 1 << 30;
+
+/// Size of the frame in front of each chunk
+const FRAME_SIZE: usize = 12;
+
+/// Ratio of the decompressed size to the compressed one up to which the buffer
+/// of a chunk is reserved at once
+const MAX_EXPANSION: usize = 64;
+
+fn put_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend_from_slice(&[n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]);
+}
+
+fn get_u32(buf: &[u8]) -> u32 {
+    buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24
+}
+
+/// Chunks given to each thread at most, waiting or coded
+const PER_THREAD: usize = 2;
+
+/// A thread of the pool, with the queues of its chunks and their results
+struct Worker {
+    jobs: Producer<Vec<u8>>,
+    results: Consumer<io::Result<Vec<u8>>>,
+    thread: JoinHandle<()>,
+}
+
+/// Threads coding the chunks of a stream, of which the results are taken in
+/// the order the chunks were given. The chunks are dealt to the threads in
+/// turn, so that the results are taken from them in the same turn.
+struct Pool {
+    workers: Vec<Worker>,
+    sent: u64,
+    taken: u64,
+}
+
+impl Pool {
+    /// Starts `threads` threads coding the chunks with `work`
+    fn new<F>(threads: usize, work: F) -> Pool
+        where F: Fn(Vec<u8>) -> io::Result<Vec<u8>> + Send + Sync + 'static {
+        let work = Arc::new(work);
+        let workers = (0..threads).map(|_| {
+            let (jobs, mut queue) = ring::<Vec<u8>>(PER_THREAD);
+            let (mut done, results) = ring(PER_THREAD);
+            let work = work.clone();
+            let thread = thread::spawn(move || {
+                while let Some(data) = queue.pop() {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| work(data))).unwrap_or_else(|_| {
+                        Err(io::Error::new(io::ErrorKind::Other, "parallel coding thread panicked"))
+                    });
+                    if done.push(result).is_err() {
+                        return
+                    }
+                }
+            });
+            Worker { jobs: jobs, results: results, thread: thread }
+        }).collect();
+        Pool {
+            workers: workers,
+            sent: 0,
+            taken: 0,
+        }
+    }
+
+    /// Returns the number of chunks of which the result isn't taken yet
+    fn pending(&self) -> usize {
+        (self.sent - self.taken) as usize
+    }
+
+    fn submit(&mut self, data: Vec<u8>) {
+        let i = (self.sent % self.workers.len() as u64) as usize;
+        let worker = &mut self.workers[i];
+        // a worker only stops once its queue is dropped
+        let _ = worker.jobs.push(data);
+        self.sent += 1;
+    }
+
+    /// Waits for the result of the oldest chunk not taken yet, if any
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        if self.taken == self.sent {
+            return None
+        }
+        let i = (self.taken % self.workers.len() as u64) as usize;
+        let worker = &mut self.workers[i];
+        self.taken += 1;
+        match worker.results.pop() {
+            Some(result) => Some(result),
+            None => Some(Err(io::Error::new(io::ErrorKind::Other, "parallel coding threads stopped"))),
+        }
+    }
+
+    /// Waits for the chunks not taken yet, dropping their results
+    fn clear(&mut self) {
+        while self.next().is_some() {}
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        for worker in self.workers.drain(..) {
+            let Worker { jobs, results, thread } = worker;
+            drop(jobs);
+            drop(results);
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Returns the number of chunks in flight for `threads` threads, so that
+/// each one has another chunk waiting
+fn max_pending(threads: usize) -> usize {
+    PER_THREAD * threads
+}
+
+/// This structure compresses a stream in chunks on several threads. This is
+/// a wrapper around an internal writer which bytes will be written to.
+pub struct Encoder<W> {
+    w: W,
+    pool: Pool,
+    threads: usize,
+    chunk_size: usize,
+    buf: Vec<u8>,
+    wrote_header: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder writing to the given output stream, compressing
+    /// the chunks with `compressor` on `threads` threads.
+    pub fn new<C>(w: W, compressor: C, threads: usize) -> Encoder<W>
+        where C: Compressor + Send + Sync + 'static {
+        let threads = if threads == 0 { 1 } else { threads };
+        Encoder {
+            w: w,
+            pool: Pool::new(threads, move |data| compress_chunk(&compressor, &data)),
+            threads: threads,
+            chunk_size: CHUNK_SIZE,
+            buf: Vec::new(),
+            wrote_header: false,
+        }
+    }
+
+    /// Sets the amount of data of each chunk, 1MB by default and 1GB at
+    /// most. This must be called before any data is written.
+    pub fn set_chunk_size(&mut self, size: usize) {
+        assert!(size > 0 && size <= MAX_CHUNK_SIZE && self.buf.is_empty() && !self.wrote_header);
+        self.chunk_size = size;
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.wrote_header {
+            let mut header = Vec::with_capacity(8);
+            put_u32(&mut header, MAGIC);
+            put_u32(&mut header, self.chunk_size as u32);
+            try!(self.w.write_all(&header));
+            self.wrote_header = true;
+        }
+        Ok(())
+    }
+
+    /// Writes the compressed chunks until at most `pending` are left
+    fn write_chunks(&mut self, pending: usize) -> io::Result<()> {
+        while self.pool.pending() > pending {
+            match self.pool.next() {
+                Some(chunk) => try!(self.w.write_all(&try!(chunk))),
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands over the buffered data to the threads, after making room for it
+    fn spawn(&mut self) -> io::Result<()> {
+        try!(self.write_header());
+        let pending = max_pending(self.threads) - 1;
+        try!(self.write_chunks(pending));
+        let data = mem::take(&mut self.buf);
+        self.pool.submit(data);
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.end();
+        let Encoder { w, .. } = self;
+        (w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`
+    /// with the same chunk size, keeping the threads. The previous writer is
+    /// returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.end();
+        self.pool.clear();
+        self.buf.truncate(0);
+        self.wrote_header = false;
+        (mem::replace(&mut self.w, w), result)
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        try!(self.flush());
+        let mut end = Vec::with_capacity(FRAME_SIZE);
+        for _ in 0..3 {
+            put_u32(&mut end, 0);
+        }
+        try!(self.w.write_all(&end));
+        self.w.flush()
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buf.capacity() == 0 {
+            self.buf.reserve_exact(self.chunk_size);
+        }
+        let amt = ::std::cmp::min(self.chunk_size - self.buf.len(), buf.len());
+        self.buf.extend_from_slice(&buf[..amt]);
+        if self.buf.len() == self.chunk_size {
+            try!(self.spawn());
+        }
+        Ok(amt)
+    }
+
+    /// Compresses the buffered data as a chunk of its own, and waits for all
+    /// the chunks to be written out
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.write_header());
+        if !self.buf.is_empty() {
+            try!(self.spawn());
+        }
+        try!(self.write_chunks(0));
+        self.w.flush()
+    }
+}
+
+/// Compresses a chunk behind its frame
+fn compress_chunk<C: Compressor>(compressor: &C, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut crc = Crc32::new();
+    crc.feed(data);
+    let mut chunk = vec![0; FRAME_SIZE];
+    try!(compressor.compress(data, &mut chunk));
+    let len = chunk.len() - FRAME_SIZE;
+    if len > u32::max_value() as usize {
+        return limit_exceeded("compressed chunk larger than 4GB")
+    }
+    let mut frame = Vec::with_capacity(FRAME_SIZE);
+    put_u32(&mut frame, len as u32);
+    put_u32(&mut frame, data.len() as u32);
+    put_u32(&mut frame, crc.result());
+    chunk[..FRAME_SIZE].copy_from_slice(&frame);
+    Ok(chunk)
+}
+
+/// Decompresses a chunk, given with its decompressed size and CRC32 in front
+fn decompress_chunk<D: Decompressor>(decompressor: &D, chunk: &[u8]) -> io::Result<Vec<u8>> {
+    let (size, expected) = (get_u32(&chunk[..4]) as usize, get_u32(&chunk[4..8]));
+    // the size in the frame is only trusted as far as the compressed data
+    // may plausibly expand, the vector growing past it as it is decoded
+    let mut data = Vec::with_capacity(cmp::min(size, MAX_EXPANSION * (chunk.len() - 8)));
+    try!(decompressor.decoder(Box::new(&chunk[8..])).take(size as u64 + 1).read_to_end(&mut data));
+    if data.len() != size {
+        return corrupt("parallel chunk size mismatch")
+    }
+    let mut crc = Crc32::new();
+    crc.feed(&data);
+    if crc.result() != expected {
+        return checksum_mismatch("parallel chunk checksum mismatch")
+    }
+    Ok(data)
+}
+
+/// State of a `Processor`, with the number of bytes it gathers
+enum State {
+    /// The magic number and the size of the chunks
+    Header,
+    /// The sizes and the CRC32 of a chunk
+    Frame,
+    /// The decompressed size and the CRC32 of a chunk, and its compressed
+    /// data, of the given length in all
+    Chunk(usize),
+    Done,
+}
+
+/// This structure decompresses the chunks of a stream on several threads, fed
+/// with slices of it
+pub struct Processor {
+    pool: Pool,
+    threads: usize,
+    state: State,
+    chunk_size: usize,
+    /// The part being gathered, with the decompressed size and the CRC32 of a
+    /// chunk kept in front of its data
+    chunk: Vec<u8>,
+    output: Vec<u8>,
+    start: usize,
+}
+
+impl Processor {
+    /// Creates a new decoder, decompressing the chunks with `decompressor` on
+    /// `threads` threads
+    pub fn new<D>(decompressor: D, threads: usize) -> Processor
+        where D: Decompressor + Send + Sync + 'static {
+        let threads = if threads == 0 { 1 } else { threads };
+        Processor {
+            pool: Pool::new(threads, move |chunk| decompress_chunk(&decompressor, &chunk)),
+            threads: threads,
+            state: State::Header,
+            chunk_size: 0,
+            chunk: Vec::new(),
+            output: Vec::new(),
+            start: 0,
+        }
+    }
+
+    /// Starts decoding a new stream, keeping the threads and the buffers.
+    /// This waits for the chunks being decompressed.
+    pub fn reset(&mut self) {
+        self.pool.clear();
+        self.state = State::Header;
+        self.chunk_size = 0;
+        self.chunk.truncate(0);
+        self.output.truncate(0);
+        self.start = 0;
+    }
+
+    /// Tests whether the end of the stream has been reached
+    pub fn eof(&self) -> bool {
+        match self.state {
+            State::Done => self.pool.pending() == 0 && self.start == self.output.len(),
+            _ => false,
+        }
+    }
+
+    /// Decodes the part gathered in `chunk`, returning the next state
+    fn decode_part(&mut self) -> io::Result<State> {
+        match self.state {
+            State::Header => {
+                if get_u32(&self.chunk) != MAGIC {
+                    return corrupt("invalid parallel stream magic")
+                }
+                let size = get_u32(&self.chunk[4..]) as usize;
+                if size == 0 || size > MAX_CHUNK_SIZE {
+                    return corrupt("invalid parallel chunk size")
+                }
+                self.chunk_size = size;
+                self.chunk.truncate(0);
+                Ok(State::Frame)
+            }
+            State::Frame => {
+                let (len, size) = (get_u32(&self.chunk) as usize, get_u32(&self.chunk[4..]) as usize);
+                if len == 0 {
+                    if size != 0 {
+                        return corrupt("invalid parallel stream end")
+                    }
+                    return Ok(State::Done)
+                }
+                if size > self.chunk_size {
+                    return corrupt("parallel chunk larger than the chunk size")
+                }
+                // the decompressed size and the CRC32 are kept in front
+                self.chunk.drain(..4);
+                Ok(State::Chunk(len + 8))
+            }
+            State::Chunk(_) => {
+                let chunk = mem::take(&mut self.chunk);
+                self.pool.submit(chunk);
+                Ok(State::Frame)
+            }
+            State::Done => unreachable!(),
+        }
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.output, &mut self.start, output, &mut produced) {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            // the chunks are read ahead until enough are pending
+            let done = match self.state { State::Done => true, _ => false };
+            if done || self.pool.pending() >= max_pending(self.threads) {
+                match self.pool.next() {
+                    Some(data) => {
+                        self.output = try!(data);
+                        self.start = 0;
+                        continue
+                    }
+                    None => return Ok((pos, produced, Status::Done)),
+                }
+            }
+            let len = match self.state {
+                State::Header => 8,
+                State::Frame => FRAME_SIZE,
+                State::Chunk(len) => len,
+                State::Done => unreachable!(),
+            };
+            if !gather(&mut self.chunk, input, &mut pos, len) {
+                return Ok((pos, produced, Status::NeedInput))
+            }
+            self.state = try!(self.decode_part());
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.state {
+            State::Done => Ok(()),
+            _ => truncated("truncated parallel stream"),
+        }
+    }
+}
+
+/// This structure decompresses the chunks of a stream on several threads.
+/// This is a wrapper around an internal reader which bytes will be read from.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder reading from the given stream, decompressing the
+    /// chunks with `decompressor` on `threads` threads.
+    pub fn new<D>(r: R, decompressor: D, threads: usize) -> Decoder<R>
+        where D: Decompressor + Send + Sync + 'static {
+        Decoder {
+            inner: Reader::new(r, Processor::new(decompressor, threads)),
+        }
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the threads of the
+    /// decoder, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Tests whether the end of the stream has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.processor().eof()
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Returns the wrapped reader. The data read ahead of the output is lost
+    /// with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+/// Compresses `data` in chunks with `compressor` on `threads` threads
+pub fn compress<C>(compressor: C, data: &[u8], threads: usize) -> io::Result<Vec<u8>>
+    where C: Compressor + Send + Sync + 'static {
+    let mut e = Encoder::new(Vec::new(), compressor, threads);
+    try!(e.write_all(data));
+    let (compressed, result) = e.finish();
+    result.map(|_| compressed)
+}
+
+/// Decompresses the chunks of `data` with `decompressor` on `threads` threads
+pub fn decompress<D>(decompressor: D, data: &[u8], threads: usize) -> io::Result<Vec<u8>>
+    where D: Decompressor + Send + Sync + 'static {
+    let mut decompressed = Vec::new();
+    try!(Decoder::new(data, decompressor, threads).read_to_end(&mut decompressed));
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use codec::{Compressor, Decompressor};
+    use error::Error;
+    use testing::push;
+    use super::{compress, decompress, Decoder, Encoder, Processor};
+
+    /// A codec copying the data
+    struct Stored;
+
+    impl Compressor for Stored {
+        fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+            io::copy(input, output)
+        }
+    }
+
+    impl Decompressor for Stored {
+        fn decoder<'a>(&self, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+            input
+        }
+    }
+
+    fn encode(data: &[u8], threads: usize, chunk_size: usize) -> Vec<u8> {
+        let mut e = Encoder::new(Vec::new(), Stored, threads);
+        e.set_chunk_size(chunk_size);
+        for piece in data.chunks(777) {
+            e.write_all(piece).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        encoded
+    }
+
+    #[test]
+    fn roundtrips() {
+        let data = &include_bytes!("data/test.txt")[..];
+        for &(threads, chunk_size) in [(1, 100), (4, 100), (3, 1000), (8, 1 << 20)].iter() {
+            let encoded = encode(data, threads, chunk_size);
+            let mut d = Decoder::new(&encoded[..], Stored, threads);
+            let mut decoded = Vec::new();
+            d.read_to_end(&mut decoded).unwrap();
+            assert!(d.eof());
+            assert!(&decoded[..] == data);
+        }
+        assert!(&decompress(Stored, &compress(Stored, data, 2).unwrap(), 2).unwrap()[..] == data);
+        assert!(decompress(Stored, &compress(Stored, b"", 2).unwrap(), 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn flushes() {
+        let mut e = Encoder::new(Vec::new(), Stored, 2);
+        e.write_all(b"abc").unwrap();
+        e.flush().unwrap();
+        e.write_all(b"defg").unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        // the header, two chunks and the end
+        assert_eq!(encoded.len(), 8 + 12 + 3 + 12 + 4 + 12);
+        assert_eq!(decompress(Stored, &encoded, 2).unwrap(), b"abcdefg");
+    }
+
+    #[test]
+    fn corrupt() {
+        let data = &include_bytes!("data/test.txt")[..];
+        let encoded = encode(data, 2, 1000);
+
+        let mut bad = encoded.clone();
+        bad[2000] ^= 1;
+        let err = decompress(Stored, &bad, 2).unwrap_err();
+        assert_eq!(Error::of(&err), Some(Error::ChecksumMismatch { detail: "parallel chunk checksum mismatch" }));
+
+        let err = decompress(Stored, &encoded[..(encoded.len() - 5)], 2).unwrap_err();
+        assert!(match Error::of(&err) { Some(Error::UnexpectedEof { .. }) => true, _ => false });
+
+        let mut bad = encoded.clone();
+        bad[0] ^= 1;
+        assert!(decompress(Stored, &bad, 2).is_err());
+
+        // a chunk claiming the largest size, of which the buffer isn't
+        // reserved in full
+        let mut bad = encoded.clone();
+        bad[4..8].copy_from_slice(&[0, 0, 0, 0x40]);
+        bad[12..16].copy_from_slice(&[0, 0, 0, 0x40]);
+        let err = decompress(Stored, &bad, 2).unwrap_err();
+        assert_eq!(Error::of(&err), Some(Error::CorruptInput { offset: None, detail: "parallel chunk size mismatch" }));
+    }
+
+    #[test]
+    #[cfg(feature="lz4")]
+    fn codec() {
+        use codec::Lz4;
+        let data = &include_bytes!("data/test.txt")[..];
+        let compressed = compress(Lz4, data, 4).unwrap();
+        assert!(&decompress(Lz4, &compressed, 4).unwrap()[..] == data);
+    }
+
+    #[test]
+    fn reset() {
+        let data = &include_bytes!("data/test.txt")[..];
+        let mut e = Encoder::new(Vec::new(), Stored, 3);
+        e.set_chunk_size(100);
+        e.write_all(data).unwrap();
+        let (a, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(b"hello").unwrap();
+        let (b, result) = e.finish();
+        result.unwrap();
+        assert!(a == encode(data, 3, 100));
+        assert!(b == encode(b"hello", 3, 100));
+
+        // chunks are still pending when the decoder is reset
+        let mut d = Decoder::new(&a[..], Stored, 3);
+        d.read_exact(&mut [0; 10]).unwrap();
+        d.reset(&b[..]);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(d.eof());
+        assert_eq!(decoded, b"hello");
+    }
+
+    #[test]
+    fn decode_push() {
+        let data = &include_bytes!("data/test.txt")[..];
+        let encoded = encode(data, 3, 1000);
+        for &step in [1, 333, 1 << 16].iter() {
+            assert!(&push(&mut Processor::new(Stored, 3), &encoded, step).unwrap()[..] == data);
+        }
+        for &len in [0, 10, 100, encoded.len() - 1].iter() {
+            assert!(push(&mut Processor::new(Stored, 3), &encoded[..len], 100).is_err());
+        }
+    }
+
+    #[test]
+    fn max_output_size() {
+        let data = &include_bytes!("data/test.txt")[..];
+        let encoded = encode(data, 3, 100);
+        let mut d = Decoder::new(&encoded[..], Stored, 3);
+        d.set_max_output_size(data.len() as u64 - 1);
+        let err = d.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(match Error::of(&err) { Some(Error::LimitExceeded { .. }) => true, _ => false });
+
+        let mut d = Decoder::new(&encoded[..], Stored, 3);
+        d.set_max_output_size(data.len() as u64);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(&decoded[..] == data);
+    }
+}
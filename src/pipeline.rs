@@ -0,0 +1,830 @@
+/*!
+
+Composition of transforms. Requires `pipeline` feature
+
+This module chains the stages of the other modules, as the LZP, BWT, MTF,
+RLE and range coding stages of a block-sorting compressor, into a single
+encoder and decoder. The stages are given in the order they are applied to
+the data, and are recorded in a header, so that the decoder builds the
+reverse chain by itself. The LZ4 and DEFLATE stages need the `lz4` and
+`flate` features as well.
+
+The stream starts with a magic number as a little-endian 32-bit integer and
+with the number of stages, then each stage as a byte telling which it is and
+its parameter, as a little-endian 32-bit integer again.
+
+# Example
+
+```rust
+use std::io::{Read, Write};
+use compress::pipeline::{Decoder, Pipeline, Stage};
+use compress::rle::Scheme;
+
+let data: Vec<u8> = (0..10000).map(|i| (i / 100) as u8).collect();
+let pipeline = Pipeline::new()
+    .stage(Stage::Lzp { min_len: 32 })
+    .stage(Stage::Bwt { block_size: 1 << 16 })
+    .stage(Stage::Mtf)
+    .stage(Stage::Rle(Scheme::Zero))
+    .stage(Stage::Ari);
+let mut e = pipeline.encoder(Vec::new());
+e.write_all(&data).unwrap();
+let (encoded, result) = e.finish();
+result.unwrap();
+
+let mut d = Decoder::new(&encoded[..]).unwrap();
+assert!(d.stages() == pipeline.stages());
+let mut decoded = Vec::new();
+d.read_to_end(&mut decoded).unwrap();
+assert!(decoded == data);
+```
+
+*/
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::mem;
+
+use error::{corrupt, truncated, unsupported};
+use process::{gather, Process, Reader, Status};
+
+use bwt;
+use entropy::ari;
+#[cfg(feature="flate")]
+use flate;
+#[cfg(feature="lz4")]
+use lz4;
+use lzp;
+use rle;
+
+const MAGIC: u32 = 0x70706321; // !cpp
+
+/// Size of the header of each stage
+const STAGE_SIZE: usize = 5;
+
+/// A transform of the chain, with its settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Removal of the predicted matches of at least `min_len` bytes
+    Lzp {
+        /// Shortest match replaced, from 4 to 255
+        min_len: usize,
+    },
+    /// Burrows-Wheeler transform of blocks of `block_size` bytes
+    Bwt {
+        /// Size of the blocks
+        block_size: usize,
+    },
+    /// Move-to-front transform
+    Mtf,
+    /// Run-length encoding with the given scheme
+    Rle(rle::Scheme),
+    /// Adaptive range coding of the bytes
+    Ari,
+    /// LZ4 frame
+    #[cfg(feature="lz4")]
+    Lz4,
+    /// Raw DEFLATE stream of the given level, from 0 to 9
+    #[cfg(feature="flate")]
+    Flate {
+        /// Level of compression
+        level: u32,
+    },
+}
+
+fn scheme_param(scheme: rle::Scheme) -> u32 {
+    use rle::Scheme::*;
+    let (id, size) = match scheme {
+        Doubled => (0, 0),
+        Words(unit) => (1, unit),
+        Escape => (2, 0),
+        Bzip2 => (3, 0),
+        PackBits => (4, 0),
+        Rows(stride) => (5, stride),
+        Zero => (6, 0),
+    };
+    assert!(size < 1 << 24, "invalid rle size {}", size);
+    id | (size as u32) << 8
+}
+
+fn param_scheme(param: u32) -> io::Result<rle::Scheme> {
+    use rle::Scheme::*;
+    let size = (param >> 8) as usize;
+    Ok(match param & 0xff {
+        0 => Doubled,
+        1 if size == 2 || size == 4 || size == 8 => Words(size),
+        2 => Escape,
+        3 => Bzip2,
+        4 => PackBits,
+        5 if size > 0 => Rows(size),
+        6 => Zero,
+        _ => return corrupt("invalid pipeline rle scheme"),
+    })
+}
+
+fn param(n: usize) -> u32 {
+    assert!(n <= u32::MAX as usize, "invalid pipeline stage parameter {}", n);
+    n as u32
+}
+
+impl Stage {
+    /// The byte telling which stage it is, and its parameter
+    fn to_header(self) -> (u8, u32) {
+        match self {
+            Stage::Lzp { min_len } => (0, param(min_len)),
+            Stage::Bwt { block_size } => (1, param(block_size)),
+            Stage::Mtf => (2, 0),
+            Stage::Rle(scheme) => (3, scheme_param(scheme)),
+            Stage::Ari => (4, 0),
+            #[cfg(feature="lz4")]
+            Stage::Lz4 => (5, 0),
+            #[cfg(feature="flate")]
+            Stage::Flate { level } => (6, level),
+        }
+    }
+
+    fn from_header(id: u8, param: u32) -> io::Result<Stage> {
+        match id {
+            0 => Ok(Stage::Lzp { min_len: param as usize }),
+            1 => Ok(Stage::Bwt { block_size: param as usize }),
+            2 => Ok(Stage::Mtf),
+            3 => param_scheme(param).map(Stage::Rle),
+            4 => Ok(Stage::Ari),
+            #[cfg(feature="lz4")]
+            5 => Ok(Stage::Lz4),
+            #[cfg(feature="flate")]
+            6 => Ok(Stage::Flate { level: param }),
+            _ if id <= 6 => unsupported("pipeline stage of a disabled feature"),
+            _ => corrupt("invalid pipeline stage"),
+        }
+    }
+
+    fn encoder<'a, W: Write + 'a>(self, w: Box<dyn Link<W> + 'a>) -> Box<dyn Link<W> + 'a> {
+        match self {
+            Stage::Lzp { min_len } => Box::new(lzp::Encoder::new(w, min_len)),
+            Stage::Bwt { block_size } => Box::new(bwt::Encoder::new(w, block_size)),
+            Stage::Mtf => Box::new(bwt::mtf::Encoder::new(w)),
+            Stage::Rle(scheme) => Box::new(rle::Encoder::with_scheme(w, scheme)),
+            Stage::Ari => Box::new(ari::ByteEncoder::new(w)),
+            #[cfg(feature="lz4")]
+            Stage::Lz4 => Box::new(lz4::Encoder::new(w)),
+            #[cfg(feature="flate")]
+            Stage::Flate { level } => Box::new(flate::Encoder::new(w, level)),
+        }
+    }
+
+    fn processor(self) -> Box<dyn Process> {
+        match self {
+            Stage::Lzp { .. } => Box::new(lzp::Processor::new()),
+            Stage::Bwt { .. } => Box::new(bwt::Processor::new(true)),
+            Stage::Mtf => Box::new(bwt::mtf::Processor::new()),
+            Stage::Rle(scheme) => Box::new(rle::Processor::with_scheme(scheme)),
+            Stage::Ari => Box::new(ari::ByteProcessor::new()),
+            #[cfg(feature="lz4")]
+            Stage::Lz4 => Box::new(lz4::Processor::new()),
+            #[cfg(feature="flate")]
+            Stage::Flate { .. } => Box::new(flate::Processor::new()),
+        }
+    }
+}
+
+/// A list of stages, applied in turn to the data
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    /// Creates a pipeline of no stages, which copies the data
+    pub fn new() -> Pipeline {
+        Pipeline { stages: Vec::new() }
+    }
+
+    /// Appends a stage, applied to the output of the previous ones. There
+    /// are 255 stages at most.
+    pub fn stage(mut self, stage: Stage) -> Pipeline {
+        assert!(self.stages.len() < 255, "too many pipeline stages");
+        // checks the parameters fit in the header
+        stage.to_header();
+        self.stages.push(stage);
+        self
+    }
+
+    /// Returns the stages, in the order they are applied to the data
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    /// Returns the stages as the header records them, after the magic number,
+    /// for the containers keeping them apart from the data
+    pub fn options(&self) -> Vec<u8> {
+        let mut options = Vec::with_capacity(1 + STAGE_SIZE * self.stages.len());
+        options.push(self.stages.len() as u8);
+        for stage in &self.stages {
+            let (id, param) = stage.to_header();
+            options.push(id);
+            put_u32(&mut options, param);
+        }
+        options
+    }
+
+    /// Reads back the stages recorded by `options`
+    pub fn from_options(options: &[u8]) -> io::Result<Pipeline> {
+        if options.is_empty() || options.len() != 1 + STAGE_SIZE * options[0] as usize {
+            return corrupt("invalid pipeline stages")
+        }
+        let mut stages = Vec::with_capacity(options[0] as usize);
+        for stage in options[1..].chunks(STAGE_SIZE) {
+            stages.push(try!(Stage::from_header(stage[0], get_u32(&stage[1..]))));
+        }
+        Ok(Pipeline { stages: stages })
+    }
+
+    /// Creates an encoder running the data through the stages, of which the
+    /// output, after the header, is written to the given output stream
+    pub fn encoder<'a, W: Write + 'a>(&self, w: W) -> Encoder<'a, W> {
+        let mut header = Vec::with_capacity(5 + STAGE_SIZE * self.stages.len());
+        put_u32(&mut header, MAGIC);
+        header.extend_from_slice(&self.options());
+        self.chain(w, header)
+    }
+
+    /// Creates an encoder as `encoder`, of which the output has no header
+    pub fn raw_encoder<'a, W: Write + 'a>(&self, w: W) -> Encoder<'a, W> {
+        self.chain(w, Vec::new())
+    }
+
+    /// Creates a decoder of the output of `raw_encoder` with the same stages
+    pub fn raw_decoder<'a, R: Read + 'a>(&self, r: R) -> Decoder<'a> {
+        Decoder { inner: Reader::new(Box::new(r), self.raw_processor()) }
+    }
+
+    /// Creates a decoder of the output of `raw_encoder` as `raw_decoder`,
+    /// fed with slices of it
+    pub fn raw_processor(&self) -> Processor {
+        let mut p = Processor::new();
+        p.build(self.stages.clone());
+        p.raw = true;
+        p
+    }
+
+    fn chain<'a, W: Write + 'a>(&self, w: W, header: Vec<u8>) -> Encoder<'a, W> {
+        Encoder {
+            chain: self.links(w, header.clone()),
+            pipeline: self.clone(),
+            header: header,
+        }
+    }
+
+    fn links<'a, W: Write + 'a>(&self, w: W, header: Vec<u8>) -> Box<dyn Link<W> + 'a> {
+        let mut chain: Box<dyn Link<W> + 'a> = Box::new(End { w: w, header: header });
+        for stage in self.stages.iter().rev() {
+            chain = stage.encoder(chain);
+        }
+        chain
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend_from_slice(&[n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]);
+}
+
+fn get_u32(buf: &[u8]) -> u32 {
+    buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24
+}
+
+/// A stage of the chain of encoders, finished with the stages after it
+trait Link<W>: Write {
+    fn finish(self: Box<Self>) -> (W, io::Result<()>);
+}
+
+/// The end of the chain, writing the header before the data
+struct End<W> {
+    w: W,
+    header: Vec<u8>,
+}
+
+impl<W: Write> End<W> {
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header.is_empty() {
+            try!(self.w.write_all(&mem::take(&mut self.header)));
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for End<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+        self.w.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.write_header());
+        self.w.flush()
+    }
+}
+
+impl<W: Write> Link<W> for End<W> {
+    fn finish(mut self: Box<Self>) -> (W, io::Result<()>) {
+        let result = self.flush();
+        (self.w, result)
+    }
+}
+
+macro_rules! links {
+    ($($feature:tt: $($encoder:ident)::+),*) => {$(
+        #[cfg(feature=$feature)]
+        impl<'a, W: Write> Link<W> for $($encoder)::+<Box<dyn Link<W> + 'a>> {
+            fn finish(self: Box<Self>) -> (W, io::Result<()>) {
+                let (next, result) = (*self).finish();
+                let (w, rest) = next.finish();
+                (w, result.and(rest))
+            }
+        }
+    )*}
+}
+
+links! {
+    "lzp": lzp::Encoder,
+    "bwt": bwt::Encoder,
+    "rle": rle::Encoder,
+    "entropy": ari::ByteEncoder,
+    "lz4": lz4::Encoder,
+    "flate": flate::Encoder
+}
+
+impl<'a, W: Write> Link<W> for bwt::mtf::Encoder<Box<dyn Link<W> + 'a>> {
+    fn finish(self: Box<Self>) -> (W, io::Result<()>) {
+        (*self).finish().finish()
+    }
+}
+
+/// Encoder of the data through the stages of a pipeline
+pub struct Encoder<'a, W> {
+    chain: Box<dyn Link<W> + 'a>,
+    pipeline: Pipeline,
+    header: Vec<u8>,
+}
+
+impl<'a, W: Write> Encoder<'a, W> {
+    /// This function is used to flag that this session of compression is done
+    /// with. The stages are finished up in turn, and then the wrapped writer
+    /// is returned.
+    pub fn finish(self) -> (W, io::Result<()>) {
+        self.chain.finish()
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`
+    /// through the same stages. The previous writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) where W: 'a {
+        let chain = self.pipeline.links(w, self.header.clone());
+        mem::replace(&mut self.chain, chain).finish()
+    }
+}
+
+impl<'a, W: Write> Write for Encoder<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.chain.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.chain.flush()
+    }
+}
+
+/// Size of the buffers between the stages of a `Processor`
+const BUF_SIZE: usize = 1 << 14;
+
+/// A stage of the chain of decoders, with the output it produced ahead of
+/// the next one
+struct Step {
+    p: Box<dyn Process>,
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    finished: bool,
+    done: bool,
+}
+
+impl Step {
+    /// Makes room at the end of the buffer, moving its data to the front
+    fn compact(&mut self) {
+        if self.start == self.end {
+            self.start = 0;
+            self.end = 0;
+        } else if self.end == self.buf.len() && self.start > 0 {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+    }
+}
+
+/// This structure is used to decode the data of a pipeline, undoing the
+/// stages of its header, fed with slices of it
+pub struct Processor {
+    header: Vec<u8>,
+    stages: Option<Vec<Stage>>,
+    /// The decoders, in the order they are applied to the data
+    chain: Vec<Step>,
+    finished: bool,
+    /// Whether the stages are set rather than read from a header
+    raw: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder, waiting for the header of a stream
+    pub fn new() -> Processor {
+        Processor {
+            header: Vec::new(),
+            stages: None,
+            chain: Vec::new(),
+            finished: false,
+            raw: false,
+        }
+    }
+
+    /// Starts decoding a new stream, of the same stages if they were set by
+    /// `Pipeline::raw_processor`, or of those of its header otherwise
+    pub fn reset(&mut self) {
+        self.header.truncate(0);
+        self.finished = false;
+        match self.stages.take() {
+            Some(stages) if self.raw => self.build(stages),
+            _ => self.chain.clear(),
+        }
+    }
+
+    /// Returns the stages of the stream, in the order they were applied to
+    /// the data, once the header is decoded
+    pub fn stages(&self) -> Option<&[Stage]> {
+        self.stages.as_ref().map(|stages| &stages[..])
+    }
+
+    /// Sets the stages, creating their decoders
+    fn build(&mut self, stages: Vec<Stage>) {
+        self.chain = stages.iter().rev().map(|stage| Step {
+            p: stage.processor(),
+            buf: vec![0; BUF_SIZE],
+            start: 0,
+            end: 0,
+            finished: false,
+            done: false,
+        }).collect();
+        self.stages = Some(stages);
+    }
+
+    /// Runs the data through the chain until it stalls
+    fn run(&mut self, input: &[u8], pos: &mut usize, output: &mut [u8], produced: &mut usize) -> io::Result<()> {
+        let n = self.chain.len();
+        let mut progress = true;
+        while progress {
+            progress = false;
+            for i in 0..n {
+                let (before, after) = self.chain.split_at_mut(i);
+                let step = &mut after[0];
+                if step.done {
+                    continue
+                }
+                let (consumed, made, status) = {
+                    let (src, src_done): (&[u8], bool) = match before.last() {
+                        Some(prev) => (&prev.buf[prev.start..prev.end], prev.done),
+                        None => (&input[*pos..], self.finished),
+                    };
+                    // the input of a stage ends with the output of the previous
+                    if src.is_empty() && src_done && !step.finished {
+                        step.finished = true;
+                        try!(step.p.finish());
+                        progress = true;
+                    }
+                    if i + 1 == n {
+                        try!(step.p.process(src, &mut output[*produced..]))
+                    } else {
+                        step.compact();
+                        try!(step.p.process(src, &mut step.buf[step.end..]))
+                    }
+                };
+                match before.last_mut() {
+                    Some(prev) => prev.start += consumed,
+                    None => *pos += consumed,
+                }
+                if i + 1 == n {
+                    *produced += made;
+                } else {
+                    step.end += made;
+                }
+                step.done = status == Status::Done;
+                progress |= consumed > 0 || made > 0 || step.done;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        while self.stages.is_none() {
+            let len = if self.header.len() < 5 { 5 } else { 5 + STAGE_SIZE * self.header[4] as usize };
+            if !gather(&mut self.header, input, &mut pos, len) {
+                return Ok((pos, 0, Status::NeedInput))
+            }
+            if get_u32(&self.header) != MAGIC {
+                return corrupt("invalid pipeline stream magic")
+            }
+            if self.header.len() == 5 + STAGE_SIZE * self.header[4] as usize {
+                let pipeline = try!(Pipeline::from_options(&self.header[4..]));
+                self.build(pipeline.stages);
+            }
+        }
+        if self.chain.is_empty() {
+            // the data as is
+            let n = cmp::min(input.len() - pos, output.len());
+            output[..n].copy_from_slice(&input[pos..(pos + n)]);
+            pos += n;
+            let status = if pos < input.len() {
+                Status::NeedOutput
+            } else if self.finished {
+                Status::Done
+            } else {
+                Status::NeedInput
+            };
+            return Ok((pos, n, status))
+        }
+        try!(self.run(input, &mut pos, output, &mut produced));
+        let status = if self.chain[self.chain.len() - 1].done {
+            Status::Done
+        } else if produced == output.len() {
+            Status::NeedOutput
+        } else {
+            Status::NeedInput
+        };
+        Ok((pos, produced, status))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.stages.is_none() {
+            return truncated("truncated pipeline header")
+        }
+        self.finished = true;
+        Ok(())
+    }
+}
+
+/// Reads the header of a stream a byte at a time, not to read ahead of it,
+/// and returns its bytes
+fn read_header<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut p = Processor::new();
+    let mut header = Vec::new();
+    let mut byte = [0];
+    while p.stages().is_none() {
+        if try!(r.read(&mut byte)) == 0 {
+            return truncated("truncated pipeline header")
+        }
+        try!(p.process(&byte, &mut []));
+        header.push(byte[0]);
+    }
+    Ok(header)
+}
+
+/// Decoder of the data of a pipeline, undoing the stages of its header
+pub struct Decoder<'a> {
+    inner: Reader<Box<dyn Read + 'a>, Processor>,
+}
+
+impl<'a> Decoder<'a> {
+    /// Reads the header from the given input stream, and creates a decoder
+    /// undoing its stages.
+    pub fn new<R: Read + 'a>(mut r: R) -> io::Result<Decoder<'a>> {
+        let header = try!(read_header(&mut r));
+        let mut p = Processor::new();
+        try!(p.process(&header, &mut []));
+        Ok(Decoder { inner: Reader::new(Box::new(r), p) })
+    }
+
+    /// Starts decoding a new stream from `r`, reading its header unless the
+    /// decoder was created by `Pipeline::raw_decoder`, and drops the previous
+    /// reader. The decoder is left as it is if the header can't be read.
+    pub fn reset<R: Read + 'a>(&mut self, mut r: R) -> io::Result<()> {
+        if self.inner.processor().raw {
+            self.inner.processor_mut().reset();
+        } else {
+            let header = try!(read_header(&mut r));
+            self.inner.processor_mut().reset();
+            try!(self.inner.processor_mut().process(&header, &mut []));
+        }
+        self.inner.reset(Box::new(r));
+        Ok(())
+    }
+
+    /// Returns the stages of the stream, in the order they were applied to
+    /// the data
+    pub fn stages(&self) -> &[Stage] {
+        match self.inner.processor().stages() {
+            Some(stages) => stages,
+            None => unreachable!(),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+}
+
+impl<'a> Read for Decoder<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(all(test, feature="flate", feature="lz4"))]
+mod test {
+    use std::io::{Read, Write};
+    use error::Error;
+    use rle::Scheme;
+    use testing::push;
+    use super::{Decoder, Pipeline, Processor, Stage};
+
+    fn roundtrip(pipeline: &Pipeline, data: &[u8]) {
+        let mut e = pipeline.encoder(Vec::new());
+        for piece in data.chunks(1000) {
+            e.write_all(piece).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+
+        let mut d = Decoder::new(&encoded[..]).unwrap();
+        assert_eq!(d.stages(), pipeline.stages());
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(&decoded[..] == data);
+    }
+
+    fn sample() -> Vec<u8> {
+        let text = include_bytes!("data/test.txt");
+        let mut data = text.to_vec();
+        data.extend((0..20000).map(|i| (i / 300) as u8));
+        data
+    }
+
+    #[test]
+    fn empty() {
+        roundtrip(&Pipeline::new(), &sample());
+        roundtrip(&Pipeline::new().stage(Stage::Mtf).stage(Stage::Ari), b"");
+        roundtrip(&Pipeline::new().stage(Stage::Bwt { block_size: 100 }).stage(Stage::Flate { level: 6 }), b"");
+    }
+
+    #[test]
+    fn block_sorting() {
+        let pipeline = Pipeline::new()
+            .stage(Stage::Lzp { min_len: 32 })
+            .stage(Stage::Bwt { block_size: 10000 })
+            .stage(Stage::Mtf)
+            .stage(Stage::Rle(Scheme::Zero))
+            .stage(Stage::Ari);
+        roundtrip(&pipeline, &sample());
+    }
+
+    #[test]
+    fn schemes() {
+        let schemes = [Scheme::Doubled, Scheme::Words(4), Scheme::Escape, Scheme::Bzip2,
+                       Scheme::PackBits, Scheme::Rows(100), Scheme::Zero];
+        for &scheme in schemes.iter() {
+            let pipeline = Pipeline::new().stage(Stage::Rle(scheme)).stage(Stage::Flate { level: 6 });
+            roundtrip(&pipeline, &sample());
+        }
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut e = Pipeline::new().stage(Stage::Lz4).encoder(Vec::new());
+        e.write_all(b"some data").unwrap();
+        let (mut encoded, result) = e.finish();
+        result.unwrap();
+
+        encoded[0] ^= 1;
+        let err = Decoder::new(&encoded[..]).err().unwrap();
+        assert!(match Error::of(&err) { Some(Error::CorruptInput { .. }) => true, _ => false });
+        encoded[0] ^= 1;
+        encoded[5] = 100;
+        assert!(Decoder::new(&encoded[..]).is_err());
+        assert!(Decoder::new(&encoded[..7]).is_err());
+    }
+
+    #[test]
+    fn raw() {
+        let pipeline = Pipeline::new()
+            .stage(Stage::Bwt { block_size: 1000 })
+            .stage(Stage::Mtf)
+            .stage(Stage::Ari);
+        assert_eq!(Pipeline::from_options(&pipeline.options()).unwrap(), pipeline);
+        assert!(Pipeline::from_options(&pipeline.options()[1..]).is_err());
+
+        let data = sample();
+        let mut e = pipeline.raw_encoder(Vec::new());
+        e.write_all(&data).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let mut decoded = Vec::new();
+        pipeline.raw_decoder(&encoded[..]).read_to_end(&mut decoded).unwrap();
+        assert!(decoded == data);
+    }
+
+    #[test]
+    fn reset() {
+        let pipeline = Pipeline::new()
+            .stage(Stage::Bwt { block_size: 1000 })
+            .stage(Stage::Mtf)
+            .stage(Stage::Ari);
+        let other = Pipeline::new().stage(Stage::Lz4);
+        let data = sample();
+        let encode = |pipeline: &Pipeline, data: &[u8]| {
+            let mut e = pipeline.encoder(Vec::new());
+            e.write_all(data).unwrap();
+            e.finish().0
+        };
+        let mut e = pipeline.encoder(Vec::new());
+        e.write_all(b"hello").unwrap();
+        let (a, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(&data).unwrap();
+        let (b, result) = e.finish();
+        result.unwrap();
+        assert!(a == encode(&pipeline, b"hello"));
+        assert!(b == encode(&pipeline, &data));
+
+        let c = encode(&other, &data);
+        let mut d = Decoder::new(&b[..]).unwrap();
+        d.read_exact(&mut [0; 1000]).unwrap();
+        assert!(d.reset(&c[..5]).is_err());
+        d.reset(&c[..]).unwrap();
+        assert_eq!(d.stages(), other.stages());
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(decoded == data);
+
+        let mut e = pipeline.raw_encoder(Vec::new());
+        e.write_all(b"hello").unwrap();
+        let (a, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(&data).unwrap();
+        let (b, result) = e.finish();
+        result.unwrap();
+        let mut d = pipeline.raw_decoder(&a[..]);
+        decoded.truncate(0);
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, b"hello");
+        d.reset(&b[..]).unwrap();
+        decoded.truncate(0);
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(decoded == data);
+    }
+
+    #[test]
+    fn decode_push() {
+        let pipeline = Pipeline::new()
+            .stage(Stage::Lzp { min_len: 32 })
+            .stage(Stage::Bwt { block_size: 10000 })
+            .stage(Stage::Mtf)
+            .stage(Stage::Rle(Scheme::Zero))
+            .stage(Stage::Ari);
+        let data = sample();
+        let mut e = pipeline.encoder(Vec::new());
+        e.write_all(&data).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        for &step in [1, 333, 1 << 16].iter() {
+            let mut p = Processor::new();
+            assert!(push(&mut p, &encoded, step).unwrap() == data);
+            assert_eq!(p.stages(), Some(pipeline.stages()));
+        }
+        for &len in [0, 10, 100, encoded.len() - 1].iter() {
+            assert!(push(&mut Processor::new(), &encoded[..len], 100).is_err());
+        }
+        assert_eq!(push(&mut Pipeline::new().raw_processor(), b"as is", 2).unwrap(), b"as is");
+    }
+
+    #[test]
+    fn max_output_size() {
+        let pipeline = Pipeline::new().stage(Stage::Rle(Scheme::Zero)).stage(Stage::Flate { level: 6 });
+        let data = sample();
+        let mut e = pipeline.encoder(Vec::new());
+        e.write_all(&data).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let mut d = Decoder::new(&encoded[..]).unwrap();
+        d.set_max_output_size(data.len() as u64 - 1);
+        let err = d.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(match Error::of(&err) { Some(Error::LimitExceeded { .. }) => true, _ => false });
+
+        let mut d = Decoder::new(&encoded[..]).unwrap();
+        d.set_max_output_size(data.len() as u64);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(decoded == data);
+    }
+}
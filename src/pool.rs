@@ -0,0 +1,254 @@
+//! Pool of the scratch buffers of the coders
+//!
+//! The coders processing blocks allocate large buffers, as the blocks and the
+//! suffix arrays of the BWT or the window of inflate. An application coding
+//! many streams shares a `Pool` with them, with `set_pool`, so that the
+//! buffers of a coder go back to the pool once it is dropped and the next
+//! coders take them instead of allocating theirs. The pool keeps no more free
+//! buffers than its limit, the others being freed.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::io::{Read, Write};
+//! use compress::flate;
+//! use compress::pool::Pool;
+//!
+//! let pool = Pool::new(1 << 20);
+//! let mut e = flate::Encoder::new(Vec::new(), 6);
+//! e.write_all(b"abracadabra abracadabra").unwrap();
+//! let (compressed, result) = e.finish();
+//! result.unwrap();
+//!
+//! for _ in 0..3 {
+//!     let mut d = flate::Decoder::new(&compressed[..]);
+//!     d.set_pool(&pool);
+//!     let mut decompressed = Vec::new();
+//!     d.read_to_end(&mut decompressed).unwrap();
+//!     assert_eq!(&decompressed[..], b"abracadabra abracadabra");
+//! }
+//! assert!(pool.reused() > 0);
+//! ```
+
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// The free buffers of a pool
+struct Shelves {
+    limit: usize,
+    held: usize,
+    reused: u64,
+    bytes: Vec<Vec<u8>>,
+    words: Vec<Vec<u32>>,
+    sizes: Vec<Vec<usize>>,
+}
+
+/// Takes the smallest buffer of `shelf` holding `capacity` elements
+fn take<T>(shelves: &mut Shelves, shelf: fn(&mut Shelves) -> &mut Vec<Vec<T>>, capacity: usize) -> Vec<T> {
+    let found = shelf(shelves).iter().enumerate()
+        .filter(|&(_, buf)| buf.capacity() >= capacity)
+        .min_by_key(|&(_, buf)| buf.capacity())
+        .map(|(i, _)| i);
+    match found {
+        Some(i) => {
+            let buf = shelf(shelves).swap_remove(i);
+            shelves.held -= buf.capacity() * mem::size_of::<T>();
+            shelves.reused += 1;
+            buf
+        }
+        None => Vec::with_capacity(capacity),
+    }
+}
+
+/// Puts `buf` back on `shelf`, unless the pool would hold more than its limit
+fn give<T>(shelves: &mut Shelves, shelf: fn(&mut Shelves) -> &mut Vec<Vec<T>>, mut buf: Vec<T>) {
+    let size = buf.capacity() * mem::size_of::<T>();
+    if size > 0 && shelves.held + size <= shelves.limit {
+        buf.clear();
+        shelves.held += size;
+        shelf(shelves).push(buf);
+    }
+}
+
+fn bytes(shelves: &mut Shelves) -> &mut Vec<Vec<u8>> {
+    &mut shelves.bytes
+}
+
+fn words(shelves: &mut Shelves) -> &mut Vec<Vec<u32>> {
+    &mut shelves.words
+}
+
+fn sizes(shelves: &mut Shelves) -> &mut Vec<Vec<usize>> {
+    &mut shelves.sizes
+}
+
+/// The free buffers shared by the coders
+#[derive(Clone)]
+pub struct Pool(Arc<Mutex<Shelves>>);
+
+impl Pool {
+    /// Creates a pool keeping up to `limit` bytes of free buffers
+    pub fn new(limit: usize) -> Pool {
+        Pool(Arc::new(Mutex::new(Shelves {
+            limit: limit,
+            held: 0,
+            reused: 0,
+            bytes: Vec::new(),
+            words: Vec::new(),
+            sizes: Vec::new(),
+        })))
+    }
+
+    fn buffer<T>(&self, shelf: fn(&mut Shelves) -> &mut Vec<Vec<T>>, capacity: usize) -> Buffer<T> {
+        let vec = take(&mut self.0.lock().unwrap(), shelf, capacity);
+        Buffer { vec: vec, home: Some((self.clone(), shelf)) }
+    }
+
+    /// Returns an empty buffer of bytes with room for `capacity` of them
+    pub fn bytes(&self, capacity: usize) -> Buffer<u8> {
+        self.buffer(bytes, capacity)
+    }
+
+    /// Returns an empty buffer of 32-bit words with room for `capacity` of
+    /// them
+    pub fn words(&self, capacity: usize) -> Buffer<u32> {
+        self.buffer(words, capacity)
+    }
+
+    /// Returns an empty buffer of sizes with room for `capacity` of them
+    pub fn sizes(&self, capacity: usize) -> Buffer<usize> {
+        self.buffer(sizes, capacity)
+    }
+
+    /// Returns the number of bytes of the free buffers
+    pub fn held(&self) -> usize {
+        self.0.lock().unwrap().held
+    }
+
+    /// Returns the number of buffers given out again
+    pub fn reused(&self) -> u64 {
+        self.0.lock().unwrap().reused
+    }
+
+    /// Frees the free buffers
+    pub fn clear(&self) {
+        let mut shelves = self.0.lock().unwrap();
+        shelves.held = 0;
+        shelves.bytes.clear();
+        shelves.words.clear();
+        shelves.sizes.clear();
+    }
+}
+
+fn reserve<T: Copy>(pool: Option<&Pool>, shelf: fn(&mut Shelves) -> &mut Vec<Vec<T>>, buf: &mut Buffer<T>,
+                    capacity: usize) {
+    if buf.capacity() >= capacity {
+        return
+    }
+    match pool {
+        Some(pool) => {
+            let mut taken = pool.buffer(shelf, capacity);
+            taken.extend_from_slice(buf);
+            *buf = taken;
+        }
+        None => {
+            let more = capacity - buf.len();
+            buf.reserve_exact(more);
+        }
+    }
+}
+
+/// Makes room for `capacity` bytes in `buf`, moving them to a buffer of
+/// `pool`, if any, when it has to grow
+pub fn reserve_bytes(pool: Option<&Pool>, buf: &mut Buffer<u8>, capacity: usize) {
+    reserve(pool, bytes, buf, capacity)
+}
+
+/// Makes room for `capacity` words in `buf`, as `reserve_bytes`
+pub fn reserve_words(pool: Option<&Pool>, buf: &mut Buffer<u32>, capacity: usize) {
+    reserve(pool, words, buf, capacity)
+}
+
+/// Makes room for `capacity` sizes in `buf`, as `reserve_bytes`
+pub fn reserve_sizes(pool: Option<&Pool>, buf: &mut Buffer<usize>, capacity: usize) {
+    reserve(pool, sizes, buf, capacity)
+}
+
+/// A vector going back to its pool once dropped, if it comes from one
+pub struct Buffer<T> {
+    vec: Vec<T>,
+    home: Option<(Pool, fn(&mut Shelves) -> &mut Vec<Vec<T>>)>,
+}
+
+impl<T> Buffer<T> {
+    /// Wraps a vector of no pool
+    pub fn detached(vec: Vec<T>) -> Buffer<T> {
+        Buffer { vec: vec, home: None }
+    }
+}
+
+impl<T> Default for Buffer<T> {
+    fn default() -> Buffer<T> {
+        Buffer::detached(Vec::new())
+    }
+}
+
+impl<T> Deref for Buffer<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.vec
+    }
+}
+
+impl<T> DerefMut for Buffer<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        &mut self.vec
+    }
+}
+
+impl<T> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        if let Some((ref pool, shelf)) = self.home {
+            // a poisoned pool keeps no more buffers
+            if let Ok(mut shelves) = pool.0.lock() {
+                give(&mut shelves, shelf, mem::take(&mut self.vec));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Buffer, Pool};
+
+    #[test]
+    fn reuse() {
+        let pool = Pool::new(1000);
+        let mut a = pool.bytes(100);
+        a.extend_from_slice(&[1; 100]);
+        let ptr = a.as_ptr();
+        drop(a);
+        assert!(pool.held() >= 100);
+
+        // the smallest buffer large enough is taken, empty
+        let big = pool.bytes(200);
+        let b = pool.bytes(50);
+        assert!(b.is_empty() && b.as_ptr() == ptr);
+        assert_eq!(pool.reused(), 1);
+        assert_eq!(pool.held(), 0);
+        drop(b);
+        drop(big);
+        assert!(pool.held() >= 300);
+
+        // the buffers past the limit are freed
+        drop(pool.sizes(1000));
+        assert!(pool.held() < 1000);
+        drop(pool.words(10));
+        assert!(pool.held() >= 340);
+        pool.clear();
+        assert_eq!(pool.held(), 0);
+        drop(Buffer::detached(vec![0u8; 10]));
+    }
+}
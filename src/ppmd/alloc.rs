@@ -0,0 +1,314 @@
+//! The sub-allocator of PPMd
+//!
+//! The model lives in a single heap, addressed by 32-bit offsets, zero being
+//! null. The text of the recent symbols grows from the bottom, and the rest is
+//! cut in units of 12 bytes, holding the contexts and the arrays of their
+//! states: the arrays are taken from the bottom of the free space, the
+//! contexts from its top. The blocks freed are kept in lists by size, and the
+//! adjacent ones are glued together when the lists run short. Running out of
+//! memory restarts the model, so the allocations must follow the reference
+//! implementation exactly.
+
+pub const UNIT_SIZE: u32 = 12;
+/// Number of sizes of the blocks: there are 4 sizes in steps of 1 unit, 4 in
+/// steps of 2, 4 in steps of 3, and the rest in steps of 4 up to 128 units
+pub const NUM_INDEXES: usize = 38;
+
+/// The lowest size of the heap
+pub const MIN_SIZE: u32 = 1 << 11;
+/// The highest size of the heap, keeping its offsets in 32 bits
+pub const MAX_SIZE: u32 = 0xffffffff - 12 * 3;
+
+fn u2b(nu: u32) -> u32 {
+    nu * UNIT_SIZE
+}
+
+pub struct SubAllocator {
+    pub heap: Vec<u8>,
+    size: u32,
+    /// Offset of the text, from which the rest is aligned
+    align_offset: u32,
+    /// The end of the text, and the start of the units
+    pub text: u32,
+    pub units_start: u32,
+    /// The bounds of the free space between the arrays and the contexts
+    pub lo_unit: u32,
+    pub hi_unit: u32,
+    /// Number of allocations left before the free blocks are glued again
+    glue_count: u32,
+    indx2units: [u8; NUM_INDEXES],
+    units2indx: [u8; 128],
+    free_list: [u32; NUM_INDEXES],
+}
+
+impl SubAllocator {
+    pub fn new(size: u32) -> SubAllocator {
+        let mut indx2units = [0; NUM_INDEXES];
+        let mut units2indx = [0; 128];
+        let mut k = 0;
+        for i in 0..NUM_INDEXES {
+            let step = if i >= 12 { 4 } else { (i >> 2) + 1 };
+            for _ in 0..step {
+                units2indx[k] = i as u8;
+                k += 1;
+            }
+            indx2units[i] = k as u8;
+        }
+        let align_offset = 4 - (size & 3);
+        SubAllocator {
+            // the end holds the head of the list of the blocks to glue
+            heap: vec![0; (align_offset + size + UNIT_SIZE) as usize],
+            size: size,
+            align_offset: align_offset,
+            text: 0,
+            units_start: 0,
+            lo_unit: 0,
+            hi_unit: 0,
+            glue_count: 0,
+            indx2units: indx2units,
+            units2indx: units2indx,
+            free_list: [0; NUM_INDEXES],
+        }
+    }
+
+    /// Frees everything, the units taking 7/8 of the heap
+    pub fn restart(&mut self) {
+        self.free_list = [0; NUM_INDEXES];
+        self.text = self.align_offset;
+        self.hi_unit = self.text + self.size;
+        self.lo_unit = self.hi_unit - self.size / 8 / UNIT_SIZE * 7 * UNIT_SIZE;
+        self.units_start = self.lo_unit;
+        self.glue_count = 0;
+    }
+
+    #[inline]
+    pub fn u16(&self, at: u32) -> u16 {
+        let at = at as usize;
+        self.heap[at] as u16 | (self.heap[at + 1] as u16) << 8
+    }
+
+    #[inline]
+    pub fn set_u16(&mut self, at: u32, value: u16) {
+        let at = at as usize;
+        self.heap[at] = value as u8;
+        self.heap[at + 1] = (value >> 8) as u8;
+    }
+
+    #[inline]
+    pub fn u32(&self, at: u32) -> u32 {
+        self.u16(at) as u32 | (self.u16(at + 2) as u32) << 16
+    }
+
+    #[inline]
+    pub fn set_u32(&mut self, at: u32, value: u32) {
+        self.set_u16(at, value as u16);
+        self.set_u16(at + 2, (value >> 16) as u16);
+    }
+
+    /// Copies `nu` units
+    pub fn copy_units(&mut self, dst: u32, src: u32, nu: u32) {
+        let src = src as usize;
+        self.heap.copy_within(src..(src + u2b(nu) as usize), dst as usize);
+    }
+
+    /// The index of the smallest size holding `nu` units
+    pub fn u2i(&self, nu: u32) -> usize {
+        self.units2indx[nu as usize - 1] as usize
+    }
+
+    fn i2u(&self, indx: usize) -> u32 {
+        self.indx2units[indx] as u32
+    }
+
+    fn insert_node(&mut self, node: u32, indx: usize) {
+        let next = self.free_list[indx];
+        self.set_u32(node, next);
+        self.free_list[indx] = node;
+    }
+
+    fn remove_node(&mut self, indx: usize) -> u32 {
+        let node = self.free_list[indx];
+        self.free_list[indx] = self.u32(node);
+        node
+    }
+
+    /// Frees the end of a block of size `old_indx` beyond the size `new_indx`
+    fn split_block(&mut self, ptr: u32, old_indx: usize, new_indx: usize) {
+        let nu = self.i2u(old_indx) - self.i2u(new_indx);
+        let ptr = ptr + u2b(self.i2u(new_indx));
+        let mut i = self.u2i(nu);
+        if self.i2u(i) != nu {
+            i -= 1;
+            let k = self.i2u(i);
+            self.insert_node(ptr + u2b(k), (nu - k - 1) as usize);
+        }
+        self.insert_node(ptr, i);
+    }
+
+    /// Merges the free blocks which follow each other, and sorts them again
+    /// by size. The blocks are linked both ways, with a stamp of zero at the
+    /// place of the number of states of the contexts, and their size.
+    fn glue_free_blocks(&mut self) {
+        const STAMP: u32 = 0;
+        const NU: u32 = 2;
+        const NEXT: u32 = 4;
+        const PREV: u32 = 8;
+        let head = self.align_offset + self.size;
+        let mut n = head;
+        self.glue_count = 255;
+
+        for i in 0..NUM_INDEXES {
+            let nu = self.i2u(i) as u16;
+            let mut next = self.free_list[i];
+            self.free_list[i] = 0;
+            while next != 0 {
+                let node = next;
+                self.set_u32(node + NEXT, n);
+                self.set_u32(n + PREV, node);
+                n = node;
+                next = self.u32(node);
+                self.set_u16(node + STAMP, 0);
+                self.set_u16(node + NU, nu);
+            }
+        }
+        self.set_u16(head + STAMP, 1);
+        self.set_u32(head + NEXT, n);
+        self.set_u32(n + PREV, head);
+        if self.lo_unit != self.hi_unit {
+            let lo_unit = self.lo_unit;
+            self.set_u16(lo_unit + STAMP, 1);
+        }
+
+        while n != head {
+            let node = n;
+            let mut nu = self.u16(node + NU) as u32;
+            loop {
+                let node2 = node + u2b(nu);
+                nu += self.u16(node2 + NU) as u32;
+                if self.u16(node2 + STAMP) != 0 || nu >= 0x10000 {
+                    break
+                }
+                let (prev, next) = (self.u32(node2 + PREV), self.u32(node2 + NEXT));
+                self.set_u32(prev + NEXT, next);
+                self.set_u32(next + PREV, prev);
+                self.set_u16(node + NU, nu as u16);
+            }
+            n = self.u32(node + NEXT);
+        }
+
+        n = self.u32(head + NEXT);
+        while n != head {
+            let mut node = n;
+            let next = self.u32(node + NEXT);
+            let mut nu = self.u16(node + NU) as u32;
+            while nu > 128 {
+                self.insert_node(node, NUM_INDEXES - 1);
+                nu -= 128;
+                node += u2b(128);
+            }
+            let mut i = self.u2i(nu);
+            if self.i2u(i) != nu {
+                i -= 1;
+                let k = self.i2u(i);
+                self.insert_node(node + u2b(k), (nu - k - 1) as usize);
+            }
+            self.insert_node(node, i);
+            n = next;
+        }
+    }
+
+    fn alloc_units_rare(&mut self, indx: usize) -> Option<u32> {
+        if self.glue_count == 0 {
+            self.glue_free_blocks();
+            if self.free_list[indx] != 0 {
+                return Some(self.remove_node(indx))
+            }
+        }
+        let mut i = indx;
+        loop {
+            i += 1;
+            if i == NUM_INDEXES {
+                // take the units from the text
+                let bytes = u2b(self.i2u(indx));
+                self.glue_count -= 1;
+                if self.units_start - self.text > bytes {
+                    self.units_start -= bytes;
+                    return Some(self.units_start)
+                }
+                return None
+            }
+            if self.free_list[i] != 0 {
+                break
+            }
+        }
+        let block = self.remove_node(i);
+        self.split_block(block, i, indx);
+        Some(block)
+    }
+
+    /// Allocates a block of the size `indx`, from the bottom of the free space
+    pub fn alloc_units(&mut self, indx: usize) -> Option<u32> {
+        if self.free_list[indx] != 0 {
+            return Some(self.remove_node(indx))
+        }
+        let bytes = u2b(self.i2u(indx));
+        if bytes <= self.hi_unit - self.lo_unit {
+            let block = self.lo_unit;
+            self.lo_unit += bytes;
+            return Some(block)
+        }
+        self.alloc_units_rare(indx)
+    }
+
+    /// Allocates a unit, from the top of the free space
+    pub fn alloc_context(&mut self) -> Option<u32> {
+        if self.hi_unit != self.lo_unit {
+            self.hi_unit -= UNIT_SIZE;
+            Some(self.hi_unit)
+        } else if self.free_list[0] != 0 {
+            Some(self.remove_node(0))
+        } else {
+            self.alloc_units_rare(0)
+        }
+    }
+
+    /// Makes room for a unit more after the `old_nu` units of a block,
+    /// returning the block, which may have moved
+    pub fn expand_units(&mut self, old: u32, old_nu: u32) -> Option<u32> {
+        let i = self.u2i(old_nu);
+        if i == self.u2i(old_nu + 1) {
+            return Some(old)
+        }
+        let block = match self.alloc_units(i + 1) {
+            Some(block) => block,
+            None => return None,
+        };
+        self.copy_units(block, old, old_nu);
+        self.insert_node(old, i);
+        Some(block)
+    }
+
+    /// Shrinks a block of `old_nu` units to `new_nu` units, returning the
+    /// block, which may have moved
+    pub fn shrink_units(&mut self, old: u32, old_nu: u32, new_nu: u32) -> u32 {
+        let i0 = self.u2i(old_nu);
+        let i1 = self.u2i(new_nu);
+        if i0 == i1 {
+            return old
+        }
+        if self.free_list[i1] != 0 {
+            let block = self.remove_node(i1);
+            self.copy_units(block, old, new_nu);
+            self.insert_node(old, i0);
+            return block
+        }
+        self.split_block(old, i0, i1);
+        old
+    }
+
+    /// Frees a block of `nu` units
+    pub fn free_units(&mut self, block: u32, nu: u32) {
+        let indx = self.u2i(nu);
+        self.insert_node(block, indx);
+    }
+}
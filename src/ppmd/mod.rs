@@ -0,0 +1,368 @@
+//! PPMd Decompression. Requires `ppmd` feature, enabled by default
+//!
+//! This module contains a decoder of the variant H of PPMd, the prediction by
+//! partial matching of Dmitry Shkarin, as found in 7z and RAR archives: each
+//! byte is range coded by its frequency in the longest of the contexts of the
+//! previous bytes where it was seen, escapes leading to the shorter contexts.
+//! The streams are raw, the order of the model and the size of its memory
+//! being given by the archive, as well as the size of the data, the streams
+//! of 7z not necessarily ending with a marker. The variants of 7z and RAR
+//! differ only by their range coder.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::ppmd;
+//! use std::fs::File;
+//! use std::path::Path;
+//! use std::io::Read;
+//!
+//! let stream = File::open(&Path::new("path/to/file.ppmd")).unwrap();
+//! let props = ppmd::Properties::from_7z(&[6, 0, 0, 0, 1]).unwrap();
+//! let mut decompressed = Vec::new();
+//! ppmd::Decoder::new(stream, props, Some(size)).read_to_end(&mut decompressed);
+//! ```
+//!
+//! # Related links
+//!
+//! * https://www.7-zip.org/sdk.html - the LZMA SDK, whose `Ppmd7` sources are
+//!   the reference of the variant as used by 7z
+//! * http://www.compression.ru/ds/ - the original implementation of PPMd
+
+use std::cmp;
+use std::io::{self, Read};
+
+use super::byteorder::{LittleEndian, ReadBytesExt};
+
+use self::model::{Model, MAX_ORDER};
+use self::range::{Rar, SevenZip};
+use error::corrupt;
+use process::{Process, Reader, Status};
+
+mod alloc;
+mod model;
+mod range;
+mod see;
+
+/// The lowest order of the model
+const MIN_ORDER: u32 = 2;
+
+/// The archive format a stream comes from, which selects the range coder
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+    /// The range coder of LZMA
+    SevenZip,
+    /// The carryless range coder of Subbotin
+    Rar,
+}
+
+/// The parameters of a stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Properties {
+    /// The highest order of the contexts, from 2 to 64
+    pub order: u8,
+    /// The size of the memory of the model, from 2 kB to 4 GB minus 36 bytes
+    pub mem_size: u32,
+    /// The archive format of the stream
+    pub variant: Variant,
+}
+
+impl Properties {
+    /// Reads the properties of a PPMd coder of a 7z archive: the order, and the
+    /// memory size in little-endian
+    pub fn from_7z(props: &[u8]) -> io::Result<Properties> {
+        if props.len() != 5 {
+            return corrupt("invalid ppmd properties")
+        }
+        let props = Properties {
+            order: props[0],
+            mem_size: try!((&props[1..]).read_u32::<LittleEndian>()),
+            variant: Variant::SevenZip,
+        };
+        if !props.is_valid() {
+            return corrupt("invalid ppmd properties")
+        }
+        Ok(props)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.order as u32 >= MIN_ORDER && self.order as u32 <= MAX_ORDER &&
+            self.mem_size >= alloc::MIN_SIZE && self.mem_size <= alloc::MAX_SIZE
+    }
+}
+
+enum RangeDecoder<R> {
+    SevenZip(SevenZip<R>),
+    Rar(Rar<R>),
+}
+
+impl RangeDecoder<Window> {
+    fn window(&mut self) -> &mut Window {
+        match *self {
+            RangeDecoder::SevenZip(ref mut rc) => &mut rc.r,
+            RangeDecoder::Rar(ref mut rc) => &mut rc.r,
+        }
+    }
+}
+
+/// The input gathered ahead of the symbols, which the range decoders read
+struct Window {
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for Window {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let n = cmp::min(dst.len(), self.buf.len() - self.pos);
+        dst[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// This structure is used to decode a PPMd stream, fed with slices of it. A
+/// symbol takes an unknown number of bytes, of which the model can't be
+/// rolled back: each one is decoded once the input holds enough bytes for
+/// the longest, or once it is over, so up to a few hundred bytes past the
+/// end of the stream are gathered with it.
+pub struct Processor {
+    rc: RangeDecoder<Window>,
+    props: Properties,
+    /// The model, kept from a stream to the next
+    model: Option<Model>,
+    /// Whether the range decoder is started
+    started: bool,
+    /// Number of bytes of the stream and left to decode, if known
+    size: Option<u64>,
+    left: Option<u64>,
+    /// Number of bytes of input to gather ahead of a symbol
+    margin: usize,
+    /// Whether the input is over
+    eof: bool,
+    done: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder of the given parameters, decoding `size` bytes
+    /// if known, or up to the end marker otherwise
+    pub fn new(props: Properties, size: Option<u64>) -> Processor {
+        assert!(props.is_valid(), "invalid ppmd properties {:?}", props);
+        let window = Window { buf: Vec::new(), pos: 0 };
+        let rc = match props.variant {
+            Variant::SevenZip => RangeDecoder::SevenZip(SevenZip::new(window)),
+            Variant::Rar => RangeDecoder::Rar(Rar::new(window)),
+        };
+        Processor {
+            rc: rc,
+            props: props,
+            model: None,
+            started: false,
+            size: size,
+            left: size,
+            // each context escaped normalizes the range, reading 4 bytes or
+            // less for the valid streams
+            margin: (props.order as usize + 2) * 16,
+            eof: false,
+            done: false,
+        }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        match self.rc {
+            RangeDecoder::SevenZip(ref mut rc) => try!(rc.reset()),
+            RangeDecoder::Rar(ref mut rc) => try!(rc.reset()),
+        }
+        match self.model {
+            Some(ref mut model) => model.reset(),
+            None => self.model = Some(Model::new(self.props.order as u32, self.props.mem_size)),
+        }
+        self.started = true;
+        Ok(())
+    }
+
+    /// Starts decoding a new stream of the same parameters and size, keeping
+    /// the memory of the model
+    pub fn reset(&mut self) {
+        {
+            let window = self.rc.window();
+            window.buf.truncate(0);
+            window.pos = 0;
+        }
+        self.started = false;
+        self.left = self.size;
+        self.eof = false;
+        self.done = false;
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if self.left == Some(0) {
+                self.done = true;
+            }
+            if self.done {
+                // the input gathered past the end of the stream isn't used
+                let window = self.rc.window();
+                pos -= cmp::min(window.buf.len() - window.pos, pos);
+                return Ok((pos, produced, Status::Done))
+            }
+            if produced == output.len() {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            {
+                let margin = self.margin;
+                let window = self.rc.window();
+                if window.buf.len() - window.pos < margin {
+                    window.buf.drain(..window.pos);
+                    window.pos = 0;
+                    let len = cmp::min(input.len() - pos, 2 * margin - window.buf.len());
+                    window.buf.extend_from_slice(&input[pos..pos + len]);
+                    pos += len;
+                    if window.buf.len() < margin && !self.eof {
+                        return Ok((pos, produced, Status::NeedInput))
+                    }
+                }
+            }
+            if !self.started {
+                try!(self.start());
+            }
+            let model = self.model.as_mut().unwrap();
+            let symbol = match self.rc {
+                RangeDecoder::SevenZip(ref mut rc) => try!(model.decode_symbol(rc)),
+                RangeDecoder::Rar(ref mut rc) => try!(model.decode_symbol(rc)),
+            };
+            match symbol {
+                Some(symbol) => {
+                    output[produced] = symbol;
+                    produced += 1;
+                    if let Some(ref mut left) = self.left {
+                        *left -= 1;
+                    }
+                }
+                None if self.left.is_some() => return corrupt("ppmd stream ended early"),
+                None => self.done = true,
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the symbols left may come from the input gathered, and reading
+        // past it fails as truncated
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a PPMd stream. The wrapped stream can be
+/// re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new PPMd decoder of the given parameters which will wrap the
+    /// specified reader, decoding `size` bytes if known, or up to the end
+    /// marker otherwise. This decoder also implements the `Reader` trait, and
+    /// the underlying reader can be re-acquired through the `unwrap` method.
+    pub fn new(r: R, props: Properties, size: Option<u64>) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new(props, size)),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Tests whether the end of the stream has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.is_done()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read};
+    use testing::push;
+    use super::{Decoder, Processor, Properties, Variant};
+
+    fn decode(input: &[u8], props: Properties, size: usize) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::new(input), props, Some(size as u64));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(push(&mut Processor::new(props, Some(size as u64)), input, 333).unwrap() == out);
+        out
+    }
+
+    #[test]
+    fn decode_file() {
+        let reference = include_bytes!("../data/test.txt");
+        let props = Properties::from_7z(&[6, 0, 0, 0, 1]).unwrap();
+        let out = decode(include_bytes!("../data/test.ppmd"), props, reference.len());
+        assert!(&out[..] == &reference[..]);
+    }
+
+    #[test]
+    fn reset() {
+        let reference = include_bytes!("../data/test.txt");
+        let input = include_bytes!("../data/test.ppmd");
+        let props = Properties::from_7z(&[6, 0, 0, 0, 1]).unwrap();
+        let mut d = Decoder::new(BufReader::new(&input[..]), props, Some(reference.len() as u64));
+        let mut buf = [0u8; 1000];
+        d.read_exact(&mut buf).unwrap();
+        d.reset(BufReader::new(&input[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(&out[..] == &reference[..]);
+    }
+
+    #[test]
+    fn properties() {
+        assert_eq!(Properties::from_7z(&[6, 0, 0, 0, 1]).unwrap(),
+                   Properties { order: 6, mem_size: 1 << 24, variant: Variant::SevenZip });
+        for props in [&[6, 0, 0, 0][..], &[1, 0, 0, 0, 1], &[65, 0, 0, 0, 1], &[6, 0, 4, 0, 0],
+                      &[6, 0xff, 0xff, 0xff, 0xff]].iter() {
+            assert!(Properties::from_7z(props).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_data() {
+        let props = Properties::from_7z(&[6, 0, 0, 0, 1]).unwrap();
+        let data = include_bytes!("../data/test.ppmd");
+        let mut out = Vec::new();
+        // a range coder header which doesn't start with zero
+        let mut d = Decoder::new(BufReader::new(&[1, 2, 3, 4, 5, 6][..]), props, Some(10));
+        assert!(d.read_to_end(&mut out).is_err());
+        // a truncated stream
+        let mut d = Decoder::new(BufReader::new(&data[..100]), props, Some(3050));
+        assert!(d.read_to_end(&mut out).is_err());
+        assert!(push(&mut Processor::new(props, Some(3050)), &data[..100], 7).is_err());
+    }
+}
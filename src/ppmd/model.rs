@@ -0,0 +1,741 @@
+//! The context model of PPMd var.H
+//!
+//! Each context holds the states of the symbols seen after it, with their
+//! frequencies and the context they lead to, and a link to its suffix, the
+//! context shorter by a symbol. The symbols are looked for from the longest
+//! context: when missing, an escape goes to the suffix, the symbols already
+//! seen being masked. The contexts of a single symbol code it in binary, with
+//! probabilities shared among similar contexts, and the escapes of the
+//! contexts with masked symbols are estimated by `See`. The model restarts
+//! whenever its memory runs out.
+
+use std::io;
+
+use super::alloc::{SubAllocator, UNIT_SIZE};
+use super::range::RangeDecoder;
+use super::see::{See, PERIOD_BITS};
+use error::corrupt;
+
+const MAX_FREQ: u32 = 124;
+const INT_BITS: u32 = 7;
+/// The total of the probabilities of the binary contexts
+const BIN_SCALE: u32 = 1 << (INT_BITS + PERIOD_BITS as u32);
+pub const MAX_ORDER: u32 = 64;
+
+/// Initial escape frequency of the contexts made from binary ones, by the
+/// probability of their symbol
+static EXP_ESCAPE: [u8; 16] = [25, 14, 9, 7, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2];
+static INIT_BIN_ESC: [u16; 8] = [0x3cdd, 0x1f3f, 0x59bf, 0x48f3, 0x64a1, 0x5abc, 0x6632, 0x6051];
+
+// The fields of the contexts, which hold their state in place of the
+// frequencies and the stats when they have a single symbol
+const NUM_STATS: u32 = 0;
+const SUMM_FREQ: u32 = 2;
+const STATS: u32 = 4;
+const SUFFIX: u32 = 8;
+const ONE_STATE: u32 = 2;
+
+// The fields of the states
+const STATE_SIZE: u32 = 6;
+const SYMBOL: u32 = 0;
+const FREQ: u32 = 1;
+const SUCCESSOR: u32 = 2;
+
+#[derive(Clone, Copy)]
+struct State {
+    symbol: u8,
+    freq: u8,
+    successor: u32,
+}
+
+fn get_mean(prob: u32) -> u32 {
+    (prob + (1 << (PERIOD_BITS - 2))) >> PERIOD_BITS
+}
+
+pub struct Model {
+    alloc: SubAllocator,
+    min_context: u32,
+    max_context: u32,
+    found_state: u32,
+    /// Number of orders below the highest the model is at
+    order_fall: u32,
+    init_esc: u32,
+    prev_success: u32,
+    max_order: u32,
+    hi_bits_flag: u32,
+    run_length: i32,
+    init_rl: i32,
+    ns2indx: [u8; 256],
+    ns2bsindx: [u8; 256],
+    hb2flag: [u8; 256],
+    dummy_see: See,
+    see: [[See; 16]; 25],
+    bin_summ: [[u16; 64]; 128],
+}
+
+impl Model {
+    pub fn new(max_order: u32, mem_size: u32) -> Model {
+        let mut ns2indx = [0; 256];
+        for i in 0..3 {
+            ns2indx[i] = i as u8;
+        }
+        let (mut m, mut k) = (3, 1);
+        for i in 3..256 {
+            ns2indx[i] = m;
+            k -= 1;
+            if k == 0 {
+                m += 1;
+                k = m - 2;
+            }
+        }
+        let mut ns2bsindx = [6; 256];
+        ns2bsindx[0] = 0;
+        ns2bsindx[1] = 2;
+        for i in 2..11 {
+            ns2bsindx[i] = 4;
+        }
+        let mut hb2flag = [8; 256];
+        for i in 0..0x40 {
+            hb2flag[i] = 0;
+        }
+        let mut model = Model {
+            alloc: SubAllocator::new(mem_size),
+            min_context: 0,
+            max_context: 0,
+            found_state: 0,
+            order_fall: 0,
+            init_esc: 0,
+            prev_success: 0,
+            max_order: max_order,
+            hi_bits_flag: 0,
+            run_length: 0,
+            init_rl: 0,
+            ns2indx: ns2indx,
+            ns2bsindx: ns2bsindx,
+            hb2flag: hb2flag,
+            dummy_see: See::dummy(),
+            see: [[See::new(0); 16]; 25],
+            bin_summ: [[0; 64]; 128],
+        };
+        model.restart_model();
+        model
+    }
+
+    /// Starts a new stream, keeping the memory of the model
+    pub fn reset(&mut self) {
+        self.init_esc = 0;
+        self.hi_bits_flag = 0;
+        self.restart_model();
+    }
+
+    fn restart_model(&mut self) {
+        self.alloc.restart();
+        self.order_fall = self.max_order;
+        self.init_rl = -(if self.max_order < 12 { self.max_order } else { 12 } as i32) - 1;
+        self.run_length = self.init_rl;
+        self.prev_success = 0;
+
+        // the order 0 context, with all the symbols
+        self.alloc.hi_unit -= UNIT_SIZE;
+        let c = self.alloc.hi_unit;
+        self.min_context = c;
+        self.max_context = c;
+        self.alloc.set_u32(c + SUFFIX, 0);
+        self.alloc.set_u16(c + NUM_STATS, 256);
+        self.alloc.set_u16(c + SUMM_FREQ, 256 + 1);
+        let stats = self.alloc.lo_unit;
+        self.found_state = stats;
+        self.alloc.lo_unit += 256 / 2 * UNIT_SIZE;
+        self.alloc.set_u32(c + STATS, stats);
+        for i in 0..256 {
+            self.set_state(stats + i * STATE_SIZE, State { symbol: i as u8, freq: 1, successor: 0 });
+        }
+
+        for (i, row) in self.bin_summ.iter_mut().enumerate() {
+            for (k, &esc) in INIT_BIN_ESC.iter().enumerate() {
+                let prob = (BIN_SCALE - esc as u32 / (i as u32 + 2)) as u16;
+                for m in 0..8 {
+                    row[k + m * 8] = prob;
+                }
+            }
+        }
+        for (i, row) in self.see.iter_mut().enumerate() {
+            for see in row.iter_mut() {
+                *see = See::new(5 * i as u16 + 10);
+            }
+        }
+    }
+
+    fn num_stats(&self, c: u32) -> u32 {
+        self.alloc.u16(c + NUM_STATS) as u32
+    }
+
+    fn set_num_stats(&mut self, c: u32, n: u32) {
+        self.alloc.set_u16(c + NUM_STATS, n as u16)
+    }
+
+    fn summ_freq(&self, c: u32) -> u32 {
+        self.alloc.u16(c + SUMM_FREQ) as u32
+    }
+
+    fn set_summ_freq(&mut self, c: u32, freq: u32) {
+        self.alloc.set_u16(c + SUMM_FREQ, freq as u16)
+    }
+
+    fn stats(&self, c: u32) -> u32 {
+        self.alloc.u32(c + STATS)
+    }
+
+    fn set_stats(&mut self, c: u32, stats: u32) {
+        self.alloc.set_u32(c + STATS, stats)
+    }
+
+    fn suffix(&self, c: u32) -> u32 {
+        self.alloc.u32(c + SUFFIX)
+    }
+
+    fn symbol(&self, s: u32) -> u8 {
+        self.alloc.heap[(s + SYMBOL) as usize]
+    }
+
+    fn freq(&self, s: u32) -> u32 {
+        self.alloc.heap[(s + FREQ) as usize] as u32
+    }
+
+    fn set_freq(&mut self, s: u32, freq: u32) {
+        self.alloc.heap[(s + FREQ) as usize] = freq as u8;
+    }
+
+    fn successor(&self, s: u32) -> u32 {
+        self.alloc.u32(s + SUCCESSOR)
+    }
+
+    fn set_successor(&mut self, s: u32, successor: u32) {
+        self.alloc.set_u32(s + SUCCESSOR, successor)
+    }
+
+    fn state(&self, s: u32) -> State {
+        State { symbol: self.symbol(s), freq: self.freq(s) as u8, successor: self.successor(s) }
+    }
+
+    fn set_state(&mut self, s: u32, state: State) {
+        self.alloc.heap[(s + SYMBOL) as usize] = state.symbol;
+        self.alloc.heap[(s + FREQ) as usize] = state.freq;
+        self.set_successor(s, state.successor);
+    }
+
+    fn swap_states(&mut self, s1: u32, s2: u32) {
+        let (state1, state2) = (self.state(s1), self.state(s2));
+        self.set_state(s1, state2);
+        self.set_state(s2, state1);
+    }
+
+    /// Returns the state of the symbol in a context holding it
+    fn find_state(&self, c: u32, symbol: u8) -> u32 {
+        if self.num_stats(c) == 1 {
+            return c + ONE_STATE
+        }
+        let mut s = self.stats(c);
+        while self.symbol(s) != symbol {
+            s += STATE_SIZE;
+        }
+        s
+    }
+
+    /// Creates the contexts following the found state in the contexts
+    /// escaped from, `skip` leaving out the current one
+    fn create_successors(&mut self, skip: bool) -> Option<u32> {
+        let mut c = self.min_context;
+        let up_branch = self.successor(self.found_state);
+        let symbol = self.symbol(self.found_state);
+        let mut ps = [0; MAX_ORDER as usize];
+        let mut num_ps = 0;
+        if !skip {
+            ps[0] = self.found_state;
+            num_ps = 1;
+        }
+        while self.suffix(c) != 0 {
+            c = self.suffix(c);
+            let s = self.find_state(c, symbol);
+            let successor = self.successor(s);
+            if successor != up_branch {
+                c = successor;
+                if num_ps == 0 {
+                    return Some(c)
+                }
+                break
+            }
+            ps[num_ps] = s;
+            num_ps += 1;
+        }
+
+        // the symbol following in the text
+        let up_symbol = self.alloc.heap[up_branch as usize];
+        let up_freq = if self.num_stats(c) == 1 {
+            self.freq(c + ONE_STATE)
+        } else {
+            let s = self.find_state(c, up_symbol);
+            let cf = self.freq(s) - 1;
+            let s0 = self.summ_freq(c).wrapping_sub(self.num_stats(c)).wrapping_sub(cf);
+            1 + if 2 * cf <= s0 { (5 * cf > s0) as u32 } else { (2 * cf + 3 * s0 - 1) / (2 * s0) }
+        };
+        let up_state = State { symbol: up_symbol, freq: up_freq as u8, successor: up_branch + 1 };
+
+        while num_ps != 0 {
+            let c1 = match self.alloc.alloc_context() {
+                Some(c1) => c1,
+                None => return None,
+            };
+            self.set_num_stats(c1, 1);
+            self.set_state(c1 + ONE_STATE, up_state);
+            self.alloc.set_u32(c1 + SUFFIX, c);
+            num_ps -= 1;
+            self.set_successor(ps[num_ps], c1);
+            c = c1;
+        }
+        Some(c)
+    }
+
+    /// Adds the found symbol to the contexts escaped from, and moves to the
+    /// context following it
+    fn update_model(&mut self) {
+        let fs = self.found_state;
+        let symbol = self.symbol(fs);
+        let mut fsuccessor = self.successor(fs);
+        let mc = self.min_context;
+
+        if self.freq(fs) < MAX_FREQ / 4 && self.suffix(mc) != 0 {
+            let c = self.suffix(mc);
+            if self.num_stats(c) == 1 {
+                let s = c + ONE_STATE;
+                if self.freq(s) < 32 {
+                    let freq = self.freq(s) + 1;
+                    self.set_freq(s, freq);
+                }
+            } else {
+                let mut s = self.stats(c);
+                if self.symbol(s) != symbol {
+                    s = self.find_state(c, symbol);
+                    if self.freq(s) >= self.freq(s - STATE_SIZE) {
+                        self.swap_states(s, s - STATE_SIZE);
+                        s -= STATE_SIZE;
+                    }
+                }
+                if self.freq(s) < MAX_FREQ - 9 {
+                    let freq = self.freq(s) + 2;
+                    self.set_freq(s, freq);
+                    let summ = self.summ_freq(c) + 2;
+                    self.set_summ_freq(c, summ);
+                }
+            }
+        }
+
+        if self.order_fall == 0 {
+            match self.create_successors(true) {
+                Some(c) => {
+                    self.min_context = c;
+                    self.max_context = c;
+                    self.set_successor(fs, c);
+                }
+                None => self.restart_model(),
+            }
+            return
+        }
+
+        let text = self.alloc.text;
+        self.alloc.heap[text as usize] = symbol;
+        self.alloc.text += 1;
+        let mut successor = self.alloc.text;
+        if self.alloc.text >= self.alloc.units_start {
+            self.restart_model();
+            return
+        }
+
+        if fsuccessor != 0 {
+            // the successor is still in the text
+            if fsuccessor <= successor {
+                fsuccessor = match self.create_successors(false) {
+                    Some(c) => c,
+                    None => {
+                        self.restart_model();
+                        return
+                    }
+                };
+            }
+            self.order_fall -= 1;
+            if self.order_fall == 0 {
+                successor = fsuccessor;
+                if self.max_context != self.min_context {
+                    self.alloc.text -= 1;
+                }
+            }
+        } else {
+            self.set_successor(fs, successor);
+            fsuccessor = self.min_context;
+        }
+
+        let ns = self.num_stats(mc);
+        let s0 = self.summ_freq(mc).wrapping_sub(ns).wrapping_sub(self.freq(fs) - 1);
+        let mut c = self.max_context;
+        while c != mc {
+            let ns1 = self.num_stats(c);
+            if ns1 != 1 {
+                // the states fill their units
+                if ns1 & 1 == 0 {
+                    let stats = self.stats(c);
+                    match self.alloc.expand_units(stats, ns1 >> 1) {
+                        Some(stats) => self.set_stats(c, stats),
+                        None => {
+                            self.restart_model();
+                            return
+                        }
+                    }
+                }
+                let summ = self.summ_freq(c);
+                let summ = summ + (2 * ns1 < ns) as u32 +
+                    2 * ((4 * ns1 <= ns) as u32 & (summ <= 8 * ns1) as u32);
+                self.set_summ_freq(c, summ);
+            } else {
+                let s = match self.alloc.alloc_units(0) {
+                    Some(s) => s,
+                    None => {
+                        self.restart_model();
+                        return
+                    }
+                };
+                let mut state = self.state(c + ONE_STATE);
+                self.set_stats(c, s);
+                state.freq = if (state.freq as u32) < MAX_FREQ / 4 - 1 {
+                    state.freq << 1
+                } else {
+                    (MAX_FREQ - 4) as u8
+                };
+                self.set_state(s, state);
+                let summ = state.freq as u32 + self.init_esc + (ns > 3) as u32;
+                self.set_summ_freq(c, summ);
+            }
+
+            let cf = 2 * self.freq(fs) * (self.summ_freq(c) + 6);
+            let sf = s0.wrapping_add(self.summ_freq(c));
+            let freq = if cf < 6 * sf {
+                let summ = self.summ_freq(c) + 3;
+                self.set_summ_freq(c, summ);
+                1 + (cf > sf) as u32 + (cf >= 4 * sf) as u32
+            } else {
+                let freq = 4 + (cf >= 9 * sf) as u32 + (cf >= 12 * sf) as u32 + (cf >= 15 * sf) as u32;
+                let summ = self.summ_freq(c) + freq;
+                self.set_summ_freq(c, summ);
+                freq
+            };
+            let s = self.stats(c) + ns1 * STATE_SIZE;
+            self.set_state(s, State { symbol: symbol, freq: freq as u8, successor: successor });
+            self.set_num_stats(c, ns1 + 1);
+            c = self.suffix(c);
+        }
+        self.max_context = fsuccessor;
+        self.min_context = fsuccessor;
+    }
+
+    /// Halves the frequencies of the current context, dropping the states
+    /// left without any
+    fn rescale(&mut self) {
+        let mc = self.min_context;
+        let stats = self.stats(mc);
+        let mut s = self.found_state;
+        let found = self.state(s);
+        while s != stats {
+            let prev = self.state(s - STATE_SIZE);
+            self.set_state(s, prev);
+            s -= STATE_SIZE;
+        }
+        self.set_state(s, found);
+
+        let mut esc_freq = self.summ_freq(mc).wrapping_sub(self.freq(s));
+        let adder = (self.order_fall != 0) as u32;
+        let freq = (self.freq(s) + 4 + adder) >> 1;
+        self.set_freq(s, freq);
+        let mut sum_freq = freq;
+        let num_stats = self.num_stats(mc);
+        for _ in 1..num_stats {
+            s += STATE_SIZE;
+            esc_freq = esc_freq.wrapping_sub(self.freq(s));
+            let freq = (self.freq(s) + adder) >> 1;
+            self.set_freq(s, freq);
+            sum_freq += freq;
+            if freq > self.freq(s - STATE_SIZE) {
+                let state = self.state(s);
+                let mut s1 = s;
+                loop {
+                    let prev = self.state(s1 - STATE_SIZE);
+                    self.set_state(s1, prev);
+                    s1 -= STATE_SIZE;
+                    if s1 == stats || freq <= self.freq(s1 - STATE_SIZE) {
+                        break
+                    }
+                }
+                self.set_state(s1, state);
+            }
+        }
+
+        if self.freq(s) == 0 {
+            let mut zeroes = 0;
+            loop {
+                zeroes += 1;
+                s -= STATE_SIZE;
+                if self.freq(s) != 0 {
+                    break
+                }
+            }
+            esc_freq = esc_freq.wrapping_add(zeroes);
+            self.set_num_stats(mc, num_stats - zeroes);
+            if num_stats - zeroes == 1 {
+                let mut state = self.state(stats);
+                loop {
+                    state.freq -= state.freq >> 1;
+                    esc_freq >>= 1;
+                    if esc_freq <= 1 {
+                        break
+                    }
+                }
+                self.alloc.free_units(stats, (num_stats + 1) >> 1);
+                self.found_state = mc + ONE_STATE;
+                self.set_state(mc + ONE_STATE, state);
+                return
+            }
+            let (n0, n1) = ((num_stats + 1) >> 1, (num_stats - zeroes + 1) >> 1);
+            if n0 != n1 {
+                let stats = self.alloc.shrink_units(stats, n0, n1);
+                self.set_stats(mc, stats);
+            }
+        }
+        self.set_summ_freq(mc, sum_freq.wrapping_add(esc_freq).wrapping_sub(esc_freq >> 1));
+        self.found_state = self.stats(mc);
+    }
+
+    /// Moves to the context following the found symbol, updating the model
+    /// unless it is already there
+    fn next_context(&mut self) {
+        let c = self.successor(self.found_state);
+        if self.order_fall == 0 && c > self.alloc.text {
+            self.min_context = c;
+            self.max_context = c;
+        } else {
+            self.update_model();
+        }
+    }
+
+    /// Updates the model after the first symbol of a context was found
+    fn update1_0(&mut self) {
+        let (fs, mc) = (self.found_state, self.min_context);
+        self.prev_success = (2 * self.freq(fs) > self.summ_freq(mc)) as u32;
+        self.run_length += self.prev_success as i32;
+        let summ = self.summ_freq(mc) + 4;
+        self.set_summ_freq(mc, summ);
+        let freq = self.freq(fs) + 4;
+        self.set_freq(fs, freq);
+        if freq > MAX_FREQ {
+            self.rescale();
+        }
+        self.next_context();
+    }
+
+    /// Updates the model after another symbol of a context was found, keeping
+    /// the states sorted
+    fn update1(&mut self) {
+        let (mut s, mc) = (self.found_state, self.min_context);
+        let freq = self.freq(s) + 4;
+        self.set_freq(s, freq);
+        let summ = self.summ_freq(mc) + 4;
+        self.set_summ_freq(mc, summ);
+        if freq > self.freq(s - STATE_SIZE) {
+            self.swap_states(s, s - STATE_SIZE);
+            s -= STATE_SIZE;
+            self.found_state = s;
+            if freq > MAX_FREQ {
+                self.rescale();
+            }
+        }
+        self.next_context();
+    }
+
+    /// Updates the model after a symbol was found following escapes
+    fn update2(&mut self) {
+        let (fs, mc) = (self.found_state, self.min_context);
+        let freq = self.freq(fs) + 4;
+        self.set_freq(fs, freq);
+        let summ = self.summ_freq(mc) + 4;
+        self.set_summ_freq(mc, summ);
+        if freq > MAX_FREQ {
+            self.rescale();
+        }
+        self.run_length = self.init_rl;
+        self.update_model();
+    }
+
+    /// Updates the model after the symbol of a binary context was found
+    fn update_bin(&mut self) {
+        let fs = self.found_state;
+        let freq = self.freq(fs);
+        self.set_freq(fs, freq + (freq < 128) as u32);
+        self.prev_success = 1;
+        self.run_length += 1;
+        self.next_context();
+    }
+
+    /// The probability of the symbol of the current binary context, by the
+    /// frequency of the symbol, and the context around it
+    fn bin_summ_index(&mut self) -> (usize, usize) {
+        let mc = self.min_context;
+        let s = mc + ONE_STATE;
+        self.hi_bits_flag = self.hb2flag[self.symbol(self.found_state) as usize] as u32;
+        let i = self.freq(s) as usize - 1;
+        let j = self.prev_success as usize +
+            self.ns2bsindx[self.num_stats(self.suffix(mc)) as usize - 1] as usize +
+            self.hi_bits_flag as usize +
+            2 * self.hb2flag[self.symbol(s) as usize] as usize +
+            ((self.run_length >> 26) & 0x20) as usize;
+        (i, j)
+    }
+
+    /// Returns the estimate of the escape of the current context, by the
+    /// shape of the context, and its frequency
+    fn make_esc_freq(&mut self, num_masked: u32) -> (Option<(usize, usize)>, u32) {
+        let mc = self.min_context;
+        let num_stats = self.num_stats(mc);
+        if num_stats == 256 {
+            return (None, 1)
+        }
+        let non_masked = num_stats - num_masked;
+        let i = self.ns2indx[non_masked as usize - 1] as usize;
+        let j = (non_masked < self.num_stats(self.suffix(mc)).wrapping_sub(num_stats)) as usize +
+            2 * (self.summ_freq(mc) < 11 * num_stats) as usize +
+            4 * (num_masked > non_masked) as usize +
+            self.hi_bits_flag as usize;
+        (Some((i, j)), self.see[i][j].mean())
+    }
+
+    fn see_mut(&mut self, index: Option<(usize, usize)>) -> &mut See {
+        match index {
+            Some((i, j)) => &mut self.see[i][j],
+            None => &mut self.dummy_see,
+        }
+    }
+
+    /// Decodes a symbol, or returns None at the end marker, an escape from the
+    /// order 0 context
+    pub fn decode_symbol<D: RangeDecoder>(&mut self, rc: &mut D) -> io::Result<Option<u8>> {
+        // the symbols which can't be coded, being in the contexts escaped from
+        let mut masked = [false; 256];
+        let mc = self.min_context;
+        let num_stats = self.num_stats(mc);
+        if num_stats != 1 {
+            let mut s = self.stats(mc);
+            let summ = self.summ_freq(mc);
+            let count = rc.threshold(summ);
+            let mut hi_cnt = self.freq(s);
+            if count < hi_cnt {
+                try!(rc.decode(0, hi_cnt));
+                self.found_state = s;
+                let symbol = self.symbol(s);
+                self.update1_0();
+                return Ok(Some(symbol))
+            }
+            self.prev_success = 0;
+            for _ in 1..num_stats {
+                s += STATE_SIZE;
+                let freq = self.freq(s);
+                hi_cnt += freq;
+                if hi_cnt > count {
+                    try!(rc.decode(hi_cnt - freq, freq));
+                    self.found_state = s;
+                    let symbol = self.symbol(s);
+                    self.update1();
+                    return Ok(Some(symbol))
+                }
+            }
+            if count >= summ {
+                return corrupt("invalid ppmd data")
+            }
+            self.hi_bits_flag = self.hb2flag[self.symbol(self.found_state) as usize] as u32;
+            try!(rc.decode(hi_cnt, summ - hi_cnt));
+            let stats = self.stats(mc);
+            for i in 0..num_stats {
+                masked[self.symbol(stats + i * STATE_SIZE) as usize] = true;
+            }
+        } else {
+            let (i, j) = self.bin_summ_index();
+            let prob = self.bin_summ[i][j] as u32;
+            if try!(rc.decode_bit(prob, BIN_SCALE)) == 0 {
+                self.bin_summ[i][j] = (prob + (1 << INT_BITS) - get_mean(prob)) as u16;
+                self.found_state = mc + ONE_STATE;
+                let symbol = self.symbol(self.found_state);
+                self.update_bin();
+                return Ok(Some(symbol))
+            }
+            let prob = prob - get_mean(prob);
+            self.bin_summ[i][j] = prob as u16;
+            self.init_esc = EXP_ESCAPE[(prob >> 10) as usize] as u32;
+            masked[self.symbol(mc + ONE_STATE) as usize] = true;
+            self.prev_success = 0;
+        }
+
+        let mut ps = [0; 256];
+        loop {
+            let num_masked = self.num_stats(self.min_context);
+            loop {
+                self.order_fall += 1;
+                let suffix = self.suffix(self.min_context);
+                if suffix == 0 {
+                    return Ok(None)
+                }
+                self.min_context = suffix;
+                if self.num_stats(suffix) != num_masked {
+                    break
+                }
+            }
+            let mc = self.min_context;
+            let num = self.num_stats(mc).wrapping_sub(num_masked) as usize;
+            let mut s = self.stats(mc);
+            let mut hi_cnt = 0;
+            let mut n = 0;
+            while n != num {
+                if n == ps.len() || (s + STATE_SIZE) as usize > self.alloc.heap.len() {
+                    return corrupt("invalid ppmd data")
+                }
+                if !masked[self.symbol(s) as usize] {
+                    hi_cnt += self.freq(s);
+                    ps[n] = s;
+                    n += 1;
+                }
+                s += STATE_SIZE;
+            }
+
+            let (see, esc_freq) = self.make_esc_freq(num_masked);
+            let freq_sum = esc_freq + hi_cnt;
+            let count = rc.threshold(freq_sum);
+            if count < hi_cnt {
+                let mut hi_cnt = 0;
+                for &s in ps[..num].iter() {
+                    let freq = self.freq(s);
+                    hi_cnt += freq;
+                    if hi_cnt > count {
+                        try!(rc.decode(hi_cnt - freq, freq));
+                        self.see_mut(see).update();
+                        self.found_state = s;
+                        let symbol = self.symbol(s);
+                        self.update2();
+                        return Ok(Some(symbol))
+                    }
+                }
+            }
+            if count >= freq_sum {
+                return corrupt("invalid ppmd data")
+            }
+            try!(rc.decode(hi_cnt, freq_sum - hi_cnt));
+            self.see_mut(see).add(freq_sum);
+            for &s in ps[..num].iter() {
+                masked[self.symbol(s) as usize] = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! The range decoders of PPMd
+//!
+//! The symbols are coded as intervals of a total, the range being divided by
+//! the total first. 7z streams use the coder of LZMA, whose encoder propagates
+//! the carries, while RAR streams use the carryless coder of Subbotin, which
+//! shrinks the range whenever its top bytes do not settle.
+
+use std::io::{self, Read};
+
+use super::super::byteorder::ReadBytesExt;
+use error::corrupt;
+
+const TOP: u32 = 1 << 24;
+const BOT: u32 = 1 << 15;
+
+pub trait RangeDecoder {
+    /// Returns the count of the next symbol, out of the given total
+    fn threshold(&mut self, total: u32) -> u32;
+    /// Takes off the interval of the symbol found, in the units of the last
+    /// total
+    fn decode(&mut self, start: u32, size: u32) -> io::Result<()>;
+    /// Decodes a bit, `size0` out of `total` being the probability of a zero
+    fn decode_bit(&mut self, size0: u32, total: u32) -> io::Result<u32>;
+}
+
+/// The range decoder of 7z streams
+pub struct SevenZip<R> {
+    pub r: R,
+    range: u32,
+    code: u32,
+}
+
+impl<R: Read> SevenZip<R> {
+    /// Wraps the stream, which must be reset before any decoding
+    pub fn new(r: R) -> SevenZip<R> {
+        SevenZip { r: r, range: 0, code: 0 }
+    }
+
+    /// Starts decoding, reading the first 5 bytes of the stream
+    pub fn reset(&mut self) -> io::Result<()> {
+        if try!(self.r.read_u8()) != 0 {
+            return corrupt("invalid ppmd range coder header")
+        }
+        self.range = 0xffffffff;
+        self.code = 0;
+        for _ in 0..4 {
+            self.code = (self.code << 8) | try!(self.r.read_u8()) as u32;
+        }
+        if self.code == self.range {
+            return corrupt("invalid ppmd range coder header")
+        }
+        Ok(())
+    }
+
+    fn normalize(&mut self) -> io::Result<()> {
+        while self.range < TOP {
+            self.code = (self.code << 8) | try!(self.r.read_u8()) as u32;
+            self.range <<= 8;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> RangeDecoder for SevenZip<R> {
+    fn threshold(&mut self, total: u32) -> u32 {
+        self.range /= total;
+        self.code / self.range
+    }
+
+    fn decode(&mut self, start: u32, size: u32) -> io::Result<()> {
+        self.code = self.code.wrapping_sub(start.wrapping_mul(self.range));
+        self.range = self.range.wrapping_mul(size);
+        self.normalize()
+    }
+
+    fn decode_bit(&mut self, size0: u32, total: u32) -> io::Result<u32> {
+        let bound = (self.range / total) * size0;
+        let bit = if self.code < bound {
+            self.range = bound;
+            0
+        } else {
+            self.code -= bound;
+            self.range -= bound;
+            1
+        };
+        try!(self.normalize());
+        Ok(bit)
+    }
+}
+
+/// The range decoder of RAR streams
+pub struct Rar<R> {
+    pub r: R,
+    range: u32,
+    code: u32,
+    low: u32,
+}
+
+impl<R: Read> Rar<R> {
+    /// Wraps the stream, which must be reset before any decoding
+    pub fn new(r: R) -> Rar<R> {
+        Rar { r: r, range: 0, code: 0, low: 0 }
+    }
+
+    /// Starts decoding, reading the first 4 bytes of the stream
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.range = 0xffffffff;
+        self.code = 0;
+        self.low = 0;
+        for _ in 0..4 {
+            self.code = (self.code << 8) | try!(self.r.read_u8()) as u32;
+        }
+        Ok(())
+    }
+
+    fn normalize(&mut self) -> io::Result<()> {
+        loop {
+            if (self.low ^ self.low.wrapping_add(self.range)) >= TOP {
+                if self.range >= BOT {
+                    return Ok(())
+                }
+                self.range = self.low.wrapping_neg() & (BOT - 1);
+            }
+            self.code = (self.code << 8) | try!(self.r.read_u8()) as u32;
+            self.range <<= 8;
+            self.low <<= 8;
+        }
+    }
+}
+
+impl<R: Read> RangeDecoder for Rar<R> {
+    fn threshold(&mut self, total: u32) -> u32 {
+        self.range /= total;
+        self.code.wrapping_sub(self.low) / self.range
+    }
+
+    fn decode(&mut self, start: u32, size: u32) -> io::Result<()> {
+        self.low = self.low.wrapping_add(start.wrapping_mul(self.range));
+        self.range = self.range.wrapping_mul(size);
+        self.normalize()
+    }
+
+    fn decode_bit(&mut self, size0: u32, total: u32) -> io::Result<u32> {
+        if self.threshold(total) < size0 {
+            try!(self.decode(0, size0));
+            Ok(0)
+        } else {
+            try!(self.decode(size0, total - size0));
+            Ok(1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+    use testing::Lcg;
+    use super::{TOP, BOT, Rar, RangeDecoder, SevenZip};
+
+    /// The intervals of a sequence of symbols, as (start, size, total), with
+    /// the bits coded as intervals of a total of 1 << 14
+    fn intervals() -> Vec<(u32, u32, u32)> {
+        let mut lcg = Lcg(7);
+        (0..20000).map(|_| {
+            let seed = lcg.step();
+            let r = seed >> 8;
+            let total = 1 + r % 3000;
+            let start = (r >> 3) % total;
+            let size = 1 + (r >> 5) % (total - start);
+            let bit = (r >> 1) & 1;
+            match r & 1 {
+                0 => (start, size, total),
+                _ => (bit * 9000, if bit == 0 { 9000 } else { (1 << 14) - 9000 }, 1 << 14),
+            }
+        }).collect()
+    }
+
+    /// The encoder of 7z, which propagates the carries through the bytes held
+    fn encode_7z(intervals: &[(u32, u32, u32)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (mut low, mut range, mut cache, mut cache_size) = (0u64, 0xffffffffu32, 0u8, 1);
+        let mut shift_low = |low: &mut u64, out: &mut Vec<u8>| {
+            if (*low as u32) < 0xff000000 || (*low >> 32) != 0 {
+                let carry = (*low >> 32) as u8;
+                let mut byte = cache;
+                while cache_size != 0 {
+                    out.push(byte.wrapping_add(carry));
+                    byte = 0xff;
+                    cache_size -= 1;
+                }
+                cache = (*low >> 24) as u8;
+            }
+            cache_size += 1;
+            *low = (*low & 0x00ffffff) << 8;
+        };
+        for &(start, size, total) in intervals.iter() {
+            if total == 1 << 14 {
+                let bound = (range >> 14) * 9000;
+                if start == 0 {
+                    range = bound;
+                } else {
+                    low += bound as u64;
+                    range -= bound;
+                }
+            } else {
+                range /= total;
+                low += start as u64 * range as u64;
+                range *= size;
+            }
+            while range < TOP {
+                range <<= 8;
+                shift_low(&mut low, &mut out);
+            }
+        }
+        for _ in 0..5 {
+            shift_low(&mut low, &mut out);
+        }
+        out
+    }
+
+    fn encode_rar(intervals: &[(u32, u32, u32)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (mut low, mut range) = (0u32, 0xffffffffu32);
+        for &(start, size, total) in intervals.iter() {
+            range /= total;
+            low = low.wrapping_add(start * range);
+            range *= size;
+            loop {
+                if (low ^ low.wrapping_add(range)) >= TOP {
+                    if range >= BOT {
+                        break
+                    }
+                    range = low.wrapping_neg() & (BOT - 1);
+                }
+                out.push((low >> 24) as u8);
+                range <<= 8;
+                low <<= 8;
+            }
+        }
+        for _ in 0..4 {
+            out.push((low >> 24) as u8);
+            low <<= 8;
+        }
+        out
+    }
+
+    fn decode<D: RangeDecoder>(rc: &mut D, intervals: &[(u32, u32, u32)]) {
+        for &(start, size, total) in intervals.iter() {
+            if total == 1 << 14 {
+                assert_eq!(rc.decode_bit(9000, total).unwrap(), (start != 0) as u32);
+            } else {
+                let count = rc.threshold(total);
+                assert!(count >= start && count < start + size);
+                rc.decode(start, size).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrips() {
+        let intervals = intervals();
+        let encoded = encode_7z(&intervals);
+        let mut rc = SevenZip::new(BufReader::new(&encoded[..]));
+        rc.reset().unwrap();
+        decode(&mut rc, &intervals);
+
+        let encoded = encode_rar(&intervals);
+        let mut rc = Rar::new(BufReader::new(&encoded[..]));
+        rc.reset().unwrap();
+        decode(&mut rc, &intervals);
+    }
+}
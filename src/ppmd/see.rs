@@ -0,0 +1,54 @@
+//! Secondary escape estimation of PPMd
+//!
+//! The frequency of the escapes of the contexts with masked symbols is not
+//! taken from the contexts, but from an adaptive estimate shared by the
+//! contexts of similar shape: the sum of the escape frequencies seen, scaled
+//! by a shift which grows with the number of updates.
+
+/// The bits of the period of the adaptation
+pub const PERIOD_BITS: u8 = 7;
+
+#[derive(Clone, Copy)]
+pub struct See {
+    summ: u16,
+    shift: u8,
+    count: u8,
+}
+
+impl See {
+    /// The estimate starting from the given escape frequency
+    pub fn new(freq: u16) -> See {
+        let shift = PERIOD_BITS - 4;
+        See { summ: freq << shift, shift: shift, count: 4 }
+    }
+
+    /// The estimate of the contexts without any masked symbol, which is
+    /// never used nor updated
+    pub fn dummy() -> See {
+        See { summ: 0, shift: PERIOD_BITS, count: 64 }
+    }
+
+    /// Returns the escape frequency, taking it off the sum
+    pub fn mean(&mut self) -> u32 {
+        let r = self.summ >> self.shift;
+        self.summ = self.summ.wrapping_sub(r);
+        r as u32 + (r == 0) as u32
+    }
+
+    /// Adds the frequencies of an escape
+    pub fn add(&mut self, freq: u32) {
+        self.summ = self.summ.wrapping_add(freq as u16);
+    }
+
+    /// Counts a symbol found after the escape, widening the period
+    pub fn update(&mut self) {
+        if self.shift < PERIOD_BITS {
+            self.count = self.count.wrapping_sub(1);
+            if self.count == 0 {
+                self.summ <<= 1;
+                self.count = (3u32 << self.shift) as u8;
+                self.shift += 1;
+            }
+        }
+    }
+}
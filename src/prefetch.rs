@@ -0,0 +1,242 @@
+/*!
+
+Read-ahead of decoders. Requires `threads` feature
+
+A `Prefetch` takes a decoder, or any other reader, and reads it on a helper
+thread one block ahead of its own reader: while the caller consumes a block,
+the next one is read from the source and decoded, so that the I/O and the
+decoding of the stream overlap with the work of the caller. The blocks are
+handed over through channels, and those consumed go back to the helper to be
+filled again, so that no more than two blocks and a spare one are held.
+
+The helper thread takes the decoder, which must then be `Send` and own its
+source. It stops at the end of the stream, after an error, or at the next
+block once the `Prefetch` is dropped.
+
+# Example
+
+```rust
+use std::io::{Read, Write};
+use compress::lz4;
+use compress::prefetch::Prefetch;
+
+let data: Vec<u8> = (0..100000).map(|i| (i / 100) as u8).collect();
+let mut e = lz4::Encoder::new(Vec::new());
+e.write_all(&data).unwrap();
+let (encoded, result) = e.finish();
+result.unwrap();
+
+let mut d = Prefetch::new(lz4::Decoder::new(std::io::Cursor::new(encoded)));
+d.set_block_size(16384);
+let mut decoded = Vec::new();
+d.read_to_end(&mut decoded).unwrap();
+assert!(decoded == data);
+```
+
+*/
+
+use std::cmp;
+use std::io::{self, Read};
+use std::mem;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+/// Default amount of decoded data of each block
+const BLOCK_SIZE: usize = 1 << 16;
+
+fn panicked() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "prefetch thread panicked")
+}
+
+/// The channels of a started helper thread
+struct Helper<R> {
+    blocks: Receiver<io::Result<Vec<u8>>>,
+    spare: SyncSender<Vec<u8>>,
+    worker: JoinHandle<R>,
+}
+
+/// Reads blocks of `size` bytes from `r` and sends them over `blocks`, until
+/// the end of `r`, an error or the receiver is dropped
+fn fill<R: Read>(mut r: R, size: usize, blocks: SyncSender<io::Result<Vec<u8>>>, spare: Receiver<Vec<u8>>) -> R {
+    loop {
+        let mut block = match spare.try_recv() {
+            Ok(mut block) => { block.clear(); block }
+            Err(_) => Vec::with_capacity(size),
+        };
+        let result = match r.by_ref().take(size as u64).read_to_end(&mut block) {
+            Ok(0) => return r,
+            Ok(_) => Ok(block),
+            Err(e) => Err(e),
+        };
+        let failed = result.is_err();
+        if blocks.send(result).is_err() || failed {
+            return r
+        }
+    }
+}
+
+/// This structure reads a decoder on a helper thread, a block ahead of its
+/// reader
+pub struct Prefetch<R> {
+    r: Option<R>,
+    block_size: usize,
+    helper: Option<Helper<R>>,
+    block: Vec<u8>,
+    pos: usize,
+    /// Whether the end of the stream or an error was received
+    done: bool,
+}
+
+impl<R: Read + Send + 'static> Prefetch<R> {
+    /// Creates a new reader of `r`, of which the helper thread starts at the
+    /// first read
+    pub fn new(r: R) -> Prefetch<R> {
+        Prefetch {
+            r: Some(r),
+            block_size: BLOCK_SIZE,
+            helper: None,
+            block: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+
+    /// Sets the amount of data read ahead at a time, 64 KiB by default. This
+    /// must be called before reading.
+    pub fn set_block_size(&mut self, size: usize) {
+        assert!(size > 0, "prefetch blocks must not be empty");
+        self.block_size = size;
+    }
+
+    fn start(&mut self) {
+        if let Some(r) = self.r.take() {
+            // the helper sends a block once the reader wants it, so that it
+            // holds one block while the reader consumes the other
+            let (blocks, blocks_rx) = sync_channel(0);
+            let (spare, spare_rx) = sync_channel(1);
+            let size = self.block_size;
+            let worker = thread::spawn(move || fill(r, size, blocks, spare_rx));
+            self.helper = Some(Helper { blocks: blocks_rx, spare: spare, worker: worker });
+        }
+    }
+
+    /// Stops the helper thread and returns the reader, once the helper is
+    /// done with the block it reads. The data read ahead is lost.
+    pub fn into_inner(mut self) -> io::Result<R> {
+        match (self.r.take(), self.helper.take()) {
+            (Some(r), _) => Ok(r),
+            (None, Some(Helper { blocks, spare, worker })) => {
+                drop(blocks);
+                drop(spare);
+                worker.join().map_err(|_| panicked())
+            }
+            (None, None) => Err(panicked()),
+        }
+    }
+}
+
+impl<R: Read + Send + 'static> Read for Prefetch<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.block.len() {
+            if self.done || dst.is_empty() {
+                return Ok(0)
+            }
+            self.start();
+            let helper = self.helper.as_ref().unwrap();
+            match helper.blocks.recv() {
+                Ok(Ok(block)) => {
+                    let old = mem::replace(&mut self.block, block);
+                    self.pos = 0;
+                    // the helper may already have another spare block
+                    let _ = helper.spare.try_send(old);
+                }
+                Ok(Err(e)) => {
+                    self.done = true;
+                    return Err(e)
+                }
+                Err(_) => {
+                    // the helper ends without a word at the end of the stream,
+                    // unless it panicked
+                    self.done = true;
+                    let Helper { worker, .. } = self.helper.take().unwrap();
+                    self.r = Some(try!(worker.join().map_err(|_| panicked())));
+                    return Ok(0)
+                }
+            }
+        }
+        let amt = cmp::min(dst.len(), self.block.len() - self.pos);
+        dst[..amt].copy_from_slice(&self.block[self.pos..(self.pos + amt)]);
+        self.pos += amt;
+        Ok(amt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Cursor, Read};
+    use super::Prefetch;
+
+    /// A reader failing after `left` bytes
+    struct Failing {
+        left: usize,
+    }
+
+    impl Read for Failing {
+        fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "failing"))
+            }
+            let amt = ::std::cmp::min(dst.len(), self.left);
+            self.left -= amt;
+            Ok(amt)
+        }
+    }
+
+    /// A reader panicking once read
+    struct Panicking;
+
+    impl Read for Panicking {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            panic!("panicking reader")
+        }
+    }
+
+    #[test]
+    fn blocks() {
+        let data = include_bytes!("data/test.txt");
+        for &size in [1, 7, 1000, 1 << 20].iter() {
+            let mut p = Prefetch::new(Cursor::new(&data[..]));
+            p.set_block_size(size);
+            let mut out = Vec::new();
+            p.read_to_end(&mut out).unwrap();
+            assert!(&out[..] == &data[..]);
+            assert_eq!(p.read(&mut [0; 10]).unwrap(), 0);
+        }
+        let p = Prefetch::new(Cursor::new(vec![1u8; 10]));
+        assert_eq!(p.into_inner().unwrap().position(), 0);
+    }
+
+    #[test]
+    fn errors() {
+        let mut p = Prefetch::new(Failing { left: 1000 });
+        p.set_block_size(100);
+        let mut out = Vec::new();
+        assert!(p.read_to_end(&mut out).is_err());
+        assert_eq!(out.len(), 1000);
+
+        let mut p = Prefetch::new(Panicking);
+        assert!(p.read(&mut [0; 10]).is_err());
+        assert!(p.into_inner().is_err());
+    }
+
+    #[test]
+    fn stop() {
+        let data = vec![0u8; 100000];
+        let mut p = Prefetch::new(Cursor::new(data));
+        p.set_block_size(1000);
+        let mut buf = [0; 10];
+        p.read_exact(&mut buf).unwrap();
+        // the helper holds at most the next block and the spare one
+        assert!(p.into_inner().unwrap().position() <= 3000);
+    }
+}
@@ -0,0 +1,469 @@
+//! Push-style decoding, without I/O
+//!
+//! A `Process` decodes the input it is given into the output it is given, and
+//! returns how much of both it used, so that the caller keeps the buffers: the
+//! data may come from an event loop, a foreign function or a fixed area of
+//! memory rather than from a `Read`, and no thread waits for it. The decoder
+//! keeps between the calls what it needs of the input, as the rest of a block
+//! for the coders of blocks, and the output which didn't fit.
+//!
+//! Once the input ends, `finish` tells the decoder so, failing if the data is
+//! truncated, and `process` is called with no input until `Status::Done`.
+//! `Reader` turns a `Process` into a `Read`, as the decoders of the crate do,
+//! and `decode_to_vec` decodes a whole slice, with or without `std`. The
+//! decoders of blocks `gather` their parts and `drain` their output.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::io::Write;
+//! use compress::lz4;
+//! use compress::process::{Process, Status};
+//!
+//! let mut encoder = lz4::Encoder::new(Vec::new());
+//! encoder.write_all(b"push it, push it, push it").unwrap();
+//! let (compressed, result) = encoder.finish();
+//! result.unwrap();
+//!
+//! let mut decoder = lz4::Processor::new();
+//! let mut decompressed = Vec::new();
+//! let mut output = [0; 4];
+//! for chunk in compressed.chunks(5) {
+//!     let mut input = chunk;
+//!     loop {
+//!         let (consumed, produced, status) = decoder.process(input, &mut output).unwrap();
+//!         input = &input[consumed..];
+//!         decompressed.extend_from_slice(&output[..produced]);
+//!         if status != Status::NeedOutput { break }
+//!     }
+//! }
+//! assert_eq!(&decompressed[..], b"push it, push it, push it");
+//! ```
+
+use std::cmp;
+#[cfg(feature="std")]
+use std::io::Read;
+use std::io;
+#[cfg(feature="std")]
+use std::mem;
+use std::vec::Vec;
+
+use error::truncated;
+#[cfg(feature="std")]
+use OutputLimit;
+
+/// Size of the input read at once by `Reader`
+#[cfg(feature="std")]
+const BUF_SIZE: usize = 1 << 14;
+
+/// What stopped a call to `process`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The input is used up, and more is needed to go on
+    NeedInput,
+    /// The output is full, and more room is needed to go on
+    NeedOutput,
+    /// The stream is over, and all its data was produced
+    Done,
+}
+
+/// A decoder fed with slices of its input
+pub trait Process {
+    /// Decodes `input` into `output`, returning the number of bytes consumed
+    /// of the first and produced in the second, along with the reason it
+    /// stopped. The input left after `Status::Done` isn't part of the stream.
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)>;
+
+    /// Tells the input is over, failing if the stream may not end here. The
+    /// output left is produced by the next calls of `process`.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+#[cfg(feature="std")]
+impl<P: Process + ?Sized> Process for Box<P> {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        (**self).process(input, output)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Moves the bytes of `input` from `*pos` into `buf` until it holds `len`
+/// bytes, returning whether it does, for the decoders gathering the parts of
+/// their stream which they decode at once
+pub fn gather(buf: &mut Vec<u8>, input: &[u8], pos: &mut usize, len: usize) -> bool {
+    let n = cmp::min(len.saturating_sub(buf.len()), input.len() - *pos);
+    buf.extend_from_slice(&input[*pos..(*pos + n)]);
+    *pos += n;
+    buf.len() >= len
+}
+
+/// Copies the bytes of `buf` from `*start` into `output` from `*produced`,
+/// returning whether they all fit, for the decoders giving the data which
+/// they decoded ahead
+pub fn drain(buf: &[u8], start: &mut usize, output: &mut [u8], produced: &mut usize) -> bool {
+    let n = cmp::min(output.len() - *produced, buf.len() - *start);
+    output[*produced..(*produced + n)].copy_from_slice(&buf[*start..(*start + n)]);
+    *start += n;
+    *produced += n;
+    *start == buf.len()
+}
+
+/// Decodes the stream held by `input` with `p`, returning its data. The
+/// input after the end of the stream is ignored.
+pub fn decode_to_vec<P: Process + ?Sized>(p: &mut P, mut input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut len = 0;
+    let mut finished = false;
+    loop {
+        if len == output.len() {
+            let size = cmp::max(output.len(), 1 << 12);
+            output.resize(len + size, 0);
+        }
+        let (consumed, produced, status) = try!(p.process(input, &mut output[len..]));
+        input = &input[consumed..];
+        len += produced;
+        match status {
+            Status::Done => break,
+            Status::NeedInput if finished => return truncated("truncated stream"),
+            Status::NeedInput => {
+                try!(p.finish());
+                finished = true;
+            }
+            Status::NeedOutput => {}
+        }
+    }
+    output.truncate(len);
+    Ok(output)
+}
+
+/// The bytes read ahead from a `Read` for a `Process`, which `Reader` feeds
+/// it, for the decoders which keep their reader apart
+#[cfg(feature="std")]
+pub struct Pump {
+    buf: Vec<u8>,
+    pos: usize,
+    end: usize,
+    eof: bool,
+    done: bool,
+    read_ahead: bool,
+    limit: OutputLimit,
+}
+
+#[cfg(feature="std")]
+impl Pump {
+    /// Creates an empty pump
+    pub fn new() -> Pump {
+        Pump {
+            buf: vec![0; BUF_SIZE],
+            pos: 0,
+            end: 0,
+            eof: false,
+            done: false,
+            read_ahead: true,
+            limit: OutputLimit::new(),
+        }
+    }
+
+    /// Drops the bytes read ahead, to be fed another stream
+    pub fn reset(&mut self) {
+        self.pos = 0;
+        self.end = 0;
+        self.eof = false;
+        self.done = false;
+        self.limit.reset();
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream produces
+    /// more than `size` bytes
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.limit.set(size);
+    }
+
+    /// Reads the input one byte at a time unless `read_ahead`, which leaves
+    /// the reader at the end of the stream, for the streams followed by other
+    /// data. The pump reads ahead by default.
+    pub fn set_read_ahead(&mut self, read_ahead: bool) {
+        self.read_ahead = read_ahead;
+    }
+
+    /// Returns whether the whole stream has been produced
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Reads from `r` what `p` needs to fill `dst`, as `Read::read`
+    pub fn read<R: Read, P: Process>(&mut self, r: &mut R, p: &mut P, dst: &mut [u8]) -> io::Result<usize> {
+        if dst.is_empty() || self.done {
+            return Ok(0)
+        }
+        let len = self.limit.clamp(dst.len());
+        let dst = &mut dst[..len];
+        loop {
+            if self.pos == self.end && !self.eof {
+                let size = if self.read_ahead { self.buf.len() } else { 1 };
+                self.end = try!(r.read(&mut self.buf[..size]));
+                self.pos = 0;
+                if self.end == 0 {
+                    self.eof = true;
+                    try!(p.finish());
+                }
+            }
+            let (consumed, produced, status) = try!(p.process(&self.buf[self.pos..self.end], dst));
+            self.pos += consumed;
+            if status == Status::Done {
+                self.done = true;
+            } else if produced == 0 && self.eof && self.pos == self.end {
+                // a decoder wanting more input after `finish` has let it pass
+                return truncated("truncated stream")
+            }
+            if produced > 0 || self.done {
+                return self.limit.count(produced)
+            }
+        }
+    }
+}
+
+/// The input gathered ahead of a part of a stream which a decoder decodes at
+/// once, as a block. Reading past it fails with `WouldBlock` until the input
+/// is over, and the decoder rolls back to the start of the part, to decode it
+/// again once more input came.
+#[cfg(feature="std")]
+pub struct Window {
+    buf: Vec<u8>,
+    pos: usize,
+    /// Number of bytes to gather before decoding the part again
+    wanted: usize,
+    eof: bool,
+}
+
+#[cfg(feature="std")]
+impl Window {
+    /// Creates an empty window
+    pub fn new() -> Window {
+        Window {
+            buf: Vec::new(),
+            pos: 0,
+            wanted: 0,
+            eof: false,
+        }
+    }
+
+    /// Drops the input, to be fed another stream
+    pub fn reset(&mut self) {
+        self.buf.truncate(0);
+        self.pos = 0;
+        self.wanted = 0;
+        self.eof = false;
+    }
+
+    /// Appends `input`, dropping the bytes read. No part is being decoded.
+    pub fn extend(&mut self, input: &[u8]) {
+        if self.pos > 0 && input.len() > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(input);
+    }
+
+    /// Tells the input is over, so that reading past it returns 0
+    pub fn finish(&mut self) {
+        self.eof = true;
+    }
+
+    /// Returns whether the input is over
+    pub fn is_finished(&self) -> bool {
+        self.eof
+    }
+
+    /// Returns the bytes not read yet
+    pub fn data(&self) -> &[u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// Drops the next `n` bytes, once looked at through `data`
+    pub fn consume(&mut self, n: usize) {
+        assert!(n <= self.buf.len() - self.pos);
+        self.pos += n;
+    }
+
+    /// Returns whether the next part is worth decoding: once it ran out of
+    /// input, the input gathered doubles first, so that a large part isn't
+    /// decoded once per slice
+    pub fn is_ready(&self) -> bool {
+        self.eof || self.buf.len() - self.pos >= self.wanted
+    }
+
+    /// Returns where the next part starts, to roll back to
+    pub fn mark(&mut self) -> usize {
+        self.wanted = 0;
+        self.pos
+    }
+
+    /// Goes back to `mark` once the part ran out of input
+    pub fn rollback(&mut self, mark: usize) {
+        self.pos = mark;
+        self.wanted = 2 * (self.buf.len() - mark);
+    }
+}
+
+#[cfg(feature="std")]
+impl Read for Window {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() && !self.eof && dst.len() > 0 {
+            return Err(io::ErrorKind::WouldBlock.into())
+        }
+        let n = cmp::min(dst.len(), self.buf.len() - self.pos);
+        dst[..n].copy_from_slice(&self.buf[self.pos..(self.pos + n)]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Decodes the data of a reader with a `Process`
+#[cfg(feature="std")]
+pub struct Reader<R, P> {
+    r: R,
+    p: P,
+    pump: Pump,
+}
+
+#[cfg(feature="std")]
+impl<R: Read, P: Process> Reader<R, P> {
+    /// Creates a reader of the data of `r` decoded by `p`
+    pub fn new(r: R, p: P) -> Reader<R, P> {
+        Reader {
+            r: r,
+            p: p,
+            pump: Pump::new(),
+        }
+    }
+
+    /// Returns the wrapped reader
+    pub fn get_ref(&self) -> &R {
+        &self.r
+    }
+
+    /// Returns the decoder
+    pub fn processor(&self) -> &P {
+        &self.p
+    }
+
+    /// Returns whether the whole stream has been decoded
+    pub fn is_done(&self) -> bool {
+        self.pump.is_done()
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream produces
+    /// more than `size` bytes
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.pump.set_max_output_size(size);
+    }
+
+    /// Reads the input one byte at a time unless `read_ahead`, as
+    /// `Pump::set_read_ahead`
+    pub fn set_read_ahead(&mut self, read_ahead: bool) {
+        self.pump.set_read_ahead(read_ahead);
+    }
+
+    /// Returns the decoder, as to reset it
+    pub fn processor_mut(&mut self) -> &mut P {
+        &mut self.p
+    }
+
+    /// Drops the data read ahead and starts reading from `r`, returning the
+    /// previous reader. The decoder is left as it is.
+    pub fn reset(&mut self, r: R) -> R {
+        self.pump.reset();
+        mem::replace(&mut self.r, r)
+    }
+
+    /// Returns the wrapped reader and the decoder, dropping the data read
+    /// ahead.
+    pub fn into_inner(self) -> (R, P) {
+        (self.r, self.p)
+    }
+}
+
+#[cfg(feature="std")]
+impl<R: Read, P: Process> Read for Reader<R, P> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.pump.read(&mut self.r, &mut self.p, dst)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="lz4")]
+mod test {
+    use std::io::Read;
+    use error::Error;
+    use lz4;
+    use super::{decode_to_vec, Process, Reader, Status};
+
+    #[test]
+    fn byte_by_byte() {
+        let input = include_bytes!("data/test.lz4.1");
+        let mut p = lz4::Processor::new();
+        let mut output = Vec::new();
+        let mut buf = [0; 3];
+        for i in 0..input.len() {
+            let mut pos = i;
+            loop {
+                let (consumed, produced, status) = p.process(&input[pos..(i + 1)], &mut buf).unwrap();
+                pos += consumed;
+                output.extend_from_slice(&buf[..produced]);
+                match status {
+                    Status::NeedOutput => continue,
+                    Status::NeedInput => assert_eq!(pos, i + 1),
+                    Status::Done => assert_eq!(i + 1, input.len()),
+                }
+                break
+            }
+        }
+        p.finish().unwrap();
+        assert!(p.eof());
+        assert_eq!(&output[..], &include_bytes!("data/test.txt")[..]);
+    }
+
+    #[test]
+    fn truncated() {
+        let input = include_bytes!("data/test.lz4.1");
+        let mut p = lz4::Processor::new();
+        let mut output = vec![0; 1 << 16];
+        let (consumed, _, status) = p.process(&input[..100], &mut output).unwrap();
+        assert_eq!((consumed, status), (100, Status::NeedInput));
+        let err = p.finish().unwrap_err();
+        assert_eq!(Error::of(&err), Some(Error::UnexpectedEof { detail: "truncated lz4 stream" }));
+
+        let mut r = Reader::new(&input[..100], lz4::Processor::new());
+        assert!(r.read_to_end(&mut Vec::new()).is_err());
+        assert!(!r.is_done());
+    }
+    #[test]
+    fn to_vec() {
+        let input = include_bytes!("data/test.lz4.1");
+        let output = decode_to_vec(&mut lz4::Processor::new(), input).unwrap();
+        assert_eq!(&output[..], &include_bytes!("data/test.txt")[..]);
+
+        let err = decode_to_vec(&mut lz4::Processor::new(), &input[..100]).unwrap_err();
+        assert_eq!(Error::of(&err), Some(Error::UnexpectedEof { detail: "truncated lz4 stream" }));
+    }
+
+    #[test]
+    fn max_output_size() {
+        let input = include_bytes!("data/test.lz4.1");
+        let len = include_bytes!("data/test.txt").len() as u64;
+        let mut r = Reader::new(&input[..], lz4::Processor::new());
+        r.set_max_output_size(len - 1);
+        let err = r.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(Error::of(&err), Some(Error::LimitExceeded { detail: "decoded data exceeds the maximum output size" }));
+
+        let mut d = lz4::Decoder::new(&input[..]);
+        d.set_max_output_size(len);
+        let mut output = Vec::new();
+        d.read_to_end(&mut output).unwrap();
+        assert_eq!(output.len() as u64, len);
+    }
+}
@@ -0,0 +1,337 @@
+/*!
+
+Rice coding of integer columns. Requires `rice` feature, enabled by default
+
+This module contains a codec dedicated to the columns of 16 and 32-bit
+integers, as the samples of sensors and of audio and the series of time
+stamps. Each value is replaced by its difference with the value a stride
+before it, as the delta filter does, so that the slowly changing series turn
+into small numbers, which are then zigzag mapped to unsigned numbers and coded
+with Rice codes. The parameter of the codes is adapted from the mean of the
+previous numbers of each channel of the stride, so that neither a table nor
+parameters are stored.
+
+The data begins with a header made of the width in bytes of the values, the
+stride and the number of values, as a 32-bit little-endian integer. The codes
+follow, most significant bit first: the quotient in unary, as ones ended by a
+zero, then the remainder. A quotient of 24 or more is replaced by 24 ones and
+the number itself.
+
+# Example
+
+```rust
+use compress::rice;
+
+let series: Vec<i32> = (0..1000).map(|i| 100000 + i * 3 + (i % 7)).collect();
+let encoded = rice::encode_i32(&series, 1);
+assert!(encoded.len() < series.len());
+assert_eq!(rice::decode_i32(&encoded).unwrap(), series);
+```
+
+# Related links
+
+* https://en.wikipedia.org/wiki/Golomb_coding - Golomb and Rice codes
+* https://www.w3.org/Graphics/JPEG/itu-t87.pdf - LOCO-I (JPEG-LS), section
+  A.5, whose adaptation of the parameter is used
+
+*/
+
+use std::cmp;
+use std::io;
+
+use error::{corrupt, truncated};
+
+/// The largest stride, in values
+pub const MAX_STRIDE: usize = 16;
+
+const HEADER_SIZE: usize = 6;
+/// Quotients from which the numbers are written as they are
+const ESCAPE: u32 = 24;
+/// Initial sum of the numbers of the channels, before the first one
+const INIT_SUM: u64 = 4;
+/// Count of numbers at which the sum and the count are halved
+const RESET: u64 = 64;
+
+/// Adapts the parameter of the codes of a channel to its numbers
+#[derive(Clone, Copy)]
+struct Context {
+    sum: u64,
+    count: u64,
+}
+
+impl Context {
+    fn new() -> Context {
+        Context { sum: INIT_SUM, count: 1 }
+    }
+
+    /// Returns the smallest parameter above the mean of the numbers, which
+    /// leaves a quotient of 0 or 1 at most
+    fn k(&self, width: u32) -> u32 {
+        let mut k = 0;
+        while (self.count << k) < self.sum && k + 1 < width {
+            k += 1;
+        }
+        k
+    }
+
+    fn update(&mut self, value: u32) {
+        self.sum += value as u64;
+        self.count += 1;
+        if self.count == RESET {
+            self.sum >>= 1;
+            self.count >>= 1;
+        }
+    }
+}
+
+/// Writes bits MSB first
+struct BitWriter {
+    out: Vec<u8>,
+    buf: u64,
+    cnt: usize,
+}
+
+impl BitWriter {
+    fn bits(&mut self, cnt: usize, value: u32) {
+        debug_assert!(cnt <= 32);
+        self.buf = (self.buf << cnt) | value as u64;
+        self.cnt += cnt;
+        while self.cnt >= 8 {
+            self.cnt -= 8;
+            self.out.push((self.buf >> self.cnt) as u8);
+        }
+    }
+
+    /// Completes the current byte with zeroes
+    fn pad(&mut self) {
+        if self.cnt > 0 {
+            let cnt = 8 - self.cnt;
+            self.bits(cnt, 0);
+        }
+    }
+}
+
+/// Reads bits MSB first, keeping them at the top of a word
+struct BitReader<'a> {
+    input: &'a [u8],
+    buf: u64,
+    cnt: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn refill(&mut self) {
+        while self.cnt <= 56 && !self.input.is_empty() {
+            self.buf |= (self.input[0] as u64) << (56 - self.cnt);
+            self.cnt += 8;
+            self.input = &self.input[1..];
+        }
+    }
+
+    fn bits(&mut self, cnt: u32) -> io::Result<u32> {
+        if cnt == 0 {
+            return Ok(0)
+        }
+        self.refill();
+        if self.cnt < cnt {
+            return truncated("truncated rice data")
+        }
+        let value = (self.buf >> (64 - cnt)) as u32;
+        self.buf <<= cnt;
+        self.cnt -= cnt;
+        Ok(value)
+    }
+
+    /// Reads a quotient in unary, returning `ESCAPE` for the escaped numbers
+    fn unary(&mut self) -> io::Result<u32> {
+        self.refill();
+        // the bits past the end are zeroes, which stop the count
+        let ones = cmp::min((!self.buf).leading_zeros(), ESCAPE);
+        let len = if ones == ESCAPE { ESCAPE } else { ones + 1 };
+        if self.cnt < len {
+            return truncated("truncated rice data")
+        }
+        self.buf <<= len;
+        self.cnt -= len;
+        Ok(ones)
+    }
+}
+
+fn mask(width: u32) -> u32 {
+    (!0u32) >> (32 - width)
+}
+
+/// Encodes the `count` values of `width` bits returned by `value`
+fn encode<F: Fn(usize) -> u32>(count: usize, stride: usize, width: u32, value: F) -> Vec<u8> {
+    assert!(stride >= 1 && stride <= MAX_STRIDE, "invalid rice stride {}", stride);
+    assert!(count <= u32::max_value() as usize, "too many values for rice coding");
+    let mut w = BitWriter { out: Vec::with_capacity(HEADER_SIZE + count), buf: 0, cnt: 0 };
+    w.out.push((width / 8) as u8);
+    w.out.push(stride as u8);
+    for i in 0..4 {
+        w.out.push((count >> (8 * i)) as u8);
+    }
+    let mask = mask(width);
+    let shift = 32 - width;
+    let mut contexts = [Context::new(); MAX_STRIDE];
+    for i in 0..count {
+        let prev = if i >= stride { value(i - stride) } else { 0 };
+        // the difference, sign extended from the width of the values
+        let diff = ((value(i).wrapping_sub(prev) << shift) as i32) >> shift;
+        let zigzag = ((diff << 1) ^ (diff >> 31)) as u32 & mask;
+        let context = &mut contexts[i % stride];
+        let k = context.k(width);
+        let q = zigzag >> k;
+        if q < ESCAPE {
+            w.bits(q as usize + 1, ((1 << q) - 1) << 1);
+            w.bits(k as usize, zigzag & ((1 << k) - 1));
+        } else {
+            w.bits(ESCAPE as usize, (1 << ESCAPE) - 1);
+            w.bits(width as usize, zigzag);
+        }
+        context.update(zigzag);
+    }
+    w.pad();
+    w.out
+}
+
+/// Decodes values of `width` bits
+fn decode(input: &[u8], width: u32) -> io::Result<Vec<u32>> {
+    if input.len() < HEADER_SIZE {
+        return truncated("truncated rice data")
+    }
+    let stride = input[1] as usize;
+    if input[0] as u32 * 8 != width || stride < 1 || stride > MAX_STRIDE {
+        return corrupt("invalid rice header")
+    }
+    let count = (0..4).fold(0, |count, i| count | (input[2 + i] as usize) << (8 * i));
+    // every value takes a bit at least
+    if count > (input.len() - HEADER_SIZE) * 8 {
+        return truncated("truncated rice data")
+    }
+    let mut r = BitReader { input: &input[HEADER_SIZE..], buf: 0, cnt: 0 };
+    let mask = mask(width);
+    let mut contexts = [Context::new(); MAX_STRIDE];
+    let mut out: Vec<u32> = Vec::with_capacity(count);
+    for i in 0..count {
+        let context = &mut contexts[i % stride];
+        let k = context.k(width);
+        let q = try!(r.unary());
+        let zigzag = if q < ESCAPE {
+            let low = try!(r.bits(k));
+            if (q as u64) << k > mask as u64 {
+                return corrupt("invalid rice code")
+            }
+            q << k | low
+        } else {
+            try!(r.bits(width))
+        };
+        context.update(zigzag);
+        let diff = (zigzag >> 1) ^ (zigzag & 1).wrapping_neg();
+        let prev = if i >= stride { out[i - stride] } else { 0 };
+        out.push(prev.wrapping_add(diff) & mask);
+    }
+    Ok(out)
+}
+
+/// Encodes a column of 16-bit integers, taking the differences of the values
+/// a stride of 1 to 16 values apart
+pub fn encode_i16(input: &[i16], stride: usize) -> Vec<u8> {
+    encode(input.len(), stride, 16, |i| input[i] as u16 as u32)
+}
+
+/// Decodes a column of 16-bit integers
+pub fn decode_i16(input: &[u8]) -> io::Result<Vec<i16>> {
+    let values = try!(decode(input, 16));
+    Ok(values.into_iter().map(|v| v as u16 as i16).collect())
+}
+
+/// Encodes a column of 32-bit integers, taking the differences of the values
+/// a stride of 1 to 16 values apart
+pub fn encode_i32(input: &[i32], stride: usize) -> Vec<u8> {
+    encode(input.len(), stride, 32, |i| input[i] as u32)
+}
+
+/// Decodes a column of 32-bit integers
+pub fn decode_i32(input: &[u8]) -> io::Result<Vec<i32>> {
+    let values = try!(decode(input, 32));
+    Ok(values.into_iter().map(|v| v as i32).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{i16, i32};
+    use testing::Lcg;
+    use super::{decode_i16, decode_i32, encode_i16, encode_i32};
+
+    fn noise(len: usize) -> Vec<u32> {
+        let mut lcg = Lcg(7);
+        (0..len).map(|_| lcg.step()).collect()
+    }
+
+    /// Two interleaved channels of a noisy sine
+    fn samples(len: usize) -> Vec<i16> {
+        noise(len).into_iter().enumerate().map(|(i, n)| {
+            let phase = (i / 2) as f64 / 50.0 + (i % 2) as f64;
+            (phase.sin() * 20000.0) as i16 + (n >> 28) as i16
+        }).collect()
+    }
+
+    #[test]
+    fn known_data() {
+        // 3 gives 6, coded with k = 2 as 10 10, then -2 gives 3, coded with
+        // k = 3 as 0 011, and 0 as 0 000
+        assert_eq!(encode_i16(&[3, 1, 1], 1), [2, 1, 3, 0, 0, 0, 0xa3, 0x00]);
+        assert_eq!(decode_i16(&[2, 1, 3, 0, 0, 0, 0xa3, 0x00]).unwrap(), [3, 1, 1]);
+        assert_eq!(encode_i32(&[], 4), [4, 4, 0, 0, 0, 0]);
+        assert_eq!(decode_i32(&[4, 4, 0, 0, 0, 0]).unwrap(), []);
+    }
+
+    #[test]
+    fn roundtrips() {
+        let input = samples(100000);
+        for stride in 1..4 {
+            let encoded = encode_i16(&input, stride);
+            assert_eq!(decode_i16(&encoded).unwrap(), input);
+        }
+        // the channels are coded apart
+        assert!(encode_i16(&input, 2).len() < input.len() * 2 * 3 / 4);
+        assert!(encode_i16(&input, 2).len() < encode_i16(&input, 1).len());
+
+        let input: Vec<i32> = noise(100000).into_iter().map(|n| n as i32).collect();
+        assert_eq!(decode_i32(&encode_i32(&input, 1)).unwrap(), input);
+        let stamps: Vec<i32> = noise(100000).into_iter().enumerate()
+                                            .map(|(i, n)| 1500000000 + 60 * i as i32 + (n >> 30) as i32)
+                                            .collect();
+        let encoded = encode_i32(&stamps, 1);
+        assert!(encoded.len() < stamps.len() * 4 / 3);
+        assert_eq!(decode_i32(&encoded).unwrap(), stamps);
+    }
+
+    #[test]
+    fn extremes() {
+        // the differences wrap around
+        let input = [i16::MIN, i16::MAX, 0, i16::MIN, -1, i16::MAX, i16::MAX];
+        assert_eq!(decode_i16(&encode_i16(&input, 1)).unwrap(), input);
+        let input = [i32::MIN, i32::MAX, 0, i32::MIN, -1, i32::MAX, i32::MAX];
+        assert_eq!(decode_i32(&encode_i32(&input, 1)).unwrap(), input);
+    }
+
+    #[test]
+    fn invalid_data() {
+        let encoded = encode_i16(&samples(1000), 2);
+        assert!(decode_i32(&encoded).is_err());
+        for len in 0..encoded.len() {
+            assert!(decode_i16(&encoded[..len]).is_err());
+        }
+        // 16 bits of a zero quotient after 0 that do not fit
+        assert!(decode_i16(&[2, 1, 1, 0, 0, 0]).is_err());
+        assert!(decode_i16(&[2, 0, 0, 0, 0, 0]).is_err());
+        assert!(decode_i16(&[2, 17, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_stride() {
+        encode_i16(&[1, 2, 3], 0);
+    }
+}
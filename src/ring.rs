@@ -0,0 +1,221 @@
+//! Bounded queues between two threads
+//!
+//! The threaded coders hand their blocks over to a worker and take back its
+//! results in the same order, one thread at each end. A `ring` is a queue of
+//! a fixed number of slots for such a pair: the producer and the consumer
+//! each move an index of their own, with no lock, and only park once the
+//! queue is full or empty, to be woken by the other end. Dropping either end
+//! closes the queue.
+
+use std::cell::UnsafeCell;
+use std::hint;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Thread};
+
+/// Times an end checks the queue again before parking
+const SPINS: usize = 64;
+
+/// An end of the queue parked until the other one moves
+struct Waiter {
+    parked: AtomicBool,
+    thread: Mutex<Option<Thread>>,
+}
+
+impl Waiter {
+    fn new() -> Waiter {
+        Waiter { parked: AtomicBool::new(false), thread: Mutex::new(None) }
+    }
+
+    /// Returns once `ready` holds, parking the thread in the meantime
+    fn wait<F: Fn() -> bool>(&self, ready: F) {
+        for _ in 0..SPINS {
+            if ready() {
+                return
+            }
+            hint::spin_loop();
+        }
+        loop {
+            *self.thread.lock().unwrap() = Some(thread::current());
+            self.parked.store(true, Ordering::SeqCst);
+            // the other end either sees the flag or moved before this check
+            if ready() {
+                self.parked.store(false, Ordering::SeqCst);
+                return
+            }
+            thread::park();
+        }
+    }
+
+    /// Wakes the thread parked on this end, if any
+    fn wake(&self) {
+        if self.parked.swap(false, Ordering::SeqCst) {
+            if let Some(ref thread) = *self.thread.lock().unwrap() {
+                thread.unpark();
+            }
+        }
+    }
+}
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// Number of values popped, moved by the consumer only
+    head: AtomicUsize,
+    /// Number of values pushed, moved by the producer only
+    tail: AtomicUsize,
+    closed: AtomicBool,
+    producer: Waiter,
+    consumer: Waiter,
+}
+
+// the slots between `head` and `tail` belong to the consumer, the others to
+// the producer
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn slot(&self, i: usize) -> *mut T {
+        self.slots[i % self.slots.len()].get() as *mut T
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.producer.wake();
+        self.consumer.wake();
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let (head, tail) = (*self.head.get_mut(), *self.tail.get_mut());
+        let mut i = head;
+        while i != tail {
+            unsafe { ptr::drop_in_place(self.slot(i)) };
+            i = i.wrapping_add(1);
+        }
+    }
+}
+
+/// The end of a ring pushing the values, which `push` borrows mutably so that
+/// only one thread uses it
+pub struct Producer<T>(Arc<Ring<T>>);
+
+/// The end of a ring popping the values, borrowed mutably as the producer
+pub struct Consumer<T>(Arc<Ring<T>>);
+
+/// Creates a queue of `capacity` values, at least 1
+pub fn ring<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "rings must hold a value");
+    let ring = Arc::new(Ring {
+        slots: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        closed: AtomicBool::new(false),
+        producer: Waiter::new(),
+        consumer: Waiter::new(),
+    });
+    (Producer(ring.clone()), Consumer(ring))
+}
+
+impl<T> Producer<T> {
+    /// Pushes `value`, waiting for a free slot, or gives it back if the
+    /// consumer is gone
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let ring = &*self.0;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        ring.producer.wait(|| {
+            ring.closed.load(Ordering::SeqCst) ||
+                tail.wrapping_sub(ring.head.load(Ordering::SeqCst)) < ring.slots.len()
+        });
+        if ring.closed.load(Ordering::SeqCst) {
+            return Err(value)
+        }
+        unsafe { ptr::write(ring.slot(tail), value) };
+        ring.tail.store(tail.wrapping_add(1), Ordering::SeqCst);
+        ring.consumer.wake();
+        Ok(())
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+impl<T> Consumer<T> {
+    /// Pops the oldest value, or returns `None` if there is none
+    pub fn try_pop(&mut self) -> Option<T> {
+        let ring = &*self.0;
+        let head = ring.head.load(Ordering::Relaxed);
+        if ring.tail.load(Ordering::SeqCst) == head {
+            return None
+        }
+        let value = unsafe { ptr::read(ring.slot(head)) };
+        ring.head.store(head.wrapping_add(1), Ordering::SeqCst);
+        ring.producer.wake();
+        Some(value)
+    }
+
+    /// Pops the oldest value, waiting for one, or returns `None` once the
+    /// queue is empty and the producer gone
+    pub fn pop(&mut self) -> Option<T> {
+        let ring = &*self.0;
+        let head = ring.head.load(Ordering::Relaxed);
+        ring.consumer.wait(|| {
+            ring.tail.load(Ordering::SeqCst) != head || ring.closed.load(Ordering::SeqCst)
+        });
+        self.try_pop()
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+    use super::ring;
+
+    #[test]
+    fn order() {
+        for &capacity in [1, 2, 7].iter() {
+            let (mut tx, mut rx) = ring(capacity);
+            let producer = thread::spawn(move || {
+                for i in 0..10000u32 {
+                    tx.push(i).unwrap();
+                }
+            });
+            for i in 0..10000u32 {
+                assert_eq!(rx.pop(), Some(i));
+            }
+            producer.join().unwrap();
+            assert_eq!(rx.pop(), None);
+        }
+    }
+
+    #[test]
+    fn close() {
+        let (mut tx, mut rx) = ring(2);
+        assert_eq!(rx.try_pop(), None);
+        tx.push(1).unwrap();
+        drop(tx);
+        assert_eq!(rx.pop(), Some(1));
+        assert_eq!(rx.pop(), None);
+
+        // the values left are dropped with the queue
+        let value = Arc::new(0);
+        let (mut tx, rx) = ring(2);
+        tx.push(value.clone()).unwrap();
+        drop(rx);
+        assert!(tx.push(value.clone()).is_err());
+        drop(tx);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,197 @@
+//! The `Bzip2` scheme, the initial run length coding of bzip2: after 4
+//! identical bytes, a byte gives the number of the next repetitions, from 0 to
+//! 251. As the runs are at most 255 bytes long, it also bounds the length of
+//! the repeats the BWT sorts.
+
+use std::cmp;
+use std::io;
+use std::vec::Vec;
+
+use error::truncated;
+
+/// Length of a run past which a repeat count follows
+const RUN_LENGTH: u64 = 4;
+/// Longest run coded at once
+const MAX_RUN: u64 = 255;
+
+pub struct Encoder {
+    byte: u8,
+    reps: u64,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            byte: 0,
+            reps: 0,
+        }
+    }
+
+    fn push_run(&mut self, out: &mut Vec<u8>) {
+        let byte = self.byte;
+        out.extend((0..cmp::min(self.reps, RUN_LENGTH)).map(|_| byte));
+        if self.reps >= RUN_LENGTH {
+            out.push((self.reps - RUN_LENGTH) as u8);
+        }
+        self.reps = 0;
+    }
+}
+
+impl super::Encode for Encoder {
+    fn reset(&mut self) {
+        self.reps = 0;
+    }
+
+    fn encode(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        for &byte in input.iter() {
+            if self.reps > 0 && byte == self.byte && self.reps < MAX_RUN {
+                self.reps += 1;
+            } else {
+                self.push_run(out);
+                self.byte = byte;
+                self.reps = 1;
+            }
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        self.push_run(out);
+    }
+}
+
+enum State {
+    /// The last byte, and how many times it came in a row
+    Same(u8, u64),
+    /// Got 4 identical bytes, waiting for the count
+    Length(u8),
+    Run(u8, u64),
+}
+
+pub struct Decoder {
+    state: State,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            state: State::Same(0, 0),
+        }
+    }
+}
+
+impl super::Decode for Decoder {
+    fn reset(&mut self) {
+        self.state = State::Same(0, 0);
+    }
+
+    fn decode(&mut self, input: &[u8], out: &mut Vec<u8>, limit: usize) -> io::Result<usize> {
+        let end = out.len() + limit;
+        let mut pos = 0;
+        while out.len() < end {
+            if let State::Run(byte, reps) = self.state {
+                let n = cmp::min(reps, (end - out.len()) as u64);
+                out.extend((0..n).map(|_| byte));
+                self.state = if n == reps { State::Same(byte, 0) } else { State::Run(byte, reps - n) };
+                continue
+            }
+            if pos == input.len() {
+                break
+            }
+            let byte = input[pos];
+            pos += 1;
+
+            self.state = match self.state {
+                State::Length(current) => State::Run(current, byte as u64),
+                State::Same(current, count) => {
+                    out.push(byte);
+                    let count = if count > 0 && byte == current { count + 1 } else { 1 };
+                    if count == RUN_LENGTH { State::Length(byte) } else { State::Same(byte, count) }
+                },
+                State::Run(..) => unreachable!(),
+            };
+        }
+        Ok(pos)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.state {
+            State::Length(_) => truncated("truncated rle run"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use super::super::{Decoder, Encoder, Scheme};
+
+    fn encode(input: &[u8]) -> Vec<u8> {
+        let mut encoder = Encoder::with_scheme(Vec::new(), Scheme::Bzip2);
+        encoder.write_all(input).unwrap();
+        let (buf, result) = encoder.finish();
+        result.unwrap();
+        buf
+    }
+
+    fn decode(input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoder = Decoder::with_scheme(input, Scheme::Bzip2);
+        let mut buf = Vec::new();
+        try!(decoder.read_to_end(&mut buf));
+        Ok(buf)
+    }
+
+    fn roundtrip(input: &[u8]) -> Vec<u8> {
+        let buf = encode(input);
+        assert_eq!(&decode(&buf).unwrap()[..], input);
+        buf
+    }
+
+    #[test]
+    fn known_data() {
+        assert_eq!(roundtrip(b""), b"");
+        assert_eq!(roundtrip(b"aaabbbbcccccc"), b"aaabbbb\0cccc\x02");
+        assert_eq!(roundtrip(&vec![7; 256]), b"\x07\x07\x07\x07\xfb\x07");
+        assert_eq!(roundtrip(&vec![7; 259]), b"\x07\x07\x07\x07\xfb\x07\x07\x07\x07\0");
+        // a count may be followed by the same byte, which starts a new run
+        assert_eq!(decode(b"aaaa\x01aaaa\0").unwrap(), b"aaaaaaaaa");
+        assert_eq!(decode(b"aaaa\xff").unwrap().len(), 259);
+    }
+
+    #[test]
+    fn roundtrips() {
+        let mut input = Vec::new();
+        for i in 0..600 {
+            input.extend((0..i).map(|_| (i / 2) as u8));
+        }
+        roundtrip(&input);
+        roundtrip(include_bytes!("../data/test.txt"));
+        // the runs are coded in at most 5 bytes each
+        let buf = roundtrip(&vec![0; 100000]);
+        assert_eq!(buf.len(), (100000 + 254) / 255 * 5);
+    }
+
+    #[test]
+    fn streams() {
+        let input: Vec<u8> = (0..20000).map(|i| (i / 3 % 7 + i / 1000) as u8).collect();
+        let expected = encode(&input);
+        for &step in [1, 3, 5000].iter() {
+            let mut encoder = Encoder::with_scheme(Vec::new(), Scheme::Bzip2);
+            for chunk in input.chunks(step) {
+                encoder.write_all(chunk).unwrap();
+            }
+            let (buf, _) = encoder.finish();
+            assert!(buf == expected);
+            let mut decoder = Decoder::with_scheme(io::BufReader::with_capacity(step, &buf[..]), Scheme::Bzip2);
+            let mut decoded = Vec::new();
+            decoder.read_to_end(&mut decoded).unwrap();
+            assert!(decoded == input);
+        }
+    }
+
+    #[test]
+    fn invalid_data() {
+        assert!(decode(b"aaaa").is_err());
+        assert!(decode(b"abaaaa").is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//! The `Doubled` and `Words` schemes: a run is coded by two repetitions of its
+//! byte, or of its unit of 2, 4 or 8 bytes, followed by its length minus two,
+//! on 7 bits per byte with the high bit set on the last byte. The bytes of the
+//! last incomplete unit are as is.
+
+use std::cmp;
+use std::io;
+use std::vec::Vec;
+
+use error::{corrupt, truncated};
+
+/// Largest number of bytes of a length
+const MAX_LENGTH_BYTES: u32 = 9;
+
+/// Size of the largest units
+const MAX_UNIT: usize = 8;
+
+type Unit = [u8; MAX_UNIT];
+
+pub struct Encoder {
+    unit: usize,
+    /// Start of the next unit
+    part: Unit,
+    fill: usize,
+    run: Unit,
+    reps: u64,
+}
+
+impl Encoder {
+    pub fn new(unit: usize) -> Encoder {
+        Encoder {
+            unit: unit,
+            part: [0; MAX_UNIT],
+            fill: 0,
+            run: [0; MAX_UNIT],
+            reps: 0,
+        }
+    }
+
+    fn push_run(&mut self, out: &mut Vec<u8>) {
+        let run = &self.run[..self.unit];
+        if self.reps == 1 {
+            out.extend_from_slice(run);
+        } else if self.reps > 1 {
+            let mut reps_encode = self.reps - 2;
+            out.extend_from_slice(run);
+            out.extend_from_slice(run);
+
+            loop {
+                let byte = (reps_encode & 0b0111_1111) as u8;
+                reps_encode = reps_encode >> 7;
+
+                if reps_encode == 0 {
+                    out.push(byte | 0b1000_0000);
+                    break;
+                }
+
+                out.push(byte);
+            }
+        }
+        self.reps = 0;
+    }
+}
+
+impl super::Encode for Encoder {
+    fn reset(&mut self) {
+        self.fill = 0;
+        self.reps = 0;
+    }
+
+    fn encode(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        for &byte in input.iter() {
+            self.part[self.fill] = byte;
+            self.fill += 1;
+            if self.fill < self.unit {
+                continue
+            }
+            self.fill = 0;
+            if self.reps > 0 && self.part[..self.unit] == self.run[..self.unit] {
+                self.reps += 1;
+            } else {
+                self.push_run(out);
+                self.run = self.part;
+                self.reps = 1;
+            }
+        }
+    }
+
+    /// Codes the last run, then the bytes of the last incomplete unit as is
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        self.push_run(out);
+        out.extend_from_slice(&self.part[..self.fill]);
+        self.fill = 0;
+    }
+}
+
+enum State {
+    Clean,
+    Single(Unit),
+    /// Reading the length of a run, with the number of its bytes read
+    Length(Unit, u64, u32),
+    /// A run, with the number of its bytes left
+    Run(Unit, u64),
+}
+
+pub struct Decoder {
+    unit: usize,
+    part: Unit,
+    fill: usize,
+    state: State,
+    /// Whether the coded data ended, leaving the incomplete unit as is
+    ended: bool,
+}
+
+impl Decoder {
+    pub fn new(unit: usize) -> Decoder {
+        Decoder {
+            unit: unit,
+            part: [0; MAX_UNIT],
+            fill: 0,
+            state: State::Clean,
+            ended: false,
+        }
+    }
+}
+
+impl super::Decode for Decoder {
+    fn reset(&mut self) {
+        self.fill = 0;
+        self.state = State::Clean;
+        self.ended = false;
+    }
+
+    fn decode(&mut self, input: &[u8], out: &mut Vec<u8>, limit: usize) -> io::Result<usize> {
+        let end = out.len() + limit;
+        let unit = self.unit;
+        let mut pos = 0;
+        while out.len() < end {
+            if let State::Run(run, left) = self.state {
+                let n = cmp::min(left, (end - out.len()) as u64);
+                // the run may have been cut within a unit
+                let skip = (unit - (left % unit as u64) as usize) % unit;
+                out.extend(run[..unit].iter().cycle().skip(skip).take(n as usize));
+                self.state = if n == left { State::Clean } else { State::Run(run, left - n) };
+                continue
+            }
+            if pos == input.len() {
+                if self.ended && self.fill > 0 {
+                    let n = cmp::min(self.fill, end - out.len());
+                    out.extend_from_slice(&self.part[..n]);
+                    self.part.copy_within(n..self.fill, 0);
+                    self.fill -= n;
+                    continue
+                }
+                break
+            }
+            let byte = input[pos];
+            pos += 1;
+
+            if let State::Length(current, reps, count) = self.state {
+                if count >= MAX_LENGTH_BYTES {
+                    return corrupt("overly long rle run")
+                }
+                let reps = reps | (((byte & 0b0111_1111) as u64) << (count * 7));
+                self.state = if byte & 0b1000_0000 != 0 {
+                    match (reps + 2).checked_mul(unit as u64) {
+                        Some(len) => State::Run(current, len),
+                        None => return corrupt("overly long rle run"),
+                    }
+                } else {
+                    State::Length(current, reps, count + 1)
+                };
+                continue
+            }
+            self.part[self.fill] = byte;
+            self.fill += 1;
+            if self.fill < unit {
+                continue
+            }
+            self.fill = 0;
+
+            self.state = match self.state {
+                State::Clean => State::Single(self.part),
+                State::Single(current) if self.part[..unit] == current[..unit] => State::Length(current, 0, 0),
+                State::Single(current) => {
+                    out.extend_from_slice(&current[..unit]);
+                    State::Single(self.part)
+                },
+                State::Length(..) | State::Run(..) => unreachable!(),
+            };
+        }
+        Ok(pos)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // flush remaining state into run, the incomplete unit following it
+        self.ended = true;
+        self.state = match self.state {
+            State::Clean => State::Clean,
+            State::Single(current) => State::Run(current, self.unit as u64),
+            State::Length(..) => return truncated("truncated rle run"),
+            State::Run(current, left) => State::Run(current, left),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use super::super::{decode_block, encode_block, Decoder, Encoder, Scheme};
+
+    fn roundtrip(input: &[u8], unit: usize) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_block(input, Scheme::Words(unit), &mut buf);
+        let mut decoded = Vec::new();
+        decode_block(&buf, Scheme::Words(unit), &mut decoded, input.len()).unwrap();
+        assert_eq!(&decoded[..], input);
+        buf
+    }
+
+    #[test]
+    fn known_data() {
+        assert_eq!(roundtrip(b"", 2), b"");
+        assert_eq!(roundtrip(b"abcdabcdabcde", 4), b"abcdabcd\x81e");
+        assert_eq!(roundtrip(b"aaab", 2), b"aaab");
+        assert_eq!(roundtrip(b"ababa", 2), b"abab\x80a");
+        assert_eq!(roundtrip(b"abcdefghabcdefgh", 8), b"abcdefghabcdefgh\x80");
+    }
+
+    #[test]
+    fn words() {
+        // the pixels of a framebuffer, as 32-bit words
+        let mut input = Vec::new();
+        for i in 0..1000u32 {
+            for _ in 0..(i % 50) {
+                input.extend_from_slice(&[(i / 7) as u8, 0x80, (i / 3) as u8, 0xff]);
+            }
+        }
+        let mut bytes = Vec::new();
+        encode_block(&input, Scheme::Doubled, &mut bytes);
+        let words = roundtrip(&input, 4);
+        assert!(words.len() * 10 < bytes.len());
+        for &unit in [2, 8].iter() {
+            roundtrip(&input, unit);
+            roundtrip(&input[1..], unit);
+            roundtrip(&input[..input.len() - 1], unit);
+        }
+    }
+
+    #[test]
+    fn streams() {
+        let input: Vec<u8> = (0..20001).map(|i| (i / 40 % 3 + (i % 4) * 11) as u8).collect();
+        for &unit in [2, 4, 8].iter() {
+            let mut expected = Vec::new();
+            encode_block(&input, Scheme::Words(unit), &mut expected);
+            for &step in [1, 3, 5000].iter() {
+                let mut encoder = Encoder::with_scheme(Vec::new(), Scheme::Words(unit));
+                for chunk in input.chunks(step) {
+                    encoder.write_all(chunk).unwrap();
+                }
+                let (buf, _) = encoder.finish();
+                assert!(buf == expected);
+                let mut decoder = Decoder::with_scheme(io::BufReader::with_capacity(step, &buf[..]),
+                                                       Scheme::Words(unit));
+                let mut decoded = Vec::new();
+                let mut chunk = vec![0; step];
+                loop {
+                    let n = decoder.read(&mut chunk).unwrap();
+                    if n == 0 {
+                        break
+                    }
+                    decoded.extend_from_slice(&chunk[..n]);
+                }
+                assert!(decoded == input);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_unit() {
+        Encoder::with_scheme(Vec::new(), Scheme::Words(3));
+    }
+}
@@ -0,0 +1,376 @@
+//! The `Escape` scheme: the data starts with a header of the escape byte,
+//! which is the least frequent byte of the first `WINDOW` bytes, the shortest
+//! run coded, 4 by default, and the largest count of a run, 255 by default.
+//! A run is then coded by the escape, the count and the byte, where the count
+//! is the length of the run minus the shortest run plus one, while the escape
+//! itself is coded by the escape followed by 0. The other bytes are as is.
+
+use std::cmp;
+use std::io;
+use std::mem;
+use std::vec::Vec;
+
+use error::{corrupt, truncated};
+use histogram::histogram;
+
+/// Number of bytes from which the escape is chosen
+const WINDOW: usize = 1 << 12;
+/// Shortest run coded as such, by default
+const MIN_RUN: usize = 4;
+/// Largest count of a run
+const MAX_COUNT: usize = 255;
+
+pub struct Encoder {
+    /// The escape, once chosen
+    escape: Option<u8>,
+    /// Start of the data, from which the escape is chosen
+    window: Vec<u8>,
+    min_run: usize,
+    max_run: usize,
+    byte: u8,
+    reps: usize,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            escape: None,
+            window: Vec::new(),
+            min_run: MIN_RUN,
+            max_run: MIN_RUN + MAX_COUNT - 1,
+            byte: 0,
+            reps: 0,
+        }
+    }
+
+    /// Chooses the escape, and codes the data it was chosen from
+    fn start(&mut self, out: &mut Vec<u8>) {
+        let freq = histogram(&self.window);
+        let escape = (0..0x100).min_by_key(|&b| freq[b]).unwrap() as u8;
+        out.extend([escape, self.min_run as u8, (self.max_run - self.min_run + 1) as u8].iter());
+        self.escape = Some(escape);
+        let window = mem::replace(&mut self.window, Vec::new());
+        self.code(&window, out, escape);
+    }
+
+    fn code(&mut self, input: &[u8], out: &mut Vec<u8>, escape: u8) {
+        for &byte in input.iter() {
+            if self.reps > 0 && byte == self.byte && self.reps < self.max_run {
+                self.reps += 1;
+            } else {
+                self.push_run(out, escape);
+                self.byte = byte;
+                self.reps = 1;
+            }
+        }
+    }
+
+    fn push_run(&mut self, out: &mut Vec<u8>, escape: u8) {
+        if self.reps >= self.min_run {
+            out.extend([escape, (self.reps - self.min_run + 1) as u8, self.byte].iter());
+        } else {
+            for _ in 0..self.reps {
+                out.push(self.byte);
+                if self.byte == escape {
+                    out.push(0);
+                }
+            }
+        }
+        self.reps = 0;
+    }
+}
+
+impl super::Encode for Encoder {
+    fn reset(&mut self) {
+        self.escape = None;
+        self.window.truncate(0);
+        self.reps = 0;
+    }
+
+    #[cfg(feature="std")]
+    fn set_runs(&mut self, min_run: usize, max_run: usize) -> io::Result<()> {
+        if self.escape.is_some() || !self.window.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "rle runs set after the data"))
+        }
+        if min_run < 2 || min_run > max_run || max_run >= min_run + MAX_COUNT {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid rle runs"))
+        }
+        self.min_run = min_run;
+        self.max_run = max_run;
+        Ok(())
+    }
+
+    fn encode(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        match self.escape {
+            Some(escape) => self.code(input, out, escape),
+            None => {
+                let n = cmp::min(input.len(), WINDOW - self.window.len());
+                self.window.extend_from_slice(&input[..n]);
+                if self.window.len() == WINDOW {
+                    self.start(out);
+                    self.encode(&input[n..], out);
+                }
+            },
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        if self.escape.is_none() && !self.window.is_empty() {
+            self.start(out);
+        }
+        if let Some(escape) = self.escape {
+            self.push_run(out, escape);
+        }
+    }
+}
+
+enum State {
+    /// Reading the header, with the number of its bytes read
+    Header(usize),
+    Clean,
+    Escaped,
+    /// Got the length of a run, waiting for its byte
+    Length(u8),
+    Run(u8, u64),
+}
+
+pub struct Decoder {
+    escape: u8,
+    min_run: usize,
+    max_count: u8,
+    state: State,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            escape: 0,
+            min_run: 0,
+            max_count: 0,
+            state: State::Header(0),
+        }
+    }
+}
+
+impl super::Decode for Decoder {
+    fn reset(&mut self) {
+        self.escape = 0;
+        self.min_run = 0;
+        self.max_count = 0;
+        self.state = State::Header(0);
+    }
+
+    fn decode(&mut self, input: &[u8], out: &mut Vec<u8>, limit: usize) -> io::Result<usize> {
+        let end = out.len() + limit;
+        let mut pos = 0;
+        while out.len() < end {
+            if let State::Run(byte, reps) = self.state {
+                let n = cmp::min(reps, (end - out.len()) as u64);
+                out.extend((0..n).map(|_| byte));
+                self.state = if n == reps { State::Clean } else { State::Run(byte, reps - n) };
+                continue
+            }
+            if pos == input.len() {
+                break
+            }
+            let byte = input[pos];
+            pos += 1;
+
+            self.state = match self.state {
+                State::Header(0) => {
+                    self.escape = byte;
+                    State::Header(1)
+                },
+                State::Header(1) if byte < 2 => return corrupt("invalid rle header"),
+                State::Header(1) => {
+                    self.min_run = byte as usize;
+                    State::Header(2)
+                },
+                State::Header(_) if byte == 0 => return corrupt("invalid rle header"),
+                State::Header(_) => {
+                    self.max_count = byte;
+                    State::Clean
+                },
+                State::Clean if byte == self.escape => State::Escaped,
+                State::Clean => {
+                    out.push(byte);
+                    State::Clean
+                },
+                State::Escaped if byte == 0 => {
+                    out.push(self.escape);
+                    State::Clean
+                },
+                State::Escaped if byte > self.max_count => return corrupt("invalid rle run length"),
+                State::Escaped => State::Length(byte),
+                State::Length(count) => State::Run(byte, (count as usize + self.min_run - 1) as u64),
+                State::Run(..) => unreachable!(),
+            };
+        }
+        Ok(pos)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.state {
+            State::Header(n) if n > 0 => truncated("truncated rle header"),
+            State::Header(_) | State::Clean | State::Run(..) => Ok(()),
+            State::Escaped | State::Length(_) => truncated("truncated rle run"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use testing::noise;
+    use super::super::{Decoder, Encoder, Scheme};
+    use super::WINDOW;
+
+    fn encode(input: &[u8]) -> Vec<u8> {
+        let mut encoder = Encoder::with_scheme(Vec::new(), Scheme::Escape);
+        encoder.write_all(input).unwrap();
+        let (buf, result) = encoder.finish();
+        result.unwrap();
+        buf
+    }
+
+    fn decode(input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoder = Decoder::with_scheme(input, Scheme::Escape);
+        let mut buf = Vec::new();
+        try!(decoder.read_to_end(&mut buf));
+        Ok(buf)
+    }
+
+    fn roundtrip(input: &[u8]) -> Vec<u8> {
+        let buf = encode(input);
+        assert_eq!(&decode(&buf).unwrap()[..], input);
+        buf
+    }
+
+    #[test]
+    fn known_data() {
+        assert_eq!(roundtrip(b""), b"");
+        assert_eq!(roundtrip(b"abbbbbbc"), b"\0\x04\xffa\0\x03bc");
+        assert_eq!(roundtrip(b"\0\0a"), b"\x01\x04\xff\0\0a");
+        // the escape is the first of the least frequent bytes
+        let all: Vec<u8> = (0..0x100).map(|b| b as u8).chain(Some(0)).collect();
+        let buf = roundtrip(&all);
+        assert_eq!(buf.len(), 3 + all.len() + 1);
+        assert_eq!(&buf[..6], &[1, 4, 255, 0, 1, 0]);
+    }
+
+    #[test]
+    fn runs() {
+        let mut input = Vec::new();
+        for i in 0..600 {
+            input.push(i as u8);
+            input.extend((0..i).map(|_| (i / 3) as u8));
+        }
+        roundtrip(&input);
+        roundtrip(&vec![0; 258]);
+        assert_eq!(roundtrip(&vec![0; 259]), b"\x01\x04\xff\x01\xff\0\0");
+        // runs of the escape, after the window
+        let mut input = noise(WINDOW + 10, 7);
+        let escape = encode(&input)[0];
+        for n in 0..300 {
+            input.extend((0..n).map(|_| escape));
+            input.push(!escape);
+        }
+        roundtrip(&input);
+    }
+
+    #[test]
+    fn no_inflation() {
+        // a byte is missing from the window, and thus never escaped
+        let mut input: Vec<u8> = noise(WINDOW * 4, 7).into_iter().map(|b| if b == 0x42 { 0x43 } else { b }).collect();
+        assert_eq!(roundtrip(&input).len(), 3 + input.len());
+        input[WINDOW * 2] = 0x42;
+        assert_eq!(roundtrip(&input).len(), 3 + input.len() + 1);
+    }
+
+    #[test]
+    fn streams() {
+        let mut input = noise(50000, 7);
+        for i in 0..input.len() / 100 {
+            for j in 0..i % 20 {
+                input[i * 100 + j] = i as u8;
+            }
+        }
+        let expected = encode(&input);
+        for &step in [1, 7, 1000, 5000].iter() {
+            let mut encoder = Encoder::with_scheme(Vec::new(), Scheme::Escape);
+            for chunk in input.chunks(step) {
+                encoder.write_all(chunk).unwrap();
+            }
+            let (buf, result) = encoder.finish();
+            result.unwrap();
+            assert!(buf == expected);
+
+            let mut decoder = Decoder::with_scheme(io::BufReader::with_capacity(step, &buf[..]), Scheme::Escape);
+            let mut decoded = Vec::new();
+            let mut chunk = vec![0; step];
+            loop {
+                let n = decoder.read(&mut chunk).unwrap();
+                if n == 0 {
+                    break
+                }
+                decoded.extend_from_slice(&chunk[..n]);
+            }
+            assert!(decoder.eof());
+            assert!(decoded == input);
+        }
+    }
+
+    fn tuned(input: &[u8], min_run: usize, max_run: usize) -> Vec<u8> {
+        let mut encoder = Encoder::with_scheme(Vec::new(), Scheme::Escape);
+        encoder.set_runs(min_run, max_run).unwrap();
+        encoder.write_all(input).unwrap();
+        let (buf, _) = encoder.finish();
+        assert_eq!(&decode(&buf).unwrap()[..], input);
+        buf
+    }
+
+    #[test]
+    fn tuned_runs() {
+        assert_eq!(tuned(b"abbbbbbc", 8, 20), b"\0\x08\x0dabbbbbbc");
+        assert_eq!(tuned(b"abbbbbbbbc", 8, 20), b"\0\x08\x0da\0\x01bc");
+        assert_eq!(tuned(b"abbbbc", 2, 3), b"\0\x02\x02a\0\x02bbc");
+        let mut input = Vec::new();
+        for i in 0..600 {
+            input.push(i as u8);
+            input.extend((0..i).map(|_| (i / 3) as u8));
+        }
+        for &(min_run, max_run) in [(2, 2), (2, 255), (8, 8), (100, 353), (255, 508)].iter() {
+            tuned(&input, min_run, max_run);
+        }
+    }
+
+    #[test]
+    fn invalid_runs() {
+        let mut encoder = Encoder::with_scheme(Vec::new(), Scheme::Escape);
+        for &(min_run, max_run) in [(8, 7), (1, 4), (2, 257)].iter() {
+            let err = encoder.set_runs(min_run, max_run).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        // the runs of the other schemes are fixed
+        let err = Encoder::new(Vec::new()).set_runs(4, 8).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn runs_after_data() {
+        let mut encoder = Encoder::with_scheme(Vec::new(), Scheme::Escape);
+        encoder.write_all(b"a").unwrap();
+        assert!(encoder.set_runs(4, 8).is_err());
+    }
+
+    #[test]
+    fn invalid_data() {
+        assert!(decode(b"\0\x04\xff").unwrap().is_empty());
+        assert!(decode(b"\0").is_err());
+        assert!(decode(b"\0\x01\xff").is_err());
+        assert!(decode(b"\0\x04\0").is_err());
+        assert!(decode(b"\0\x04\xffa\0").is_err());
+        assert!(decode(b"\0\x04\xffa\0\x05").is_err());
+        assert!(decode(b"\0\x04\x05a\0\x06b").is_err());
+    }
+}
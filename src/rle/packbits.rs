@@ -0,0 +1,218 @@
+//! The `PackBits` scheme of TIFF, ILBM and MacPaint: a header byte `n` is
+//! followed by `n + 1` literal bytes when below 128, or by a byte repeated
+//! `257 - n` times when above 128, while 128 is skipped.
+
+use std::cmp;
+use std::io;
+use std::vec::Vec;
+
+use error::truncated;
+
+/// Longest run or literals coded at once
+const MAX_LEN: usize = 128;
+
+pub struct Encoder {
+    /// Literals not yet written
+    lits: Vec<u8>,
+    byte: u8,
+    reps: usize,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            lits: Vec::with_capacity(MAX_LEN),
+            byte: 0,
+            reps: 0,
+        }
+    }
+
+    fn push_lits(&mut self, out: &mut Vec<u8>) {
+        if !self.lits.is_empty() {
+            out.push((self.lits.len() - 1) as u8);
+            out.extend_from_slice(&self.lits);
+            self.lits.truncate(0);
+        }
+    }
+
+    fn push_run(&mut self, out: &mut Vec<u8>) {
+        // a run of 2 costs as much as 2 more literals, but ends them
+        if self.reps >= 3 || (self.reps == 2 && self.lits.is_empty()) {
+            self.push_lits(out);
+            out.push((257 - self.reps) as u8);
+            out.push(self.byte);
+        } else {
+            for _ in 0..self.reps {
+                self.lits.push(self.byte);
+                if self.lits.len() == MAX_LEN {
+                    self.push_lits(out);
+                }
+            }
+        }
+        self.reps = 0;
+    }
+}
+
+impl super::Encode for Encoder {
+    fn reset(&mut self) {
+        self.lits.truncate(0);
+        self.reps = 0;
+    }
+
+    fn encode(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        for &byte in input.iter() {
+            if self.reps > 0 && byte == self.byte && self.reps < MAX_LEN {
+                self.reps += 1;
+            } else {
+                self.push_run(out);
+                self.byte = byte;
+                self.reps = 1;
+            }
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        self.push_run(out);
+        self.push_lits(out);
+    }
+}
+
+enum State {
+    Header,
+    /// Number of literal bytes left
+    Literal(usize),
+    /// Got the length of a run, waiting for its byte
+    Length(usize),
+    Run(u8, usize),
+}
+
+pub struct Decoder {
+    state: State,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            state: State::Header,
+        }
+    }
+}
+
+impl super::Decode for Decoder {
+    fn reset(&mut self) {
+        self.state = State::Header;
+    }
+
+    fn decode(&mut self, input: &[u8], out: &mut Vec<u8>, limit: usize) -> io::Result<usize> {
+        let end = out.len() + limit;
+        let mut pos = 0;
+        while out.len() < end {
+            if let State::Run(byte, reps) = self.state {
+                let n = cmp::min(reps, end - out.len());
+                out.extend((0..n).map(|_| byte));
+                self.state = if n == reps { State::Header } else { State::Run(byte, reps - n) };
+                continue
+            }
+            if pos == input.len() {
+                break
+            }
+            if let State::Literal(left) = self.state {
+                let n = cmp::min(cmp::min(left, input.len() - pos), end - out.len());
+                out.extend_from_slice(&input[pos..pos + n]);
+                pos += n;
+                self.state = if n == left { State::Header } else { State::Literal(left - n) };
+                continue
+            }
+            let byte = input[pos];
+            pos += 1;
+
+            self.state = match self.state {
+                State::Header if byte < 128 => State::Literal(byte as usize + 1),
+                State::Header if byte == 128 => State::Header,
+                State::Header => State::Length(257 - byte as usize),
+                State::Length(len) => State::Run(byte, len),
+                State::Literal(_) | State::Run(..) => unreachable!(),
+            };
+        }
+        Ok(pos)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.state {
+            State::Literal(_) => truncated("truncated rle literals"),
+            State::Length(_) => truncated("truncated rle run"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use super::super::{decode_block, encode_block, Decoder, Encoder, Scheme};
+
+    fn roundtrip(input: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_block(input, Scheme::PackBits, &mut buf);
+        let mut decoded = Vec::new();
+        decode_block(&buf, Scheme::PackBits, &mut decoded, input.len()).unwrap();
+        assert_eq!(&decoded[..], input);
+        buf
+    }
+
+    #[test]
+    fn known_data() {
+        // from Apple's Technical Note TN1023
+        let packed = [0xfe, 0xaa, 0x02, 0x80, 0x00, 0x2a, 0xfd, 0xaa, 0x03, 0x80,
+                      0x00, 0x2a, 0x22, 0xf7, 0xaa];
+        let unpacked = [0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0xaa, 0xaa, 0xaa, 0xaa,
+                        0x80, 0x00, 0x2a, 0x22, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa,
+                        0xaa, 0xaa, 0xaa, 0xaa];
+        assert_eq!(&roundtrip(&unpacked)[..], &packed[..]);
+        assert_eq!(roundtrip(b""), b"");
+        assert_eq!(roundtrip(b"aab"), b"\xffa\0b");
+        assert_eq!(roundtrip(b"abbc"), b"\x03abbc");
+        // 128 is skipped
+        let mut decoded = Vec::new();
+        decode_block(b"\x80\x00a\x80", Scheme::PackBits, &mut decoded, 1).unwrap();
+        assert_eq!(decoded, b"a");
+    }
+
+    #[test]
+    fn long_data() {
+        let buf = roundtrip(&vec![5; 300]);
+        assert_eq!(buf, b"\x81\x05\x81\x05\xd5\x05");
+        let input: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let buf = roundtrip(&input);
+        assert_eq!(buf.len(), 300 + 3);
+        assert_eq!((buf[0], buf[129], buf[258]), (127, 127, 43));
+    }
+
+    #[test]
+    fn streams() {
+        let input: Vec<u8> = (0..20000).map(|i| if i % 300 < 150 { (i / 7) as u8 } else { (i * 7 / 3) as u8 }).collect();
+        let mut expected = Vec::new();
+        encode_block(&input, Scheme::PackBits, &mut expected);
+        for &step in [1, 3, 5000].iter() {
+            let mut encoder = Encoder::with_scheme(Vec::new(), Scheme::PackBits);
+            for chunk in input.chunks(step) {
+                encoder.write_all(chunk).unwrap();
+            }
+            let (buf, _) = encoder.finish();
+            assert!(buf == expected);
+            let mut decoder = Decoder::with_scheme(io::BufReader::with_capacity(step, &buf[..]), Scheme::PackBits);
+            let mut decoded = Vec::new();
+            decoder.read_to_end(&mut decoded).unwrap();
+            assert!(decoded == input);
+        }
+    }
+
+    #[test]
+    fn invalid_data() {
+        let mut decoded = Vec::new();
+        assert!(decode_block(b"\x02ab", Scheme::PackBits, &mut decoded, 10).is_err());
+        assert!(decode_block(b"\xfe", Scheme::PackBits, &mut decoded, 10).is_err());
+        assert!(decode_block(b"\xfea", Scheme::PackBits, &mut decoded, 2).is_err());
+        assert!(decode_block(b"\x01ab\x00c", Scheme::PackBits, &mut decoded, 2).is_err());
+    }
+}
@@ -0,0 +1,338 @@
+//! The `Rows` scheme, for images stored row after row: besides the runs of a
+//! byte, it codes the spans which are the same as in the previous row. Each
+//! item starts with a tag whose 2 high bits give the kind: literals, a run or
+//! a span of the previous row, and whose 6 low bits give the length minus one,
+//! up to 62. The value 63 means the length minus 64 follows, by groups of 7
+//! bits with the high bit set when more follow. The literals follow their tag,
+//! as the byte of a run.
+
+use std::cmp;
+use std::io;
+use std::vec::Vec;
+
+use error::{corrupt, truncated};
+
+/// Longest item, and the data the encoder looks ahead
+const MAX_LEN: usize = 1 << 16;
+/// Shortest runs and spans of the previous row coded as such
+const MIN_RUN: usize = 3;
+const MIN_ABOVE: usize = 2;
+/// Largest length in a tag
+const TAG_LEN: usize = 63;
+/// Largest number of bytes following a tag
+const MAX_EXTRA: u32 = 3;
+
+const LITERALS: u8 = 0;
+const RUN: u8 = 1;
+const ABOVE: u8 = 2;
+
+fn push_tag(out: &mut Vec<u8>, kind: u8, len: usize) {
+    let n = len - 1;
+    if n < TAG_LEN {
+        out.push(kind << 6 | n as u8);
+        return
+    }
+    out.push(kind << 6 | TAG_LEN as u8);
+    let mut n = n - TAG_LEN;
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+pub struct Encoder {
+    stride: usize,
+    /// The data from the previous row of the next uncoded byte
+    data: Vec<u8>,
+    /// Position of the next uncoded byte, and of the pending literals
+    pos: usize,
+    lits: usize,
+    /// Whether `data` starts at the first row
+    first: bool,
+}
+
+impl Encoder {
+    pub fn new(stride: usize) -> Encoder {
+        Encoder {
+            stride: stride,
+            data: Vec::new(),
+            pos: 0,
+            lits: 0,
+            first: true,
+        }
+    }
+
+    fn push_lits(&mut self, out: &mut Vec<u8>) {
+        if self.lits < self.pos {
+            push_tag(out, LITERALS, self.pos - self.lits);
+            out.extend_from_slice(&self.data[self.lits..self.pos]);
+        }
+        self.lits = self.pos;
+    }
+
+    /// Codes the items starting at least `MAX_LEN` bytes before the end of
+    /// the data, unless it is the last one
+    fn parse(&mut self, out: &mut Vec<u8>, last: bool) {
+        let data_end = if last { self.data.len() } else { self.data.len().saturating_sub(MAX_LEN) };
+        while self.pos < data_end {
+            let (above, run, byte) = {
+                let (pos, data) = (self.pos, &self.data);
+                let end = cmp::min(data.len(), pos + MAX_LEN);
+                let above = if self.first && pos < self.stride { 0 } else {
+                    (pos..end).take_while(|&i| data[i] == data[i - self.stride]).count()
+                };
+                (above, (pos..end).take_while(|&i| data[i] == data[pos]).count(), data[pos])
+            };
+            if above >= MIN_ABOVE && above >= run {
+                self.push_lits(out);
+                push_tag(out, ABOVE, above);
+                self.pos += above;
+                self.lits = self.pos;
+            } else if run >= MIN_RUN {
+                self.push_lits(out);
+                push_tag(out, RUN, run);
+                out.push(byte);
+                self.pos += run;
+                self.lits = self.pos;
+            } else {
+                self.pos += 1;
+                if self.pos - self.lits == MAX_LEN {
+                    self.push_lits(out);
+                }
+            }
+        }
+        if last {
+            self.push_lits(out);
+        }
+        // only the previous row of the uncoded data is kept, dropping the rest
+        // by large pieces
+        let keep = cmp::min(self.lits, self.pos).saturating_sub(self.stride);
+        if keep >= MAX_LEN {
+            self.data.drain(..keep);
+            self.pos -= keep;
+            self.lits -= keep;
+            self.first = false;
+        }
+    }
+}
+
+impl super::Encode for Encoder {
+    fn reset(&mut self) {
+        self.data.truncate(0);
+        self.pos = 0;
+        self.lits = 0;
+        self.first = true;
+    }
+
+    fn encode(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        self.data.extend_from_slice(input);
+        self.parse(out, false);
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        self.parse(out, true);
+    }
+}
+
+enum State {
+    Tag,
+    /// Reading the length following a tag, with the number of its bytes read
+    Length(u8, usize, u32),
+    /// Got the length of a run, waiting for its byte
+    Byte(usize),
+    Literal(usize),
+    Run(u8, usize),
+    Above(usize),
+}
+
+pub struct Decoder {
+    /// The last row decoded, at the positions modulo the stride
+    row: Vec<u8>,
+    /// Number of bytes decoded
+    total: u64,
+    state: State,
+}
+
+impl Decoder {
+    pub fn new(stride: usize) -> Decoder {
+        Decoder {
+            row: vec![0; stride],
+            total: 0,
+            state: State::Tag,
+        }
+    }
+
+    fn start(&mut self, kind: u8, len: usize) -> io::Result<State> {
+        match kind {
+            LITERALS => Ok(State::Literal(len)),
+            RUN => Ok(State::Byte(len)),
+            ABOVE if self.total < self.row.len() as u64 => corrupt("rle span above the first row"),
+            ABOVE => Ok(State::Above(len)),
+            _ => corrupt("invalid rle tag"),
+        }
+    }
+}
+
+impl super::Decode for Decoder {
+    fn reset(&mut self) {
+        for byte in self.row.iter_mut() {
+            *byte = 0;
+        }
+        self.total = 0;
+        self.state = State::Tag;
+    }
+
+    fn decode(&mut self, input: &[u8], out: &mut Vec<u8>, limit: usize) -> io::Result<usize> {
+        let end = out.len() + limit;
+        let stride = self.row.len() as u64;
+        let mut pos = 0;
+        while out.len() < end {
+            let n = match self.state {
+                State::Run(_, left) | State::Above(left) => cmp::min(left, end - out.len()),
+                State::Literal(left) if pos < input.len() =>
+                    cmp::min(cmp::min(left, input.len() - pos), end - out.len()),
+                _ => 0,
+            };
+            if n > 0 {
+                let start = out.len();
+                match self.state {
+                    State::Run(byte, _) => out.extend((0..n).map(|_| byte)),
+                    State::Literal(_) => {
+                        out.extend_from_slice(&input[pos..pos + n]);
+                        pos += n;
+                    },
+                    // the previous row is already there
+                    _ => {
+                        for i in 0..n as u64 {
+                            out.push(self.row[((self.total + i) % stride) as usize]);
+                        }
+                    },
+                }
+                for (i, &byte) in out[start..].iter().enumerate() {
+                    self.row[((self.total + i as u64) % stride) as usize] = byte;
+                }
+                self.total += n as u64;
+                self.state = match self.state {
+                    State::Run(byte, left) if left > n => State::Run(byte, left - n),
+                    State::Literal(left) if left > n => State::Literal(left - n),
+                    State::Above(left) if left > n => State::Above(left - n),
+                    _ => State::Tag,
+                };
+                continue
+            }
+            if pos == input.len() {
+                break
+            }
+            let byte = input[pos];
+            pos += 1;
+
+            self.state = match self.state {
+                State::Tag if (byte & 0x3f) as usize == TAG_LEN => State::Length(byte >> 6, 0, 0),
+                State::Tag => try!(self.start(byte >> 6, (byte & 0x3f) as usize + 1)),
+                State::Length(_, _, count) if count == MAX_EXTRA => return corrupt("overly long rle run"),
+                State::Length(kind, n, count) => {
+                    let n = n | ((byte & 0x7f) as usize) << (count * 7);
+                    if byte & 0x80 != 0 {
+                        State::Length(kind, n, count + 1)
+                    } else {
+                        try!(self.start(kind, n + TAG_LEN + 1))
+                    }
+                },
+                State::Byte(len) => State::Run(byte, len),
+                State::Literal(_) | State::Run(..) | State::Above(_) => unreachable!(),
+            };
+        }
+        Ok(pos)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.state {
+            State::Tag | State::Run(..) | State::Above(_) => Ok(()),
+            State::Literal(_) => truncated("truncated rle literals"),
+            State::Length(..) | State::Byte(_) => truncated("truncated rle run"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use super::super::{decode_block, encode_block, Decoder, Encoder, Scheme};
+
+    fn roundtrip(input: &[u8], stride: usize) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_block(input, Scheme::Rows(stride), &mut buf);
+        let mut decoded = Vec::new();
+        decode_block(&buf, Scheme::Rows(stride), &mut decoded, input.len()).unwrap();
+        assert_eq!(&decoded[..], input);
+        buf
+    }
+
+    /// Returns a screen of rows of `width` bytes, with a few windows
+    fn screen(width: usize, height: usize) -> Vec<u8> {
+        let mut data = vec![0x20; width * height];
+        for (i, &(x, y, w, h)) in [(10, 5, 50, 40), (30, 20, 60, 70), (5, 80, 90, 15)].iter().enumerate() {
+            for row in y..y + h {
+                for col in x..x + w {
+                    // a title bar, then lines of text, each of 8 rows
+                    data[row * width + col] = if row < y + 3 { 0x80 + i as u8 } else { (col * 7 + row / 8 * 3) as u8 };
+                }
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn known_data() {
+        assert_eq!(roundtrip(b"", 4), b"");
+        assert_eq!(roundtrip(b"abcdabcd", 4), b"\x03abcd\x83");
+        assert_eq!(roundtrip(b"abaaaaac", 4), b"\x01ab\x44a\x00c");
+        // the first row is not above its start
+        assert_eq!(roundtrip(b"aaaaaaaa", 4), b"\x47a");
+        assert_eq!(roundtrip(&vec![1; 100], 1), b"\x7f\x24\x01");
+    }
+
+    #[test]
+    fn screens() {
+        let input = screen(100, 100);
+        let mut bytes = Vec::new();
+        encode_block(&input, Scheme::Doubled, &mut bytes);
+        let rows = roundtrip(&input, 100);
+        assert!(rows.len() * 5 < bytes.len());
+        for &stride in [1, 3, 99, 1000, 20000].iter() {
+            roundtrip(&input, stride);
+        }
+        let input = screen(1000, 300);
+        assert!(roundtrip(&input, 1000).len() * 100 < input.len());
+    }
+
+    #[test]
+    fn streams() {
+        let input = screen(400, 300);
+        let mut expected = Vec::new();
+        encode_block(&input, Scheme::Rows(400), &mut expected);
+        for &step in [1, 500, 70001].iter() {
+            let mut encoder = Encoder::with_scheme(Vec::new(), Scheme::Rows(400));
+            for chunk in input.chunks(step) {
+                encoder.write_all(chunk).unwrap();
+            }
+            let (buf, _) = encoder.finish();
+            assert!(buf == expected);
+            let mut decoder = Decoder::with_scheme(io::BufReader::with_capacity(step, &buf[..]), Scheme::Rows(400));
+            let mut decoded = Vec::new();
+            decoder.read_to_end(&mut decoded).unwrap();
+            assert!(decoded == input);
+        }
+    }
+
+    #[test]
+    fn invalid_data() {
+        let mut decoded = Vec::new();
+        assert!(decode_block(b"\x01ab\x83", Scheme::Rows(4), &mut decoded, 10).is_err());
+        assert!(decode_block(b"\xc0", Scheme::Rows(4), &mut decoded, 10).is_err());
+        assert!(decode_block(b"\x01a", Scheme::Rows(4), &mut decoded, 10).is_err());
+        assert!(decode_block(b"\x7f\xff\xff\xff\x01a", Scheme::Rows(4), &mut decoded, 10).is_err());
+        assert!(decode_block(b"\x41", Scheme::Rows(4), &mut decoded, 10).is_err());
+    }
+}
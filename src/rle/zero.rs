@@ -0,0 +1,184 @@
+//! The `Zero` scheme: a run of 1 to 256 zeros is coded by a zero followed by
+//! the length of the run minus one, while the other bytes are as is, so that
+//! they are copied by whole spans.
+
+use std::cmp;
+use std::io;
+use std::vec::Vec;
+
+use error::truncated;
+
+/// Longest run coded at once
+const MAX_RUN: usize = 256;
+
+pub struct Encoder {
+    zeros: usize,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            zeros: 0,
+        }
+    }
+
+    fn push_run(&mut self, out: &mut Vec<u8>) {
+        if self.zeros > 0 {
+            out.push(0);
+            out.push((self.zeros - 1) as u8);
+            self.zeros = 0;
+        }
+    }
+}
+
+impl super::Encode for Encoder {
+    fn reset(&mut self) {
+        self.zeros = 0;
+    }
+
+    fn encode(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        let mut pos = 0;
+        while pos < input.len() {
+            if input[pos] == 0 {
+                self.zeros += 1;
+                pos += 1;
+                if self.zeros == MAX_RUN {
+                    self.push_run(out);
+                }
+                continue
+            }
+            self.push_run(out);
+            let end = input[pos..].iter().position(|&b| b == 0).map_or(input.len(), |n| pos + n);
+            out.extend_from_slice(&input[pos..end]);
+            pos = end;
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        self.push_run(out);
+    }
+}
+
+enum State {
+    Clean,
+    /// Got a zero, waiting for the length of its run
+    Zero,
+    Run(usize),
+}
+
+pub struct Decoder {
+    state: State,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            state: State::Clean,
+        }
+    }
+}
+
+impl super::Decode for Decoder {
+    fn reset(&mut self) {
+        self.state = State::Clean;
+    }
+
+    fn decode(&mut self, input: &[u8], out: &mut Vec<u8>, limit: usize) -> io::Result<usize> {
+        let end = out.len() + limit;
+        let mut pos = 0;
+        while out.len() < end {
+            match self.state {
+                State::Run(zeros) => {
+                    let n = cmp::min(zeros, end - out.len());
+                    out.extend((0..n).map(|_| 0));
+                    self.state = if n == zeros { State::Clean } else { State::Run(zeros - n) };
+                },
+                _ if pos == input.len() => break,
+                State::Zero => {
+                    self.state = State::Run(input[pos] as usize + 1);
+                    pos += 1;
+                },
+                State::Clean if input[pos] == 0 => {
+                    self.state = State::Zero;
+                    pos += 1;
+                },
+                State::Clean => {
+                    let span = cmp::min(input.len(), pos + end - out.len());
+                    let stop = input[pos..span].iter().position(|&b| b == 0).map_or(span, |n| pos + n);
+                    out.extend_from_slice(&input[pos..stop]);
+                    pos = stop;
+                },
+            }
+        }
+        Ok(pos)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.state {
+            State::Zero => truncated("truncated rle run"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use super::super::{decode_block, encode_block, Decoder, Encoder, Scheme};
+    #[cfg(feature="bwt")]
+    use super::super::super::bwt::{encode_simple, mtf};
+
+    fn roundtrip(input: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_block(input, Scheme::Zero, &mut buf);
+        let mut decoded = Vec::new();
+        decode_block(&buf, Scheme::Zero, &mut decoded, input.len()).unwrap();
+        assert_eq!(&decoded[..], input);
+        buf
+    }
+
+    #[test]
+    fn known_data() {
+        assert_eq!(roundtrip(b""), b"");
+        assert_eq!(roundtrip(b"aaab"), b"aaab");
+        assert_eq!(roundtrip(b"a\0b\0\0\0"), b"a\0\0b\0\x02");
+        assert_eq!(roundtrip(&[0; 300]), [0, 255, 0, 43]);
+    }
+
+    #[cfg(feature="bwt")]
+    #[test]
+    fn after_mtf() {
+        let (bwt, _) = encode_simple(include_bytes!("../data/test.txt"));
+        let mut e = mtf::Encoder::new(Vec::new());
+        e.write_all(&bwt).unwrap();
+        let ranks = e.finish();
+        let buf = roundtrip(&ranks);
+        assert!(buf.len() * 20 < ranks.len() * 19);
+    }
+
+    #[test]
+    fn streams() {
+        let input: Vec<u8> = (0..20000).map(|i| if i % 300 < 150 { 0 } else { (i * 7 / 3) as u8 }).collect();
+        let mut expected = Vec::new();
+        encode_block(&input, Scheme::Zero, &mut expected);
+        for &step in [1, 3, 5000].iter() {
+            let mut encoder = Encoder::with_scheme(Vec::new(), Scheme::Zero);
+            for chunk in input.chunks(step) {
+                encoder.write_all(chunk).unwrap();
+            }
+            let (buf, _) = encoder.finish();
+            assert!(buf == expected);
+            let mut decoder = Decoder::with_scheme(io::BufReader::with_capacity(step, &buf[..]), Scheme::Zero);
+            let mut decoded = Vec::new();
+            decoder.read_to_end(&mut decoded).unwrap();
+            assert!(decoded == input);
+        }
+    }
+
+    #[test]
+    fn invalid_data() {
+        let mut decoded = Vec::new();
+        assert!(decode_block(b"ab\0", Scheme::Zero, &mut decoded, 10).is_err());
+        assert!(decode_block(b"ab\0\x01", Scheme::Zero, &mut decoded, 3).is_err());
+    }
+}
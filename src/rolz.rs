@@ -0,0 +1,557 @@
+/*!
+
+ROLZ Decompression and Compression. Requires `rolz` feature, enabled by default
+
+This module contains a reduced offset LZ77 codec, which sits between LZ4 and
+LZMA for speed and ratio. The matches are not looked for at any distance, but
+only at the last 64 positions following the same byte as the current one, so
+that a match is told by its rank among them, in 6 bits, instead of its
+distance. The encoder and the decoder both keep these tables of positions, as
+the data is known to both. The literals, coded within the context of the
+previous byte, the flags telling them from the matches, the ranks and the
+lengths of the matches are all coded with the adaptive binary models of the
+arithmetic coder of the crate.
+
+The blocks of `decode_block` and `encode_block` hold no header, their size
+being needed to decode them. The streams are cut into blocks of 1 MB at most,
+each preceded by its size and the size of its data, as two 32-bit
+little-endian integers.
+
+# Example
+
+```rust
+use std::io::{Read, Write};
+use compress::rolz;
+
+let text = b"the rain in spain stays mainly in the plain, the rain in spain";
+let mut e = rolz::Encoder::new(Vec::new());
+e.write_all(text).unwrap();
+let (encoded, result) = e.finish();
+result.unwrap();
+
+let mut d = rolz::Decoder::new(&encoded[..]);
+let mut decoded = Vec::new();
+d.read_to_end(&mut decoded).unwrap();
+assert_eq!(&decoded[..], &text[..]);
+```
+
+# Related links
+
+* https://en.wikipedia.org/wiki/Reduced_offset_Lempel%E2%80%93Ziv - the
+  principle of the reduced offsets
+* http://mattmahoney.net/dc/dce.html#Section_525 - a description of ROLZ and
+  of its coders
+
+*/
+
+use std::cmp;
+use std::mem;
+use std::io::{self, Read, Write};
+
+use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use super::entropy::ari::{self, bin, Border};
+use contexts::Tables;
+use error::{corrupt, truncated};
+use hash;
+use process::{drain, gather, Process, Reader, Status};
+
+/// Largest block of the streams
+pub const BLOCK_SIZE: usize = 1 << 20;
+
+const SLOT_BITS: usize = 6;
+/// Number of positions remembered for each context
+const SLOTS: usize = 1 << SLOT_BITS;
+const MIN_MATCH: usize = 3;
+const LEN_BITS: usize = 8;
+const MAX_MATCH: usize = MIN_MATCH + (1 << LEN_BITS) - 1;
+/// Total of the probabilities of the binary models
+const MODEL_TOTAL: Border = 1 << 12;
+const MODEL_RATE: Border = 4;
+
+fn models(count: usize) -> Vec<bin::Model> {
+    (0..count).map(|_| bin::Model::new_flat(MODEL_TOTAL, MODEL_RATE)).collect()
+}
+
+/// The state shared by the encoder and the decoder of a block
+struct Context {
+    /// The last positions following each byte, 0 for none, as a ring
+    positions: Vec<u32>,
+    heads: [u8; 256],
+    /// Whether the last item was a match, and the flags of each state
+    matched: bool,
+    flags: Vec<bin::Model>,
+    /// Trees of bits, for the literals of each previous byte, the slots and
+    /// the lengths
+    literals: Tables<bin::Model>,
+    slots: Vec<bin::Model>,
+    lengths: Vec<bin::Model>,
+}
+
+impl Context {
+    fn new() -> Context {
+        Context {
+            positions: vec![0; 256 * SLOTS],
+            heads: [0; 256],
+            matched: false,
+            flags: models(2),
+            literals: Tables::new(256, 1 << 8, bin::Model::new_flat(MODEL_TOTAL, MODEL_RATE)),
+            slots: models(SLOTS),
+            lengths: models(1 << LEN_BITS),
+        }
+    }
+
+    /// Returns the position of the given slot, the most recent first, after
+    /// the byte `prev`
+    fn position(&self, prev: u8, slot: usize) -> usize {
+        let head = self.heads[prev as usize] as usize;
+        self.positions[(prev as usize) * SLOTS + (head.wrapping_sub(slot) & (SLOTS - 1))] as usize
+    }
+
+    /// Remembers the position `pos`, which follows the byte `prev`
+    fn insert(&mut self, prev: u8, pos: usize) {
+        let head = (self.heads[prev as usize] as usize + 1) & (SLOTS - 1);
+        self.heads[prev as usize] = head as u8;
+        self.positions[(prev as usize) * SLOTS + head] = pos as u32;
+    }
+}
+
+fn encode_tree<W: Write>(e: &mut ari::Encoder<W>, models: &mut [bin::Model],
+                         bits: usize, value: usize) -> io::Result<()> {
+    let mut node = 1;
+    for i in (0..bits).rev() {
+        let bit = (value >> i) & 1 != 0;
+        try!(e.encode(bit, &models[node]));
+        models[node].update(bit);
+        node = (node << 1) | bit as usize;
+    }
+    Ok(())
+}
+
+fn decode_tree<R: Read>(d: &mut ari::Decoder<R>, models: &mut [bin::Model],
+                        bits: usize) -> io::Result<usize> {
+    let mut node = 1;
+    for _ in 0..bits {
+        let bit = try!(d.decode(&models[node]));
+        models[node].update(bit);
+        node = (node << 1) | bit as usize;
+    }
+    Ok(node - (1 << bits))
+}
+
+/// Finds the longest match of the data at `pos` among the positions
+/// remembered, returning its slot and its length
+fn find_match(ctx: &Context, data: &[u8], pos: usize) -> (usize, usize) {
+    let limit = cmp::min(MAX_MATCH, data.len() - pos);
+    let mut best = (0, 0);
+    if limit < MIN_MATCH {
+        return best
+    }
+    let prev = data[pos - 1];
+    for slot in 0..SLOTS {
+        let cand = ctx.position(prev, slot);
+        if cand == 0 {
+            break
+        }
+        // the byte beyond the best length is the most likely to differ
+        if data[cand + best.1] != data[pos + best.1] {
+            continue
+        }
+        let len = hash::match_len(&data[cand..(cand + limit)], &data[pos..(pos + limit)]);
+        if len > best.1 {
+            best = (slot, len);
+            if len == limit {
+                break
+            }
+        }
+    }
+    best
+}
+
+/// Encodes a whole block of data into `output`
+pub fn encode_block(input: &[u8], output: &mut Vec<u8>) {
+    assert!(input.len() < u32::max_value() as usize, "rolz block too large");
+    let mut ctx = Context::new();
+    let mut e = ari::Encoder::new(output);
+    let mut pos = 0;
+    // writing to a vector does not fail
+    while pos < input.len() {
+        let (slot, len) = if pos > 0 { find_match(&ctx, input, pos) } else { (0, 0) };
+        let prev = if pos > 0 { input[pos - 1] } else { 0 };
+        let matched = len >= MIN_MATCH;
+        e.encode(matched, &ctx.flags[ctx.matched as usize]).unwrap();
+        ctx.flags[ctx.matched as usize].update(matched);
+        ctx.matched = matched;
+        let end = if matched {
+            encode_tree(&mut e, &mut ctx.slots, SLOT_BITS, slot).unwrap();
+            encode_tree(&mut e, &mut ctx.lengths, LEN_BITS, len - MIN_MATCH).unwrap();
+            pos + len
+        } else {
+            let models = ctx.literals.get(prev as usize);
+            encode_tree(&mut e, models, 8, input[pos] as usize).unwrap();
+            pos + 1
+        };
+        for i in cmp::max(pos, 1)..end {
+            ctx.insert(input[i - 1], i);
+        }
+        pos = end;
+    }
+    e.finish().1.unwrap();
+}
+
+/// Decodes a block holding `len` bytes of data, appending them to `output`
+pub fn decode_block(input: &[u8], len: usize, output: &mut Vec<u8>) -> io::Result<()> {
+    let base = output.len();
+    output.reserve(len);
+    let mut ctx = Context::new();
+    let mut d = ari::Decoder::new(input);
+    let mut pos = 0;
+    while pos < len {
+        let prev = if pos > 0 { output[base + pos - 1] } else { 0 };
+        let matched = try!(d.decode(&ctx.flags[ctx.matched as usize]));
+        ctx.flags[ctx.matched as usize].update(matched);
+        ctx.matched = matched;
+        let end = if matched {
+            let slot = try!(decode_tree(&mut d, &mut ctx.slots, SLOT_BITS));
+            let n = try!(decode_tree(&mut d, &mut ctx.lengths, LEN_BITS)) + MIN_MATCH;
+            let cand = if pos > 0 { ctx.position(prev, slot) } else { 0 };
+            if cand == 0 {
+                return corrupt("invalid rolz match")
+            }
+            if n > len - pos {
+                return corrupt("rolz data exceeds the block")
+            }
+            for i in 0..n {
+                let byte = output[base + cand + i];
+                output.push(byte);
+            }
+            pos + n
+        } else {
+            let models = ctx.literals.get(prev as usize);
+            output.push(try!(decode_tree(&mut d, models, 8)) as u8);
+            pos + 1
+        };
+        for i in cmp::max(pos, 1)..end {
+            ctx.insert(output[base + i - 1], i);
+        }
+        pos = end;
+    }
+    d.finish().1
+}
+
+/// This structure is used to compress a stream of bytes using the ROLZ
+/// codec. This is a wrapper around an internal writer which bytes will be
+/// written to.
+pub struct Encoder<W> {
+    w: W,
+    buf: Vec<u8>,
+    packed: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will have its output written to the given
+    /// output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder {
+            w: w,
+            buf: Vec::with_capacity(BLOCK_SIZE),
+            packed: Vec::new(),
+        }
+    }
+
+    fn encode_block(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(())
+        }
+        self.packed.truncate(0);
+        encode_block(&self.buf, &mut self.packed);
+        try!(self.w.write_u32::<LittleEndian>(self.buf.len() as u32));
+        try!(self.w.write_u32::<LittleEndian>(self.packed.len() as u32));
+        try!(self.w.write_all(&self.packed));
+        self.buf.truncate(0);
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.flush();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`,
+    /// keeping the buffers. The previous writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.flush();
+        self.buf.truncate(0);
+        (mem::replace(&mut self.w, w), result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let amt = cmp::min(BLOCK_SIZE - self.buf.len(), rest.len());
+            self.buf.extend(rest[..amt].iter().cloned());
+            rest = &rest[amt..];
+            if self.buf.len() == BLOCK_SIZE {
+                try!(self.encode_block());
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.encode_block());
+        self.w.flush()
+    }
+}
+
+/// The part of the stream a `Processor` expects next
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// The sizes of the next block
+    Header,
+    /// The packed data of a block of this size
+    Block(usize),
+}
+
+/// This structure is used to decode a stream of ROLZ blocks, fed with slices
+/// of it. The stream ends with its input.
+pub struct Processor {
+    state: State,
+    head: Vec<u8>,
+    packed: Vec<u8>,
+    output: Vec<u8>,
+    start: usize,
+    eof: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder, waiting for the first block
+    pub fn new() -> Processor {
+        Processor {
+            state: State::Header,
+            head: Vec::new(),
+            packed: Vec::new(),
+            output: Vec::new(),
+            start: 0,
+            eof: false,
+        }
+    }
+
+    /// Starts decoding a new stream, keeping the buffers
+    pub fn reset(&mut self) {
+        self.state = State::Header;
+        self.head.truncate(0);
+        self.packed.truncate(0);
+        self.output.truncate(0);
+        self.start = 0;
+        self.eof = false;
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.output, &mut self.start, output, &mut produced) {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            let gathered = match self.state {
+                State::Header => gather(&mut self.head, input, &mut pos, 8),
+                State::Block(_) => {
+                    let packed_len = (&self.head[4..]).read_u32::<LittleEndian>().unwrap() as usize;
+                    gather(&mut self.packed, input, &mut pos, packed_len)
+                }
+            };
+            if !gathered {
+                let status = if self.eof { Status::Done } else { Status::NeedInput };
+                return Ok((pos, produced, status))
+            }
+
+            self.state = match self.state {
+                State::Header => {
+                    let len = (&self.head[..]).read_u32::<LittleEndian>().unwrap() as usize;
+                    let packed_len = (&self.head[4..]).read_u32::<LittleEndian>().unwrap() as usize;
+                    // no block is twice larger than its data, when coded
+                    if len > BLOCK_SIZE || packed_len > 2 * BLOCK_SIZE {
+                        return corrupt("invalid rolz block")
+                    }
+                    State::Block(len)
+                }
+                State::Block(len) => {
+                    self.output.truncate(0);
+                    self.start = 0;
+                    try!(decode_block(&self.packed, len, &mut self.output));
+                    self.head.truncate(0);
+                    self.packed.truncate(0);
+                    State::Header
+                }
+            };
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the stream ends in between the blocks
+        if self.state != State::Header || !self.head.is_empty() {
+            return truncated("truncated rolz stream")
+        }
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a stream of ROLZ blocks, which ends with
+/// the underlying stream. The wrapped stream can be re-acquired through the
+/// unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new()),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Returns whether the end of the data has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.is_done()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing::{noise, push, Lcg};
+    use super::{decode_block, encode_block, Decoder, Encoder, Processor, BLOCK_SIZE};
+
+    fn roundtrip(input: &[u8]) -> usize {
+        let mut encoded = Vec::new();
+        encode_block(input, &mut encoded);
+        let mut decoded = b"prefix".to_vec();
+        decode_block(&encoded, input.len(), &mut decoded).unwrap();
+        assert!(&decoded[6..] == input);
+        encoded.len()
+    }
+
+    #[test]
+    fn roundtrips() {
+        assert_eq!(roundtrip(b""), 4);
+        roundtrip(b"a");
+        roundtrip(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+        let text = include_bytes!("data/test.txt");
+        assert!(roundtrip(text) < text.len() * 2 / 3);
+        let large = include_bytes!("data/test.large");
+        let large = &large[..1 << 20];
+        assert!(roundtrip(large) < large.len() * 11 / 20);
+        let noise = noise(100000, 7);
+        assert!(roundtrip(&noise) < noise.len() + noise.len() / 40);
+        // runs longer than the longest match
+        let runs: Vec<u8> = (0..100000).map(|i| (i / 1000) as u8).collect();
+        assert!(roundtrip(&runs) < 1000);
+    }
+
+    #[test]
+    fn streams() {
+        let mut input = include_bytes!("data/test.large").to_vec();
+        input.truncate(BLOCK_SIZE + 12345);
+        let mut e = Encoder::new(Vec::new());
+        for chunk in input.chunks(100000) {
+            e.write_all(chunk).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let mut d = Decoder::new(BufReader::new(&encoded[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(out == input);
+        assert!(push(&mut Processor::new(), &encoded, 10000).unwrap() == input);
+    }
+
+    #[test]
+    fn reset() {
+        let (first, second) = (include_bytes!("data/test.txt"), noise(20000, 5));
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(first).unwrap();
+        let (a, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(&second).unwrap();
+        let (b, result) = e.finish();
+        result.unwrap();
+        let mut fresh = Encoder::new(Vec::new());
+        fresh.write_all(&second).unwrap();
+        assert!(b == fresh.finish().0);
+
+        let mut d = Decoder::new(BufReader::new(&a[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(&out[..] == &first[..]);
+        d.reset(BufReader::new(&b[..]));
+        out.truncate(0);
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof() && out == second);
+    }
+
+    #[test]
+    fn invalid_data() {
+        let text = include_bytes!("data/test.txt");
+        let mut encoded = Vec::new();
+        encode_block(text, &mut encoded);
+        // truncated blocks, and data longer than the block
+        for len in 0..encoded.len() {
+            assert!(decode_block(&encoded[..len], text.len(), &mut Vec::new()).is_err());
+        }
+        let mut lcg = Lcg(1);
+        for _ in 0..1000 {
+            let mut data = encoded.clone();
+            let seed = lcg.step();
+            let at = (seed >> 8) as usize % data.len();
+            data[at] ^= 1 << (seed >> 29);
+            // no panic, but the garbage may decode
+            let _ = decode_block(&data, text.len(), &mut Vec::new());
+        }
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(text).unwrap();
+        let (mut stream, _) = e.finish();
+        for len in 1..12 {
+            let mut d = Decoder::new(&stream[..len]);
+            assert!(d.read_to_end(&mut Vec::new()).is_err());
+        }
+        stream[3] = 1;
+        let mut d = Decoder::new(&stream[..]);
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,664 @@
+/*!
+
+Seekable compressed format. Requires `seekable` feature
+
+This module contains a framing of the data in blocks compressed apart, with
+any of the codecs of the `codec` module, followed by an index of the blocks:
+a block may then be found from its position in the decompressed data and
+decompressed alone, without decompressing what precedes it.
+
+The stream starts with a magic number, followed by the compressed blocks.
+The index lists, for each block, its offset in the stream and in the
+decompressed data, as little-endian 64-bit integers. It ends with the size
+of the decompressed data, the number of blocks and the magic number again,
+so that it is read from the end of the stream.
+
+`Reader` seeks within the decompressed data of such a stream, decompressing
+the block holding the position on the next read. An index of the members of
+a BGZF file, the gzip variant of which each member records its size, is also
+built by `Index::read_bgzf`, for `Reader` to seek within it as well.
+
+# Example
+
+```rust
+use std::io::{Cursor, Write};
+use compress::codec;
+use compress::seekable::{self, Encoder, Index};
+
+let data: Vec<u8> = (0..100000).map(|i| (i / 100) as u8).collect();
+let mut e = Encoder::new(Vec::new(), codec::Lz4, 4096);
+e.write_all(&data).unwrap();
+let (encoded, result) = e.finish();
+result.unwrap();
+
+let mut r = Cursor::new(encoded);
+let index = Index::read(&mut r).unwrap();
+assert_eq!(index.size(), data.len() as u64);
+let block = *index.find(50000).unwrap();
+let decoded = seekable::read_block(&mut r, &block, &codec::Lz4).unwrap();
+let start = block.offset as usize;
+assert!(&decoded[..] == &data[start..(start + decoded.len())]);
+```
+
+*/
+
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+
+use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use codec::{Compressor, Decompressor};
+use error::{corrupt, truncated};
+use OutputLimit;
+use Skip;
+
+/// The header of the members of BGZF: gzip with an extra field
+const BGZF_HEADER: [u8; 4] = [0x1f, 0x8b, 8, 4];
+
+const MAGIC: [u8; 4] = *b"\x89CSK";
+
+/// Size of an entry of the index
+const ENTRY_SIZE: u64 = 16;
+/// Size of the end of the index: the decompressed size, the number of blocks
+/// and the magic number
+const FOOTER_SIZE: u64 = 8 + 8 + 4;
+
+/// A block of the stream, as listed by the index
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Block {
+    /// Offset of the block in the decompressed data
+    pub offset: u64,
+    /// Size of the decompressed block
+    pub size: u64,
+    /// Offset of the compressed block in the stream
+    pub compressed_offset: u64,
+    /// Size of the compressed block
+    pub compressed_size: u64,
+}
+
+/// The index of the blocks of a stream
+#[derive(Clone, Debug)]
+pub struct Index {
+    blocks: Vec<Block>,
+    size: u64,
+}
+
+impl Index {
+    /// Reads the index at the end of the stream of `r`
+    pub fn read<R: Read + Seek>(r: &mut R) -> io::Result<Index> {
+        let len = try!(r.seek(SeekFrom::End(0)));
+        if len < MAGIC.len() as u64 + FOOTER_SIZE {
+            return truncated("truncated seekable stream")
+        }
+        try!(r.seek(SeekFrom::Start(len - FOOTER_SIZE)));
+        let size = try!(r.read_u64::<LittleEndian>());
+        let count = try!(r.read_u64::<LittleEndian>());
+        let mut magic = [0u8; 4];
+        try!(r.read_exact(&mut magic));
+        if magic != MAGIC {
+            return corrupt("invalid seekable stream magic")
+        }
+        let data_len = len - MAGIC.len() as u64 - FOOTER_SIZE;
+        // the data of a stream without blocks is empty
+        if count > data_len / ENTRY_SIZE || (count == 0 && size != 0) {
+            return corrupt("invalid seekable index size")
+        }
+        let end = len - FOOTER_SIZE - count * ENTRY_SIZE;
+        try!(r.seek(SeekFrom::Start(end)));
+
+        let mut blocks: Vec<Block> = Vec::with_capacity(count as usize);
+        let mut last = (MAGIC.len() as u64, 0);
+        for i in 0..count {
+            let compressed_offset = try!(r.read_u64::<LittleEndian>());
+            let offset = try!(r.read_u64::<LittleEndian>());
+            // the first block starts right after the magic number
+            if compressed_offset < last.0 || offset < last.1 ||
+                (i == 0 && (compressed_offset, offset) != last) {
+                return corrupt("invalid seekable index entry")
+            }
+            if let Some(prev) = blocks.last_mut() {
+                prev.compressed_size = compressed_offset - prev.compressed_offset;
+                prev.size = offset - prev.offset;
+            }
+            blocks.push(Block {
+                offset: offset,
+                size: 0,
+                compressed_offset: compressed_offset,
+                compressed_size: 0,
+            });
+            last = (compressed_offset, offset);
+        }
+        if last.0 > end || last.1 > size {
+            return corrupt("invalid seekable index entry")
+        }
+        if let Some(prev) = blocks.last_mut() {
+            prev.compressed_size = end - prev.compressed_offset;
+            prev.size = size - prev.offset;
+        }
+        Ok(Index { blocks: blocks, size: size })
+    }
+
+    /// Builds the index of the members of the BGZF file of `r`, from their
+    /// headers and trailers only. The blocks are decompressed by the gzip
+    /// codec.
+    pub fn read_bgzf<R: Read + Seek>(r: &mut R) -> io::Result<Index> {
+        let len = try!(r.seek(SeekFrom::End(0)));
+        let mut pos = 0;
+        let mut blocks = Vec::new();
+        let mut size = 0;
+        while pos < len {
+            try!(r.seek(SeekFrom::Start(pos)));
+            let mut header = [0u8; 12];
+            try!(r.read_exact(&mut header));
+            if header[..4] != BGZF_HEADER {
+                return corrupt("invalid bgzf member header")
+            }
+            // the extra field holds the subfield BC of the member size
+            let mut xlen = header[10] as usize | (header[11] as usize) << 8;
+            let mut member_size = None;
+            while xlen >= 4 {
+                let mut sub = [0u8; 4];
+                try!(r.read_exact(&mut sub));
+                let slen = sub[2] as usize | (sub[3] as usize) << 8;
+                if slen + 4 > xlen {
+                    break
+                }
+                if &sub[..2] == b"BC" && slen == 2 {
+                    member_size = Some(try!(r.read_u16::<LittleEndian>()) as u64 + 1);
+                } else {
+                    try!(r.seek(SeekFrom::Current(slen as i64)));
+                }
+                xlen -= slen + 4;
+            }
+            let member_size = match member_size {
+                Some(n) if pos + n <= len && n >= 12 + 8 => n,
+                _ => return corrupt("invalid bgzf member size"),
+            };
+            try!(r.seek(SeekFrom::Start(pos + member_size - 4)));
+            let block_size = try!(r.read_u32::<LittleEndian>()) as u64;
+            blocks.push(Block {
+                offset: size,
+                size: block_size,
+                compressed_offset: pos,
+                compressed_size: member_size,
+            });
+            pos += member_size;
+            size += block_size;
+        }
+        Ok(Index { blocks: blocks, size: size })
+    }
+
+    /// Returns the blocks, in the order of the stream
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Returns the size of the decompressed data
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the block holding the byte at `offset` in the decompressed
+    /// data, if it isn't past the end
+    pub fn find(&self, offset: u64) -> Option<&Block> {
+        if offset >= self.size {
+            return None
+        }
+        let i = match self.blocks.binary_search_by(|b| b.offset.cmp(&offset)) {
+            Ok(i) => i,
+            Err(i) => match i.checked_sub(1) {
+                Some(i) => i,
+                None => return None,
+            },
+        };
+        // the empty blocks share their offset with the next one
+        self.blocks[i..].iter().find(|b| b.size > 0)
+    }
+}
+
+/// Reads the block of the stream of `r` and decompresses it with `d`
+pub fn read_block<R: Read + Seek>(r: &mut R, block: &Block, d: &dyn Decompressor) -> io::Result<Vec<u8>> {
+    try!(r.seek(SeekFrom::Start(block.compressed_offset)));
+    let mut compressed = Vec::new();
+    try!(r.take(block.compressed_size).read_to_end(&mut compressed));
+    if (compressed.len() as u64) < block.compressed_size {
+        return truncated("truncated seekable block")
+    }
+    let data = try!(d.decompress_to_vec(&compressed));
+    if data.len() as u64 != block.size {
+        return corrupt("seekable block size mismatch")
+    }
+    Ok(data)
+}
+
+/// A reader of the decompressed data of a stream with an index, which seeks
+/// by decompressing only the block holding the position
+pub struct Reader<R, D> {
+    r: R,
+    d: D,
+    index: Index,
+    pos: u64,
+    /// The decompressed block, and its offset in the data
+    block: Vec<u8>,
+    start: u64,
+    limit: OutputLimit,
+}
+
+impl<R: Read + Seek, D: Decompressor> Reader<R, D> {
+    /// Creates a reader of the seekable stream of `r`, of which the blocks
+    /// are decompressed by `d`, reading its index
+    pub fn new(mut r: R, d: D) -> io::Result<Reader<R, D>> {
+        let index = try!(Index::read(&mut r));
+        Ok(Reader::with_index(r, d, index))
+    }
+
+    /// Creates a reader of the stream of `r` of which the blocks are listed by
+    /// `index`, and decompressed by `d`
+    pub fn with_index(r: R, d: D, index: Index) -> Reader<R, D> {
+        Reader {
+            r: r,
+            d: d,
+            index: index,
+            pos: 0,
+            block: Vec::new(),
+            start: 0,
+            limit: OutputLimit::new(),
+        }
+    }
+
+    /// Returns the index of the stream
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once they return more than
+    /// `size` bytes in all, wherever they read from
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.limit.set(size);
+    }
+
+    /// Destroys this reader, returning the underlying reader.
+    pub fn unwrap(self) -> R {
+        self.r
+    }
+}
+
+impl<R: Read + Seek, D: Decompressor> Read for Reader<R, D> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        if self.pos < self.start || self.pos >= self.start + self.block.len() as u64 {
+            let block = match self.index.find(self.pos) {
+                Some(block) => *block,
+                None => return Ok(0),
+            };
+            self.block = try!(read_block(&mut self.r, &block, &self.d));
+            self.start = block.offset;
+        }
+        let at = (self.pos - self.start) as usize;
+        let amt = cmp::min(self.limit.clamp(dst.len()), self.block.len() - at);
+        dst[..amt].copy_from_slice(&self.block[at..(at + amt)]);
+        self.pos += amt as u64;
+        self.limit.count(amt)
+    }
+}
+
+impl<R: Read + Seek, D: Decompressor> Seek for Reader<R, D> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n)
+            }
+            SeekFrom::End(n) => (self.index.size, n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        let pos = if delta >= 0 {
+            base.checked_add(delta as u64)
+        } else {
+            base.checked_sub(delta.wrapping_neg() as u64)
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+        }
+    }
+}
+
+impl<R: Read + Seek, D: Decompressor> Skip for Reader<R, D> {
+    /// Moves past the skipped data with the index, decompressing nothing
+    fn skip(&mut self, n: u64) -> io::Result<u64> {
+        let n = cmp::min(n, self.index.size.saturating_sub(self.pos));
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// This structure is used to compress data into blocks followed by their
+/// index. This is a wrapper around an internal writer which bytes will be
+/// written to.
+pub struct Encoder<W, C> {
+    w: W,
+    compressor: C,
+    block_size: usize,
+    buf: Vec<u8>,
+    tmp: Vec<u8>,
+    /// The offsets of the blocks, in the stream and in the data
+    index: Vec<(u64, u64)>,
+    pos: u64,
+    size: u64,
+}
+
+impl<W: Write, C: Compressor> Encoder<W, C> {
+    /// Creates a new encoder compressing each block of `block_size` bytes of
+    /// the data with `compressor`, and having its output written to the given
+    /// output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(w: W, compressor: C, block_size: usize) -> Encoder<W, C> {
+        assert!(block_size > 0, "invalid seekable block size");
+        Encoder {
+            w: w,
+            compressor: compressor,
+            block_size: block_size,
+            buf: Vec::with_capacity(block_size),
+            tmp: Vec::new(),
+            index: Vec::new(),
+            pos: 0,
+            size: 0,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.pos == 0 {
+            try!(self.w.write_all(&MAGIC));
+            self.pos = MAGIC.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn encode_block(&mut self) -> io::Result<()> {
+        try!(self.write_header());
+        self.tmp.truncate(0);
+        try!(self.compressor.compress(&self.buf, &mut self.tmp));
+        try!(self.w.write_all(&self.tmp));
+        self.index.push((self.pos, self.size));
+        self.pos += self.tmp.len() as u64;
+        self.size += self.buf.len() as u64;
+        self.buf.truncate(0);
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The last block and the index are written, and then the wrapped
+    /// writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.end();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`
+    /// with the same compressor and block size, keeping the buffers. The
+    /// previous writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.end();
+        self.buf.truncate(0);
+        self.index.truncate(0);
+        self.pos = 0;
+        self.size = 0;
+        (mem::replace(&mut self.w, w), result)
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            try!(self.encode_block());
+        }
+        try!(self.write_header());
+        for &(pos, offset) in self.index.iter() {
+            try!(self.w.write_u64::<LittleEndian>(pos));
+            try!(self.w.write_u64::<LittleEndian>(offset));
+        }
+        try!(self.w.write_u64::<LittleEndian>(self.size));
+        try!(self.w.write_u64::<LittleEndian>(self.index.len() as u64));
+        try!(self.w.write_all(&MAGIC));
+        self.w.flush()
+    }
+}
+
+impl<W: Write, C: Compressor> Write for Encoder<W, C> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len();
+        while !buf.is_empty() {
+            let amt = cmp::min(self.block_size - self.buf.len(), buf.len());
+            self.buf.extend_from_slice(&buf[..amt]);
+            if self.buf.len() == self.block_size {
+                try!(self.encode_block());
+            }
+            buf = &buf[amt..];
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            try!(self.encode_block());
+        }
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+    use codec::{Compressor, Decompressor};
+    use error::Error;
+    use Skip;
+    use super::{read_block, Encoder, Index, Reader};
+
+    /// A codec copying the data, with the length of the block in front
+    struct Stored;
+
+    impl Compressor for Stored {
+        fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+            let mut data = Vec::new();
+            try!(input.read_to_end(&mut data));
+            try!(output.write_all(&[data.len() as u8]));
+            try!(output.write_all(&data));
+            Ok(data.len() as u64)
+        }
+    }
+
+    impl Decompressor for Stored {
+        fn decoder<'a>(&self, mut input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+            let mut len = [0u8];
+            input.read_exact(&mut len).unwrap();
+            Box::new(input.take(len[0] as u64))
+        }
+    }
+
+    fn encode(data: &[u8], block_size: usize, chunk: usize) -> Vec<u8> {
+        let mut e = Encoder::new(Vec::new(), Stored, block_size);
+        for c in data.chunks(chunk) {
+            e.write_all(c).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        encoded
+    }
+
+    #[test]
+    fn random_access() {
+        let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut r = Cursor::new(encode(&data, 100, 33));
+        let index = Index::read(&mut r).unwrap();
+        assert_eq!(index.size(), 1000);
+        assert_eq!(index.blocks().len(), 10);
+        for &offset in [0u64, 99, 100, 555, 999].iter() {
+            let block = *index.find(offset).unwrap();
+            assert!(block.offset <= offset && offset < block.offset + block.size);
+            let decoded = read_block(&mut r, &block, &Stored).unwrap();
+            let start = block.offset as usize;
+            assert!(&decoded[..] == &data[start..(start + 100)]);
+        }
+        assert!(index.find(1000).is_none());
+    }
+
+    #[test]
+    fn flushes() {
+        let mut e = Encoder::new(Vec::new(), Stored, 100);
+        e.write_all(b"abc").unwrap();
+        e.flush().unwrap();
+        e.write_all(b"defg").unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let mut r = Cursor::new(encoded);
+        let index = Index::read(&mut r).unwrap();
+        let sizes: Vec<u64> = index.blocks().iter().map(|b| b.size).collect();
+        assert_eq!(sizes, [3, 4]);
+        let block = *index.find(5).unwrap();
+        assert_eq!(read_block(&mut r, &block, &Stored).unwrap(), b"defg");
+    }
+
+    #[test]
+    fn reset() {
+        let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut e = Encoder::new(Vec::new(), Stored, 100);
+        e.write_all(&data[..555]).unwrap();
+        let (a, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(&data).unwrap();
+        let (b, result) = e.finish();
+        result.unwrap();
+        assert!(a == encode(&data[..555], 100, 555));
+        assert!(b == encode(&data, 100, 1000));
+    }
+
+    #[test]
+    fn empty() {
+        let mut r = Cursor::new(encode(b"", 100, 1));
+        let index = Index::read(&mut r).unwrap();
+        assert_eq!(index.size(), 0);
+        assert!(index.blocks().is_empty());
+        assert!(index.find(0).is_none());
+    }
+
+    #[test]
+    fn seeks() {
+        let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut r = Reader::new(Cursor::new(encode(&data, 100, 100)), Stored).unwrap();
+        let mut buf = [0u8; 150];
+        assert_eq!(r.seek(SeekFrom::Start(950)).unwrap(), 950);
+        assert_eq!(r.read(&mut buf).unwrap(), 50);
+        assert!(&buf[..50] == &data[950..]);
+        assert_eq!(r.read(&mut buf).unwrap(), 0);
+        assert_eq!(r.seek(SeekFrom::Current(-1000)).unwrap(), 0);
+        r.read_exact(&mut buf).unwrap();
+        assert!(&buf[..] == &data[..150]);
+        assert_eq!(r.seek(SeekFrom::End(-345)).unwrap(), 655);
+        let mut rest = Vec::new();
+        r.read_to_end(&mut rest).unwrap();
+        assert!(&rest[..] == &data[655..]);
+        assert!(r.seek(SeekFrom::Current(-1001)).is_err());
+    }
+
+    #[test]
+    fn skips() {
+        let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut r = Reader::new(Cursor::new(encode(&data, 100, 100)), Stored).unwrap();
+        let mut buf = [0u8; 10];
+        assert_eq!(r.skip(555).unwrap(), 555);
+        r.read_exact(&mut buf).unwrap();
+        assert!(&buf[..] == &data[555..565]);
+        assert_eq!(r.skip(1000).unwrap(), 435);
+        assert_eq!(r.read(&mut buf).unwrap(), 0);
+    }
+
+    /// A BGZF member of stored deflate blocks
+    fn bgzf_member(data: &[u8], crc: u32) -> Vec<u8> {
+        let mut member = vec![0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0, 0, 0];
+        let len = data.len() as u16;
+        member.extend_from_slice(&[1, len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        member.extend_from_slice(data);
+        for &n in [crc, data.len() as u32].iter() {
+            member.extend_from_slice(&[n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]);
+        }
+        let size = member.len() - 1;
+        member[16] = size as u8;
+        member[17] = (size >> 8) as u8;
+        member
+    }
+
+    #[test]
+    fn bgzf_index() {
+        let mut file = bgzf_member(b"hello ", 0);
+        file.extend(bgzf_member(b"world", 0));
+        file.extend(bgzf_member(b"", 0));
+        let index = Index::read_bgzf(&mut Cursor::new(&file[..])).unwrap();
+        assert_eq!(index.size(), 11);
+        let sizes: Vec<(u64, u64)> = index.blocks().iter().map(|b| (b.offset, b.size)).collect();
+        assert_eq!(sizes, [(0, 6), (6, 5), (11, 0)]);
+        assert_eq!(index.find(7).unwrap().compressed_offset, 37);
+
+        file[37] = 0;
+        assert!(Index::read_bgzf(&mut Cursor::new(&file[..])).is_err());
+        assert!(Index::read_bgzf(&mut Cursor::new(&file[..40])).is_err());
+    }
+
+    #[test]
+    #[cfg(feature="gzip")]
+    fn bgzf_reader() {
+        use codec::Gzip;
+        use Crc32;
+        let mut file = Vec::new();
+        for chunk in [&b"hello "[..], b"world"].iter() {
+            let mut crc = Crc32::new();
+            crc.feed(chunk);
+            file.extend(bgzf_member(chunk, crc.result()));
+        }
+        let index = Index::read_bgzf(&mut Cursor::new(&file[..])).unwrap();
+        let mut r = Reader::with_index(Cursor::new(file), Gzip { level: 6 }, index);
+        r.seek(SeekFrom::Start(4)).unwrap();
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"o world");
+    }
+
+    #[test]
+    fn invalid_index() {
+        let encoded = encode(b"hello world", 4, 4);
+        assert!(Index::read(&mut Cursor::new(&encoded[..10])).is_err());
+        let mut bad = encoded.clone();
+        let last = bad.len() - 1;
+        bad[last] ^= 1;
+        assert!(Index::read(&mut Cursor::new(bad)).is_err());
+        // a count of blocks larger than the stream
+        let mut bad = encoded.clone();
+        let at = bad.len() - 12;
+        bad[at + 7] = 1;
+        assert!(Index::read(&mut Cursor::new(bad)).is_err());
+        // data without blocks
+        let mut bad = encode(b"", 4, 4);
+        let at = bad.len() - 20;
+        bad[at] = 5;
+        assert!(Index::read(&mut Cursor::new(bad)).is_err());
+    }
+
+    #[test]
+    fn max_output_size() {
+        let data = &include_bytes!("data/test.txt")[..];
+        let encoded = encode(data, 100, 777);
+        let mut r = Reader::new(Cursor::new(&encoded[..]), Stored).unwrap();
+        r.set_max_output_size(data.len() as u64 - 1);
+        let err = r.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(match Error::of(&err) { Some(Error::LimitExceeded { .. }) => true, _ => false });
+
+        // the data read again counts as well
+        let mut r = Reader::new(Cursor::new(&encoded[..]), Stored).unwrap();
+        r.set_max_output_size(data.len() as u64);
+        let mut decoded = Vec::new();
+        r.read_to_end(&mut decoded).unwrap();
+        assert!(&decoded[..] == data);
+        r.seek(SeekFrom::Start(0)).unwrap();
+        assert!(r.read(&mut [0; 10]).is_err());
+    }
+}
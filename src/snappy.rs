@@ -0,0 +1,698 @@
+/*!
+
+Snappy Decompression and Compression. Requires `snappy` feature, enabled by
+default
+
+This module contains an implementation of the snappy format, an LZ77 variant
+with byte-aligned tags close to LZ4, as used by LevelDB, Hadoop and gRPC. Raw
+blocks are handled by `decode_block` and `encode_block`, while streams in the
+framing format, whose chunks are checked with a masked CRC-32C, are exposed
+as standard `Reader` and `Writer` interfaces wrapping an underlying stream.
+
+# Example
+
+```rust,ignore
+use compress::snappy;
+use std::fs::File;
+use std::path::Path;
+use std::io::Read;
+
+let stream = File::open(&Path::new("path/to/file.sz")).unwrap();
+let mut decompressed = Vec::new();
+snappy::Decoder::new(stream).read_to_end(&mut decompressed);
+```
+
+# Related links
+
+* https://github.com/google/snappy/blob/main/format_description.txt - the
+  block format
+* https://github.com/google/snappy/blob/main/framing_format.txt - the framing
+  format
+
+*/
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::iter::repeat;
+use std::mem;
+
+use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use checksum::crc32c;
+use copy::copy_match;
+use error::{checksum_mismatch, corrupt, corrupt_at, truncated};
+use hash;
+use process::{drain, gather, Process, Pump, Status};
+
+const TAG_LITERAL: u8 = 0;
+const TAG_COPY1: u8 = 1;
+const TAG_COPY2: u8 = 2;
+
+/// The block is compressed in independent fragments of this size, so that
+/// offsets always fit in two bytes
+const FRAGMENT_SIZE: usize = 1 << 16;
+const MIN_MATCH: usize = 4;
+const HASH_LOG: u32 = 14;
+/// Inputs shorter than that are written as a single literal
+const MIN_NON_LITERAL: usize = 15;
+
+const CHUNK_STREAM_ID: u8 = 0xff;
+const CHUNK_COMPRESSED: u8 = 0x00;
+const CHUNK_UNCOMPRESSED: u8 = 0x01;
+const CHUNK_PADDING: u8 = 0xfe;
+const STREAM_ID: &'static [u8] = b"sNaPpY";
+/// Largest amount of uncompressed data in a chunk
+const MAX_CHUNK_DATA: usize = 1 << 16;
+
+/// Returns the maximum size of the block compressing `size` bytes
+pub fn max_compressed_len(size: usize) -> usize {
+    32 + size + size / 6
+}
+
+fn read_varint(input: &[u8]) -> io::Result<(usize, usize)> {
+    let mut value = 0u64;
+    for (i, &byte) in input.iter().enumerate().take(5) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            if value > u32::max_value() as u64 {
+                break
+            }
+            return Ok((value as usize, i + 1))
+        }
+    }
+    corrupt("invalid snappy length")
+}
+
+/// Returns the size of the data compressed in a raw block, as found in its
+/// preamble
+pub fn decompressed_len(input: &[u8]) -> io::Result<usize> {
+    read_varint(input).map(|(len, _)| len)
+}
+
+/// Decodes a raw snappy block, appending the data to `output`
+pub fn decode_block(input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+    let (total, mut pos) = try!(read_varint(input));
+    let start = output.len();
+    output.reserve(total);
+    while pos < input.len() {
+        let item = pos;
+        let tag = input[pos];
+        pos += 1;
+        let (len, offset) = match tag & 3 {
+            TAG_LITERAL => {
+                let mut n = (tag >> 2) as usize;
+                if n >= 60 {
+                    let k = n - 59;
+                    if pos + k > input.len() {
+                        return truncated("truncated snappy literal")
+                    }
+                    n = input[pos..(pos + k)].iter().rev()
+                        .fold(0, |acc, &b| (acc << 8) | b as usize);
+                    pos += k;
+                }
+                let n = n + 1;
+                if n > input.len() - pos || output.len() - start + n > total {
+                    return corrupt_at(item as u64, "invalid snappy literal")
+                }
+                output.extend(input[pos..(pos + n)].iter().cloned());
+                pos += n;
+                continue
+            }
+            TAG_COPY1 => {
+                if pos + 1 > input.len() {
+                    return truncated("truncated snappy copy")
+                }
+                let offset = ((tag as usize >> 5) << 8) | input[pos] as usize;
+                pos += 1;
+                (MIN_MATCH + ((tag >> 2) & 7) as usize, offset)
+            }
+            TAG_COPY2 => {
+                if pos + 2 > input.len() {
+                    return truncated("truncated snappy copy")
+                }
+                let offset = input[pos] as usize | (input[pos + 1] as usize) << 8;
+                pos += 2;
+                ((tag >> 2) as usize + 1, offset)
+            }
+            // copy with a 4-byte offset
+            _ => {
+                if pos + 4 > input.len() {
+                    return truncated("truncated snappy copy")
+                }
+                let offset = input[pos..(pos + 4)].iter().rev()
+                    .fold(0, |acc, &b| (acc << 8) | b as usize);
+                pos += 4;
+                ((tag >> 2) as usize + 1, offset)
+            }
+        };
+        let done = output.len() - start;
+        if offset == 0 || offset > done {
+            return corrupt_at(item as u64, "invalid snappy copy offset")
+        }
+        if done + len > total {
+            return corrupt_at(item as u64, "invalid snappy copy length")
+        }
+        copy_match(output, offset, len);
+    }
+    if output.len() - start != total {
+        return corrupt("snappy block length mismatch")
+    }
+    Ok(())
+}
+
+fn emit_literal(output: &mut Vec<u8>, literal: &[u8]) {
+    let n = literal.len() - 1;
+    if n < 60 {
+        output.push((n as u8) << 2 | TAG_LITERAL);
+    } else {
+        let bytes = if n < 1 << 8 { 1 } else if n < 1 << 16 { 2 } else if n < 1 << 24 { 3 } else { 4 };
+        output.push(((59 + bytes) as u8) << 2 | TAG_LITERAL);
+        for i in 0..bytes {
+            output.push((n >> (8 * i)) as u8);
+        }
+    }
+    output.extend(literal.iter().cloned());
+}
+
+fn emit_copy(output: &mut Vec<u8>, offset: usize, mut len: usize) {
+    // pieces of 64 bytes at most, leaving at least 4 for the last one
+    while len >= 68 {
+        emit_copy_piece(output, offset, 64);
+        len -= 64;
+    }
+    if len > 64 {
+        emit_copy_piece(output, offset, 60);
+        len -= 60;
+    }
+    emit_copy_piece(output, offset, len);
+}
+
+fn emit_copy_piece(output: &mut Vec<u8>, offset: usize, len: usize) {
+    debug_assert!(len >= MIN_MATCH && len <= 64 && offset < 1 << 16);
+    if len < 12 && offset < 2048 {
+        output.push(((offset >> 8) as u8) << 5 | ((len - MIN_MATCH) as u8) << 2 | TAG_COPY1);
+        output.push(offset as u8);
+    } else {
+        output.push(((len - 1) as u8) << 2 | TAG_COPY2);
+        output.push(offset as u8);
+        output.push((offset >> 8) as u8);
+    }
+}
+
+#[inline(always)]
+fn seq_at(input: &[u8], pos: usize) -> u32 {
+    (input[pos + 3] as u32) << 24
+        | (input[pos + 2] as u32) << 16
+        | (input[pos + 1] as u32) << 8
+        | (input[pos] as u32)
+}
+
+fn encode_fragment(input: &[u8], output: &mut Vec<u8>, table: &mut [u16]) {
+    if input.len() < MIN_NON_LITERAL {
+        if !input.is_empty() {
+            emit_literal(output, input);
+        }
+        return
+    }
+    for entry in table.iter_mut() {
+        *entry = 0;
+    }
+    let limit = input.len() - MIN_NON_LITERAL;
+    let (mut pos, mut anchor) = (1, 0);
+    // the step grows while no match is found, to skip incompressible data
+    let mut skip = 32;
+    while pos < limit {
+        let seq = seq_at(input, pos);
+        let hash = hash::hash4(&input[pos..], HASH_LOG);
+        let candidate = table[hash] as usize;
+        table[hash] = pos as u16;
+        if seq != seq_at(input, candidate) {
+            pos += skip >> 5;
+            skip += 1;
+            continue
+        }
+        skip = 32;
+        if anchor < pos {
+            emit_literal(output, &input[anchor..pos]);
+        }
+        let len = MIN_MATCH + hash::match_len(&input[(pos + MIN_MATCH)..], &input[(candidate + MIN_MATCH)..]);
+        emit_copy(output, pos - candidate, len);
+        pos += len;
+        anchor = pos;
+    }
+    if anchor < input.len() {
+        emit_literal(output, &input[anchor..]);
+    }
+}
+
+/// Encodes the input into a raw snappy block, appended to `output`
+pub fn encode_block(input: &[u8], output: &mut Vec<u8>) {
+    output.reserve(max_compressed_len(input.len()));
+    let mut n = input.len();
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            output.push(byte);
+            break
+        }
+        output.push(byte | 0x80);
+    }
+    let mut table: Vec<u16> = repeat(0).take(1 << HASH_LOG).collect();
+    for fragment in input.chunks(FRAGMENT_SIZE) {
+        encode_fragment(fragment, output, &mut table);
+    }
+}
+
+/// The CRC-32C of the data of a chunk, masked as the framing format requires
+fn masked_crc(data: &[u8]) -> u32 {
+    let mut state = crc32c::State32::new();
+    state.feed(data);
+    let crc = state.result();
+    ((crc >> 15) | (crc << 17)).wrapping_add(0xa282ead8)
+}
+
+/// This structure is used to decode a snappy stream in the framing format,
+/// fed with slices of it. The stream ends with its input.
+pub struct Processor {
+    head: Vec<u8>,
+    /// The kind and the length of the chunk gathered, once its header is read
+    chunk: Option<(u8, usize)>,
+    temp: Vec<u8>,
+    output: Vec<u8>,
+    start: usize,
+    header: bool,
+    eof: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder, waiting for the start of a stream
+    pub fn new() -> Processor {
+        Processor {
+            head: Vec::new(),
+            chunk: None,
+            temp: Vec::new(),
+            output: Vec::new(),
+            start: 0,
+            header: false,
+            eof: false,
+        }
+    }
+
+    /// Resets this decoder back to its initial state, keeping its buffers
+    pub fn reset(&mut self) {
+        self.head.truncate(0);
+        self.chunk = None;
+        self.temp.truncate(0);
+        self.output.truncate(0);
+        self.start = 0;
+        self.header = false;
+        self.eof = false;
+    }
+
+    /// Decodes the chunk of the given kind gathered in `temp`
+    fn decode_chunk(&mut self, kind: u8) -> io::Result<()> {
+        let len = self.temp.len();
+        if kind == CHUNK_STREAM_ID {
+            if &self.temp[..] != STREAM_ID {
+                return corrupt("invalid snappy stream identifier")
+            }
+            self.header = true;
+            return Ok(())
+        }
+        if !self.header {
+            return corrupt("missing snappy stream identifier")
+        }
+        match kind {
+            CHUNK_COMPRESSED | CHUNK_UNCOMPRESSED => {
+                if len < 4 {
+                    return corrupt("snappy chunk is too short")
+                }
+                let crc = (&self.temp[..4]).read_u32::<LittleEndian>().unwrap();
+                self.output.truncate(0);
+                self.start = 0;
+                if kind == CHUNK_COMPRESSED {
+                    if try!(decompressed_len(&self.temp[4..])) > MAX_CHUNK_DATA {
+                        return corrupt("snappy chunk is too large")
+                    }
+                    try!(decode_block(&self.temp[4..], &mut self.output));
+                } else {
+                    if len - 4 > MAX_CHUNK_DATA {
+                        return corrupt("snappy chunk is too large")
+                    }
+                    self.output.extend(self.temp[4..].iter().cloned());
+                }
+                if masked_crc(&self.output) != crc {
+                    return checksum_mismatch("invalid snappy chunk checksum")
+                }
+            }
+            // reserved unskippable chunks
+            kind if kind < 0x80 => return corrupt("unknown snappy chunk"),
+            CHUNK_PADDING => {}
+            // reserved skippable chunks
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if !drain(&self.output, &mut self.start, output, &mut produced) {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            let gathered = match self.chunk {
+                None => gather(&mut self.head, input, &mut pos, 4),
+                Some((_, len)) => gather(&mut self.temp, input, &mut pos, len),
+            };
+            if !gathered {
+                let status = if self.eof { Status::Done } else { Status::NeedInput };
+                return Ok((pos, produced, status))
+            }
+            match self.chunk.take() {
+                None => {
+                    // the kind is followed by the 24-bit length
+                    let len = (&self.head[..]).read_u32::<LittleEndian>().unwrap() as usize >> 8;
+                    self.chunk = Some((self.head[0], len));
+                    self.head.truncate(0);
+                }
+                Some((kind, _)) => {
+                    try!(self.decode_chunk(kind));
+                    self.temp.truncate(0);
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the stream ends in between the chunks
+        if self.chunk.is_some() || !self.head.is_empty() {
+            return truncated("truncated snappy chunk")
+        }
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a snappy stream in the framing format.
+/// This wraps an internal reader which is read from when this decoder's read
+/// method is called.
+pub struct Decoder<R> {
+    /// The internally wrapped reader. This is exposed so it may be moved out
+    /// of. Note that the data read ahead of the output is lost with it, and
+    /// that if data is read from the reader while decoding is in progress the
+    /// output stream will get corrupted.
+    pub r: R,
+
+    p: Processor,
+    pump: Pump,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream. The
+    /// inner stream can be re-acquired by moving out of the `r` field of this
+    /// structure.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r: r,
+            p: Processor::new(),
+            pump: Pump::new(),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.pump.set_max_output_size(size);
+    }
+
+    /// Resets this decoder back to its initial state, keeping its buffers.
+    /// Note that the underlying stream is not seeked on or has any
+    /// alterations performed on it, while the data read ahead from it is
+    /// dropped.
+    pub fn reset(&mut self) {
+        self.p.reset();
+        self.pump.reset();
+    }
+
+    /// Returns whether the end of the stream has been reached
+    pub fn eof(&self) -> bool { self.pump.is_done() }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.pump.read(&mut self.r, &mut self.p, dst)
+    }
+}
+
+/// This structure is used to compress a stream of bytes in the snappy framing
+/// format. This is a wrapper around an internal writer which bytes will be
+/// written to.
+pub struct Encoder<W> {
+    w: W,
+    buf: Vec<u8>,
+    tmp: Vec<u8>,
+    wrote_header: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will have its output written to the given
+    /// output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder {
+            w: w,
+            buf: Vec::with_capacity(MAX_CHUNK_DATA),
+            tmp: Vec::new(),
+            wrote_header: false,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.wrote_header {
+            try!(self.w.write_all(&[CHUNK_STREAM_ID, STREAM_ID.len() as u8, 0, 0]));
+            try!(self.w.write_all(STREAM_ID));
+            self.wrote_header = true;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered data as a chunk, compressed unless that saves less
+    /// than an eighth of it
+    fn encode_chunk(&mut self) -> io::Result<()> {
+        try!(self.write_header());
+        self.tmp.truncate(0);
+        encode_block(&self.buf, &mut self.tmp);
+        let (kind, data) = if self.tmp.len() < self.buf.len() - self.buf.len() / 8 {
+            (CHUNK_COMPRESSED, &self.tmp[..])
+        } else {
+            (CHUNK_UNCOMPRESSED, &self.buf[..])
+        };
+        let len = data.len() + 4;
+        try!(self.w.write_all(&[kind, len as u8, (len >> 8) as u8, (len >> 16) as u8]));
+        try!(self.w.write_u32::<LittleEndian>(masked_crc(&self.buf)));
+        try!(self.w.write_all(data));
+        self.buf.truncate(0);
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.write_header().and_then(|_| self.flush());
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`,
+    /// keeping the buffers. The previous writer is returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.write_header().and_then(|_| self.flush());
+        self.buf.truncate(0);
+        self.wrote_header = false;
+        (mem::replace(&mut self.w, w), result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while rest.len() > 0 {
+            let amt = cmp::min(MAX_CHUNK_DATA - self.buf.len(), rest.len());
+            self.buf.extend(rest[..amt].iter().cloned());
+            if self.buf.len() == MAX_CHUNK_DATA {
+                try!(self.encode_chunk());
+            }
+            rest = &rest[amt..];
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.len() > 0 {
+            try!(self.encode_chunk());
+        }
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use testing::{noise, push};
+    use super::{Decoder, Encoder, Processor, decode_block, encode_block, max_compressed_len};
+
+    fn roundtrip_block(input: &[u8]) {
+        let mut encoded = Vec::new();
+        encode_block(input, &mut encoded);
+        assert!(encoded.len() <= max_compressed_len(input.len()));
+        let mut decoded = Vec::new();
+        decode_block(&encoded, &mut decoded).unwrap();
+        assert!(&decoded[..] == input);
+    }
+
+    fn encode(input: &[u8]) -> Vec<u8> {
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(input).unwrap();
+        let (out, result) = e.finish();
+        result.unwrap();
+        out
+    }
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(push(&mut Processor::new(), input, 3).unwrap() == out);
+        out
+    }
+
+    #[test]
+    fn decode_raw_block() {
+        // a literal and an overlapping copy
+        let mut out = Vec::new();
+        decode_block(&[9, 0x08, b'a', b'b', b'c', 0x09, 3], &mut out).unwrap();
+        assert_eq!(&out[..], b"abcabcabc");
+        out.truncate(0);
+        decode_block(&[0], &mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn invalid_blocks() {
+        for input in [&[4, 0x08, b'a', b'b', b'c'][..], // length mismatch
+                      &[9, 0x08, b'a', b'b'], // truncated literal
+                      &[9, 0x08, b'a', b'b', b'c', 0x09, 0], // null offset
+                      &[9, 0x08, b'a', b'b', b'c', 0x09, 4], // offset too far
+                      &[3, 0x08, b'a', b'b', b'c', 0x09, 3], // too long
+                      &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff]].iter() {
+            assert!(decode_block(input, &mut Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn raw_roundtrips() {
+        roundtrip_block(b"");
+        roundtrip_block(b"a");
+        roundtrip_block(include_bytes!("data/test.txt"));
+        roundtrip_block(&noise(100000, 7));
+        let runs: Vec<u8> = (0..200000).map(|i| (i / 1000) as u8).collect();
+        roundtrip_block(&runs);
+        let mut encoded = Vec::new();
+        encode_block(&runs, &mut encoded);
+        assert!(encoded.len() < runs.len() / 10);
+    }
+
+    #[test]
+    fn frame_layout() {
+        let mut expected = b"\xff\x06\x00\x00sNaPpY".to_vec();
+        assert_eq!(encode(b""), expected);
+        expected.extend(b"\x01\x09\x00\x00\xbb\x1f\x1c\x19hello".iter().cloned());
+        assert_eq!(encode(b"hello"), expected);
+        assert_eq!(decode(&expected), b"hello");
+    }
+
+    #[test]
+    fn framed_roundtrips() {
+        let input = include_bytes!("data/test.txt");
+        let encoded = encode(input);
+        assert!(encoded.len() < input.len());
+        assert!(&decode(&encoded)[..] == &input[..]);
+
+        let large = noise(300000, 7);
+        assert!(decode(&encode(&large)) == large);
+    }
+
+    #[test]
+    fn small_reads_and_writes() {
+        let input = include_bytes!("data/test.txt");
+        let mut e = Encoder::new(Vec::new());
+        for (i, chunk) in input.chunks(33).enumerate() {
+            e.write_all(chunk).unwrap();
+            if i % 50 == 0 {
+                e.flush().unwrap();
+            }
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let mut d = Decoder::new(BufReader::new(&encoded[..]));
+        let mut out = Vec::new();
+        let mut buf = [0u8; 7];
+        loop {
+            match d.read(&mut buf).unwrap() {
+                0 => break,
+                n => out.extend(buf[..n].iter().cloned()),
+            }
+        }
+        assert!(&out[..] == &input[..]);
+    }
+
+    #[test]
+    fn reset() {
+        let input = include_bytes!("data/test.txt");
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(b"hello").unwrap();
+        let (first, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(input).unwrap();
+        let (second, result) = e.finish();
+        result.unwrap();
+        assert!(first == encode(b"hello"));
+        assert!(second == encode(input));
+
+        let mut d = Decoder::new(BufReader::new(&first[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"hello");
+        d.r = BufReader::new(&second[..]);
+        d.reset();
+        out.truncate(0);
+        d.read_to_end(&mut out).unwrap();
+        assert!(&out[..] == &input[..]);
+    }
+
+    #[test]
+    fn chunk_kinds() {
+        let mut input = encode(b"hello");
+        // padding and skippable chunks are ignored, others are errors
+        input.extend(b"\xfe\x02\x00\x00\x00\x00\x80\x01\x00\x00x".iter().cloned());
+        assert_eq!(decode(&input), b"hello");
+        input.extend(b"\x02\x00\x00\x00".iter().cloned());
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut input = encode(b"hello");
+        let last = input.len() - 1;
+        input[last] ^= 1;
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+        let mut d = Decoder::new(BufReader::new(&b"\x01\x09\x00\x00"[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}
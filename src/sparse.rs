@@ -0,0 +1,475 @@
+/*!
+
+Sparse data Decompression and Compression. Requires `sparse` feature, enabled
+by default
+
+This module contains a stage which cuts the long runs of a same byte, as the
+zeroed and unused regions of disk images and memory snapshots, out of the
+data: they are written as holes, made of their byte and their length, while
+the rest goes through as data records, to be compressed by the stage which
+follows. The holes thus skip the following stage, whatever their size.
+
+The records start with a tag, 0 for data and 1 for holes, followed by their
+length, a LEB128 varint, and the data or the byte of the hole. The stage is
+exposed as standard `Reader` and `Writer` interfaces wrapping an underlying
+stream.
+
+# Example
+
+```rust
+use std::io::{Read, Write};
+use compress::sparse;
+
+let mut image = vec![0u8; 1 << 20];
+image[1000..1005].copy_from_slice(b"hello");
+let mut e = sparse::Encoder::new(Vec::new(), 512);
+e.write_all(&image).unwrap();
+let (encoded, result) = e.finish();
+result.unwrap();
+// a hole, the data and another hole
+assert!(encoded.len() < 32);
+
+let mut d = sparse::Decoder::new(&encoded[..]);
+let mut decoded = Vec::new();
+d.read_to_end(&mut decoded).unwrap();
+assert!(decoded == image);
+```
+
+*/
+
+use std::cmp;
+use std::mem;
+use std::io::{self, Read, Write};
+
+use error::{corrupt, truncated};
+use process::{Process, Reader, Status};
+
+const TAG_DATA: u8 = 0;
+const TAG_HOLE: u8 = 1;
+
+/// Largest data record written
+const MAX_DATA: usize = 1 << 16;
+
+fn write_record<W: Write>(w: &mut W, tag: u8, mut len: u64) -> io::Result<()> {
+    let mut header = [tag, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let mut n = 1;
+    loop {
+        header[n] = (len & 0x7f) as u8;
+        len >>= 7;
+        n += 1;
+        if len == 0 {
+            break
+        }
+        header[n - 1] |= 0x80;
+    }
+    w.write_all(&header[..n])
+}
+
+/// This structure is used to compress a stream of sparse data. This is a
+/// wrapper around an internal writer which bytes will be written to.
+pub struct Encoder<W> {
+    w: W,
+    min_run: usize,
+    /// Data not written yet, ending with `tail` times the same byte
+    data: Vec<u8>,
+    tail: usize,
+    /// The byte and the length of the current hole
+    hole: Option<(u8, u64)>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will write the runs of at least `min_run`
+    /// bytes, from 8, as holes, and have its output written to the given
+    /// output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(w: W, min_run: usize) -> Encoder<W> {
+        assert!(min_run >= 8, "invalid sparse minimum run {}", min_run);
+        Encoder {
+            w: w,
+            min_run: min_run,
+            data: Vec::with_capacity(MAX_DATA),
+            tail: 0,
+            hole: None,
+        }
+    }
+
+    /// Writes the data held, but for the `keep` last bytes
+    fn write_data(&mut self, keep: usize) -> io::Result<()> {
+        let len = self.data.len() - keep;
+        if len > 0 {
+            try!(write_record(&mut self.w, TAG_DATA, len as u64));
+            try!(self.w.write_all(&self.data[..len]));
+            self.data.drain(..len);
+        }
+        Ok(())
+    }
+
+    fn write_hole(&mut self) -> io::Result<()> {
+        if let Some((byte, len)) = self.hole.take() {
+            try!(write_record(&mut self.w, TAG_HOLE, len));
+            try!(self.w.write_all(&[byte]));
+        }
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.flush();
+        (self.w, result)
+    }
+
+    /// Finishes the stream, as `finish` does, then starts a new one into `w`
+    /// with the same minimum run, keeping the buffer. The previous writer is
+    /// returned.
+    pub fn reset(&mut self, w: W) -> (W, io::Result<()>) {
+        let result = self.flush();
+        self.data.truncate(0);
+        self.hole = None;
+        (mem::replace(&mut self.w, w), result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            if let Some((byte, ref mut len)) = self.hole {
+                let n = rest.iter().position(|&b| b != byte).unwrap_or(rest.len());
+                *len += n as u64;
+                rest = &rest[n..];
+                if rest.is_empty() {
+                    break
+                }
+            }
+            try!(self.write_hole());
+            let byte = rest[0];
+            rest = &rest[1..];
+            if self.data.last() == Some(&byte) {
+                self.tail += 1;
+            } else {
+                self.tail = 1;
+            }
+            self.data.push(byte);
+            if self.tail == self.min_run {
+                let tail = self.tail;
+                try!(self.write_data(tail));
+                self.data.truncate(0);
+                self.tail = 0;
+                self.hole = Some((byte, tail as u64));
+            } else if self.data.len() == MAX_DATA {
+                // the run at the end may still become a hole
+                let tail = self.tail;
+                try!(self.write_data(tail));
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.write_data(0));
+        self.tail = 0;
+        try!(self.write_hole());
+        self.w.flush()
+    }
+}
+
+/// Reads the header of a record, returning whether it is a hole, with its
+/// byte, and its length, or `None` until `head` holds all of it
+fn read_record(head: &[u8]) -> io::Result<Option<(Option<u8>, u64)>> {
+    let mut len = 0u64;
+    let mut end = 1;
+    loop {
+        let byte = match head.get(end) {
+            Some(&byte) => byte,
+            None => return Ok(None),
+        };
+        if end == 10 && byte > 1 {
+            return corrupt("invalid sparse length")
+        }
+        len |= ((byte & 0x7f) as u64) << (7 * (end - 1));
+        end += 1;
+        if byte & 0x80 == 0 || end == 11 {
+            break
+        }
+    }
+    if len == 0 {
+        return corrupt("invalid sparse length")
+    }
+    match head[0] {
+        TAG_DATA => Ok(Some((None, len))),
+        TAG_HOLE => Ok(head.get(end).map(|&byte| (Some(byte), len))),
+        _ => corrupt("invalid sparse record"),
+    }
+}
+
+/// This structure is used to decode a stream of sparse data, fed with slices
+/// of it. The stream ends with its input.
+pub struct Processor {
+    /// The header of the next record, as far as it is gathered
+    head: Vec<u8>,
+    /// The data or the hole being read, and its length left
+    hole: Option<u8>,
+    left: u64,
+    eof: bool,
+}
+
+impl Processor {
+    /// Creates a new decoder, waiting for the first record
+    pub fn new() -> Processor {
+        Processor { head: Vec::new(), hole: None, left: 0, eof: false }
+    }
+
+    /// Starts decoding a new stream
+    pub fn reset(&mut self) {
+        self.head.truncate(0);
+        self.hole = None;
+        self.left = 0;
+        self.eof = false;
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            if self.left == 0 {
+                if pos == input.len() {
+                    let status = if self.eof { Status::Done } else { Status::NeedInput };
+                    return Ok((pos, produced, status))
+                }
+                self.head.push(input[pos]);
+                pos += 1;
+                if let Some((hole, len)) = try!(read_record(&self.head)) {
+                    self.head.truncate(0);
+                    self.hole = hole;
+                    self.left = len;
+                }
+                continue
+            }
+            if produced == output.len() {
+                return Ok((pos, produced, Status::NeedOutput))
+            }
+            let room = cmp::min(self.left, (output.len() - produced) as u64) as usize;
+            let amt = match self.hole {
+                Some(byte) => {
+                    for b in output[produced..(produced + room)].iter_mut() {
+                        *b = byte;
+                    }
+                    room
+                }
+                None => {
+                    if pos == input.len() {
+                        return Ok((pos, produced, Status::NeedInput))
+                    }
+                    let amt = cmp::min(room, input.len() - pos);
+                    output[produced..(produced + amt)].copy_from_slice(&input[pos..(pos + amt)]);
+                    pos += amt;
+                    amt
+                }
+            };
+            produced += amt;
+            self.left -= amt as u64;
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the stream ends in between the records, or in a hole
+        if (self.left != 0 && self.hole.is_none()) || !self.head.is_empty() {
+            return truncated("truncated sparse data")
+        }
+        self.eof = true;
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a stream of sparse data, which ends with
+/// the underlying stream. The wrapped stream can be re-acquired through the
+/// unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder { inner: Reader::new(r, Processor::new()) }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Returns whether the end of the data has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.is_done()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use error::Error;
+    use testing::{noise, push, Lcg};
+    use super::{Decoder, Encoder, Processor};
+
+    fn encode(input: &[u8], min_run: usize) -> Vec<u8> {
+        let mut e = Encoder::new(Vec::new(), min_run);
+        e.write_all(input).unwrap();
+        let (out, result) = e.finish();
+        result.unwrap();
+        out
+    }
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(push(&mut Processor::new(), input, 333).unwrap() == out);
+        out
+    }
+
+    /// An image of randomly sized data and runs of zeros and of 0xff
+    fn image() -> Vec<u8> {
+        let mut lcg = Lcg(1);
+        let mut out = Vec::new();
+        for i in 0..200 {
+            let seed = lcg.step();
+            let len = (seed >> 8) as usize % 20000;
+            match i % 3 {
+                0 => out.extend(noise(len, 7).into_iter()),
+                1 => out.extend((0..len).map(|_| 0)),
+                _ => out.extend((0..len).map(|_| 0xff)),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn known_data() {
+        let mut input = b"ab".to_vec();
+        input.extend([0u8; 300].iter());
+        input.extend(b"cddddddd".iter());
+        // the data ends with a run too short to be a hole
+        assert_eq!(encode(&input, 8), b"\x00\x02ab\x01\xac\x02\x00\x00\x08cddddddd");
+        assert!(decode(&encode(&input, 8)) == input);
+        assert_eq!(encode(b"", 8), b"");
+        assert_eq!(decode(b""), b"");
+    }
+
+    #[test]
+    fn roundtrips() {
+        let input = image();
+        for &min_run in [8, 100, 4096].iter() {
+            let encoded = encode(&input, min_run);
+            assert!(encoded.len() < input.len() / 2);
+            assert!(decode(&encoded) == input);
+        }
+        let noise = noise(200000, 7);
+        assert!(decode(&encode(&noise, 8)) == noise);
+    }
+
+    #[test]
+    fn streams() {
+        let input = image();
+        let mut e = Encoder::new(Vec::new(), 64);
+        for (i, chunk) in input.chunks(777).enumerate() {
+            e.write_all(chunk).unwrap();
+            if i % 100 == 0 {
+                e.flush().unwrap();
+            }
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let mut d = Decoder::new(BufReader::new(&encoded[..]));
+        let mut out = Vec::new();
+        let mut buf = [0u8; 1000];
+        loop {
+            match d.read(&mut buf).unwrap() {
+                0 => break,
+                n => out.extend(buf[..n].iter().cloned()),
+            }
+        }
+        assert!(out == input);
+    }
+
+    #[test]
+    fn reset() {
+        let (first, second) = (image(), include_bytes!("data/test.txt").to_vec());
+        let mut e = Encoder::new(Vec::new(), 8);
+        e.write_all(&first).unwrap();
+        let (a, result) = e.reset(Vec::new());
+        result.unwrap();
+        e.write_all(&second).unwrap();
+        let (b, result) = e.finish();
+        result.unwrap();
+        let mut fresh = Encoder::new(Vec::new(), 8);
+        fresh.write_all(&second).unwrap();
+        assert!(b == fresh.finish().0);
+
+        let mut d = Decoder::new(BufReader::new(&a[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(&out[..] == &first[..]);
+        d.reset(BufReader::new(&b[..]));
+        out.truncate(0);
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof() && out == second);
+    }
+
+    #[test]
+    fn invalid_data() {
+        for input in [&[0, 3, b'a'][..], // truncated data
+                      &[1, 3], // truncated hole
+                      &[0, 0], // empty record
+                      &[2, 1, 0], // unknown record
+                      &[1, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, 0]].iter() {
+            let mut d = Decoder::new(BufReader::new(*input));
+            assert!(d.read_to_end(&mut Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn max_output_size() {
+        // a hole of 2^40 bytes
+        let input = [1, 0x80, 0x80, 0x80, 0x80, 0x80, 0x20, 0];
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        d.set_max_output_size(1 << 20);
+        let mut out = Vec::new();
+        let err = d.read_to_end(&mut out).unwrap_err();
+        assert!(match Error::of(&err) { Some(Error::LimitExceeded { .. }) => true, _ => false });
+        assert_eq!(out.len(), 1 << 20);
+
+        let input = image();
+        let encoded = encode(&input, 8);
+        let mut d = Decoder::new(BufReader::new(&encoded[..]));
+        d.set_max_output_size(input.len() as u64);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(out == input);
+    }
+}
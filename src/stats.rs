@@ -0,0 +1,169 @@
+//! Statistics of the encoders
+//!
+//! The encoders of `flate` and `bwt` count what they do into a `Sink`, once
+//! given one with `set_stats`: the bytes they are given and write, the blocks,
+//! and the literals and lengths of the matches of the LZ coders or the symbols
+//! of the blocks transformed by the BWT. The sink is shared with the encoder,
+//! so that the statistics are read once it is finished, and costs nothing to
+//! the encoders without one.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::io::Write;
+//! use compress::flate;
+//! use compress::stats::Sink;
+//!
+//! let sink = Sink::new();
+//! let mut e = flate::Encoder::new(Vec::new(), 6);
+//! e.set_stats(sink.clone());
+//! e.write_all(b"abracadabra abracadabra").unwrap();
+//! let (compressed, result) = e.finish();
+//! result.unwrap();
+//!
+//! let stats = sink.get();
+//! assert_eq!(stats.input, 23);
+//! assert_eq!(stats.output, compressed.len() as u64);
+//! assert!(stats.matches() > 0);
+//! ```
+
+use std::sync::{Arc, Mutex};
+
+/// The counts gathered on a stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of bytes given to the encoder
+    pub input: u64,
+    /// Number of bytes written by the encoder
+    pub output: u64,
+    /// Number of blocks written
+    pub blocks: u64,
+    /// Occurrences of each byte coded as a literal, by the LZ coders
+    pub literals: Vec<u64>,
+    /// Occurrences of each length of the matches, by the LZ coders
+    pub match_lengths: Vec<u64>,
+    /// Occurrences of each byte of the transformed blocks, by the BWT coders
+    pub symbols: Vec<u64>,
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats::new()
+    }
+}
+
+impl Stats {
+    /// Creates statistics of nothing
+    pub fn new() -> Stats {
+        Stats {
+            input: 0,
+            output: 0,
+            blocks: 0,
+            literals: vec![0; 256],
+            match_lengths: Vec::new(),
+            symbols: vec![0; 256],
+        }
+    }
+
+    /// Counts a match of `len` bytes
+    pub fn add_match(&mut self, len: usize) {
+        if self.match_lengths.len() <= len {
+            self.match_lengths.resize(len + 1, 0);
+        }
+        self.match_lengths[len] += 1;
+    }
+
+    /// Returns the number of matches
+    pub fn matches(&self) -> u64 {
+        self.match_lengths.iter().sum()
+    }
+
+    /// Adds the counts of `other` to these
+    pub fn merge(&mut self, other: &Stats) {
+        self.input += other.input;
+        self.output += other.output;
+        self.blocks += other.blocks;
+        for (a, b) in self.literals.iter_mut().zip(other.literals.iter()) {
+            *a += *b;
+        }
+        if self.match_lengths.len() < other.match_lengths.len() {
+            self.match_lengths.resize(other.match_lengths.len(), 0);
+        }
+        for (a, b) in self.match_lengths.iter_mut().zip(other.match_lengths.iter()) {
+            *a += *b;
+        }
+        for (a, b) in self.symbols.iter_mut().zip(other.symbols.iter()) {
+            *a += *b;
+        }
+    }
+
+    /// Returns the entropy of the transformed symbols, in bits per symbol
+    pub fn symbol_entropy(&self) -> f64 {
+        entropy(&self.symbols)
+    }
+
+    /// Returns the entropy of the literals, in bits per literal
+    pub fn literal_entropy(&self) -> f64 {
+        entropy(&self.literals)
+    }
+}
+
+/// Returns the order-0 entropy of the given occurrences of symbols, in bits
+/// per symbol, which is 0 for no symbols
+pub fn entropy(counts: &[u64]) -> f64 {
+    let total = counts.iter().sum::<u64>() as f64;
+    counts.iter().filter(|&&n| n > 0).map(|&n| {
+        let p = n as f64 / total;
+        -p * p.log2()
+    }).sum()
+}
+
+/// The statistics an encoder adds to, shared with the callers reading them
+#[derive(Clone, Debug, Default)]
+pub struct Sink(Arc<Mutex<Stats>>);
+
+impl Sink {
+    /// Creates a sink of no statistics yet
+    pub fn new() -> Sink {
+        Sink::default()
+    }
+
+    /// Returns the statistics gathered so far
+    pub fn get(&self) -> Stats {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Forgets the statistics gathered so far
+    pub fn clear(&self) {
+        *self.0.lock().unwrap() = Stats::new();
+    }
+
+    /// Updates the statistics with `f`
+    pub fn update<F: FnOnce(&mut Stats)>(&self, f: F) {
+        f(&mut self.0.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{entropy, Stats};
+
+    #[test]
+    fn entropies() {
+        assert_eq!(entropy(&[]), 0.0);
+        assert_eq!(entropy(&[0, 10, 0]), 0.0);
+        assert_eq!(entropy(&[5, 5, 5, 5]), 2.0);
+
+        let mut stats = Stats::new();
+        stats.add_match(3);
+        stats.add_match(258);
+        stats.literals[b'a' as usize] = 4;
+        let mut total = Stats::new();
+        total.merge(&stats);
+        total.merge(&stats);
+        assert_eq!(total.matches(), 4);
+        assert_eq!(total.match_lengths[258], 2);
+        assert_eq!(total.literals[b'a' as usize], 8);
+        assert_eq!(total.literal_entropy(), 0.0);
+    }
+}
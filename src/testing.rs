@@ -0,0 +1,61 @@
+//! Reproducible noise and piecewise decoding for the tests of the modules
+//!
+//! The tests draw their pseudo-random data from this linear congruential
+//! generator, the one of the C standard, so that they all see the same data
+//! from one run to the next. `push` decodes with a `Process` fed and drained
+//! a few bytes at a time, as the streams cut at any byte.
+
+use std::cmp;
+use std::io;
+use std::vec::Vec;
+
+use error::truncated;
+use process::{Process, Status};
+
+/// The state of the generator
+pub struct Lcg(pub u32);
+
+impl Lcg {
+    /// Steps the generator, returning its new state. Its low bits are the
+    /// least random.
+    pub fn step(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(1103515245).wrapping_add(12345);
+        self.0
+    }
+}
+
+/// Returns `len` bytes of noise, from the generator seeded with `seed`
+pub fn noise(len: usize, seed: u32) -> Vec<u8> {
+    let mut lcg = Lcg(seed);
+    (0..len).map(|_| (lcg.step() >> 16) as u8).collect()
+}
+
+/// Decodes `input` with `p`, pushed `step` bytes at a time into an output of
+/// `step` bytes, checking the counts returned along the way
+pub fn push<P: Process + ?Sized>(p: &mut P, input: &[u8], step: usize) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buf = vec![0; step];
+    let mut pos = 0;
+    let mut finished = false;
+    loop {
+        let end = cmp::min(pos + step, input.len());
+        let (consumed, produced, status) = try!(p.process(&input[pos..end], &mut buf));
+        assert!(consumed <= end - pos && produced <= step);
+        pos += consumed;
+        output.extend_from_slice(&buf[..produced]);
+        match status {
+            Status::Done => return Ok(output),
+            Status::NeedInput => {
+                assert_eq!(pos, end, "input left over");
+                if pos == input.len() {
+                    if finished {
+                        return truncated("truncated stream")
+                    }
+                    try!(p.finish());
+                    finished = true;
+                }
+            }
+            Status::NeedOutput => assert_eq!(produced, step, "output left over"),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Settings fitted to the machine
+//!
+//! The block and chunk sizes of the coders and their number of threads
+//! default to fixed values, too small for large machines and too large for
+//! the small ones. The `auto` settings of the coders, as `BwtOptions::auto`
+//! and `DeflateOptions::auto`, pick them instead from the number of cores and
+//! a memory budget given by the caller, with the functions of this module.
+//!
+//! # Example
+//!
+//! ```rust
+//! use compress::tune;
+//!
+//! // 4 jobs of 1 kB each, plus 2 bytes per byte of their block
+//! let size = tune::block_size(1 << 20, 4, 1000, 2, 1 << 10, 1 << 20);
+//! assert_eq!(size, 1 << 16);
+//! assert!(tune::cores() >= 1);
+//! ```
+
+use std::cmp;
+use std::thread;
+
+/// Returns the number of threads the machine runs at once, 1 if unknown
+pub fn cores() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Returns the largest power of two from `min` to `max` for the blocks of
+/// `jobs` jobs at once to take no more than `budget` bytes, each job taking
+/// `fixed` bytes and `per_byte` bytes per byte of its block, or `min` if even
+/// those take more
+pub fn block_size(budget: usize, jobs: usize, fixed: usize, per_byte: usize, min: usize, max: usize) -> usize {
+    debug_assert!(min > 0 && min <= max);
+    let share = budget / cmp::max(jobs, 1);
+    let most = share.saturating_sub(fixed) / cmp::max(per_byte, 1);
+    let mut size = min;
+    while size < max && size.saturating_mul(2) <= most {
+        size *= 2;
+    }
+    cmp::min(size, max)
+}
+
+/// Returns the number of jobs, from 1 to `cores()`, of which `budget` bytes
+/// hold each one taking `memory` bytes
+pub fn jobs(budget: usize, memory: usize) -> usize {
+    cmp::max(1, cmp::min(cores(), budget / cmp::max(memory, 1)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{block_size, cores, jobs};
+
+    #[test]
+    fn sizes() {
+        assert_eq!(block_size(1 << 20, 1, 0, 1, 1, 1 << 30), 1 << 20);
+        assert_eq!(block_size((1 << 20) - 1, 1, 0, 1, 1, 1 << 30), 1 << 19);
+        assert_eq!(block_size(1 << 30, 2, 0, 1, 1, 1 << 20), 1 << 20);
+        assert_eq!(block_size(100, 2, 1000, 1, 1 << 10, 1 << 20), 1 << 10);
+        assert_eq!(block_size(usize::max_value(), 0, 0, 0, 1, 1 << 20), 1 << 20);
+        assert_eq!(jobs(0, 100), 1);
+        assert_eq!(jobs(usize::max_value(), 100), cores());
+    }
+}
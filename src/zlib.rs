@@ -24,104 +24,212 @@ use std::io::{self, Read};
 use super::byteorder::{BigEndian, ReadBytesExt};
 
 use Adler32;
+#[cfg(feature="dictionary")]
+use dictionary::Dictionary;
+use error::{checksum_mismatch, corrupt, truncated, unsupported};
 use flate;
+use process::{gather, Process, Reader, Status};
 
-/// Structure used to decode a ZLIB-encoded stream. The wrapped stream can be
-/// re-acquired through the unwrap() method.
-pub struct Decoder<R> {
+/// The part of the stream a `Processor` expects next
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// The method and the flags
+    Header,
+    /// The Adler-32 of the preset dictionary
+    Dictionary,
+    /// The DEFLATE stream
+    Data,
+    /// The Adler-32 of the data
+    Checksum,
+    Done,
+}
+
+/// This structure is used to decode a ZLIB-encoded stream, fed with slices
+/// of it.
+pub struct Processor {
+    state: State,
+    head: Vec<u8>,
     hash: Adler32,
-    inner: flate::Decoder<R>,
-    read_header: bool,
+    inner: flate::Processor,
+    /// The Adler-32 of the preset dictionary, if any
+    dictionary_id: Option<u32>,
+    finished: bool,
 }
 
-impl<R: Read> Decoder<R> {
-    /// Creates a new ZLIB-stream decoder which will wrap the specified reader.
-    /// This decoder also implements the `Reader` trait, and the underlying
-    /// reader can be re-acquired through the `unwrap` method.
-    pub fn new(r: R) -> Decoder<R> {
-        Decoder {
+impl Processor {
+    /// Creates a new ZLIB-stream decoder, waiting for the header
+    pub fn new() -> Processor {
+        Processor::with_inner(flate::Processor::new(), None)
+    }
+
+    /// Creates a new ZLIB-stream decoder of the streams compressed with the
+    /// given preset dictionary, which their header identifies.
+    #[cfg(feature="dictionary")]
+    pub fn with_dictionary(dictionary: &Dictionary) -> Processor {
+        let mut hash = Adler32::new();
+        hash.feed(dictionary.content());
+        Processor::with_inner(flate::Processor::with_dictionary(dictionary), Some(hash.result()))
+    }
+
+    fn with_inner(inner: flate::Processor, dictionary_id: Option<u32>) -> Processor {
+        Processor {
+            state: State::Header,
+            head: Vec::new(),
             hash: Adler32::new(),
-            inner: flate::Decoder::new(r),
-            read_header: false,
+            inner: inner,
+            dictionary_id: dictionary_id,
+            finished: false,
         }
     }
 
-    /// Destroys this decoder, returning the underlying reader.
-    pub fn unwrap(self) -> R {
-        self.inner.r
+    /// Starts decoding a new stream, keeping the buffers of the decoder
+    pub fn reset(&mut self) {
+        self.state = State::Header;
+        self.head.truncate(0);
+        self.hash.reset();
+        self.inner.reset();
+        self.finished = false;
     }
 
-    fn validate_header(&mut self) -> io::Result<()> {
-        let cmf = try!(self.inner.r.read_u8());
-        let flg = try!(self.inner.r.read_u8());
+    /// Tests if this stream has reached the EOF point yet.
+    pub fn eof(&self) -> bool {
+        self.state == State::Done
+    }
+
+    fn validate_header(&self) -> io::Result<State> {
+        let (cmf, flg) = (self.head[0], self.head[1]);
         if cmf & 0xf != 0x8 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "unsupported zlib stream format"
-            ))
+            return unsupported("unsupported zlib stream format")
         }
 
         if cmf & 0xf0 != 0x70 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "unsupported zlib window size"
-            ))
+            return unsupported("unsupported zlib window size")
+        }
+
+        if ((cmf as u16) * 256 + (flg as u16)) % 31 != 0 {
+            return checksum_mismatch("invalid zlib header checksum")
         }
 
         if flg & 0x20 != 0 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "unsupported initial dictionary in the output stream"
-            ))
+            return Ok(State::Dictionary)
         }
+        Ok(State::Data)
+    }
+}
 
-        if ((cmf as u16) * 256 + (flg as u16)) % 31 != 0 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "invalid zlib header checksum"
-            ))
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        let mut pos = 0;
+        let mut produced = 0;
+        loop {
+            let len = match self.state {
+                State::Header => 2,
+                State::Dictionary | State::Checksum => 4,
+                State::Data => {
+                    let (consumed, n, status) = try!(self.inner.process(&input[pos..], &mut output[produced..]));
+                    self.hash.feed(&output[produced..(produced + n)]);
+                    pos += consumed;
+                    produced += n;
+                    match status {
+                        Status::Done => {
+                            self.state = State::Checksum;
+                            continue
+                        }
+                        status => return Ok((pos, produced, status)),
+                    }
+                }
+                State::Done => return Ok((pos, produced, Status::Done)),
+            };
+            if !gather(&mut self.head, input, &mut pos, len) {
+                if self.finished {
+                    return truncated("truncated zlib stream")
+                }
+                return Ok((pos, produced, Status::NeedInput))
+            }
+
+            self.state = match self.state {
+                State::Header => try!(self.validate_header()),
+                State::Dictionary => {
+                    let id = (&self.head[..]).read_u32::<BigEndian>().unwrap();
+                    match self.dictionary_id {
+                        None => return unsupported("unsupported initial dictionary in the output stream"),
+                        Some(expected) if expected != id => return corrupt("zlib stream of another dictionary"),
+                        Some(_) => State::Data,
+                    }
+                }
+                _ => {
+                    let cksum = (&self.head[..]).read_u32::<BigEndian>().unwrap();
+                    if cksum != self.hash.result() {
+                        return checksum_mismatch("invalid checksum on zlib stream")
+                    }
+                    State::Done
+                }
+            };
+            self.head.truncate(0);
         }
-        Ok(())
     }
 
-    /// Tests if this stream has reached the EOF point yet.
-    pub fn eof(&self) -> bool { self.inner.eof() }
+    fn finish(&mut self) -> io::Result<()> {
+        self.finished = true;
+        match self.state {
+            State::Data => self.inner.finish(),
+            State::Done => Ok(()),
+            _ => truncated("truncated zlib stream"),
+        }
+    }
+}
 
-    #[allow(dead_code)]
-    fn reset(&mut self) {
-        self.inner.reset();
-        self.hash.reset();
-        self.read_header = false;
+/// Structure used to decode a ZLIB-encoded stream. The wrapped stream can be
+/// re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new ZLIB-stream decoder which will wrap the specified reader.
+    /// This decoder also implements the `Reader` trait, and the underlying
+    /// reader can be re-acquired through the `unwrap` method.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new()),
+        }
     }
+
+    /// Creates a new ZLIB-stream decoder of the streams compressed with the
+    /// given preset dictionary, which their header identifies.
+    #[cfg(feature="dictionary")]
+    pub fn with_dictionary(r: R, dictionary: &Dictionary) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::with_dictionary(dictionary)),
+        }
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Tests if this stream has reached the EOF point yet.
+    pub fn eof(&self) -> bool { self.inner.processor().eof() }
 }
 
 impl<R: Read> Read for Decoder<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if !self.read_header {
-            try!(self.validate_header());
-            self.read_header = true;
-        } else if self.inner.eof() {
-            return Ok(0);
-        }
-        match self.inner.read(buf) {
-            Ok(0) => {
-                let cksum = try!(self.inner.r.read_u32::<BigEndian>());
-                if cksum != self.hash.result() {
-                    Err(io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        "invalid checksum on zlib stream"
-                    ))
-                }
-                else {
-                    Ok(0)
-                }
-            }
-            Ok(n) => {
-                self.hash.feed(&buf[..n]);
-                Ok(n)
-            }
-            Err(e) => Err(e)
-        }
+        self.inner.read(buf)
     }
 }
 
@@ -132,7 +240,8 @@ mod test {
     use super::super::rand::{random, Rand};
     use super::super::byteorder::{LittleEndian, BigEndian, WriteBytesExt, ReadBytesExt};
     use std::str;
-    use super::{Decoder};
+    use testing::push;
+    use super::{Decoder, Processor};
     #[cfg(feature="unstable")]
     use test;
 
@@ -145,6 +254,7 @@ mod test {
         }
 
         assert!(&buf[..] == output);
+        assert!(push(&mut Processor::new(), input, 333).unwrap() == output);
     }
 
     #[test]
@@ -226,8 +336,7 @@ mod test {
         let mut output = [0u8; 65536];
         let mut output_size = 0;
         bh.iter(|| {
-            d.inner.r = BufReader::new(input);
-            d.reset();
+            d.reset(BufReader::new(input));
             output_size = d.read(&mut output[..]).unwrap();
         });
         bh.bytes = output_size as u64;
@@ -0,0 +1,373 @@
+//! Decoding of the compressed blocks of zstd
+//!
+//! A compressed block holds the literals, raw or Huffman coded, then the
+//! sequences, each made of a number of literals to copy, followed by a match
+//! of a given length and offset. The codes of the literal lengths, match
+//! lengths and offsets are coded with three interleaved FSE states, and the
+//! tables may be reused by the following blocks of the frame.
+
+use std::io;
+
+use copy::copy_match;
+use error::{corrupt, truncated};
+use super::fse::{BackwardBits, FseTable};
+use super::huffman::HuffmanTable;
+
+/// Maximum size of the content of a block
+pub const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+static LL_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+    16, 18, 20, 22, 24, 28, 32, 40, 48, 64, 128, 256, 512, 1024, 2048, 4096,
+    8192, 16384, 32768, 65536];
+static LL_BITS: [u8; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15, 16];
+static LL_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1];
+
+static ML_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18,
+    19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34,
+    35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027, 2051,
+    4099, 8195, 16387, 32771, 65539];
+static ML_BITS: [u8; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16];
+static ML_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1,
+    -1, -1, -1, -1, -1];
+
+static OF_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1];
+const MAX_OFFSET_CODE: usize = 31;
+
+/// Description of the three kinds of codes of the sequences
+struct Codes {
+    default: &'static [i16],
+    default_log: u32,
+    max_log: u32,
+    max_symbol: usize,
+}
+
+static LL_CODES: Codes = Codes { default: &LL_DEFAULT, default_log: 6, max_log: 9, max_symbol: 35 };
+static OF_CODES: Codes = Codes { default: &OF_DEFAULT, default_log: 5, max_log: 8, max_symbol: MAX_OFFSET_CODE };
+static ML_CODES: Codes = Codes { default: &ML_DEFAULT, default_log: 6, max_log: 9, max_symbol: 52 };
+
+/// Modes of the tables of the sequences
+const MODE_DEFAULT: u8 = 0;
+const MODE_RLE: u8 = 1;
+const MODE_FSE: u8 = 2;
+
+/// Kinds of literals sections
+const LITERALS_RAW: u8 = 0;
+const LITERALS_RLE: u8 = 1;
+const LITERALS_HUFFMAN: u8 = 2;
+
+/// Reads a table of the sequences, returning the number of bytes it took
+fn read_table(mode: u8, input: &[u8], codes: &Codes, table: &mut Option<FseTable>)
+              -> io::Result<usize> {
+    match mode {
+        MODE_DEFAULT => {
+            *table = Some(try!(FseTable::from_counts(codes.default, codes.default_log)));
+            Ok(0)
+        }
+        MODE_RLE => match input.first() {
+            Some(&s) if s as usize <= codes.max_symbol => {
+                *table = Some(FseTable::rle(s));
+                Ok(1)
+            }
+            Some(_) => corrupt("invalid zstd sequences table"),
+            None => truncated("truncated zstd block"),
+        },
+        MODE_FSE => {
+            let (t, used) = try!(FseTable::read(input, codes.max_log, codes.max_symbol));
+            *table = Some(t);
+            Ok(used)
+        }
+        // the table of the previous block is repeated
+        _ => match *table {
+            Some(_) => Ok(0),
+            None => corrupt("zstd sequences table repeated before being set"),
+        },
+    }
+}
+
+/// Reads a little endian integer of `n` bytes
+fn read_le(input: &[u8], n: usize) -> io::Result<usize> {
+    if input.len() < n {
+        return truncated("truncated zstd block")
+    }
+    Ok(input[..n].iter().rev().fold(0, |v, &b| (v << 8) | b as usize))
+}
+
+/// The entropy tables and offsets, which a block may reuse from the previous
+/// one, or from a dictionary for the first block of a frame
+#[derive(Clone)]
+pub struct Tables {
+    huffman: Option<HuffmanTable>,
+    ll: Option<FseTable>,
+    of: Option<FseTable>,
+    ml: Option<FseTable>,
+    /// The last offsets, which the sequences can repeat
+    pub reps: [usize; 3],
+}
+
+impl Tables {
+    pub fn new() -> Tables {
+        Tables {
+            huffman: None,
+            ll: None,
+            of: None,
+            ml: None,
+            reps: [1, 4, 8],
+        }
+    }
+
+    /// Reads the tables of a dictionary, returning them with the number of
+    /// bytes they took
+    pub fn read_dictionary(input: &[u8]) -> io::Result<(Tables, usize)> {
+        let (huffman, mut pos) = try!(HuffmanTable::read(input));
+        let mut tables = Tables::new();
+        tables.huffman = Some(huffman);
+        pos += try!(read_table(MODE_FSE, &input[pos..], &OF_CODES, &mut tables.of));
+        pos += try!(read_table(MODE_FSE, &input[pos..], &ML_CODES, &mut tables.ml));
+        pos += try!(read_table(MODE_FSE, &input[pos..], &LL_CODES, &mut tables.ll));
+        if input.len() < pos + 12 {
+            return truncated("truncated zstd dictionary")
+        }
+        for i in 0..3 {
+            tables.reps[i] = try!(read_le(&input[(pos + 4 * i)..], 4));
+        }
+        Ok((tables, pos + 12))
+    }
+}
+
+/// The state of the decoding of the blocks, kept from one block to the next
+/// ones of the frame
+pub struct BlockDecoder {
+    tables: Tables,
+    literals: Vec<u8>,
+}
+
+impl BlockDecoder {
+    pub fn new() -> BlockDecoder {
+        BlockDecoder {
+            tables: Tables::new(),
+            literals: Vec::new(),
+        }
+    }
+
+    /// Starts a new frame, with the tables of its dictionary if any
+    pub fn reset(&mut self, tables: Option<&Tables>) {
+        self.tables = match tables {
+            Some(tables) => tables.clone(),
+            None => Tables::new(),
+        };
+    }
+
+    /// Decodes the literals section, returning the number of bytes it took
+    fn read_literals(&mut self, input: &[u8]) -> io::Result<usize> {
+        let b = match input.first() {
+            Some(&b) => b,
+            None => return truncated("truncated zstd block"),
+        };
+        let kind = b & 3;
+        let format = (b >> 2) & 3;
+        self.literals.truncate(0);
+
+        if kind == LITERALS_RAW || kind == LITERALS_RLE {
+            let (size, header) = match format {
+                1 => (try!(read_le(input, 2)) >> 4, 2),
+                3 => (try!(read_le(input, 3)) >> 4, 3),
+                _ => ((b >> 3) as usize, 1),
+            };
+            if size > MAX_BLOCK_SIZE {
+                return corrupt("zstd literals are too large")
+            }
+            if kind == LITERALS_RAW {
+                if input.len() < header + size {
+                    return truncated("truncated zstd block")
+                }
+                self.literals.extend(input[header..(header + size)].iter().cloned());
+                return Ok(header + size)
+            }
+            match input.get(header) {
+                Some(&b) => self.literals.extend((0..size).map(|_| b)),
+                None => return truncated("truncated zstd block"),
+            }
+            return Ok(header + 1)
+        }
+
+        let (streams, header, bits) = match format {
+            0 => (1, 3, 10),
+            1 => (4, 3, 10),
+            2 => (4, 4, 14),
+            _ => (4, 5, 18),
+        };
+        let value = try!(read_le(input, header));
+        let mask = (1 << bits) - 1;
+        let size = (value >> 4) & mask;
+        let compressed = (value >> (4 + bits)) & mask;
+        if size > MAX_BLOCK_SIZE {
+            return corrupt("zstd literals are too large")
+        }
+        if input.len() < header + compressed {
+            return truncated("truncated zstd block")
+        }
+        let mut data = &input[header..(header + compressed)];
+        if kind == LITERALS_HUFFMAN {
+            let (table, used) = try!(HuffmanTable::read(data));
+            self.tables.huffman = Some(table);
+            data = &data[used..];
+        }
+        let table = match self.tables.huffman {
+            Some(ref table) => table,
+            None => return corrupt("zstd huffman table repeated before being set"),
+        };
+        if streams == 1 {
+            try!(table.decode(data, size, &mut self.literals));
+        } else {
+            // a jump table gives the sizes of the first three streams
+            if data.len() < 6 {
+                return truncated("truncated zstd block")
+            }
+            let mut sizes = [0; 4];
+            for i in 0..3 {
+                sizes[i] = try!(read_le(&data[(2 * i)..], 2));
+            }
+            let total = sizes[0] + sizes[1] + sizes[2] + 6;
+            if total > data.len() {
+                return truncated("truncated zstd block")
+            }
+            sizes[3] = data.len() - total;
+            let segment = (size + 3) / 4;
+            if segment * 3 > size {
+                return corrupt("invalid zstd literals size")
+            }
+            let mut pos = 6;
+            for i in 0..4 {
+                let count = if i < 3 { segment } else { size - 3 * segment };
+                try!(table.decode(&data[pos..(pos + sizes[i])], count, &mut self.literals));
+                pos += sizes[i];
+            }
+        }
+        Ok(header + compressed)
+    }
+
+    /// Decodes a compressed block at the end of `out`, the history of the
+    /// frame which the matches may reach back to
+    pub fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        let mut pos = try!(self.read_literals(input));
+        let count = match input.get(pos) {
+            Some(&b) if b < 128 => {
+                pos += 1;
+                b as usize
+            }
+            Some(&255) => {
+                pos += 3;
+                try!(read_le(&input[(pos - 2)..], 2)) + 0x7f00
+            }
+            Some(&b) => {
+                pos += 2;
+                (((b as usize) - 128) << 8) + try!(read_le(&input[(pos - 1)..], 1))
+            }
+            None => return truncated("truncated zstd block"),
+        };
+        let block_start = out.len();
+        if count == 0 {
+            if pos != input.len() {
+                return corrupt("invalid zstd block size")
+            }
+            out.extend(self.literals.iter().cloned());
+            return Ok(())
+        }
+
+        let modes = match input.get(pos) {
+            Some(&b) if b & 3 == 0 => b,
+            Some(_) => return corrupt("invalid zstd sequences modes"),
+            None => return truncated("truncated zstd block"),
+        };
+        pos += 1;
+        pos += try!(read_table(modes >> 6, &input[pos..], &LL_CODES, &mut self.tables.ll));
+        pos += try!(read_table((modes >> 4) & 3, &input[pos..], &OF_CODES, &mut self.tables.of));
+        pos += try!(read_table((modes >> 2) & 3, &input[pos..], &ML_CODES, &mut self.tables.ml));
+        if pos > input.len() {
+            return truncated("truncated zstd block")
+        }
+
+        let (ll, of, ml) = (self.tables.ll.as_ref().unwrap(), self.tables.of.as_ref().unwrap(),
+                            self.tables.ml.as_ref().unwrap());
+        let reps = &mut self.tables.reps;
+        let mut bits = try!(BackwardBits::new(&input[pos..]));
+        let mut ll_state = bits.read(ll.log) as usize;
+        let mut of_state = bits.read(of.log) as usize;
+        let mut ml_state = bits.read(ml.log) as usize;
+        let mut literals = &self.literals[..];
+        for i in 0..count {
+            let (ll_entry, of_entry, ml_entry) =
+                (ll.entries[ll_state], of.entries[of_state], ml.entries[ml_state]);
+            let of_code = of_entry.symbol as u32;
+            let offset_value = (1u64 << of_code) as usize + bits.read(of_code) as usize;
+            let ml_code = ml_entry.symbol as usize;
+            let match_len = ML_BASE[ml_code] as usize + bits.read(ML_BITS[ml_code] as u32) as usize;
+            let ll_code = ll_entry.symbol as usize;
+            let lit_len = LL_BASE[ll_code] as usize + bits.read(LL_BITS[ll_code] as u32) as usize;
+
+            // the offsets 1 to 3 repeat the last ones, shifted by one when
+            // there are no literals
+            let offset = if offset_value > 3 {
+                *reps = [offset_value - 3, reps[0], reps[1]];
+                reps[0]
+            } else {
+                match offset_value - 1 + (lit_len == 0) as usize {
+                    0 => {}
+                    1 => *reps = [reps[1], reps[0], reps[2]],
+                    2 => *reps = [reps[2], reps[0], reps[1]],
+                    _ => {
+                        if reps[0] == 1 {
+                            return corrupt("invalid zstd offset")
+                        }
+                        *reps = [reps[0] - 1, reps[0], reps[1]];
+                    }
+                }
+                reps[0]
+            };
+
+            if i + 1 < count {
+                ll_state = ll_entry.base as usize + bits.read(ll_entry.bits as u32) as usize;
+                ml_state = ml_entry.base as usize + bits.read(ml_entry.bits as u32) as usize;
+                of_state = of_entry.base as usize + bits.read(of_entry.bits as u32) as usize;
+            }
+
+            if lit_len > literals.len() {
+                return corrupt("invalid zstd literal length")
+            }
+            out.extend(literals[..lit_len].iter().cloned());
+            literals = &literals[lit_len..];
+            if offset > out.len() {
+                return corrupt("invalid zstd offset")
+            }
+            if out.len() + match_len - block_start > MAX_BLOCK_SIZE {
+                return corrupt("zstd block is too large")
+            }
+            copy_match(out, offset, match_len);
+        }
+        if !bits.is_empty() {
+            return corrupt("corrupted zstd sequences")
+        }
+        out.extend(literals.iter().cloned());
+        if out.len() - block_start > MAX_BLOCK_SIZE {
+            return corrupt("zstd block is too large")
+        }
+        Ok(())
+    }
+}
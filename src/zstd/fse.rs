@@ -0,0 +1,296 @@
+//! Finite state entropy decoding, and the bit streams of zstd
+//!
+//! The tables are described by a forward bit stream, read from the lowest bit
+//! of each byte, while the symbols are read from a backward bit stream: the
+//! last byte holds a marker bit above the first bits to read, and the stream
+//! is consumed from there down to the first bit of the first byte.
+
+use std::io;
+
+use error::{corrupt, truncated};
+
+/// Reader of the bit stream of a table description
+pub struct ForwardBits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ForwardBits<'a> {
+    pub fn new(data: &'a [u8]) -> ForwardBits<'a> {
+        ForwardBits { data: data, pos: 0 }
+    }
+
+    /// Returns the next `n` bits, reading zeroes past the end of the data
+    pub fn peek(&self, n: u32) -> u32 {
+        let mut value = 0;
+        for i in 0..n as usize {
+            let bit = self.pos + i;
+            if bit / 8 < self.data.len() {
+                value |= ((self.data[bit / 8] >> (bit % 8)) as u32 & 1) << i;
+            }
+        }
+        value
+    }
+
+    pub fn consume(&mut self, n: u32) {
+        self.pos += n as usize;
+    }
+
+    pub fn read(&mut self, n: u32) -> u32 {
+        let value = self.peek(n);
+        self.consume(n);
+        value
+    }
+
+    /// Returns the number of bytes the bits read so far span, failing if
+    /// that goes past the end of the data
+    pub fn bytes_used(&self) -> io::Result<usize> {
+        let used = (self.pos + 7) / 8;
+        if used > self.data.len() {
+            return truncated("truncated zstd table description")
+        }
+        Ok(used)
+    }
+}
+
+/// Reader of a bit stream from its end
+pub struct BackwardBits<'a> {
+    data: &'a [u8],
+    /// Number of bits left, negative once more than the stream were read
+    pos: isize,
+}
+
+impl<'a> BackwardBits<'a> {
+    pub fn new(data: &'a [u8]) -> io::Result<BackwardBits<'a>> {
+        match data.last() {
+            Some(&last) if last != 0 => {
+                let pos = data.len() * 8 - 1 - last.leading_zeros() as usize;
+                Ok(BackwardBits { data: data, pos: pos as isize })
+            }
+            _ => corrupt("invalid zstd bit stream"),
+        }
+    }
+
+    /// Returns the next `n` bits, up to 56, the first being the highest.
+    /// Past the start of the stream, zeroes are read.
+    #[inline]
+    pub fn peek(&self, n: u32) -> u64 {
+        let pos = self.pos - n as isize;
+        if pos >= 0 {
+            self.bits(pos as usize, n)
+        } else if pos + (n as isize) > 0 {
+            self.bits(0, (n as isize + pos) as u32) << (-pos) as u32
+        } else {
+            0
+        }
+    }
+
+    /// Returns `n` bits starting at bit `pos`
+    #[inline]
+    fn bits(&self, pos: usize, n: u32) -> u64 {
+        let start = pos / 8;
+        let mut word = 0u64;
+        if start + 8 <= self.data.len() {
+            for (i, &b) in self.data[start..(start + 8)].iter().enumerate() {
+                word |= (b as u64) << (8 * i);
+            }
+        } else {
+            for (i, &b) in self.data[start..].iter().enumerate() {
+                word |= (b as u64) << (8 * i);
+            }
+        }
+        (word >> (pos % 8)) & ((1u64 << n) - 1)
+    }
+
+    #[inline]
+    pub fn consume(&mut self, n: u32) {
+        self.pos -= n as isize;
+    }
+
+    #[inline]
+    pub fn read(&mut self, n: u32) -> u64 {
+        let value = self.peek(n);
+        self.consume(n);
+        value
+    }
+
+    /// Tests whether all the bits were read, and no more
+    pub fn is_empty(&self) -> bool {
+        self.pos == 0
+    }
+
+    /// Tests whether more bits than the stream holds were read
+    pub fn overflowed(&self) -> bool {
+        self.pos < 0
+    }
+}
+
+fn highest_bit(n: u32) -> u32 {
+    31 - n.leading_zeros()
+}
+
+#[derive(Clone, Copy)]
+pub struct Entry {
+    pub symbol: u8,
+    pub bits: u8,
+    pub base: u16,
+}
+
+/// A FSE decoding table, indexed by the states
+#[derive(Clone)]
+pub struct FseTable {
+    pub log: u32,
+    pub entries: Vec<Entry>,
+}
+
+impl FseTable {
+    /// Builds the table of the normalized counts of the symbols, -1 standing
+    /// for the symbols of probability lower than 1
+    pub fn from_counts(counts: &[i16], log: u32) -> io::Result<FseTable> {
+        let size = 1usize << log;
+        let total = counts.iter().fold(0, |t, &c| t + if c == -1 { 1 } else { c as isize });
+        if counts.iter().any(|&c| c < -1) || total != size as isize {
+            return corrupt("invalid zstd fse table")
+        }
+        let mut symbols = vec![0u8; size];
+        let mut next = vec![0u32; counts.len()];
+        let mut high = size as isize - 1;
+        for (s, &c) in counts.iter().enumerate() {
+            if c == -1 {
+                symbols[high as usize] = s as u8;
+                high -= 1;
+                next[s] = 1;
+            } else {
+                next[s] = c as u32;
+            }
+        }
+
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mask = size - 1;
+        let mut pos = 0;
+        for (s, &c) in counts.iter().enumerate() {
+            for _ in 0..(if c > 0 { c as usize } else { 0 }) {
+                symbols[pos] = s as u8;
+                loop {
+                    pos = (pos + step) & mask;
+                    if pos as isize <= high {
+                        break
+                    }
+                }
+            }
+        }
+
+        let entries = symbols.iter().map(|&s| {
+            let n = next[s as usize];
+            next[s as usize] += 1;
+            let bits = log - highest_bit(n);
+            Entry {
+                symbol: s,
+                bits: bits as u8,
+                base: ((n << bits) - size as u32) as u16,
+            }
+        }).collect();
+        Ok(FseTable { log: log, entries: entries })
+    }
+
+    /// The table of a single symbol, which takes no bits
+    pub fn rle(symbol: u8) -> FseTable {
+        FseTable {
+            log: 0,
+            entries: vec![Entry { symbol: symbol, bits: 0, base: 0 }],
+        }
+    }
+
+    /// Reads the description of a table, returning it with the number of
+    /// bytes it took
+    pub fn read(input: &[u8], max_log: u32, max_symbol: usize) -> io::Result<(FseTable, usize)> {
+        let mut bits = ForwardBits::new(input);
+        let log = bits.read(4) + 5;
+        if log > max_log {
+            return corrupt("invalid zstd fse table")
+        }
+        let mut counts = Vec::new();
+        let mut remaining = (1i32 << log) + 1;
+        let mut threshold = 1i32 << log;
+        let mut nbits = log + 1;
+        while remaining > 1 {
+            if counts.len() > max_symbol {
+                return corrupt("invalid zstd fse table")
+            }
+            // values below `max` take one bit less
+            let max = 2 * threshold - 1 - remaining;
+            let mut count = bits.peek(nbits - 1) as i32;
+            if count < max {
+                bits.consume(nbits - 1);
+            } else {
+                count = bits.read(nbits) as i32;
+                if count >= threshold {
+                    count -= max;
+                }
+            }
+            count -= 1;
+            remaining -= count.abs();
+            counts.push(count as i16);
+            if remaining < 1 {
+                return corrupt("invalid zstd fse table")
+            }
+            if count == 0 {
+                loop {
+                    let repeat = bits.read(2);
+                    for _ in 0..repeat {
+                        counts.push(0);
+                    }
+                    if repeat != 3 {
+                        break
+                    }
+                }
+            }
+            while remaining < threshold {
+                nbits -= 1;
+                threshold >>= 1;
+            }
+        }
+        if counts.len() > max_symbol + 1 {
+            return corrupt("invalid zstd fse table")
+        }
+        let used = try!(bits.bytes_used());
+        Ok((try!(FseTable::from_counts(&counts, log)), used))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BackwardBits, FseTable};
+
+    #[test]
+    fn backward_bits() {
+        // the marker is the highest bit of the last byte
+        let data = [0b1011_0110, 0b0000_0101];
+        let mut bits = BackwardBits::new(&data).unwrap();
+        assert_eq!(bits.read(2), 0b01);
+        assert_eq!(bits.read(3), 0b101);
+        assert_eq!(bits.read(0), 0);
+        assert_eq!(bits.read(5), 0b10110);
+        assert!(bits.is_empty());
+        assert_eq!(bits.read(3), 0);
+        assert!(bits.overflowed());
+        assert!(BackwardBits::new(&[1, 0]).is_err());
+    }
+
+    #[test]
+    fn spread() {
+        // every state must be reachable, and lead to states of the table
+        let counts = [4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2,
+                      2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1, -1, -1, -1, -1];
+        let table = FseTable::from_counts(&counts, 6).unwrap();
+        assert_eq!(table.entries.len(), 64);
+        for (s, &c) in counts.iter().enumerate() {
+            let n = table.entries.iter().filter(|e| e.symbol as usize == s).count();
+            assert_eq!(n, if c == -1 { 1 } else { c as usize });
+        }
+        for e in table.entries.iter() {
+            assert!(e.base as usize + (1 << e.bits) <= 64);
+        }
+        assert!(FseTable::from_counts(&[3, 2], 2).is_err());
+    }
+}
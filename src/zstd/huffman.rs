@@ -0,0 +1,163 @@
+//! Huffman decoding of the zstd literals
+//!
+//! The codes are described by the weights of the symbols, a weight `w` giving
+//! a code of `max_bits + 1 - w` bits, and the weight of the last symbol being
+//! implied by the others so that the codes fill the tree.
+
+use std::io;
+
+use error::{corrupt, truncated};
+use super::fse::{BackwardBits, FseTable};
+
+/// Maximum length of the codes
+const MAX_BITS: u32 = 11;
+/// Maximum accuracy of the table the weights are coded with
+const WEIGHTS_LOG: u32 = 6;
+
+/// A decoding table, indexed by the next `max_bits` bits of the stream
+#[derive(Clone)]
+pub struct HuffmanTable {
+    max_bits: u32,
+    /// Symbol and length of the code
+    entries: Vec<(u8, u8)>,
+}
+
+impl HuffmanTable {
+    /// Reads the description of a table, returning it with the number of
+    /// bytes it took
+    pub fn read(input: &[u8]) -> io::Result<(HuffmanTable, usize)> {
+        let header = match input.first() {
+            Some(&b) => b as usize,
+            None => return truncated("truncated zstd literals"),
+        };
+        if header >= 128 {
+            // weights of 4 bits, directly
+            let count = header - 127;
+            let size = (count + 1) / 2;
+            if input.len() < 1 + size {
+                return truncated("truncated zstd literals")
+            }
+            let weights: Vec<u8> = (0..count).map(|i| {
+                let b = input[1 + i / 2];
+                if i % 2 == 0 { b >> 4 } else { b & 0xf }
+            }).collect();
+            Ok((try!(HuffmanTable::from_weights(&weights)), 1 + size))
+        } else {
+            if input.len() < 1 + header {
+                return truncated("truncated zstd literals")
+            }
+            let data = &input[1..(1 + header)];
+            let weights = try!(read_weights(data));
+            Ok((try!(HuffmanTable::from_weights(&weights)), 1 + header))
+        }
+    }
+
+    fn from_weights(weights: &[u8]) -> io::Result<HuffmanTable> {
+        if weights.len() > 255 {
+            return corrupt("invalid zstd huffman weights")
+        }
+        let mut total = 0u32;
+        for &w in weights.iter() {
+            if w as u32 > MAX_BITS {
+                return corrupt("invalid zstd huffman weights")
+            }
+            total += (1 << w) >> 1;
+        }
+        if total == 0 {
+            return corrupt("invalid zstd huffman weights")
+        }
+        let max_bits = 32 - total.leading_zeros();
+        let left = (1 << max_bits) - total;
+        if max_bits > MAX_BITS || !left.is_power_of_two() {
+            return corrupt("invalid zstd huffman weights")
+        }
+        let mut weights = weights.to_vec();
+        weights.push((32 - left.leading_zeros()) as u8);
+
+        // the codes of each weight start after the ones of the lower weights
+        let mut starts = vec![0usize; max_bits as usize + 2];
+        for &w in weights.iter() {
+            if w > 0 {
+                starts[w as usize + 1] += 1 << (w - 1);
+            }
+        }
+        for w in 1..starts.len() {
+            starts[w] += starts[w - 1];
+        }
+        let mut entries = vec![(0, 0); 1 << max_bits];
+        for (s, &w) in weights.iter().enumerate() {
+            if w == 0 {
+                continue
+            }
+            let start = starts[w as usize];
+            let len = 1 << (w - 1);
+            for e in entries[start..(start + len)].iter_mut() {
+                *e = (s as u8, (max_bits + 1 - w as u32) as u8);
+            }
+            starts[w as usize] += len;
+        }
+        Ok(HuffmanTable { max_bits: max_bits, entries: entries })
+    }
+
+    /// Decodes `count` symbols of a stream, which must use all its bits
+    pub fn decode(&self, input: &[u8], count: usize, out: &mut Vec<u8>) -> io::Result<()> {
+        let mut bits = try!(BackwardBits::new(input));
+        for _ in 0..count {
+            let (symbol, len) = self.entries[bits.peek(self.max_bits) as usize];
+            bits.consume(len as u32);
+            out.push(symbol);
+        }
+        if !bits.is_empty() {
+            return corrupt("corrupted zstd literals")
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the weights coded with a FSE table, whose two states alternate
+/// until the stream runs out
+fn read_weights(data: &[u8]) -> io::Result<Vec<u8>> {
+    let (table, used) = try!(FseTable::read(data, WEIGHTS_LOG, 255));
+    let mut bits = try!(BackwardBits::new(&data[used..]));
+    let mut states = [bits.read(table.log) as usize, bits.read(table.log) as usize];
+    let mut weights = Vec::new();
+    let mut current = 0;
+    loop {
+        if weights.len() > 255 {
+            return corrupt("invalid zstd huffman weights")
+        }
+        let e = table.entries[states[current]];
+        weights.push(e.symbol);
+        states[current] = e.base as usize + bits.read(e.bits as u32) as usize;
+        current ^= 1;
+        if bits.overflowed() {
+            weights.push(table.entries[states[current]].symbol);
+            break
+        }
+    }
+    Ok(weights)
+}
+
+#[cfg(test)]
+mod test {
+    use super::HuffmanTable;
+
+    #[test]
+    fn weights() {
+        // weights 2, 1, 1 and the implied 3: codes of 1, 2, 3 and 3 bits
+        let (table, used) = HuffmanTable::read(&[130, 0x21, 0x10]).unwrap();
+        assert_eq!(used, 3);
+        assert_eq!(table.max_bits, 3);
+        let lens: Vec<_> = (0..4).map(|s| {
+            table.entries.iter().filter(|e| e.0 == s).map(|e| e.1).next().unwrap()
+        }).collect();
+        assert_eq!(lens, vec![2, 3, 3, 1]);
+        // the marker bit, then the codes of 3, 0 and 2
+        let mut out = Vec::new();
+        table.decode(&[0b1_1_01_001], 3, &mut out).unwrap();
+        assert_eq!(out, vec![3, 0, 2]);
+        assert!(table.decode(&[0b1_1_01_001], 2, &mut Vec::new()).is_err());
+        // the weights must leave room for a power of two
+        assert!(HuffmanTable::read(&[130, 0x22, 0x10]).is_err());
+    }
+}
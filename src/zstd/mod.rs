@@ -0,0 +1,621 @@
+//! Zstandard Decompression. Requires `zstd` feature, enabled by default
+//!
+//! This module contains a decoder of the Zstandard format, as written by
+//! `zstd`. A stream is a sequence of frames, each made of blocks which are
+//! either raw, a single repeated byte, or compressed: Huffman coded literals
+//! followed by the sequences of literal lengths, match lengths and offsets
+//! coded with FSE (tANS). Skippable frames are ignored, and the content
+//! checksums of the frames are verified.
+//!
+//! Frames compressed with a dictionary, such as the ones trained by
+//! `zstd --train` for small messages, are decoded by giving the dictionary
+//! to `Decoder::with_dictionary`. The dictionaries of the `dictionary`
+//! module are read by `Dictionary::from_shared`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::zstd;
+//! use std::fs::File;
+//! use std::path::Path;
+//! use std::io::Read;
+//!
+//! let stream = File::open(&Path::new("path/to/file.zst")).unwrap();
+//! let mut decompressed = Vec::new();
+//! zstd::Decoder::new(stream).read_to_end(&mut decompressed);
+//!
+//! let mut dictionary = Vec::new();
+//! File::open(&Path::new("path/to/dictionary")).unwrap().read_to_end(&mut dictionary);
+//! let dictionary = zstd::Dictionary::new(&dictionary).unwrap();
+//! let stream = File::open(&Path::new("path/to/message.zst")).unwrap();
+//! let mut decompressed = Vec::new();
+//! zstd::Decoder::with_dictionary(stream, dictionary).read_to_end(&mut decompressed);
+//! ```
+//!
+//! # Related links
+//!
+//! * https://tools.ietf.org/html/rfc8878 - the specification of the format
+//! * https://github.com/facebook/zstd - the reference implementation
+
+use std::cmp;
+use std::io::{self, Read};
+
+use super::ReadExact;
+use super::byteorder::{LittleEndian, ReadBytesExt};
+
+use checksum::xxhash;
+#[cfg(feature="dictionary")]
+use dictionary;
+use error::{checksum_mismatch, corrupt, truncated, unsupported};
+use process::{self, drain, Process, Reader, Status};
+use self::block::{BlockDecoder, Tables, MAX_BLOCK_SIZE};
+
+mod block;
+mod fse;
+mod huffman;
+
+const MAGIC: u32 = 0xfd2fb528;
+/// Magic numbers of the skippable frames, of which the low 4 bits are free
+const SKIPPABLE_MAGIC: u32 = 0x184d2a50;
+const DICTIONARY_MAGIC: u32 = 0xec30a437;
+
+/// Largest window accepted, as in the reference decoder
+const MAX_WINDOW_LOG: u32 = 27;
+
+const BLOCK_RAW: u32 = 0;
+const BLOCK_RLE: u32 = 1;
+const BLOCK_COMPRESSED: u32 = 2;
+
+/// A dictionary, which gives the content the matches of a frame can refer
+/// to from its start, and the tables and offsets its first block starts with
+pub struct Dictionary {
+    id: u32,
+    /// The tables, unless the dictionary is made of raw content only
+    tables: Option<Tables>,
+    content: Vec<u8>,
+}
+
+impl Dictionary {
+    /// Reads a dictionary, as written by `zstd --train`. Without the magic
+    /// number of dictionaries, the data is taken as raw content.
+    pub fn new(data: &[u8]) -> io::Result<Dictionary> {
+        if data.len() < 8 || try!((&data[..]).read_u32::<LittleEndian>()) != DICTIONARY_MAGIC {
+            return Ok(Dictionary { id: 0, tables: None, content: data.to_vec() })
+        }
+        let id = try!((&data[4..]).read_u32::<LittleEndian>());
+        let (tables, used) = try!(Tables::read_dictionary(&data[8..]));
+        let content = data[(8 + used)..].to_vec();
+        if tables.reps.iter().any(|&r| r == 0 || r > content.len()) {
+            return corrupt("invalid zstd dictionary offsets")
+        }
+        Ok(Dictionary { id: id, tables: Some(tables), content: content })
+    }
+
+    /// Reads the content of a dictionary shared with the other codecs, as
+    /// `new` does
+    #[cfg(feature="dictionary")]
+    pub fn from_shared(dictionary: &dictionary::Dictionary) -> io::Result<Dictionary> {
+        Dictionary::new(dictionary.content())
+    }
+
+    /// Returns the identifier of the dictionary, which the frames compressed
+    /// with it may give, or 0 for raw content
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// The frame being decoded
+struct Frame {
+    window_size: usize,
+    /// Size of the history the matches can reach, the dictionary included
+    history_size: usize,
+    content_size: Option<u64>,
+    checksum: Option<xxhash::State64>,
+    decoded: u64,
+    last_block: bool,
+}
+
+/// This structure is used to decode a zstd stream, fed with slices of it.
+/// The input of a block is gathered until it holds the whole block, which is
+/// decoded again from its start whenever it runs out.
+pub struct Processor {
+    r: process::Window,
+    dictionary: Option<Dictionary>,
+    block: BlockDecoder,
+    frame: Option<Frame>,
+    frames: usize,
+    /// The decoded data, kept for as long as the matches can reach it
+    history: Vec<u8>,
+    /// Position in the history of the next byte to return
+    pos: usize,
+    input: Vec<u8>,
+    /// Number of bytes of a skippable frame left to skip
+    skip: u64,
+    eof: bool,
+}
+
+impl Processor {
+    /// Creates a new zstd decoder
+    pub fn new() -> Processor {
+        Processor {
+            r: process::Window::new(),
+            dictionary: None,
+            block: BlockDecoder::new(),
+            frame: None,
+            frames: 0,
+            history: Vec::new(),
+            pos: 0,
+            input: Vec::new(),
+            skip: 0,
+            eof: false,
+        }
+    }
+
+    /// Creates a new zstd decoder of frames compressed with the given
+    /// dictionary.
+    pub fn with_dictionary(dictionary: Dictionary) -> Processor {
+        let mut p = Processor::new();
+        p.dictionary = Some(dictionary);
+        p
+    }
+
+    /// Starts decoding a new stream, keeping the buffers and the dictionary
+    /// of the decoder
+    pub fn reset(&mut self) {
+        self.r.reset();
+        self.frame = None;
+        self.frames = 0;
+        self.history.truncate(0);
+        self.pos = 0;
+        self.skip = 0;
+        self.eof = false;
+    }
+
+    /// Tests whether the end of the stream has been reached
+    pub fn eof(&self) -> bool {
+        self.eof
+    }
+
+    /// Reads the magic number of the next frame, returning None at the end of
+    /// the stream
+    fn read_magic(&mut self) -> io::Result<Option<u32>> {
+        let mut buf = [0u8; 4];
+        let mut n = 0;
+        while n < 4 {
+            match try!(self.r.read(&mut buf[n..])) {
+                0 => break,
+                amt => n += amt,
+            }
+        }
+        if n == 0 && self.frames > 0 {
+            return Ok(None)
+        }
+        if n < 4 {
+            return truncated("truncated zstd frame")
+        }
+        Ok(Some(try!((&buf[..]).read_u32::<LittleEndian>())))
+    }
+
+    /// Reads the header of the next frame, returning false at the end of the
+    /// stream
+    fn read_frame_header(&mut self) -> io::Result<bool> {
+        let magic = match try!(self.read_magic()) {
+            Some(magic) => magic,
+            None => return Ok(false),
+        };
+        if magic & 0xfffffff0 == SKIPPABLE_MAGIC {
+            // skipped as it comes
+            self.skip = try!(self.r.read_u32::<LittleEndian>()) as u64;
+            self.frames += 1;
+            return Ok(true)
+        }
+        if magic != MAGIC {
+            return corrupt("invalid zstd magic number")
+        }
+
+        let descriptor = try!(self.r.read_u8());
+        if descriptor & 0x08 != 0 {
+            return unsupported("unsupported zstd frame")
+        }
+        let single_segment = descriptor & 0x20 != 0;
+        let mut window_size = 0;
+        if !single_segment {
+            let b = try!(self.r.read_u8());
+            let log = 10 + (b >> 3) as u32;
+            if log > MAX_WINDOW_LOG {
+                return corrupt("zstd window is too large")
+            }
+            window_size = (1 << log) + ((1 << log) >> 3) * (b & 7) as usize;
+        }
+        let dict_id = match descriptor & 3 {
+            0 => 0,
+            1 => try!(self.r.read_u8()) as u32,
+            2 => try!(self.r.read_u16::<LittleEndian>()) as u32,
+            _ => try!(self.r.read_u32::<LittleEndian>()),
+        };
+        match self.dictionary {
+            Some(ref d) if d.id != 0 && dict_id != 0 && d.id != dict_id => {
+                return corrupt("wrong zstd dictionary")
+            }
+            None if dict_id != 0 => return corrupt("zstd dictionary required"),
+            _ => {}
+        }
+        let content_size = match descriptor >> 6 {
+            0 if single_segment => Some(try!(self.r.read_u8()) as u64),
+            0 => None,
+            1 => Some(try!(self.r.read_u16::<LittleEndian>()) as u64 + 256),
+            2 => Some(try!(self.r.read_u32::<LittleEndian>()) as u64),
+            _ => Some(try!(self.r.read_u64::<LittleEndian>())),
+        };
+        if single_segment {
+            let size = content_size.unwrap();
+            if size > 1 << MAX_WINDOW_LOG {
+                return corrupt("zstd window is too large")
+            }
+            window_size = size as usize;
+        }
+
+        self.frames += 1;
+        self.history.truncate(0);
+        match self.dictionary {
+            Some(ref d) => {
+                self.block.reset(d.tables.as_ref());
+                self.history.extend(d.content.iter().cloned());
+            }
+            None => self.block.reset(None),
+        }
+        self.pos = self.history.len();
+        self.frame = Some(Frame {
+            window_size: window_size,
+            history_size: window_size + self.pos,
+            content_size: content_size,
+            checksum: if descriptor & 0x04 != 0 { Some(xxhash::State64::new(0)) } else { None },
+            decoded: 0,
+            last_block: false,
+        });
+        Ok(true)
+    }
+
+    /// Decodes the next block of the frame at the end of the history
+    fn read_block(&mut self) -> io::Result<()> {
+        let frame = self.frame.as_mut().unwrap();
+        let header = try!(self.r.read_uint::<LittleEndian>(3)) as u32;
+        let size = (header >> 3) as usize;
+        let max_size = cmp::min(frame.window_size, MAX_BLOCK_SIZE);
+        let start = self.history.len();
+        match (header >> 1) & 3 {
+            BLOCK_RAW => {
+                if size > max_size {
+                    return corrupt("zstd block is too large")
+                }
+                try!(self.r.push_exactly(size as u64, &mut self.history));
+            }
+            BLOCK_RLE => {
+                if size > max_size {
+                    return corrupt("zstd block is too large")
+                }
+                let b = try!(self.r.read_u8());
+                self.history.extend((0..size).map(|_| b));
+            }
+            BLOCK_COMPRESSED => {
+                if size > max_size {
+                    return corrupt("zstd block is too large")
+                }
+                self.input.truncate(0);
+                try!(self.r.push_exactly(size as u64, &mut self.input));
+                try!(self.block.decode(&self.input, &mut self.history));
+            }
+            _ => return corrupt("invalid zstd block type"),
+        }
+
+        frame.last_block = header & 1 != 0;
+        let decoded = &self.history[start..];
+        if let Some(ref mut checksum) = frame.checksum {
+            checksum.feed(decoded);
+        }
+        frame.decoded += decoded.len() as u64;
+        if frame.content_size.map_or(false, |s| frame.decoded > s) || decoded.len() > max_size {
+            return corrupt("zstd frame size mismatch")
+        }
+        Ok(())
+    }
+
+    /// Checks the end of the frame
+    fn end_frame(&mut self) -> io::Result<()> {
+        {
+            let frame = self.frame.as_ref().unwrap();
+            if frame.content_size.map_or(false, |s| frame.decoded != s) {
+                return corrupt("zstd frame size mismatch")
+            }
+            if let Some(ref checksum) = frame.checksum {
+                if try!(self.r.read_u32::<LittleEndian>()) != checksum.result() as u32 {
+                    return checksum_mismatch("invalid zstd frame checksum")
+                }
+            }
+        }
+        self.frame = None;
+        Ok(())
+    }
+
+    /// Reads the next frame header, block or frame end from its start,
+    /// rolling the input back when it runs out, or returns `None` to wait for
+    /// more of it
+    fn next_part(&mut self) -> io::Result<Option<()>> {
+        if !self.r.is_ready() {
+            return Ok(None)
+        }
+        if let Some(ref frame) = self.frame {
+            // forget what the matches can't reach anymore, once it was read
+            if self.history.len() > 2 * cmp::max(frame.history_size, MAX_BLOCK_SIZE) {
+                let drop = self.history.len() - frame.history_size;
+                self.history.drain(..drop);
+                self.pos -= drop;
+            }
+        }
+        let (mark, len) = (self.r.mark(), self.history.len());
+        let result = match self.frame {
+            None => match self.read_frame_header() {
+                Ok(false) => {
+                    self.eof = true;
+                    Ok(())
+                }
+                result => result.map(|_| ()),
+            },
+            Some(ref frame) if frame.last_block => self.end_frame(),
+            Some(_) => self.read_block(),
+        };
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.r.rollback(mark);
+                self.history.truncate(len);
+                Ok(None)
+            }
+            result => result.map(Some),
+        }
+    }
+}
+
+impl Process for Processor {
+    fn process(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize, Status)> {
+        self.r.extend(input);
+        let mut produced = 0;
+        loop {
+            drain(&self.history, &mut self.pos, output, &mut produced);
+            if self.pos < self.history.len() {
+                return Ok((input.len(), produced, Status::NeedOutput))
+            }
+            if self.eof {
+                // the input gathered past the end of the stream isn't used
+                let pos = input.len() - cmp::min(self.r.data().len(), input.len());
+                return Ok((pos, produced, Status::Done))
+            }
+            if self.skip > 0 {
+                let n = cmp::min(self.skip, self.r.data().len() as u64);
+                self.r.consume(n as usize);
+                self.skip -= n;
+                if self.skip > 0 {
+                    if self.r.is_finished() {
+                        return truncated("truncated zstd skippable frame")
+                    }
+                    return Ok((input.len(), produced, Status::NeedInput))
+                }
+            }
+            if try!(self.next_part()).is_none() {
+                return Ok((input.len(), produced, Status::NeedInput))
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // the frames left may come from the input gathered, and reading past
+        // it fails as truncated
+        self.r.finish();
+        Ok(())
+    }
+}
+
+/// This structure is used to decode a zstd stream. The wrapped stream can be
+/// re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    inner: Reader<R, Processor>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new zstd decoder which will wrap the specified reader.
+    /// This decoder also implements the `Reader` trait, and the underlying
+    /// reader can be re-acquired through the `unwrap` method.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::new()),
+        }
+    }
+
+    /// Fails the reads with `Error::LimitExceeded` once the stream decodes to
+    /// more than `size` bytes.
+    pub fn set_max_output_size(&mut self, size: u64) {
+        self.inner.set_max_output_size(size);
+    }
+
+    /// Starts decoding a new stream from `r`, keeping the buffers of the
+    /// decoder and its settings, and returns the previous reader.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.processor_mut().reset();
+        self.inner.reset(r)
+    }
+
+    /// Creates a new zstd decoder of frames compressed with the given
+    /// dictionary.
+    pub fn with_dictionary(r: R, dictionary: Dictionary) -> Decoder<R> {
+        Decoder {
+            inner: Reader::new(r, Processor::with_dictionary(dictionary)),
+        }
+    }
+
+    /// Destroys this decoder, returning the underlying reader. The data read
+    /// ahead of the output is lost with it.
+    pub fn unwrap(self) -> R {
+        self.inner.into_inner().0
+    }
+
+    /// Tests whether the end of the stream has been reached
+    pub fn eof(&self) -> bool {
+        self.inner.processor().eof()
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read};
+    use testing::{noise, push};
+    use super::{Decoder, Dictionary, Processor};
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        out
+    }
+
+    /// The input of test.multi.zst, compressed with a window and blocks of
+    /// 1 kB: incompressible data, then text, then a run
+    fn multi() -> Vec<u8> {
+        let mut data = noise(4000, 1);
+        for _ in 0..4 {
+            data.extend(include_bytes!("../data/test.txt").iter().cloned());
+        }
+        data.extend((0..3000).map(|_| b'z'));
+        data
+    }
+
+    /// The message of test.dict.zst, like the ones test.dict was trained on
+    fn message(i: usize) -> Vec<u8> {
+        format!("{{\"id\": {}, \"name\": \"user{}\", \"email\": \"user{}@example.com\", \"active\": {}}}",
+                i, i * 7, i * 7, i % 2 == 0).into_bytes()
+    }
+
+    #[test]
+    fn decode_file() {
+        assert!(&decode(include_bytes!("../data/test.zst"))[..] ==
+                &include_bytes!("../data/test.txt")[..]);
+        assert!(decode(include_bytes!("../data/test.multi.zst")) == multi());
+    }
+
+    #[test]
+    fn decode_push() {
+        let input = include_bytes!("../data/test.multi.zst");
+        for &step in [1, 333, 1 << 16].iter() {
+            assert!(push(&mut Processor::new(), input, step).unwrap() == multi());
+        }
+        let input = include_bytes!("../data/test.zst");
+        for &len in [0, 10, 100, input.len() - 1].iter() {
+            assert!(push(&mut Processor::new(), &input[..len], 100).is_err());
+        }
+    }
+
+    #[test]
+    fn reset() {
+        // a stream left halfway doesn't affect the next one
+        let mut d = Decoder::new(BufReader::new(&include_bytes!("../data/test.multi.zst")[..]));
+        let mut out = [0; 5000];
+        d.read_exact(&mut out).unwrap();
+        d.reset(BufReader::new(&include_bytes!("../data/test.zst")[..]));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        assert!(&out[..] == &include_bytes!("../data/test.txt")[..]);
+        d.reset(BufReader::new(&include_bytes!("../data/test.multi.zst")[..]));
+        out.truncate(0);
+        d.read_to_end(&mut out).unwrap();
+        assert!(out == multi());
+    }
+
+    #[test]
+    fn frames() {
+        // frames follow each other, with skippable frames in between
+        let mut input = include_bytes!("../data/test.zst").to_vec();
+        input.extend([0x5a, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1, 2, 3].iter().cloned());
+        input.extend(include_bytes!("../data/test.multi.zst").iter().cloned());
+        let mut expected = include_bytes!("../data/test.txt").to_vec();
+        expected.extend(multi().into_iter());
+        assert!(decode(&input) == expected);
+        assert!(push(&mut Processor::new(), &input, 5).unwrap() == expected);
+
+        // raw and RLE blocks, without checksum
+        let raw = [0x28, 0xb5, 0x2f, 0xfd, 0x20, 5, 0x12, 0, 0, 0xab, 0x19, 0, 0, b'c', b'c', b'c'];
+        assert_eq!(decode(&raw), vec![0xab, 0xab, b'c', b'c', b'c']);
+    }
+
+    #[test]
+    fn dictionaries() {
+        let dictionary = include_bytes!("../data/test.dict");
+        assert!(Dictionary::new(dictionary).unwrap().id() != 0);
+        // each frame starts from the dictionary
+        let mut input = include_bytes!("../data/test.dict.zst").to_vec();
+        input.extend(include_bytes!("../data/test.dict.zst").iter().cloned());
+        let mut d = Decoder::with_dictionary(BufReader::new(&input[..]),
+                                             Dictionary::new(dictionary).unwrap());
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        let mut expected = message(1234);
+        expected.extend(message(1234).into_iter());
+        assert_eq!(out, expected);
+
+        // the frame requires its dictionary
+        assert!(Decoder::new(&input[..]).read_to_end(&mut Vec::new()).is_err());
+        let mut other = dictionary.to_vec();
+        other[4] ^= 1;
+        let mut d = Decoder::with_dictionary(&input[..], Dictionary::new(&other).unwrap());
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+
+        // a dictionary of raw content
+        let text = include_bytes!("../data/test.txt");
+        let dictionary = Dictionary::new(text).unwrap();
+        assert_eq!(dictionary.id(), 0);
+        let input = include_bytes!("../data/test.prefix.zst");
+        let mut d = Decoder::with_dictionary(&input[..], dictionary);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        let mut expected = b"prefix: ".to_vec();
+        expected.extend(text[1000..2000].iter().cloned());
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn small_reads() {
+        let input = include_bytes!("../data/test.multi.zst");
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        let mut out = Vec::new();
+        let mut buf = [0u8; 7];
+        loop {
+            match d.read(&mut buf).unwrap() {
+                0 => break,
+                n => out.extend(buf[..n].iter().cloned()),
+            }
+        }
+        assert!(out == multi());
+    }
+
+    #[test]
+    fn corrupted() {
+        let input = include_bytes!("../data/test.zst");
+        // frame header, literals, sequences, then checksum
+        for &pos in [4, 12, input.len() / 2, input.len() - 10, input.len() - 2].iter() {
+            let mut bad = input.to_vec();
+            bad[pos] ^= 0x04;
+            let mut d = Decoder::new(BufReader::new(&bad[..]));
+            assert!(d.read_to_end(&mut Vec::new()).is_err(), "undetected at {}", pos);
+        }
+        let mut d = Decoder::new(BufReader::new(&input[..100]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+        let mut d = Decoder::new(BufReader::new(&b""[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}